@@ -4,6 +4,7 @@ Test script to demonstrate viberot action plugin functionality.
 This script will be triggered when a monitored command runs.
 """
 
+import json
 import os
 import sys
 import time
@@ -11,10 +12,23 @@ import tkinter as tk
 from tkinter import ttk
 import threading
 
+def accessibility():
+    """VibeRot's [accessibility] settings"""
+    try:
+        return json.loads(os.getenv('VIBEROT_ACCESSIBILITY', '{}'))
+    except ValueError:
+        return {}
+
 def create_gui():
     root = tk.Tk()
     root.title("VibeRot Action Plugin")
     root.geometry("500x300")
+
+    if accessibility().get('high_contrast'):
+        style = ttk.Style(root)
+        style.theme_use('default')
+        style.configure('.', background='black', foreground='yellow')
+        root.configure(background='black')
     
     # Main frame
     main_frame = ttk.Frame(root, padding="10")
@@ -31,6 +45,7 @@ def create_gui():
         ("Command that was run:", os.getenv('VIBEROT_COMMAND', 'Not set')),
         ("Timestamp:", os.getenv('VIBEROT_TIMESTAMP', 'Not set')),
         ("VibeRot Home:", os.getenv('VIBEROT_HOME', 'Not set')),
+        ("Accessibility:", os.getenv('VIBEROT_ACCESSIBILITY', 'Not set')),
         ("Current working directory:", os.getcwd()),
         ("Script location:", os.path.abspath(__file__))
     ]
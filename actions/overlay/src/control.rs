@@ -0,0 +1,337 @@
+// Live control of the overlay
+//
+// The same commands are reachable two ways:
+// - as Tauri commands (`set_url`, `set_opacity`, `show_banner`, `play_pause`) for local control pages
+// - as JSON lines on stdin, e.g. `{"cmd": "set_opacity", "opacity": 0.3}`, for the core and scripts
+//
+// `open` and `close` (stdin only) manage additional overlay windows, `attach` and `detach` show and
+// hide the overlay in daemon mode (see `daemon`). The other commands act on the
+// window named by their optional `window` field, or on every window when it is left out, through
+// the `window.__viberot` helpers injected by `page_script`.
+
+use crate::daemon;
+use crate::overlay_window::{self, WindowOptions};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, WebviewWindow};
+use url::Url;
+
+/// How long banners stay up when no duration is given
+const DEFAULT_BANNER_MS: u64 = 4000;
+
+/// Stops page animations, transitions and smooth scrolling for `reduced_motion`
+const REDUCED_MOTION_STYLE: &str = "*, *::before, *::after { animation-duration: 0.001ms !important; \
+    animation-iteration-count: 1 !important; transition-duration: 0.001ms !important; scroll-behavior: auto !important; }";
+
+/// Colours and font of banners, and the high-contrast ones
+const BANNER_STYLE: &str = "font:600 18px sans-serif;color:#fff;background-color:rgba(0,0,0,0.75) !important;";
+const HIGH_CONTRAST_BANNER_STYLE: &str =
+    "font:700 22px sans-serif;color:#ff0;background-color:#000 !important;border:3px solid #fff;";
+
+/// VibeRot's `[accessibility]` settings, passed as JSON in `VIBEROT_ACCESSIBILITY`
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct Accessibility {
+    /// Turn off page animations, transitions and smooth scrolling
+    pub reduced_motion: bool,
+    /// High-contrast banners
+    pub high_contrast: bool,
+}
+
+impl Accessibility {
+    pub fn from_env() -> Self {
+        let Ok(json) = std::env::var("VIBEROT_ACCESSIBILITY") else {
+            return Self::default();
+        };
+        serde_json::from_str(&json).unwrap_or_else(|e| {
+            eprintln!("Ignoring invalid VIBEROT_ACCESSIBILITY: {}", e);
+            Self::default()
+        })
+    }
+}
+
+/// Overlay settings shared by all windows, and the ones that must survive page navigations
+pub struct OverlayState {
+    pub options: WindowOptions,
+    /// Opacity of windows without their own
+    default_opacity: Mutex<f64>,
+    /// Opacity set at runtime for a single window, by label
+    window_opacity: Mutex<HashMap<String, f64>>,
+}
+
+impl OverlayState {
+    pub fn new(options: WindowOptions) -> Self {
+        Self {
+            default_opacity: Mutex::new(options.opacity),
+            window_opacity: Mutex::new(HashMap::new()),
+            options,
+        }
+    }
+
+    pub fn opacity_of(&self, label: &str) -> f64 {
+        match self.window_opacity.lock().unwrap().get(label) {
+            Some(opacity) => *opacity,
+            None => *self.default_opacity.lock().unwrap(),
+        }
+    }
+
+    /// Drops the settings of a closed window
+    pub fn forget(&self, label: &str) {
+        self.window_opacity.lock().unwrap().remove(label);
+    }
+}
+
+/// A command received over stdin
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ControlCommand {
+    /// Opens another overlay window, e.g. for a newly triggered event
+    Open { id: String, url: Option<String>, monitor: Option<usize> },
+    Close { id: String },
+    Attach { id: String },
+    Detach { id: String },
+    SetUrl { url: String, window: Option<String> },
+    SetOpacity { opacity: f64, window: Option<String> },
+    ShowBanner { text: String, duration_ms: Option<u64>, window: Option<String> },
+    PlayPause { window: Option<String> },
+}
+
+/// Runs a command against its target overlay window(s)
+pub fn execute(app: &AppHandle, command: ControlCommand) -> Result<(), String> {
+    match command {
+        ControlCommand::Open { id, url, monitor } => {
+            let url = url.as_deref().map(parse_url).transpose()?;
+            let options = app.state::<OverlayState>().options.clone();
+            overlay_window::open(app, &overlay_window::label_for(&id), &options, url, monitor, Some(id.clone())).map(|_| ())
+        }
+        ControlCommand::Close { id } => overlay_window::close(app, &overlay_window::label_for(&id)),
+        ControlCommand::Attach { id } => daemon::attach(app, id),
+        ControlCommand::Detach { id } => daemon::detach(app, &id),
+        ControlCommand::SetUrl { url, window } => {
+            let url = parse_url(&url)?;
+            for_each_target(app, window, |target| target.navigate(url.clone()).map_err(|e| e.to_string()))
+        }
+        ControlCommand::SetOpacity { opacity, window } => {
+            if !(0.0..=1.0).contains(&opacity) {
+                return Err(format!("Opacity must be between 0.0 and 1.0, got {}", opacity));
+            }
+            let state = app.state::<OverlayState>();
+            match &window {
+                Some(label) => {
+                    state.window_opacity.lock().unwrap().insert(label.clone(), opacity);
+                }
+                None => {
+                    *state.default_opacity.lock().unwrap() = opacity;
+                    state.window_opacity.lock().unwrap().clear();
+                }
+            }
+            for_each_target(app, window, |target| apply_opacity(target, opacity))
+        }
+        ControlCommand::ShowBanner { text, duration_ms, window } => {
+            let text = serde_json::to_string(&text).map_err(|e| e.to_string())?;
+            let duration_ms = duration_ms.unwrap_or(DEFAULT_BANNER_MS);
+            for_each_target(app, window, |target| {
+                target
+                    .eval(format!("window.__viberot && window.__viberot.showBanner({}, {});", text, duration_ms))
+                    .map_err(|e| e.to_string())
+            })
+        }
+        ControlCommand::PlayPause { window } => for_each_target(app, window, |target| {
+            target
+                .eval("window.__viberot && window.__viberot.playPause();")
+                .map_err(|e| e.to_string())
+        }),
+    }
+}
+
+fn parse_url(url: &str) -> Result<Url, String> {
+    Url::parse(url).map_err(|e| format!("Invalid URL '{}': {}", url, e))
+}
+
+/// Runs `f` on the named window, or on every overlay window when no name is given
+fn for_each_target(
+    app: &AppHandle,
+    label: Option<String>,
+    f: impl Fn(&WebviewWindow) -> Result<(), String>,
+) -> Result<(), String> {
+    match label {
+        Some(label) => {
+            let window = app
+                .get_webview_window(&label)
+                .ok_or_else(|| format!("Overlay window '{}' is not open", label))?;
+            f(&window)
+        }
+        None => {
+            let windows = app.webview_windows();
+            if windows.is_empty() {
+                return Err("No overlay window is open".to_string());
+            }
+            windows.values().try_for_each(f)
+        }
+    }
+}
+
+/// Re-applies the current opacity, e.g. after the page changed
+pub fn apply_opacity(window: &WebviewWindow, opacity: f64) -> Result<(), String> {
+    window
+        .eval(format!("window.__viberot && window.__viberot.setOpacity({});", opacity))
+        .map_err(|e| e.to_string())
+}
+
+/// Pauses or resumes the videos on the page, e.g. while the window is hidden
+pub fn apply_playing(window: &WebviewWindow, playing: bool) -> Result<(), String> {
+    window
+        .eval(format!("window.__viberot && window.__viberot.setPlaying({});", playing))
+        .map_err(|e| e.to_string())
+}
+
+/// Tells VibeRot on stdout that the overlay is on screen, so it can measure trigger latency.
+/// `id` is the event the overlay was shown for, if it wasn't started for a single one.
+pub fn report_visible(id: Option<&str>) {
+    report("visible", id);
+}
+
+/// Tells VibeRot the user closed the overlay shown for an event, rather than VibeRot
+pub fn report_dismissed(id: Option<&str>) {
+    report("dismissed", id);
+}
+
+fn report(event: &str, id: Option<&str>) {
+    use std::io::Write;
+
+    // Nobody may be reading stdout anymore, which must not take the overlay down
+    let _ = writeln!(std::io::stdout(), "{}", serde_json::json!({ "event": event, "id": id }));
+}
+
+/// Handles one line of the stdin protocol; blank lines are ignored
+pub fn handle_line(app: &AppHandle, line: &str) {
+    let line = line.trim();
+    if line.is_empty() {
+        return;
+    }
+    match serde_json::from_str::<ControlCommand>(line) {
+        Ok(command) => {
+            if let Err(e) = execute(app, command) {
+                eprintln!("Error running overlay command: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Ignoring invalid overlay command '{}': {}", line, e),
+    }
+}
+
+#[tauri::command]
+pub fn set_url(app: AppHandle, url: String, window: Option<String>) -> Result<(), String> {
+    execute(&app, ControlCommand::SetUrl { url, window })
+}
+
+#[tauri::command]
+pub fn set_opacity(app: AppHandle, opacity: f64, window: Option<String>) -> Result<(), String> {
+    execute(&app, ControlCommand::SetOpacity { opacity, window })
+}
+
+#[tauri::command]
+pub fn show_banner(app: AppHandle, text: String, duration_ms: Option<u64>, window: Option<String>) -> Result<(), String> {
+    execute(&app, ControlCommand::ShowBanner { text, duration_ms, window })
+}
+
+#[tauri::command]
+pub fn play_pause(app: AppHandle, window: Option<String>) -> Result<(), String> {
+    execute(&app, ControlCommand::PlayPause { window })
+}
+
+/// Script injected into every page: keeps the page transparent and exposes the control helpers
+pub fn page_script(opacity: f64, extra_style: &str, playing: bool, accessibility: Accessibility) -> String {
+    let motion_style = if accessibility.reduced_motion { REDUCED_MOTION_STYLE } else { "" };
+    let banner_style = if accessibility.high_contrast { HIGH_CONTRAST_BANNER_STYLE } else { BANNER_STYLE };
+    format!(r#"
+        (function() {{
+            if (window.__viberot) {{
+                return;
+            }}
+            let opacity = {opacity};
+            let playing = {playing};
+            // Videos we paused, so resuming doesn't start every video on the page
+            const pausedByUs = new Set();
+
+            function css() {{
+                return `
+                    html,
+                    body {{
+                        opacity: ${{opacity}} !important;
+                        background-color: transparent !important;
+                    }}
+                    div {{
+                        background-color: transparent !important;
+                    }}
+                    {extra_style}
+                    {motion_style}
+                `;
+            }}
+
+            function applyStyle() {{
+                let style = document.getElementById('viberot-style');
+                if (!style) {{
+                    style = document.createElement('style');
+                    style.id = 'viberot-style';
+                    (document.head || document.documentElement).appendChild(style);
+                }}
+                const text = css();
+                if (style.innerHTML !== text) {{
+                    style.innerHTML = text;
+                }}
+            }}
+
+            window.__viberot = {{
+                setOpacity(value) {{
+                    opacity = value;
+                    applyStyle();
+                }},
+                showBanner(text, durationMs) {{
+                    const banner = document.createElement('div');
+                    banner.textContent = text;
+                    banner.style.cssText = 'position:fixed;top:24px;left:50%;transform:translateX(-50%);' +
+                        'z-index:2147483647;padding:12px 20px;border-radius:12px;pointer-events:none;' +
+                        '{banner_style}';
+                    document.documentElement.appendChild(banner);
+                    setTimeout(() => banner.remove(), durationMs);
+                }},
+                playPause() {{
+                    document.querySelectorAll('video').forEach(video => video.paused ? video.play() : video.pause());
+                }},
+                setPlaying(value) {{
+                    playing = value;
+                    if (playing) {{
+                        pausedByUs.forEach(video => video.play());
+                        pausedByUs.clear();
+                    }} else {{
+                        pauseAll();
+                    }}
+                }},
+            }};
+
+            function pauseAll() {{
+                document.querySelectorAll('video').forEach(video => {{
+                    if (!video.paused) {{
+                        video.pause();
+                        pausedByUs.add(video);
+                    }}
+                }});
+            }}
+
+            // Hidden windows get no animation frames, and pages autoplay whenever they like
+            setInterval(() => {{
+                if (!playing) {{
+                    pauseAll();
+                }}
+            }}, 250);
+
+            // Pages may rebuild <head>, so keep re-applying the style
+            function updateStyleOnFrame() {{
+                applyStyle();
+                requestAnimationFrame(updateStyleOnFrame);
+            }}
+            requestAnimationFrame(updateStyleOnFrame);
+        }})();
+    "#)
+}
@@ -0,0 +1,180 @@
+// Daemon mode
+//
+// With `--daemon` the overlay stays resident with its window already loaded but hidden, so it
+// shows up instantly instead of after a multi-second webview cold start. Clients (the VibeRot
+// service) connect to a local socket (a named pipe on Windows) and send the stdin control
+// commands as JSON lines, plus:
+// - `{"cmd": "attach", "id": "..."}` shows the overlay and resumes playback
+// - `{"cmd": "detach", "id": "..."}` releases it; the overlay hides and pauses once nothing is attached
+//
+// IDs attached over a connection are detached when the connection closes, so a crashed client
+// can't leave the overlay on screen.
+//
+// `--prewarm` gives the same hidden start and `attach`/`detach` over stdin only, for overlays
+// started and signalled by the VibeRot service.
+
+use crate::control::{self, ControlCommand};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// IDs currently attached, from all clients
+#[derive(Default)]
+pub struct Attachments {
+    ids: Mutex<HashSet<String>>,
+}
+
+/// Socket path used when `--socket` is not given; the VibeRot service uses the same default
+pub fn default_socket_path() -> String {
+    #[cfg(windows)]
+    {
+        r"\\.\pipe\viberot-overlay".to_string()
+    }
+    #[cfg(not(windows))]
+    {
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        dir.join("viberot-overlay.sock").to_string_lossy().into_owned()
+    }
+}
+
+/// Whether anything is attached, i.e. daemon windows should be visible
+pub fn is_attached(app: &AppHandle) -> bool {
+    app.try_state::<Attachments>()
+        .is_some_and(|attachments| !attachments.ids.lock().unwrap().is_empty())
+}
+
+/// IDs currently attached, e.g. to report which events a dismissed overlay was shown for
+pub fn attached_ids(app: &AppHandle) -> Vec<String> {
+    app.try_state::<Attachments>()
+        .map(|attachments| attachments.ids.lock().unwrap().iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+pub fn attach(app: &AppHandle, id: String) -> Result<(), String> {
+    let attachments = app.try_state::<Attachments>().ok_or("attach needs --daemon or --prewarm")?;
+    let mut ids = attachments.ids.lock().unwrap();
+    let first = ids.is_empty();
+    ids.insert(id.clone());
+    drop(ids);
+    if first {
+        set_visible(app, true);
+    }
+    control::report_visible(Some(&id));
+    Ok(())
+}
+
+pub fn detach(app: &AppHandle, id: &str) -> Result<(), String> {
+    let attachments = app.try_state::<Attachments>().ok_or("detach needs --daemon or --prewarm")?;
+    let mut ids = attachments.ids.lock().unwrap();
+    if ids.remove(id) && ids.is_empty() {
+        drop(ids);
+        set_visible(app, false);
+    }
+    Ok(())
+}
+
+fn set_visible(app: &AppHandle, visible: bool) {
+    for window in app.webview_windows().into_values() {
+        // Pause before hiding so nothing keeps playing in the background
+        if let Err(e) = control::apply_playing(&window, visible) {
+            eprintln!("Error changing playback: {}", e);
+        }
+        let result = if visible { window.show() } else { window.hide() };
+        if let Err(e) = result {
+            eprintln!("Error changing window visibility: {}", e);
+        }
+    }
+}
+
+/// Starts accepting clients on the socket; exits the app if the socket can't be created
+pub fn listen(app: AppHandle, path: String) {
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = serve(app.clone(), &path).await {
+            eprintln!("Error listening on {}: {}", path, e);
+            app.exit(1);
+        }
+    });
+}
+
+/// Removes the socket file on exit
+pub fn cleanup(path: &str) {
+    #[cfg(not(windows))]
+    let _ = std::fs::remove_file(path);
+    #[cfg(windows)]
+    let _ = path;
+}
+
+#[cfg(not(windows))]
+async fn serve(app: AppHandle, path: &str) -> std::io::Result<()> {
+    use tokio::net::{UnixListener, UnixStream};
+
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            "another overlay daemon is already running",
+        ));
+    }
+    // Left behind by a daemon that didn't exit cleanly
+    let _ = std::fs::remove_file(path);
+
+    let listener = UnixListener::bind(path)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tauri::async_runtime::spawn(handle_client(app.clone(), stream));
+    }
+}
+
+#[cfg(windows)]
+async fn serve(app: AppHandle, path: &str) -> std::io::Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    // Fails if another daemon already owns the pipe
+    let mut server = ServerOptions::new().first_pipe_instance(true).create(path)?;
+    loop {
+        server.connect().await?;
+        // Create the next instance before handing this one off, so clients never find no pipe
+        let client = std::mem::replace(&mut server, ServerOptions::new().create(path)?);
+        tauri::async_runtime::spawn(handle_client(app.clone(), client));
+    }
+}
+
+async fn handle_client(app: AppHandle, stream: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(stream).lines();
+    let mut attached = HashSet::new();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+        let command = match serde_json::from_str::<ControlCommand>(&line) {
+            Ok(command) => command,
+            Err(e) => {
+                eprintln!("Ignoring invalid overlay command '{}': {}", line, e);
+                continue;
+            }
+        };
+        match &command {
+            ControlCommand::Attach { id } => {
+                attached.insert(id.clone());
+            }
+            ControlCommand::Detach { id } => {
+                attached.remove(id);
+            }
+            _ => {}
+        }
+        // Window calls block until the main thread has run them
+        let handle = app.clone();
+        let result = tauri::async_runtime::spawn_blocking(move || control::execute(&handle, command)).await;
+        if let Ok(Err(e)) = result {
+            eprintln!("Error running overlay command: {}", e);
+        }
+    }
+
+    for id in attached {
+        let _ = detach(&app, &id);
+    }
+}
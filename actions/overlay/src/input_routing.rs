@@ -0,0 +1,227 @@
+// Input routing between the overlay and the app underneath it
+//
+// - capture: the overlay takes mouse and keyboard input (default)
+// - passthrough: all input goes to the app underneath, the overlay is view-only
+// - hotkey: like passthrough, but a reserved hotkey toggles the overlay between
+//   view-only and interactive. The hotkey is swallowed, every other key passes through.
+//
+// The hotkey is watched with a low-level keyboard hook on Windows and toggles every overlay window
+// at once. Other platforms fall back to passthrough for now.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use tauri::WebviewWindow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    Capture,
+    Passthrough,
+    Hotkey,
+}
+
+impl InputMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "capture" => Some(InputMode::Capture),
+            "passthrough" => Some(InputMode::Passthrough),
+            "hotkey" => Some(InputMode::Hotkey),
+            _ => None,
+        }
+    }
+}
+
+/// A key with modifiers, e.g. `ctrl+alt+v`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotkey {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    /// Windows virtual-key code
+    pub key: u32,
+}
+
+impl Hotkey {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut hotkey = Hotkey { ctrl: false, alt: false, shift: false, key: 0 };
+        for part in spec.split('+').map(|p| p.trim().to_ascii_lowercase()) {
+            match part.as_str() {
+                "ctrl" | "control" => hotkey.ctrl = true,
+                "alt" => hotkey.alt = true,
+                "shift" => hotkey.shift = true,
+                key => {
+                    if hotkey.key != 0 {
+                        return None;
+                    }
+                    hotkey.key = virtual_key(key)?;
+                }
+            }
+        }
+        (hotkey.key != 0).then_some(hotkey)
+    }
+}
+
+fn virtual_key(name: &str) -> Option<u32> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_alphanumeric() {
+            return Some(c.to_ascii_uppercase() as u32);
+        }
+    }
+    match name {
+        "space" => Some(0x20),
+        "esc" | "escape" => Some(0x1B),
+        "tab" => Some(0x09),
+        "pause" => Some(0x13),
+        "scrolllock" => Some(0x91),
+        _ => {
+            let number: u32 = name.strip_prefix('f')?.parse().ok()?;
+            (1..=24).contains(&number).then(|| 0x70 + number - 1)
+        }
+    }
+}
+
+/// Whether the overlays currently take input in hotkey mode
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Applies the input mode to a freshly created overlay window
+pub fn apply(window: &WebviewWindow, mode: InputMode, hotkey: Hotkey) {
+    match mode {
+        InputMode::Capture => {}
+        InputMode::Passthrough => set_interactive(window, false),
+        InputMode::Hotkey => {
+            // Windows opened later follow the current toggle state
+            set_interactive(window, INTERACTIVE.load(Ordering::SeqCst));
+            #[cfg(windows)]
+            windows_hook::install(window.clone(), hotkey);
+            #[cfg(not(windows))]
+            {
+                let _ = hotkey;
+                eprintln!("Hotkey input mode is only supported on Windows, falling back to passthrough");
+            }
+        }
+    }
+}
+
+/// Stops toggling a window that was closed
+pub fn forget(label: &str) {
+    #[cfg(windows)]
+    windows_hook::forget(label);
+    #[cfg(not(windows))]
+    let _ = label;
+}
+
+fn set_interactive(window: &WebviewWindow, interactive: bool) {
+    if let Err(e) = window.set_ignore_cursor_events(!interactive) {
+        eprintln!("Error changing cursor passthrough: {}", e);
+    }
+    if let Err(e) = window.set_focusable(interactive) {
+        eprintln!("Error changing window focusability: {}", e);
+    }
+    if interactive {
+        let _ = window.set_focus();
+    }
+}
+
+#[cfg(windows)]
+mod windows_hook {
+    use super::{set_interactive, Hotkey, INTERACTIVE};
+    use std::sync::atomic::Ordering;
+    use std::sync::{Mutex, OnceLock};
+    use tauri::WebviewWindow;
+    use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+    use windows::Win32::UI::Input::KeyboardAndMouse::{GetAsyncKeyState, VK_CONTROL, VK_MENU, VK_SHIFT};
+    use windows::Win32::UI::WindowsAndMessaging::{
+        CallNextHookEx, GetForegroundWindow, GetMessageW, SetForegroundWindow, SetWindowsHookExW,
+        HC_ACTION, KBDLLHOOKSTRUCT, MSG, WH_KEYBOARD_LL, WM_KEYDOWN, WM_KEYUP, WM_SYSKEYDOWN, WM_SYSKEYUP,
+    };
+
+    struct HookState {
+        windows: Mutex<Vec<WebviewWindow>>,
+        hotkey: Hotkey,
+        /// Window that had focus before the overlay became interactive, as a raw handle
+        previous_foreground: Mutex<isize>,
+    }
+
+    static STATE: OnceLock<HookState> = OnceLock::new();
+
+    /// Adds a window to the ones the hotkey toggles, installing the hook for the first one
+    pub fn install(window: WebviewWindow, hotkey: Hotkey) {
+        let mut first = false;
+        let state = STATE.get_or_init(|| {
+            first = true;
+            HookState { windows: Mutex::new(Vec::new()), hotkey, previous_foreground: Mutex::new(0) }
+        });
+        state.windows.lock().unwrap().push(window);
+        if !first {
+            return;
+        }
+
+        // Low-level hooks are called on the installing thread, which must pump messages
+        std::thread::spawn(|| unsafe {
+            if let Err(e) = SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook), None, 0) {
+                eprintln!("Error installing keyboard hook: {}", e);
+                return;
+            }
+            let mut msg = MSG::default();
+            while GetMessageW(&mut msg, None, 0, 0).as_bool() {}
+        });
+    }
+
+    pub fn forget(label: &str) {
+        if let Some(state) = STATE.get() {
+            state.windows.lock().unwrap().retain(|window| window.label() != label);
+        }
+    }
+
+    fn modifier_down(key: i32) -> bool {
+        // The high bit is set while the key is held
+        let state = unsafe { GetAsyncKeyState(key) };
+        state < 0
+    }
+
+    unsafe extern "system" fn keyboard_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        if code == HC_ACTION as i32 {
+            if let Some(state) = STATE.get() {
+                let event = unsafe { &*(lparam.0 as *const KBDLLHOOKSTRUCT) };
+                let hotkey = state.hotkey;
+                let message = wparam.0 as u32;
+                let is_hotkey = event.vkCode == hotkey.key
+                    && modifier_down(VK_CONTROL.0 as i32) == hotkey.ctrl
+                    && modifier_down(VK_MENU.0 as i32) == hotkey.alt
+                    && modifier_down(VK_SHIFT.0 as i32) == hotkey.shift;
+
+                if is_hotkey {
+                    if message == WM_KEYDOWN || message == WM_SYSKEYDOWN {
+                        toggle(state);
+                    }
+                    // Swallow both key down and up so the app underneath never sees the hotkey
+                    if matches!(message, WM_KEYDOWN | WM_SYSKEYDOWN | WM_KEYUP | WM_SYSKEYUP) {
+                        return LRESULT(1);
+                    }
+                }
+            }
+        }
+        unsafe { CallNextHookEx(None, code, wparam, lparam) }
+    }
+
+    fn toggle(state: &HookState) {
+        let interactive = !INTERACTIVE.fetch_xor(true, Ordering::SeqCst);
+        let mut previous = state.previous_foreground.lock().unwrap();
+        if interactive {
+            *previous = unsafe { GetForegroundWindow() }.0 as isize;
+        }
+
+        // Window calls must not block the hook, which Windows times out after a few hundred ms
+        let windows = state.windows.lock().unwrap().clone();
+        let restore = *previous;
+        std::thread::spawn(move || {
+            for window in &windows {
+                set_interactive(window, interactive);
+            }
+            if !interactive && restore != 0 {
+                unsafe {
+                    let _ = SetForegroundWindow(HWND(restore as *mut _));
+                }
+            }
+        });
+    }
+}
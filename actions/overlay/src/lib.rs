@@ -1,16 +1,67 @@
+mod control;
+mod daemon;
+mod input_routing;
+mod overlay_window;
+
+use control::OverlayState;
+use input_routing::{Hotkey, InputMode};
+use overlay_window::WindowOptions;
 use std::io::{BufRead, BufReader};
 use std::thread;
+use tauri::window::Effect;
 use tauri::Manager;
 use tauri_plugin_cli::CliExt;
 use url::Url;
 
+/// How the process reacts to its last window closing
+struct Lifecycle {
+    /// Keep running with no windows open, until stdin closes (`--no-window`, `--daemon`)
+    keep_alive: bool,
+    /// Socket to clean up on exit in daemon mode
+    daemon_socket: Option<String>,
+}
+
+/// Maps the `--effect` flag to a native window background effect
+fn parse_effect(name: &str) -> Option<Effect> {
+    match name {
+        "acrylic" => Some(Effect::Acrylic),
+        "mica" => Some(Effect::Mica),
+        "blur" => Some(Effect::Blur),
+        // macOS has no acrylic/mica, its closest material is the HUD window vibrancy
+        #[cfg(target_os = "macos")]
+        "vibrancy" => Some(Effect::HudWindow),
+        _ => None,
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_cli::init())
+        .invoke_handler(tauri::generate_handler![
+            control::set_url,
+            control::set_opacity,
+            control::show_banner,
+            control::play_pause,
+        ])
         .setup(|app| {
             let mut overlay_opacity = 0.6;
-            let mut overlay_url = Url::parse("https://www.tiktok.com/foryou").unwrap();
+            // VibeRot's local waiting room page when it serves one, which works offline
+            let mut overlay_url = std::env::var("VIBEROT_WAITING_ROOM_URL").ok()
+                .and_then(|url| Url::parse(&url).ok())
+                .unwrap_or_else(|| Url::parse("https://www.tiktok.com/foryou").unwrap());
+            let mut pip_mode = false;
+            let mut respect_work_area = false;
+            let mut overlay_effect = None;
+            let mut corner_radius = 12.0;
+            let mut input_mode = InputMode::Capture;
+            let mut hotkey = Hotkey::parse("ctrl+alt+v").unwrap();
+            let mut exit_on_stdin_close = false;
+            let mut no_window = false;
+            let mut all_monitors = false;
+            let mut daemon_mode = false;
+            let mut prewarm = false;
+            let mut socket_path = None;
             match app.cli().matches() {
                 // `matches` here is a Struct with { args, subcommand }.
                 // `args` is `HashMap<String, ArgData>` where `ArgData` is a struct with { value, occurrences }.
@@ -32,59 +83,156 @@ pub fn run() {
                         }
                     }
 
+                    if let Some(pip_arg) = matches.args.get("pip") {
+                        pip_mode = pip_arg.value.as_bool().unwrap_or(false);
+                    }
+
+                    if let Some(work_area_arg) = matches.args.get("respect-work-area") {
+                        respect_work_area = work_area_arg.value.as_bool().unwrap_or(false);
+                    }
+
+                    if let Some(effect_arg) = matches.args.get("effect") {
+                        if let Some(effect_str) = effect_arg.value.as_str() {
+                            overlay_effect = parse_effect(effect_str);
+                            if overlay_effect.is_none() && effect_str != "none" {
+                                eprintln!("Unsupported window effect '{}', ignoring", effect_str);
+                            }
+                        }
+                    }
+
+                    if let Some(radius_arg) = matches.args.get("corner-radius") {
+                        if let Some(radius_str) = radius_arg.value.as_str() {
+                            if let Ok(radius) = radius_str.parse::<f64>() {
+                                if radius >= 0.0 {
+                                    corner_radius = radius;
+                                }
+                            }
+                        }
+                    }
+
+                    if let Some(mode_arg) = matches.args.get("input-mode") {
+                        if let Some(mode_str) = mode_arg.value.as_str() {
+                            match InputMode::parse(mode_str) {
+                                Some(mode) => input_mode = mode,
+                                None => eprintln!("Unknown input mode '{}', ignoring", mode_str),
+                            }
+                        }
+                    }
+
+                    if let Some(hotkey_arg) = matches.args.get("hotkey") {
+                        if let Some(hotkey_str) = hotkey_arg.value.as_str() {
+                            match Hotkey::parse(hotkey_str) {
+                                Some(parsed) => hotkey = parsed,
+                                None => eprintln!("Invalid hotkey '{}', using the default", hotkey_str),
+                            }
+                        }
+                    }
+
+                    if let Some(no_window_arg) = matches.args.get("no-window") {
+                        no_window = no_window_arg.value.as_bool().unwrap_or(false);
+                    }
+
+                    if let Some(all_monitors_arg) = matches.args.get("all-monitors") {
+                        all_monitors = all_monitors_arg.value.as_bool().unwrap_or(false);
+                    }
+
+                    if let Some(daemon_arg) = matches.args.get("daemon") {
+                        daemon_mode = daemon_arg.value.as_bool().unwrap_or(false);
+                    }
+
+                    if let Some(prewarm_arg) = matches.args.get("prewarm") {
+                        prewarm = prewarm_arg.value.as_bool().unwrap_or(false);
+                    }
+
+                    if let Some(socket_arg) = matches.args.get("socket") {
+                        socket_path = socket_arg.value.as_str().map(str::to_string);
+                    }
+
                     if let Some(stdin_arg) = matches.args.get("exit-on-stdin-close") {
                         if let Some(stdin_value) = stdin_arg.value.as_bool() {
-                            if stdin_value {
-                                setup_stdin_monitor(app.handle().clone());
-                            }
+                            exit_on_stdin_close = stdin_value;
                         }
                     }
                 }
                 Err(_) => {}
             }
 
+            let options = WindowOptions {
+                url: overlay_url,
+                opacity: overlay_opacity,
+                pip: pip_mode,
+                effect: overlay_effect,
+                corner_radius,
+                input_mode,
+                hotkey,
+                hidden: daemon_mode || prewarm,
+                respect_work_area,
+                accessibility: control::Accessibility::from_env(),
+            };
+            let daemon_socket = daemon_mode.then(|| socket_path.unwrap_or_else(daemon::default_socket_path));
+            app.manage(OverlayState::new(options.clone()));
+            app.manage(Lifecycle { keep_alive: no_window || options.hidden, daemon_socket: daemon_socket.clone() });
+            if options.hidden {
+                app.manage(daemon::Attachments::default());
+            }
+            if let Some(path) = daemon_socket {
+                daemon::listen(app.handle().clone(), path);
+            }
+            setup_stdin_monitor(app.handle().clone(), exit_on_stdin_close);
             setup_ctrlc_handler(app.handle().clone());
 
-            let handle = app.handle().clone();
-            std::thread::spawn(move || {
-                let _ = tauri::WebviewWindowBuilder::new(&handle, "main", tauri::WebviewUrl::External(overlay_url))
-                    .initialization_script(format!(r#"
-                        function updateOpacityOnFrame(timestamp) {{
-                            const style = document.createElement('style');
-                            style.innerHTML = `
-                                html,
-                                body {{
-                                    opacity: {overlay_opacity} !important;
-                                    background-color: transparent !important;
-                                }}
-                                div {{
-                                    background-color: transparent !important;
-                                }}
-                            `;
-                            document.head.appendChild(style);
-
-                            // Request the next animation frame to create a loop
-                            requestAnimationFrame(updateStyleOnFrame);
-                        }};
-                        // Start the loop
-                        requestAnimationFrame(updateOpacityOnFrame);
-                    "#))
-                    .title("VibeRot Overlay")
-                    .transparent(true)
-                    .fullscreen(true)
-                    .resizable(false)
-                    .always_on_top(true)
-                    .decorations(false)
-                    .build()
-                    .unwrap();
-            });
+            if !no_window {
+                let handle = app.handle().clone();
+                std::thread::spawn(move || {
+                    if all_monitors {
+                        overlay_window::open_on_all_monitors(&handle, &options);
+                    } else {
+                        overlay_window::open(&handle, overlay_window::MAIN_LABEL, &options, None, None, None).unwrap();
+                    }
+                });
+            }
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| match event {
+            // Windows are opened and closed on request in these modes, so stay alive without any
+            tauri::RunEvent::ExitRequested { code: None, api, .. } if app.state::<Lifecycle>().keep_alive => {
+                api.prevent_exit();
+            }
+            tauri::RunEvent::Exit => {
+                if let Some(path) = &app.state::<Lifecycle>().daemon_socket {
+                    daemon::cleanup(path);
+                }
+            }
+            _ => {}
+        });
+}
+
+/// Asks DWM for rounded corners (Windows 11; silently ignored on Windows 10)
+#[cfg(windows)]
+pub(crate) fn round_window_corners(window: &tauri::WebviewWindow) {
+    use windows::Win32::Graphics::Dwm::{DwmSetWindowAttribute, DWMWA_WINDOW_CORNER_PREFERENCE, DWMWCP_ROUND};
+
+    let Ok(hwnd) = window.hwnd() else {
+        return;
+    };
+    let preference = DWMWCP_ROUND;
+    let result = unsafe {
+        DwmSetWindowAttribute(
+            hwnd,
+            DWMWA_WINDOW_CORNER_PREFERENCE,
+            &preference as *const _ as *const std::ffi::c_void,
+            std::mem::size_of_val(&preference) as u32,
+        )
+    };
+    if let Err(e) = result {
+        eprintln!("Error rounding window corners: {}", e);
+    }
 }
 
-fn setup_stdin_monitor(app_handle: tauri::AppHandle) {
+/// Reads control commands from stdin, and exits when it closes if `exit_on_close` is set
+fn setup_stdin_monitor(app_handle: tauri::AppHandle, exit_on_close: bool) {
     thread::spawn(move || {
         let stdin = std::io::stdin();
         let mut reader = BufReader::new(stdin.lock());
@@ -97,17 +245,18 @@ fn setup_stdin_monitor(app_handle: tauri::AppHandle) {
                     break;
                 }
                 Ok(_) => {
-                    // Successfully read a line, continue
+                    // Successfully read a line, run it as a control command
+                    control::handle_line(&app_handle, &line);
+                    line.clear();
                 }
                 Err(e) => {
                     // Error reading from stdin (likely closed)
-                    eprintln!("Error reading stdin: {}, terminating application", e);
-                    // Close window first to avoid resource leaks
-                    if let Some(window) = app_handle.get_webview_window("main") {
-                        if let Err(e) = window.close() {
-                            eprintln!("Error closing window: {}", e);
-                        }
+                    if !exit_on_close {
+                        return;
                     }
+                    eprintln!("Error reading stdin: {}, terminating application", e);
+                    // Close windows first to avoid resource leaks
+                    overlay_window::close_all(&app_handle);
         
                     app_handle.exit(1);
                     return;
@@ -116,12 +265,11 @@ fn setup_stdin_monitor(app_handle: tauri::AppHandle) {
         }
 
         // If we exit the loop naturally (stdin closed)
-        // Close window first to avoid resource leaks
-        if let Some(window) = app_handle.get_webview_window("main") {
-            if let Err(e) = window.close() {
-                eprintln!("Error closing window: {}", e);
-            }
+        if !exit_on_close {
+            return;
         }
+        // Close windows first to avoid resource leaks
+        overlay_window::close_all(&app_handle);
 
         app_handle.exit(0);
     });
@@ -129,12 +277,8 @@ fn setup_stdin_monitor(app_handle: tauri::AppHandle) {
 
 fn setup_ctrlc_handler(app_handle: tauri::AppHandle) {
     ctrlc::set_handler(move || {        
-        // Close window first to avoid resource leaks
-        if let Some(window) = app_handle.get_webview_window("main") {
-            if let Err(e) = window.close() {
-                eprintln!("Error closing window: {}", e);
-            }
-        }
+        // Close windows first to avoid resource leaks
+        overlay_window::close_all(&app_handle);
 
         app_handle.exit(0);
     }).expect("Error setting SIGINT handler");
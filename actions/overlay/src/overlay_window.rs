@@ -0,0 +1,261 @@
+// Overlay windows
+//
+// One process can host several overlay windows, each identified by a label:
+// - `main` for the window opened at startup
+// - `monitor-N` for the windows opened with `--all-monitors`
+// - the ID given to an `open` command, e.g. one window per triggered event
+//
+// All windows share the options given on the command line; the URL and monitor can be picked
+// per window.
+//
+// Windows are placed in physical pixels of their monitor once built: logical positions given to
+// the builder are scaled by the DPI of whichever monitor the window starts on, which puts it on the
+// wrong screen, or at the wrong size, when monitors scale differently.
+
+use crate::control::{self, OverlayState};
+use crate::daemon;
+use crate::input_routing::{self, Hotkey, InputMode};
+use tauri::window::{Effect, EffectsBuilder};
+use tauri::{AppHandle, LogicalSize, Manager, Monitor, PhysicalPosition, PhysicalSize, WebviewWindow};
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use url::Url;
+
+/// Size of the picture-in-picture window, portrait like the short-form videos it shows
+const PIP_WIDTH: f64 = 360.0;
+const PIP_HEIGHT: f64 = 640.0;
+/// Gap between the PiP window and the edge of the screen
+const PIP_MARGIN: f64 = 24.0;
+
+/// Label of the window opened at startup
+pub const MAIN_LABEL: &str = "main";
+
+/// Windows being closed on request, by label; any other close is the user dismissing the overlay
+static CLOSING: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Command-line options applied to every window
+#[derive(Clone)]
+pub struct WindowOptions {
+    pub url: Url,
+    pub opacity: f64,
+    pub pip: bool,
+    pub effect: Option<Effect>,
+    pub corner_radius: f64,
+    pub input_mode: InputMode,
+    pub hotkey: Hotkey,
+    /// Keep windows hidden until something attaches (`--daemon`)
+    pub hidden: bool,
+    /// Leave the taskbar or dock uncovered (`--respect-work-area`)
+    pub respect_work_area: bool,
+    pub accessibility: control::Accessibility,
+}
+
+/// Turns an arbitrary ID into a valid window label
+pub fn label_for(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '/') { c } else { '_' })
+        .collect()
+}
+
+/// Opens an overlay window, on the given monitor or the primary one
+///
+/// `id` is the event the window was opened for, reported once the window is first on screen.
+/// Must not be called from the main thread, where building a webview can deadlock on Windows.
+pub fn open(
+    handle: &AppHandle,
+    label: &str,
+    options: &WindowOptions,
+    url: Option<Url>,
+    monitor: Option<usize>,
+    id: Option<String>,
+) -> Result<WebviewWindow, String> {
+    if handle.get_webview_window(label).is_some() {
+        return Err(format!("Overlay window '{}' is already open", label));
+    }
+
+    // Round the page itself in PiP mode so corners are clipped even where the OS can't round the window
+    let pip_style = if options.pip {
+        format!("html {{ clip-path: inset(0 round {}px); }}", options.corner_radius)
+    } else {
+        String::new()
+    };
+    let opacity = handle.state::<OverlayState>().opacity_of(label);
+    let url = url.unwrap_or_else(|| options.url.clone());
+    let visible = !options.hidden || daemon::is_attached(handle);
+    let reported = AtomicBool::new(false);
+    let dismissed_id = id.clone();
+
+    let mut builder = tauri::WebviewWindowBuilder::new(handle, label, tauri::WebviewUrl::External(url))
+        .initialization_script(control::page_script(opacity, &pip_style, visible, options.accessibility))
+        .on_page_load(move |window, payload| {
+            // Navigations reset the page, restore any opacity set at runtime and the playback state
+            if payload.event() == tauri::webview::PageLoadEvent::Finished {
+                let opacity = window.state::<OverlayState>().opacity_of(window.label());
+                let _ = control::apply_opacity(&window, opacity);
+                let visible = window.is_visible().unwrap_or(true);
+                let _ = control::apply_playing(&window, visible);
+                // Hidden windows report when they are attached instead
+                if visible && !reported.swap(true, Ordering::SeqCst) {
+                    control::report_visible(id.as_deref());
+                }
+            }
+        })
+        .title("VibeRot Overlay")
+        .transparent(true)
+        .resizable(false)
+        .always_on_top(true)
+        .decorations(false)
+        // Shown once it's where it belongs
+        .visible(false);
+    if options.pip {
+        // Where it stays if there's no monitor to place it on
+        builder = builder
+            .inner_size(PIP_WIDTH, PIP_HEIGHT)
+            .position(PIP_MARGIN, PIP_MARGIN)
+            .skip_taskbar(true);
+    }
+
+    if let Some(effect) = options.effect {
+        builder = builder.effects(
+            EffectsBuilder::new()
+                .effect(effect)
+                .radius(options.corner_radius) // Only honoured on macOS
+                .build(),
+        );
+    }
+
+    // Keep cookies and site data in the state directory VibeRot gives the action, so logins
+    // survive between runs
+    if let Some(state_dir) = std::env::var_os("VIBEROT_STATE_DIR") {
+        builder = builder.data_directory(std::path::PathBuf::from(state_dir).join("webview"));
+    }
+
+    let window = builder.build().map_err(|e| e.to_string())?;
+    match target_monitor(handle, monitor) {
+        Some(monitor) => place(&window, &monitor, options),
+        // Nothing to place it by; covering the screen it's on is the best left
+        None if !options.pip => window.set_fullscreen(true).map_err(|e| e.to_string())?,
+        None => {}
+    }
+    if visible {
+        window.show().map_err(|e| e.to_string())?;
+    }
+
+    #[cfg(windows)]
+    if options.pip {
+        crate::round_window_corners(&window);
+    }
+    input_routing::apply(&window, options.input_mode, options.hotkey);
+
+    let app = handle.clone();
+    let closed_label = label.to_string();
+    window.on_window_event(move |event| match event {
+        tauri::WindowEvent::CloseRequested { .. } if !is_closing(&closed_label) => {
+            // Daemon windows are shown for whatever is attached
+            match &dismissed_id {
+                Some(id) => control::report_dismissed(Some(id)),
+                None => match daemon::attached_ids(&app).as_slice() {
+                    [] => control::report_dismissed(None),
+                    ids => ids.iter().for_each(|id| control::report_dismissed(Some(id))),
+                },
+            }
+        }
+        tauri::WindowEvent::Destroyed => {
+            CLOSING.lock().unwrap().remove(&closed_label);
+            input_routing::forget(&closed_label);
+            app.state::<OverlayState>().forget(&closed_label);
+        }
+        _ => {}
+    });
+
+    Ok(window)
+}
+
+/// Opens one window per connected monitor, labelled `monitor-N`
+pub fn open_on_all_monitors(handle: &AppHandle, options: &WindowOptions) {
+    let count = handle.available_monitors().map(|monitors| monitors.len()).unwrap_or(0);
+    if count == 0 {
+        eprintln!("No monitors found, opening a single overlay window");
+        if let Err(e) = open(handle, MAIN_LABEL, options, None, None, None) {
+            eprintln!("Error opening overlay window: {}", e);
+        }
+        return;
+    }
+    for index in 0..count {
+        if let Err(e) = open(handle, &format!("monitor-{}", index), options, None, Some(index), None) {
+            eprintln!("Error opening overlay window on monitor {}: {}", index, e);
+        }
+    }
+}
+
+/// Closes the window with the given label
+pub fn close(handle: &AppHandle, label: &str) -> Result<(), String> {
+    let window = handle
+        .get_webview_window(label)
+        .ok_or_else(|| format!("Overlay window '{}' is not open", label))?;
+    CLOSING.lock().unwrap().insert(label.to_string());
+    window.close().map_err(|e| e.to_string())
+}
+
+/// Closes every overlay window, e.g. before exiting
+pub fn close_all(handle: &AppHandle) {
+    for window in handle.webview_windows().into_values() {
+        CLOSING.lock().unwrap().insert(window.label().to_string());
+        if let Err(e) = window.close() {
+            eprintln!("Error closing window: {}", e);
+        }
+    }
+}
+
+fn is_closing(label: &str) -> bool {
+    CLOSING.lock().unwrap().contains(label)
+}
+
+/// Moves and sizes a window for its monitor: covering it (fullscreen, or its work area with
+/// `respect_work_area`), or in PiP mode in the bottom-right corner
+fn place(window: &WebviewWindow, monitor: &Monitor, options: &WindowOptions) {
+    let (origin, size) = if options.respect_work_area {
+        let area = monitor.work_area();
+        (area.position, area.size)
+    } else {
+        (*monitor.position(), *monitor.size())
+    };
+    let (position, size) = if options.pip {
+        let scale = monitor.scale_factor();
+        let pip: PhysicalSize<u32> = LogicalSize::new(PIP_WIDTH, PIP_HEIGHT).to_physical(scale);
+        let margin = (PIP_MARGIN * scale).round() as i32;
+        // Without the work area, clear a taskbar of about the margin's height
+        let bottom_margin = if options.respect_work_area { margin } else { margin * 2 };
+        let position = PhysicalPosition::new(
+            origin.x + size.width as i32 - pip.width as i32 - margin,
+            origin.y + size.height as i32 - pip.height as i32 - bottom_margin,
+        );
+        (position, pip)
+    } else {
+        (origin, size)
+    };
+    // Moving first, as arriving on a monitor with another scale makes the OS resize the window
+    if let Err(e) = window.set_position(position).and_then(|()| window.set_size(size)) {
+        eprintln!("Error placing overlay window on monitor {:?}: {}", monitor.name(), e);
+    }
+    // Borderless fullscreen on the monitor it was just moved to, covering the taskbar too
+    if !options.pip && !options.respect_work_area {
+        if let Err(e) = window.set_fullscreen(true) {
+            eprintln!("Error making overlay window fullscreen: {}", e);
+        }
+    }
+}
+
+fn target_monitor(handle: &AppHandle, index: Option<usize>) -> Option<Monitor> {
+    match index {
+        Some(index) => match handle.available_monitors() {
+            Ok(mut monitors) if index < monitors.len() => Some(monitors.swap_remove(index)),
+            _ => {
+                eprintln!("Monitor {} not found, using the primary monitor", index);
+                handle.primary_monitor().ok().flatten()
+            }
+        },
+        None => handle.primary_monitor().ok().flatten(),
+    }
+}
@@ -0,0 +1,169 @@
+// VibeRot settings app
+// A window for what the terminal otherwise does: rules are listed, turned on and off, edited and
+// tried against command lines through `viberot-service rules`, the history charts come from
+// `recent --json`, and overlay options are previewed by starting the overlay with them. The page
+// in ui/ calls the commands below; nothing here talks to the service but its CLI.
+
+mod service;
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use tauri::{Manager, RunEvent};
+
+/// The overlay started by "Preview", until it's stopped or another one starts
+#[derive(Default)]
+struct Preview(Mutex<Option<Child>>);
+
+impl Preview {
+    fn stop(&self) {
+        if let Some(mut child) = self.0.lock().unwrap().take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// A rule as edited in the page; empty fields are left as they are
+#[derive(Deserialize)]
+struct RuleDraft {
+    name: Option<String>,
+    #[serde(default)]
+    patterns: Vec<String>,
+    when: Option<String>,
+    /// "overlay", "notify", a named action or a program; the current actions are kept without one
+    action: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+impl RuleDraft {
+    /// Arguments of `rules add` and `rules edit`
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(name) = self.name.as_deref().filter(|name| !name.is_empty()) {
+            args.extend(["--name".to_string(), name.to_string()]);
+        }
+        for pattern in self.patterns.iter().filter(|pattern| !pattern.is_empty()) {
+            args.extend(["--command".to_string(), pattern.clone()]);
+        }
+        if let Some(when) = self.when.as_deref().filter(|when| !when.is_empty()) {
+            args.extend(["--when".to_string(), when.to_string()]);
+        }
+        if let Some(action) = self.action.as_deref().filter(|action| !action.is_empty()) {
+            args.extend(["--action".to_string(), action.to_string()]);
+            // `--arg=` so arguments like `--pip` aren't taken for options of the CLI
+            args.extend(self.args.iter().map(|arg| format!("--arg={}", arg)));
+        }
+        args
+    }
+}
+
+/// The rules, the named actions and where `${VIBEROT_ACTIONS}` points
+#[tauri::command(async)]
+fn rules() -> Result<Value, String> {
+    service::json(&["rules", "list", "--json"])
+}
+
+/// Mutes, streaks and what the last config reload changed
+#[tauri::command(async)]
+fn status() -> Result<Value, String> {
+    service::json(&["status", "--json"])
+}
+
+#[tauri::command(async)]
+fn set_enabled(rule: String, enabled: bool) -> Result<String, String> {
+    service::run(&["rules", if enabled { "enable" } else { "disable" }, &rule])
+}
+
+/// Adds a rule, or changes the given one
+#[tauri::command(async)]
+fn save_rule(rule: Option<String>, draft: RuleDraft) -> Result<String, String> {
+    let mut args = match rule {
+        Some(rule) => vec!["rules".to_string(), "edit".to_string(), rule],
+        None => vec!["rules".to_string(), "add".to_string()],
+    };
+    args.extend(draft.args());
+    service::run(&args)
+}
+
+#[tauri::command(async)]
+fn remove_rule(rule: String) -> Result<String, String> {
+    service::run(&["rules", "remove", &rule])
+}
+
+/// How the configured rules, or the draft patterns if there are any, fare against a command line
+#[tauri::command(async)]
+fn test_command(command: String, patterns: Vec<String>, case_insensitive: bool) -> Result<Value, String> {
+    let mut args = vec!["rules".to_string(), "test".to_string(), "--json".to_string()];
+    for pattern in patterns.into_iter().filter(|pattern| !pattern.is_empty()) {
+        args.push(format!("--pattern={}", pattern));
+    }
+    if case_insensitive {
+        args.push("--case-insensitive".to_string());
+    }
+    // After `--`, so a command line starting with a dash isn't taken for an option
+    args.extend(["--".to_string(), command]);
+    service::json(&args)
+}
+
+/// The last commands the service saw, oldest first
+#[tauri::command(async)]
+fn history(limit: usize) -> Result<Value, String> {
+    service::json(&["recent", "--json", "--limit", &limit.to_string()])
+}
+
+/// Starts the overlay with the options being edited, in place of any preview still open
+#[tauri::command(async)]
+fn preview_overlay(preview: tauri::State<'_, Preview>, path: String, actions_dir: Option<String>, args: Vec<String>) -> Result<(), String> {
+    let path = match actions_dir {
+        Some(dir) => PathBuf::from(path.replace("${VIBEROT_ACTIONS}", &dir)),
+        None => PathBuf::from(path),
+    };
+    // Only ever the overlay, whatever the page asks for
+    let is_overlay = path.file_stem().and_then(|stem| stem.to_str()) == Some("viberot-overlay");
+    if !is_overlay || !Path::new(&path).is_file() {
+        return Err(format!("No overlay at {}; `viberot-service setup-actions` puts one there", path.display()));
+    }
+    preview.stop();
+    // Without --exit-on-stdin-close, as there's no service holding its stdin
+    let args = args.iter().filter(|arg| !matches!(arg.as_str(), "--exit-on-stdin-close" | "--daemon" | "--prewarm" | "--no-window"));
+    let child = Command::new(&path)
+        .args(args)
+        .stdin(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Couldn't start the overlay: {}", e))?;
+    *preview.0.lock().unwrap() = Some(child);
+    Ok(())
+}
+
+#[tauri::command(async)]
+fn stop_preview(preview: tauri::State<'_, Preview>) {
+    preview.stop();
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .manage(Preview::default())
+        .invoke_handler(tauri::generate_handler![
+            rules,
+            status,
+            set_enabled,
+            save_rule,
+            remove_rule,
+            test_command,
+            history,
+            preview_overlay,
+            stop_preview,
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building the settings app")
+        .run(|handle, event| {
+            if let RunEvent::Exit = event {
+                handle.state::<Preview>().stop();
+            }
+        });
+}
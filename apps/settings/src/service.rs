@@ -0,0 +1,61 @@
+// Running viberot-service
+// Every setting goes through the service's CLI, which edits config.toml the way `rules add/edit`
+// do from a terminal: comments are kept, invalid edits aren't saved, and the running service picks
+// them up through its config watcher. The CLI is found through `VIBEROT_SERVICE`, next to this
+// app's executable, or on PATH.
+
+use serde_json::Value;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Name of the service executable on this platform
+const SERVICE: &str = if cfg!(windows) { "viberot-service.exe" } else { "viberot-service" };
+
+/// The viberot-service executable to run
+fn binary() -> PathBuf {
+    if let Some(path) = std::env::var_os("VIBEROT_SERVICE") {
+        return PathBuf::from(path);
+    }
+    // Installed or built into the same directory as this app
+    std::env::current_exe().ok()
+        .and_then(|exe| Some(exe.parent()?.join(SERVICE)))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(SERVICE))
+}
+
+/// Runs a CLI command, returning what it printed, or what it complained about if it failed
+pub fn run<S: AsRef<std::ffi::OsStr>>(args: &[S]) -> Result<String, String> {
+    let mut command = Command::new(binary());
+    command.args(args);
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW: no console flashing up for every call
+        command.creation_flags(0x0800_0000);
+    }
+    let output = command.output().map_err(|e| format!("Couldn't run {}: {}", binary().display(), e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // Errors come last as `Error: "..."`, the debug form main returns them in
+        let message = stderr.lines().rev().find_map(|line| line.strip_prefix("Error: "))
+            .map(|message| serde_json::from_str::<String>(message).unwrap_or_else(|_| message.to_string()));
+        Err(message.unwrap_or_else(|| format!("viberot-service exited with {}", output.status)))
+    }
+}
+
+/// Version of the CLI's `--json` documents this app reads
+const OUTPUT_VERSION: u64 = 1;
+
+/// Runs a CLI command with `--json`, returning the `data` of the document it printed
+pub fn json<S: AsRef<std::ffi::OsStr>>(args: &[S]) -> Result<Value, String> {
+    let output = run(args)?;
+    let mut document: Value = serde_json::from_str(&output)
+        .map_err(|e| format!("viberot-service printed something other than JSON: {}", e))?;
+    match document["version"].as_u64() {
+        Some(OUTPUT_VERSION) => Ok(document["data"].take()),
+        version => Err(format!("viberot-service printed version {} of its JSON, this app reads version {}; update one to match the other",
+                               version.map_or("?".to_string(), |version| version.to_string()), OUTPUT_VERSION)),
+    }
+}
@@ -0,0 +1,326 @@
+// Settings page: three tabs over the commands in src/lib.rs
+const { invoke } = window.__TAURI__.core;
+const $ = id => document.getElementById(id);
+
+const OVERLAY = /(^|[\\/])viberot-overlay(\.exe)?$/;
+const KEEP = "", PROGRAM = "\u0000program";
+
+let listing = { rules: [], actions: [], actions_dir: null };
+let selected = null; // Name of the rule in the editor, null for a new one
+
+function show(text, error = false) {
+  const message = $("message");
+  message.textContent = text;
+  message.classList.toggle("error", error);
+}
+
+async function call(command, args = {}) {
+  try {
+    return await invoke(command, args);
+  } catch (e) {
+    show(String(e), true);
+    throw e;
+  }
+}
+
+function el(tag, props = {}, ...children) {
+  const element = Object.assign(document.createElement(tag), props);
+  element.append(...children);
+  return element;
+}
+
+const lines = text => text.split("\n").map(line => line.trim()).filter(Boolean);
+const asList = value => value == null ? [] : Array.isArray(value) ? value : [value];
+const overlayAction = rule => asList(rule.action).find(action => action.type === "exec" && OVERLAY.test(action.path));
+
+// Tabs
+for (const tab of document.querySelectorAll(".tab")) {
+  tab.addEventListener("click", () => {
+    for (const other of document.querySelectorAll(".tab")) {
+      other.classList.toggle("selected", other === tab);
+      $(other.dataset.tab).hidden = other !== tab;
+    }
+    if (tab.dataset.tab === "history") loadHistory();
+  });
+}
+
+// Rules
+async function loadRules() {
+  listing = await call("rules");
+  let muted = new Set();
+  try {
+    const status = await call("status");
+    muted = new Set(Object.keys(status.mutes?.rules ?? {}));
+  } catch (e) {
+    // Only the badges are missing
+  }
+  const list = $("rule-list");
+  list.replaceChildren(...listing.rules.map(({ name, degraded, rule }) => {
+    const toggle = el("input", { type: "checkbox", checked: rule.enabled, title: "On" });
+    toggle.addEventListener("click", async event => {
+      event.stopPropagation();
+      show(await call("set_enabled", { rule: name, enabled: toggle.checked }));
+      await loadRules();
+    });
+    const patterns = asList(rule.command).join(", ") || (rule.when ? `when ${rule.when}` : "");
+    const details = el("div", {}, el("div", { className: "rule-name", textContent: name }),
+                       el("div", { className: "rule-patterns", textContent: patterns }));
+    if (muted.has(name)) details.append(el("div", { className: "badge", textContent: "muted" }));
+    if (degraded.length) details.append(el("div", { className: "badge", textContent: `the probe doesn't report ${degraded.join(" or ")}` }));
+    const item = el("li", { className: rule.enabled ? "" : "off" }, toggle, details);
+    item.classList.toggle("selected", name === selected);
+    item.addEventListener("click", () => edit(name));
+    return item;
+  }));
+  fillActions();
+  fillOverlayRules();
+}
+
+function fillActions() {
+  const choices = [[KEEP, selected ? "Keep its current actions" : "Choose an action…"], ["overlay", "Overlay"], ["notify", "Notification"]];
+  for (const name of listing.actions) choices.push([name, `${name} (from [actions])`]);
+  choices.push([PROGRAM, "Run a program…"]);
+  $("rule-action").replaceChildren(...choices.map(([value, label]) => el("option", { value, textContent: label })));
+  actionChanged();
+}
+
+function actionChanged() {
+  const action = $("rule-action").value;
+  $("program-field").hidden = action !== PROGRAM;
+  $("args-field").hidden = action === KEEP || action === "notify";
+}
+
+function edit(name) {
+  selected = name;
+  const entry = listing.rules.find(rule => rule.name === name);
+  const rule = entry?.rule;
+  $("editor-title").textContent = rule ? name : "New rule";
+  $("rule-name").value = rule?.name ?? "";
+  $("rule-patterns").value = asList(rule?.command).join("\n");
+  $("rule-when").value = rule?.when ?? "";
+  $("rule-program").value = "";
+  $("rule-args").value = "";
+  $("remove-rule").hidden = !rule;
+  for (const item of $("rule-list").children) {
+    item.classList.toggle("selected", item.querySelector(".rule-name").textContent === name);
+  }
+  fillActions();
+  test();
+}
+
+$("new-rule").addEventListener("click", () => edit(null));
+$("rule-action").addEventListener("change", actionChanged);
+
+$("editor").addEventListener("submit", async event => {
+  event.preventDefault();
+  const choice = $("rule-action").value;
+  const action = choice === PROGRAM ? $("rule-program").value.trim() : choice;
+  if (!selected && !action) {
+    show("A new rule needs an action", true);
+    return;
+  }
+  const draft = {
+    name: $("rule-name").value.trim() || null,
+    patterns: lines($("rule-patterns").value),
+    when: $("rule-when").value.trim() || null,
+    action: action || null,
+    args: lines($("rule-args").value),
+  };
+  show(await call("save_rule", { rule: selected, draft }));
+  selected = draft.name ?? selected;
+  await loadRules();
+  edit(selected);
+});
+
+$("remove-rule").addEventListener("click", async () => {
+  if (!selected || !confirm(`Remove the rule '${selected}'?`)) return;
+  show(await call("remove_rule", { rule: selected }));
+  selected = null;
+  await loadRules();
+  edit(null);
+});
+
+// Live testing, a moment after typing stops
+let testTimer = null;
+function test() {
+  clearTimeout(testTimer);
+  testTimer = setTimeout(async () => {
+    const command = $("test-command").value.trim();
+    const patterns = lines($("rule-patterns").value);
+    if (!command) {
+      $("pattern-results").replaceChildren();
+      $("rule-results").replaceChildren();
+      return;
+    }
+    const result = ({ ok, text }) => el("li", { className: ok ? "yes" : "no", textContent: text });
+    const drafts = patterns.length ? await call("test_command", { command, patterns, caseInsensitive: false }) : [];
+    $("pattern-results").replaceChildren(...drafts.map(draft => result({ ok: draft.matched, text: draft.hint ?? draft.pattern })));
+    const rules = await call("test_command", { command, patterns: [], caseInsensitive: false });
+    $("rule-results").replaceChildren(...rules
+      .filter(rule => rule.matched || rule.reason !== "turned off")
+      .map(rule => result({ ok: rule.matched, text: rule.matched ? rule.rule : `${rule.rule}: ${rule.hint ?? rule.reason}` })));
+  }, 250);
+}
+$("test-command").addEventListener("input", test);
+$("rule-patterns").addEventListener("input", test);
+
+// Overlay options, read from and written back to a rule's overlay arguments
+const FLAGS = { "--pip": "overlay-pip", "--respect-work-area": "overlay-work-area", "--all-monitors": "overlay-all-monitors" };
+const VALUES = { "--opacity": "overlay-opacity", "-O": "overlay-opacity", "--url": "overlay-url", "-u": "overlay-url",
+                 "--effect": "overlay-effect", "--corner-radius": "overlay-radius", "--input-mode": "overlay-input" };
+let otherArgs = [];
+
+function fillOverlayRules() {
+  const rules = listing.rules.filter(({ rule }) => overlayAction(rule));
+  const select = $("overlay-rule");
+  const previous = select.value;
+  select.replaceChildren(...rules.map(({ name }) => el("option", { value: name, textContent: name })));
+  if (rules.some(({ name }) => name === previous)) select.value = previous;
+  $("overlay-none").hidden = rules.length > 0;
+  $("overlay-apply").disabled = rules.length === 0;
+  loadOverlayOptions();
+}
+
+function loadOverlayOptions() {
+  const entry = listing.rules.find(({ name }) => name === $("overlay-rule").value);
+  const args = entry ? [...(overlayAction(entry.rule).args ?? [])] : [];
+  for (const id of Object.values(FLAGS)) $(id).checked = false;
+  for (const id of new Set(Object.values(VALUES))) $(id).value = "";
+  $("overlay-opacity").value = "0.6";
+  otherArgs = [];
+  while (args.length) {
+    const arg = args.shift();
+    const [flag, inline] = arg.startsWith("--") && arg.includes("=") ? arg.split(/=(.*)/s) : [arg, null];
+    if (FLAGS[flag]) $(FLAGS[flag]).checked = true;
+    else if (VALUES[flag]) $(VALUES[flag]).value = inline ?? args.shift() ?? "";
+    else if (flag !== "--exit-on-stdin-close") otherArgs.push(arg);
+  }
+  $("overlay-opacity-value").textContent = $("overlay-opacity").value;
+}
+
+function overlayArgs() {
+  const args = [...otherArgs];
+  for (const [flag, id] of Object.entries(FLAGS)) if ($(id).checked) args.push(flag);
+  for (const [flag, id] of [["--opacity", "overlay-opacity"], ["--url", "overlay-url"], ["--effect", "overlay-effect"],
+                            ["--corner-radius", "overlay-radius"], ["--input-mode", "overlay-input"]]) {
+    const value = $(id).value.trim();
+    if (value && !(flag === "--opacity" && value === "0.6")) args.push(flag, value);
+  }
+  return args;
+}
+
+$("overlay-rule").addEventListener("change", loadOverlayOptions);
+$("overlay-opacity").addEventListener("input", () => { $("overlay-opacity-value").textContent = $("overlay-opacity").value; });
+
+$("overlay-preview").addEventListener("click", async () => {
+  const entry = listing.rules.find(({ name }) => name === $("overlay-rule").value);
+  const path = entry ? overlayAction(entry.rule).path : "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay";
+  await call("preview_overlay", { path, actionsDir: listing.actions_dir, args: overlayArgs() });
+  show("Previewing the overlay");
+});
+$("overlay-stop").addEventListener("click", async () => {
+  await call("stop_preview");
+  show("");
+});
+
+$("overlay-form").addEventListener("submit", async event => {
+  event.preventDefault();
+  const rule = $("overlay-rule").value;
+  // A named action's arguments replace its own, so they need what the built-in overlay adds
+  const named = listing.actions.includes("overlay");
+  const args = named ? ["--exit-on-stdin-close", ...overlayArgs()] : overlayArgs();
+  show(await call("save_rule", { rule, draft: { action: "overlay", args } }));
+  await loadRules();
+});
+
+// History
+const DAY = 86400;
+
+function barChart(container, labels, series, format = String) {
+  const width = 480, height = 160, top = 10, bottom = 22, left = 32;
+  const max = Math.max(1, ...labels.map((_, i) => series.reduce((sum, s) => sum + s.values[i], 0)));
+  const slot = (width - left) / Math.max(1, labels.length), bar = slot * 0.7;
+  const svg = document.createElementNS("http://www.w3.org/2000/svg", "svg");
+  svg.setAttribute("viewBox", `0 0 ${width} ${height}`);
+  const add = (tag, attrs, text) => {
+    const node = document.createElementNS("http://www.w3.org/2000/svg", tag);
+    for (const [name, value] of Object.entries(attrs)) node.setAttribute(name, value);
+    if (text !== undefined) node.textContent = text;
+    svg.append(node);
+    return node;
+  };
+  add("text", { x: 0, y: top + 8 }, format(max));
+  add("text", { x: 0, y: height - bottom }, "0");
+  labels.forEach((label, i) => {
+    let y = height - bottom;
+    for (const s of series) {
+      const h = (s.values[i] / max) * (height - top - bottom);
+      if (h > 0) {
+        add("rect", { class: `bar ${s.className ?? ""}`, x: left + i * slot, y: y - h, width: bar, height: h, rx: 2 })
+          .append(Object.assign(document.createElementNS("http://www.w3.org/2000/svg", "title"), { textContent: `${label}: ${format(s.values[i])} ${s.name}` }));
+      }
+      y -= h;
+    }
+    if (labels.length <= 14 || i % 2 === 0) add("text", { x: left + i * slot, y: height - 6 }, label);
+  });
+  container.replaceChildren(svg);
+}
+
+function rankChart(container, counts, classes = {}) {
+  const entries = Object.entries(counts).sort((a, b) => b[1] - a[1]).slice(0, 8);
+  if (!entries.length) {
+    container.replaceChildren(el("p", { className: "hint", textContent: "Nothing yet" }));
+    return;
+  }
+  const max = entries[0][1];
+  container.replaceChildren(...entries.map(([name, count]) => {
+    const bar = el("div");
+    bar.style.cssText = `height: 8px; border-radius: 4px; background: var(--${classes[name] ?? "accent"}); width: ${(count / max) * 100}%`;
+    return el("div", { style: "margin-bottom: 6px" }, el("div", { textContent: `${name}  ${count}` }), bar);
+  }));
+}
+
+async function loadHistory() {
+  const events = await call("history", { limit: 10000 });
+  const started = events.filter(event => event.event === "started");
+  if (!started.length) {
+    $("history-range").textContent = "The service hasn't seen any commands yet.";
+  } else {
+    const since = new Date(started[0].at * 1000).toLocaleString();
+    $("history-range").textContent = `The last ${started.length} commands, since ${since}. Set recent_events in the config to keep more.`;
+  }
+
+  const today = Math.floor(Date.now() / 1000 / DAY);
+  const days = Array.from({ length: 14 }, (_, i) => today - 13 + i);
+  const labels = days.map(day => new Date(day * DAY * 1000).toLocaleDateString(undefined, { day: "numeric", month: "numeric" }));
+  const index = at => days.indexOf(Math.floor(at / DAY));
+  const withActions = days.map(() => 0), without = days.map(() => 0), waiting = days.map(() => 0);
+  const rules = {}, results = {};
+  const running = new Map();
+  for (const event of events) {
+    const day = index(event.at);
+    if (event.event === "started") {
+      const acted = typeof event.decision === "object" && "started" in event.decision;
+      if (day >= 0) (acted ? withActions : without)[day] += 1;
+      if (acted) running.set(event.pid, event.at);
+      for (const rule of event.rules) if (rule.matched) rules[rule.rule] = (rules[rule.rule] ?? 0) + 1;
+    } else if (event.event === "ended" && running.has(event.pid)) {
+      const start = running.get(event.pid);
+      running.delete(event.pid);
+      if (index(start) >= 0) waiting[index(start)] += (event.at - start) / 60;
+    } else if (event.event === "action_result") {
+      results[event.outcome] = (results[event.outcome] ?? 0) + 1;
+    } else if (event.event === "action_failed") {
+      results["couldn't start"] = (results["couldn't start"] ?? 0) + 1;
+    }
+  }
+  barChart($("chart-commands"), labels, [
+    { name: "with actions", values: withActions },
+    { name: "without", values: without, className: "secondary" },
+  ]);
+  barChart($("chart-waiting"), labels, [{ name: "minutes", values: waiting }], value => String(Math.round(value)));
+  rankChart($("chart-rules"), rules);
+  rankChart($("chart-results"), results, { failed: "failure", "couldn't start": "failure" });
+}
+
+loadRules().then(() => edit(null));
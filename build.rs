@@ -3,13 +3,18 @@ extern crate winres;
 
 #[cfg(target_os = "windows")]
 fn main() {
+    // Only the ETW probe needs the service to run elevated. `highestAvailable` rather than
+    // `requireAdministrator`, so standard users can run a per-user service subscribed to the broker.
+    if std::env::var_os("CARGO_FEATURE_ETW").is_none() {
+        return;
+    }
     let mut res = winres::WindowsResource::new();
     res.set_manifest(r#"
 <assembly xmlns="urn:schemas-microsoft-com:asm.v1" manifestVersion="1.0">
 <trustInfo xmlns="urn:schemas-microsoft-com:asm.v3">
     <security>
         <requestedPrivileges>
-            <requestedExecutionLevel level="requireAdministrator" uiAccess="false" />
+            <requestedExecutionLevel level="highestAvailable" uiAccess="false" />
         </requestedPrivileges>
     </security>
 </trustInfo>
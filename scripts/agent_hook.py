@@ -0,0 +1,135 @@
+#!/usr/bin/env python3
+# VibeRot hook for coding-agent CLIs
+# Tells the service's `[agent_hooks]` when an agent run starts and finishes:
+#   Gemini CLI: run as `python3 agent_hook.py gemini` from the BeforeAgent and AfterAgent hooks,
+#               which pass the hook's JSON on stdin
+#   Cursor:     run as `python3 agent_hook.py cursor` from the beforeSubmitPrompt and stop hooks
+#               in ~/.cursor/hooks.json, which pass the hook's JSON on stdin as well
+#   Codex CLI:  `notify = ["python3", "/path/to/agent_hook.py", "codex"]`, which appends the
+#               notification's JSON as the last argument
+# It never fails or holds up the agent: without a running service it does nothing.
+
+import json
+import os
+import re
+import socket
+import sys
+
+SERVICE_TIMEOUT = 1.0
+PROMPT_CHARS = 2000
+
+
+def endpoint():
+    data_dir = os.environ.get("VIBEROT_PREFIX") or os.path.join(os.path.expanduser("~"), ".viberot")
+    try:
+        with open(os.path.join(data_dir, ".agents"), encoding="utf-8") as f:
+            return f.readline().strip() or None
+    except OSError:
+        return None
+
+
+def send(message):
+    path = endpoint()
+    if not path:
+        return
+    line = (json.dumps(message) + "\n").encode("utf-8")
+    if path.startswith("\\\\.\\pipe\\"):
+        with open(path, "wb") as pipe:
+            pipe.write(line)
+        return
+    with socket.socket(socket.AF_UNIX, socket.SOCK_STREAM) as sock:
+        sock.settimeout(SERVICE_TIMEOUT)
+        sock.connect(path)
+        sock.sendall(line)
+
+
+def codex_model():
+    """The model in ~/.codex/config.toml, if it names one"""
+    codex_home = os.environ.get("CODEX_HOME") or os.path.join(os.path.expanduser("~"), ".codex")
+    try:
+        with open(os.path.join(codex_home, "config.toml"), encoding="utf-8") as f:
+            for line in f:
+                if line.lstrip().startswith("["):
+                    break
+                match = re.match(r'\s*model\s*=\s*"([^"]+)"', line)
+                if match:
+                    return match.group(1)
+    except OSError:
+        pass
+    return None
+
+
+def gemini_message(hook):
+    events = {"BeforeAgent": "started", "AfterAgent": "finished"}
+    event = events.get(hook.get("hook_event_name"))
+    if event is None:
+        return None
+    return {
+        "agent": "gemini",
+        "event": event,
+        "session": hook.get("session_id"),
+        "model": hook.get("model") or os.environ.get("GEMINI_MODEL"),
+        "prompt": (hook.get("prompt") or "")[:PROMPT_CHARS] or None,
+        "working_directory": hook.get("cwd") or os.getcwd(),
+    }
+
+
+def cursor_message(hook):
+    event = {"beforeSubmitPrompt": "started", "stop": "finished"}.get(hook.get("hook_event_name"))
+    if event is None:
+        return None
+    # The first folder of the workspace is the project
+    workspace_roots = hook.get("workspace_roots") or []
+    return {
+        "agent": "cursor",
+        "event": event,
+        "session": hook.get("conversation_id"),
+        "model": hook.get("model"),
+        "prompt": (hook.get("prompt") or "")[:PROMPT_CHARS] or None,
+        "working_directory": workspace_roots[0] if workspace_roots else os.getcwd(),
+        "exit_code": {"completed": 0, "error": 1}.get(hook.get("status")),
+    }
+
+
+def codex_message(notification):
+    if notification.get("type") != "agent-turn-complete":
+        return None
+    prompts = notification.get("input-messages") or []
+    return {
+        "agent": "codex",
+        "event": "finished",
+        "session": notification.get("thread-id"),
+        "model": codex_model(),
+        "prompt": (prompts[-1] if prompts else "")[:PROMPT_CHARS] or None,
+        "working_directory": notification.get("cwd") or os.getcwd(),
+    }
+
+
+def main():
+    if len(sys.argv) < 2:
+        print("usage: agent_hook.py gemini|cursor|codex [notification JSON]", file=sys.stderr)
+        return
+    agent = sys.argv[1]
+    try:
+        if agent == "gemini":
+            message = gemini_message(json.load(sys.stdin))
+        elif agent == "cursor":
+            # Cursor waits for the hook's answer before sending the prompt
+            hook = json.load(sys.stdin)
+            if hook.get("hook_event_name") == "beforeSubmitPrompt":
+                print(json.dumps({"continue": True}), flush=True)
+            message = cursor_message(hook)
+        elif agent == "codex":
+            message = codex_message(json.loads(sys.argv[-1]))
+        else:
+            print(f"agent_hook.py doesn't know the agent '{agent}'", file=sys.stderr)
+            return
+        if message:
+            send(message)
+    except (OSError, ValueError):
+        # The service isn't running, or the agent sent something unexpected
+        pass
+
+
+if __name__ == "__main__":
+    main()
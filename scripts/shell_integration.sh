@@ -33,15 +33,76 @@ if [[ -z "$ZSH_VERSION" && -z "$bash_preexec_imported" ]]; then
     return 1
 fi
 # Read socket path from config file
-_viberot_config_file="$HOME/.viberot/.socket"
+_viberot_config_file="${VIBEROT_PREFIX:-$HOME/.viberot}/.socket"
+# Changes each time the service starts
+_viberot_instance_file="${VIBEROT_PREFIX:-$HOME/.viberot}/.shell-instance"
 if [[ ! -f "$_viberot_config_file" ]]; then
     # Fail silently because the user may not always want to enable VibeRot
     return 0
 fi
 
 # Read the socket path from the config file
-_viberot_socket_path=$(cat "$_viberot_config_file" 2>/dev/null | head -n 1 | tr -d '\n\r')
-if [[ -z "$_viberot_socket_path" ]]; then
+# The service may listen on several sockets (one per line); use the first one reachable from here.
+# Shells inside sandboxes (Flatpak, Snap, containers) prefer the localhost TCP fallback ("tcp:host:port").
+_viberot_in_sandbox=""
+_viberot_sandbox_name=""
+if [[ -f /.flatpak-info || -n "$FLATPAK_ID" ]]; then
+    _viberot_in_sandbox="flatpak"
+    _viberot_sandbox_name="${FLATPAK_ID:-<terminal app id>}"
+elif [[ -n "$SNAP" ]]; then
+    _viberot_in_sandbox="snap"
+    _viberot_sandbox_name="${SNAP_NAME:-<snap name>}"
+elif [[ -n "$container" ]]; then
+    _viberot_in_sandbox="container"
+    _viberot_sandbox_name="$container"
+elif [[ -f /run/.containerenv ]]; then
+    _viberot_in_sandbox="container"
+    _viberot_sandbox_name="podman"
+elif [[ -f /.dockerenv ]]; then
+    _viberot_in_sandbox="container"
+    _viberot_sandbox_name="docker"
+fi
+_viberot_socket_path=""
+_viberot_tcp_address=""
+while IFS= read -r _viberot_candidate || [[ -n "$_viberot_candidate" ]]; do
+    _viberot_candidate="${_viberot_candidate%$'\r'}"
+    [[ -z "$_viberot_candidate" ]] && continue
+    if [[ "$_viberot_candidate" == tcp:* ]]; then
+        _viberot_tcp_address="${_viberot_candidate#tcp:}"
+        continue
+    fi
+    if [[ -z "$_viberot_socket_path" ]] || [[ -S "$_viberot_candidate" && ! -S "$_viberot_socket_path" ]]; then
+        _viberot_socket_path="$_viberot_candidate"
+    fi
+done < "$_viberot_config_file"
+unset _viberot_candidate
+if [[ -n "$_viberot_tcp_address" ]] && [[ -n "$_viberot_in_sandbox" || ! -S "$_viberot_socket_path" ]]; then
+    _viberot_socket_path=""
+fi
+if [[ -n "$_viberot_in_sandbox" && -z "$_viberot_tcp_address" && ! -S "$_viberot_socket_path" ]]; then
+    # Only this shell can tell it's sandboxed, so it explains why the service can't be reached, with
+    # the instructions the service left for each sandbox in its language. Once per terminal: shells
+    # started from this one inherit VIBEROT_SANDBOX_HINTED.
+    _viberot_hints_file="${VIBEROT_PREFIX:-$HOME/.viberot}/.sandbox-hints"
+    if [[ -z "$VIBEROT_SANDBOX_HINTED" && -f "$_viberot_hints_file" ]]; then
+        _viberot_section=""
+        echo >&2
+        while IFS= read -r _viberot_line || [[ -n "$_viberot_line" ]]; do
+            if [[ "$_viberot_line" == \[*\] ]]; then
+                _viberot_section="${_viberot_line#\[}"
+                _viberot_section="${_viberot_section%\]}"
+            elif [[ "$_viberot_section" == "$_viberot_in_sandbox" ]]; then
+                printf '%s\n' "${_viberot_line//"{name}"/$_viberot_sandbox_name}" >&2
+            fi
+        done < "$_viberot_hints_file"
+        echo >&2
+        export VIBEROT_SANDBOX_HINTED=1
+        unset _viberot_section _viberot_line
+    fi
+    unset _viberot_hints_file
+    return 0
+fi
+if [[ -z "$_viberot_socket_path" && -z "$_viberot_tcp_address" ]]; then
     # Fail silently if socket path is empty
     return 0
 fi
@@ -62,36 +123,258 @@ _viberot_base64_encode() {
 # Function to communicate with VibeRot service
 _viberot_send_message() {
     local message="$1"
-    # Send single-line JSON message terminated with newline
+    # Send JSON messages, one per line, terminated with newline
+    if [[ -z "$_viberot_socket_path" ]]; then
+        local host="${_viberot_tcp_address%:*}"
+        local port="${_viberot_tcp_address##*:}"
+        if command -v nc >/dev/null 2>&1; then
+            printf '%s\n' "$message" | timeout 5 nc -w 0 "$host" "$port" 2>/dev/null || true disown
+        elif command -v socat >/dev/null 2>&1; then
+            printf '%s\n' "$message" | timeout 5 socat - TCP:"$host":"$port" 2>/dev/null || true disown
+        fi
+        return 0
+    fi
     if command -v nc >/dev/null 2>&1; then
-        printf '%s\n' "$message" | nc -w 0 -U "$_viberot_socket_path" 2>/dev/null || true disown
+        printf '%s\n' "$message" | timeout 5 nc -w 0 -U "$_viberot_socket_path" 2>/dev/null || true disown
     elif command -v socat >/dev/null 2>&1; then
-        printf '%s\n' "$message" | socat - UNIX-CONNECT:"$_viberot_socket_path" 2>/dev/null || true disown
+        printf '%s\n' "$message" | timeout 5 socat - UNIX-CONNECT:"$_viberot_socket_path" 2>/dev/null || true disown
     else
         echo "Error: Neither nc nor socat command found" >&2
         exit 1
     fi
 }
 
+# The running command's start as a CommandInFlight message, and the service instance it was sent to
+_viberot_in_flight=""
+_viberot_in_flight_instance=""
+
+# Whether the service restarted since the running command's start was sent, and missed it
+_viberot_restarted() {
+    [[ -n "$_viberot_in_flight" ]] || return 1
+    local instance=""
+    IFS= read -r instance 2>/dev/null < "$_viberot_instance_file"
+    [[ "$instance" != "$_viberot_in_flight_instance" ]]
+}
+
+# Sends the running command's start again if the service restarted since it was sent
+_viberot_replay_if_restarted() {
+    if _viberot_restarted; then
+        _viberot_send_message "$_viberot_in_flight"
+        IFS= read -r _viberot_in_flight_instance 2>/dev/null < "$_viberot_instance_file"
+    fi
+}
+
+# Builds a message into $_viberot_json: `start <command> <directory> <pid> <started at>`,
+# `end <exit code>` or `detached <pid>`. A restarted service gets the start of the command that
+# ends or is detached first.
+_viberot_message() {
+    local replay=
+    case "$1" in
+        start)
+            # Base64 encode values that may contain special characters
+            local encoded_command="$(_viberot_base64_encode "$2")"
+            local encoded_pwd="$(_viberot_base64_encode "$3")"
+            local pid_field=""
+            if [[ -n "$4" ]]; then
+                pid_field=",\"pid\":$4"
+            fi
+            local tty_field=""
+            if [[ -n "$_viberot_tty" ]]; then
+                tty_field=",\"tty\":\"$_viberot_tty\""
+            fi
+            local time_field=""
+            if [[ -n "$5" ]]; then
+                time_field=",\"started_at\":$5"
+            fi
+            _viberot_json="{\"session_id\":\"$$\",\"event_type\":\"CommandStart\",\"shell_pid\":$$${pid_field}${tty_field}${time_field},\"command_b64\":\"$encoded_command\",\"working_directory_b64\":\"$encoded_pwd\",\"environment\":{$_viberot_environment}}"
+            _viberot_in_flight="${_viberot_json/\"CommandStart\"/\"CommandInFlight\"}"
+            _viberot_in_flight_instance=""
+            IFS= read -r _viberot_in_flight_instance 2>/dev/null < "$_viberot_instance_file"
+            return 0
+            ;;
+        end)
+            # The service only needs to know it ended, so it doesn't start anything for it
+            _viberot_restarted && replay="${_viberot_in_flight%\}},\"exit_code\":$2}"
+            _viberot_json="{\"session_id\":\"$$\",\"event_type\":\"CommandEnd\",\"exit_code\":$2}"
+            ;;
+        detached)
+            _viberot_restarted && replay="$_viberot_in_flight"
+            # Sent to the background (`cmd &`): VibeRot follows the job's PID instead
+            _viberot_json="{\"session_id\":\"$$\",\"event_type\":\"CommandDetached\",\"pid\":$2}"
+            ;;
+    esac
+    _viberot_in_flight=""
+    if [[ -n "$replay" ]]; then
+        _viberot_json="$replay"$'\n'"$_viberot_json"
+    fi
+}
+
+# The hooks only write records to a pipe, so no base64, nc or subshell delays the prompt: a
+# background emitter, started with the first command, turns them into messages and sends those
+# that queued up meanwhile over one connection. The pipe is a FIFO only this shell and the emitter
+# hold open; its file is removed right away. Shells without `{fd}` redirections (bash before 4.1)
+# send each message themselves.
+_viberot_emitter_fd=""
+_viberot_emitter_pid=""
+_viberot_async=1
+if [[ -n "$BASH_VERSION" ]] && (( BASH_VERSINFO[0] < 4 || (BASH_VERSINFO[0] == 4 && BASH_VERSINFO[1] < 1) )); then
+    _viberot_async=""
+fi
+
+# Reads the rest of a record from stdin and adds its message to $_viberot_batch
+_viberot_emitter_record() {
+    local kind="$1" command directory pid started_at value
+    case "$kind" in
+        start)
+            IFS= read -r -d $'\0' command
+            IFS= read -r -d $'\0' directory
+            IFS= read -r -d $'\0' pid
+            IFS= read -r -d $'\0' started_at
+            _viberot_message start "$command" "$directory" "$pid" "$started_at"
+            ;;
+        end|detached)
+            IFS= read -r -d $'\0' value
+            _viberot_message "$kind" "$value"
+            ;;
+        *)
+            return 0
+            ;;
+    esac
+    _viberot_batch="${_viberot_batch:+$_viberot_batch$'\n'}$_viberot_json"
+}
+
+_viberot_emitter() {
+    local kind before
+    while :; do
+        before=$SECONDS
+        if ! IFS= read -r -d $'\0' -t 30 kind; then
+            # Gone along with the shell, or the pipe broke (no wait): the shell starts another
+            if (( SECONDS - before < 1 )) || ! kill -0 $$ 2>/dev/null; then
+                return 0
+            fi
+            # A service started meanwhile picks up the running command before it ends
+            _viberot_replay_if_restarted
+            continue
+        fi
+        _viberot_batch=""
+        _viberot_emitter_record "$kind"
+        # Records written while the last ones were being encoded go along with them
+        while IFS= read -r -d $'\0' -t 0.05 kind 2>/dev/null; do
+            _viberot_emitter_record "$kind"
+        done
+        _viberot_send_message "$_viberot_batch"
+    done
+}
+
+# Starts the emitter unless it is running; fails where there can't be one
+_viberot_emitter_ensure() {
+    [[ -n "$_viberot_async" ]] || return 1
+    if [[ -n "$_viberot_emitter_pid" ]] && kill -0 "$_viberot_emitter_pid" 2>/dev/null; then
+        return 0
+    fi
+    # After the emitter died, records still in the pipe go to the next one
+    if [[ -z "$_viberot_emitter_fd" ]]; then
+        local fifo="${TMPDIR:-/tmp}/viberot-$$-$RANDOM.fifo"
+        if ! mkfifo -m 600 "$fifo" 2>/dev/null; then
+            _viberot_async=""
+            return 1
+        fi
+        # Read-write, so opening it doesn't wait for a reader and writes never find none
+        exec {_viberot_emitter_fd}<>"$fifo"
+        rm -f "$fifo"
+    fi
+    if [[ -n "$ZSH_VERSION" ]]; then
+        _viberot_emitter <&$_viberot_emitter_fd >/dev/null 2>&1 &!
+        _viberot_emitter_pid=$!
+    else
+        # Without job control, bash doesn't announce the job
+        local monitor=""
+        if [[ $- == *m* ]]; then
+            monitor=1
+            set +m
+        fi
+        _viberot_emitter <&"$_viberot_emitter_fd" >/dev/null 2>&1 &
+        _viberot_emitter_pid=$!
+        disown "$_viberot_emitter_pid" 2>/dev/null
+        if [[ -n "$monitor" ]]; then
+            set -m
+        fi
+    fi
+    return 0
+}
+
+# Reports a record to VibeRot, through the emitter where there is one
+_viberot_report() {
+    if _viberot_emitter_ensure; then
+        if [[ "$1" == start ]]; then
+            printf 'start\0%s\0%s\0%s\0%s\0' "$2" "$3" "$4" "$5" >&"$_viberot_emitter_fd" 2>/dev/null && return 0
+        else
+            printf '%s\0%s\0' "$1" "$2" >&"$_viberot_emitter_fd" 2>/dev/null && return 0
+        fi
+    fi
+    _viberot_message "$@"
+    _viberot_send_message "$_viberot_json"
+}
+
+# $EPOCHSECONDS, when commands started; built into bash 5, a module in zsh
+if [[ -n "$ZSH_VERSION" ]]; then
+    zmodload zsh/datetime 2>/dev/null
+fi
+
+# Terminal the shell runs on, where VibeRot can show progress
+_viberot_tty="$(tty 2>/dev/null)" || _viberot_tty=""
+# tmux pane the shell runs in, where `bell` actions show their text
+_viberot_environment=""
+if [[ -n "$TMUX" && -n "$TMUX_PANE" ]]; then
+    _viberot_environment="\"TMUX\":\"$TMUX\",\"TMUX_PANE\":\"$TMUX_PANE\""
+fi
+
 # This flag allows precmd to determine if a command is actually executed
 _viberot_last_command=""
+# PID of the last background job when the command started, to spot commands sent to the background
+_viberot_background_pid=""
+
+# Reports the start of a command; the second argument is its real PID, if known
+_viberot_command_start() {
+    _viberot_report start "$1" "$PWD" "$2" "$EPOCHSECONDS"
+}
+
+# Runs a command with its real PID reported to VibeRot, so actions can check whether it is
+# still alive, e.g. `viberot_track cargo build`. Only works for external commands.
+viberot_track() {
+    (
+        if [[ -n "$ZSH_VERSION" ]]; then
+            zmodload zsh/system 2>/dev/null
+            _viberot_command_start "$*" "${sysparams[pid]}"
+        else
+            _viberot_command_start "$*" "$BASHPID"
+        fi
+        # The subshell's PID becomes the command's PID
+        exec "$@"
+    )
+}
 
 _viberot_pre_command_hook() {
     if [[ "$1" != _viberot_* ]] && [ -n "$1" ]; then
+        # Before noting `$!`, which starting the emitter changes
+        _viberot_emitter_ensure
         _viberot_last_command="$1"
-        # Base64 encode values that may contain special characters
-        local encoded_command="$(_viberot_base64_encode "$1")"
-        local encoded_pwd="$(_viberot_base64_encode "$PWD")"
-        local json_msg="{\"session_id\":\"$$\",\"event_type\":\"CommandStart\",\"command_b64\":\"$encoded_command\",\"working_directory_b64\":\"$encoded_pwd\",\"environment\":{}}"
-        _viberot_send_message "$json_msg"
+        _viberot_background_pid="$!"
+        # viberot_track reports the start itself, with the real PID
+        if [[ "$1" != viberot_track\ * ]]; then
+            _viberot_command_start "$1" ""
+        fi
     fi
 }
 
 _viberot_post_command_hook() {
+    local exit_code=$?
     if [[ -n "$_viberot_last_command" ]]; then
-        local exit_code=$?
-        local json_msg="{\"session_id\":\"$$\",\"event_type\":\"CommandEnd\",\"exit_code\":$exit_code}"
-        _viberot_send_message "$json_msg"
+        if [[ -n "$!" && "$!" != "$_viberot_background_pid" ]]; then
+            _viberot_report detached "$!"
+        else
+            _viberot_report end "$exit_code"
+        fi
     fi
     _viberot_last_command=""
 }
@@ -0,0 +1,305 @@
+// Action environment contract
+// Every environment variable VibeRot passes to actions is declared here, so the variables set at
+// spawn time and the schema printed by `viberot-service env-schema` can't drift apart.
+// Names are `<prefix>_<suffix>`, with the prefix configurable via `action_env_prefix`.
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::config::AccessibilityConfig;
+use crate::platform::ProcessEvent;
+
+/// Prefix used when `action_env_prefix` is not configured
+pub const DEFAULT_PREFIX: &str = "VIBEROT";
+
+/// Checks an `action_env_prefix` makes names shells and scripts can use: `[A-Z_][A-Z0-9_]*`
+pub fn validate_prefix(prefix: &str) -> Result<(), String> {
+    let mut chars = prefix.chars();
+    let valid = chars.next().is_some_and(|first| first.is_ascii_uppercase() || first == '_')
+        && chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        return Err(format!("`action_env_prefix` must be upper-case letters, digits and underscores, not starting with a digit, like \"{}\"; got '{}'",
+                           DEFAULT_PREFIX, prefix));
+    }
+    Ok(())
+}
+
+/// Bumped whenever a variable is removed or changes meaning
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A variable in the action environment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EnvVar {
+    Pid,
+    PidType,
+    RealPid,
+    Command,
+    CommandB64,
+    Timestamp,
+    EventId,
+    RuleName,
+    Home,
+    StateDir,
+    Accessibility,
+    WaitingRoomUrl,
+    WorkingDirectory,
+    ShellSessionId,
+    ExpectedDuration,
+    Probe,
+    ProbeVersion,
+    Hostname,
+    SessionId,
+    Repo,
+    Remote,
+    Branch,
+    Dirty,
+    Language,
+    TaskRunner,
+    Task,
+    Category,
+}
+
+impl EnvVar {
+    pub const ALL: &'static [EnvVar] = &[
+        EnvVar::Pid,
+        EnvVar::PidType,
+        EnvVar::RealPid,
+        EnvVar::Command,
+        EnvVar::CommandB64,
+        EnvVar::Timestamp,
+        EnvVar::EventId,
+        EnvVar::RuleName,
+        EnvVar::Home,
+        EnvVar::StateDir,
+        EnvVar::Accessibility,
+        EnvVar::WaitingRoomUrl,
+        EnvVar::WorkingDirectory,
+        EnvVar::ShellSessionId,
+        EnvVar::ExpectedDuration,
+        EnvVar::Probe,
+        EnvVar::ProbeVersion,
+        EnvVar::Hostname,
+        EnvVar::SessionId,
+        EnvVar::Repo,
+        EnvVar::Remote,
+        EnvVar::Branch,
+        EnvVar::Dirty,
+        EnvVar::Language,
+        EnvVar::TaskRunner,
+        EnvVar::Task,
+        EnvVar::Category,
+    ];
+
+    /// Name without the prefix
+    pub fn suffix(self) -> &'static str {
+        match self {
+            EnvVar::Pid => "PID",
+            EnvVar::PidType => "PID_TYPE",
+            EnvVar::RealPid => "REAL_PID",
+            EnvVar::Command => "COMMAND",
+            EnvVar::CommandB64 => "COMMAND_B64",
+            EnvVar::Timestamp => "TIMESTAMP",
+            EnvVar::EventId => "EVENT_ID",
+            EnvVar::RuleName => "RULE_NAME",
+            EnvVar::Home => "HOME",
+            EnvVar::StateDir => "STATE_DIR",
+            EnvVar::Accessibility => "ACCESSIBILITY",
+            EnvVar::WaitingRoomUrl => "WAITING_ROOM_URL",
+            EnvVar::WorkingDirectory => "WORKING_DIRECTORY",
+            EnvVar::ShellSessionId => "SHELL_SESSION_ID",
+            EnvVar::ExpectedDuration => "EXPECTED_DURATION",
+            EnvVar::Probe => "PROBE",
+            EnvVar::ProbeVersion => "PROBE_VERSION",
+            EnvVar::Hostname => "HOSTNAME",
+            EnvVar::SessionId => "SESSION_ID",
+            EnvVar::Repo => "REPO",
+            EnvVar::Remote => "REMOTE",
+            EnvVar::Branch => "BRANCH",
+            EnvVar::Dirty => "DIRTY",
+            EnvVar::Language => "LANGUAGE",
+            EnvVar::TaskRunner => "TASK_RUNNER",
+            EnvVar::Task => "TASK",
+            EnvVar::Category => "CATEGORY",
+        }
+    }
+
+    /// JSON-schema-style type of the value
+    pub fn value_type(self) -> &'static str {
+        match self {
+            EnvVar::Pid | EnvVar::RealPid | EnvVar::Timestamp | EnvVar::ExpectedDuration => "integer",
+            _ => "string",
+        }
+    }
+
+    /// Whether the variable is set for every action
+    pub fn always_set(self) -> bool {
+        !matches!(
+            self,
+            EnvVar::RealPid | EnvVar::CommandB64 | EnvVar::Home | EnvVar::StateDir | EnvVar::WaitingRoomUrl | EnvVar::WorkingDirectory | EnvVar::ShellSessionId
+                | EnvVar::ExpectedDuration | EnvVar::SessionId | EnvVar::Repo | EnvVar::Remote
+                | EnvVar::Branch | EnvVar::Dirty | EnvVar::Language | EnvVar::TaskRunner | EnvVar::Task
+                | EnvVar::Category
+        )
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            EnvVar::Pid => "ID of the monitored process; synthetic for the shell probe",
+            EnvVar::PidType => "\"system\" for real OS PIDs, \"synthetic\" for shell probe PIDs",
+            EnvVar::RealPid => "OS PID of the command, safe for liveness checks; for synthetic PIDs only set when the command ran through `viberot_track`",
+            EnvVar::Command => "Full command line of the monitored process, in Unicode NFC form",
+            EnvVar::CommandB64 => "Base64 of the command line's bytes exactly as the probe reported them (WTF-8 on Windows); only set when they aren't valid UTF-8 or normalization changed them",
+            EnvVar::Timestamp => "When the process started (Unix timestamp, seconds)",
+            EnvVar::EventId => "Unique ID of the start event, shared by all actions it triggered",
+            EnvVar::RuleName => "Name of the rule that matched, or \"rule #N\" for unnamed rules",
+            EnvVar::Home => "VibeRot project root",
+            EnvVar::StateDir => "Directory the action can keep files in between runs, the same for every run of this action of this rule; removed after `action_state_retention` without use",
+            EnvVar::Accessibility => "`[accessibility]` settings as JSON, e.g. {\"skip_animated\":false,\"reduced_motion\":true,\"captions\":false,\"high_contrast\":true}; built-in actions follow them",
+            EnvVar::WaitingRoomUrl => "Local page showing the command's progress and images from `[waiting_room] memes_dir`, the overlay's default; unset with `[waiting_room] enabled = false`",
+            EnvVar::WorkingDirectory => "Working directory of the command (shell and atuin probes, and ETW with `[etw] working_directory`)",
+            EnvVar::ShellSessionId => "ID of the shell session that ran the command (shell probe only)",
+            EnvVar::ExpectedDuration => "Median duration of recent runs in seconds, once the command has history",
+            EnvVar::Probe => "Probe that saw the command: \"WindowsEtw\", \"WindowsWmi\", \"PosixShell\", \"Atuin\", \"Mqtt\", \"WebSocket\", \"External\", \"Agent\" or \"Cursor\", or \"Manual\" for rules triggered over D-Bus",
+            EnvVar::ProbeVersion => "Version of the VibeRot service",
+            EnvVar::Hostname => "Machine the command ran on",
+            EnvVar::SessionId => "OS login session of the command, when known",
+            EnvVar::Repo => "Name of the git repository the command ran in",
+            EnvVar::Remote => "Git remote of the repository as host/owner/name, e.g. \"github.com/acme/app\"",
+            EnvVar::Dirty => "\"true\" if tracked files have uncommitted changes, \"false\" otherwise",
+            EnvVar::Branch => "Checked out git branch, or the short commit hash when detached",
+            EnvVar::Language => "Language of the project the command ran in, e.g. \"rust\"",
+            EnvVar::TaskRunner => "Task runner that ran the command: \"just\", \"make\", \"task\", \"npm\", \"yarn\", \"pnpm\" or \"bun\"",
+            EnvVar::Task => "Task the task runner ran, e.g. \"deploy\" for `just deploy`",
+            EnvVar::Category => "Kind of command: \"build\", \"test\", \"install\" or \"deploy\"",
+        }
+    }
+}
+
+/// Builds the environment for a single action
+pub struct ActionEnvBuilder {
+    prefix: String,
+    vars: BTreeMap<EnvVar, String>,
+}
+
+impl ActionEnvBuilder {
+    pub fn new(prefix: Option<&str>) -> Self {
+        Self {
+            prefix: prefix.unwrap_or(DEFAULT_PREFIX).to_string(),
+            vars: BTreeMap::new(),
+        }
+    }
+
+    /// Sets everything that comes from the process event
+    pub fn event(mut self, event: &ProcessEvent) -> Self {
+        self.set(EnvVar::Pid, event.pid);
+        self.set(EnvVar::PidType, event.probe_source.pid_type());
+        self.set_opt(EnvVar::RealPid, event.real_pid);
+        self.set(EnvVar::Command, &event.command);
+        self.set_opt(EnvVar::CommandB64, event.raw_command.as_ref().map(|raw| general_purpose::STANDARD.encode(raw)));
+        self.set(EnvVar::Timestamp, event.timestamp);
+        self.set(EnvVar::EventId, &event.event_id);
+        self.set_opt(EnvVar::WorkingDirectory, event.working_directory.as_ref());
+        self.set_opt(EnvVar::ShellSessionId, event.shell_session_id.as_ref());
+        self.set_opt(EnvVar::ExpectedDuration, event.expected_duration);
+        self.set(EnvVar::Probe, format!("{:?}", event.probe_source));
+        self.set(EnvVar::ProbeVersion, &event.provenance.probe_version);
+        self.set(EnvVar::Hostname, &event.provenance.hostname);
+        self.set_opt(EnvVar::SessionId, event.provenance.session_id.as_ref());
+        self.set_opt(EnvVar::Repo, event.tags.get("repo"));
+        self.set_opt(EnvVar::Remote, event.tags.get("remote"));
+        self.set_opt(EnvVar::Branch, event.tags.get("branch"));
+        self.set_opt(EnvVar::Dirty, event.tags.get("dirty"));
+        self.set_opt(EnvVar::Language, event.tags.get("language"));
+        self.set_opt(EnvVar::TaskRunner, event.tags.get("task_runner"));
+        self.set_opt(EnvVar::Task, event.tags.get("task"));
+        self.set_opt(EnvVar::Category, event.tags.get("category"));
+        self
+    }
+
+    pub fn rule_name(mut self, rule_name: &str) -> Self {
+        self.set(EnvVar::RuleName, rule_name);
+        self
+    }
+
+    pub fn home(mut self, home: &Path) -> Self {
+        self.set(EnvVar::Home, home.to_string_lossy());
+        self
+    }
+
+    pub fn state_dir(mut self, dir: &Path) -> Self {
+        self.set(EnvVar::StateDir, dir.to_string_lossy());
+        self
+    }
+
+    pub fn accessibility(mut self, settings: &AccessibilityConfig) -> Self {
+        self.set_opt(EnvVar::Accessibility, serde_json::to_string(settings).ok());
+        self
+    }
+
+    pub fn waiting_room_url(mut self, url: String) -> Self {
+        self.set(EnvVar::WaitingRoomUrl, url);
+        self
+    }
+
+    /// Variable name/value pairs ready to pass to `Command::envs`
+    pub fn build(self) -> Vec<(String, String)> {
+        self.vars.into_iter()
+            .map(|(var, value)| (format!("{}_{}", self.prefix, var.suffix()), value))
+            .collect()
+    }
+
+    fn set(&mut self, var: EnvVar, value: impl ToString) {
+        self.vars.insert(var, value.to_string());
+    }
+
+    fn set_opt(&mut self, var: EnvVar, value: Option<impl ToString>) {
+        if let Some(value) = value {
+            self.set(var, value);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchemaEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub value_type: &'static str,
+    pub always_set: bool,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Schema {
+    pub version: u32,
+    pub prefix: String,
+    pub variables: Vec<SchemaEntry>,
+}
+
+pub fn schema(prefix: Option<&str>) -> Schema {
+    let prefix = prefix.unwrap_or(DEFAULT_PREFIX).to_string();
+    Schema {
+        version: SCHEMA_VERSION,
+        variables: EnvVar::ALL.iter()
+            .map(|var| SchemaEntry {
+                name: format!("{}_{}", prefix, var.suffix()),
+                value_type: var.value_type(),
+                always_set: var.always_set(),
+                description: var.description(),
+            })
+            .collect(),
+        prefix,
+    }
+}
+
+/// Human-readable description of the action environment
+pub fn schema_text(schema: &Schema) -> String {
+    let width = schema.variables.iter().map(|v| v.name.len()).max().unwrap_or(0);
+    let mut text = format!("Environment passed to actions (schema v{}):\n\n", schema.version);
+    for var in &schema.variables {
+        let presence = if var.always_set { "always" } else { "optional" };
+        text.push_str(&format!("  {:width$}  {:7}  {:8}  {}\n", var.name, var.value_type, presence, var.description, width = width));
+    }
+    text
+}
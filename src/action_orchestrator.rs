@@ -1,44 +1,395 @@
-use crate::config::{Action, Config};
+use crate::action_env::ActionEnvBuilder;
+use crate::action_protocol::{self, ActionEvent, ActionResult, EndSummary, StdinCommand};
+use crate::action_state::ActionStateDirs;
+use crate::attach::{self, Attachment};
+use crate::audit::{self, AuditLog, AuditTrigger, ExecRecord};
+use crate::capture::CaptureGuard;
+use crate::config::{self, AccessibilityConfig, Action, Config, OnlyIf, ThrottleTarget};
+use crate::cpu;
+use crate::i18n::{t, tf, Msg};
+use crate::input_activity::InputActivity;
+use crate::latency::LatencyTracker;
+use crate::notify::{self, Notifier};
 use crate::platform::ProcessEvent;
-use std::collections::{HashMap, HashSet};
+use crate::preflight;
+use crate::rule_engine::MatchedAction;
+use crate::rule_log;
+use crate::runtime_paths;
+use crate::sandbox::{self, SandboxPolicy};
+use crate::stats::StatsStore;
+use crate::terminal_bell::Terminal;
+use crate::throttle::{self, Throttler};
+use crate::waiting_room::WaitingRoom;
+use crate::workspace::{self, WorkspaceSwitcher};
+use crate::tts::Speaker;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::process::Stdio;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
-use tokio::sync::RwLock;
+use futures_util::Stream;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{info, warn, debug};
 
 pub struct ActionOrchestrator {
     active_actions: Arc<RwLock<HashMap<u32, Vec<ActiveAction>>>>,
     running_single_instance_actions: Arc<RwLock<HashSet<String>>>,
+    shared_actions: Arc<RwLock<HashMap<String, SharedAction>>>,
+    attachments: Arc<RwLock<HashMap<u32, Vec<Attachment>>>>,
+    /// `attach` actions waiting for the daemon they launched to start listening, by monitored PID
+    pending_attachments: Arc<RwLock<HashMap<u32, Vec<JoinHandle<()>>>>>,
+    /// Timers of escalation tiers, and of actions waiting for `only_if_cpu_above`, that haven't
+    /// fired yet, by monitored PID
+    escalations: Arc<RwLock<HashMap<u32, Vec<Escalation>>>>,
+    /// `speak` actions waiting for their command to end, by monitored PID
+    announcements: Arc<RwLock<HashMap<u32, Vec<Announcement>>>>,
+    /// `on_end = true` actions waiting for their command to end, by monitored PID
+    end_actions: Arc<RwLock<HashMap<u32, Vec<EndAction>>>>,
+    /// `throttle` actions of running commands, by monitored PID
+    throttles: Arc<RwLock<HashMap<u32, Vec<Action>>>>,
+    throttler: Option<Arc<Throttler>>,
+    /// Switches desktops for `desktop` actions
+    workspaces: Arc<WorkspaceSwitcher>,
+    speaker: Speaker,
+    latency: Arc<LatencyTracker>,
+    audit: Option<Arc<AuditLog>>,
+    capture: Option<CaptureGuard>,
+    notifier: Option<Arc<Notifier>>,
+    state_dirs: Option<Arc<ActionStateDirs>>,
+    /// Input activity while actions run, deciding how loudly their command's end is announced
+    input_activity: Option<Arc<InputActivity>>,
+    /// The page actions get as `VIBEROT_WAITING_ROOM_URL`
+    waiting_room: Option<Arc<WaitingRoom>>,
     config: Option<Config>,
+    events: broadcast::Sender<OrchestratorEvent>,
 }
 
 pub struct ActiveAction {
     pub child: tokio::process::Child,
     pub action: Action,
+    /// The command the action was started for alone, None for shared and prewarmed actions
+    pub trigger: Option<ActionTrigger>,
 }
 
-impl ActionOrchestrator {
-    pub fn new() -> Self {
-        Self {
-            active_actions: Arc::new(RwLock::new(HashMap::new())),
-            running_single_instance_actions: Arc::new(RwLock::new(HashSet::new())),
-            config: None,
+/// What an action's `end` line says about the command it was started for
+pub struct ActionTrigger {
+    pub event_id: String,
+    pub rule: String,
+    /// When the command started, in seconds since the Unix epoch
+    pub started: u64,
+}
+
+/// An `on_end = true` action of a running command
+struct EndAction {
+    action: Action,
+    rule: String,
+    event: ProcessEvent,
+}
+
+/// An escalation tier waiting to fire; the timer starts it only while it isn't cancelled
+struct Escalation {
+    cancelled: Arc<AtomicBool>,
+    timer: JoinHandle<()>,
+}
+
+impl Escalation {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.timer.abort();
+    }
+}
+
+/// How long to wait for a daemon launched by an `attach` action to start listening
+const DAEMON_START_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// A `shared = true` or `prewarm = true` action process and the commands it was told about
+struct SharedAction {
+    active: ActiveAction,
+    /// Event IDs of the running commands, by monitored PID
+    events: HashMap<u32, String>,
+    /// Prewarmed processes get `attach`/`detach` instead of `open`/`close`, and stay running when idle
+    prewarmed: bool,
+}
+
+/// A `speak` or `bell` action of a running command
+struct Announcement {
+    action: Action,
+    rule: String,
+    command: String,
+    started: Instant,
+    /// Where the command runs, for `bell` actions
+    terminal: Option<Terminal>,
+}
+
+impl Announcement {
+    /// The text to speak or show now that the command ended
+    fn text(&self, exit_code: Option<i32>) -> String {
+        let (Action::Speak { text_template, .. } | Action::Bell { text_template, .. }) = &self.action else {
+            return String::new();
+        };
+        let template = text_template.as_deref().unwrap_or_else(|| match exit_code {
+            Some(_) => t(Msg::SpeakDefault),
+            None => t(Msg::SpeakDefaultNoOutcome),
+        });
+        let outcome = match exit_code {
+            Some(0) => t(Msg::SpeakSucceeded),
+            Some(_) => t(Msg::SpeakFailed),
+            None => t(Msg::SpeakNoOutcome),
+        };
+        template
+            .replace("{command_line}", &self.command)
+            .replace("{command}", &StatsStore::command_key(&self.command))
+            .replace("{rule}", &self.rule)
+            .replace("{duration}", &spoken_duration(self.started.elapsed()))
+            .replace("{outcome}", outcome)
+    }
+}
+
+/// e.g. "3 minutes", or "1 hour 5 minutes", as it reads out loud
+fn spoken_duration(elapsed: Duration) -> String {
+    let unit = |count: u64, one: Msg, many: Msg| tf(if count == 1 { one } else { many }, &[&count]);
+    let seconds = elapsed.as_secs();
+    if seconds < 60 {
+        return unit(seconds, Msg::SpeakSecond, Msg::SpeakSeconds);
+    }
+    let minutes = (seconds + 30) / 60;
+    if minutes < 60 {
+        return unit(minutes, Msg::SpeakMinute, Msg::SpeakMinutes);
+    }
+    let hours = unit(minutes / 60, Msg::SpeakHour, Msg::SpeakHours);
+    match minutes % 60 {
+        0 => hours,
+        minutes => format!("{} {}", hours, unit(minutes, Msg::SpeakMinute, Msg::SpeakMinutes)),
+    }
+}
+
+/// A line an action printed on stdout, e.g. `{"event": "visible", "id": "..."}`,
+/// `{"event": "dismissed", ...}` when the user closed it or `{"event": "result", "outcome": ...}`
+#[derive(Debug, serde::Deserialize)]
+struct ActionReport {
+    event: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    outcome: Option<String>,
+    #[serde(default)]
+    seconds: Option<f64>,
+    #[serde(default)]
+    detail: Option<String>,
+}
+
+/// Follows what an action reports on stdout
+struct Reporter {
+    action_key: String,
+    /// Monitored PID and event ID of the command the action was started for, if any
+    trigger: Option<(u32, String)>,
+    latency: Arc<LatencyTracker>,
+    shared_actions: Arc<RwLock<HashMap<String, SharedAction>>>,
+    events: broadcast::Sender<OrchestratorEvent>,
+    input_activity: Option<Arc<InputActivity>>,
+}
+
+impl Reporter {
+    /// Reads what the action prints, recording when it reports being visible or dismissed, and its
+    /// results. Reports without an ID refer to the event the action was started for.
+    async fn read(self, stdout: tokio::process::ChildStdout) {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let Some((report, event)) = serde_json::from_str::<ActionReport>(&line).ok()
+                .and_then(|report| ActionEvent::parse(&report.event).map(|event| (report, event)))
+            else {
+                debug!("Action output: {}", line);
+                continue;
+            };
+            let Some(id) = report.id.as_ref().or(self.trigger.as_ref().map(|(_, id)| id)) else {
+                continue;
+            };
+            if event == ActionEvent::Visible {
+                self.latency.visible(id);
+            }
+            let Some(pid) = self.pid_of(id).await else {
+                continue;
+            };
+            let action = self.action_key.clone();
+            let event = match event {
+                ActionEvent::Visible => OrchestratorEvent::ActionVisible { pid, action },
+                ActionEvent::Dismissed => {
+                    if let Some(ref activity) = self.input_activity {
+                        activity.forget(pid);
+                    }
+                    OrchestratorEvent::ActionDismissed { pid, action }
+                }
+                ActionEvent::Result => {
+                    match ActionResult::reported(report.outcome.as_deref(), report.seconds, report.detail.as_deref()) {
+                        Some(result) => OrchestratorEvent::ActionResult { pid, action, result },
+                        None => {
+                            debug!("Ignoring result without an outcome from '{}': {}", action, line);
+                            continue;
+                        }
+                    }
+                }
+            };
+            // Nobody listening is fine
+            let _ = self.events.send(event);
         }
     }
-    
+
+    /// The monitored PID of an event, which shared actions are told about as commands come and go
+    async fn pid_of(&self, event_id: &str) -> Option<u32> {
+        if let Some((pid, _)) = self.trigger.as_ref().filter(|(_, id)| id == event_id) {
+            return Some(*pid);
+        }
+        let shared_actions = self.shared_actions.read().await;
+        let shared = shared_actions.get(&self.action_key)?;
+        shared.events.iter().find(|(_, id)| *id == event_id).map(|(pid, _)| *pid)
+    }
+}
+
+/// What the orchestrator did, for anything following along through `subscribe`. Actions are
+/// named by their key, e.g. `exec:path:args` or `attach:socket`.
+#[derive(Debug, Clone)]
+pub enum OrchestratorEvent {
+    /// A rule's actions are about to start, or to wait for their escalation delay
+    RuleMatched {
+        pid: u32,
+        rule: String,
+        #[cfg_attr(not(all(target_os = "linux", feature = "dbus")), allow(dead_code))] // Only D-Bus signals it
+        command: String,
+        actions: usize,
+    },
+    ActionStarted { pid: u32, rule: String, action: String },
+    /// The command ended, so the action was stopped or told about it
+    ActionEnded { pid: u32, action: String },
+    ActionFailed { pid: u32, rule: String, action: String, error: String },
+    /// The action reported being on screen for the command
+    ActionVisible { pid: u32, action: String },
+    /// The user closed the action before the command ended
+    ActionDismissed { pid: u32, action: String },
+    /// The action reported what came of it, or exited with a failing status
+    ActionResult { pid: u32, action: String, result: ActionResult },
+}
+
+impl std::fmt::Display for OrchestratorEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RuleMatched { pid, rule, actions, .. } => {
+                write!(f, "rule '{}' matched PID {} with {} action(s)", rule, pid, actions)
+            }
+            Self::ActionStarted { pid, rule, action } => {
+                write!(f, "action '{}' of rule '{}' started for PID {}", action, rule, pid)
+            }
+            Self::ActionEnded { pid, action } => write!(f, "action '{}' ended for PID {}", action, pid),
+            Self::ActionFailed { pid, rule, action, error } => {
+                write!(f, "action '{}' of rule '{}' failed for PID {}: {}", action, rule, pid, error)
+            }
+            Self::ActionVisible { pid, action } => write!(f, "action '{}' visible for PID {}", action, pid),
+            Self::ActionDismissed { pid, action } => write!(f, "action '{}' dismissed for PID {}", action, pid),
+            Self::ActionResult { pid, action, result } => write!(f, "action '{}' for PID {}: {}", action, pid, result),
+        }
+    }
+}
+
+/// Orchestrator events a slow subscriber can fall behind by before missing some
+const EVENT_CAPACITY: usize = 256;
+
+impl ActionOrchestrator {
     pub fn with_config(config: Config) -> Self {
         Self {
             active_actions: Arc::new(RwLock::new(HashMap::new())),
             running_single_instance_actions: Arc::new(RwLock::new(HashSet::new())),
+            shared_actions: Arc::new(RwLock::new(HashMap::new())),
+            attachments: Arc::new(RwLock::new(HashMap::new())),
+            pending_attachments: Arc::new(RwLock::new(HashMap::new())),
+            escalations: Arc::new(RwLock::new(HashMap::new())),
+            announcements: Arc::new(RwLock::new(HashMap::new())),
+            end_actions: Arc::new(RwLock::new(HashMap::new())),
+            throttles: Arc::new(RwLock::new(HashMap::new())),
+            throttler: None,
+            workspaces: Arc::new(WorkspaceSwitcher::new(workspace::create_backend())),
+            speaker: Speaker::new(),
+            latency: Arc::new(LatencyTracker::new(config.latency_budget())),
+            audit: None,
+            capture: None,
+            notifier: None,
+            state_dirs: None,
+            input_activity: None,
+            waiting_room: None,
             config: Some(config),
+            events: broadcast::channel(EVENT_CAPACITY).0,
         }
     }
 
+    /// Records every program started from now on in the audit log
+    pub fn with_audit_log(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Swaps actions for the capture-safe ones while the screen is streamed or recorded
+    pub fn with_capture_guard(mut self, capture: CaptureGuard) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Shows `notify` actions, whose buttons report back through the notifier's channel
+    pub fn with_notifier(mut self, notifier: Arc<Notifier>) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Suspends and resumes processes for `throttle` actions
+    pub fn with_throttler(mut self, throttler: Arc<Throttler>) -> Self {
+        self.throttler = Some(throttler);
+        self
+    }
+
+    /// Gives every exec action a state directory of its own (`VIBEROT_STATE_DIR`)
+    pub fn with_state_dirs(mut self, state_dirs: Arc<ActionStateDirs>) -> Self {
+        self.state_dirs = Some(state_dirs);
+        self
+    }
+
+    /// Samples input activity while actions run, ending them with a banner and a sound when the
+    /// user was still busy with them
+    pub fn with_input_activity(mut self, input_activity: Arc<InputActivity>) -> Self {
+        self.input_activity = Some(input_activity);
+        self
+    }
+
+    /// Tells actions where the waiting room page is, for the overlay to open by default
+    pub fn with_waiting_room(mut self, waiting_room: Arc<WaitingRoom>) -> Self {
+        self.waiting_room = Some(waiting_room);
+        self
+    }
+
+    /// Events for every rule match and action from now on. A subscriber that falls more than a
+    /// few hundred events behind skips the oldest ones.
+    pub fn subscribe(&self) -> impl Stream<Item = OrchestratorEvent> + Send + 'static {
+        futures_util::stream::unfold(self.events.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        debug!("Orchestrator event subscriber missed {} event(s)", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    fn emit(&self, event: OrchestratorEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
     /// Resolves a path string with environment variable expansion and predictable relative path handling
-    fn resolve_action_path(&self, path: &str) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    pub fn resolve_action_path(&self, path: &str) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
         let path_str = path.trim();
         
         // If it's just an executable name (no path separators), preserve PATH lookup behavior
@@ -92,10 +443,24 @@ impl ActionOrchestrator {
     /// Checks if the action is configured to run as a single instance
     fn is_single_instance(&self, action: &Action) -> bool {
         match action {
-            Action::Executable { single_instance, .. } => *single_instance,
+            // Shared actions are single-instance by nature, but also hear about every command
+            Action::Executable { single_instance, shared, .. } => *single_instance && !*shared,
             Action::Lua { single_instance, .. } => *single_instance,
+            Action::Attach { .. } | Action::Notify { .. } | Action::Speak { .. } | Action::Bell { .. } | Action::Throttle { .. } | Action::Desktop { .. } => false,
         }
     }
+
+    /// Checks if the action is marked as flashing or moving a lot
+    fn is_animated(&self, action: &Action) -> bool {
+        match action {
+            Action::Executable { animated, .. } | Action::Attach { animated, .. } => *animated,
+            Action::Lua { .. } | Action::Notify { .. } | Action::Speak { .. } | Action::Bell { .. } | Action::Throttle { .. } | Action::Desktop { .. } => false,
+        }
+    }
+
+    fn accessibility(&self) -> AccessibilityConfig {
+        self.config.as_ref().map(|config| config.accessibility).unwrap_or_default()
+    }
     
     /// Gets a unique key for the action to track single instances
     fn get_action_key(&self, action: &Action) -> String {
@@ -109,84 +474,47 @@ impl ActionOrchestrator {
             Action::Lua { script, .. } => {
                 format!("lua:{}", script)
             }
-        }
-    }
-
-    /// Development environment fallback: tries to find project root by walking up directories
-    fn development_root_detection() -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
-        // Try to find the project root by looking for Cargo.toml starting from current exe
-        if let Ok(exe_path) = env::current_exe() {
-            let mut current = exe_path.parent();
-            while let Some(dir) = current {
-                if dir.join("Cargo.toml").exists() {
-                    // Check if this looks like the viberot root by looking for expected structure
-                    if dir.join("src").exists() && dir.join("actions").exists() {
-                        debug!("Found viberot root via exe path: {}", dir.display());
-                        return Ok(dir.to_path_buf());
-                    }
-                }
-                current = dir.parent();
+            Action::Attach { socket, .. } => {
+                format!("attach:{}", socket.clone().unwrap_or_else(attach::default_socket_path))
             }
-        }
-        
-        // Fallback: try current working directory and walk up
-        let mut current = env::current_dir()?;
-        for _ in 0..5 { // Limit search depth
-            if current.join("Cargo.toml").exists() && 
-               current.join("src").exists() && 
-               current.join("actions").exists() {
-                debug!("Found viberot root via cwd: {}", current.display());
-                return Ok(current);
-            }
-            if let Some(parent) = current.parent() {
-                current = parent.to_path_buf();
-            } else {
-                break;
+            Action::Notify { title, .. } => {
+                format!("notify:{}", title.clone().unwrap_or_default())
+            }
+            Action::Speak { text_template, .. } => {
+                format!("speak:{}", text_template.clone().unwrap_or_default())
+            }
+            Action::Bell { text_template, .. } => {
+                format!("bell:{}", text_template.clone().unwrap_or_default())
             }
+            Action::Throttle { target: ThrottleTarget::List, processes, .. } => {
+                format!("throttle:{}", processes.join(","))
+            }
+            Action::Throttle { target: ThrottleTarget::SelfSpawned, .. } => "throttle:self-spawned".to_string(),
+            Action::Desktop { desktop, .. } => format!("desktop:{}", desktop),
         }
-        
-        Err("Could not find viberot project root directory. Expected to find Cargo.toml with src/ and actions/ directories.".into())
     }
 
     /// Gets the viberot project root directory using configuration-based approach
     fn get_viberot_root(&self) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
-        // 1. Environment variable override (highest priority)
-        if let Ok(path) = env::var("VIBEROT_HOME") {
-            let path_buf = PathBuf::from(path);
-            if path_buf.exists() {
-                debug!("Using VIBEROT_HOME environment variable: {}", path_buf.display());
-                return Ok(path_buf);
-            } else {
-                warn!("VIBEROT_HOME environment variable points to non-existent path: {}", path_buf.display());
-            }
-        }
-        
-        // 2. Check config file for installation root
-        if let Some(ref config) = self.config {
-            if let Some(ref home_path) = config.viberot_home {
-                let path_buf = PathBuf::from(home_path);
-                if path_buf.exists() {
-                    debug!("Using viberot_home from config: {}", path_buf.display());
-                    return Ok(path_buf);
-                } else {
-                    warn!("viberot_home in config points to non-existent path: {}", path_buf.display());
-                }
-            }
-        }
-                
-        // 3. Development fallback (lowest priority)
-        debug!("Falling back to development root detection");
-        Self::development_root_detection()
+        runtime_paths::install_root(self.config.as_ref())
     }
     
-    pub async fn start_action(&self, action: Action, event: &ProcessEvent) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn start_action(&self, action: Action, event: &ProcessEvent, rule_name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Started by `command_ended`
+        if let Action::Executable { on_end: true, .. } = action {
+            rule_log::debug!(event.pid, "Starting an action of rule '{}' once PID {} ends", rule_name, event.pid);
+            let end_action = EndAction { action, rule: rule_name.to_string(), event: event.clone() };
+            self.end_actions.write().await.entry(event.pid).or_default().push(end_action);
+            return Ok(());
+        }
+
         // Check if this is a single-instance action and if it's already running
         if self.is_single_instance(&action) {
             let action_key = self.get_action_key(&action);
             let mut running_actions = self.running_single_instance_actions.write().await;
             
             if running_actions.contains(&action_key) {
-                info!("Single-instance action '{}' is already running, skipping", action_key);
+                rule_log::info!(event.pid, "Single-instance action '{}' is already running, skipping", action_key);
                 return Ok(());
             }
             
@@ -194,23 +522,147 @@ impl ActionOrchestrator {
             running_actions.insert(action_key.clone());
         }
         
-        match action.clone() {
-            Action::Executable { path, args, single_instance: _ } => {
-                self.start_executable_action(path, args, action, event).await
+        self.latency.triggered(&event.event_id);
+        let action_key = self.get_action_key(&action);
+        // Notifications and announcements don't keep the user busy
+        let on_screen = matches!(action, Action::Executable { .. } | Action::Attach { .. });
+
+        let result = match action.clone() {
+            Action::Executable { path, args, shared, prewarm, .. } if shared || prewarm => {
+                self.start_shared_action(path, args, action, event, rule_name).await
+            }
+            Action::Executable { path, args, .. } => {
+                self.start_executable_action(path, args, action, event, rule_name).await
             }
             Action::Lua { script: _, single_instance: _ } => {
                 // TODO: Implement Lua execution in future milestones
                 warn!("Lua actions not yet implemented");
+                return Ok(());
+            }
+            Action::Attach { socket, path, args, .. } => {
+                self.start_attach_action(socket, path, args, event, rule_name).await
+            }
+            Action::Notify { title, message, buttons } => {
+                let Some(ref notifier) = self.notifier else {
+                    warn!("Notifications aren't set up, skipping notify action of rule '{}'", rule_name);
+                    return Ok(());
+                };
+                let title = title.unwrap_or_else(|| rule_name.to_string());
+                let message = message.unwrap_or_else(|| event.command.clone());
+                notifier.show(&title, &message, &buttons, event, rule_name)
+                    .map_err(|e| format!("Failed to show notification for rule '{}': {}", rule_name, e).into())
+            }
+            Action::Speak { .. } | Action::Bell { .. } => {
+                let terminal = match action {
+                    Action::Bell { .. } => match Terminal::of(event) {
+                        Some(terminal) => Some(terminal),
+                        None => {
+                            warn!("No terminal known for PID {}, skipping bell action of rule '{}'", event.pid, rule_name);
+                            return Ok(());
+                        }
+                    },
+                    _ => None,
+                };
+                let announcement = Announcement {
+                    action,
+                    rule: rule_name.to_string(),
+                    command: event.command.clone(),
+                    started: Instant::now(),
+                    terminal,
+                };
+                self.announcements.write().await.entry(event.pid).or_default().push(announcement);
                 Ok(())
             }
+            Action::Throttle { .. } => self.start_throttle(action, event, rule_name).await,
+            Action::Desktop { desktop, back_to } => {
+                let workspaces = Arc::clone(&self.workspaces);
+                let pid = event.pid;
+                tokio::task::spawn_blocking(move || workspaces.hold(pid, desktop, back_to)).await?
+                    .map_err(|e| format!("Failed to switch to desktop {} for rule '{}': {}", desktop, rule_name, e).into())
+            }
+        };
+
+        if let (Ok(()), Some(activity), true) = (&result, &self.input_activity, on_screen) {
+            activity.watch(event.pid, &event.command);
+        }
+
+        let rule = rule_name.to_string();
+        match &result {
+            Ok(()) => self.emit(OrchestratorEvent::ActionStarted { pid: event.pid, rule, action: action_key }),
+            Err(e) => self.emit(OrchestratorEvent::ActionFailed { pid: event.pid, rule, action: action_key, error: e.to_string() }),
+        }
+        result
+    }
+
+    /// Starts actions now, or once the command has been running for their escalation delay and
+    /// busy enough for `only_if_cpu_above` or `only_if`
+    pub async fn start_actions(self: &Arc<Self>, actions: Vec<MatchedAction>, event: &ProcessEvent) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut matched_rules: Vec<(&str, usize)> = Vec::new();
+        for matched in &actions {
+            match matched_rules.iter_mut().find(|(rule, _)| *rule == matched.rule_name) {
+                Some((_, count)) => *count += 1,
+                None => matched_rules.push((&matched.rule_name, 1)),
+            }
+        }
+        for (rule, count) in matched_rules {
+            self.emit(OrchestratorEvent::RuleMatched {
+                pid: event.pid,
+                rule: rule.to_string(),
+                command: event.command.clone(),
+                actions: count,
+            });
+        }
+
+        let (immediate, escalated): (Vec<_>, Vec<_>) = actions.into_iter().partition(|m| m.after.is_zero() && m.cpu_above.is_none() && m.only_if.is_none());
+
+        // One timer per tier, so a tier is swapped for the capture-safe actions as a whole
+        let mut tiers: Vec<Vec<MatchedAction>> = Vec::new();
+        for matched in escalated {
+            match tiers.iter_mut().find(|tier| tier[0].rule_name == matched.rule_name && tier[0].after == matched.after) {
+                Some(tier) => tier.push(matched),
+                None => tiers.push(vec![matched]),
+            }
+        }
+        for tier in tiers {
+            self.schedule_escalation(tier, event).await;
         }
+
+        self.start_batch(immediate, event).await
     }
 
-    pub async fn start_actions(&self, actions: Vec<Action>, event: &ProcessEvent) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// Starts a batch of actions, or the capture-safe ones instead while the screen is captured
+    async fn start_batch(&self, actions: Vec<MatchedAction>, event: &ProcessEvent) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let actions = match (&self.capture, actions.first()) {
+            (Some(capture), Some(first)) => match capture.detect().await {
+                Some(reason) => {
+                    let safe = capture.safe_actions();
+                    rule_log::info!(event.pid, "{}, starting {} capture-safe action(s) instead of {}", reason, safe.len(), actions.len());
+                    let rule_name = first.rule_name.clone();
+                    safe.into_iter()
+                        .map(|action| MatchedAction { rule_name: rule_name.clone(), action, after: Duration::ZERO, cpu_above: None, only_if: None, with: BTreeMap::new() })
+                        .collect()
+                }
+                None => actions,
+            },
+            _ => actions,
+        };
+        let mut actions = if self.accessibility().skip_animated {
+            let (animated, still): (Vec<_>, Vec<_>) = actions.into_iter().partition(|m| self.is_animated(&m.action));
+            if !animated.is_empty() {
+                rule_log::info!(event.pid, "Skipping {} animated action(s) for PID {}", animated.len(), event.pid);
+            }
+            still
+        } else {
+            actions
+        };
+        // On the new desktop before the others start, so their windows open there
+        actions.sort_by_key(|m| !matches!(m.action, Action::Desktop { .. }));
+
         let mut errors = Vec::new();
         
-        for action in actions {
-            if let Err(e) = self.start_action(action, event).await {
+        for matched in actions {
+            let action = matched.action.with_params(&matched.with);
+            if let Err(e) = self.start_action(action, event, &matched.rule_name).await {
                 errors.push(e);
             }
         }
@@ -223,57 +675,433 @@ impl ActionOrchestrator {
         Ok(())
     }
 
+    /// Starts an escalation tier after its delay and once the command is busy enough, unless the
+    /// command ends first (see `finish_action`)
+    async fn schedule_escalation(self: &Arc<Self>, tier: Vec<MatchedAction>, event: &ProcessEvent) {
+        let after = tier[0].after;
+        let cpu_above = tier[0].cpu_above;
+        let only_if = tier[0].only_if;
+        rule_log::debug!(event.pid, "Escalating PID {} with {} action(s) after {:?}", event.pid, tier.len(), after);
+        let orchestrator = Arc::clone(self);
+        let event = event.clone();
+        let pid = event.pid;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancel = Arc::clone(&cancelled);
+        // Registered before the timer can look, and before `finish_action` can cancel it
+        let mut escalations = self.escalations.write().await;
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            if (cpu_above.is_some() || only_if.is_some())
+                && !cpu::wait_until_busy(&event, cpu_above, only_if == Some(OnlyIf::Busy)).await
+            {
+                return;
+            }
+            // Held while starting, so `finish_action` either cancels the tier first or waits and
+            // then stops what it started
+            let escalations = orchestrator.escalations.read().await;
+            if cancel.load(Ordering::SeqCst) {
+                return;
+            }
+            if after.is_zero() {
+                rule_log::info!(event.pid, "PID {} is busy, starting rule '{}'", event.pid, tier[0].rule_name);
+            } else {
+                rule_log::info!(event.pid, "PID {} still running after {}s, escalating with rule '{}'",
+                      event.pid, after.as_secs(), tier[0].rule_name);
+            }
+            if let Err(e) = orchestrator.start_batch(tier, &event).await {
+                warn!("Failed to start escalation for PID {}: {}", event.pid, e);
+            }
+            drop(escalations);
+        });
+
+        let timers = escalations.entry(pid).or_default();
+        timers.retain(|escalation| !escalation.timer.is_finished());
+        timers.push(Escalation { cancelled, timer });
+    }
+
     async fn start_executable_action(
         &self,
         path: String,
         args: Option<Vec<String>>,
         action: Action,
         event: &ProcessEvent,
+        rule_name: &str,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let state_dir = self.state_dir(rule_name, &action);
+        let action_key = self.get_action_key(&action);
+        let child = self.spawn_executable(&path, args, &action_key, self.sandbox_policy(&action)?, state_dir, Some((event, rule_name))).await?;
+
+        // Store the active action
+        let active_action = ActiveAction {
+            child,
+            action,
+            trigger: Some(ActionTrigger { event_id: event.event_id.clone(), rule: rule_name.to_string(), started: event.timestamp }),
+        };
+
+        // Store by PID (synthetic or real)
+        {
+            let mut active_actions = self.active_actions.write().await;
+            active_actions.entry(event.pid).or_insert_with(Vec::new).push(active_action);
+        }
+
+        Ok(())
+    }
+
+    /// Tells the shared process for this action about a new command, starting it if needed
+    async fn start_shared_action(
+        &self,
+        path: String,
+        args: Option<Vec<String>>,
+        action: Action,
+        event: &ProcessEvent,
+        rule_name: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let action_key = self.get_action_key(&action);
+        let prewarmed = matches!(action, Action::Executable { prewarm: true, .. });
+        let cmd = if prewarmed { StdinCommand::Attach } else { StdinCommand::Open };
+        let open = cmd.message(&event.event_id);
+        let mut shared_actions = self.shared_actions.write().await;
+
+        if let Some(shared) = shared_actions.get_mut(&action_key) {
+            // A throttle action may have suspended it while it was idle
+            if let (Some(throttler), Some(pid)) = (&self.throttler, shared.active.child.id()) {
+                if throttler.is_suspended(pid) {
+                    throttler.resume(&[(pid, action_key.clone())]);
+                }
+            }
+            match Self::send_command(&mut shared.active.child, &open).await {
+                Ok(()) => {
+                    debug!("Sent PID {} to shared action '{}'", event.pid, action_key);
+                    shared.events.insert(event.pid, event.event_id.clone());
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!("Shared action '{}' is not accepting commands ({}), restarting it", action_key, e);
+                    if let Some(dead) = shared_actions.remove(&action_key) {
+                        self.terminate_action(dead.active, &action_key, false, None).await;
+                    }
+                }
+            }
+        }
+
+        if prewarmed {
+            info!("Prewarmed action '{}' is not running, starting it cold", action_key);
+        }
+        let state_dir = self.state_dir(rule_name, &action);
+        let mut child = self.spawn_executable(&path, args, &action_key, self.sandbox_policy(&action)?, state_dir, Some((event, rule_name))).await?;
+        Self::send_command(&mut child, &open).await?;
+        shared_actions.insert(action_key, SharedAction {
+            active: ActiveAction { child, action, trigger: None },
+            events: HashMap::from([(event.pid, event.event_id.clone())]),
+            prewarmed,
+        });
+        Ok(())
+    }
+
+    /// Starts every `prewarm = true` action in the config, so triggers only have to signal them
+    pub async fn prewarm_actions(&self) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let mut shared_actions = self.shared_actions.write().await;
+
+        for (index, rule) in config.rules.iter().enumerate() {
+            let escalations = rule.escalate.iter().flat_map(|escalation| escalation.action.as_vec());
+            for action in rule.action.as_vec().into_iter().chain(escalations) {
+                let action = &action.with_params(&rule.with);
+                let Action::Executable { path, args, prewarm: true, .. } = action else {
+                    continue;
+                };
+                let action_key = self.get_action_key(action);
+                if shared_actions.contains_key(&action_key) {
+                    continue;
+                }
+                let state_dir = self.state_dir(&rule.display_name(index), action);
+                let spawned = match self.sandbox_policy(action) {
+                    Ok(sandbox) => self.spawn_executable(path, args.clone(), &action_key, sandbox, state_dir, None).await,
+                    Err(e) => Err(e),
+                };
+                match spawned {
+                    Ok(child) => {
+                        info!("Prewarmed action '{}'", action_key);
+                        shared_actions.insert(action_key, SharedAction {
+                            active: ActiveAction { child, action: action.clone(), trigger: None },
+                            events: HashMap::new(),
+                            prewarmed: true,
+                        });
+                    }
+                    Err(e) => warn!("Failed to prewarm action '{}': {}", action_key, e),
+                }
+            }
+        }
+    }
+
+    /// Suspends the processes of a `throttle` action until the command ends, or notes them to be
+    /// resumed then
+    async fn start_throttle(&self, action: Action, event: &ProcessEvent, rule_name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Action::Throttle { suspend_on_start, resume_on_end, .. } = action else {
+            return Ok(());
+        };
+        let Some(ref throttler) = self.throttler else {
+            warn!("Throttling isn't set up, skipping throttle action of rule '{}'", rule_name);
+            return Ok(());
+        };
+        if suspend_on_start {
+            let targets = self.throttle_targets(&action).await?;
+            if targets.is_empty() {
+                debug!("Nothing to suspend for rule '{}'", rule_name);
+            }
+            let failed = throttler.suspend(event.pid, &targets, !resume_on_end);
+            if !failed.is_empty() {
+                warn!("Rule '{}' couldn't suspend {}", rule_name, failed.join(", "));
+            }
+        }
+        self.throttles.write().await.entry(event.pid).or_default().push(action);
+        Ok(())
+    }
+
+    /// Running processes a `throttle` action is about: the listed ones, or the `shared` and
+    /// `prewarm` action processes no command uses
+    async fn throttle_targets(&self, action: &Action) -> Result<Vec<(u32, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let Action::Throttle { target, processes, .. } = action else {
+            return Ok(Vec::new());
+        };
+        match target {
+            ThrottleTarget::List => throttle::find_processes(processes)
+                .map_err(|e| format!("Failed to list processes to throttle: {}", e).into()),
+            ThrottleTarget::SelfSpawned => Ok(self.shared_actions.read().await.iter()
+                .filter(|(_, shared)| shared.events.is_empty())
+                .filter_map(|(action_key, shared)| shared.active.child.id().map(|pid| (pid, action_key)))
+                // By process name, so that resuming them after a crash recognizes them
+                .map(|(pid, action_key)| (pid, throttle::process_name(pid).unwrap_or_else(|| action_key.clone())))
+                .collect()),
+        }
+    }
+
+    /// Attaches to a resident daemon for the duration of the command, launching it if needed
+    async fn start_attach_action(
+        &self,
+        socket: Option<String>,
+        path: Option<String>,
+        args: Option<Vec<String>>,
+        event: &ProcessEvent,
+        rule_name: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let socket = socket.unwrap_or_else(attach::default_socket_path);
+
+        let attachment = match Attachment::attach(&socket, &event.event_id).await {
+            Ok(attachment) => attachment,
+            Err(e) => {
+                let Some(path) = path else {
+                    return Err(format!("No daemon is listening on {}: {}", socket, e).into());
+                };
+                rule_log::info!(event.pid, "No daemon listening on {}, launching '{}'", socket, path);
+                let resolved_path = self.resolve_action_path(&path)?;
+                let args = args.unwrap_or_default();
+                let mut cmd = Command::new(&resolved_path);
+                cmd.args(&args);
+                let working_directory = self.get_viberot_root().ok();
+                if let Some(viberot_root) = &working_directory {
+                    cmd.current_dir(viberot_root);
+                }
+                // Not the command's environment, the daemon outlives it
+                let prefix = self.config.as_ref().and_then(|c| c.action_env_prefix.as_deref());
+                let mut env = ActionEnvBuilder::new(prefix).accessibility(&self.accessibility());
+                if let Some(room) = &self.waiting_room {
+                    env = env.waiting_room_url(room.url(None));
+                }
+                let env_vars = env.build();
+                let env_names = env_vars.iter().map(|(name, _)| name.clone()).collect();
+                cmd.envs(env_vars);
+                // The daemon outlives this command, so don't tie its stdio to us
+                cmd.stdin(Stdio::null());
+                cmd.stdout(Stdio::null());
+                cmd.stderr(Stdio::null());
+                let result = self.spawn_checked(cmd, &path, &resolved_path).await;
+                self.audit_exec(ExecRecord {
+                    timestamp: audit::unix_now(),
+                    path: path.clone(),
+                    resolved_path: resolved_path.display().to_string(),
+                    args,
+                    working_directory: working_directory.map(|dir| dir.display().to_string()),
+                    env_vars: env_names,
+                    sandboxed: false,
+                    trigger: Some(AuditTrigger::new(event, rule_name)),
+                    child_pid: result.as_ref().ok().and_then(|child| child.id()),
+                    error: result.as_ref().err().cloned(),
+                });
+                result.map_err(|e| format!("Failed to launch daemon '{}': {}", path, e))?;
+                self.attach_when_ready(socket, path, event, rule_name).await;
+                return Ok(());
+            }
+        };
+
+        rule_log::info!(event.pid, "Attached daemon at {} for monitored PID {}", socket, event.pid);
+        self.attachments.write().await.entry(event.pid).or_default().push(attachment);
+        Ok(())
+    }
+
+    /// Attaches to a freshly launched daemon once it listens, in the background so a slow daemon
+    /// doesn't hold up other commands' events
+    async fn attach_when_ready(&self, socket: String, path: String, event: &ProcessEvent, rule_name: &str) {
+        let attachments = Arc::clone(&self.attachments);
+        let pending_attachments = Arc::clone(&self.pending_attachments);
+        let events = self.events.clone();
+        let (pid, event_id, rule) = (event.pid, event.event_id.clone(), rule_name.to_string());
+        // Held until the task is recorded, so it can't look for itself before then
+        let mut pending = self.pending_attachments.write().await;
+        let task = tokio::spawn(async move {
+            match attach::attach_when_ready(&socket, &event_id, DAEMON_START_TIMEOUT).await {
+                Ok(attachment) => {
+                    // Held while recording, so `finish_action` either cancels this first or finds
+                    // the attachment
+                    let pending = pending_attachments.read().await;
+                    if !pending.contains_key(&pid) {
+                        attachment.detach().await;
+                        return;
+                    }
+                    rule_log::info!(pid, "Attached daemon at {} for monitored PID {}", socket, pid);
+                    attachments.write().await.entry(pid).or_default().push(attachment);
+                }
+                Err(e) => {
+                    let error = format!("Daemon '{}' did not start listening on {}: {}", path, socket, e);
+                    warn!("Failed to start action of rule '{}': {}", rule, error);
+                    let _ = events.send(OrchestratorEvent::ActionFailed { pid, rule, action: format!("attach:{}", socket), error });
+                }
+            }
+        });
+        let tasks = pending.entry(pid).or_default();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    /// Writes one JSON command line to an action's stdin
+    async fn send_command(
+        child: &mut tokio::process::Child,
+        command: &serde_json::Value,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let stdin = child.stdin.as_mut().ok_or("Action stdin is closed")?;
+        stdin.write_all(format!("{}\n", command).as_bytes()).await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    /// What a `sandbox = true` action may still do, or `None` if it isn't sandboxed
+    fn sandbox_policy(&self, action: &Action) -> Result<Option<SandboxPolicy>, Box<dyn std::error::Error + Send + Sync>> {
+        match action {
+            Action::Executable { sandbox: true, sandbox_allow_network, sandbox_writable, .. } => {
+                let writable = sandbox_writable.iter()
+                    .map(|path| self.resolve_action_path(path))
+                    .collect::<Result<_, _>>()?;
+                Ok(Some(SandboxPolicy { allow_network: *sandbox_allow_network, writable }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The state directory of an action of a rule, if state directories are set up and it could
+    /// be created
+    fn state_dir(&self, rule_name: &str, action: &Action) -> Option<PathBuf> {
+        let state_dirs = self.state_dirs.as_ref()?;
+        let action_key = self.get_action_key(action);
+        state_dirs.dir_for(rule_name, &action_key)
+            .inspect_err(|e| warn!("Failed to create the state directory of action '{}': {}", action_key, e))
+            .ok()
+    }
+
+    /// Checks that the program an action resolved to can be run, then spawns it, both on a
+    /// blocking thread and bounded by `spawn_timeout`: looking at or executing a file on a network
+    /// share that stopped answering can hang. Errors say which action and where it resolved to
+    async fn spawn_checked(&self, mut cmd: Command, path: &str, resolved_path: &Path) -> Result<tokio::process::Child, String> {
+        let timeout = self.config.as_ref().map_or(Some(config::DEFAULT_SPAWN_TIMEOUT), Config::spawn_timeout);
+        let action = path.to_string();
+        let (path, resolved_path) = (path.to_string(), resolved_path.to_path_buf());
+        let mut spawning = tokio::task::spawn_blocking(move || {
+            preflight::check(&path, &resolved_path).map_err(|e| format!("Can't start action '{}': {}", path, e))?;
+            cmd.spawn().map_err(|e| format!("Failed to spawn action '{}' (resolved to '{}'): {}", path, resolved_path.display(), e))
+        });
+        let Some(timeout) = timeout else {
+            return spawning.await.map_err(|e| e.to_string())?;
+        };
+        match tokio::time::timeout(timeout, &mut spawning).await {
+            Ok(joined) => joined.map_err(|e| e.to_string())?,
+            Err(_) => {
+                // Should it start after all, it's too late to be of use
+                tokio::spawn(async move {
+                    if let Ok(Ok(mut child)) = spawning.await {
+                        let _ = child.start_kill();
+                    }
+                });
+                Err(format!("Action '{}' didn't start within {} (`spawn_timeout`); is it on a network share that isn't answering?",
+                            action, humantime::format_duration(timeout)))
+            }
+        }
+    }
+
+    /// Spawns an executable action with the environment describing the triggering event and rule,
+    /// if there is one (prewarmed actions start before any command)
+    async fn spawn_executable(
+        &self,
+        path: &str,
+        args: Option<Vec<String>>,
+        action_key: &str,
+        mut sandbox: Option<SandboxPolicy>,
+        state_dir: Option<PathBuf>,
+        trigger: Option<(&ProcessEvent, &str)>,
+    ) -> Result<tokio::process::Child, Box<dyn std::error::Error + Send + Sync>> {
         // Resolve the path with environment variable expansion and predictable relative path handling
-        let resolved_path = self.resolve_action_path(&path)?;
-        
-        info!("Starting action: '{}' -> '{}'", path, resolved_path.display());
+        let resolved_path = self.resolve_action_path(path)?;
         
-        let mut cmd = Command::new(&resolved_path);
+        rule_log::info!(trigger.map(|(event, _)| event.pid), "Starting action: '{}' -> '{}'", path, resolved_path.display());
         
-        if let Some(args) = args {
-            cmd.args(args);
+        let args = args.unwrap_or_default();
+        // Sandboxed actions can still keep their state
+        if let (Some(policy), Some(dir)) = (&mut sandbox, &state_dir) {
+            policy.writable.push(dir.clone());
         }
+        let mut cmd = match &sandbox {
+            Some(policy) => {
+                let (program, wrapped_args) = sandbox::wrap(&resolved_path, &args, policy)
+                    .map_err(|e| format!("Failed to sandbox action '{}': {}", path, e))?;
+                debug!("Sandboxing action '{}' with {}", path, program.display());
+                let mut cmd = Command::new(program);
+                cmd.args(wrapped_args);
+                sandbox::prepare(&mut cmd);
+                cmd
+            }
+            None => {
+                let mut cmd = Command::new(&resolved_path);
+                cmd.args(&args);
+                cmd
+            }
+        };
 
         // Set a predictable working directory (viberot project root)
-        if let Ok(viberot_root) = self.get_viberot_root() {
-            cmd.current_dir(&viberot_root);
+        let working_directory = self.get_viberot_root().ok();
+        if let Some(viberot_root) = &working_directory {
+            cmd.current_dir(viberot_root);
             debug!("Set working directory to: {}", viberot_root.display());
         }
 
         // Set environment variables
-        cmd.env("VIBEROT_PID", event.pid.to_string());
-        cmd.env("VIBEROT_COMMAND", &event.command);
-        cmd.env("VIBEROT_TIMESTAMP", event.timestamp.to_string());
-        
-        if let Some(ref wd) = event.working_directory {
-            cmd.env("VIBEROT_WORKING_DIRECTORY", wd);
+        let prefix = self.config.as_ref().and_then(|c| c.action_env_prefix.as_deref());
+        let mut env = ActionEnvBuilder::new(prefix);
+        if let Some((event, rule_name)) = trigger {
+            env = env.event(event).rule_name(rule_name);
         }
-        
-        if let Some(ref session_id) = event.shell_session_id {
-            cmd.env("VIBEROT_SHELL_SESSION_ID", session_id);
+        if let Ok(viberot_root) = self.get_viberot_root() {
+            env = env.home(&viberot_root);
         }
-        
-        // Add note about synthetic PIDs for shell probe
-        match event.probe_source {
-            crate::platform::ProbeSource::PosixShell => {
-                cmd.env("VIBEROT_PID_TYPE", "synthetic");
-            }
-            _ => {
-                cmd.env("VIBEROT_PID_TYPE", "system");
-            }
+        if let Some(dir) = &state_dir {
+            env = env.state_dir(dir);
         }
-        
-        // Also provide the viberot root as an environment variable for the action
-        if let Ok(viberot_root) = self.get_viberot_root() {
-            cmd.env("VIBEROT_HOME", viberot_root.to_string_lossy().as_ref());
+        env = env.accessibility(&self.accessibility());
+        if let Some(room) = &self.waiting_room {
+            env = env.waiting_room_url(room.url(trigger.map(|(event, _)| event.pid)));
         }
+        let env_vars = env.build();
+        let env_names = env_vars.iter().map(|(name, _)| name.clone()).collect();
+        cmd.envs(env_vars);
 
         // Configure stdio
         cmd.stdin(Stdio::piped());
@@ -281,52 +1109,136 @@ impl ActionOrchestrator {
         cmd.stderr(Stdio::piped());
 
         // Spawn the process
-        let child = cmd.spawn().map_err(|e| {
-            format!("Failed to spawn action '{}' (resolved to '{}'): {}", 
-                   path, resolved_path.display(), e)
-        })?;
+        let result = self.spawn_checked(cmd, path, &resolved_path).await.and_then(|mut child| {
+            if sandbox.is_some() {
+                if let Err(e) = sandbox::contain(&child) {
+                    let _ = child.start_kill();
+                    return Err(format!("Failed to sandbox action '{}': {}", path, e));
+                }
+            }
+            Ok(child)
+        });
+        self.audit_exec(ExecRecord {
+            timestamp: audit::unix_now(),
+            path: path.to_string(),
+            resolved_path: resolved_path.display().to_string(),
+            args,
+            working_directory: working_directory.map(|dir| dir.display().to_string()),
+            env_vars: env_names,
+            sandboxed: sandbox.is_some(),
+            trigger: trigger.map(|(event, rule_name)| AuditTrigger::new(event, rule_name)),
+            child_pid: result.as_ref().ok().and_then(|child| child.id()),
+            error: result.as_ref().err().cloned(),
+        });
+        let mut child = result?;
         let child_pid = child.id().unwrap_or(0);
 
-        let pid_type = match event.probe_source {
-            crate::platform::ProbeSource::PosixShell => "synthetic",
-            _ => "system",
-        };
+        if let Some((event, _)) = trigger {
+            let pid_type = event.probe_source.pid_type();
+            rule_log::info!(event.pid, "Started action plugin '{}' with PID {} for monitored {} PID {}", 
+                  resolved_path.display(), child_pid, pid_type, event.pid);
+        } else {
+            info!("Started action plugin '{}' with PID {}", resolved_path.display(), child_pid);
+        }
 
-        info!("Started action plugin '{}' with PID {} for monitored {} PID {}", 
-              resolved_path.display(), child_pid, pid_type, event.pid);
+        if let Some(stdout) = child.stdout.take() {
+            let reporter = Reporter {
+                action_key: action_key.to_string(),
+                trigger: trigger.map(|(event, _)| (event.pid, event.event_id.clone())),
+                latency: self.latency.clone(),
+                shared_actions: self.shared_actions.clone(),
+                events: self.events.clone(),
+                input_activity: self.input_activity.clone(),
+            };
+            tokio::spawn(reporter.read(stdout));
+        }
 
-        // Store the active action
-        let active_action = ActiveAction {
-            child,
-            action,
-        };
+        Ok(child)
+    }
 
-        // Store by PID (synthetic or real)
-        {
-            let mut active_actions = self.active_actions.write().await;
-            active_actions.entry(event.pid).or_insert_with(Vec::new).push(active_action);
+    fn audit_exec(&self, record: ExecRecord) {
+        if let Some(audit) = &self.audit {
+            audit.record_exec(record);
         }
+    }
 
-        Ok(())
+    /// Human-readable summary of the running actions, for the state dump on SIGUSR1
+    pub async fn state_text(&self) -> String {
+        let mut text = String::new();
+        let active_actions = self.active_actions.read().await;
+        if active_actions.is_empty() {
+            text.push_str("No actions running\n");
+        }
+        for (pid, action_list) in active_actions.iter() {
+            let keys: Vec<String> = action_list.iter().map(|active| self.get_action_key(&active.action)).collect();
+            text.push_str(&format!("PID {}: {}\n", pid, keys.join(", ")));
+        }
+        for (action_key, shared) in self.shared_actions.read().await.iter() {
+            let kind = if shared.prewarmed { "prewarmed" } else { "shared" };
+            text.push_str(&format!("{} action '{}' for {} command(s)\n", kind, action_key, shared.events.len()));
+        }
+        let escalations: usize = self.escalations.read().await.values().map(Vec::len).sum();
+        if escalations > 0 {
+            text.push_str(&format!("{} escalation tier(s) waiting\n", escalations));
+        }
+        if let Some(ref throttler) = self.throttler {
+            text.push_str(&throttler.state_text());
+        }
+        text
     }
 
     /// Gracefully shutdown all active actions
     pub async fn shutdown(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         info!("Shutting down action orchestrator...");
+
+        for (_, timers) in self.escalations.write().await.drain() {
+            timers.iter().for_each(Escalation::cancel);
+        }
+        self.announcements.write().await.clear();
+        self.speaker.stop();
+        // Suspended processes are resumed first: they would stay suspended if the rest hangs
+        self.throttles.write().await.clear();
+        if let Some(ref throttler) = self.throttler {
+            throttler.resume_all();
+        }
+        self.workspaces.release_all();
         
         let mut active_actions = self.active_actions.write().await;
         for (pid, action_list) in active_actions.drain() {
             info!("Terminating {} action(s) for PID {}", action_list.len(), pid);
             for active_action in action_list {
-                self.terminate_action(active_action, &format!("PID {}", pid), true).await;
+                self.terminate_action(active_action, &format!("PID {}", pid), true, Some(pid)).await;
             }
         }
         
+        let mut shared_actions = self.shared_actions.write().await;
+        for (action_key, shared) in shared_actions.drain() {
+            info!("Terminating shared action '{}'", action_key);
+            self.terminate_action(shared.active, &action_key, true, None).await;
+        }
+
+        for (_, tasks) in self.pending_attachments.write().await.drain() {
+            tasks.iter().for_each(JoinHandle::abort);
+        }
+        for (_, attachments) in self.attachments.write().await.drain() {
+            for attachment in attachments {
+                attachment.detach().await;
+            }
+        }
+
         // Clear all single instance tracking
         {
             let mut running_actions = self.running_single_instance_actions.write().await;
             running_actions.clear();
         }
+
+        if let Some(summary) = self.latency.summary() {
+            info!("Action latency over the last {} trigger(s): median {} ms, p95 {} ms, max {} ms",
+                  summary.count, summary.median.as_millis(), summary.p95.as_millis(), summary.max.as_millis());
+        }
+        if let Some(audit) = &self.audit {
+            audit.flush();
+        }
         
         info!("Action orchestrator shutdown complete");
         Ok(())
@@ -338,55 +1250,212 @@ impl ActionOrchestrator {
     /// * `active_action` - The action to terminate
     /// * `target_name` - Human-readable identifier for logging
     /// * `wait_for_completion` - If true, waits for termination; if false, spawns async task
-    async fn terminate_action(&self, mut active_action: ActiveAction, target_name: &str, wait_for_completion: bool) {
+    /// * `command_pid` - The command the action was started for alone, which gets a `failed`
+    ///   result if the action exits with a failing status
+    async fn terminate_action(&self, mut active_action: ActiveAction, target_name: &str, wait_for_completion: bool, command_pid: Option<u32>) {
         // Close stdin to signal the action plugin
         if let Some(stdin) = active_action.child.stdin.take() {
             drop(stdin);
         }
+        let failures = command_pid.map(|pid| (pid, self.get_action_key(&active_action.action), self.events.clone()));
         
         if wait_for_completion {
             // Synchronous termination for shutdown scenarios
-            self.terminate_action_sync(&mut active_action, target_name).await;
+            Self::wait_or_kill(&mut active_action, target_name, failures).await;
         } else {
             // Asynchronous termination for runtime scenarios
             let target_name = target_name.to_string();
             tokio::spawn(async move {
-                Self::terminate_action_async(active_action, &target_name).await;
+                Self::wait_or_kill(&mut active_action, &target_name, failures).await;
             });
         }
     }
     
-    /// Synchronous termination with timeout and force kill
-    async fn terminate_action_sync(&self, active_action: &mut ActiveAction, target_name: &str) {
+    /// Termination with timeout and force kill, reporting a failing exit status to `failures`
+    async fn wait_or_kill(
+        active_action: &mut ActiveAction,
+        target_name: &str,
+        failures: Option<(u32, String, broadcast::Sender<OrchestratorEvent>)>,
+    ) {
         match tokio::time::timeout(
-            tokio::time::Duration::from_secs(3),
+            action_protocol::EXIT_GRACE,
             active_action.child.wait()
         ).await {
-            Ok(_) => {
-                debug!("Action for {} exited gracefully", target_name);
+            Ok(Ok(status)) => {
+                debug!("Action for {} exited gracefully ({})", target_name, status);
+                if let (Some(exit_code), Some((pid, action, events))) = (status.code().filter(|code| *code != 0), failures) {
+                    // Nobody listening is fine
+                    let _ = events.send(OrchestratorEvent::ActionResult { pid, action, result: ActionResult::failed(exit_code) });
+                }
             }
+            Ok(Err(e)) => debug!("Failed to wait for the action for {}: {}", target_name, e),
             Err(_) => {
                 info!("Action for {} did not exit gracefully, force killing", target_name);
                 let _ = active_action.child.kill().await;
             }
         }
     }
-    
-    /// Asynchronous termination with delayed force kill
-    async fn terminate_action_async(mut active_action: ActiveAction, target_name: &str) {
-        tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
-        if let Err(_e) = active_action.child.kill().await {
-            // Process already exited, which is fine
-            debug!("Action for {} already exited", target_name);
+
+    /// Called when a probe detects that a monitored process has ended: announces it, then
+    /// finishes its actions
+    pub async fn command_ended(&self, target_pid: u32, exit_code: Option<i32>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(announcements) = self.announcements.write().await.remove(&target_pid) {
+            for announcement in announcements {
+                let text = announcement.text(exit_code);
+                match (&announcement.action, &announcement.terminal) {
+                    (Action::Speak { voice, .. }, _) => {
+                        if self.accessibility().captions {
+                            self.caption(&announcement.rule, &text);
+                        }
+                        self.speaker.speak(text, voice.clone());
+                    }
+                    (Action::Bell { bell, .. }, Some(terminal)) => terminal.ring(&text, *bell).await,
+                    _ => continue,
+                }
+                let action = self.get_action_key(&announcement.action);
+                self.emit(OrchestratorEvent::ActionEnded { pid: target_pid, action });
+            }
+        }
+        // The last line before their stdin closes
+        if let Some(action_list) = self.active_actions.write().await.get_mut(&target_pid) {
+            for active_action in action_list {
+                let Some(ref trigger) = active_action.trigger else {
+                    continue;
+                };
+                let end = EndSummary::new(trigger.started, exit_code, &trigger.rule).message(&trigger.event_id);
+                if let Err(e) = Self::send_command(&mut active_action.child, &end).await {
+                    debug!("Failed to tell an action of rule '{}' that PID {} ended: {}", trigger.rule, target_pid, e);
+                }
+            }
+        }
+        let end_actions = self.end_actions.write().await.remove(&target_pid).unwrap_or_default();
+        let busy_with = self.input_activity.as_ref().and_then(|activity| activity.finish(target_pid));
+        self.finish_action(target_pid).await?;
+        for end_action in end_actions {
+            let summary = EndSummary::new(end_action.event.timestamp, exit_code, &end_action.rule);
+            self.start_end_action(end_action, summary).await;
+        }
+        if let Some(command) = busy_with {
+            self.end_distraction(&command);
+        }
+        Ok(())
+    }
+
+    /// Tells the actions started for a command how far along it is
+    pub async fn command_progress(&self, target_pid: u32, percent: Option<u8>, message: Option<&str>) {
+        let mut active_actions = self.active_actions.write().await;
+        let Some(action_list) = active_actions.get_mut(&target_pid) else {
+            return;
+        };
+        for active_action in action_list {
+            let Some(ref trigger) = active_action.trigger else {
+                continue;
+            };
+            let mut line = StdinCommand::Progress.message(&trigger.event_id);
+            line["percent"] = serde_json::json!(percent);
+            line["message"] = serde_json::json!(message);
+            if let Err(e) = Self::send_command(&mut active_action.child, &line).await {
+                debug!("Failed to tell an action of rule '{}' how far PID {} is: {}", trigger.rule, target_pid, e);
+            }
+        }
+    }
+
+    /// Starts an `on_end = true` action with the summary as its last argument, leaving it to finish
+    /// on its own
+    async fn start_end_action(&self, end_action: EndAction, summary: EndSummary) {
+        let EndAction { action, rule, event } = end_action;
+        let Action::Executable { ref path, ref args, .. } = action else {
+            return;
+        };
+        let action_key = self.get_action_key(&action);
+        let mut args = args.clone().unwrap_or_default();
+        args.push(serde_json::to_string(&summary).unwrap_or_default());
+        let result = match self.sandbox_policy(&action) {
+            Ok(policy) => self.spawn_executable(path, Some(args), &action_key, policy, self.state_dir(&rule, &action), Some((&event, &rule))).await,
+            Err(e) => Err(e),
+        };
+        let mut child = match result {
+            Ok(child) => child,
+            Err(e) => {
+                warn!("Failed to start the action of rule '{}' for the end of PID {}: {}", rule, event.pid, e);
+                self.emit(OrchestratorEvent::ActionFailed { pid: event.pid, rule, action: action_key, error: e.to_string() });
+                return;
+            }
+        };
+        self.emit(OrchestratorEvent::ActionStarted { pid: event.pid, rule, action: action_key.clone() });
+        // Nothing more to tell it
+        drop(child.stdin.take());
+        let events = self.events.clone();
+        let pid = event.pid;
+        tokio::spawn(async move {
+            match child.wait().await {
+                Ok(status) => {
+                    debug!("Action '{}' for the end of PID {} exited ({})", action_key, pid, status);
+                    if let Some(exit_code) = status.code().filter(|code| *code != 0) {
+                        let _ = events.send(OrchestratorEvent::ActionResult { pid, action: action_key.clone(), result: ActionResult::failed(exit_code) });
+                    }
+                }
+                Err(e) => debug!("Failed to wait for action '{}' for the end of PID {}: {}", action_key, pid, e),
+            }
+            let _ = events.send(OrchestratorEvent::ActionEnded { pid, action: action_key });
+        });
+    }
+
+    /// Makes sure a user still busy with the actions notices their command is done
+    fn end_distraction(&self, command: &str) {
+        info!("User was still busy with the actions when '{}' ended, announcing it", command);
+        if let Some(ref notifier) = self.notifier {
+            if let Err(e) = notifier.announce(t(Msg::DistractionOverTitle), &tf(Msg::DistractionOverMessage, &[&command])) {
+                warn!("Failed to announce that '{}' ended: {}", command, e);
+            }
+        }
+        if self.input_activity.as_ref().is_some_and(|activity| activity.sound()) {
+            notify::play_sound();
         }
     }
 
-    /// Called when a probe detects that a monitored process has ended
+    /// Shows spoken text as a notification as well, for `[accessibility] captions`
+    fn caption(&self, rule: &str, text: &str) {
+        let Some(ref notifier) = self.notifier else {
+            return;
+        };
+        if let Err(e) = notifier.announce(rule, text) {
+            warn!("Failed to caption announcement of rule '{}': {}", rule, e);
+        }
+    }
+
+    /// Stops the actions of a command, e.g. when it ended or they were dismissed
     pub async fn finish_action(&self, target_pid: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Dismissed actions end quietly
+        if let Some(ref activity) = self.input_activity {
+            activity.forget(target_pid);
+        }
+        // Tiers that haven't fired won't
+        if let Some(timers) = self.escalations.write().await.remove(&target_pid) {
+            timers.iter().for_each(Escalation::cancel);
+        }
+        // Dismissed commands aren't announced
+        if let Some(announcements) = self.announcements.write().await.remove(&target_pid) {
+            for announcement in announcements {
+                let action = self.get_action_key(&announcement.action);
+                self.emit(OrchestratorEvent::ActionEnded { pid: target_pid, action });
+            }
+        }
+        if let Some(end_actions) = self.end_actions.write().await.remove(&target_pid) {
+            debug!("Not starting {} action(s) for the end of dismissed PID {}", end_actions.len(), target_pid);
+        }
+
+        self.finish_throttles(target_pid).await;
+
+        let workspaces = Arc::clone(&self.workspaces);
+        if let Ok(Some(desktop)) = tokio::task::spawn_blocking(move || workspaces.release(target_pid)).await {
+            self.emit(OrchestratorEvent::ActionEnded { pid: target_pid, action: format!("desktop:{}", desktop) });
+        }
+
         let mut active_actions = self.active_actions.write().await;
         
         if let Some(action_list) = active_actions.remove(&target_pid) {
-            info!("Finishing {} action(s) for PID {}", action_list.len(), target_pid);
+            rule_log::info!(target_pid, "Finishing {} action(s) for PID {}", action_list.len(), target_pid);
             
             // Clean up single instance tracking for all actions
             {
@@ -402,12 +1471,80 @@ impl ActionOrchestrator {
             
             // Terminate all actions asynchronously to avoid blocking the event loop
             for active_action in action_list {
-                self.terminate_action(active_action, &format!("PID {}", target_pid), false).await;
+                let action = self.get_action_key(&active_action.action);
+                self.terminate_action(active_action, &format!("PID {}", target_pid), false, Some(target_pid)).await;
+                self.emit(OrchestratorEvent::ActionEnded { pid: target_pid, action });
             }
         } else {
-            debug!("No active actions found for PID {}", target_pid);
+            rule_log::debug!(target_pid, "No active actions found for PID {}", target_pid);
+        }
+        drop(active_actions);
+
+        self.finish_shared_actions(target_pid).await;
+
+        if let Some(tasks) = self.pending_attachments.write().await.remove(&target_pid) {
+            tasks.iter().for_each(JoinHandle::abort);
+        }
+        if let Some(attachments) = self.attachments.write().await.remove(&target_pid) {
+            for attachment in attachments {
+                let action = format!("attach:{}", attachment.socket());
+                attachment.detach().await;
+                self.emit(OrchestratorEvent::ActionEnded { pid: target_pid, action });
+            }
+        }
+
+        if let Some(ref notifier) = self.notifier {
+            notifier.hide(target_pid);
         }
 
         Ok(())
     }
+
+    /// Resumes what a command's `throttle` actions suspended, and what they resume when it ends
+    async fn finish_throttles(&self, target_pid: u32) {
+        let throttles = self.throttles.write().await.remove(&target_pid).unwrap_or_default();
+        let Some(ref throttler) = self.throttler else {
+            return;
+        };
+        for action in &throttles {
+            if let Action::Throttle { suspend_on_start: false, resume_on_end: true, .. } = action {
+                match self.throttle_targets(action).await {
+                    Ok(targets) => throttler.resume(&targets),
+                    Err(e) => warn!("{}", e),
+                }
+            }
+        }
+        throttler.release(target_pid);
+        for action in &throttles {
+            self.emit(OrchestratorEvent::ActionEnded { pid: target_pid, action: self.get_action_key(action) });
+        }
+    }
+
+    /// Tells shared actions that a command ended, stopping the ones with no commands left
+    async fn finish_shared_actions(&self, target_pid: u32) {
+        let mut shared_actions = self.shared_actions.write().await;
+        let mut idle = Vec::new();
+
+        for (action_key, shared) in shared_actions.iter_mut() {
+            let Some(event_id) = shared.events.remove(&target_pid) else {
+                continue;
+            };
+            let cmd = if shared.prewarmed { StdinCommand::Detach } else { StdinCommand::Close };
+            let close = cmd.message(&event_id);
+            if let Err(e) = Self::send_command(&mut shared.active.child, &close).await {
+                debug!("Failed to notify shared action '{}' that PID {} ended: {}", action_key, target_pid, e);
+            }
+            self.emit(OrchestratorEvent::ActionEnded { pid: target_pid, action: action_key.clone() });
+            if shared.events.is_empty() && !shared.prewarmed {
+                idle.push(action_key.clone());
+            }
+        }
+
+        for action_key in idle {
+            if let Some(shared) = shared_actions.remove(&action_key) {
+                info!("Last command for shared action '{}' ended, stopping it", action_key);
+                self.terminate_action(shared.active, &action_key, false, None).await;
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,323 @@
+// Action protocol
+// Everything an `exec` action sees of VibeRot besides its environment (see `action_env`): the JSON
+// lines written to its stdin, the ones it may print on stdout, and how it's stopped. What came of
+// an action, a `result` it printed or a failing exit status, is kept with its command in the
+// recent events, to see which actions users actually engage with. The
+// orchestrator builds and parses these messages through the types here, so the schema printed by
+// `viberot-service sdk schema` (docs/action-protocol.json) and the helper libraries generated from
+// it by `sdk generate` can't drift from what the service does.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::action_env::{self, Schema};
+
+/// Bumped whenever a message is removed or changes meaning
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How long an action has to exit once its stdin closed, before it's killed
+pub const EXIT_GRACE: Duration = Duration::from_secs(3);
+
+/// A JSON line VibeRot writes to an action's stdin, e.g. `{"cmd": "open", "id": "<event id>"}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdinCommand {
+    Open,
+    Close,
+    Attach,
+    Detach,
+    Progress,
+    End,
+}
+
+impl StdinCommand {
+    pub const ALL: &'static [StdinCommand] = &[
+        StdinCommand::Open,
+        StdinCommand::Close,
+        StdinCommand::Attach,
+        StdinCommand::Detach,
+        StdinCommand::Progress,
+        StdinCommand::End,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StdinCommand::Open => "open",
+            StdinCommand::Close => "close",
+            StdinCommand::Attach => "attach",
+            StdinCommand::Detach => "detach",
+            StdinCommand::Progress => "progress",
+            StdinCommand::End => "end",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            StdinCommand::Open => "A matched command started; sent to `shared = true` actions, including for the command they were started for",
+            StdinCommand::Close => "A command `open` announced ended; sent to `shared = true` actions",
+            StdinCommand::Attach => "A matched command started; sent to `prewarm = true` actions, which should come on screen",
+            StdinCommand::Detach => "A command `attach` announced ended; `prewarm = true` actions should hide once none is left",
+            StdinCommand::Progress => "How far along the command the action was started for is, when its source reports that, e.g. an editor's task over `[websocket_source]`; sent to actions started for one command",
+            StdinCommand::End => "The command the action was started for ended, what it came to; the last line before stdin closes, sent to actions started for one command",
+        }
+    }
+
+    /// Keys of the line besides `cmd`
+    pub fn fields(self) -> Vec<FieldSpec> {
+        let mut fields = vec![FieldSpec {
+            name: "id",
+            value_type: "string",
+            description: "Event ID of the command, as in the EVENT_ID variable",
+        }];
+        match self {
+            StdinCommand::Progress => fields.extend([
+                FieldSpec {
+                    name: "percent",
+                    value_type: "integer|null",
+                    description: "From 0 to 100; null if the source only said what it's doing",
+                },
+                FieldSpec {
+                    name: "message",
+                    value_type: "string|null",
+                    description: "What the command is doing, e.g. \"Compiling\"",
+                },
+            ]),
+            StdinCommand::End => fields.extend(EndSummary::fields()),
+            _ => {}
+        }
+        fields
+    }
+
+    /// The line for a command about the given start event
+    pub fn message(self, event_id: &str) -> serde_json::Value {
+        serde_json::json!({ "cmd": self.name(), "id": event_id })
+    }
+}
+
+/// What a command came to, in the `end` line and as the last argument of `on_end = true` actions,
+/// e.g. `{"elapsed_secs": 95, "exit_code": 0, "rule": "Long builds"}`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EndSummary {
+    /// Seconds since the command started
+    pub elapsed_secs: u64,
+    /// None if the probe doesn't know
+    pub exit_code: Option<i32>,
+    /// Rule the action belongs to
+    pub rule: String,
+}
+
+impl EndSummary {
+    /// Summary of a command that started at `started` (seconds since the Unix epoch) and just ended
+    pub fn new(started: u64, exit_code: Option<i32>, rule: &str) -> Self {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+        Self { elapsed_secs: now.saturating_sub(started), exit_code, rule: rule.to_string() }
+    }
+
+    /// The `end` line for the command with the given event ID
+    pub fn message(&self, event_id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "cmd": StdinCommand::End.name(),
+            "id": event_id,
+            "elapsed_secs": self.elapsed_secs,
+            "exit_code": self.exit_code,
+            "rule": self.rule,
+        })
+    }
+
+    fn fields() -> [FieldSpec; 3] {
+        [
+            FieldSpec {
+                name: "elapsed_secs",
+                value_type: "integer",
+                description: "Seconds since the command started",
+            },
+            FieldSpec {
+                name: "exit_code",
+                value_type: "integer|null",
+                description: "Exit status of the command; null if the probe doesn't know it",
+            },
+            FieldSpec {
+                name: "rule",
+                value_type: "string",
+                description: "Name of the rule the action belongs to",
+            },
+        ]
+    }
+}
+
+/// A JSON line an action may print on stdout, e.g. `{"event": "visible", "id": null}`. Other
+/// output is only logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionEvent {
+    Visible,
+    Dismissed,
+    Result,
+}
+
+impl ActionEvent {
+    pub const ALL: &'static [ActionEvent] = &[ActionEvent::Visible, ActionEvent::Dismissed, ActionEvent::Result];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ActionEvent::Visible => "visible",
+            ActionEvent::Dismissed => "dismissed",
+            ActionEvent::Result => "result",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ActionEvent::Visible => "The action came on screen; measures how long it took to appear, and feeds the streaks",
+            ActionEvent::Dismissed => "The user closed the action before the command ended; counts against the streaks",
+            ActionEvent::Result => "What came of the action, kept with the command in `viberot-service recent`, e.g. outcome \"clicked\" for a notification button, \"watched\" with the seconds the user watched, or \"ignored\"; may be printed more than once",
+        }
+    }
+
+    /// Keys of the line besides `event`
+    pub fn fields(self) -> Vec<FieldSpec> {
+        let mut fields = vec![FieldSpec {
+            name: "id",
+            value_type: "string|null",
+            description: "Event ID of the command it's about; null or left out for the one the action was started for",
+        }];
+        if self == ActionEvent::Result {
+            fields.extend([
+                FieldSpec {
+                    name: "outcome",
+                    value_type: "string",
+                    description: "What the user did, in a word; up to 64 characters",
+                },
+                FieldSpec {
+                    name: "seconds",
+                    value_type: "number|null",
+                    description: "How long the user engaged with the action",
+                },
+                FieldSpec {
+                    name: "detail",
+                    value_type: "string|null",
+                    description: "Anything else worth keeping, e.g. the button clicked; up to 500 characters",
+                },
+            ]);
+        }
+        fields
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|event| event.name() == name)
+    }
+}
+
+/// Longest `outcome` kept
+const MAX_OUTCOME_LENGTH: usize = 64;
+/// Longest `detail` kept
+const MAX_DETAIL_LENGTH: usize = 500;
+
+/// What came of an action for a command: the `result` it printed, or how it exited
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActionResult {
+    pub outcome: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seconds: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Exit status of an action that failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+impl ActionResult {
+    /// A reported result, cut down to what's kept; None without an outcome
+    pub fn reported(outcome: Option<&str>, seconds: Option<f64>, detail: Option<&str>) -> Option<Self> {
+        let outcome = outcome.map(str::trim).filter(|outcome| !outcome.is_empty())?;
+        Some(Self {
+            outcome: outcome.chars().take(MAX_OUTCOME_LENGTH).collect(),
+            seconds: seconds.filter(|seconds| seconds.is_finite() && *seconds >= 0.0),
+            detail: detail.map(|detail| detail.chars().take(MAX_DETAIL_LENGTH).collect()),
+            exit_code: None,
+        })
+    }
+
+    /// The result of an action exiting with a failing status once its command ended
+    pub fn failed(exit_code: i32) -> Self {
+        Self { outcome: "failed".to_string(), seconds: None, detail: None, exit_code: Some(exit_code) }
+    }
+}
+
+impl std::fmt::Display for ActionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.outcome)?;
+        if let Some(seconds) = self.seconds {
+            write!(f, " for {:.0}s", seconds)?;
+        }
+        if let Some(exit_code) = self.exit_code {
+            write!(f, " with exit status {}", exit_code)?;
+        }
+        if let Some(ref detail) = self.detail {
+            write!(f, ": {}", detail)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldSpec {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub value_type: &'static str,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageSpec {
+    /// Value of the `cmd` (stdin) or `event` (stdout) key
+    pub name: &'static str,
+    pub description: &'static str,
+    pub fields: Vec<FieldSpec>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExitSpec {
+    pub description: &'static str,
+    pub grace_period_seconds: u64,
+}
+
+/// The whole contract between VibeRot and its actions
+#[derive(Debug, Serialize)]
+pub struct Protocol {
+    pub version: u32,
+    pub env: Schema,
+    /// Lines written to the action's stdin, keyed by `cmd`
+    pub stdin: Vec<MessageSpec>,
+    /// Lines the action may print on stdout, keyed by `event`
+    pub stdout: Vec<MessageSpec>,
+    pub exit: ExitSpec,
+}
+
+pub fn protocol(prefix: Option<&str>) -> Protocol {
+    Protocol {
+        version: PROTOCOL_VERSION,
+        env: action_env::schema(prefix),
+        stdin: StdinCommand::ALL.iter()
+            .map(|command| MessageSpec {
+                name: command.name(),
+                description: command.description(),
+                fields: command.fields(),
+            })
+            .collect(),
+        stdout: ActionEvent::ALL.iter()
+            .map(|event| MessageSpec {
+                name: event.name(),
+                description: event.description(),
+                fields: event.fields(),
+            })
+            .collect(),
+        exit: ExitSpec {
+            description: "Stdin closes when the command ends (for shared actions, when the last one ended) or the service stops; the action should exit then, or it's killed after the grace period. `on_end = true` actions start once the command ended, with the `end` line's summary as their last argument, and aren't killed. An action started for one command (not shared) exiting with a non-zero status records a \"failed\" result for it",
+            grace_period_seconds: EXIT_GRACE.as_secs(),
+        },
+    }
+}
+
+/// Machine-readable description of the protocol
+pub fn protocol_json(prefix: Option<&str>) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(&protocol(prefix))
+}
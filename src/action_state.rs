@@ -0,0 +1,90 @@
+// Action state directories
+// Every exec action gets a directory of its own under ~/.viberot/state, passed as
+// `VIBEROT_STATE_DIR`, for caches, cookies and anything else it wants to keep between runs. The
+// directory is named after the rule plus a hash of the rule name and the action, so it stays the
+// same across runs and restarts for as long as neither changes. Directories that haven't been
+// used for `action_state_retention` are removed when the service starts and once a day.
+
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::audit;
+
+/// How long an unused state directory is kept when `action_state_retention` isn't set
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// When the directory was last handed to an action, as a Unix timestamp
+const LAST_USED_FILE: &str = ".viberot-last-used";
+
+pub struct ActionStateDirs {
+    root: PathBuf,
+    /// `None` keeps directories forever
+    retention: Option<Duration>,
+}
+
+impl ActionStateDirs {
+    /// State directories under `root`, removed after `retention` without use (0 keeps them)
+    pub fn new(root: impl AsRef<Path>, retention: Duration) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            retention: (!retention.is_zero()).then_some(retention),
+        }
+    }
+
+    /// The directory of an action of a rule, created if needed and marked as used now
+    pub fn dir_for(&self, rule_name: &str, action_key: &str) -> std::io::Result<PathBuf> {
+        let dir = self.root.join(dir_name(rule_name, action_key));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(LAST_USED_FILE), audit::unix_now().to_string())?;
+        Ok(dir)
+    }
+
+    /// Removes the directories unused for longer than the retention, returning how many
+    pub fn prune(&self) -> std::io::Result<usize> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        let entries = match std::fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let cutoff = audit::unix_now().saturating_sub(retention.as_secs());
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            let dir = entry.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            if last_used(&dir).is_some_and(|at| at < cutoff) {
+                std::fs::remove_dir_all(&dir)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// `<rule>-<hash>`, with the rule name cut down to what's safe in a file name everywhere
+fn dir_name(rule_name: &str, action_key: &str) -> String {
+    let slug: String = rule_name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c.to_ascii_lowercase() } else { '-' })
+        .take(40)
+        .collect();
+    let hash = Sha256::digest(format!("{}\0{}", rule_name, action_key));
+    let hash: String = hash[..6].iter().map(|byte| format!("{:02x}", byte)).collect();
+    match slug.trim_matches('-') {
+        "" => hash,
+        slug => format!("{}-{}", slug, hash),
+    }
+}
+
+/// When a directory was last used, or when it was last modified if it has no record of that
+fn last_used(dir: &Path) -> Option<u64> {
+    if let Some(at) = std::fs::read_to_string(dir.join(LAST_USED_FILE)).ok().and_then(|at| at.trim().parse().ok()) {
+        return Some(at);
+    }
+    let modified = std::fs::metadata(dir).and_then(|metadata| metadata.modified()).ok()?;
+    modified.duration_since(std::time::UNIX_EPOCH).ok().map(|age| age.as_secs())
+}
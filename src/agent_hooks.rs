@@ -0,0 +1,170 @@
+// Coding agent hooks
+// `[agent_hooks]` turns the lifecycle hooks of coding agents into commands: Gemini CLI runs
+// scripts/agent_hook.py before and after each agent run (its BeforeAgent and AfterAgent hooks),
+// Cursor when a prompt is submitted and when the agent stops (beforeSubmitPrompt and stop), and
+// Codex CLI runs it as its `notify` program when a turn completes. The script hands the service one
+// JSON line per hook over a named pipe (Windows) or Unix socket (elsewhere), whose path the service
+// writes to ~/.viberot/.agents:
+//   {"agent": "gemini", "event": "started", "session": "8f1c", "model": "gemini-2.5-pro",
+//    "prompt": "Fix the failing tests", "working_directory": "/src/app"}
+//   {"agent": "gemini", "event": "finished", "session": "8f1c"}
+// The command is the agent's name, so rules match "gemini", "cursor" or "codex", with the `agent`,
+// `model` and `prompt` (the first line of the prompt, shortened) tags for `when` conditions, and
+// the project (Cursor's first workspace folder) as the working directory. Cursor's runs have their
+// own probe, `Cursor`, so rules can tell the editor from the CLIs. Codex only says when a turn is
+// done: a finish without a start starts and ends the command at once, which is enough for
+// notifications. Hooks are one-shot, so a session's command runs until it finishes or the same
+// session starts again, not until the script disconnects.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::config::AgentHooksConfig;
+use crate::pipe_source::{self, Connection};
+use crate::platform::{ProbeSource, ProcessEvent, ProcessLifecycleEvent};
+use crate::runtime_paths;
+
+/// Synthetic PIDs of agent runs, between the atuin and pipe source ones
+static AGENT_PID_COUNTER: AtomicU32 = AtomicU32::new(2_250_000_000);
+
+/// Characters of the prompt kept in the `prompt` tag
+const PROMPT_SUMMARY_CHARS: usize = 80;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum HookEvent {
+    Started,
+    Finished,
+}
+
+#[derive(Debug, Deserialize)]
+struct HookMessage {
+    /// "gemini", "cursor", "codex", ...
+    agent: String,
+    event: HookEvent,
+    /// The agent's session or thread, for telling concurrent runs apart
+    #[serde(default)]
+    session: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    prompt: Option<String>,
+    #[serde(default)]
+    working_directory: Option<String>,
+    #[serde(default)]
+    exit_code: Option<i32>,
+}
+
+/// Running agent runs by agent and session
+type Running = Arc<Mutex<HashMap<(String, String), u32>>>;
+
+/// Listens for the hook script until stopped
+pub struct AgentHooks {
+    task: JoinHandle<()>,
+    #[cfg_attr(not(unix), allow(dead_code))] // Named pipes go away with their handles
+    path: String,
+}
+
+impl AgentHooks {
+    pub fn start(config: &AgentHooksConfig, lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let path = config.path.clone().unwrap_or_else(runtime_paths::agent_endpoint);
+        let running = Running::default();
+        let task = pipe_source::listen(&path, move |connection| {
+            read_hooks(connection, Arc::clone(&running), lifecycle_sender.clone())
+        })?;
+        let discovery_file = runtime_paths::agent_discovery_file()?;
+        if let Some(dir) = discovery_file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&discovery_file, format!("{}\n", path))
+            .map_err(|e| format!("Failed to write {}: {}", discovery_file.display(), e))?;
+        info!("Receiving coding agent hooks on {}", path);
+        Ok(Self { task, path })
+    }
+
+    pub fn stop(&self) {
+        self.task.abort();
+        #[cfg(unix)]
+        let _ = std::fs::remove_file(&self.path);
+        if let Ok(discovery_file) = runtime_paths::agent_discovery_file() {
+            let _ = std::fs::remove_file(discovery_file);
+        }
+    }
+}
+
+async fn read_hooks(connection: Connection, running: Running, lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) {
+    let mut lines = BufReader::new(connection).lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) if line.trim().is_empty() => {}
+            Ok(Some(line)) => match serde_json::from_str::<HookMessage>(&line) {
+                Ok(message) => handle_hook(message, &running, &lifecycle_sender),
+                Err(e) => warn!("Ignoring an agent hook message VibeRot doesn't know ({}): {}", e, line),
+            },
+            Ok(None) => break,
+            Err(e) => {
+                debug!("Agent hook connection failed: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn handle_hook(message: HookMessage, running: &Running, lifecycle_sender: &broadcast::Sender<ProcessLifecycleEvent>) {
+    let key = (message.agent.clone(), message.session.clone().unwrap_or_default());
+    let mut running = running.lock().unwrap();
+    match message.event {
+        HookEvent::Started => {
+            // Starting a session again ends its previous run
+            if let Some(previous) = running.remove(&key) {
+                let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid: previous, exit_code: None });
+            }
+            running.insert(key, start(&message, lifecycle_sender));
+        }
+        HookEvent::Finished => {
+            let pid = match running.remove(&key) {
+                Some(pid) => pid,
+                None => {
+                    debug!("{} finished a run it didn't report starting, starting and ending it now", message.agent);
+                    start(&message, lifecycle_sender)
+                }
+            };
+            let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid, exit_code: message.exit_code });
+        }
+    }
+}
+
+/// Sends the start of an agent run, returning its PID
+fn start(message: &HookMessage, lifecycle_sender: &broadcast::Sender<ProcessLifecycleEvent>) -> u32 {
+    let pid = AGENT_PID_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let source = if message.agent == "cursor" { ProbeSource::Cursor } else { ProbeSource::Agent };
+    let mut event = ProcessEvent::new(pid, message.agent.clone(), source);
+    if let Some(ref working_directory) = message.working_directory {
+        event = event.with_working_directory(working_directory.clone());
+    }
+    event.tags.insert("agent".to_string(), message.agent.clone());
+    if let Some(ref model) = message.model {
+        event.tags.insert("model".to_string(), model.clone());
+    }
+    if let Some(prompt) = message.prompt.as_deref().map(summarize).filter(|summary| !summary.is_empty()) {
+        event.tags.insert("prompt".to_string(), prompt);
+    }
+    debug!("{} run started with synthetic PID {}", message.agent, pid);
+    let _ = lifecycle_sender.send(ProcessLifecycleEvent::Started(event));
+    pid
+}
+
+/// The first line of a prompt, cut at `PROMPT_SUMMARY_CHARS`
+fn summarize(prompt: &str) -> String {
+    let first_line = prompt.trim().lines().next().unwrap_or_default().trim();
+    match first_line.char_indices().nth(PROMPT_SUMMARY_CHARS) {
+        Some((cut, _)) => format!("{}…", first_line[..cut].trim_end()),
+        None => first_line.to_string(),
+    }
+}
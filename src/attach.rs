@@ -0,0 +1,102 @@
+// Attach actions
+// Instead of spawning a process per command, `attach` actions connect to a resident daemon
+// (normally `viberot-overlay --daemon`) over a local socket, or a named pipe on Windows, and ask
+// it to show itself while the command runs. The connection stays open until the command ends, so
+// the daemon also hides again if the service dies.
+
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tracing::debug;
+
+#[cfg(unix)]
+type LocalStream = tokio::net::UnixStream;
+#[cfg(windows)]
+type LocalStream = tokio::net::windows::named_pipe::NamedPipeClient;
+
+/// Socket the overlay daemon listens on by default; must match the overlay's default
+pub fn default_socket_path() -> String {
+    #[cfg(windows)]
+    {
+        r"\\.\pipe\viberot-overlay".to_string()
+    }
+    #[cfg(not(windows))]
+    {
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        dir.join("viberot-overlay.sock").to_string_lossy().into_owned()
+    }
+}
+
+/// An open attachment to a daemon, detached when the command ends
+pub struct Attachment {
+    stream: LocalStream,
+    socket: String,
+    id: String,
+}
+
+impl Attachment {
+    /// Connects to the daemon and attaches `id`
+    pub async fn attach(socket: &str, id: &str) -> std::io::Result<Self> {
+        let mut stream = connect(socket).await?;
+        let line = serde_json::json!({ "cmd": "attach", "id": id }).to_string() + "\n";
+        stream.write_all(line.as_bytes()).await?;
+        stream.flush().await?;
+        debug!("Attached {} to daemon at {}", id, socket);
+        Ok(Self {
+            stream,
+            socket: socket.to_string(),
+            id: id.to_string(),
+        })
+    }
+
+    /// Where the daemon listens
+    pub fn socket(&self) -> &str {
+        &self.socket
+    }
+
+    /// Detaches and closes the connection
+    pub async fn detach(mut self) {
+        let line = serde_json::json!({ "cmd": "detach", "id": self.id }).to_string() + "\n";
+        // The daemon detaches on disconnect anyway, so a failed write is harmless
+        if let Err(e) = self.stream.write_all(line.as_bytes()).await {
+            debug!("Failed to detach {} from daemon at {}: {}", self.id, self.socket, e);
+        }
+        let _ = self.stream.shutdown().await;
+    }
+}
+
+#[cfg(unix)]
+async fn connect(socket: &str) -> std::io::Result<LocalStream> {
+    tokio::net::UnixStream::connect(socket).await
+}
+
+#[cfg(windows)]
+async fn connect(socket: &str) -> std::io::Result<LocalStream> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+    use windows::Win32::Foundation::ERROR_PIPE_BUSY;
+
+    // All pipe instances can be busy for a moment while the daemon creates the next one
+    let mut attempts = 0;
+    loop {
+        match ClientOptions::new().open(socket) {
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY.0 as i32) && attempts < 20 => {
+                attempts += 1;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Waits for a freshly launched daemon to start listening
+pub async fn attach_when_ready(socket: &str, id: &str, timeout: Duration) -> std::io::Result<Attachment> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        match Attachment::attach(socket, id).await {
+            Ok(attachment) => return Ok(attachment),
+            Err(e) if tokio::time::Instant::now() >= deadline => return Err(e),
+            Err(_) => tokio::time::sleep(Duration::from_millis(200)).await,
+        }
+    }
+}
@@ -0,0 +1,280 @@
+// Audit log
+// An append-only record of every program VibeRot executes, kept apart from the diagnostic logs so
+// it can be reviewed or exported on its own. Entries are JSON lines in ~/.viberot/audit/audit.jsonl
+// with the resolved path, arguments, the names of the variables VibeRot added to the environment
+// (values are left out, the inherited environment may hold secrets) and the triggering event.
+// The file is rotated by size (audit.1.jsonl is the newest rotated file). Bursts beyond the rate
+// limit are counted in a `suppressed` entry instead of being written one by one.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, warn};
+
+use crate::config::AuditConfig;
+use crate::platform::ProcessEvent;
+
+const FILE_NAME: &str = "audit.jsonl";
+const DEFAULT_MAX_ENTRIES_PER_MINUTE: u32 = 600;
+const DEFAULT_MAX_FILE_SIZE_KB: u64 = 10 * 1024;
+const DEFAULT_MAX_FILES: usize = 5;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditEntry {
+    /// A program VibeRot started, or tried to
+    Exec(Box<ExecRecord>),
+    /// Entries dropped by the rate limit
+    Suppressed { timestamp: u64, count: u64 },
+}
+
+impl AuditEntry {
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            AuditEntry::Exec(record) => record.timestamp,
+            AuditEntry::Suppressed { timestamp, .. } => *timestamp,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecRecord {
+    /// Unix timestamp
+    pub timestamp: u64,
+    /// Path as configured, e.g. `${VIBEROT_ACTIONS}/overlay/...`
+    pub path: String,
+    pub resolved_path: String,
+    pub args: Vec<String>,
+    pub working_directory: Option<String>,
+    /// Variables VibeRot added to the inherited environment
+    pub env_vars: Vec<String>,
+    /// Whether it ran in the action sandbox
+    #[serde(default)]
+    pub sandboxed: bool,
+    pub trigger: Option<AuditTrigger>,
+    /// PID of the started program, if it started
+    pub child_pid: Option<u32>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditTrigger {
+    pub event_id: String,
+    pub pid: u32,
+    pub pid_type: String,
+    pub command: String,
+    pub probe: String,
+    pub rule_name: String,
+}
+
+impl AuditTrigger {
+    pub fn new(event: &ProcessEvent, rule_name: &str) -> Self {
+        Self {
+            event_id: event.event_id.clone(),
+            pid: event.pid,
+            pid_type: event.probe_source.pid_type().to_string(),
+            command: event.command.clone(),
+            probe: format!("{:?}", event.probe_source),
+            rule_name: rule_name.to_string(),
+        }
+    }
+}
+
+struct RateState {
+    window_start: Instant,
+    written: u32,
+    suppressed: u64,
+}
+
+pub struct AuditLog {
+    dir: PathBuf,
+    max_entries_per_minute: u32,
+    max_file_size: u64,
+    max_files: usize,
+    file: Mutex<Option<File>>,
+    rate: Mutex<RateState>,
+}
+
+impl AuditLog {
+    pub fn new(dir: impl AsRef<Path>, config: &AuditConfig) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_entries_per_minute: config.max_entries_per_minute.unwrap_or(DEFAULT_MAX_ENTRIES_PER_MINUTE),
+            max_file_size: config.max_file_size_kb.unwrap_or(DEFAULT_MAX_FILE_SIZE_KB) * 1024,
+            max_files: config.max_files.unwrap_or(DEFAULT_MAX_FILES).max(1),
+            file: Mutex::new(None),
+            rate: Mutex::new(RateState { window_start: Instant::now(), written: 0, suppressed: 0 }),
+        }
+    }
+
+    /// Records an execution; failures are logged but never stop the action
+    pub fn record_exec(&self, record: ExecRecord) {
+        let suppressed = {
+            let mut rate = self.rate.lock().unwrap();
+            if rate.window_start.elapsed() >= RATE_WINDOW {
+                rate.window_start = Instant::now();
+                rate.written = 0;
+            }
+            if rate.written >= self.max_entries_per_minute {
+                if rate.suppressed == 0 {
+                    warn!("Audit log rate limit of {} entries per minute reached, counting further entries", self.max_entries_per_minute);
+                }
+                rate.suppressed += 1;
+                return;
+            }
+            rate.written += 1;
+            std::mem::take(&mut rate.suppressed)
+        };
+
+        if suppressed > 0 {
+            self.write(&AuditEntry::Suppressed { timestamp: unix_now(), count: suppressed });
+        }
+        self.write(&AuditEntry::Exec(Box::new(record)));
+    }
+
+    /// Writes out the count of entries still held back by the rate limit
+    pub fn flush(&self) {
+        let suppressed = std::mem::take(&mut self.rate.lock().unwrap().suppressed);
+        if suppressed > 0 {
+            self.write(&AuditEntry::Suppressed { timestamp: unix_now(), count: suppressed });
+        }
+    }
+
+    fn write(&self, entry: &AuditEntry) {
+        if let Err(e) = self.try_write(entry) {
+            error!("Failed to write audit log entry: {}", e);
+        }
+    }
+
+    fn try_write(&self, entry: &AuditEntry) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let line = serde_json::to_string(entry)? + "\n";
+        let mut file = self.file.lock().unwrap();
+        let path = self.dir.join(FILE_NAME);
+
+        let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > self.max_file_size {
+            *file = None;
+            self.rotate()?;
+        }
+        if file.is_none() {
+            *file = Some(open_append(&path)?);
+        }
+        let handle = file.as_mut().unwrap();
+        handle.write_all(line.as_bytes())?;
+        handle.flush()?;
+        Ok(())
+    }
+
+    /// Shifts audit.jsonl to audit.1.jsonl, audit.1.jsonl to audit.2.jsonl and so on,
+    /// dropping the oldest
+    fn rotate(&self) -> std::io::Result<()> {
+        let _ = std::fs::remove_file(self.dir.join(rotated_name(self.max_files)));
+        for index in (1..self.max_files).rev() {
+            let from = self.dir.join(rotated_name(index));
+            if from.exists() {
+                std::fs::rename(&from, self.dir.join(rotated_name(index + 1)))?;
+            }
+        }
+        std::fs::rename(self.dir.join(FILE_NAME), self.dir.join(rotated_name(1)))
+    }
+}
+
+fn rotated_name(index: usize) -> String {
+    format!("audit.{}.jsonl", index)
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    // Only the owner may read what was executed
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Reads all entries in the audit directory, oldest first, skipping unreadable lines
+pub fn read_entries(dir: &Path, since: Option<u64>) -> std::io::Result<Vec<AuditEntry>> {
+    let mut files: Vec<(usize, PathBuf)> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let index = if name == FILE_NAME {
+                0
+            } else {
+                name.strip_prefix("audit.")?.strip_suffix(".jsonl")?.parse().ok()?
+            };
+            Some((index, entry.path()))
+        })
+        .collect();
+    // Higher indexes are older
+    files.sort_by_key(|(index, _)| std::cmp::Reverse(*index));
+
+    let mut entries = Vec::new();
+    for (_, path) in files {
+        for line in BufReader::new(File::open(&path)?).lines() {
+            match serde_json::from_str::<AuditEntry>(&line?) {
+                Ok(entry) if since.is_none_or(|since| entry.timestamp() >= since) => entries.push(entry),
+                Ok(_) => {}
+                Err(e) => warn!("Skipping unreadable audit entry in {:?}: {}", path, e),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Formats entries as CSV, one row per entry
+pub fn to_csv(entries: &[AuditEntry]) -> String {
+    let mut out = String::from("timestamp,kind,path,resolved_path,args,working_directory,env_vars,sandboxed,event_id,pid,pid_type,command,probe,rule_name,child_pid,error,suppressed\n");
+    for entry in entries {
+        let fields: Vec<String> = match entry {
+            AuditEntry::Exec(record) => {
+                let trigger = record.trigger.as_ref();
+                vec![
+                    record.timestamp.to_string(),
+                    "exec".to_string(),
+                    record.path.clone(),
+                    record.resolved_path.clone(),
+                    record.args.join(" "),
+                    record.working_directory.clone().unwrap_or_default(),
+                    record.env_vars.join(" "),
+                    record.sandboxed.to_string(),
+                    trigger.map(|t| t.event_id.clone()).unwrap_or_default(),
+                    trigger.map(|t| t.pid.to_string()).unwrap_or_default(),
+                    trigger.map(|t| t.pid_type.clone()).unwrap_or_default(),
+                    trigger.map(|t| t.command.clone()).unwrap_or_default(),
+                    trigger.map(|t| t.probe.clone()).unwrap_or_default(),
+                    trigger.map(|t| t.rule_name.clone()).unwrap_or_default(),
+                    record.child_pid.map(|pid| pid.to_string()).unwrap_or_default(),
+                    record.error.clone().unwrap_or_default(),
+                    String::new(),
+                ]
+            }
+            AuditEntry::Suppressed { timestamp, count } => {
+                let mut fields = vec![timestamp.to_string(), "suppressed".to_string()];
+                fields.resize(16, String::new());
+                fields.push(count.to_string());
+                fields
+            }
+        };
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
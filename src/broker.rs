@@ -0,0 +1,491 @@
+// System-wide deployment
+// Splits the service in two for machines with several users:
+// - `viberot-service broker` runs once, with the privileges the probe needs (ETW needs an
+//   administrator; the shell probe listens on sockets every user can reach). It publishes process
+//   lifecycle events as JSON lines on a local socket (Unix) or named pipe (Windows).
+// - Each user runs a normal, unprivileged service with `[broker] connect = true`. Instead of a
+//   probe, it subscribes to the broker and applies that user's own rules and actions.
+// The broker learns who subscribed from the OS, not from anything the subscriber says: the peer
+// uid of the socket (SO_PEERCRED) or the login session of the pipe client. Subscribers only get
+// events of their own commands.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, warn};
+
+use crate::config::Config;
+use crate::platform::{
+    self, PlatformCapability, PlatformProbeTrait, ProbeData, ProbeFuture, ProcessEvent, ProcessLifecycleEvent,
+};
+
+#[cfg(unix)]
+pub const DEFAULT_ENDPOINT: &str = "/run/viberot/broker.sock";
+#[cfg(windows)]
+pub const DEFAULT_ENDPOINT: &str = r"\\.\pipe\viberot-broker";
+#[cfg(not(any(unix, windows)))]
+pub const DEFAULT_ENDPOINT: &str = "";
+
+/// Shell socket of the broker when the config doesn't set one; reachable by every user
+#[cfg(unix)]
+const DEFAULT_SHARED_SHELL_SOCKET: &str = "/run/viberot/shell.sock";
+
+/// How long a subscriber waits before reconnecting to a broker that went away
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A line sent from the broker to a subscriber
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BrokerMessage {
+    /// Sent first: the shell sockets the subscriber's hooks should report to
+    Hello { shell_sockets: Vec<String> },
+    Started { event: Box<ProcessEvent> },
+    Ended {
+        pid: u32,
+        #[serde(default)]
+        exit_code: Option<i32>,
+    },
+    PidUpgraded { pid: u32, real_pid: u32 },
+}
+
+/// Who a subscriber is, as reported by the OS
+#[derive(Debug, Clone, Default)]
+struct Peer {
+    uid: Option<u32>,
+    /// Windows login session
+    session_id: Option<String>,
+}
+
+impl Peer {
+    fn owns(&self, event: &ProcessEvent) -> bool {
+        (self.uid.is_some() && self.uid == event.provenance.uid)
+            || (self.session_id.is_some() && self.session_id == event.provenance.session_id)
+    }
+}
+
+/// Runs the broker until Ctrl+C: the probe, and a server publishing its events
+pub async fn run_broker(mut config: Config, endpoint: Option<String>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let endpoint = endpoint.or(config.broker.endpoint.clone()).unwrap_or_else(|| DEFAULT_ENDPOINT.to_string());
+    info!("Starting VibeRot broker on {}", endpoint);
+
+    // Shell commands are attributed to the user connecting to the socket, which TCP can't tell
+    config.shell.shared = true;
+    #[cfg(unix)]
+    if config.shell.socket_paths.is_empty() {
+        config.shell.socket_paths.push(DEFAULT_SHARED_SHELL_SOCKET.to_string());
+    }
+    if config.shell.tcp_port.take().is_some() {
+        warn!("The shell TCP fallback can't tell users apart, it is off for the broker");
+    }
+
+    let (lifecycle_tx, _) = broadcast::channel(1024);
+    let (probe, capability) = platform::detect_best_probe(lifecycle_tx.clone(), &config);
+    info!("Using platform probe with {:?} capability", capability);
+    let probe = platform::start_probe(probe, lifecycle_tx.clone(), &config).await?;
+
+    let hello = BrokerMessage::Hello { shell_sockets: config.shell.socket_paths.clone() };
+    let result = tokio::select! {
+        result = serve(&endpoint, lifecycle_tx, hello) => result,
+        reason = crate::signals::shutdown_requested() => {
+            info!("Received shutdown signal ({}), stopping broker...", reason);
+            Ok(())
+        }
+    };
+
+    if let Err(e) = probe.stop().await {
+        error!("Error stopping probe: {}", e);
+    }
+    #[cfg(unix)]
+    let _ = std::fs::remove_file(&endpoint);
+    result
+}
+
+#[cfg(unix)]
+async fn serve(
+    endpoint: &str,
+    lifecycle_tx: broadcast::Sender<ProcessLifecycleEvent>,
+    hello: BrokerMessage,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::path::PathBuf::from(endpoint);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path)
+        .map_err(|e| format!("Failed to bind broker socket {}: {}", path.display(), e))?;
+    // Anyone may subscribe; what they get depends on who they are
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666))?;
+    info!("Broker listening on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let peer = match stream.peer_cred() {
+            Ok(cred) => Peer { uid: Some(cred.uid()), session_id: None },
+            Err(e) => {
+                warn!("Rejecting broker subscriber of unknown identity: {}", e);
+                continue;
+            }
+        };
+        tokio::spawn(publish(stream, peer, lifecycle_tx.subscribe(), hello.clone()));
+    }
+}
+
+#[cfg(windows)]
+async fn serve(
+    endpoint: &str,
+    lifecycle_tx: broadcast::Sender<ProcessLifecycleEvent>,
+    hello: BrokerMessage,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use std::os::windows::io::AsRawHandle;
+    use tokio::net::windows::named_pipe::ServerOptions;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::System::Pipes::GetNamedPipeClientProcessId;
+    use windows::Win32::System::RemoteDesktop::ProcessIdToSessionId;
+
+    // Subscribers only read. The default pipe security lets every user read and only the
+    // broker's account write, and being the first instance keeps other processes from posing
+    // as the broker.
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .access_inbound(false)
+        .create(endpoint)
+        .map_err(|e| format!("Failed to create broker pipe {}: {}", endpoint, e))?;
+    info!("Broker listening on {}", endpoint);
+
+    loop {
+        server.connect().await?;
+        let connected = server;
+        server = ServerOptions::new().access_inbound(false).create(endpoint)?;
+
+        let mut client_pid = 0u32;
+        let mut session_id = 0u32;
+        let handle = HANDLE(connected.as_raw_handle() as isize);
+        // SAFETY: the handle belongs to the connected pipe, which outlives these calls
+        let identified = unsafe {
+            GetNamedPipeClientProcessId(handle, &mut client_pid)
+                .and_then(|()| ProcessIdToSessionId(client_pid, &mut session_id))
+        };
+        if let Err(e) = identified {
+            warn!("Rejecting broker subscriber of unknown identity: {}", e);
+            continue;
+        }
+        let peer = Peer { uid: None, session_id: Some(session_id.to_string()) };
+        tokio::spawn(publish(connected, peer, lifecycle_tx.subscribe(), hello.clone()));
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn serve(
+    _endpoint: &str,
+    _lifecycle_tx: broadcast::Sender<ProcessLifecycleEvent>,
+    _hello: BrokerMessage,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    Err("The broker isn't supported on this platform".into())
+}
+
+/// Sends a subscriber the events of its own commands until it disconnects
+async fn publish<W: AsyncWrite + Unpin>(
+    mut stream: W,
+    peer: Peer,
+    mut lifecycle_rx: broadcast::Receiver<ProcessLifecycleEvent>,
+    hello: BrokerMessage,
+) {
+    info!("Broker subscriber connected: {:?}", peer);
+    // PIDs of this subscriber's running commands, the only ones whose ends it gets
+    let mut owned = HashSet::new();
+    let mut next = vec![hello];
+
+    loop {
+        for message in next.drain(..) {
+            let line = match serde_json::to_string(&message) {
+                Ok(line) => line + "\n",
+                Err(e) => {
+                    error!("Failed to encode broker message: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = stream.write_all(line.as_bytes()).await {
+                debug!("Broker subscriber {:?} disconnected: {}", peer, e);
+                return;
+            }
+        }
+
+        let lifecycle_event = match lifecycle_rx.recv().await {
+            Ok(lifecycle_event) => lifecycle_event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Broker subscriber {:?} fell behind, skipped {} event(s)", peer, skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        // Subscribers get a batch's events one by one
+        for lifecycle_event in lifecycle_event.into_events() {
+            match lifecycle_event {
+                ProcessLifecycleEvent::Started(event) if peer.owns(&event) => {
+                    owned.insert(event.pid);
+                    next.push(BrokerMessage::Started { event: Box::new(event) });
+                }
+                ProcessLifecycleEvent::Ended { pid, exit_code } if owned.remove(&pid) => {
+                    next.push(BrokerMessage::Ended { pid, exit_code });
+                }
+                ProcessLifecycleEvent::PidUpgraded { pid, real_pid } if owned.contains(&pid) => {
+                    next.push(BrokerMessage::PidUpgraded { pid, real_pid });
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Stands in for the probe of a per-user service, feeding it the broker's events
+pub struct BrokerClient {
+    lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
+    endpoint: String,
+    task: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
+}
+
+impl BrokerClient {
+    pub fn new(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>, config: &Config) -> Self {
+        Self {
+            lifecycle_sender,
+            endpoint: config.broker.endpoint.clone().unwrap_or_else(|| DEFAULT_ENDPOINT.to_string()),
+            task: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// Receives events until the broker goes away, then reconnects
+    async fn subscribe_forever(endpoint: String, lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) {
+        loop {
+            let mut running = HashSet::new();
+            match connect(&endpoint).await {
+                Ok(stream) => {
+                    info!("Subscribed to the broker at {}", endpoint);
+                    if let Err(e) = Self::receive(stream, &lifecycle_sender, &mut running).await {
+                        warn!("Lost the broker at {}: {}", endpoint, e);
+                    } else {
+                        warn!("The broker at {} went away", endpoint);
+                    }
+                }
+                Err(e) => warn!("Can't reach the broker at {}: {}", endpoint, e),
+            }
+            // Nobody will report the end of commands that were running
+            for pid in running {
+                let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid, exit_code: None });
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    async fn receive<R: AsyncRead + Unpin>(
+        stream: R,
+        lifecycle_sender: &broadcast::Sender<ProcessLifecycleEvent>,
+        running: &mut HashSet<u32>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut lines = BufReader::new(stream).lines();
+        while let Some(line) = lines.next_line().await? {
+            let message = match serde_json::from_str::<BrokerMessage>(&line) {
+                Ok(message) => message,
+                Err(e) => {
+                    debug!("Ignoring unreadable broker message: {}", e);
+                    continue;
+                }
+            };
+            let lifecycle_event = match message {
+                BrokerMessage::Hello { shell_sockets } => {
+                    write_shell_sockets(&shell_sockets);
+                    continue;
+                }
+                BrokerMessage::Started { mut event } => {
+                    running.insert(event.pid);
+                    event.received_at = Some(Instant::now());
+                    ProcessLifecycleEvent::Started(*event)
+                }
+                BrokerMessage::Ended { pid, exit_code } => {
+                    running.remove(&pid);
+                    ProcessLifecycleEvent::Ended { pid, exit_code }
+                }
+                BrokerMessage::PidUpgraded { pid, real_pid } => ProcessLifecycleEvent::PidUpgraded { pid, real_pid },
+            };
+            if let Err(e) = lifecycle_sender.send(lifecycle_event) {
+                debug!("Failed to forward broker event: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PlatformProbeTrait for BrokerClient {
+    fn start(&self) -> ProbeFuture<'_> {
+        Box::pin(async move {
+            let task = tokio::spawn(Self::subscribe_forever(self.endpoint.clone(), self.lifecycle_sender.clone()));
+            *self.task.lock().unwrap() = Some(task);
+            Ok(())
+        })
+    }
+
+    fn stop(&self) -> ProbeFuture<'_> {
+        Box::pin(async move {
+            if let Some(task) = self.task.lock().unwrap().take() {
+                task.abort();
+            }
+            Ok(())
+        })
+    }
+
+    fn get_capability(&self) -> PlatformCapability {
+        // The broker runs the probe this platform would pick
+        if cfg!(windows) {
+            PlatformCapability::SystemWide
+        } else {
+            PlatformCapability::ShellOnly
+        }
+    }
+
+    fn provided_data(&self) -> ProbeData {
+        // Depends on the broker's config, which subscribers don't see
+        ProbeData::ALL
+    }
+}
+
+/// Connects to a broker, making sure it runs as root or as ourselves
+#[cfg(unix)]
+async fn connect(endpoint: &str) -> Result<tokio::net::UnixStream, Box<dyn std::error::Error + Send + Sync>> {
+    let stream = tokio::net::UnixStream::connect(endpoint).await?;
+    let broker_uid = stream.peer_cred()?.uid();
+    // SAFETY: getuid has no preconditions
+    let own_uid = unsafe { libc::getuid() };
+    if broker_uid != 0 && broker_uid != own_uid {
+        return Err(format!("the broker runs as uid {}, not root; refusing its events", broker_uid).into());
+    }
+    Ok(stream)
+}
+
+/// Connects to a broker, making sure it runs as SYSTEM or an administrator
+#[cfg(windows)]
+async fn connect(endpoint: &str) -> Result<tokio::net::windows::named_pipe::NamedPipeClient, Box<dyn std::error::Error + Send + Sync>> {
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::System::Pipes::GetNamedPipeServerProcessId;
+
+    let client = tokio::net::windows::named_pipe::ClientOptions::new().write(false).open(endpoint)?;
+    let pipe = HANDLE(client.as_raw_handle() as isize);
+    let mut server_pid = 0u32;
+    // SAFETY: the handle belongs to the open pipe, which outlives the call
+    unsafe { GetNamedPipeServerProcessId(pipe, &mut server_pid) }
+        .map_err(|e| format!("can't tell who serves the broker pipe: {}", e))?;
+    // Any user can create the pipe before the broker does
+    if !windows_server::is_privileged(server_pid, pipe) {
+        return Err(format!("the broker pipe is served by PID {}, which runs as neither SYSTEM nor an administrator; refusing its events", server_pid).into());
+    }
+    Ok(client)
+}
+
+/// Who serves the broker pipe
+#[cfg(windows)]
+mod windows_server {
+    use tracing::debug;
+    use windows::Win32::Foundation::{CloseHandle, LocalFree, HANDLE, HLOCAL, PSID};
+    use windows::Win32::Security::Authorization::{GetSecurityInfo, SE_KERNEL_OBJECT};
+    use windows::Win32::Security::{
+        GetTokenInformation, IsWellKnownSid, TokenGroups, TokenUser, WinBuiltinAdministratorsSid, WinLocalSystemSid,
+        OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, TOKEN_GROUPS, TOKEN_INFORMATION_CLASS, TOKEN_QUERY, TOKEN_USER,
+    };
+    use windows::Win32::System::Threading::{OpenProcess, OpenProcessToken, PROCESS_QUERY_LIMITED_INFORMATION};
+
+    /// `SE_GROUP_ENABLED`, a group the token's access checks count
+    const GROUP_ENABLED: u32 = 0x4;
+
+    /// Whether the process serving `pipe` runs as SYSTEM or with the Administrators group. A user
+    /// who isn't an administrator can't read the token of a SYSTEM service, so then the pipe's
+    /// owner, which the server's token gave it, has to be SYSTEM or Administrators instead.
+    pub fn is_privileged(server_pid: u32, pipe: HANDLE) -> bool {
+        match privileged_token(server_pid) {
+            Ok(privileged) => privileged,
+            Err(e) => {
+                debug!("Can't read the token of broker PID {} ({}), checking who owns its pipe", server_pid, e);
+                privileged_owner(pipe)
+            }
+        }
+    }
+
+    fn privileged_token(pid: u32) -> windows::core::Result<bool> {
+        // SAFETY: both handles are opened here and closed before returning
+        unsafe {
+            let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid)?;
+            let mut token = HANDLE::default();
+            let opened = OpenProcessToken(process, TOKEN_QUERY, &mut token);
+            let _ = CloseHandle(process);
+            opened?;
+            let privileged = token_is_privileged(token);
+            let _ = CloseHandle(token);
+            privileged
+        }
+    }
+
+    /// # Safety
+    /// `token` must be a token handle opened with `TOKEN_QUERY`
+    unsafe fn token_is_privileged(token: HANDLE) -> windows::core::Result<bool> {
+        let user = token_information(token, TokenUser)?;
+        let user = &*(user.as_ptr() as *const TOKEN_USER);
+        if IsWellKnownSid(user.User.Sid, WinLocalSystemSid).as_bool() {
+            return Ok(true);
+        }
+        let groups = token_information(token, TokenGroups)?;
+        let groups = &*(groups.as_ptr() as *const TOKEN_GROUPS);
+        let groups = std::slice::from_raw_parts(groups.Groups.as_ptr(), groups.GroupCount as usize);
+        Ok(groups.iter().any(|group| {
+            group.Attributes & GROUP_ENABLED != 0 && IsWellKnownSid(group.Sid, WinBuiltinAdministratorsSid).as_bool()
+        }))
+    }
+
+    /// A token information class, in a buffer aligned for the struct it holds
+    ///
+    /// # Safety
+    /// `token` must be a token handle opened with `TOKEN_QUERY`
+    unsafe fn token_information(token: HANDLE, class: TOKEN_INFORMATION_CLASS) -> windows::core::Result<Vec<u64>> {
+        let mut length = 0u32;
+        // Fails with the length it needs
+        let _ = GetTokenInformation(token, class, None, 0, &mut length);
+        let mut buffer = vec![0u64; (length as usize).div_ceil(8)];
+        GetTokenInformation(token, class, Some(buffer.as_mut_ptr().cast()), length, &mut length)?;
+        Ok(buffer)
+    }
+
+    fn privileged_owner(pipe: HANDLE) -> bool {
+        let mut owner = PSID::default();
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        // SAFETY: the owner points into the descriptor, which is freed after its last use
+        unsafe {
+            if GetSecurityInfo(pipe, SE_KERNEL_OBJECT, OWNER_SECURITY_INFORMATION, Some(&mut owner), None, None, None, Some(&mut descriptor)).is_err() {
+                return false;
+            }
+            let privileged = IsWellKnownSid(owner, WinLocalSystemSid).as_bool()
+                || IsWellKnownSid(owner, WinBuiltinAdministratorsSid).as_bool();
+            let _ = LocalFree(HLOCAL(descriptor.0));
+            privileged
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn connect(_endpoint: &str) -> Result<tokio::io::Empty, Box<dyn std::error::Error + Send + Sync>> {
+    Err("The broker isn't supported on this platform".into())
+}
+
+/// Points this user's shell hooks at the broker's shell sockets
+fn write_shell_sockets(shell_sockets: &[String]) {
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), feature = "shell"))]
+    if !shell_sockets.is_empty() {
+        let paths: Vec<std::path::PathBuf> = shell_sockets.iter().map(std::path::PathBuf::from).collect();
+        if let Err(e) = platform::posix_shell::write_socket_discovery_file(&paths, None) {
+            warn!("Failed to point shell hooks at the broker: {}", e);
+        }
+    }
+    // Without the shell probe there are no hooks to point anywhere
+    #[cfg(not(all(any(target_os = "linux", target_os = "macos"), feature = "shell")))]
+    let _ = shell_sockets;
+}
@@ -0,0 +1,104 @@
+// Daily action budgets
+// `daily_action_budget = "45m"`, for all rules or per rule, caps how long actions may run a day.
+// Time is counted from when a command's actions start until the command ends: per rule, and once
+// per command for the global budget. Notifications, announcements, throttling and desktop
+// switches don't count.
+// Once a budget is used up, the actions of the rules it covers are swapped for a notification
+// telling the user to go touch grass, until the next (local) day. Usage is kept in the stats
+// store, so restarts don't reset it.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::info;
+
+use crate::config::{Action, Config, NotifyButton};
+use crate::i18n::{t, tf, Msg};
+use crate::mutes::format_duration;
+use crate::rule_engine::MatchedAction;
+use crate::stats::StatsStore;
+
+/// Swaps the actions of rules whose budget, or the global one, is used up for a notification
+pub fn apply(actions: Vec<MatchedAction>, config: &Config, stats: &StatsStore) -> Vec<MatchedAction> {
+    let global = config.daily_action_budget.map(|budget| budget.0);
+    let mut kept = Vec::with_capacity(actions.len());
+    let mut exhausted: Vec<String> = Vec::new();
+
+    for matched in actions {
+        if exhausted.contains(&matched.rule_name) {
+            continue;
+        }
+        let budget = match exhausted_budget(&matched.rule_name, global, config, stats) {
+            Some(budget) if !matches!(matched.action, Action::Notify { .. } | Action::Speak { .. } | Action::Bell { .. } | Action::Throttle { .. } | Action::Desktop { .. }) => budget,
+            _ => {
+                kept.push(matched);
+                continue;
+            }
+        };
+        info!("Daily action budget of {} is used up, notifying instead of starting rule '{}'",
+              format_duration(budget.as_secs()), matched.rule_name);
+        // The rule's other actions, and its notifications, make way for this one
+        kept.retain(|other: &MatchedAction| other.rule_name != matched.rule_name);
+        kept.push(MatchedAction {
+            rule_name: matched.rule_name.clone(),
+            action: Action::Notify {
+                title: Some(t(Msg::BudgetTitle).to_string()),
+                message: Some(tf(Msg::BudgetMessage, &[&format_duration(budget.as_secs())])),
+                buttons: vec![NotifyButton::Dismiss],
+            },
+            after: Duration::ZERO,
+            cpu_above: None,
+            only_if: None,
+            with: BTreeMap::new(),
+        });
+        exhausted.push(matched.rule_name);
+    }
+    kept
+}
+
+/// Rules whose actions count against the budgets, i.e. that start more than notifications,
+/// announcements, throttling and desktop switches
+pub fn counted_rules(actions: &[MatchedAction]) -> Vec<String> {
+    let mut rules: Vec<String> = Vec::new();
+    for matched in actions {
+        if !matches!(matched.action, Action::Notify { .. } | Action::Speak { .. } | Action::Bell { .. } | Action::Throttle { .. } | Action::Desktop { .. }) && !rules.contains(&matched.rule_name) {
+            rules.push(matched.rule_name.clone());
+        }
+    }
+    rules
+}
+
+/// The budget a rule's actions are out of, if any: its own, or else the global one
+fn exhausted_budget(rule_name: &str, global: Option<Duration>, config: &Config, stats: &StatsStore) -> Option<Duration> {
+    let own = config.rules.iter().enumerate()
+        .find(|(index, rule)| rule.display_name(*index) == rule_name)
+        .and_then(|(_, rule)| rule.daily_action_budget)
+        .map(|budget| budget.0);
+    if let Some(own) = own.filter(|own| stats.action_time_today(Some(rule_name)) >= *own) {
+        return Some(own);
+    }
+    global.filter(|global| stats.action_time_today(None) >= *global)
+}
+
+/// Today's date in local time, e.g. "2025-03-14", which budgets are counted for
+#[cfg(unix)]
+pub fn local_date() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as libc::time_t;
+    // SAFETY: localtime_r only writes to the tm we own
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        return String::new();
+    }
+    format!("{:04}-{:02}-{:02}", tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday)
+}
+
+/// Today's date in local time, e.g. "2025-03-14", which budgets are counted for
+#[cfg(windows)]
+pub fn local_date() -> String {
+    use windows::Win32::System::SystemInformation::GetLocalTime;
+
+    let time = unsafe { GetLocalTime() };
+    format!("{:04}-{:02}-{:02}", time.wYear, time.wMonth, time.wDay)
+}
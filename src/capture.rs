@@ -0,0 +1,244 @@
+// Screen capture safe mode
+// Detects when the screen is being streamed or recorded and swaps matched actions for a configured
+// "safe" set (e.g. a silent notification), so nothing ends up on stream. Checked for every batch of
+// actions, including escalation tiers, with the result cached for a few seconds.
+// Detection:
+// - OBS: asks obs-websocket (OBS 28+, Tools > WebSocket Server Settings) whether streaming,
+//   recording or the virtual camera is active. When OBS answers, merely having it open is fine.
+// - Processes: known streaming and recording apps running (Streamlabs, XSplit, screen recorders,
+//   and OBS when its websocket isn't reachable); `processes` in the config adds more. Windows and
+//   macOS don't tell other apps when the screen is captured, so this is the OS-level check.
+
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+use crate::config::{Action, CaptureSafeConfig};
+
+const DEFAULT_OBS_WEBSOCKET: &str = "ws://127.0.0.1:4455";
+/// How long a detection result is reused
+const CACHE_TTL: Duration = Duration::from_secs(5);
+/// Longest an obs-websocket query may take before OBS counts as unreachable
+const OBS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Process names of OBS, only checked when its websocket doesn't answer
+const OBS_PROCESSES: &[&str] = &["obs", "obs64", "obs32", "obs-studio"];
+/// Process names of other streaming and recording apps
+const CAPTURE_PROCESSES: &[&str] = &[
+    "streamlabs obs",
+    "streamlabs desktop",
+    "xsplit.core",
+    "xsplit broadcaster",
+    "twitch studio",
+    "simplescreenrecorder",
+    "gpu-screen-recorder",
+    "wf-recorder",
+    "kazam",
+    "vokoscreenng",
+    "kooha",
+];
+
+/// Decides whether actions must be swapped for the safe set
+pub struct CaptureGuard {
+    config: CaptureSafeConfig,
+    /// Last result: what is capturing, if anything
+    cached: Mutex<Option<(Instant, Option<String>)>>,
+}
+
+impl CaptureGuard {
+    pub fn new(config: CaptureSafeConfig) -> Self {
+        Self {
+            config,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Actions to run instead while capturing; none means everything is suppressed
+    pub fn safe_actions(&self) -> Vec<Action> {
+        self.config.action.as_vec().into_iter().cloned().collect()
+    }
+
+    /// What is capturing the screen, or None if nothing seems to be
+    pub async fn detect(&self) -> Option<String> {
+        if let Some((checked, ref result)) = *self.cached.lock().unwrap() {
+            if checked.elapsed() < CACHE_TTL {
+                return result.clone();
+            }
+        }
+
+        let result = self.check().await;
+        if let Some(ref reason) = result {
+            debug!("Screen capture detected: {}", reason);
+        }
+        *self.cached.lock().unwrap() = Some((Instant::now(), result.clone()));
+        result
+    }
+
+    async fn check(&self) -> Option<String> {
+        let url = self.config.obs_websocket.as_deref().unwrap_or(DEFAULT_OBS_WEBSOCKET);
+        let obs = match tokio::time::timeout(OBS_TIMEOUT, obs_outputs(url, self.config.obs_password.as_deref())).await {
+            Ok(Ok(outputs)) => Some(outputs),
+            Ok(Err(e)) => {
+                debug!("obs-websocket at {} not available: {}", url, e);
+                None
+            }
+            Err(_) => {
+                debug!("obs-websocket at {} timed out", url);
+                None
+            }
+        };
+        if let Some(ref outputs) = obs {
+            if !outputs.is_empty() {
+                return Some(format!("OBS is {}", outputs.join(" and ")));
+            }
+        }
+
+        let mut names: Vec<String> = CAPTURE_PROCESSES.iter().map(|name| name.to_string()).collect();
+        if obs.is_none() {
+            names.extend(OBS_PROCESSES.iter().map(|name| name.to_string()));
+        }
+        names.extend(self.config.processes.iter().map(|name| normalize_process_name(name)));
+
+        let running = match tokio::task::spawn_blocking(running_processes).await {
+            Ok(Ok(running)) => running,
+            Ok(Err(e)) => {
+                warn!("Failed to list processes for screen capture detection: {}", e);
+                return None;
+            }
+            Err(e) => {
+                warn!("Screen capture detection failed: {}", e);
+                return None;
+            }
+        };
+        running.iter()
+            .find(|process| names.iter().any(|name| process_matches(process, name)))
+            .map(|process| format!("{} is running", process))
+    }
+}
+
+/// Active OBS outputs ("streaming", "recording", "using the virtual camera")
+async fn obs_outputs(url: &str, password: Option<&str>) -> Result<Vec<&'static str>, Box<dyn std::error::Error + Send + Sync>> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(url).await?;
+
+    // Hello (op 0), carrying the authentication challenge if a password is set
+    let hello = read_message(&mut socket).await?;
+    let mut identify = json!({ "rpcVersion": 1, "eventSubscriptions": 0 });
+    if let Some(auth) = hello["d"].get("authentication") {
+        let password = password.ok_or("obs-websocket needs a password (`obs_password`)")?;
+        let challenge = auth["challenge"].as_str().ok_or("missing challenge")?;
+        let salt = auth["salt"].as_str().ok_or("missing salt")?;
+        identify["authentication"] = json!(obs_auth(password, salt, challenge));
+    }
+    socket.send(Message::Text(json!({ "op": 1, "d": identify }).to_string())).await?;
+
+    // Identified (op 2); a wrong password closes the connection instead
+    let identified = read_message(&mut socket).await?;
+    if identified["op"] != 2 {
+        return Err("obs-websocket rejected the connection".into());
+    }
+
+    let mut outputs = Vec::new();
+    let requests = [
+        ("GetStreamStatus", "streaming"),
+        ("GetRecordStatus", "recording"),
+        ("GetVirtualCamStatus", "using the virtual camera"),
+    ];
+    for (request_type, output) in requests {
+        let request = json!({ "op": 6, "d": { "requestType": request_type, "requestId": request_type } });
+        socket.send(Message::Text(request.to_string())).await?;
+        let response = loop {
+            let message = read_message(&mut socket).await?;
+            if message["op"] == 7 && message["d"]["requestId"] == request_type {
+                break message;
+            }
+        };
+        if response["d"]["responseData"]["outputActive"] == true {
+            outputs.push(output);
+        }
+    }
+
+    let _ = socket.close(None).await;
+    Ok(outputs)
+}
+
+async fn read_message<S>(socket: &mut S) -> Result<Value, Box<dyn std::error::Error + Send + Sync>>
+where
+    S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        match socket.next().await.ok_or("connection closed")?? {
+            Message::Text(text) => return Ok(serde_json::from_str(&text)?),
+            Message::Close(_) => return Err("connection closed".into()),
+            _ => continue,
+        }
+    }
+}
+
+/// `base64(sha256(base64(sha256(password + salt)) + challenge))`, as obs-websocket expects
+fn obs_auth(password: &str, salt: &str, challenge: &str) -> String {
+    let base64 = base64::engine::general_purpose::STANDARD;
+    let secret = base64.encode(Sha256::digest(format!("{}{}", password, salt)));
+    base64.encode(Sha256::digest(format!("{}{}", secret, challenge)))
+}
+
+/// Lowercase, without `.exe`, so names compare the same on every platform
+pub fn normalize_process_name(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    name.strip_suffix(".exe").map(str::to_string).unwrap_or(name)
+}
+
+pub fn process_matches(process: &str, name: &str) -> bool {
+    // Linux truncates process names to 15 characters
+    process == name || (cfg!(target_os = "linux") && process.len() == 15 && name.starts_with(process))
+}
+
+/// Names of running processes, normalized
+#[cfg(target_os = "linux")]
+fn running_processes() -> std::io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        if !entry.file_name().to_string_lossy().chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        if let Ok(comm) = std::fs::read_to_string(entry.path().join("comm")) {
+            names.push(normalize_process_name(&comm));
+        }
+    }
+    Ok(names)
+}
+
+/// Names of running processes, normalized
+#[cfg(target_os = "macos")]
+fn running_processes() -> std::io::Result<Vec<String>> {
+    let output = std::process::Command::new("ps").args(["-axco", "comm="]).output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(normalize_process_name).collect())
+}
+
+/// Names of running processes, normalized
+#[cfg(windows)]
+fn running_processes() -> std::io::Result<Vec<String>> {
+    let output = std::process::Command::new("tasklist").args(["/fo", "csv", "/nh"]).output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split(',').next())
+        .map(|name| normalize_process_name(name.trim_matches('"')))
+        .collect())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn running_processes() -> std::io::Result<Vec<String>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "listing processes isn't supported on this platform"))
+}
+
+/// Logs how screen capture is detected, once at startup
+pub fn log_setup(config: &CaptureSafeConfig) {
+    let safe = config.action.as_vec().len();
+    info!("Screen capture safe mode is on: {} while OBS ({}) or a capture app is active",
+          if safe == 0 { "suppressing actions".to_string() } else { format!("running {} safe action(s)", safe) },
+          config.obs_websocket.as_deref().unwrap_or(DEFAULT_OBS_WEBSOCKET));
+}
@@ -0,0 +1,102 @@
+// Command line parsing shared by the rule engine and rule conditions
+// Splits raw command strings from probes into a normalized executable name and argv, and brings
+// command lines and command globs to the same Unicode form so they match however they were typed
+
+/// A command line split into its executable and arguments
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandLine {
+    /// Normalized executable name: no directory, no `.exe` suffix (e.g. `cargo`)
+    pub exe: String,
+    /// Arguments after the executable
+    pub args: Vec<String>,
+}
+
+impl CommandLine {
+    /// Parses a command line as reported by a probe.
+    /// Leading `VAR=value` environment assignments are skipped.
+    pub fn parse(command: &str) -> Self {
+        let mut argv = split_command_line(command).into_iter()
+            .skip_while(|token| is_env_assignment(token));
+
+        let exe = argv.next()
+            .map(|program| normalize_exe(&program))
+            .unwrap_or_default();
+
+        Self {
+            exe,
+            args: argv.collect(),
+        }
+    }
+}
+
+/// Strips the directory and a trailing `.exe` from a program path
+pub fn normalize_exe(program: &str) -> String {
+    let name = program.rsplit(['/', '\\']).next().unwrap_or(program);
+    match name.len().checked_sub(4) {
+        Some(stem_len) if name.is_char_boundary(stem_len) && name[stem_len..].eq_ignore_ascii_case(".exe") => {
+            name[..stem_len].to_string()
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// Unicode NFC form of a command line or glob, so `é` matches whether it was typed as one code
+/// point or as `e` and a combining accent (as macOS and some IMEs do)
+pub fn normalize_unicode(text: &str) -> String {
+    use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+    match is_nfc_quick(text.chars()) {
+        IsNormalized::Yes => text.to_string(),
+        _ => text.nfc().collect(),
+    }
+}
+
+fn is_env_assignment(token: &str) -> bool {
+    match token.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// Splits a command line into tokens, honoring single and double quotes.
+/// Backslashes only escape quotes so Windows paths survive intact.
+fn split_command_line(command: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut quote: Option<char> = None;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (_, '\\') if matches!(chars.peek(), Some('"') | Some('\'')) => {
+                current.push(chars.next().unwrap_or('\\'));
+                in_token = true;
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                in_token = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut current));
+                    in_token = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_token = true;
+            }
+        }
+    }
+
+    if in_token {
+        tokens.push(current);
+    }
+    tokens
+}
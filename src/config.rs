@@ -1,13 +1,462 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 use tracing::{info, warn};
 
-#[derive(Debug, Clone, Deserialize, Serialize, Hash)]
+use crate::action_env;
+use crate::expr::Expr;
+use crate::i18n::{t, Msg};
+use crate::matcher;
+use crate::mqtt;
+use crate::schedule::Schedule;
+use crate::scripting;
+
+/// Start events older than this don't start actions, unless `max_event_age` says otherwise
+const DEFAULT_MAX_EVENT_AGE: std::time::Duration = std::time::Duration::from_secs(10);
+/// Longest starting an action's program may take, unless `spawn_timeout` says otherwise
+pub const DEFAULT_SPAWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// Matches a minute that make a rule noisy, unless `noisy_rule_threshold` says otherwise
+const DEFAULT_NOISY_RULE_THRESHOLD: u32 = 30;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
 pub struct Config {
     pub rules: Vec<Rule>,
+    /// Sets of rules sharing defaults, turned on and off together
+    #[serde(default)]
+    pub groups: Vec<RuleGroup>,
     /// Optional override for viberot home directory
-    /// If not specified, uses environment variable or platform defaults
+    /// If not specified, found as `runtime_paths::install_root` says
     pub viberot_home: Option<String>,
+    /// Shell probe settings (Linux/macOS)
+    #[serde(default)]
+    pub shell: ShellConfig,
+    /// ETW probe settings (Windows)
+    #[serde(default)]
+    pub etw: EtwConfig,
+    /// Taskbar/launcher progress and do-not-disturb sync for matched commands
+    #[serde(default)]
+    pub desktop_integration: DesktopIntegrationConfig,
+    /// Prefix of the environment variables passed to actions (default `VIBEROT`)
+    #[serde(default)]
+    pub action_env_prefix: Option<String>,
+    /// Language of prompts and notices, e.g. "de"; detected from the environment if unset
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// Warn when an action takes longer than this to become visible, e.g. "2s"
+    #[serde(default)]
+    pub latency_budget: Option<ConfigDuration>,
+    /// `latency_budget` in milliseconds, from before durations took units
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_budget_ms: Option<u64>,
+    /// Warn when matching a command against the rules takes longer than this, e.g. "50ms"
+    #[serde(default)]
+    pub match_budget: Option<ConfigDuration>,
+    /// `match_budget` in milliseconds, from before durations took units
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_budget_ms: Option<u64>,
+    /// How many recent commands `viberot-service recent` can show (default 200, 0 turns it off)
+    #[serde(default)]
+    pub recent_events: Option<usize>,
+    /// Keep a full decision trace of recent commands for `viberot-service explain`: the checks
+    /// each rule got past and the actions chosen
+    #[serde(default)]
+    pub explain: bool,
+    /// How long an action's state directory is kept when unused, e.g. "2w" (default "30d", "0"
+    /// keeps them)
+    #[serde(default)]
+    pub action_state_retention: Option<ConfigDuration>,
+    /// `action_state_retention` in days, from before durations took units
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action_state_retention_days: Option<u64>,
+    /// How long actions may run a day, e.g. "45m", before they are swapped for a notification
+    #[serde(default)]
+    pub daily_action_budget: Option<ConfigDuration>,
+    /// Start events older than this, e.g. "10s", don't start actions: the command may well have
+    /// ended by then (default 10s, "0" turns the check off)
+    #[serde(default)]
+    pub max_event_age: Option<ConfigDuration>,
+    /// Give up on starting an action's program after this long, e.g. when it's on a network share
+    /// that stopped answering (default 5s, "0" waits as long as it takes)
+    #[serde(default)]
+    pub spawn_timeout: Option<ConfigDuration>,
+    /// What a matched rule does with actions whose program isn't there (default "skip")
+    #[serde(default)]
+    pub missing_action: Option<MissingAction>,
+    /// Warn about rules matching more than this many commands a minute (default 30, 0 turns the
+    /// warning off)
+    #[serde(default)]
+    pub noisy_rule_threshold: Option<u32>,
+    /// Audit log of executed programs
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// Tagging of events with repository, language and command category
+    #[serde(default)]
+    pub enrichment: EnrichmentConfig,
+    /// Safe actions while the screen is streamed or recorded
+    #[serde(default)]
+    pub capture_safe: CaptureSafeConfig,
+    /// Getting events from a system-wide broker instead of a probe of our own
+    #[serde(default)]
+    pub broker: BrokerConfig,
+    /// External systems told about matched commands
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    /// Commands reported by other devices over MQTT
+    #[serde(default)]
+    pub mqtt_source: Option<MqttSourceConfig>,
+    /// Commands editors and agent plugins report over a WebSocket
+    #[serde(default)]
+    pub websocket_source: Option<WebSocketSourceConfig>,
+    /// Commands other programs report over a named pipe or Unix socket
+    #[serde(default)]
+    pub pipe_source: Option<PipeSourceConfig>,
+    /// Runs of coding agents, reported by their hooks
+    #[serde(default)]
+    pub agent_hooks: Option<AgentHooksConfig>,
+    /// Durations from atuin's shell history, and atuin as a probe
+    #[serde(default)]
+    pub atuin: AtuinConfig,
+    /// Reduced motion, captions and high contrast, for actions and the service alike
+    #[serde(default)]
+    pub accessibility: AccessibilityConfig,
+    /// How the end of a command is announced, depending on how caught up in its actions the user is
+    #[serde(default)]
+    pub end_of_distraction: EndOfDistractionConfig,
+    /// Actions defined once under `[actions.<name>]`, which rules refer to by name
+    #[serde(default)]
+    pub actions: BTreeMap<String, Action>,
+    /// Counting which probes and actions are used, and sharing the counts (both off by default)
+    #[serde(default)]
+    pub usage_stats: UsageStatsConfig,
+    /// Syncing the config between machines through git
+    #[serde(default)]
+    pub sync: SyncConfig,
+    /// Where stats, streaks and recent events are kept; read when the service starts
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// The page the overlay shows by default, served on localhost
+    #[serde(default)]
+    pub waiting_room: WaitingRoomConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct ShellConfig {
+    /// Unix socket paths the shell probe listens on.
+    /// If empty, uses `$XDG_RUNTIME_DIR/viberot-shell.sock`
+    #[serde(default)]
+    pub socket_paths: Vec<String>,
+    /// Optional localhost TCP port for shells that can't reach the Unix sockets,
+    /// e.g. terminals running inside Flatpak/Snap sandboxes or containers
+    #[serde(default)]
+    pub tcp_port: Option<u16>,
+    /// Set by `viberot-service broker`: the probe serves every user of the machine
+    #[serde(skip)]
+    pub shared: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct EtwConfig {
+    /// Read the working directory of every new process, for `cwd` in rules and the repository
+    /// and language tags. Costs a few extra system calls per process, so it's off by default
+    #[serde(default)]
+    pub working_directory: bool,
+    /// Read the variables npm, yarn, pnpm and bun set for the scripts they run, for the
+    /// `lifecycle` and `package` tags. Costs a few extra system calls per process, so it's off by
+    /// default
+    #[serde(default)]
+    pub package_scripts: bool,
+    /// Apps like "code.exe" or "rider64.exe" whose descendants get a `parent_app` tag, so builds
+    /// they start can be matched even when wrappers mangle the command line
+    #[serde(default)]
+    pub parent_apps: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct DesktopIntegrationConfig {
+    /// Show progress on the terminal's taskbar button (Windows) or launcher icon (Linux)
+    #[serde(default)]
+    pub taskbar_progress: bool,
+    /// Desktop file of the terminal whose launcher icon shows progress on Linux,
+    /// e.g. "org.gnome.Terminal.desktop"
+    #[serde(default)]
+    pub launcher_desktop_file: Option<String>,
+    /// Don't start actions while the OS do-not-disturb / Focus Assist is on
+    #[serde(default)]
+    pub respect_dnd: bool,
+    /// Turn do-not-disturb on while matched commands run (Linux/GNOME only),
+    /// restoring it when the last one ends
+    #[serde(default)]
+    pub enable_dnd_while_running: bool,
+    /// Show progress in the terminal running the command too, with OSC 9;4 sequences
+    /// (Windows Terminal, ConEmu, WezTerm and others; needs the shell hooks)
+    #[serde(default)]
+    pub terminal_progress: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct AuditConfig {
+    /// Turn off the audit log of executed programs
+    #[serde(default)]
+    pub disabled: bool,
+    /// Entries written per minute before further ones are only counted (default 600)
+    #[serde(default)]
+    pub max_entries_per_minute: Option<u32>,
+    /// Size at which the audit file is rotated, in KiB (default 10240)
+    #[serde(default)]
+    pub max_file_size_kb: Option<u64>,
+    /// Number of rotated audit files kept (default 5)
+    #[serde(default)]
+    pub max_files: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct EnrichmentConfig {
+    /// Built-in enrichers to skip: "git", "language", "task" or "category"
+    #[serde(default)]
+    pub disabled: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct AtuinConfig {
+    /// atuin's history database; defaults to where atuin keeps it
+    #[serde(default)]
+    pub db_path: Option<String>,
+    /// Take commands from atuin's history instead of the shell hooks
+    #[serde(default)]
+    pub probe: bool,
+    /// Don't use atuin's durations for commands the service hasn't timed yet
+    #[serde(default)]
+    pub skip_durations: bool,
+}
+
+impl AtuinConfig {
+    /// `db_path`, or atuin's default: `$ATUIN_DB_PATH`, or `history.db` in `$XDG_DATA_HOME/atuin`
+    /// (`~/.local/share/atuin` on every platform)
+    pub fn db_path(&self) -> Option<std::path::PathBuf> {
+        if let Some(ref path) = self.db_path {
+            return Some(path.into());
+        }
+        if let Some(path) = std::env::var_os("ATUIN_DB_PATH") {
+            return Some(path.into());
+        }
+        let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+            Some(dir) => dir.into(),
+            None => dirs::home_dir()?.join(".local").join("share"),
+        };
+        Some(data_dir.join("atuin").join("history.db"))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct BrokerConfig {
+    /// Subscribe to `viberot-service broker` instead of running a probe
+    #[serde(default)]
+    pub connect: bool,
+    /// Broker socket (Unix) or named pipe (Windows); defaults to `/run/viberot/broker.sock`
+    /// or `\\.\pipe\viberot-broker`
+    #[serde(default)]
+    pub endpoint: Option<String>,
+}
+
+/// Where matched commands are republished, e.g. to a home-automation system
+#[derive(Debug, Clone, Deserialize, Serialize, Hash)]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub target: SinkTarget,
+    /// Only commands matched by these rules (by name); all matched commands if empty
+    #[serde(default)]
+    pub rules: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Hash)]
+#[serde(tag = "type")]
+pub enum SinkTarget {
+    /// JSON lines written to a listening Unix socket (named pipe on Windows)
+    #[serde(rename = "socket")]
+    Socket { path: String },
+    /// JSON POSTed to an HTTP(S) endpoint
+    #[serde(rename = "webhook")]
+    Webhook {
+        url: String,
+        /// Extra request headers, e.g. `Authorization`
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+    /// JSON published to an MQTT topic
+    #[serde(rename = "mqtt")]
+    Mqtt {
+        #[serde(flatten)]
+        connection: MqttConnection,
+        topic: String,
+        /// Keep the last message on the broker for clients that subscribe later
+        #[serde(default)]
+        retain: bool,
+    },
+}
+
+/// How to reach an MQTT broker
+#[derive(Debug, Clone, Deserialize, Serialize, Hash)]
+pub struct MqttConnection {
+    /// `mqtt://host[:port]`, or `mqtts://host[:port]` for TLS
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// CA certificate (PEM) to verify the broker with instead of the system's
+    #[serde(default)]
+    pub ca_file: Option<String>,
+    /// Client certificate and key (PEM) for brokers that require them
+    #[serde(default)]
+    pub client_cert: Option<String>,
+    #[serde(default)]
+    pub client_key: Option<String>,
+    /// Defaults to `viberot-<hostname>-...`
+    #[serde(default)]
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Hash)]
+pub struct MqttSourceConfig {
+    #[serde(flatten)]
+    pub connection: MqttConnection,
+    /// Topic filter to subscribe to, e.g. "viberot/remote/#"
+    pub topic: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct WebSocketSourceConfig {
+    /// Localhost port to serve on (default 47914)
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct PipeSourceConfig {
+    /// Named pipe (Windows) or Unix socket to listen on (default `\\.\pipe\viberot-probe`, or
+    /// viberot-probe.sock in the runtime directory)
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct AgentHooksConfig {
+    /// Named pipe (Windows) or Unix socket the hook script reaches the service on (default
+    /// `\\.\pipe\viberot-agents`, or viberot-agents.sock in the runtime directory)
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct CaptureSafeConfig {
+    /// Detect screen capture and swap actions for the safe ones
+    #[serde(default)]
+    pub enabled: bool,
+    /// obs-websocket URL (default `ws://127.0.0.1:4455`)
+    #[serde(default)]
+    pub obs_websocket: Option<String>,
+    #[serde(default)]
+    pub obs_password: Option<String>,
+    /// More process names that mean the screen is captured, e.g. "discord"
+    #[serde(default)]
+    pub processes: Vec<String>,
+    /// Actions run instead of the matched ones; none suppresses them
+    #[serde(alias = "actions", default)]
+    pub action: Actions,
+}
+
+/// Accessibility settings; actions get them as JSON in `VIBEROT_ACCESSIBILITY` when they start
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, Hash)]
+pub struct AccessibilityConfig {
+    /// Skip actions marked `animated = true`, like flashing or video overlays
+    #[serde(default)]
+    pub skip_animated: bool,
+    /// Ask actions to keep still; the overlay turns off page animations, transitions and smooth
+    /// scrolling
+    #[serde(default)]
+    pub reduced_motion: bool,
+    /// Show what `speak` actions say as a notification too
+    #[serde(default)]
+    pub captions: bool,
+    /// Ask actions for a high-contrast look, like the overlay's banners
+    #[serde(default)]
+    pub high_contrast: bool,
+}
+
+/// Ends actions with a banner and a sound when the user was busy with them until the command ended
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct EndOfDistractionConfig {
+    /// Sample keyboard and mouse idle time while actions run
+    #[serde(default)]
+    pub enabled: bool,
+    /// Share of the samples with input, in percent, that counts as deep in the distraction
+    /// (default 60)
+    #[serde(default)]
+    pub active_percent: Option<u32>,
+    /// How far back before the command ended samples count, e.g. "2m" (default "1m")
+    #[serde(default)]
+    pub window: Option<ConfigDuration>,
+    /// Play a sound with the banner (default true)
+    #[serde(default)]
+    pub sound: Option<bool>,
+}
+
+/// Usage stats: counts only, never commands, rule names or paths
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct UsageStatsConfig {
+    /// Count commands per probe, rule matches and actions per type, for `viberot-service usage`
+    #[serde(default)]
+    pub enabled: bool,
+    /// Also send the counts to `submit_url` once a day, without anything identifying the machine
+    #[serde(default)]
+    pub submit: bool,
+    #[serde(default)]
+    pub submit_url: Option<String>,
+}
+
+/// Syncing the config through the repository `viberot-service config sync init` cloned
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct SyncConfig {
+    /// Push the config whenever it changes and pull it when the service starts
+    #[serde(default)]
+    pub auto: bool,
+}
+
+/// The backend `storage` persists to
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct StorageConfig {
+    /// "file", "sqlite" or "memory" (default "file")
+    #[serde(default)]
+    pub backend: Option<StorageBackend>,
+    /// Database of the "sqlite" backend (default ~/.viberot/viberot.db)
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// JSON files in ~/.viberot, keeping the last `recent_events` commands
+    #[default]
+    File,
+    /// One SQLite database keeping every command, for dashboards
+    Sqlite,
+    /// Nothing written to disk; history lasts until the service stops
+    Memory,
+}
+
+/// The waiting room page: progress of the command and images from a folder, served on 127.0.0.1
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash)]
+pub struct WaitingRoomConfig {
+    /// Serve the page and make it the overlay's default (default true)
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Port on 127.0.0.1 it is served on (default 47613)
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Folder of images and videos it shows (default ~/.viberot/memes)
+    #[serde(default)]
+    pub memes_dir: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Hash)]
@@ -26,12 +475,149 @@ pub enum Actions {
 
 #[derive(Debug, Clone, Deserialize, Serialize, Hash)]
 pub struct Rule {
-    #[serde(alias = "commands")]
-    pub command: Commands,
+    /// Optional human-readable name, passed to actions and shown in logs
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Whether the rule is on; `false` keeps it in the config without matching anything
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// The `[[groups]]` entry the rule takes its defaults from, and is turned off with
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Glob pattern(s) matched against the full command line
+    #[serde(alias = "commands", default)]
+    pub command: Option<Commands>,
+    /// Match `command` globs ignoring case, so `*npm install*` also matches `NPM INSTALL`
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// How `command` patterns are matched: "glob" (default), "regex", "expr" (expressions like
+    /// `when`) or "custom:<name>" for a matcher registered with the rule engine
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matcher: Option<String>,
+    /// Program name(s) the command runs, without directory or `.exe`, e.g. "cargo"; unlike a
+    /// `*cargo*` glob this doesn't match `echo cargo`. Combined with `command` if both are set
+    #[serde(default)]
+    pub exe: Option<Commands>,
+    /// Arguments the command must have, each matching a whole argument, e.g. ["build", "--release"]
+    #[serde(default)]
+    pub args_contains: Vec<String>,
+    /// Optional condition expression, e.g. `cmd.exe == 'cargo' && args.contains('build')`
+    /// Combined with `command` if both are set
+    #[serde(default)]
+    pub when: Option<String>,
+    /// Glob pattern(s) matched against the git remote (`github.com/acme/app`) or the repository
+    /// directory name of the command's working directory
+    #[serde(default)]
+    pub repo: Option<Commands>,
+    /// Glob pattern(s) matched against the checked out git branch
+    #[serde(default)]
+    pub branch: Option<Commands>,
+    /// Only match when the repository has (`true`) or hasn't (`false`) uncommitted changes
+    #[serde(default)]
+    pub dirty: Option<bool>,
+    /// Glob pattern(s) matched against the task a task runner runs (`just deploy`, `npm run build`)
+    #[serde(default)]
+    pub task: Option<Commands>,
+    /// Match commands run through `viberot-service wrap --detect-stall` once they stop printing,
+    /// instead of when they start; the actions stop when output resumes
+    #[serde(default)]
+    pub stalled: bool,
+    /// `session` matches each rebuild of a watch-mode command run through
+    /// `viberot-service wrap --cycles`, instead of when the command starts
+    #[serde(default)]
+    pub mode: RuleMode,
+    /// Optional Lua hook run on match; can veto the match or rewrite the action list
+    #[serde(default)]
+    pub script: Option<String>,
+    /// Actions started as soon as the command does; optional if the rule escalates
+    #[serde(alias = "actions", default)]
+    pub action: Actions,
+    /// Further actions started while the command is still running after some time
+    #[serde(default)]
+    pub escalate: Vec<Escalation>,
+    /// How long this rule's actions may run a day, e.g. "20m", before they are swapped for a
+    /// notification
+    #[serde(default)]
+    pub daily_action_budget: Option<ConfigDuration>,
+    /// Hold the actions back until the command and the processes it started use more than this
+    /// percentage of a core for a couple of seconds, e.g. 50; skips commands stuck on a prompt
+    #[serde(default)]
+    pub only_if_cpu_above: Option<u32>,
+    /// Hold the actions back until the command is doing something rather than waiting: using
+    /// some CPU or the disk for a couple of seconds; skips commands waiting for input
+    #[serde(default)]
+    pub only_if: Option<OnlyIf>,
+    /// Hold the actions back until the command has run this long, e.g. "30s"; quick commands
+    /// end before they'd start
+    #[serde(default)]
+    pub min_duration: Option<ConfigDuration>,
+    /// Don't match again for this long after the rule set off its actions, e.g. "10m"
+    #[serde(default)]
+    pub cooldown: Option<ConfigDuration>,
+    /// Times of the week the rule matches in, local time, e.g. "mon-fri 09:00-18:00"; see
+    /// schedule.rs
+    #[serde(default)]
+    pub schedule: Option<Commands>,
+    /// Values for `{name}` placeholders in the rule's actions, e.g. `with = { url = "..." }` for
+    /// a named action with `args = ["--url", "{url}"]`
+    #[serde(default)]
+    pub with: BTreeMap<String, String>,
+    /// How much is logged about the commands the rule matches (default "info")
+    #[serde(default)]
+    pub log: Option<RuleLog>,
+}
+
+impl Rule {
+    /// `command` patterns if they're globs; empty for rules with another `matcher`
+    pub fn command_globs(&self) -> Vec<&String> {
+        match self.matcher.as_deref() {
+            None | Some("glob") => self.command.as_ref().map(|c| c.as_vec()).unwrap_or_default(),
+            Some(_) => Vec::new(),
+        }
+    }
+
+    /// `name`, or `rule #N` (counting from 1) for unnamed rules
+    pub fn display_name(&self, index: usize) -> String {
+        self.name.clone().unwrap_or_else(|| format!("rule #{}", index + 1))
+    }
+
+    /// Hash of everything in the rule, for telling whether it changed across reloads
+    pub fn content_hash(&self) -> u64 {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// An escalation tier of a rule, e.g. start the overlay once a build has run for 5 minutes
+#[derive(Debug, Clone, Deserialize, Serialize, Hash)]
+pub struct Escalation {
+    /// How long the command must have been running, e.g. "90s", "5m" or "1h30m"
+    pub after: ConfigDuration,
     #[serde(alias = "actions")]
     pub action: Actions,
 }
 
+/// Defaults shared by the rules naming the group with `group = "<name>"`; a rule's own keys win
+#[derive(Debug, Clone, Deserialize, Serialize, Hash)]
+pub struct RuleGroup {
+    pub name: String,
+    /// Whether the group's rules are on; `false` turns them all off
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(alias = "actions", default)]
+    pub action: Actions,
+    #[serde(default)]
+    pub cooldown: Option<ConfigDuration>,
+    #[serde(default)]
+    pub schedule: Option<Commands>,
+    #[serde(default)]
+    pub min_duration: Option<ConfigDuration>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Hash)]
 #[serde(tag = "type")]
 pub enum Action {
@@ -41,6 +627,31 @@ pub enum Action {
         args: Option<Vec<String>>,
         #[serde(default)]
         single_instance: bool,
+        /// Keep one process for all matched commands and tell it about each one over stdin
+        /// (`open`/`close` JSON lines), instead of spawning a process per command
+        #[serde(default)]
+        shared: bool,
+        /// Start the action when the service starts, and only signal it (`attach`/`detach`
+        /// JSON lines) when commands start and end
+        #[serde(default)]
+        prewarm: bool,
+        /// Start the action once the command ended instead of when it starts, with a JSON summary
+        /// of it (`elapsed_secs`, `exit_code`, `rule`) as its last argument
+        #[serde(default)]
+        on_end: bool,
+        /// Run the action in a restricted context: read-only filesystem and no network
+        /// (bubblewrap on Linux, sandbox-exec on macOS, a UI-restricted job object on Windows)
+        #[serde(default)]
+        sandbox: bool,
+        /// Let a sandboxed action use the network, e.g. an overlay loading a website
+        #[serde(default)]
+        sandbox_allow_network: bool,
+        /// Paths a sandboxed action may write to (Linux/macOS)
+        #[serde(default)]
+        sandbox_writable: Vec<String>,
+        /// Flashes or moves a lot, so `[accessibility] skip_animated` skips it
+        #[serde(default)]
+        animated: bool,
     },
     #[serde(rename = "lua")]
     Lua { 
@@ -48,6 +659,192 @@ pub enum Action {
         #[serde(default)]
         single_instance: bool,
     },
+    /// Shows a resident daemon (e.g. `viberot-overlay --daemon`) while the command runs
+    #[serde(rename = "attach")]
+    Attach {
+        /// Daemon socket (named pipe on Windows), defaults to the overlay daemon's
+        #[serde(default)]
+        socket: Option<String>,
+        /// Daemon to launch when none is listening yet
+        #[serde(default)]
+        path: Option<String>,
+        #[serde(default)]
+        args: Option<Vec<String>>,
+        /// Flashes or moves a lot, so `[accessibility] skip_animated` skips it
+        #[serde(default)]
+        animated: bool,
+    },
+    /// Shows a desktop notification; on Windows with buttons that act on the command
+    #[serde(rename = "notify")]
+    Notify {
+        /// Defaults to the rule name
+        #[serde(default)]
+        title: Option<String>,
+        /// Defaults to the command line
+        #[serde(default)]
+        message: Option<String>,
+        #[serde(default = "default_notify_buttons")]
+        buttons: Vec<NotifyButton>,
+    },
+    /// Says out loud that the command finished, how long it took and whether it worked
+    #[serde(rename = "speak")]
+    Speak {
+        /// What to say, with `{command}` (e.g. `cargo build`), `{command_line}`, `{rule}`,
+        /// `{duration}` and `{outcome}` filled in
+        #[serde(default, alias = "text")]
+        text_template: Option<String>,
+        /// A voice the platform's text-to-speech knows, e.g. "Samantha" on macOS
+        #[serde(default)]
+        voice: Option<String>,
+    },
+    /// Rings the bell and shows a notification in the command's terminal when it ends
+    #[serde(rename = "bell")]
+    Bell {
+        /// The notification's text, with the same placeholders as `speak`
+        #[serde(default, alias = "text")]
+        text_template: Option<String>,
+        /// Ring the bell as well as showing the text
+        #[serde(default = "default_true")]
+        bell: bool,
+    },
+    /// Suspends background processes, e.g. a game or OBS's replay buffer, while the command runs
+    #[serde(rename = "throttle")]
+    Throttle {
+        #[serde(default)]
+        target: ThrottleTarget,
+        /// Process names for `target = "list"`, e.g. ["obs64", "game.exe"]
+        #[serde(default)]
+        processes: Vec<String>,
+        #[serde(default = "default_true")]
+        suspend_on_start: bool,
+        /// Resume them when the command ends; otherwise they stay suspended until a throttle
+        /// action resumes them or the service stops
+        #[serde(default = "default_true")]
+        resume_on_end: bool,
+    },
+    /// Switches to another virtual desktop while the command runs, and back when it ends
+    #[serde(rename = "desktop")]
+    Desktop {
+        /// Desktop number, from 1
+        desktop: u32,
+        /// Where to go back to when the current desktop can't be read (macOS)
+        #[serde(default)]
+        back_to: Option<u32>,
+    },
+}
+
+/// What a rule matches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleMode {
+    /// A command starting
+    #[default]
+    Command,
+    /// A rebuild of a watch-mode command
+    Session,
+}
+
+/// What a command must be doing before a rule's actions start (`only_if`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum OnlyIf {
+    /// Working on the CPU or the disk, not waiting for input
+    Busy,
+}
+
+/// How much is logged about a rule's matches (`log`), quietest first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleLog {
+    /// Nothing
+    Off,
+    /// Only with `RUST_LOG=debug`
+    Debug,
+    #[default]
+    Info,
+}
+
+/// What becomes of actions whose program doesn't exist (`missing_action`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingAction {
+    /// Left out; the service said so when the config was loaded
+    Skip,
+    /// A notification about the command in their place
+    Notify,
+}
+
+/// What a `throttle` action suspends
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, Hash)]
+pub enum ThrottleTarget {
+    /// The processes named in `processes`
+    #[default]
+    #[serde(rename = "list")]
+    List,
+    /// VibeRot's own `shared` and `prewarm` action processes while no command uses them
+    #[serde(rename = "self-spawned")]
+    SelfSpawned,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A button on a notification (Windows only)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyButton {
+    /// Opens a terminal in the command's working directory
+    OpenTerminal,
+    /// Stops the rule from matching for an hour
+    Snooze,
+    /// Stops the command's actions
+    Dismiss,
+}
+
+pub fn default_notify_buttons() -> Vec<NotifyButton> {
+    vec![NotifyButton::OpenTerminal, NotifyButton::Snooze, NotifyButton::Dismiss]
+}
+
+impl Action {
+    /// The action with `{name}` placeholders in its paths, arguments and texts replaced by a
+    /// rule's `with` values; placeholders without a value stay as they are
+    pub fn with_params(&self, params: &BTreeMap<String, String>) -> Action {
+        if params.is_empty() {
+            return self.clone();
+        }
+        let fill = |text: &str| params.iter().fold(text.to_string(), |text, (name, value)| text.replace(&format!("{{{}}}", name), value));
+        let fill_all = |texts: &Option<Vec<String>>| texts.as_ref().map(|texts| texts.iter().map(|text| fill(text)).collect());
+        let mut action = self.clone();
+        match &mut action {
+            Action::Executable { path, args, .. } => {
+                *path = fill(path);
+                *args = fill_all(args);
+            }
+            Action::Lua { .. } => {}
+            Action::Attach { socket, path, args, .. } => {
+                *socket = socket.as_deref().map(fill);
+                *path = path.as_deref().map(fill);
+                *args = fill_all(args);
+            }
+            Action::Notify { title, message, .. } => {
+                *title = title.as_deref().map(fill);
+                *message = message.as_deref().map(fill);
+            }
+            Action::Speak { text_template, voice } => {
+                *text_template = text_template.as_deref().map(fill);
+                *voice = voice.as_deref().map(fill);
+            }
+            Action::Throttle { processes, .. } => {
+                *processes = processes.iter().map(|process| fill(process)).collect();
+            }
+            Action::Bell { text_template, .. } => {
+                *text_template = text_template.as_deref().map(fill);
+            }
+            Action::Desktop { .. } => {}
+        }
+        action
+    }
 }
 
 impl Config {
@@ -62,11 +859,168 @@ impl Config {
         }
 
         let content = std::fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
+        let config = Self::parse(&content)?;
+        config.validate()?;
         info!("Loaded config with {} rules", config.rules.len());
         Ok(config)
     }
 
+    /// Parses a config file, filling in environment variables, group defaults and the named actions
+    /// rules refer to
+    pub fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut document: toml::Table = toml::from_str(content)?;
+        let interpolated = interpolate_env(&mut document)?;
+        let grouped = apply_groups(&mut document)?;
+        if !resolve_named_actions(&mut document)? && !interpolated && !grouped {
+            // Straight from the text, so errors point at a line
+            return Ok(toml::from_str(content)?);
+        }
+        Ok(toml::Value::Table(document).try_into().map_err(|e: toml::de::Error| e.to_string())?)
+    }
+
+    /// How old start events may be to start actions, None if any age will do
+    pub fn max_event_age(&self) -> Option<std::time::Duration> {
+        let age = self.max_event_age.map_or(DEFAULT_MAX_EVENT_AGE, |age| age.0);
+        (!age.is_zero()).then_some(age)
+    }
+
+    /// Matches a minute that make a rule noisy, 0 if none do
+    pub fn noisy_rule_threshold(&self) -> u32 {
+        self.noisy_rule_threshold.unwrap_or(DEFAULT_NOISY_RULE_THRESHOLD)
+    }
+
+    /// Longest starting an action's program may take, None if it may take as long as it takes
+    pub fn spawn_timeout(&self) -> Option<std::time::Duration> {
+        let timeout = self.spawn_timeout.map_or(DEFAULT_SPAWN_TIMEOUT, |timeout| timeout.0);
+        (!timeout.is_zero()).then_some(timeout)
+    }
+
+    /// `latency_budget`, or the older `latency_budget_ms`
+    pub fn latency_budget(&self) -> Option<std::time::Duration> {
+        self.latency_budget.map(|budget| budget.0)
+            .or(self.latency_budget_ms.map(std::time::Duration::from_millis))
+    }
+
+    /// `match_budget`, or the older `match_budget_ms`
+    pub fn match_budget(&self) -> Option<std::time::Duration> {
+        self.match_budget.map(|budget| budget.0)
+            .or(self.match_budget_ms.map(std::time::Duration::from_millis))
+    }
+
+    /// `action_state_retention`, or the older `action_state_retention_days`
+    pub fn action_state_retention(&self) -> Option<std::time::Duration> {
+        self.action_state_retention.map(|retention| retention.0)
+            .or(self.action_state_retention_days.map(|days| std::time::Duration::from_secs(days * 24 * 60 * 60)))
+    }
+
+    /// Checks rule definitions that can't be expressed in the TOML schema alone
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.command.is_none() && rule.exe.is_none() && rule.when.is_none() && rule.repo.is_none() && rule.branch.is_none() && rule.task.is_none() {
+                return Err(format!("Rule #{} needs a `command`, `exe`, `repo`, `branch` or `task` pattern, or a `when` expression", index + 1).into());
+            }
+            for exe in rule.exe.iter().flat_map(|exe| exe.as_vec()) {
+                if exe.is_empty() || exe.contains(['/', '\\']) {
+                    return Err(format!("Rule #{} has `exe = \"{}\"`, which should be a program name like \"cargo\", without its directory", index + 1, exe).into());
+                }
+            }
+            if rule.action.as_vec().is_empty() && rule.escalate.is_empty() {
+                return Err(format!("Rule #{} needs an `action` or an `escalate` tier", index + 1).into());
+            }
+            matcher::validate(rule).map_err(|e| format!("Rule #{}: {}", index + 1, e))?;
+            let actions = rule.action.as_vec().into_iter().chain(rule.escalate.iter().flat_map(|tier| tier.action.as_vec()));
+            for action in actions {
+                if let Action::Throttle { target, processes, suspend_on_start, resume_on_end } = action {
+                    match target {
+                        ThrottleTarget::List if processes.is_empty() => {
+                            return Err(format!("Rule #{} has a `throttle` action without `processes` to suspend", index + 1).into());
+                        }
+                        ThrottleTarget::SelfSpawned if !processes.is_empty() => {
+                            return Err(format!("Rule #{} has a `throttle` action with `processes`, which only go with `target = \"list\"`", index + 1).into());
+                        }
+                        _ => {}
+                    }
+                    if !suspend_on_start && !resume_on_end {
+                        return Err(format!("Rule #{} has a `throttle` action that neither suspends nor resumes", index + 1).into());
+                    }
+                }
+                if let Action::Executable { on_end: true, shared, prewarm, .. } = action {
+                    if *shared || *prewarm {
+                        return Err(format!("Rule #{} has an `exec` action with `on_end` and `shared` or `prewarm`, which keep one process for all commands", index + 1).into());
+                    }
+                }
+                if let Action::Desktop { desktop, back_to } = action {
+                    if *desktop == 0 || *back_to == Some(0) {
+                        return Err(format!("Rule #{} has a `desktop` action with desktop 0; desktops are numbered from 1", index + 1).into());
+                    }
+                }
+            }
+            if rule.only_if_cpu_above == Some(0) {
+                return Err(format!("Rule #{} needs an `only_if_cpu_above` above 0", index + 1).into());
+            }
+            for pattern in [&rule.repo, &rule.branch, &rule.task].into_iter().flatten().flat_map(|p| p.as_vec()) {
+                globset::Glob::new(pattern)
+                    .map_err(|e| format!("Rule #{} has an invalid pattern '{}': {}", index + 1, pattern, e))?;
+            }
+            if let Some(ref when) = rule.when {
+                Expr::parse(when).map_err(|e| {
+                    format!("Rule #{} has an invalid `when` expression {}:\n  {}\n  {}^",
+                            index + 1, e, when, " ".repeat(e.column.saturating_sub(1)))
+                })?;
+            }
+            if let Some(ref script) = rule.script {
+                scripting::validate(script)
+                    .map_err(|e| format!("Rule #{} has an invalid `script`: {}", index + 1, e))?;
+            }
+            if let Some(ref entries) = rule.schedule {
+                Schedule::parse(&entries.as_vec()).map_err(|e| format!("Rule #{}: {}", index + 1, e))?;
+            }
+        }
+        if let Some(ref prefix) = self.action_env_prefix {
+            action_env::validate_prefix(prefix)?;
+        }
+        for group in &self.groups {
+            if let Some(ref entries) = group.schedule {
+                Schedule::parse(&entries.as_vec()).map_err(|e| format!("Group '{}': {}", group.name, e))?;
+            }
+        }
+        if self.end_of_distraction.active_percent.is_some_and(|percent| percent > 100) {
+            return Err("`[end_of_distraction] active_percent` must be at most 100".into());
+        }
+        for (index, sink) in self.sinks.iter().enumerate() {
+            match sink.target {
+                SinkTarget::Webhook { ref url, .. } => {
+                    if !url.starts_with("http://") && !url.starts_with("https://") {
+                        return Err(format!("Sink #{} needs an http:// or https:// `url`, got '{}'", index + 1, url).into());
+                    }
+                }
+                SinkTarget::Mqtt { ref connection, ref topic, .. } => {
+                    mqtt::validate(connection, topic)
+                        .map_err(|e| format!("Sink #{} has invalid MQTT settings: {}", index + 1, e))?;
+                }
+                SinkTarget::Socket { .. } => {}
+            }
+        }
+        if let Some(ref source) = self.mqtt_source {
+            mqtt::validate(&source.connection, &source.topic)
+                .map_err(|e| format!("Invalid `[mqtt_source]`: {}", e))?;
+        }
+        if self.usage_stats.submit {
+            if !self.usage_stats.enabled {
+                return Err("`[usage_stats] submit` needs `enabled = true` too".into());
+            }
+            match self.usage_stats.submit_url {
+                Some(ref url) if url.starts_with("https://") => {}
+                Some(ref url) => return Err(format!("`[usage_stats] submit_url` must be https://, got '{}'", url).into()),
+                None => return Err("`[usage_stats] submit` needs a `submit_url` to send the counts to".into()),
+            }
+        }
+        if cfg!(not(feature = "sqlite")) && self.storage.backend == Some(StorageBackend::Sqlite) {
+            return Err("`[storage] backend = \"sqlite\"` needs a build with the `sqlite` feature".into());
+        }
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let content = toml::to_string_pretty(self)?;
@@ -75,18 +1029,198 @@ impl Config {
     }
 
     pub fn save_with_comments<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let content = r#"# VibeRot Configuration File
-# 
-# This file defines rules for intercepting and handling commands.
-# Each rule consists of command pattern(s) and action(s) to execute.
+        let content = t(Msg::ConfigHeader).to_string() + r#"
+# Global Configuration:
+# viberot_home = "/custom/path/to/viberot"  # Optional: Override where the bundled actions are found
+#                                           # If not set, uses VIBEROT_HOME env var, VIBEROT_PREFIX,
+#                                           # the install next to the executable or the checkout
+# action_env_prefix = "VIBEROT"             # Optional: Prefix of the env vars passed to actions
+#                                           # (run `viberot-service env-schema` to list them)
+# locale = "de"                             # Optional: Language of prompts and notices (en, de, es)
+#                                           # If not set, detected from LANG / the system locale
+# Durations are written like "90s", "500ms", "5m", "1h30m" or "2d"; a bare number is seconds
+# latency_budget = "2s"                     # Optional: Warn when an action takes longer than this
+#                                           # to show up after its command started
+# match_budget = "50ms"                     # Optional: Warn when matching a command against the
+#                                           # rules takes longer than this (default 50ms)
+# recent_events = 200                       # Optional: How many commands `viberot-service recent`
+#                                           # can show (0 turns it off)
+# explain = true                            # Optional: Also record the checks each rule passed
+#                                           # and the actions chosen, for `viberot-service explain`
+# daily_action_budget = "45m"               # Optional: How long actions may run a day before
+#                                           # they're swapped for a notification; rules can set
+#                                           # their own too
+# max_event_age = "10s"                     # Optional: Don't start actions for commands reported
+#                                           # this late, e.g. after a hiccup ("0" turns it off)
+# spawn_timeout = "5s"                      # Optional: Give up on starting an action's program
+#                                           # after this long, e.g. on a hung network share
+# missing_action = "notify"                 # Optional: Show a notification in place of actions
+#                                           # whose program isn't there, like the overlay before
+#                                           # `viberot-service setup-actions` ("skip" by default)
+# noisy_rule_threshold = 30                 # Optional: Warn about rules matching more commands than
+#                                           # this a minute (0 turns it off); `log = "off"` on a
+#                                           # rule keeps its matches out of the log
+# action_state_retention = "30d"            # Optional: Remove the state directory of an action
+#                                           # ($VIBEROT_STATE_DIR) after this long without use
+#                                           # ("0" keeps them)
+
+# Shell probe sockets (Linux/macOS). Useful when terminals run inside containers or sandboxes
+# that can't see $XDG_RUNTIME_DIR. The shell hooks use the first socket that exists.
+# [shell]
+# socket_paths = ["/run/user/1000/viberot-shell.sock", "/home/me/.var/app/viberot-shell.sock"]
+# tcp_port = 47913  # Localhost fallback used automatically by shells inside Flatpak/Snap/containers
+
+# ETW probe (Windows). Reading each process's working directory lets rules use `cwd` and the
+# repository/language tags, at the cost of a few system calls per process.
+# [etw]
+# working_directory = true
+# Processes npm/yarn/pnpm/bun scripts start get `lifecycle` (`postinstall`, `build`, ...) and
+# `package` tags: `when = "lifecycle == 'postinstall'"`
+# package_scripts = true
+# Processes started from these apps, directly or through wrappers, get a `parent_app` tag, so
+# rules can match IDE builds whatever their command line looks like:
+# `when = "parent_app == 'code.exe' && image == 'cargo.exe'"`
+# parent_apps = ["code.exe", "rider64.exe"]
+
+# Taskbar/dock progress and do-not-disturb sync while a matched command runs:
+# [desktop_integration]
+# taskbar_progress = true
+# launcher_desktop_file = "org.gnome.Terminal.desktop"  # Linux only: which launcher icon shows progress
+# respect_dnd = true                # Skip actions while do-not-disturb / Focus Assist is on
+# enable_dnd_while_running = true   # GNOME only: turn on do-not-disturb while matched commands run
+# terminal_progress = true          # Progress in the terminal's tab/taskbar button (OSC 9;4), e.g. in
+#                                   # Windows Terminal, ConEmu or WezTerm; needs the shell hooks
+
+# Events are tagged with the git repo and branch, project language, task runner task and command
+# category (build, test, install, deploy), usable in `when` expressions, e.g. "category == 'test'".
+# [enrichment]
+# disabled = ["language"]  # Skip enrichers you don't need: "git", "language", "task", "category"
+
+# atuin (https://atuin.sh): its recorded durations fill in for commands VibeRot hasn't timed yet,
+# and its history can stand in for the shell hooks.
+# [atuin]
+# db_path = "/home/me/.local/share/atuin/history.db"  # Optional: Defaults to atuin's own
+# probe = true                                        # Take commands from atuin instead of the shell hooks
+# skip_durations = true                               # Don't use atuin's durations
+
+# Screen capture safe mode: while OBS streams/records or a capture app runs, matched actions are
+# replaced by these (or suppressed if there are none). Needs obs-websocket enabled in OBS
+# (Tools > WebSocket Server Settings) to tell an idle OBS from a live one.
+# [capture_safe]
+# enabled = true
+# obs_websocket = "ws://127.0.0.1:4455"  # Default
+# obs_password = "..."                   # If authentication is enabled in OBS
+# processes = ["discord"]                # Optional: more apps that mean you're sharing your screen
+# action = { type = "exec", path = "notify-send", args = ["--urgency=low", "Command running"] }
+
+# Multi-user machines: one privileged `viberot-service broker` runs the probe, and each user's
+# unprivileged service subscribes to it for the events of their own commands.
+# [broker]
+# connect = true
+# endpoint = "/run/viberot/broker.sock"  # Default; `\\.\pipe\viberot-broker` on Windows
+
+# Republish matched commands (start and end, as JSON) to other systems, e.g. home automation.
+# Environment variables of the command are left out.
+# [[sinks]]
+# type = "webhook"
+# url = "http://homeassistant.local:8123/api/webhook/viberot"
+# headers = { Authorization = "Bearer ..." }
+# rules = ["Long builds"]            # Optional: Only commands matched by these rules
 #
-# Rules support the following:
-# - Single command or array of commands
-# - Single action or array of actions
+# [[sinks]]
+# type = "socket"
+# path = "/tmp/viberot-events.sock"  # Must be listening already; a named pipe on Windows
+#
+# [[sinks]]
+# type = "mqtt"
+# url = "mqtts://broker.lan:8883"    # mqtt:// for plain TCP (port 1883 by default)
+# topic = "viberot/events"
+# username = "viberot"
+# password = "..."
+# ca_file = "/etc/ssl/lan-ca.pem"    # Optional: Verify the broker with this CA instead of the system's
+# client_cert = "client.pem"         # Optional: Client certificate authentication
+# client_key = "client.key"
 
-# Global Configuration:
-# viberot_home = "/custom/path/to/viberot"  # Optional: Override viberot installation directory
-#                                           # If not set, uses VIBEROT_HOME env var or platform defaults
+# Commands reported by other devices over MQTT, matched like local ones. Messages are JSON:
+# {"type": "started", "id": "job-42", "command": "make firmware", "host": "printer"}
+# {"type": "ended", "id": "job-42"}
+# Another VibeRot's MQTT sink can be subscribed to as well.
+# [mqtt_source]
+# url = "mqtt://broker.lan"
+# topic = "viberot/remote/#"
+# username = "viberot"               # TLS and authentication options as for sinks
+# password = "..."
+
+# Commands editors and agent plugins report over a WebSocket on localhost, ending whatever a client
+# started when its connection goes. Clients connect to the URL in ~/.viberot/.websocket and send:
+# {"type": "started", "id": "task-1", "command": "cargo test", "working_directory": "/src/app"}
+# {"type": "progress", "id": "task-1", "percent": 40, "message": "Compiling"}
+# {"type": "ended", "id": "task-1", "exit_code": 0}
+# [websocket_source]
+# port = 47914
+
+# Commands other programs that watch processes, like the .NET probe or editor extensions, report as
+# JSON lines over a named pipe on Windows or a Unix socket elsewhere. A line is a ProcessEvent when
+# a command starts, or {"ended": {"pid": 4242, "exit_code": 0}} when it ends.
+# [pipe_source]
+# path = "/run/user/1000/viberot-probe.sock"   # Default: \\.\pipe\viberot-probe on Windows,
+#                                              # viberot-probe.sock in the runtime directory elsewhere
+
+# Runs of coding agents (Gemini CLI, Cursor, Codex CLI) as commands named after the agent, with
+# `agent`, `model` and `prompt` tags. Their hooks run scripts/agent_hook.py, which finds the service
+# through ~/.viberot/.agents; see the README for the agents' settings.
+# [agent_hooks]
+# path = "/run/user/1000/viberot-agents.sock"  # Default: \\.\pipe\viberot-agents on Windows,
+#                                              # viberot-agents.sock in the runtime directory elsewhere
+
+# Audit log of every program VibeRot executes, in ~/.viberot/audit (on by default).
+# Export it with `viberot-service audit-export`.
+# [audit]
+# disabled = false
+# max_entries_per_minute = 600      # Further entries in the same minute are only counted
+# max_file_size_kb = 10240          # Rotate audit.jsonl at this size
+# max_files = 5                     # Rotated files kept
+
+# Accessibility, passed to actions as JSON in VIBEROT_ACCESSIBILITY; built-in actions follow it.
+# [accessibility]
+# skip_animated = true    # Skip actions marked `animated = true` (flashing or moving ones)
+# reduced_motion = true   # The overlay turns off page animations, transitions and smooth scrolling
+# captions = true         # Show what `speak` actions say as a notification too
+# high_contrast = true    # High-contrast overlay banners and debug display
+
+# End of distraction: while actions run, keyboard/mouse idle time is sampled every 5 seconds (never
+# what was typed). If you were still busy when the command ended, you get a banner and a sound
+# instead of the actions just closing. Needs xprintidle or GNOME on Linux.
+# [end_of_distraction]
+# enabled = true
+# active_percent = 60     # Share of the samples with input that counts as busy
+# window = "1m"           # How far back before the command ended samples count
+# sound = true
+
+# Usage stats: counts of commands per probe, rule matches and actions per type, never commands,
+# rule names or paths. Off by default; see them with `viberot-service usage`.
+# [usage_stats]
+# enabled = true          # Count, on this machine only
+# submit = true           # Also send the counts once a day, without anything identifying you
+# submit_url = "https://..."
+
+# Keeping the config the same on several machines, through a git repository set up with
+# `viberot-service config sync init <git URL>`; passwords and request headers stay on each machine:
+# [sync]
+# auto = true             # Push the config when it changes, pull it when the service starts
+
+# Where command durations, streaks and recent commands are kept, read when the service starts:
+# [storage]
+# backend = "sqlite"      # "file" (default): JSON files in ~/.viberot; "sqlite": every command in
+#                         # one database; "memory": nothing written to disk
+# path = "${HOME}/viberot.db"   # Database of the "sqlite" backend (default ~/.viberot/viberot.db)
+
+# The overlay's default page, served on 127.0.0.1 so it works offline: the command's progress and
+# the images and videos in a folder, taking turns:
+# [waiting_room]
+# enabled = false         # Don't serve it; the overlay opens TikTok instead
+# port = 47613
+# memes_dir = "${HOME}/Pictures/memes"   # Default ~/.viberot/memes
 
 # Example configuration structures:
 
@@ -102,6 +1236,8 @@ impl Config {
 # Rule with multiple commands mapping to the same action:
 # [[rules]]
 # command = ["*cargo.exe build*", "*cargo.exe check*", "*cargo.exe test*"]
+# enabled = false  # Optional: Keep the rule without it matching anything
+# log = "debug"     # Optional: Log its matches only with RUST_LOG=debug, or not at all with "off"
 # [rules.action]
 # type = "exec"
 # path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay.exe"
@@ -116,14 +1252,195 @@ impl Config {
 #   { type = "exec", path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay.exe", args = ["--exit-on-stdin-close"], single_instance = true }
 # ]
 
-# Rule with multiple commands mapping to multiple actions:
+# Rule with multiple commands mapping to multiple actions, ignoring case (`NPM.CMD install`):
 # [[rules]]
 # command = ["*npm* install*", "*yarn* install*", "*pnpm* install*"]
+# case_insensitive = true
 # action = [
 #   { type = "exec", path = "python", args = ["scripts/package-install-notify.py"] },
 #   { type = "exec", path = "notepad.exe", args = ["package-install-log.txt"] }
 # ]
 
+# Rule matching the program a command runs and its arguments, so `echo "cargo build"` doesn't match:
+# [[rules]]
+# exe = "cargo"
+# args_contains = ["build"]
+# action = { type = "notify" }
+
+# Rule matching its commands with a regular expression instead of globs; `matcher` can also be
+# "expr" (commands given as expressions, like `when`) or "custom:<name>":
+# [[rules]]
+# command = '^(cargo|cross)\s+(build|test)\b'
+# matcher = "regex"
+# action = { type = "notify" }
+
+# Rule with a condition expression instead of (or in addition to) a glob:
+# [[rules]]
+# when = "cmd.exe == 'cargo' && args.contains('build') && !args.contains('--offline')"
+# [rules.action]
+# type = "exec"
+# path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay"
+# args = ["--exit-on-stdin-close"]
+#
+# Available variables: cmd.exe, cmd.line, args, cwd, probe, duration_estimate (seconds)
+# Methods: contains, starts_with, ends_with, matches (glob), len
+# Durations can be written as 90s, 5m or 1h
+
+# Rule limited to some repositories and branches (read from the command's working directory):
+# [[rules]]
+# command = "*cargo build*"
+# repo = ["github.com/me/*", "gitlab.com/me/*"]  # Remote `host/owner/name`, or the directory name
+# branch = ["main", "feature/*"]                 # Optional
+# dirty = true                                   # Optional: only with uncommitted changes
+# [rules.action]
+# type = "exec"
+# path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay"
+# args = ["--exit-on-stdin-close"]
+#
+# Repository state is also available to `when` expressions (`remote`, `branch`, `dirty`) and to
+# actions (VIBEROT_REPO, VIBEROT_REMOTE, VIBEROT_BRANCH, VIBEROT_DIRTY).
+
+# Rule for a task, however it's started (`just deploy`, `make deploy`, `npm run deploy`, ...):
+# [[rules]]
+# task = ["deploy", "release-*"]
+# action = { type = "notify" }
+#
+# Tasks are also available to `when` expressions (`task`, `task_runner`, `task_command`) and to
+# actions (VIBEROT_TASK, VIBEROT_TASK_RUNNER).
+
+# Rule that says out loud when a command is done, e.g. "cargo build finished after 3 minutes, it failed":
+# [[rules]]
+# command = "*cargo build*"
+# action = { type = "speak" }  # Optional: text = "{command} is done, {outcome}", voice = "..."
+
+# Rule that rings the terminal's bell when a command is done, showing the text in tmux or as the
+# terminal's notification:
+# [[rules]]
+# command = "*cargo test*"
+# action = { type = "bell" }  # Optional: text = "{command} is done, {outcome}", bell = false
+
+# Rule that pauses a game and OBS while a build runs, resuming them when it ends; `target =
+# "self-spawned"` suspends VibeRot's idle prewarmed actions instead:
+# [[rules]]
+# exe = "cargo"
+# args_contains = ["build"]
+# action = { type = "throttle", processes = ["obs64", "game.exe"] }
+
+# Rule that moves to virtual desktop 3 while a build runs, so the overlay isn't on top of the
+# editor, and back when it ends (on macOS, add `back_to = 1`):
+# [[rules]]
+# command = "*cargo build*"
+# action = [
+#     { type = "desktop", desktop = 3 },
+#     { type = "exec", path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay", args = ["--exit-on-stdin-close"] },
+# ]
+
+# Actions defined once and shared by rules, which refer to them by name; `use` overrides some keys:
+# [actions.overlay]
+# type = "exec"
+# path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay"
+# args = ["--exit-on-stdin-close"]
+# single_instance = true
+#
+# [[rules]]
+# command = ["*cargo build*", "*cargo test*"]
+# action = ["overlay", { use = "overlay", args = ["--exit-on-stdin-close", "--pip"] }]
+#
+# `with` fills `{name}` placeholders in a rule's actions, e.g. for
+# `[actions.feed] ... args = ["--exit-on-stdin-close", "--url", "{url}"]`:
+# [[rules]]
+# command = "*docker build*"
+# action = "feed"
+# with = { url = "https://www.youtube.com/shorts" }
+
+# Rule that escalates the longer a command runs:
+# [[rules]]
+# command = ["*cargo build*", "*npm run build*"]
+# action = { type = "exec", path = "notify-send", args = ["Build started"] }  # Optional, runs right away
+# [[rules.escalate]]
+# after = "1m"
+# action = { type = "exec", path = "notify-send", args = ["Still building..."] }
+# [[rules.escalate]]
+# after = "5m"
+# action = { type = "exec", path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay", args = ["--exit-on-stdin-close"] }
+# [[rules.escalate]]
+# after = "20m"
+# action = { type = "exec", path = "python", args = ["scripts/telegram-notify.py"] }
+#
+# Tiers only fire while the command is still running, and their actions stop with it like any other.
+
+# Groups of rules sharing defaults, turned on and off together (`rules disable --group docker`):
+# [[groups]]
+# name = "rust"
+# action = "overlay"                  # For rules of the group without an action of their own
+# min_duration = "20s"                # Only once the command has run this long
+# schedule = "mon-fri 09:00-18:00"    # Only at these times, local time; a list for several
+# [[groups]]
+# name = "docker"
+# enabled = false                     # Turns all its rules off
+# action = { type = "notify", title = "Docker is on it" }
+# cooldown = "10m"                    # Not again for this long once it set off its actions
+# [[rules]]
+# group = "rust"
+# exe = "cargo"
+# args_contains = ["build"]
+# [[rules]]
+# group = "rust"
+# exe = "cargo"
+# args_contains = ["test"]
+# min_duration = "1m"                 # A rule's own setting wins over its group's
+# [[rules]]
+# group = "docker"
+# command = ["*docker build*", "*docker compose up*"]
+
+# Rules for a command that may get stuck, run as `viberot-service wrap --detect-stall -- docker pull ...`:
+# [[rules]]
+# command = "*docker pull*"
+# action = { type = "exec", path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay", args = ["--exit-on-stdin-close"] }
+# [[rules]]
+# command = "*docker pull*"
+# stalled = true  # Once it printed nothing for 30s (`--stall-after`), until it prints again
+# action = { type = "notify", title = "docker pull looks stuck" }
+
+# Rule for each rebuild of a watch-mode command, run as `viberot-service wrap --cycles -- cargo watch -x build`:
+# [[rules]]
+# command = "*cargo watch*"
+# mode = "session"  # Each rebuild, not the watcher's start; the actions stop when the rebuild finishes
+# action = { type = "exec", path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay", args = ["--exit-on-stdin-close"] }
+
+# Rule that waits until the command is actually busy, so one stuck on a prompt (a credentials
+# helper, a passphrase) doesn't start anything:
+# [[rules]]
+# command = "*cargo build*"
+# only_if_cpu_above = 50  # Percent of a core (100 = one full core) for a couple of seconds
+# [rules.action]
+# type = "exec"
+# path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay"
+# args = ["--exit-on-stdin-close"]
+#
+# Escalation tiers wait for it too. Shell commands are sampled together with the shell running them.
+#
+# Or wait for any work at all, disk I/O included, so an `rsync` copying files counts but one whose
+# ssh asks for a passphrase doesn't (and the prompt isn't hidden behind the overlay):
+# [[rules]]
+# command = "rsync *"
+# only_if = "busy"
+# action = { type = "exec", path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay", args = ["--exit-on-stdin-close"] }
+
+# Rule with a Lua hook that can veto the match or rewrite the actions:
+# [[rules]]
+# command = "*cargo build*"
+# script = """
+# if event.working_directory and event.working_directory:find("/work/") then
+#   return false  -- no brainrot at work
+# end
+# actions[1].args = { "--exit-on-stdin-close", "--url", "https://www.youtube.com/shorts" }
+# return actions
+# """
+# [rules.action]
+# type = "exec"
+# path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay"
+
 # Alternative syntax using aliases:
 # [[rules]]
 # commands = ["*git* push*", "*git* pull*"]  # "commands" is an alias for "command"
@@ -131,6 +1448,79 @@ impl Config {
 #   { type = "exec", path = "python", args = ["scripts/git-notify.py"] }
 # ]
 
+# One overlay process for all concurrent builds, with a window per build:
+# [[rules]]
+# command = ["*cargo build*", "*npm run build*"]
+# [rules.action]
+# type = "exec"
+# path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay"
+# args = ["--no-window", "--exit-on-stdin-close"]
+# shared = true
+#
+# A shared action is started for the first matched command and receives
+# {"cmd": "open", "id": "<event id>"} / {"cmd": "close", "id": "<event id>"} lines on stdin as
+# commands start and end. It is stopped when the last one ends. Its environment describes the
+# first command. `shared` takes precedence over `single_instance`.
+
+# Start the overlay hidden when the service starts, so it shows up without the webview cold start:
+# [[rules]]
+# command = "*cargo build*"
+# [rules.action]
+# type = "exec"
+# path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay"
+# args = ["--prewarm", "--exit-on-stdin-close"]
+# prewarm = true
+#
+# Prewarmed actions work like shared ones, but receive {"cmd": "attach", ...} / {"cmd": "detach", ...}
+# and keep running while no command is. They only get VIBEROT_HOME in their environment, since
+# they start before any command.
+
+# Log every long build once it's done. `on_end = true` starts the action when the command ends,
+# with {"elapsed_secs": 95, "exit_code": 0, "rule": "Long builds"} as its last argument:
+# [[rules]]
+# name = "Long builds"
+# command = "*cargo build*"
+# [rules.action]
+# type = "exec"
+# path = "python"
+# args = ["scripts/log-build.py"]
+# on_end = true
+#
+# Actions started when a command starts get the same summary as a last line on stdin before it
+# closes: {"cmd": "end", "id": "<event id>", "elapsed_secs": ..., "exit_code": ..., "rule": ...}
+
+# Keep the overlay resident with the page preloaded, and only show it while commands run:
+# [[rules]]
+# command = "*cargo build*"
+# [rules.action]
+# type = "attach"
+# path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay"  # Launched if not running yet
+# args = ["--daemon"]
+# socket = "/run/user/1000/viberot-overlay.sock"  # Optional, must match the daemon's --socket
+
+# Show a notification. On Windows it's a toast with buttons to open a terminal in the command's
+# directory, snooze the rule for an hour, or dismiss (stop the command's actions):
+# [[rules]]
+# name = "Long builds"
+# command = "*cargo build*"
+# [rules.action]
+# type = "notify"
+# title = "Building..."                    # Optional: Defaults to the rule name
+# message = "Go touch grass"               # Optional: Defaults to the command line
+# buttons = ["open_terminal", "snooze", "dismiss"]  # Default
+
+# Run an action sandboxed: read-only filesystem (except /tmp) and no network.
+# Linux needs bubblewrap (`bwrap`) installed; the action doesn't run if the sandbox can't be set up.
+# [[rules]]
+# command = "*cargo build*"
+# [rules.action]
+# type = "exec"
+# path = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay"
+# args = ["--exit-on-stdin-close"]
+# sandbox = true
+# sandbox_allow_network = true                          # The overlay needs to load its website
+# sandbox_writable = ["/home/me/.local/share/viberot"]  # Optional: Paths the action may write to
+
 # Path Resolution:
 # - Executable names (e.g., "python", "notepad.exe") are found via PATH
 # - Absolute paths (e.g., "C:\path\to\action.exe") are used as-is
@@ -138,7 +1528,8 @@ impl Config {
 # - Environment variables are supported:
 #   - ${VIBEROT_HOME}: viberot project root
 #   - ${VIBEROT_ACTIONS}: viberot/actions directory
-#   - Other standard environment variables work too
+#   - Other environment variables work in any string of the config, e.g. tokens:
+#     password = "${MQTT_PASSWORD}"; write $${ for a literal ${
 
 # Single command and single action
 [[rules]]
@@ -179,6 +1570,12 @@ impl Commands {
     }
 }
 
+impl Default for Actions {
+    fn default() -> Self {
+        Actions::Multiple(Vec::new())
+    }
+}
+
 impl Actions {
     pub fn as_vec(&self) -> Vec<&Action> {
         match self {
@@ -186,20 +1583,276 @@ impl Actions {
             Actions::Multiple(actions) => actions.iter().collect(),
         }
     }
-    
-    pub fn into_vec(self) -> Vec<Action> {
-        match self {
-            Actions::Single(action) => vec![action],
-            Actions::Multiple(actions) => actions,
+}
+
+/// Replaces references to named actions in rules, escalation tiers and `[capture_safe]` with their
+/// definition under `[actions.<name>]`. A reference is the name, `"overlay"`, or a table naming it
+/// with `use` whose other keys override the definition's, `{ use = "overlay", args = ["--mute"] }`.
+/// Returns whether there were any.
+fn resolve_named_actions(document: &mut toml::Table) -> Result<bool, String> {
+    let named = match document.get("actions") {
+        Some(toml::Value::Table(named)) => named.clone(),
+        Some(_) => return Err("`actions` must be a table of named actions, like [actions.overlay]".into()),
+        None => toml::Table::new(),
+    };
+    let mut resolved = false;
+    let mut resolve = |slot: &mut toml::Table, place: &str| -> Result<(), String> {
+        // `actions` is an alias of `action`
+        for key in ["action", "actions"] {
+            let Some(actions) = slot.get_mut(key) else {
+                continue;
+            };
+            let entries: Vec<&mut toml::Value> = match actions {
+                toml::Value::Array(entries) => entries.iter_mut().collect(),
+                entry => vec![entry],
+            };
+            for entry in entries {
+                let (name, overrides) = match entry {
+                    toml::Value::String(name) => (name.clone(), toml::Table::new()),
+                    toml::Value::Table(table) if table.contains_key("use") => {
+                        let mut overrides = table.clone();
+                        let Some(toml::Value::String(name)) = overrides.remove("use") else {
+                            return Err(format!("{} has a `use` that isn't an action name", place));
+                        };
+                        (name, overrides)
+                    }
+                    _ => continue,
+                };
+                let Some(toml::Value::Table(definition)) = named.get(&name) else {
+                    return Err(format!("{} refers to action '{}', which isn't defined under [actions.{}]", place, name, name));
+                };
+                let mut action = definition.clone();
+                action.extend(overrides);
+                *entry = toml::Value::Table(action);
+                resolved = true;
+            }
+        }
+        Ok(())
+    };
+
+    if let Some(toml::Value::Array(rules)) = document.get_mut("rules") {
+        for (index, rule) in rules.iter_mut().enumerate() {
+            let toml::Value::Table(rule) = rule else {
+                continue;
+            };
+            resolve(rule, &format!("Rule #{}", index + 1))?;
+            if let Some(toml::Value::Array(tiers)) = rule.get_mut("escalate") {
+                for tier in tiers.iter_mut().filter_map(toml::Value::as_table_mut) {
+                    resolve(tier, &format!("An escalation tier of rule #{}", index + 1))?;
+                }
+            }
+        }
+    }
+    if let Some(toml::Value::Array(groups)) = document.get_mut("groups") {
+        for (index, group) in groups.iter_mut().enumerate() {
+            if let toml::Value::Table(group) = group {
+                resolve(group, &format!("Group #{}", index + 1))?;
+            }
         }
     }
+    if let Some(toml::Value::Table(capture_safe)) = document.get_mut("capture_safe") {
+        resolve(capture_safe, "[capture_safe]")?;
+    }
+    Ok(resolved)
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            rules: vec![],
-            viberot_home: None,
+/// Rule keys a `[[groups]]` entry sets for its rules
+const GROUP_DEFAULTS: &[&[&str]] = &[&["action", "actions"], &["cooldown"], &["schedule"], &["min_duration"]];
+
+/// Gives rules with a `group` the defaults of that `[[groups]]` entry for the keys they don't set
+/// themselves, and turns them off with it. Returns whether any rule has a group.
+fn apply_groups(document: &mut toml::Table) -> Result<bool, String> {
+    let mut groups: BTreeMap<String, toml::Table> = BTreeMap::new();
+    match document.get("groups") {
+        Some(toml::Value::Array(entries)) => {
+            for (index, entry) in entries.iter().enumerate() {
+                let Some(group) = entry.as_table() else {
+                    return Err("`groups` must be written as [[groups]] tables".into());
+                };
+                let Some(toml::Value::String(name)) = group.get("name") else {
+                    return Err(format!("Group #{} needs a `name`", index + 1));
+                };
+                if groups.insert(name.clone(), group.clone()).is_some() {
+                    return Err(format!("There are two groups named '{}'", name));
+                }
+            }
+        }
+        Some(_) => return Err("`groups` must be written as [[groups]] tables".into()),
+        None => {}
+    }
+
+    let mut grouped = false;
+    let Some(toml::Value::Array(rules)) = document.get_mut("rules") else {
+        return Ok(false);
+    };
+    for (index, rule) in rules.iter_mut().enumerate() {
+        let toml::Value::Table(rule) = rule else {
+            continue;
+        };
+        let name = match rule.get("group") {
+            Some(toml::Value::String(name)) => name,
+            Some(_) => return Err(format!("Rule #{} has a `group` that isn't a group name", index + 1)),
+            None => continue,
+        };
+        let Some(group) = groups.get(name) else {
+            return Err(format!("Rule #{} is in group '{}', which isn't defined under [[groups]]", index + 1, name));
+        };
+        for keys in GROUP_DEFAULTS {
+            if keys.iter().any(|key| rule.contains_key(*key)) {
+                continue;
+            }
+            if let Some((key, value)) = keys.iter().find_map(|key| Some((*key, group.get(*key)?))) {
+                rule.insert(key.to_string(), value.clone());
+            }
+        }
+        if group.get("enabled").and_then(toml::Value::as_bool) == Some(false) {
+            rule.insert("enabled".to_string(), toml::Value::Boolean(false));
         }
+        grouped = true;
     }
-}
\ No newline at end of file
+    Ok(grouped)
+}
+
+/// Keys holding code rather than text, which `${...}` isn't filled in for: Lua and `when` expressions
+const CODE_KEYS: [&str; 2] = ["script", "when"];
+
+/// Fills `${NAME}` in every string of the config from the service's environment, `$${` being a
+/// literal `${`. The service's own variables (`${VIBEROT_ACTIONS}`, and the ones actions get) are
+/// left for later, and so is shell syntax like `${1}` and anything under `CODE_KEYS`. Returns
+/// whether anything was filled in.
+fn interpolate_env(document: &mut toml::Table) -> Result<bool, String> {
+    let mut own_prefixes = vec![format!("{}_", action_env::DEFAULT_PREFIX)];
+    if let Some(toml::Value::String(prefix)) = document.get("action_env_prefix") {
+        action_env::validate_prefix(prefix)?;
+        own_prefixes.push(format!("{}_", prefix));
+    }
+    let mut changed = false;
+    for (key, value) in document.iter_mut() {
+        changed |= interpolate_value(value, key, &own_prefixes)?;
+    }
+    Ok(changed)
+}
+
+fn interpolate_value(value: &mut toml::Value, path: &str, own_prefixes: &[String]) -> Result<bool, String> {
+    let mut changed = false;
+    match value {
+        toml::Value::String(text) => {
+            let filled = interpolate(text, own_prefixes)
+                .map_err(|name| format!("`{}` uses ${{{}}}, which isn't set in the service's environment", path, name))?;
+            if let Some(filled) = filled {
+                *text = filled;
+                changed = true;
+            }
+        }
+        toml::Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                changed |= interpolate_value(item, &format!("{}[{}]", path, index), own_prefixes)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (key, item) in table.iter_mut().filter(|(key, _)| !CODE_KEYS.contains(&key.as_str())) {
+                changed |= interpolate_value(item, &format!("{}.{}", path, key), own_prefixes)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(changed)
+}
+
+/// `text` with its `${NAME}` filled in, None if there was nothing to fill in; the name of the
+/// first variable that isn't set otherwise
+fn interpolate(text: &str, own_prefixes: &[String]) -> Result<Option<String>, String> {
+    if !text.contains("${") {
+        return Ok(None);
+    }
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            filled.push_str(&rest[..start - 1]);
+            filled.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        filled.push_str(&rest[..start]);
+        let Some(length) = rest[start..].find('}') else {
+            // Never closed, kept as written
+            filled.push_str(&rest[start..]);
+            return Ok((filled != text).then_some(filled));
+        };
+        let reference = &rest[start..=start + length];
+        let name = &reference[2..reference.len() - 1];
+        let is_variable = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_variable || own_prefixes.iter().any(|prefix| name.starts_with(prefix.as_str())) {
+            filled.push_str(reference);
+        } else {
+            filled.push_str(&std::env::var(name).map_err(|_| name.to_string())?);
+        }
+        rest = &rest[start + length + 1..];
+    }
+    filled.push_str(rest);
+    Ok((filled != text).then_some(filled))
+}
+
+/// Parses a duration like "90s", "5m", "1h30m", "500ms" or "2d"; a bare number is seconds
+pub fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(std::time::Duration::from_secs(seconds));
+    }
+    humantime::parse_duration(value)
+        .map_err(|e| format!("invalid duration '{}': {} (expected e.g. \"90s\", \"5m\" or \"1h30m\")", value, e))
+}
+
+/// A duration in the config, written like `parse_duration` takes it: "90s", "1h30m", or a bare
+/// number of seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConfigDuration(pub std::time::Duration);
+
+impl<'de> Deserialize<'de> for ConfigDuration {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = ConfigDuration;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a duration like \"90s\", \"5m\" or \"1h30m\", or a number of seconds")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<ConfigDuration, E> {
+                parse_duration(value).map(ConfigDuration).map_err(E::custom)
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, seconds: u64) -> Result<ConfigDuration, E> {
+                Ok(ConfigDuration(std::time::Duration::from_secs(seconds)))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, seconds: i64) -> Result<ConfigDuration, E> {
+                u64::try_from(seconds).map_err(|_| E::custom(format!("negative duration {}", seconds)))
+                    .and_then(|seconds| self.visit_u64(seconds))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl Serialize for ConfigDuration {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&humantime::format_duration(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolate_keeps_unterminated_reference() {
+        let own_prefixes = ["VIBEROT_".to_string()];
+        assert_eq!(interpolate("abc ${oops", &own_prefixes), Ok(None));
+        assert_eq!(interpolate("$${x} ${oops", &own_prefixes), Ok(Some("${x} ${oops".to_string())));
+    }
+}
@@ -0,0 +1,131 @@
+// Config diffs
+// When the config is reloaded, the service works out what changed: rules are told apart by name
+// (unnamed ones by their content, so editing one shows up as removed and added), settings by their
+// top-level key. Only changed rules are recompiled, and the command glob set is kept when no
+// rule's globs changed (see `RuleEngine`); running actions are left alone. The summary is logged
+// and the last one written to ~/.viberot/last_reload.json for `viberot-service status`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::audit;
+use crate::config::{Config, Rule};
+use crate::mutes::format_duration;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigDiff {
+    /// Names of the rules, as shown in logs
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+    pub unchanged: usize,
+    /// Rules kept their content but not their order, which is the order their actions start in
+    #[serde(default)]
+    pub reordered: bool,
+    /// Top-level settings that changed, e.g. `audit` or `etw`
+    pub settings: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub fn between(old: &Config, new: &Config) -> Self {
+        let mut diff = Self::default();
+
+        let old_rules: HashMap<String, (usize, &Rule)> = old.rules.iter().enumerate()
+            .map(|(index, rule)| (rule_key(rule), (index, rule)))
+            .collect();
+        let mut kept = Vec::new();
+        for (index, rule) in new.rules.iter().enumerate() {
+            match old_rules.get(&rule_key(rule)) {
+                Some((old_index, old_rule)) => {
+                    kept.push(*old_index);
+                    if old_rule.content_hash() == rule.content_hash() {
+                        diff.unchanged += 1;
+                    } else {
+                        diff.changed.push(rule.display_name(index));
+                    }
+                }
+                None => diff.added.push(rule.display_name(index)),
+            }
+        }
+        diff.reordered = kept.windows(2).any(|pair| pair[0] > pair[1]);
+        let new_keys: Vec<String> = new.rules.iter().map(rule_key).collect();
+        diff.removed = old.rules.iter().enumerate()
+            .filter(|(_, rule)| !new_keys.contains(&rule_key(rule)))
+            .map(|(index, rule)| rule.display_name(index))
+            .collect();
+
+        if let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+            (serde_json::to_value(old), serde_json::to_value(new))
+        {
+            let mut keys: Vec<&String> = old.keys().chain(new.keys()).filter(|key| *key != "rules").collect();
+            keys.sort();
+            keys.dedup();
+            diff.settings = keys.into_iter()
+                .filter(|key| old.get(*key) != new.get(*key))
+                .cloned()
+                .collect();
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() && !self.reordered
+            && self.settings.is_empty()
+    }
+
+    /// e.g. "rules: 1 changed (build), 1 added (deploy), 4 unchanged; settings: audit"
+    pub fn summary(&self) -> String {
+        if self.is_empty() {
+            return "nothing changed".to_string();
+        }
+        let mut rules = Vec::new();
+        for (names, what) in [(&self.changed, "changed"), (&self.added, "added"), (&self.removed, "removed")] {
+            if !names.is_empty() {
+                rules.push(format!("{} {} ({})", names.len(), what, names.join(", ")));
+            }
+        }
+        rules.push(format!("{} unchanged", self.unchanged));
+        if self.reordered {
+            rules.push("reordered".to_string());
+        }
+        let mut summary = format!("rules: {}", rules.join(", "));
+        if !self.settings.is_empty() {
+            summary.push_str(&format!("; settings: {}", self.settings.join(", ")));
+        }
+        summary
+    }
+}
+
+/// What tells a rule apart across reloads
+fn rule_key(rule: &Rule) -> String {
+    match &rule.name {
+        Some(name) => format!("name:{}", name),
+        None => format!("content:{:x}", rule.content_hash()),
+    }
+}
+
+/// The last reload, as written by the service
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Reload {
+    /// Unix timestamp
+    pub at: u64,
+    pub diff: ConfigDiff,
+}
+
+pub fn save(path: &Path, diff: &ConfigDiff) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let reload = Reload { at: audit::unix_now(), diff: diff.clone() };
+    std::fs::write(path, serde_json::to_string_pretty(&reload)?)?;
+    Ok(())
+}
+
+/// The last reload of the running service, if there was one
+pub fn load(path: &Path) -> Option<Reload> {
+    serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+/// Human-readable form of the last reload for `viberot-service status`
+pub fn to_text(reload: &Reload) -> String {
+    let ago = format_duration(audit::unix_now().saturating_sub(reload.at));
+    format!("Config last reloaded {} ago: {}\n", ago, reload.diff.summary())
+}
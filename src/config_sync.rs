@@ -0,0 +1,437 @@
+// Moving the config between machines
+// `viberot-service config export --bundle out.tar` packs the config, named actions included, into
+// a tar bundle with a manifest of where it came from; `config import out.tar` puts it in place.
+// Secrets stay behind: `password`s and request `headers` are left out of bundles unless they're
+// `${VAR}` references, and an import keeps the ones this machine's config has, for entries like
+// sinks matched by their type and address rather than their position.
+// `config sync` does the same through a git repository cloned to ~/.viberot/sync: `push` commits
+// the config and pushes it, `pull` pulls and imports what the other machines pushed. With
+// `[sync] auto = true` the service pushes whenever the config changes and pulls when it starts;
+// `[sync]` itself is up to each machine, so it's never shared or replaced.
+// Everything goes through the `git` command, with the user's credentials and never a prompt.
+
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use toml_edit::{DocumentMut, Item, TableLike, Value};
+use tracing::{debug, info, warn};
+
+use crate::platform::Provenance;
+use crate::rule_editor;
+use crate::runtime_paths;
+
+/// Keys whose values are secrets wherever they are, besides everything under `headers`
+const SECRET_KEYS: &[&str] = &["password", "obs_password"];
+/// Keys that tell the entries of an array apart, e.g. sinks, so their secrets follow them when the
+/// array is reordered
+const IDENTITY_KEYS: &[&str] = &["type", "name", "url", "host", "path"];
+/// Tables that are about this machine, so they're neither shared nor replaced
+const LOCAL_TABLES: &[&str] = &["sync"];
+/// The config in bundles and the sync repository
+const CONFIG_FILE: &str = "config.toml";
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Where a secret is in the config, e.g. `["sinks", 0, "headers", "Authorization"]`
+type SecretPath = Vec<serde_json::Value>;
+
+#[derive(Subcommand)]
+pub enum SyncCommand {
+    /// Clone the repository the config is synced through, then pull the config from it, or push
+    /// this machine's if it has none yet
+    Init {
+        /// Git URL, e.g. git@github.com:me/viberot-config.git
+        remote: String,
+    },
+    /// Commit the config, if it changed, and push it
+    Push,
+    /// Pull the config the other machines pushed and put it in place
+    Pull,
+}
+
+/// Where a bundle came from and what it leaves out
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    /// Version of VibeRot that exported it
+    version: String,
+    hostname: String,
+    /// Seconds since the Unix epoch
+    exported_at: u64,
+    /// The secrets left out
+    #[serde(default)]
+    secrets: Vec<SecretPath>,
+}
+
+/// Writes the config, without its secrets, to a bundle
+pub fn export(config_path: &Path, bundle: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (content, secrets) = shareable_config(config_path)?;
+    let manifest = Manifest {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        hostname: Provenance::local().hostname,
+        exported_at: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+        secrets,
+    };
+    let file = std::fs::File::create(bundle).map_err(|e| format!("Failed to create {}: {}", bundle.display(), e))?;
+    let mut builder = tar::Builder::new(file);
+    for (name, data) in [(CONFIG_FILE, content.into_bytes()), (MANIFEST_FILE, serde_json::to_vec_pretty(&manifest)?)] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(manifest.exported_at);
+        header.set_cksum();
+        builder.append_data(&mut header, name, data.as_slice())?;
+    }
+    builder.into_inner()?;
+
+    println!("Exported {} to {}", config_path.display(), bundle.display());
+    if !manifest.secrets.is_empty() {
+        println!("Left out {} secret(s): {}", manifest.secrets.len(), describe_paths(&manifest.secrets));
+    }
+    Ok(())
+}
+
+/// Puts the config of a bundle in place, keeping this machine's secrets
+pub fn import(config_path: &Path, bundle: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let file = std::fs::File::open(bundle).map_err(|e| format!("Failed to open {}: {}", bundle.display(), e))?;
+    let mut archive = tar::Archive::new(file);
+    let mut content = None;
+    let mut manifest = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let slot = match name.as_str() {
+            CONFIG_FILE => &mut content,
+            MANIFEST_FILE => &mut manifest,
+            _ => {
+                debug!("Skipping {} in {}", name, bundle.display());
+                continue;
+            }
+        };
+        let mut data = String::new();
+        entry.read_to_string(&mut data)?;
+        *slot = Some(data);
+    }
+    let content = content.ok_or_else(|| format!("{} isn't a config bundle: it has no {}", bundle.display(), CONFIG_FILE))?;
+    if let Some(manifest) = manifest.and_then(|manifest| serde_json::from_str::<Manifest>(&manifest).ok()) {
+        println!("Importing the config exported from {} by VibeRot {}", manifest.hostname, manifest.version);
+    }
+    match install(config_path, &content)? {
+        Some(dropped) => {
+            println!("Imported {} into {}; the running service reloads the config on its own", bundle.display(), config_path.display());
+            print_dropped(&dropped);
+        }
+        None => println!("{} already has the config of {}", config_path.display(), bundle.display()),
+    }
+    Ok(())
+}
+
+pub fn run(command: SyncCommand, config_path: &Path, sync_dir: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let pull_now = match command {
+        SyncCommand::Init { remote } => {
+            if sync_dir.join(".git").is_dir() {
+                return Err(format!("{} is a sync repository already; remove it to sync through another one", sync_dir.display()).into());
+            }
+            git(None, &["clone", "--quiet", &remote, &sync_dir.to_string_lossy()])?;
+            println!("Cloned {} to {}", remote, sync_dir.display());
+            sync_dir.join(CONFIG_FILE).is_file()
+        }
+        SyncCommand::Push => false,
+        SyncCommand::Pull => true,
+    };
+    if pull_now {
+        match pull(config_path, sync_dir)? {
+            Some(dropped) => {
+                println!("Pulled the config into {}; the running service reloads it on its own", config_path.display());
+                print_dropped(&dropped);
+            }
+            None => println!("The config is up to date"),
+        }
+        return Ok(());
+    }
+    match push(config_path, sync_dir)? {
+        Some(secrets) => {
+            println!("Pushed the config");
+            if !secrets.is_empty() {
+                println!("Left out {} secret(s): {}", secrets.len(), describe_paths(&secrets));
+            }
+        }
+        None => println!("The synced config is up to date"),
+    }
+    Ok(())
+}
+
+/// Commits the config to the sync repository if it changed, and pushes it. Returns the secrets
+/// left out, or None if there was nothing to push
+pub fn push(config_path: &Path, sync_dir: &Path) -> Result<Option<Vec<SecretPath>>, Box<dyn std::error::Error + Send + Sync>> {
+    check_repository(sync_dir)?;
+    let (content, secrets) = shareable_config(config_path)?;
+    std::fs::write(sync_dir.join(CONFIG_FILE), content)?;
+    git(Some(sync_dir), &["add", CONFIG_FILE])?;
+    if git(Some(sync_dir), &["diff", "--cached", "--quiet"]).is_ok() {
+        return Ok(None);
+    }
+    let message = format!("Update the config from {}", Provenance::local().hostname);
+    git(Some(sync_dir), &["commit", "--quiet", "-m", &message])?;
+    if has_upstream(sync_dir) {
+        // Whatever the other machines pushed first goes underneath
+        pull_rebase(sync_dir)?;
+        git(Some(sync_dir), &["push", "--quiet"])?;
+    } else {
+        // The first push to an empty repository
+        git(Some(sync_dir), &["push", "--quiet", "--set-upstream", "origin", "HEAD"])?;
+    }
+    Ok(Some(secrets))
+}
+
+/// Pulls the sync repository and puts its config in place. Returns the secrets dropped with their
+/// entries, or None if the config didn't change
+pub fn pull(config_path: &Path, sync_dir: &Path) -> Result<Option<Vec<SecretPath>>, Box<dyn std::error::Error + Send + Sync>> {
+    check_repository(sync_dir)?;
+    if has_upstream(sync_dir) {
+        pull_rebase(sync_dir)?;
+    }
+    let content = match std::fs::read_to_string(sync_dir.join(CONFIG_FILE)) {
+        Ok(content) => content,
+        // Nothing was pushed yet
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    install(config_path, &content)
+}
+
+/// Pushes the config after it changed, for `[sync] auto`
+pub fn auto_push(config_path: PathBuf, sync_dir: PathBuf) {
+    tokio::task::spawn_blocking(move || match push(&config_path, &sync_dir) {
+        Ok(Some(_)) => info!("Pushed the changed config to {}", sync_dir.display()),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to sync the changed config: {}", e),
+    });
+}
+
+/// Pulls the config when the service starts, for `[sync] auto`
+pub fn auto_pull(config_path: PathBuf, sync_dir: PathBuf) {
+    tokio::task::spawn_blocking(move || match pull(&config_path, &sync_dir) {
+        Ok(Some(dropped)) => {
+            info!("Pulled a newer config from {}", sync_dir.display());
+            if !dropped.is_empty() {
+                warn!("Dropped {} secret(s) whose entries the pulled config no longer has: {}", dropped.len(), describe_paths(&dropped));
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to pull the synced config: {}", e),
+    });
+}
+
+/// The config's text without secrets, and the paths of the secrets left out
+fn shareable_config(config_path: &Path) -> Result<(String, Vec<SecretPath>), Box<dyn std::error::Error + Send + Sync>> {
+    let content = std::fs::read_to_string(config_path).map_err(|e| format!("Failed to read {}: {}", config_path.display(), e))?;
+    let mut document: DocumentMut = content.parse().map_err(|e| format!("Failed to parse {}: {}", config_path.display(), e))?;
+    let mut secrets = Vec::new();
+    strip_secrets(document.as_table_mut(), &mut Vec::new(), false, &mut secrets);
+    for table in LOCAL_TABLES {
+        document.remove(table);
+    }
+    Ok((document.to_string(), secrets))
+}
+
+/// Writes a config in place of the current one, with the current one's secrets where it has none
+/// and its local tables, keeping the current one as `config.toml.bak`. Returns the secrets dropped
+/// because their entries are gone, or None if nothing changed
+fn install(config_path: &Path, content: &str) -> Result<Option<Vec<SecretPath>>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut document: DocumentMut = content.parse().map_err(|e| format!("The config to import can't be parsed: {}", e))?;
+    let current = std::fs::read_to_string(config_path).ok();
+    let mut dropped = Vec::new();
+    if let Some(current_document) = current.as_deref().and_then(|current| current.parse::<DocumentMut>().ok()) {
+        let mut secrets = Vec::new();
+        strip_secrets(current_document.clone().as_table_mut(), &mut Vec::new(), false, &mut secrets);
+        for path in secrets {
+            let Some(target) = relocate(current_document.as_item(), document.as_item(), &path) else {
+                dropped.push(path);
+                continue;
+            };
+            if get(document.as_item(), &target).is_none() {
+                if let Some(secret) = get(current_document.as_item(), &path).cloned() {
+                    set(document.as_item_mut(), &target, secret);
+                }
+            }
+        }
+        for table in LOCAL_TABLES {
+            document.remove(table);
+            if let Some(local) = current_document.get(table) {
+                document.insert(table, local.clone());
+            }
+        }
+    }
+    if current.as_deref() == Some(document.to_string().as_str()) {
+        return Ok(None);
+    }
+    rule_editor::save(&document, config_path)
+        .map_err(|e| e.to_string().replace("The edited config", "The imported config"))?;
+    if let Some(current) = current {
+        std::fs::write(runtime_paths::config_backup_file(config_path), current)?;
+    }
+    Ok(Some(dropped))
+}
+
+/// Removes secret values from a table and the tables in it, noting where they were
+fn strip_secrets(table: &mut dyn TableLike, path: &mut Vec<serde_json::Value>, in_headers: bool, secrets: &mut Vec<SecretPath>) {
+    let keys: Vec<String> = table.iter().map(|(key, _)| key.to_string()).collect();
+    for key in keys {
+        path.push(key.clone().into());
+        let secret = in_headers || SECRET_KEYS.contains(&key.as_str());
+        let Some(item) = table.get_mut(&key) else {
+            path.pop();
+            continue;
+        };
+        match item {
+            Item::Value(Value::String(text)) if secret && !is_env_reference(text.value()) => {
+                table.remove(&key);
+                secrets.push(path.clone());
+            }
+            Item::Table(child) => strip_secrets(child, path, key == "headers", secrets),
+            Item::Value(Value::InlineTable(child)) => strip_secrets(child, path, key == "headers", secrets),
+            Item::ArrayOfTables(tables) => {
+                for (index, child) in tables.iter_mut().enumerate() {
+                    path.push(index.into());
+                    strip_secrets(child, path, false, secrets);
+                    path.pop();
+                }
+            }
+            Item::Value(Value::Array(values)) => {
+                for (index, value) in values.iter_mut().enumerate() {
+                    if let Value::InlineTable(child) = value {
+                        path.push(index.into());
+                        strip_secrets(child, path, false, secrets);
+                        path.pop();
+                    }
+                }
+            }
+            _ => {}
+        }
+        path.pop();
+    }
+}
+
+/// Where a secret of the current config goes in a new one. Array entries are matched by their
+/// `IDENTITY_KEYS`, not their position, so a secret never lands on another sink; None if the entry
+/// it belongs to isn't in the new config, or isn't told apart from the others.
+fn relocate(current: &Item, new: &Item, path: &[serde_json::Value]) -> Option<SecretPath> {
+    let (key, parent_path) = path.split_last()?;
+    let (mut current, mut new) = (current, new);
+    let mut relocated = Vec::with_capacity(path.len());
+    for segment in parent_path {
+        let segment = match segment {
+            serde_json::Value::Number(index) => {
+                let identity = identity(current.get(index.as_u64()? as usize)?)?;
+                let ([_], [position]) = (&matching(current, &identity)[..], &matching(new, &identity)[..]) else {
+                    return None;
+                };
+                current = current.get(index.as_u64()? as usize)?;
+                serde_json::Value::from(*position)
+            }
+            key => {
+                current = get(current, std::slice::from_ref(key))?;
+                key.clone()
+            }
+        };
+        new = get(new, std::slice::from_ref(&segment))?;
+        relocated.push(segment);
+    }
+    relocated.push(key.clone());
+    Some(relocated)
+}
+
+/// An array entry's `IDENTITY_KEYS`, None if it has none of them
+fn identity(entry: &Item) -> Option<Vec<&str>> {
+    let identity: Vec<&str> = IDENTITY_KEYS.iter().map(|key| entry.get(key).and_then(Item::as_str).unwrap_or("")).collect();
+    identity.iter().any(|value| !value.is_empty()).then_some(identity)
+}
+
+/// Positions of the entries of an array with the given identity
+fn matching(array: &Item, wanted: &[&str]) -> Vec<usize> {
+    (0..).map_while(|index| array.get(index).map(|entry| (index, entry)))
+        .filter(|(_, entry)| identity(entry).as_deref() == Some(wanted))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Whether a value is only a `${VAR}` reference, which is safe to share
+fn is_env_reference(text: &str) -> bool {
+    text.starts_with("${") && text.ends_with('}') && !text[2..].contains('$')
+}
+
+fn get<'a>(item: &'a Item, path: &[serde_json::Value]) -> Option<&'a Item> {
+    path.iter().try_fold(item, |item, segment| match segment {
+        serde_json::Value::String(key) => item.get(key.as_str()),
+        serde_json::Value::Number(index) => item.get(index.as_u64()? as usize),
+        _ => None,
+    })
+}
+
+/// Puts a value where `path` points, if the table it goes in exists
+fn set(item: &mut Item, path: &[serde_json::Value], value: Item) {
+    let Some((serde_json::Value::String(key), parent_path)) = path.split_last() else {
+        return;
+    };
+    let parent = parent_path.iter().try_fold(item, |item, segment| match segment {
+        serde_json::Value::String(key) => item.get_mut(key.as_str()),
+        serde_json::Value::Number(index) => item.get_mut(index.as_u64()? as usize),
+        _ => None,
+    });
+    if let Some(table) = parent.and_then(Item::as_table_like_mut) {
+        table.insert(key, value);
+    }
+}
+
+fn print_dropped(dropped: &[SecretPath]) {
+    if !dropped.is_empty() {
+        println!("Dropped {} secret(s) of this machine whose entries are gone: {}", dropped.len(), describe_paths(dropped));
+    }
+}
+
+/// e.g. `sinks[0].headers.Authorization, capture_safe.obs_password`
+fn describe_paths(paths: &[SecretPath]) -> String {
+    let described: Vec<String> = paths.iter().map(|path| {
+        path.iter().enumerate().map(|(position, segment)| match segment {
+            serde_json::Value::Number(index) => format!("[{}]", index),
+            serde_json::Value::String(key) if position == 0 => key.clone(),
+            serde_json::Value::String(key) => format!(".{}", key),
+            _ => String::new(),
+        }).collect()
+    }).collect();
+    described.join(", ")
+}
+
+fn check_repository(sync_dir: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !sync_dir.join(".git").is_dir() {
+        return Err(format!("The config isn't synced yet; start with `viberot-service config sync init <git URL>`, \
+                            which clones the repository to {}", sync_dir.display()).into());
+    }
+    Ok(())
+}
+
+fn has_upstream(sync_dir: &Path) -> bool {
+    git(Some(sync_dir), &["rev-parse", "--verify", "--quiet", "@{upstream}"]).is_ok()
+}
+
+/// Pulls, rebasing this machine's commits on top; a conflict leaves the repository as it was
+fn pull_rebase(sync_dir: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    git(Some(sync_dir), &["pull", "--quiet", "--rebase"]).inspect_err(|_| {
+        let _ = git(Some(sync_dir), &["rebase", "--abort"]);
+    })
+}
+
+/// Runs git, failing with what it printed if it doesn't succeed
+fn git(dir: Option<&Path>, args: &[&str]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut command = Command::new("git");
+    command.args(args).env("GIT_TERMINAL_PROMPT", "0");
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    let output = command.output().map_err(|e| format!("Failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,284 @@
+// CPU usage gate
+// `only_if_cpu_above = 50` on a rule holds its actions back until the matched command is actually
+// working: its process and everything it started use more than that percentage of a core
+// (100 = one full core) for `SUSTAIN`. Commands that block on a prompt right away, like
+// `cargo build` waiting for a credentials helper, never start them.
+// `only_if = "busy"` is the same gate for commands that may work without using much CPU: each
+// sample is classified as CPU-bound, I/O-bound (reading or writing the disk, or blocked on it) or
+// idle-waiting, and anything but idle counts. An ssh waiting for a passphrase is idle; an `rsync`
+// or `docker load` busy with the disk is not.
+// The process tree is sampled every `SAMPLE_INTERVAL` while the command waits: from /proc on
+// Linux, `ps` on macOS (no I/O counters there, only processes blocked on the disk), and a ToolHelp
+// snapshot plus GetProcessTimes and GetProcessIoCounters on Windows, cheaper than tracing disk
+// events for the one process tree. Shell commands whose own PID the hooks didn't report are
+// sampled through the shell running them, whose children they are. Events from other machines, or that can't be sampled, aren't held back.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::platform::{ProbeSource, ProcessEvent, Provenance};
+
+/// How often a waiting command is sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the usage has to stay above the threshold
+const SUSTAIN: Duration = Duration::from_secs(2);
+/// CPU usage, in percent of a core, above which a command is CPU-bound
+const BUSY_CPU_PERCENT: f64 = 10.0;
+/// Disk throughput above which a command that isn't CPU-bound is I/O-bound
+const BUSY_IO_BYTES_PER_SECOND: f64 = 256.0 * 1024.0;
+
+/// What a command's process tree was doing between two samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Cpu,
+    Io,
+    /// Waiting for input, a lock or the network
+    Idle,
+}
+
+/// A process tree's counters at one point
+struct Sample {
+    cpu_time: Duration,
+    /// Bytes read from and written to storage, where the platform counts them
+    io_bytes: Option<u64>,
+    /// Whether a process was blocked on the disk
+    blocked: bool,
+}
+
+impl Sample {
+    /// What the tree did since an earlier sample, and its CPU usage in percent of a core
+    fn activity_since(&self, earlier: &Sample, elapsed: Duration) -> (Activity, f64) {
+        let seconds = elapsed.as_secs_f64();
+        let usage = self.cpu_time.saturating_sub(earlier.cpu_time).as_secs_f64() / seconds * 100.0;
+        // Counters of exited processes leave the sum, so a drop is no I/O rather than a wrap
+        let io_rate = match (self.io_bytes, earlier.io_bytes) {
+            (Some(now), Some(then)) => now.saturating_sub(then) as f64 / seconds,
+            _ => 0.0,
+        };
+        let activity = if usage > BUSY_CPU_PERCENT {
+            Activity::Cpu
+        } else if self.blocked || io_rate > BUSY_IO_BYTES_PER_SECOND {
+            Activity::Io
+        } else {
+            Activity::Idle
+        };
+        (activity, usage)
+    }
+}
+
+/// The OS process whose tree is sampled for an event, if it ran here
+fn sampled_pid(event: &ProcessEvent) -> Option<u32> {
+    if event.provenance.hostname != Provenance::local().hostname {
+        return None;
+    }
+    event.real_pid.or_else(|| match event.probe_source {
+        // The hooks use the shell's PID as the session ID
+        ProbeSource::PosixShell => event.shell_session_id.as_deref()?.parse().ok(),
+        ProbeSource::Osc133 => event.shell_session_id.as_deref()?.strip_prefix("pty-")?.parse().ok(),
+        _ => None,
+    })
+}
+
+/// Waits until the command's process tree sustains more than `cpu_above` percent of a core, or
+/// with `or_io` until it sustains any activity but waiting: CPU use above `cpu_above` (a little by
+/// default) or disk I/O. Returns whether its actions should start: false if the process went away
+/// first, true right away if it can't be sampled.
+pub async fn wait_until_busy(event: &ProcessEvent, cpu_above: Option<u32>, or_io: bool) -> bool {
+    let Some(root) = sampled_pid(event) else {
+        debug!("Can't sample CPU usage of PID {}, not holding its actions back", event.pid);
+        return true;
+    };
+    let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+    let threshold = cpu_above.map_or(BUSY_CPU_PERCENT, f64::from);
+    let mut last: Option<(Instant, Sample)> = None;
+    let mut busy_since: Option<Instant> = None;
+    loop {
+        interval.tick().await;
+        let sample = match tokio::task::spawn_blocking(move || tree_sample(root)).await {
+            Ok(Ok(Some(sample))) => sample,
+            Ok(Ok(None)) => {
+                debug!("Process {} of PID {} exited before getting busy", root, event.pid);
+                return false;
+            }
+            Ok(Err(e)) => {
+                warn!("Failed to sample CPU usage of process {}, starting its actions: {}", root, e);
+                return true;
+            }
+            Err(e) => {
+                warn!("CPU sampling task failed: {}", e);
+                return true;
+            }
+        };
+        let now = Instant::now();
+        if let Some((then, previous)) = last {
+            let (activity, usage) = sample.activity_since(&previous, now.duration_since(then));
+            if usage > threshold || (or_io && activity == Activity::Io) {
+                let since = *busy_since.get_or_insert(then);
+                if now.duration_since(since) >= SUSTAIN {
+                    debug!("PID {} sustained {:.0}% CPU ({:?})", event.pid, usage, activity);
+                    return true;
+                }
+            } else if busy_since.take().is_some() {
+                debug!("PID {} no longer busy enough ({:?}, {:.0}% CPU)", event.pid, activity, usage);
+            }
+        }
+        last = Some((now, sample));
+    }
+}
+
+/// Processes descending from `root`, itself included, given every process's parent
+fn descendants(parents: &HashMap<u32, u32>, root: u32) -> Vec<u32> {
+    let mut tree = vec![root];
+    let mut next = 0;
+    while next < tree.len() {
+        let parent = tree[next];
+        // Parent PIDs can be stale and reused on Windows, so guard against cycles
+        let children: Vec<u32> = parents.iter()
+            .filter(|&(pid, &ppid)| ppid == parent && !tree.contains(pid))
+            .map(|(&pid, _)| pid)
+            .collect();
+        tree.extend(children);
+        next += 1;
+    }
+    tree
+}
+
+/// Counters of a process and its descendants, or None if it doesn't exist
+#[cfg(target_os = "linux")]
+fn tree_sample(root: u32) -> std::io::Result<Option<Sample>> {
+    // SAFETY: sysconf has no preconditions
+    let ticks_per_second = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as f64,
+        _ => 100.0,
+    };
+    let mut processes = HashMap::new();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else {
+            continue;
+        };
+        // Processes can exit while the directory is read
+        let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        // The fields after the parenthesized name: state, ppid, ..., utime (14th), stime,
+        // cutime, cstime. Reaped children's time moves to cutime/cstime, so the sum doesn't drop
+        // when they exit
+        let Some(fields) = stat.rsplit_once(')').map(|(_, rest)| rest.split_whitespace().collect::<Vec<_>>()) else {
+            continue;
+        };
+        let number = |index: usize| fields.get(index).and_then(|field| field.parse::<u64>().ok());
+        if let (Some(ppid), Some(ticks)) = (number(1), (11..15).map(number).sum::<Option<u64>>()) {
+            // D: uninterruptible sleep, nearly always waiting for the disk
+            processes.insert(pid, (ppid as u32, ticks, fields[0] == "D"));
+        }
+    }
+    if !processes.contains_key(&root) {
+        return Ok(None);
+    }
+    let parents = processes.iter().map(|(&pid, &(ppid, _, _))| (pid, ppid)).collect();
+    let tree = descendants(&parents, root);
+    let ticks: u64 = tree.iter().filter_map(|pid| processes.get(pid)).map(|&(_, ticks, _)| ticks).sum();
+    // Storage I/O; reads served from the page cache show up as CPU time instead
+    let io_bytes = tree.iter()
+        .filter_map(|pid| std::fs::read_to_string(format!("/proc/{}/io", pid)).ok())
+        .map(|io| {
+            io.lines()
+                .filter_map(|line| line.strip_prefix("read_bytes: ").or_else(|| line.strip_prefix("write_bytes: ")))
+                .filter_map(|bytes| bytes.trim().parse::<u64>().ok())
+                .sum::<u64>()
+        })
+        .sum();
+    Ok(Some(Sample {
+        cpu_time: Duration::from_secs_f64(ticks as f64 / ticks_per_second),
+        io_bytes: Some(io_bytes),
+        blocked: tree.iter().filter_map(|pid| processes.get(pid)).any(|&(_, _, blocked)| blocked),
+    }))
+}
+
+/// Counters of a process and its descendants, or None if it doesn't exist
+#[cfg(target_os = "macos")]
+fn tree_sample(root: u32) -> std::io::Result<Option<Sample>> {
+    let output = std::process::Command::new("ps").args(["-axo", "pid=,ppid=,stat=,time="]).output()?;
+    let mut processes = HashMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if let [pid, ppid, stat, time] = fields[..] {
+            if let (Ok(pid), Ok(ppid), Some(time)) = (pid.parse::<u32>(), ppid.parse::<u32>(), parse_ps_time(time)) {
+                // U: uninterruptible wait, nearly always for the disk
+                processes.insert(pid, (ppid, time, stat.starts_with('U')));
+            }
+        }
+    }
+    if !processes.contains_key(&root) {
+        return Ok(None);
+    }
+    let parents = processes.iter().map(|(&pid, &(ppid, _, _))| (pid, ppid)).collect();
+    let tree: Vec<_> = descendants(&parents, root).iter().filter_map(|pid| processes.get(pid)).copied().collect();
+    Ok(Some(Sample {
+        cpu_time: tree.iter().map(|&(_, time, _)| time).sum(),
+        io_bytes: None,
+        blocked: tree.iter().any(|&(_, _, blocked)| blocked),
+    }))
+}
+
+/// `ps` CPU time, `[[dd-]hh:]mm:ss.ss`
+#[cfg(target_os = "macos")]
+fn parse_ps_time(time: &str) -> Option<Duration> {
+    let (days, rest) = match time.split_once('-') {
+        Some((days, rest)) => (days.parse::<f64>().ok()?, rest),
+        None => (0.0, time),
+    };
+    let seconds = rest.split(':').try_fold(0.0, |total, part| Some(total * 60.0 + part.parse::<f64>().ok()?))?;
+    Some(Duration::from_secs_f64(days * 86400.0 + seconds))
+}
+
+/// Counters of a process and its descendants, or None if it doesn't exist
+#[cfg(windows)]
+fn tree_sample(root: u32) -> std::io::Result<Option<Sample>> {
+    use windows::Win32::Foundation::{CloseHandle, FILETIME};
+    use windows::Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+    use windows::Win32::System::Threading::{GetProcessIoCounters, GetProcessTimes, OpenProcess, IO_COUNTERS, PROCESS_QUERY_LIMITED_INFORMATION};
+
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) }.map_err(std::io::Error::other)?;
+    let mut parents = HashMap::new();
+    let mut entry = PROCESSENTRY32W {
+        dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+        ..Default::default()
+    };
+    let mut more = unsafe { Process32FirstW(snapshot, &mut entry) }.is_ok();
+    while more {
+        parents.insert(entry.th32ProcessID, entry.th32ParentProcessID);
+        more = unsafe { Process32NextW(snapshot, &mut entry) }.is_ok();
+    }
+    let _ = unsafe { CloseHandle(snapshot) };
+    if !parents.contains_key(&root) {
+        return Ok(None);
+    }
+
+    let as_duration = |time: FILETIME| Duration::from_nanos(((time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64) * 100);
+    let mut cpu_time = Duration::ZERO;
+    let mut io_bytes = 0;
+    for pid in descendants(&parents, root) {
+        // Processes that exited since the snapshot, or that we may not query, are left out
+        let Ok(process) = (unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }) else {
+            continue;
+        };
+        let (mut creation, mut exit, mut kernel, mut user) = Default::default();
+        if unsafe { GetProcessTimes(process, &mut creation, &mut exit, &mut kernel, &mut user) }.is_ok() {
+            cpu_time += as_duration(kernel) + as_duration(user);
+        }
+        // Reads and writes of files, pipes and devices alike; typing at a prompt is a few bytes
+        let mut counters = IO_COUNTERS::default();
+        if unsafe { GetProcessIoCounters(process, &mut counters) }.is_ok() {
+            io_bytes += counters.ReadTransferCount + counters.WriteTransferCount;
+        }
+        let _ = unsafe { CloseHandle(process) };
+    }
+    Ok(Some(Sample { cpu_time, io_bytes: Some(io_bytes), blocked: false }))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn tree_sample(_root: u32) -> std::io::Result<Option<Sample>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "sampling CPU usage isn't supported on this platform"))
+}
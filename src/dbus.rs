@@ -0,0 +1,133 @@
+// D-Bus interface (Linux)
+// Serves `org.viberot.Service1` at /org/viberot/Service1 on the session bus, so GNOME extensions,
+// KDE widgets and waybar modules can pause the rules, trigger one by hand and follow what matched
+// without going through the shell socket or the state files.
+// Methods:
+//   Pause(s duration)                 snoozes every rule like `snooze-all`; "" snoozes for an hour
+//   Resume() -> b                     lifts that snooze; whether the rules were snoozed
+//   TriggerRule(s rule, u seconds) -> u pid
+//                                     runs a rule's actions as if a command matched it, ending
+//                                     that command after `seconds`
+// Signals:
+//   RuleMatched(u pid, s rule, s command)
+//   ActionStarted(u pid, s rule, s action)
+//   ActionEnded(u pid, s action)
+
+use futures_util::StreamExt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{debug, error, info};
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+
+use crate::action_orchestrator::{ActionOrchestrator, OrchestratorEvent};
+use crate::config::{self, Config};
+use crate::platform::{ProbeSource, ProcessEvent};
+use crate::rule_engine::RuleEngine;
+
+pub const BUS_NAME: &str = "org.viberot.Service1";
+pub const OBJECT_PATH: &str = "/org/viberot/Service1";
+
+/// How long `Pause("")` snoozes the rules for
+const DEFAULT_PAUSE: Duration = Duration::from_secs(60 * 60);
+
+/// Synthetic PIDs of rules triggered by hand, above the ranges of the other probes
+static MANUAL_PID_COUNTER: AtomicU32 = AtomicU32::new(4_000_000_000);
+
+struct Service1 {
+    orchestrator: Arc<ActionOrchestrator>,
+    rule_engine: Arc<RuleEngine>,
+    config: Arc<RwLock<Config>>,
+}
+
+#[zbus::interface(name = "org.viberot.Service1")]
+impl Service1 {
+    async fn pause(&self, duration: &str) -> fdo::Result<()> {
+        let duration = match duration {
+            "" => DEFAULT_PAUSE,
+            duration => config::parse_duration(duration).map_err(fdo::Error::InvalidArgs)?,
+        };
+        self.rule_engine.mutes().snooze_all(duration).map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    async fn resume(&self) -> fdo::Result<bool> {
+        let resumed = self.rule_engine.mutes().resume().map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        if resumed {
+            info!("Rules resumed over D-Bus");
+        }
+        Ok(resumed)
+    }
+
+    /// Starts the actions of a rule, muted or not, for a command named after the rule
+    async fn trigger_rule(&self, rule: &str, seconds: u32) -> fdo::Result<u32> {
+        if seconds == 0 {
+            return Err(fdo::Error::InvalidArgs("The command has to run for at least a second".to_string()));
+        }
+        let actions = self.rule_engine.actions_of(rule, &*self.config.read().await).await
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("No rule named '{}'", rule)))?;
+
+        let pid = MANUAL_PID_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let event = ProcessEvent::new(pid, rule.to_string(), ProbeSource::Manual);
+        info!("Triggering rule '{}' over D-Bus as PID {} for {}s", rule, pid, seconds);
+        self.orchestrator.start_actions(actions, &event).await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+
+        let orchestrator = Arc::clone(&self.orchestrator);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(seconds.into())).await;
+            if let Err(e) = orchestrator.command_ended(pid, None).await {
+                error!("Failed to finish actions of triggered PID {}: {}", pid, e);
+            }
+        });
+        Ok(pid)
+    }
+
+    #[zbus(signal)]
+    async fn rule_matched(emitter: &SignalEmitter<'_>, pid: u32, rule: &str, command: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn action_started(emitter: &SignalEmitter<'_>, pid: u32, rule: &str, action: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn action_ended(emitter: &SignalEmitter<'_>, pid: u32, action: &str) -> zbus::Result<()>;
+}
+
+/// Claims the bus name and relays orchestrator events as signals; the service stays on the bus
+/// while the returned connection is held
+pub async fn start(
+    orchestrator: Arc<ActionOrchestrator>,
+    rule_engine: Arc<RuleEngine>,
+    config: Arc<RwLock<Config>>,
+) -> zbus::Result<zbus::Connection> {
+    let events = orchestrator.subscribe();
+    let service = Service1 { orchestrator, rule_engine, config };
+    let connection = zbus::connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, service)?
+        .build()
+        .await?;
+    info!("Serving {} on the session bus", BUS_NAME);
+
+    let emitter = SignalEmitter::new(&connection, OBJECT_PATH)?.into_owned();
+    tokio::spawn(async move {
+        let mut events = std::pin::pin!(events);
+        while let Some(event) = events.next().await {
+            let result = match event {
+                OrchestratorEvent::RuleMatched { pid, rule, command, .. } => {
+                    Service1::rule_matched(&emitter, pid, &rule, &command).await
+                }
+                OrchestratorEvent::ActionStarted { pid, rule, action } => {
+                    Service1::action_started(&emitter, pid, &rule, &action).await
+                }
+                OrchestratorEvent::ActionEnded { pid, action } => Service1::action_ended(&emitter, pid, &action).await,
+                _ => continue,
+            };
+            if let Err(e) = result {
+                debug!("Failed to emit D-Bus signal: {}", e);
+            }
+        }
+    });
+    Ok(connection)
+}
@@ -0,0 +1,141 @@
+// Duplicate event suppression
+// When several probes watch the same machine (e.g. ETW and the shell hooks on Windows, or the
+// hooks and the prompt marks of a shell in `viberot-service pty`), one command is reported once
+// per probe, with different PIDs. Starts from different probes with the
+// same normalized command line and host (and working directory, when both know it) within a short
+// window are merged into one logical lifecycle: the first report wins, later ones are dropped and
+// their PIDs become aliases of the first. The lifecycle ends with whichever probe reports the end first.
+// A batch a source sent at once is filtered in one go and comes out as the events left of it.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::command_line::CommandLine;
+use crate::platform::{ProbeSource, ProcessLifecycleEvent};
+
+/// How far apart two probes' reports of the same command may be
+const DEDUP_WINDOW: Duration = Duration::from_secs(2);
+/// Merged lifecycles whose end never arrived are forgotten after this long
+const GROUP_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+struct RecentStart {
+    pid: u32,
+    source: ProbeSource,
+    key: String,
+    hostname: String,
+    working_directory: Option<String>,
+    seen: Instant,
+}
+
+#[derive(Default)]
+struct DedupState {
+    /// Starts within the dedup window, oldest first
+    recent: VecDeque<RecentStart>,
+    /// PIDs of merged lifecycles, mapped to the PID actions know them by
+    groups: HashMap<u32, (u32, Instant)>,
+    /// Merged lifecycles that haven't ended yet, by primary PID
+    open: HashSet<u32>,
+}
+
+#[derive(Default)]
+pub struct Deduplicator {
+    state: Mutex<DedupState>,
+}
+
+impl Deduplicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Passes the events of a batch, or a single event, through, dropping those another probe
+    /// already reported
+    pub fn filter(&self, event: ProcessLifecycleEvent) -> Vec<ProcessLifecycleEvent> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.recent.retain(|start| now.duration_since(start.seen) < DEDUP_WINDOW);
+        state.groups.retain(|_, (_, since)| now.duration_since(*since) < GROUP_TTL);
+
+        event.into_events().into_iter().filter_map(|event| Self::filter_one(&mut state, now, event)).collect()
+    }
+
+    fn filter_one(state: &mut DedupState, now: Instant, event: ProcessLifecycleEvent) -> Option<ProcessLifecycleEvent> {
+        match event {
+            ProcessLifecycleEvent::Started(event) => {
+                let key = correlation_key(&event.command);
+                let duplicate_of = state.recent.iter()
+                    .find(|start| {
+                        start.source != event.probe_source
+                            && start.key == key
+                            && start.hostname == event.provenance.hostname
+                            && match (&start.working_directory, &event.working_directory) {
+                                (Some(a), Some(b)) => a == b,
+                                _ => true,
+                            }
+                    })
+                    .map(|start| start.pid);
+
+                if let Some(primary) = duplicate_of {
+                    debug!("Merging start of '{}' from {:?} (PID {}) into PID {}",
+                           event.command, event.probe_source, event.pid, primary);
+                    state.groups.entry(primary).or_insert((primary, now));
+                    state.groups.insert(event.pid, (primary, now));
+                    state.open.insert(primary);
+                    return None;
+                }
+
+                state.recent.push_back(RecentStart {
+                    pid: event.pid,
+                    source: event.probe_source,
+                    key,
+                    hostname: event.provenance.hostname.clone(),
+                    working_directory: event.working_directory.clone(),
+                    seen: now,
+                });
+                Some(ProcessLifecycleEvent::Started(event))
+            }
+            ProcessLifecycleEvent::Ended { pid, exit_code } => {
+                state.recent.retain(|start| start.pid != pid);
+                match state.groups.remove(&pid) {
+                    Some((primary, _)) if state.open.remove(&primary) => {
+                        Some(ProcessLifecycleEvent::Ended { pid: primary, exit_code })
+                    }
+                    Some((primary, _)) => {
+                        debug!("Dropping end of PID {}, merged into PID {} which already ended", pid, primary);
+                        None
+                    }
+                    None => Some(ProcessLifecycleEvent::Ended { pid, exit_code }),
+                }
+            }
+            ProcessLifecycleEvent::PidUpgraded { pid, real_pid } => match state.groups.get(&pid) {
+                Some(&(primary, _)) if state.open.contains(&primary) => {
+                    Some(ProcessLifecycleEvent::PidUpgraded { pid: primary, real_pid })
+                }
+                Some(_) => None,
+                None => Some(ProcessLifecycleEvent::PidUpgraded { pid, real_pid }),
+            },
+            ProcessLifecycleEvent::Progress { pid, percent, message } => match state.groups.get(&pid) {
+                Some(&(primary, _)) if state.open.contains(&primary) => {
+                    Some(ProcessLifecycleEvent::Progress { pid: primary, percent, message })
+                }
+                Some(_) => None,
+                None => Some(ProcessLifecycleEvent::Progress { pid, percent, message }),
+            },
+            // Flattened by `into_events`
+            ProcessLifecycleEvent::Batch(_) => None,
+        }
+    }
+}
+
+/// Probes report the same command differently (`C:\...\cargo.exe build` vs `cargo build`),
+/// so compare the normalized executable and arguments
+fn correlation_key(command: &str) -> String {
+    let command_line = CommandLine::parse(command);
+    let mut key = command_line.exe.to_lowercase();
+    for arg in &command_line.args {
+        key.push(' ');
+        key.push_str(arg);
+    }
+    key
+}
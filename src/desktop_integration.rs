@@ -0,0 +1,271 @@
+// Desktop progress integration
+// Reflects matched long-running commands as progress on the terminal's taskbar button (Windows)
+// or launcher icon (Linux desktops implementing the Unity LauncherEntry API, e.g. GNOME docks, KDE).
+// Progress is determinate when the command has a duration history, indeterminate otherwise.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::config::DesktopIntegrationConfig;
+use crate::platform::ProcessEvent;
+
+/// How often determinate progress is refreshed
+const PROGRESS_UPDATE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Where progress for a command is displayed, captured when the command starts
+#[derive(Debug, Clone)]
+enum ProgressTarget {
+    /// Taskbar button of the window that was in the foreground (the terminal)
+    #[cfg(windows)]
+    Window(isize),
+    /// Launcher entry of a desktop application, e.g. `application://org.gnome.Terminal.desktop`
+    #[cfg(target_os = "linux")]
+    Launcher(String),
+}
+
+/// Progress target and refresh task per monitored PID
+type ProgressUpdaters = HashMap<u32, (ProgressTarget, JoinHandle<()>)>;
+
+/// Tracks progress indicators for running matched commands
+pub struct DesktopProgress {
+    config: DesktopIntegrationConfig,
+    updaters: Arc<Mutex<ProgressUpdaters>>,
+}
+
+impl DesktopProgress {
+    pub fn new(config: DesktopIntegrationConfig) -> Self {
+        if config.taskbar_progress && cfg!(target_os = "linux") && config.launcher_desktop_file.is_none() {
+            warn!("taskbar_progress is enabled but launcher_desktop_file is not set, progress will not be shown");
+        }
+
+        Self {
+            config,
+            updaters: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Starts showing progress for a matched command
+    // ProgressTarget has no variants on unsupported platforms
+    #[cfg_attr(not(any(windows, target_os = "linux")), allow(unreachable_code, unused_variables))]
+    pub fn start(&self, event: &ProcessEvent) {
+        if !self.config.taskbar_progress {
+            return;
+        }
+        let Some(target) = self.capture_target() else {
+            return;
+        };
+
+        let expected = event.expected_duration.filter(|d| *d > 0).map(Duration::from_secs);
+        let started = Instant::now();
+        let update_target = target.clone();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PROGRESS_UPDATE_INTERVAL);
+            loop {
+                interval.tick().await;
+                // Never report completion before the command actually ends
+                let progress = expected.map(|expected| {
+                    (started.elapsed().as_secs_f64() / expected.as_secs_f64()).min(0.99)
+                });
+                set_progress(&update_target, progress);
+                if progress.is_none() {
+                    // Indeterminate state doesn't need refreshing
+                    break;
+                }
+            }
+        });
+
+        if let Some((old_target, old_handle)) = self.updaters.lock().unwrap().insert(event.pid, (target, handle)) {
+            old_handle.abort();
+            clear_progress(&old_target);
+        }
+    }
+
+    /// Shows the progress the command's source reported instead of the estimate
+    pub fn report(&self, pid: u32, percent: u8) {
+        if let Some((target, handle)) = self.updaters.lock().unwrap().get(&pid) {
+            handle.abort();
+            set_progress(target, Some(f64::from(percent.min(100)) / 100.0));
+        }
+    }
+
+    /// Clears the progress indicator when the command ends
+    pub fn finish(&self, pid: u32) {
+        if let Some((target, handle)) = self.updaters.lock().unwrap().remove(&pid) {
+            handle.abort();
+            clear_progress(&target);
+        }
+    }
+
+    /// Clears all progress indicators, e.g. on shutdown
+    pub async fn clear_all(&self) {
+        for (_, (target, handle)) in self.updaters.lock().unwrap().drain() {
+            handle.abort();
+            clear_progress(&target);
+        }
+        #[cfg(target_os = "linux")]
+        flush_launcher_updates().await;
+    }
+
+    #[cfg(windows)]
+    fn capture_target(&self) -> Option<ProgressTarget> {
+        // The command was just launched from a terminal, which is almost always the foreground window
+        let hwnd = unsafe { windows::Win32::UI::WindowsAndMessaging::GetForegroundWindow() };
+        if hwnd.0 == 0 {
+            return None;
+        }
+        Some(ProgressTarget::Window(hwnd.0))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn capture_target(&self) -> Option<ProgressTarget> {
+        self.config.launcher_desktop_file.as_ref()
+            .map(|desktop_file| ProgressTarget::Launcher(format!("application://{}", desktop_file)))
+    }
+
+    #[cfg(not(any(windows, target_os = "linux")))]
+    fn capture_target(&self) -> Option<ProgressTarget> {
+        debug!("Desktop progress is not supported on this platform");
+        None
+    }
+}
+
+#[cfg(windows)]
+fn set_progress(target: &ProgressTarget, progress: Option<f64>) {
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_INPROC_SERVER, COINIT_APARTMENTTHREADED};
+    use windows::Win32::UI::Shell::{ITaskbarList3, TaskbarList, TBPF_INDETERMINATE, TBPF_NOPROGRESS, TBPF_NORMAL};
+
+    let ProgressTarget::Window(hwnd) = target;
+    let hwnd = HWND(*hwnd);
+    let result = unsafe {
+        let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+        CoCreateInstance::<_, ITaskbarList3>(&TaskbarList, None, CLSCTX_INPROC_SERVER).and_then(|taskbar| {
+            taskbar.HrInit()?;
+            match progress {
+                Some(value) if value < 0.0 => taskbar.SetProgressState(hwnd, TBPF_NOPROGRESS),
+                Some(value) => {
+                    taskbar.SetProgressState(hwnd, TBPF_NORMAL)?;
+                    taskbar.SetProgressValue(hwnd, (value * 1000.0) as u64, 1000)
+                }
+                None => taskbar.SetProgressState(hwnd, TBPF_INDETERMINATE),
+            }
+        })
+    };
+    if let Err(e) = result {
+        debug!("Failed to update taskbar progress: {}", e);
+    }
+}
+
+#[cfg(windows)]
+fn clear_progress(target: &ProgressTarget) {
+    // Negative progress means "no progress" for the Windows backend
+    set_progress(target, Some(-1.0));
+}
+
+#[cfg(target_os = "linux")]
+fn set_progress(target: &ProgressTarget, progress: Option<f64>) {
+    // The LauncherEntry API has no indeterminate state, so show an empty bar instead
+    let properties = format!(
+        "{{'progress-visible': <true>, 'progress': <{:.3}>}}",
+        progress.unwrap_or(0.0)
+    );
+    emit_launcher_update(target, &properties);
+}
+
+#[cfg(target_os = "linux")]
+fn clear_progress(target: &ProgressTarget) {
+    emit_launcher_update(target, "{'progress-visible': <false>}");
+}
+
+/// How long gdbus may take to emit one update
+#[cfg(target_os = "linux")]
+const GDBUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A launcher entry update, or a request to be told once the updates before it went out
+#[cfg(target_os = "linux")]
+enum LauncherUpdate {
+    Emit { app_uri: String, properties: String },
+    Flush(tokio::sync::oneshot::Sender<()>),
+}
+
+/// Sends updates to the task emitting them, which runs gdbus for one at a time so they arrive in
+/// order and the event loop never waits on it
+#[cfg(target_os = "linux")]
+fn launcher_updates() -> &'static tokio::sync::mpsc::UnboundedSender<LauncherUpdate> {
+    static SENDER: std::sync::OnceLock<tokio::sync::mpsc::UnboundedSender<LauncherUpdate>> = std::sync::OnceLock::new();
+    SENDER.get_or_init(|| {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(update) = receiver.recv().await {
+                let mut updates = vec![update];
+                while let Ok(update) = receiver.try_recv() {
+                    updates.push(update);
+                }
+                // Updates carry the whole state, so a slow gdbus only needs to send the latest
+                let mut latest: Vec<(String, String)> = Vec::new();
+                let mut flushes = Vec::new();
+                for update in updates {
+                    match update {
+                        LauncherUpdate::Emit { app_uri, properties } => {
+                            latest.retain(|(uri, _)| *uri != app_uri);
+                            latest.push((app_uri, properties));
+                        }
+                        LauncherUpdate::Flush(done) => flushes.push(done),
+                    }
+                }
+                for (app_uri, properties) in latest {
+                    run_gdbus(&app_uri, &properties).await;
+                }
+                for done in flushes {
+                    let _ = done.send(());
+                }
+            }
+        });
+        sender
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn emit_launcher_update(target: &ProgressTarget, properties: &str) {
+    let ProgressTarget::Launcher(app_uri) = target;
+    let _ = launcher_updates().send(LauncherUpdate::Emit { app_uri: app_uri.clone(), properties: properties.to_string() });
+}
+
+/// Waits for the updates sent so far to go out
+#[cfg(target_os = "linux")]
+async fn flush_launcher_updates() {
+    let (done, flushed) = tokio::sync::oneshot::channel();
+    if launcher_updates().send(LauncherUpdate::Flush(done)).is_ok() {
+        let _ = flushed.await;
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn run_gdbus(app_uri: &str, properties: &str) {
+    // Borrow gdbus instead of pulling in a D-Bus client for a single signal
+    let status = tokio::process::Command::new("gdbus")
+        .args([
+            "emit", "--session",
+            "--object-path", "/com/canonical/unity/launcherentry/viberot",
+            "--signal", "com.canonical.Unity.LauncherEntry.Update",
+            app_uri, properties,
+        ])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .status();
+    match tokio::time::timeout(GDBUS_TIMEOUT, status).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => debug!("Failed to emit launcher progress update: {}", e),
+        Err(_) => debug!("gdbus took over {:?} to emit a launcher progress update", GDBUS_TIMEOUT),
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn set_progress(_target: &ProgressTarget, _progress: Option<f64>) {}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn clear_progress(_target: &ProgressTarget) {}
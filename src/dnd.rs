@@ -0,0 +1,331 @@
+// Do-not-disturb sync
+// Reads the OS do-not-disturb / Focus Assist state so actions stay quiet while the user asked for
+// quiet, and can optionally switch it on while matched commands run (restoring it afterwards).
+// Backends: GNOME notification banners (Linux), Focus Assist via SHQueryUserNotificationState
+// (Windows, read-only) and the Focus assertions database (macOS, read-only).
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::config::DesktopIntegrationConfig;
+
+/// How often the do-not-disturb state is read while actions respect it
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+/// How long shutting down waits for do-not-disturb to be restored
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Platform access to the do-not-disturb state
+pub trait DndBackend: Send + Sync {
+    /// Whether do-not-disturb is on, or None if it can't be determined
+    fn is_active(&self) -> Option<bool>;
+
+    /// Turns do-not-disturb on or off
+    fn set_active(&self, active: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Returns the do-not-disturb backend for the current platform
+pub fn create_backend() -> Box<dyn DndBackend> {
+    #[cfg(target_os = "linux")]
+    {
+        Box::new(GnomeDnd)
+    }
+
+    #[cfg(windows)]
+    {
+        Box::new(FocusAssistDnd)
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        Box::new(MacFocusDnd)
+    }
+
+    #[cfg(not(any(target_os = "linux", windows, target_os = "macos")))]
+    {
+        Box::new(UnsupportedDnd)
+    }
+}
+
+#[derive(Debug, Default)]
+struct DndState {
+    /// PIDs of matched commands that want do-not-disturb on
+    holders: HashSet<u32>,
+    /// Whether we switched do-not-disturb on ourselves and must restore it
+    enabled_by_us: bool,
+}
+
+/// What the backend thread is asked to do
+enum Request {
+    /// Read the state now, as actions just started respecting it
+    Refresh,
+    Hold(u32),
+    Release(u32),
+    ReleaseAll(tokio::sync::oneshot::Sender<()>),
+}
+
+/// The last do-not-disturb state read, as `DndSync::active` stores it
+const STATE_OFF: u8 = 0;
+const STATE_ON: u8 = 1;
+const STATE_UNKNOWN: u8 = 2;
+
+/// Applies the do-not-disturb settings to matched commands. The backend is only used on a thread
+/// of its own, as reading and switching the state can mean running programs; it reads the state
+/// every `REFRESH_INTERVAL` while actions respect it, and the event loop sees the state it last read.
+pub struct DndSync {
+    requests: mpsc::Sender<Request>,
+    /// One of the `STATE_` constants
+    active: Arc<AtomicU8>,
+    enabled_by_us: Arc<AtomicBool>,
+    /// Whether actions respect do-not-disturb, so the state has to be kept fresh
+    polling: Arc<AtomicBool>,
+}
+
+impl DndSync {
+    pub fn new(backend: Box<dyn DndBackend>, config: &DesktopIntegrationConfig) -> Self {
+        let (requests, receiver) = mpsc::channel();
+        let active = Arc::new(AtomicU8::new(STATE_UNKNOWN));
+        let enabled_by_us = Arc::new(AtomicBool::new(false));
+        let polling = Arc::new(AtomicBool::new(config.respect_dnd));
+        let worker = DndWorker {
+            backend,
+            state: DndState::default(),
+            active: Arc::clone(&active),
+            enabled_by_us: Arc::clone(&enabled_by_us),
+            polling: Arc::clone(&polling),
+            last_read: Instant::now(),
+        };
+        std::thread::spawn(move || worker.run(receiver));
+        let _ = requests.send(Request::Refresh);
+        Self { requests, active, enabled_by_us, polling }
+    }
+
+    /// Whether actions for a matched command should be suppressed because the user is in do-not-disturb
+    pub fn should_suppress(&self, config: &DesktopIntegrationConfig) -> bool {
+        if !config.respect_dnd {
+            self.polling.store(false, Ordering::Relaxed);
+            return false;
+        }
+        // Turned on by a config reload
+        if !self.polling.swap(true, Ordering::Relaxed) {
+            let _ = self.requests.send(Request::Refresh);
+        }
+        // Don't let our own do-not-disturb silence the next command
+        if self.enabled_by_us.load(Ordering::Relaxed) {
+            return false;
+        }
+        self.active.load(Ordering::Relaxed) == STATE_ON
+    }
+
+    /// Switches do-not-disturb on while the matched command runs, if configured
+    pub fn hold(&self, pid: u32, config: &DesktopIntegrationConfig) {
+        if config.enable_dnd_while_running {
+            let _ = self.requests.send(Request::Hold(pid));
+        }
+    }
+
+    /// Restores do-not-disturb once the last holding command ends
+    pub fn release(&self, pid: u32) {
+        let _ = self.requests.send(Request::Release(pid));
+    }
+
+    /// Restores do-not-disturb regardless of running commands, e.g. on shutdown
+    pub async fn release_all(&self) {
+        let (done, released) = tokio::sync::oneshot::channel();
+        if self.requests.send(Request::ReleaseAll(done)).is_ok()
+            && tokio::time::timeout(RELEASE_TIMEOUT, released).await.is_err()
+        {
+            warn!("Do-not-disturb wasn't restored within {:?}", RELEASE_TIMEOUT);
+        }
+    }
+}
+
+/// Owns the backend on the do-not-disturb thread
+struct DndWorker {
+    backend: Box<dyn DndBackend>,
+    state: DndState,
+    active: Arc<AtomicU8>,
+    enabled_by_us: Arc<AtomicBool>,
+    polling: Arc<AtomicBool>,
+    last_read: Instant,
+}
+
+impl DndWorker {
+    fn run(mut self, requests: mpsc::Receiver<Request>) {
+        loop {
+            let received = if self.polling.load(Ordering::Relaxed) {
+                let next_read = (self.last_read + REFRESH_INTERVAL).saturating_duration_since(Instant::now());
+                requests.recv_timeout(next_read)
+            } else {
+                requests.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected)
+            };
+            let request = match received {
+                Ok(request) => request,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    self.read();
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            match request {
+                Request::Refresh => {
+                    self.read();
+                }
+                Request::Hold(pid) => self.hold(pid),
+                Request::Release(pid) => {
+                    if self.state.holders.remove(&pid) && self.state.holders.is_empty() {
+                        self.restore();
+                    }
+                }
+                Request::ReleaseAll(done) => {
+                    self.state.holders.clear();
+                    self.restore();
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    /// Reads the state, keeping it for `DndSync::should_suppress`
+    fn read(&mut self) -> Option<bool> {
+        self.last_read = Instant::now();
+        let active = self.backend.is_active();
+        let state = match active {
+            Some(true) => STATE_ON,
+            Some(false) => STATE_OFF,
+            None => STATE_UNKNOWN,
+        };
+        self.active.store(state, Ordering::Relaxed);
+        active
+    }
+
+    fn hold(&mut self, pid: u32) {
+        if self.state.holders.is_empty() && !self.state.enabled_by_us {
+            if self.read() != Some(false) {
+                // Already on (or unknown), leave it to the user
+                debug!("Do-not-disturb already active or unknown, not changing it");
+                return;
+            }
+            match self.backend.set_active(true) {
+                Ok(()) => {
+                    info!("Enabled do-not-disturb while PID {} runs", pid);
+                    self.set_enabled_by_us(true);
+                }
+                Err(e) => {
+                    warn!("Failed to enable do-not-disturb: {}", e);
+                    return;
+                }
+            }
+        }
+        if self.state.enabled_by_us {
+            self.state.holders.insert(pid);
+        }
+    }
+
+    fn restore(&mut self) {
+        if !self.state.enabled_by_us {
+            return;
+        }
+        self.set_enabled_by_us(false);
+        match self.backend.set_active(false) {
+            Ok(()) => info!("Restored do-not-disturb state"),
+            Err(e) => warn!("Failed to disable do-not-disturb: {}", e),
+        }
+    }
+
+    fn set_enabled_by_us(&mut self, enabled: bool) {
+        self.state.enabled_by_us = enabled;
+        self.enabled_by_us.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// GNOME: do-not-disturb is `show-banners = false` in the notification settings
+#[cfg(target_os = "linux")]
+struct GnomeDnd;
+
+#[cfg(target_os = "linux")]
+impl DndBackend for GnomeDnd {
+    fn is_active(&self) -> Option<bool> {
+        let output = std::process::Command::new("gsettings")
+            .args(["get", "org.gnome.desktop.notifications", "show-banners"])
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        match String::from_utf8_lossy(&output.stdout).trim() {
+            "false" => Some(true),
+            "true" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn set_active(&self, active: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let status = std::process::Command::new("gsettings")
+            .args(["set", "org.gnome.desktop.notifications", "show-banners", if active { "false" } else { "true" }])
+            .status()?;
+        if !status.success() {
+            return Err(format!("gsettings exited with {}", status).into());
+        }
+        Ok(())
+    }
+}
+
+/// Windows: Focus Assist shows up as quiet time / presentation mode in the notification state.
+/// There is no public API to change it.
+#[cfg(windows)]
+struct FocusAssistDnd;
+
+#[cfg(windows)]
+impl DndBackend for FocusAssistDnd {
+    fn is_active(&self) -> Option<bool> {
+        use windows::Win32::UI::Shell::{SHQueryUserNotificationState, QUNS_PRESENTATION_MODE, QUNS_QUIET_TIME};
+
+        let state = unsafe { SHQueryUserNotificationState() }.ok()?;
+        Some(state == QUNS_QUIET_TIME || state == QUNS_PRESENTATION_MODE)
+    }
+
+    fn set_active(&self, _active: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Err("Focus Assist can't be changed programmatically on Windows".into())
+    }
+}
+
+/// macOS: an active Focus is recorded as an assertion in ~/Library/DoNotDisturb/DB/Assertions.json.
+/// Focus modes can only be changed by the user (or Shortcuts).
+#[cfg(target_os = "macos")]
+struct MacFocusDnd;
+
+#[cfg(target_os = "macos")]
+impl DndBackend for MacFocusDnd {
+    fn is_active(&self) -> Option<bool> {
+        let path = dirs::home_dir()?.join("Library/DoNotDisturb/DB/Assertions.json");
+        let content = std::fs::read_to_string(path).ok()?;
+        let assertions: serde_json::Value = serde_json::from_str(&content).ok()?;
+        let focused = assertions["data"].as_array()?
+            .iter()
+            .filter_map(|entry| entry["storeAssertionRecords"].as_array())
+            .any(|records| !records.is_empty());
+        Some(focused)
+    }
+
+    fn set_active(&self, _active: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Err("Focus modes can't be changed programmatically on macOS".into())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows, target_os = "macos")))]
+struct UnsupportedDnd;
+
+#[cfg(not(any(target_os = "linux", windows, target_os = "macos")))]
+impl DndBackend for UnsupportedDnd {
+    fn is_active(&self) -> Option<bool> {
+        None
+    }
+
+    fn set_active(&self, _active: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Err("Do-not-disturb is not supported on this platform".into())
+    }
+}
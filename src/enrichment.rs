@@ -0,0 +1,735 @@
+// Event enrichment
+// Runs between the probes and the rule engine. Each enricher looks at a started process and adds
+// tags to it, which rules can match on instead of command globs (`when = "category == 'test'"`),
+// scripts see as `event.tags` and actions get as environment variables. Enrichers run in order,
+// so later ones can build on earlier tags (language detection starts at the git repo root).
+//
+// Built-in enrichers:
+// - `git`: `repo` (name of the repository directory), `repo_root`, `remote` (`host/owner/name` of
+//   origin), `branch` and `dirty` (uncommitted changes to tracked files), from the cwd
+// - `language`: `language` of the project, from marker files like Cargo.toml
+// - `task`: for task runners (just, make, go-task, npm/yarn/pnpm/bun scripts), the `task_runner`,
+//   the `task` that ran (the default one if none was given) and, from the justfile, Makefile or
+//   package.json, the `task_command` it runs
+// - `lifecycle`: for npm/yarn/pnpm/bun, the `lifecycle` script being run (`postinstall`, `build`,
+//   `test`) and the `package` it belongs to, from the `npm_lifecycle_event` and `npm_package_name`
+//   variables package managers set for scripts and everything they start, as ETW (with
+//   `[etw] package_scripts`) and `viberot-service wrap` report them; for a package manager command
+//   itself, from what it runs
+// - `category`: `category` of the command: build, test, install or deploy; for tasks, of the
+//   command the task runs if that tells more
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+use crate::command_line::CommandLine;
+use crate::config::EnrichmentConfig;
+use crate::platform::ProcessEvent;
+
+/// Tags the built-in enrichers can add
+pub const TAGS: &[&str] = &[
+    "repo", "repo_root", "remote", "branch", "dirty", "language", "task_runner", "task", "task_command", "lifecycle", "package",
+    "category",
+];
+
+/// Variables package managers set for the scripts they run, which probes report in the environment
+pub const PACKAGE_SCRIPT_ENV_VARS: &[&str] = &["npm_lifecycle_event", "npm_package_name"];
+
+/// Tags that need the working directory of the command
+pub const CWD_TAGS: &[&str] = &["repo", "repo_root", "remote", "branch", "dirty", "language", "task_command"];
+
+/// Adds tags to process events before rules are matched
+pub trait Enricher: Send + Sync {
+    /// Name used in logs and in `enrichment.disabled`
+    fn name(&self) -> &'static str;
+
+    fn enrich(&self, event: &ProcessEvent, tags: &mut BTreeMap<String, String>);
+}
+
+pub struct EnrichmentPipeline {
+    enrichers: Vec<Box<dyn Enricher>>,
+}
+
+impl EnrichmentPipeline {
+    /// The built-in enrichers, minus those disabled in the config
+    pub fn new(config: &EnrichmentConfig) -> Self {
+        let enrichers: Vec<Box<dyn Enricher>> = vec![
+            Box::new(GitEnricher::default()),
+            Box::new(LanguageEnricher),
+            Box::new(TaskEnricher),
+            Box::new(LifecycleEnricher),
+            Box::new(CategoryEnricher),
+        ];
+        Self {
+            enrichers: enrichers.into_iter()
+                .filter(|enricher| !config.disabled.iter().any(|name| name == enricher.name()))
+                .collect(),
+        }
+    }
+
+    /// Tags an event on a blocking thread, since enrichers read files and run git
+    pub async fn enrich(self: &Arc<Self>, event: ProcessEvent) -> ProcessEvent {
+        let pipeline = Arc::clone(self);
+        let fallback = event.clone();
+        let enriched = tokio::task::spawn_blocking(move || {
+            let mut event = event;
+            pipeline.enrich_blocking(&mut event);
+            event
+        });
+        match enriched.await {
+            Ok(event) => event,
+            Err(e) => {
+                error!("Event enrichment failed: {}", e);
+                fallback
+            }
+        }
+    }
+
+    fn enrich_blocking(&self, event: &mut ProcessEvent) {
+        let mut tags = std::mem::take(&mut event.tags);
+        for enricher in &self.enrichers {
+            enricher.enrich(event, &mut tags);
+        }
+        if !tags.is_empty() {
+            debug!("Tagged PID {} with {:?}", event.pid, tags);
+        }
+        event.tags = tags;
+    }
+}
+
+/// How long git state is reused before the repository is read again
+const GIT_CACHE_TTL: Duration = Duration::from_secs(5);
+/// Longest `git status` may take before the dirty state is left unknown
+const GIT_STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Default)]
+struct GitState {
+    remote: Option<String>,
+    branch: Option<String>,
+    dirty: Option<bool>,
+}
+
+/// Repository, remote, branch and dirty state of the working directory. Read from `.git` directly,
+/// except the dirty state, which needs `git status`; cached per repository for a few seconds.
+#[derive(Default)]
+pub struct GitEnricher {
+    cache: Mutex<HashMap<PathBuf, (Instant, GitState)>>,
+}
+
+impl Enricher for GitEnricher {
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    fn enrich(&self, event: &ProcessEvent, tags: &mut BTreeMap<String, String>) {
+        let Some(cwd) = event.working_directory.as_deref() else {
+            return;
+        };
+        let Some((root, git_dir)) = find_git_dir(Path::new(cwd)) else {
+            return;
+        };
+        if let Some(name) = root.file_name() {
+            tags.insert("repo".to_string(), name.to_string_lossy().into_owned());
+        }
+        tags.insert("repo_root".to_string(), root.to_string_lossy().into_owned());
+
+        let state = self.state(&root, &git_dir);
+        if let Some(remote) = state.remote {
+            tags.insert("remote".to_string(), remote);
+        }
+        if let Some(branch) = state.branch {
+            tags.insert("branch".to_string(), branch);
+        }
+        if let Some(dirty) = state.dirty {
+            tags.insert("dirty".to_string(), dirty.to_string());
+        }
+    }
+}
+
+impl GitEnricher {
+    fn state(&self, root: &Path, git_dir: &Path) -> GitState {
+        let now = Instant::now();
+        {
+            let mut cache = self.cache.lock().unwrap();
+            cache.retain(|_, (read, _)| now.duration_since(*read) < GIT_CACHE_TTL);
+            if let Some((_, state)) = cache.get(root) {
+                return state.clone();
+            }
+        }
+
+        let state = GitState {
+            remote: read_remote(git_dir),
+            branch: read_branch(git_dir),
+            dirty: is_dirty(root),
+        };
+        self.cache.lock().unwrap().insert(root.to_path_buf(), (now, state.clone()));
+        state
+    }
+}
+
+/// Finds the repository root and its git directory, following `.git` files (worktrees, submodules)
+fn find_git_dir(start: &Path) -> Option<(PathBuf, PathBuf)> {
+    for dir in start.ancestors() {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() {
+            return Some((dir.to_path_buf(), dot_git));
+        }
+        if dot_git.is_file() {
+            let content = std::fs::read_to_string(&dot_git).ok()?;
+            let git_dir = Path::new(content.trim().strip_prefix("gitdir:")?.trim());
+            return Some((dir.to_path_buf(), dir.join(git_dir)));
+        }
+    }
+    None
+}
+
+/// Branch name, or the short commit hash when HEAD is detached
+fn read_branch(git_dir: &Path) -> Option<String> {
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string())
+        }
+        None => head.get(..7).map(str::to_string),
+    }
+}
+
+/// URL of the `origin` remote (or the first one), as `host/owner/name`
+fn read_remote(git_dir: &Path) -> Option<String> {
+    // Worktrees share the main repository's config
+    let common_dir = match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(content) => git_dir.join(content.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    };
+    let config = std::fs::read_to_string(common_dir.join("config")).ok()?;
+
+    let mut section = String::new();
+    let mut first = None;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            section = line.to_string();
+            continue;
+        }
+        let Some(url) = line.strip_prefix("url").map(str::trim_start).and_then(|rest| rest.strip_prefix('=')) else {
+            continue;
+        };
+        if section == r#"[remote "origin"]"# {
+            return Some(normalize_remote(url.trim()));
+        }
+        if section.starts_with("[remote ") && first.is_none() {
+            first = Some(normalize_remote(url.trim()));
+        }
+    }
+    first
+}
+
+/// Turns the many ways to write a remote into `host/owner/name`, dropping credentials:
+/// `git@github.com:acme/app.git`, `https://token@github.com/acme/app` -> `github.com/acme/app`.
+/// Local paths are kept as they are.
+fn normalize_remote(url: &str) -> String {
+    let (rest, scp_like) = match url.split_once("://") {
+        Some(("file", path)) => return path.trim_end_matches(".git").to_string(),
+        Some((_, rest)) => (rest, false),
+        None => (url, true),
+    };
+    // Credentials end at the last `@` before the path
+    let path_start = rest.find('/').unwrap_or(rest.len());
+    let rest = match rest[..path_start].rfind('@') {
+        Some(at) => &rest[at + 1..],
+        None => rest,
+    };
+    let split = if scp_like { rest.split_once(':') } else { rest.split_once('/') };
+    let Some((host, path)) = split else {
+        return url.trim_end_matches(".git").to_string();
+    };
+    // `C:\repos\app` or `/srv/git/app.git` aren't hosts
+    if host.is_empty() || host.contains(['/', '\\']) || (scp_like && host.len() == 1) {
+        return url.trim_end_matches(".git").to_string();
+    }
+    let host = host.split(':').next().unwrap_or(host).to_lowercase();
+    let path = path.trim_matches('/').trim_end_matches(".git");
+    format!("{}/{}", host, path)
+}
+
+/// Whether tracked files have uncommitted changes; unknown if git isn't installed or is too slow
+fn is_dirty(root: &Path) -> Option<bool> {
+    let mut child = std::process::Command::new("git")
+        .arg("-C").arg(root)
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    // Drained as git writes, so a long status can't fill the pipe and stall it
+    let mut stdout = child.stdout.take()?;
+    let reader = std::thread::spawn(move || -> std::io::Result<bool> {
+        let mut buffer = [0u8; 8192];
+        let mut changed = false;
+        loop {
+            let read = stdout.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(changed);
+            }
+            changed |= buffer[..read].iter().any(|byte| !byte.is_ascii_whitespace());
+        }
+    });
+
+    let deadline = Instant::now() + GIT_STATUS_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => break,
+            Ok(Some(_)) | Err(_) => return None,
+            Ok(None) if Instant::now() >= deadline => {
+                debug!("git status in {} took too long, dirty state unknown", root.display());
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    reader.join().ok()?.ok()
+}
+
+/// Marker files and the language they indicate, most specific first
+const LANGUAGE_MARKERS: &[(&str, &str)] = &[
+    ("Cargo.toml", "rust"),
+    ("tsconfig.json", "typescript"),
+    ("package.json", "javascript"),
+    ("go.mod", "go"),
+    ("pyproject.toml", "python"),
+    ("setup.py", "python"),
+    ("requirements.txt", "python"),
+    ("pom.xml", "java"),
+    ("build.gradle.kts", "kotlin"),
+    ("build.gradle", "java"),
+    ("Gemfile", "ruby"),
+    ("composer.json", "php"),
+    ("mix.exs", "elixir"),
+    ("pubspec.yaml", "dart"),
+    ("Package.swift", "swift"),
+    ("CMakeLists.txt", "cpp"),
+];
+
+/// Project language, from marker files between the working directory and the repository root
+pub struct LanguageEnricher;
+
+impl Enricher for LanguageEnricher {
+    fn name(&self) -> &'static str {
+        "language"
+    }
+
+    fn enrich(&self, event: &ProcessEvent, tags: &mut BTreeMap<String, String>) {
+        let Some(cwd) = event.working_directory.as_deref() else {
+            return;
+        };
+        // Outside a repository only the working directory itself is checked
+        let stop = tags.get("repo_root").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(cwd));
+        for dir in Path::new(cwd).ancestors() {
+            if let Some(language) = detect_language(dir) {
+                tags.insert("language".to_string(), language.to_string());
+                return;
+            }
+            if dir == stop {
+                return;
+            }
+        }
+    }
+}
+
+fn detect_language(dir: &Path) -> Option<&'static str> {
+    if let Some((_, language)) = LANGUAGE_MARKERS.iter().find(|(marker, _)| dir.join(marker).is_file()) {
+        return Some(language);
+    }
+    // .NET projects are named after the project, so look at extensions
+    let entries = std::fs::read_dir(dir).ok()?;
+    entries.filter_map(|entry| entry.ok()).find_map(|entry| {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.ends_with(".csproj") || name.ends_with(".sln") {
+            Some("csharp")
+        } else if name.ends_with(".fsproj") {
+            Some("fsharp")
+        } else {
+            None
+        }
+    })
+}
+
+/// Largest justfile, Makefile or package.json read, so a generated one can't hold up enrichment
+const MAX_TASK_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Task runners: which task ran, and what it runs
+pub struct TaskEnricher;
+
+impl Enricher for TaskEnricher {
+    fn name(&self) -> &'static str {
+        "task"
+    }
+
+    fn enrich(&self, event: &ProcessEvent, tags: &mut BTreeMap<String, String>) {
+        let argv = unwrap_argv(&event.command);
+        let Some((program, args)) = argv.split_first() else {
+            return;
+        };
+        let files = TaskFiles {
+            cwd: event.working_directory.as_deref().map(PathBuf::from),
+            repo_root: tags.get("repo_root").map(PathBuf::from),
+        };
+        let Some((runner, task)) = find_task(program, args, &files) else {
+            return;
+        };
+        let task_command = match runner {
+            "just" => files.find(&["justfile", "Justfile", ".justfile"], true)
+                .and_then(|content| justfile_recipe(&content, &task)),
+            "make" => files.find(&["GNUmakefile", "makefile", "Makefile"], false)
+                .and_then(|content| makefile_target(&content, &task)),
+            "task" => None,
+            _ => package_script(&files, &task),
+        };
+        debug!("{} task {:?} runs {:?}", runner, task, task_command);
+        tags.insert("task_runner".to_string(), runner.to_string());
+        tags.insert("task".to_string(), task);
+        if let Some(task_command) = task_command {
+            tags.insert("task_command".to_string(), task_command);
+        }
+    }
+}
+
+/// Runner and task of a task runner invocation, with the default task looked up if none is given
+fn find_task(program: &str, args: &[String], files: &TaskFiles) -> Option<(&'static str, String)> {
+    match program {
+        "npm" => {
+            let positional = positional_args(args, &["--prefix", "-w", "--workspace"]);
+            let task = match positional.first().copied()? {
+                "run" | "run-script" | "rum" | "urn" => positional.get(1)?,
+                "test" | "t" | "tst" => "test",
+                subcommand @ ("start" | "stop" | "restart") => subcommand,
+                _ => return None,
+            };
+            Some(("npm", task.to_string()))
+        }
+        "yarn" | "pnpm" | "bun" => {
+            let runner = match program {
+                "yarn" => "yarn",
+                "pnpm" => "pnpm",
+                _ => "bun",
+            };
+            let positional = positional_args(args, &["--cwd", "--filter", "-F", "-C", "--dir"]);
+            let (task, named) = match positional.as_slice() {
+                ["run", task, ..] => (*task, true),
+                [task, ..] => (*task, false),
+                [] => return None,
+            };
+            // `yarn build` runs the script, `yarn add` doesn't; `bun run` runs files too
+            let is_script = || package_script(files, task).is_some();
+            ((named && runner != "bun") || is_script()).then(|| (runner, task.to_string()))
+        }
+        "just" => {
+            let positional = positional_args(args, &[
+                "-f", "--justfile", "-d", "--working-directory", "--shell", "--shell-arg", "--color",
+                "--dotenv-filename", "--dotenv-path", "--chooser", "--list-heading", "--list-prefix",
+            ]);
+            let task = positional.into_iter().find(|arg| !arg.contains('='))
+                .map(str::to_string)
+                .or_else(|| files.find(&["justfile", "Justfile", ".justfile"], true)
+                    .and_then(|content| justfile_recipes(&content).next().map(|(name, _)| name.to_string())))?;
+            Some(("just", task))
+        }
+        "make" | "gmake" => {
+            let positional = positional_args(args, &[
+                "-C", "-f", "-I", "-o", "-W", "--directory", "--file", "--makefile", "--include-dir",
+            ]);
+            let task = positional.into_iter().find(|arg| !arg.contains('='))
+                .map(str::to_string)
+                .or_else(|| files.find(&["GNUmakefile", "makefile", "Makefile"], false)
+                    .and_then(|content| makefile_targets(&content).next().map(|(names, _)| {
+                        names.split_whitespace().next().unwrap_or(names).to_string()
+                    })))?;
+            Some(("make", task))
+        }
+        "task" | "go-task" => {
+            let positional = positional_args(args, &[
+                "-d", "--dir", "-t", "--taskfile", "-o", "--output", "-C", "--concurrency", "-I", "--interval",
+            ]);
+            let task = positional.into_iter().find(|arg| !arg.contains('=')).unwrap_or("default");
+            Some(("task", task.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Arguments that aren't flags or flag values, up to `--`
+fn positional_args<'a>(args: &'a [String], value_flags: &[&str]) -> Vec<&'a str> {
+    let mut positional = Vec::new();
+    let mut args = args.iter().map(String::as_str).peekable();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if !arg.starts_with('-') {
+            positional.push(arg);
+        } else if value_flags.contains(&arg) {
+            args.next();
+        } else if matches!(arg, "-j" | "-l") && args.peek().is_some_and(|next| next.parse::<f64>().is_ok()) {
+            // make's `-j 8`; plain `-j` takes no value
+            args.next();
+        }
+    }
+    positional
+}
+
+/// Where to look for task files: the working directory and up to the repository root
+struct TaskFiles {
+    cwd: Option<PathBuf>,
+    repo_root: Option<PathBuf>,
+}
+
+impl TaskFiles {
+    /// Contents of the first of the files found; `upward` looks in parent directories too, like
+    /// just and npm, where make only reads the working directory
+    fn find(&self, names: &[&str], upward: bool) -> Option<String> {
+        let cwd = self.cwd.as_deref()?;
+        for dir in cwd.ancestors() {
+            if let Some(content) = names.iter().find_map(|name| read_task_file(&dir.join(name))) {
+                return Some(content);
+            }
+            if !upward || self.repo_root.as_deref().is_none_or(|root| dir == root) {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+fn read_task_file(path: &Path) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    if !file.metadata().ok()?.is_file() {
+        return None;
+    }
+    let mut content = String::new();
+    file.take(MAX_TASK_FILE_SIZE).read_to_string(&mut content).ok()?;
+    Some(content)
+}
+
+/// The script a package.json runs for a task
+fn package_script(files: &TaskFiles, task: &str) -> Option<String> {
+    let content = files.find(&["package.json"], true)?;
+    let package: serde_json::Value = serde_json::from_str(&content).ok()?;
+    package.get("scripts")?.get(task)?.as_str().map(str::to_string)
+}
+
+/// Recipes of a justfile with the first line of their body, in order
+fn justfile_recipes(content: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut recipes = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.starts_with([' ', '\t', '#', '[']) {
+            continue;
+        }
+        let Some((head, rest)) = line.split_once(':') else {
+            continue;
+        };
+        // `name := value` is an assignment
+        if rest.starts_with('=') {
+            continue;
+        }
+        let name = head.trim_start_matches('@').split_whitespace().next().unwrap_or("");
+        if name.is_empty()
+            || ["set", "alias", "export", "import", "mod"].contains(&name)
+            || !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        {
+            continue;
+        }
+        let body = lines[index + 1..].iter()
+            .take_while(|line| line.is_empty() || line.starts_with([' ', '\t']))
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.trim_start_matches(['@', '-']));
+        recipes.push((name, body));
+    }
+    recipes.into_iter()
+}
+
+fn justfile_recipe(content: &str, recipe: &str) -> Option<String> {
+    justfile_recipes(content).find(|(name, _)| *name == recipe)?.1.map(str::to_string)
+}
+
+/// Targets of a Makefile (space-separated when a rule has several) with the first line of their
+/// recipe, in order; special targets like `.PHONY` and pattern rules are left out
+fn makefile_targets(content: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut targets = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.starts_with(['\t', '#', '.', ' ']) {
+            continue;
+        }
+        let Some((names, rest)) = line.split_once(':') else {
+            continue;
+        };
+        // `VAR := value`, `VAR ::= value`, and `VAR = a:b`
+        if rest.starts_with([':', '=']) || names.contains(['=', '%', '$']) || names.trim().is_empty() {
+            continue;
+        }
+        let recipe = lines[index + 1..].iter()
+            .take_while(|line| line.is_empty() || line.starts_with('\t') || line.starts_with('#'))
+            .filter_map(|line| line.strip_prefix('\t'))
+            .map(|line| line.trim().trim_start_matches(['@', '-', '+']).trim_start())
+            .find(|line| !line.is_empty());
+        targets.push((names.trim(), recipe));
+    }
+    targets.into_iter()
+}
+
+fn makefile_target(content: &str, target: &str) -> Option<String> {
+    makefile_targets(content)
+        .find(|(names, _)| names.split_whitespace().any(|name| name == target))?
+        .1.map(str::to_string)
+}
+
+/// Program and arguments of a command line, looking through wrappers like `sudo` or `npx` to the
+/// command they run
+pub fn unwrap_argv(command: &str) -> Vec<String> {
+    let command_line = CommandLine::parse(command);
+    let mut argv: Vec<String> = std::iter::once(command_line.exe).chain(command_line.args).collect();
+    loop {
+        match argv.first().map(String::as_str) {
+            Some("sudo" | "npx" | "bunx" | "time" | "nice") => {
+                argv.remove(0);
+                if let Some(program) = argv.first_mut() {
+                    *program = crate::command_line::normalize_exe(program);
+                }
+            }
+            // `python -m pytest`
+            Some("python" | "python3" | "py") if argv.get(1).map(String::as_str) == Some("-m") => {
+                argv.drain(..2);
+            }
+            // Package managers as seen by ETW: `node .../npm-cli.js install`
+            Some("node") if argv.len() > 1 => {
+                let script = crate::command_line::normalize_exe(&argv[1]);
+                let Some(tool) = ["npm", "yarn", "pnpm"].into_iter().find(|tool| script.starts_with(tool)) else {
+                    break;
+                };
+                argv.drain(..2);
+                argv.insert(0, tool.to_string());
+            }
+            _ => break,
+        }
+    }
+    argv
+}
+
+/// Lifecycle scripts of npm/yarn/pnpm/bun: the one a process runs in, or the one a package manager
+/// command runs
+pub struct LifecycleEnricher;
+
+impl Enricher for LifecycleEnricher {
+    fn name(&self) -> &'static str {
+        "lifecycle"
+    }
+
+    fn enrich(&self, event: &ProcessEvent, tags: &mut BTreeMap<String, String>) {
+        let variable = |name: &str| event.environment.as_ref()
+            .and_then(|env| env.get(name))
+            .filter(|value| !value.is_empty())
+            .cloned();
+        // A package manager runs scripts of its own, whatever script it was started from
+        let argv = unwrap_argv(&event.command);
+        let lifecycle = match argv.split_first() {
+            Some((program, args)) if matches!(program.as_str(), "npm" | "yarn" | "pnpm" | "bun") => {
+                match positional_args(args, &["--prefix", "-w", "--workspace", "--cwd", "--filter", "-F", "-C", "--dir"]).first().copied() {
+                    // A bare `yarn` installs
+                    None if program == "yarn" => Some("install".to_string()),
+                    Some("install" | "i" | "ci" | "add") => Some("install".to_string()),
+                    _ if tags.get("task_runner") == Some(program) => tags.get("task").cloned(),
+                    _ => None,
+                }
+            }
+            // Set for the script and inherited by everything it starts, e.g. node-gyp in a postinstall
+            _ => variable("npm_lifecycle_event"),
+        };
+        if let Some(lifecycle) = lifecycle {
+            tags.insert("lifecycle".to_string(), lifecycle);
+        }
+        if let Some(package) = variable("npm_package_name") {
+            tags.insert("package".to_string(), package);
+        }
+    }
+}
+
+/// Commands by category: the program, and the subcommand if it needs one (`*` for any)
+const CATEGORIES: &[(&str, &[(&str, &str)])] = &[
+    ("test", &[
+        ("cargo", "test"), ("cargo", "nextest"), ("npm", "test"), ("yarn", "test"), ("pnpm", "test"),
+        ("bun", "test"), ("go", "test"), ("dotnet", "test"), ("mvn", "test"), ("gradle", "test"),
+        ("gradlew", "test"), ("bazel", "test"), ("pytest", "*"), ("jest", "*"), ("vitest", "*"),
+        ("tox", "*"), ("ctest", "*"), ("rspec", "*"), ("phpunit", "*"),
+    ]),
+    ("install", &[
+        ("npm", "install"), ("npm", "i"), ("npm", "ci"), ("yarn", "install"), ("yarn", "add"),
+        ("pnpm", "install"), ("pnpm", "add"), ("bun", "install"), ("pip", "install"), ("pip3", "install"),
+        ("uv", "sync"), ("poetry", "install"), ("cargo", "install"), ("go", "install"), ("go", "get"),
+        ("bundle", "install"), ("gem", "install"), ("composer", "install"), ("apt", "install"),
+        ("apt-get", "install"), ("dnf", "install"), ("yum", "install"), ("brew", "install"),
+        ("choco", "install"), ("winget", "install"), ("dotnet", "restore"),
+    ]),
+    ("deploy", &[
+        ("kubectl", "apply"), ("helm", "install"), ("helm", "upgrade"), ("terraform", "apply"),
+        ("tofu", "apply"), ("pulumi", "up"), ("fly", "deploy"), ("flyctl", "deploy"), ("vercel", "*"),
+        ("netlify", "deploy"), ("firebase", "deploy"), ("serverless", "deploy"), ("sls", "deploy"),
+        ("docker", "push"), ("cargo", "publish"), ("npm", "publish"),
+    ]),
+    ("build", &[
+        ("cargo", "build"), ("cargo", "check"), ("cargo", "run"), ("npm", "build"), ("yarn", "build"),
+        ("pnpm", "build"), ("bun", "build"), ("go", "build"), ("dotnet", "build"), ("dotnet", "publish"), ("mvn", "package"),
+        ("mvn", "compile"), ("mvn", "install"), ("gradle", "build"), ("gradle", "assemble"),
+        ("gradlew", "build"), ("gradlew", "assemble"), ("bazel", "build"), ("docker", "build"),
+        ("make", "*"), ("ninja", "*"), ("msbuild", "*"), ("tsc", "*"), ("webpack", "*"),
+        ("vite", "build"), ("next", "build"), ("cmake", "--build"), ("xcodebuild", "*"),
+    ]),
+];
+
+/// Classifies the command as build, test, install or deploy
+pub struct CategoryEnricher;
+
+impl Enricher for CategoryEnricher {
+    fn name(&self) -> &'static str {
+        "category"
+    }
+
+    fn enrich(&self, event: &ProcessEvent, tags: &mut BTreeMap<String, String>) {
+        // `npm run ci` is whatever its script does
+        let category = tags.get("task_command").and_then(|command| classify(command))
+            .or_else(|| classify(&event.command));
+        if let Some(category) = category {
+            tags.insert("category".to_string(), category.to_string());
+        }
+    }
+}
+
+/// Category of a command line, if it has one
+pub fn classify(command: &str) -> Option<&'static str> {
+    let argv = unwrap_argv(command);
+    let program = argv.first()?;
+    let mut positional = argv.iter().skip(1).filter(|arg| !arg.starts_with('-') || arg.as_str() == "--build");
+    let mut subcommand = positional.next();
+    // `npm run test` is categorized by the script it runs
+    if subcommand.is_some_and(|s| s == "run") && matches!(program.as_str(), "npm" | "yarn" | "pnpm" | "bun") {
+        subcommand = positional.next();
+    }
+    CATEGORIES.iter()
+        .find(|(_, commands)| {
+            commands.iter().any(|(exe, sub)| {
+                exe == program && (*sub == "*" || subcommand.is_some_and(|s| s == sub))
+            })
+        })
+        .map(|(category, _)| *category)
+}
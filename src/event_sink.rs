@@ -0,0 +1,227 @@
+// Event sinks
+// Republishes matched commands to external systems (e.g. home automation) over a socket, a
+// webhook or MQTT, as JSON: a `started`
+// message with the event, in the same schema the broker uses, and an `ended` message once the
+// command exits. Raw probe events aren't published, only commands a rule matched; the command's
+// environment variables are left out as they may hold secrets.
+// Each sink is delivered to by its own task, so a slow endpoint never holds up the event loop;
+// messages that can't be delivered are logged and dropped.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::config::{SinkConfig, SinkTarget};
+use crate::mqtt::MqttPublisher;
+use crate::platform::ProcessEvent;
+use crate::rule_engine::MatchedAction;
+
+/// Messages queued per sink before new ones are dropped
+const QUEUE_SIZE: usize = 64;
+/// Longest a single delivery may take
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What sinks receive, one JSON object per message
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkMessage {
+    Started {
+        /// Names of the rules that matched
+        rules: Vec<String>,
+        event: Box<ProcessEvent>,
+    },
+    Ended {
+        pid: u32,
+        event_id: String,
+        rules: Vec<String>,
+        duration_secs: u64,
+        /// If the probe knows it
+        exit_code: Option<i32>,
+    },
+}
+
+struct Sink {
+    /// Rule names this sink wants; all if empty
+    rules: Vec<String>,
+    sender: mpsc::Sender<Arc<SinkMessage>>,
+    name: String,
+}
+
+impl Sink {
+    fn wants(&self, rules: &[String]) -> bool {
+        self.rules.is_empty() || rules.iter().any(|rule| self.rules.contains(rule))
+    }
+}
+
+/// A matched command that is still running
+struct MatchedCommand {
+    event_id: String,
+    rules: Vec<String>,
+    started: Instant,
+}
+
+pub struct EventSinks {
+    sinks: Vec<Sink>,
+    running: Mutex<HashMap<u32, MatchedCommand>>,
+}
+
+impl EventSinks {
+    /// Starts a delivery task for every configured sink
+    pub fn new(configs: &[SinkConfig]) -> Self {
+        let sinks = configs.iter().map(|config| {
+            let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+            let name = describe(&config.target);
+            tokio::spawn(deliver(config.target.clone(), name.clone(), receiver));
+            Sink { rules: config.rules.clone(), sender, name }
+        }).collect();
+        Self {
+            sinks,
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Publishes a command that matched rules
+    pub fn matched(&self, event: &ProcessEvent, actions: &[MatchedAction]) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        let mut rules: Vec<String> = Vec::new();
+        for matched in actions {
+            if !rules.contains(&matched.rule_name) {
+                rules.push(matched.rule_name.clone());
+            }
+        }
+        self.running.lock().unwrap().insert(event.pid, MatchedCommand {
+            event_id: event.event_id.clone(),
+            rules: rules.clone(),
+            started: Instant::now(),
+        });
+
+        let mut event = event.clone();
+        event.environment = None;
+        self.publish(SinkMessage::Started { rules, event: Box::new(event) });
+    }
+
+    /// Publishes the end of a matched command; other PIDs are ignored
+    pub fn ended(&self, pid: u32, exit_code: Option<i32>) {
+        let Some(command) = self.running.lock().unwrap().remove(&pid) else {
+            return;
+        };
+        self.publish(SinkMessage::Ended {
+            pid,
+            event_id: command.event_id,
+            rules: command.rules,
+            duration_secs: command.started.elapsed().as_secs(),
+            exit_code,
+        });
+    }
+
+    fn publish(&self, message: SinkMessage) {
+        let rules = match &message {
+            SinkMessage::Started { rules, .. } | SinkMessage::Ended { rules, .. } => rules.clone(),
+        };
+        let message = Arc::new(message);
+        for sink in self.sinks.iter().filter(|sink| sink.wants(&rules)) {
+            if sink.sender.try_send(Arc::clone(&message)).is_err() {
+                warn!("Event sink {} is falling behind, dropping a message", sink.name);
+            }
+        }
+    }
+}
+
+fn describe(target: &SinkTarget) -> String {
+    match target {
+        SinkTarget::Socket { path } => format!("socket {}", path),
+        SinkTarget::Webhook { url, .. } => format!("webhook {}", url),
+        SinkTarget::Mqtt { connection, topic, .. } => format!("MQTT {} at {}", topic, connection.url),
+    }
+}
+
+/// Delivers a sink's messages one at a time until the service stops
+async fn deliver(target: SinkTarget, name: String, mut receiver: mpsc::Receiver<Arc<SinkMessage>>) {
+    let client = reqwest::Client::new();
+    let mqtt = match &target {
+        SinkTarget::Mqtt { connection, .. } => match MqttPublisher::connect(connection) {
+            Ok(publisher) => Some(publisher),
+            Err(e) => {
+                warn!("Event sink {} is unusable: {}", name, e);
+                return;
+            }
+        },
+        _ => None,
+    };
+    while let Some(message) = receiver.recv().await {
+        let result = match &target {
+            SinkTarget::Socket { path } => tokio::time::timeout(SEND_TIMEOUT, write_socket(path, &message)).await,
+            SinkTarget::Webhook { url, headers } => {
+                tokio::time::timeout(SEND_TIMEOUT, post_webhook(&client, url, headers, &message)).await
+            }
+            SinkTarget::Mqtt { topic, retain, .. } => {
+                let publisher = mqtt.as_ref().expect("MQTT sinks connect before delivering");
+                tokio::time::timeout(SEND_TIMEOUT, publish_mqtt(publisher, topic, *retain, &message)).await
+            }
+        };
+        match result {
+            Ok(Ok(())) => debug!("Published to event sink {}", name),
+            Ok(Err(e)) => warn!("Failed to publish to event sink {}: {}", name, e),
+            Err(_) => warn!("Event sink {} didn't accept a message within {}s", name, SEND_TIMEOUT.as_secs()),
+        }
+    }
+}
+
+/// Writes a message as a JSON line, on a connection of its own
+async fn write_socket(path: &str, message: &SinkMessage) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+
+    let mut stream = connect(path).await?;
+    stream.write_all(line.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn connect(path: &str) -> Result<tokio::net::UnixStream, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(tokio::net::UnixStream::connect(path).await?)
+}
+
+#[cfg(windows)]
+async fn connect(path: &str) -> Result<tokio::net::windows::named_pipe::NamedPipeClient, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(tokio::net::windows::named_pipe::ClientOptions::new().open(path)?)
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn connect(_path: &str) -> Result<tokio::io::Sink, Box<dyn std::error::Error + Send + Sync>> {
+    Err("Socket sinks aren't supported on this platform".into())
+}
+
+async fn post_webhook(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &BTreeMap<String, String>,
+    message: &SinkMessage,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut request = client.post(url).json(message);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+async fn publish_mqtt(
+    publisher: &MqttPublisher,
+    topic: &str,
+    retain: bool,
+    message: &SinkMessage,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    publisher.publish(topic, retain, serde_json::to_vec(message)?).await
+}
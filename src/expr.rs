@@ -0,0 +1,563 @@
+// Small expression language for rule `when` conditions
+//
+// Example:
+//   cmd.exe == 'cargo' && args.contains('build') && duration_estimate > 20s
+//
+// Supported syntax:
+// - literals: 'strings', "strings", numbers, durations (`90s`, `5m`, `1h`), true/false
+// - variables: see `EvalContext`, with `.field` access on maps
+// - methods: contains, starts_with, ends_with, matches (glob), len
+// - operators: == != < <= > >= && || ! and parentheses
+//
+// Expressions are compiled once at config load so syntax errors are reported immediately.
+
+use globset::Glob;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Runtime value of an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Number(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::List(items) => !items.is_empty(),
+            Value::Map(fields) => !fields.is_empty(),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<Option<&str>> for Value {
+    fn from(s: Option<&str>) -> Self {
+        s.map(Value::from).unwrap_or(Value::Null)
+    }
+}
+
+/// Variables available to an expression
+pub type EvalContext = BTreeMap<String, Value>;
+
+/// A syntax or evaluation error, with the character column where it occurred
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExprError {
+    pub message: String,
+    pub column: usize,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(Value),
+    Variable(String),
+    Field(Box<Node>, String),
+    Call { target: Box<Node>, method: String, args: Vec<Node>, column: usize },
+    Not(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+/// A compiled `when` expression
+#[derive(Debug, Clone)]
+pub struct Expr {
+    root: Node,
+}
+
+impl Expr {
+    /// Parses an expression, reporting the position of the first syntax error
+    pub fn parse(source: &str) -> Result<Self, ExprError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let root = parser.parse_or()?;
+        match parser.peek() {
+            Token { kind: TokenKind::End, .. } => Ok(Self { root }),
+            token => Err(ExprError {
+                message: format!("unexpected {}", token.kind.describe()),
+                column: token.column,
+            }),
+        }
+    }
+
+    /// Evaluates the expression to a boolean against the given variables
+    pub fn eval(&self, context: &EvalContext) -> Result<bool, ExprError> {
+        Ok(eval_node(&self.root, context)?.truthy())
+    }
+
+    /// Names of the variables the expression reads, e.g. `cmd` for `cmd.exe == 'cargo'`
+    pub fn variables(&self) -> Vec<&str> {
+        fn collect<'a>(node: &'a Node, names: &mut Vec<&'a str>) {
+            match node {
+                Node::Literal(_) => {}
+                Node::Variable(name) => names.push(name),
+                Node::Field(target, _) | Node::Not(target) => collect(target, names),
+                Node::Call { target, args, .. } => {
+                    collect(target, names);
+                    args.iter().for_each(|arg| collect(arg, names));
+                }
+                Node::Binary(_, left, right) => {
+                    collect(left, names);
+                    collect(right, names);
+                }
+            }
+        }
+        let mut names = Vec::new();
+        collect(&self.root, &mut names);
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tokenizer
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Str(String),
+    Number(f64),
+    Dot,
+    Comma,
+    LParen,
+    RParen,
+    Not,
+    Op(BinaryOp),
+    End,
+}
+
+impl TokenKind {
+    fn describe(&self) -> String {
+        match self {
+            TokenKind::Ident(name) => format!("identifier '{}'", name),
+            TokenKind::Str(s) => format!("string '{}'", s),
+            TokenKind::Number(n) => format!("number {}", n),
+            TokenKind::Dot => "'.'".to_string(),
+            TokenKind::Comma => "','".to_string(),
+            TokenKind::LParen => "'('".to_string(),
+            TokenKind::RParen => "')'".to_string(),
+            TokenKind::Not => "'!'".to_string(),
+            TokenKind::Op(op) => format!("operator {:?}", op),
+            TokenKind::End => "end of expression".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ExprError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let two: String = chars[i..chars.len().min(i + 2)].iter().collect();
+        let op = match two.as_str() {
+            "==" => Some(BinaryOp::Eq),
+            "!=" => Some(BinaryOp::Ne),
+            "<=" => Some(BinaryOp::Le),
+            ">=" => Some(BinaryOp::Ge),
+            "&&" => Some(BinaryOp::And),
+            "||" => Some(BinaryOp::Or),
+            _ => None,
+        };
+        if let Some(op) = op {
+            tokens.push(Token { kind: TokenKind::Op(op), column });
+            i += 2;
+            continue;
+        }
+
+        let kind = match c {
+            '.' => Some(TokenKind::Dot),
+            ',' => Some(TokenKind::Comma),
+            '(' => Some(TokenKind::LParen),
+            ')' => Some(TokenKind::RParen),
+            '!' => Some(TokenKind::Not),
+            '<' => Some(TokenKind::Op(BinaryOp::Lt)),
+            '>' => Some(TokenKind::Op(BinaryOp::Gt)),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            tokens.push(Token { kind, column });
+            i += 1;
+            continue;
+        }
+
+        if c == '\'' || c == '"' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('\\') if chars.get(i + 1).is_some() => {
+                        value.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some(&q) if q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(&other) => {
+                        value.push(other);
+                        i += 1;
+                    }
+                    None => {
+                        return Err(ExprError { message: "unterminated string".to_string(), column });
+                    }
+                }
+            }
+            tokens.push(Token { kind: TokenKind::Str(value), column });
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            let mut value: f64 = number.parse().map_err(|_| ExprError {
+                message: format!("invalid number '{}'", number),
+                column,
+            })?;
+
+            // Duration suffixes convert to seconds
+            let unit_start = i;
+            while i < chars.len() && chars[i].is_ascii_alphabetic() {
+                i += 1;
+            }
+            let unit: String = chars[unit_start..i].iter().collect();
+            value *= match unit.as_str() {
+                "" | "s" => 1.0,
+                "ms" => 0.001,
+                "m" => 60.0,
+                "h" => 3600.0,
+                other => {
+                    return Err(ExprError {
+                        message: format!("unknown duration unit '{}' (expected ms, s, m or h)", other),
+                        column,
+                    });
+                }
+            };
+            tokens.push(Token { kind: TokenKind::Number(value), column });
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().collect();
+            tokens.push(Token { kind: TokenKind::Ident(ident), column });
+            continue;
+        }
+
+        return Err(ExprError { message: format!("unexpected character '{}'", c), column });
+    }
+
+    tokens.push(Token { kind: TokenKind::End, column: chars.len() + 1 });
+    Ok(tokens)
+}
+
+// ---------------------------------------------------------------------------
+// Parser (recursive descent)
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<(), ExprError> {
+        let token = self.advance();
+        if token.kind == kind {
+            Ok(())
+        } else {
+            Err(ExprError {
+                message: format!("expected {}, found {}", kind.describe(), token.kind.describe()),
+                column: token.column,
+            })
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Node, ExprError> {
+        let mut left = self.parse_and()?;
+        while self.peek().kind == TokenKind::Op(BinaryOp::Or) {
+            self.advance();
+            let right = self.parse_and()?;
+            left = Node::Binary(BinaryOp::Or, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, ExprError> {
+        let mut left = self.parse_not()?;
+        while self.peek().kind == TokenKind::Op(BinaryOp::And) {
+            self.advance();
+            let right = self.parse_not()?;
+            left = Node::Binary(BinaryOp::And, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Node, ExprError> {
+        if self.peek().kind == TokenKind::Not {
+            self.advance();
+            return Ok(Node::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Node, ExprError> {
+        let left = self.parse_postfix()?;
+        if let TokenKind::Op(op) = self.peek().kind {
+            if !matches!(op, BinaryOp::And | BinaryOp::Or) {
+                self.advance();
+                let right = self.parse_postfix()?;
+                return Ok(Node::Binary(op, Box::new(left), Box::new(right)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn parse_postfix(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.parse_primary()?;
+        while self.peek().kind == TokenKind::Dot {
+            self.advance();
+            let token = self.advance();
+            let TokenKind::Ident(name) = token.kind else {
+                return Err(ExprError {
+                    message: format!("expected field or method name, found {}", token.kind.describe()),
+                    column: token.column,
+                });
+            };
+
+            if self.peek().kind == TokenKind::LParen {
+                self.advance();
+                let mut args = Vec::new();
+                if self.peek().kind != TokenKind::RParen {
+                    loop {
+                        args.push(self.parse_or()?);
+                        if self.peek().kind == TokenKind::Comma {
+                            self.advance();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                self.expect(TokenKind::RParen)?;
+                check_method(&name, args.len(), token.column)?;
+                node = Node::Call { target: Box::new(node), method: name, args, column: token.column };
+            } else {
+                node = Node::Field(Box::new(node), name);
+            }
+        }
+        Ok(node)
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, ExprError> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Str(s) => Ok(Node::Literal(Value::Str(s))),
+            TokenKind::Number(n) => Ok(Node::Literal(Value::Number(n))),
+            TokenKind::Ident(name) => match name.as_str() {
+                "true" => Ok(Node::Literal(Value::Bool(true))),
+                "false" => Ok(Node::Literal(Value::Bool(false))),
+                "null" => Ok(Node::Literal(Value::Null)),
+                _ => Ok(Node::Variable(name)),
+            },
+            TokenKind::LParen => {
+                let inner = self.parse_or()?;
+                self.expect(TokenKind::RParen)?;
+                Ok(inner)
+            }
+            other => Err(ExprError {
+                message: format!("expected a value, found {}", other.describe()),
+                column: token.column,
+            }),
+        }
+    }
+}
+
+fn check_method(name: &str, arg_count: usize, column: usize) -> Result<(), ExprError> {
+    let expected = match name {
+        "contains" | "starts_with" | "ends_with" | "matches" => 1,
+        "len" => 0,
+        _ => {
+            return Err(ExprError { message: format!("unknown method '{}'", name), column });
+        }
+    };
+    if arg_count != expected {
+        return Err(ExprError {
+            message: format!("method '{}' takes {} argument(s), got {}", name, expected, arg_count),
+            column,
+        });
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Evaluation
+
+fn eval_node(node: &Node, context: &EvalContext) -> Result<Value, ExprError> {
+    match node {
+        Node::Literal(value) => Ok(value.clone()),
+        // Unknown variables evaluate to null so rules written against newer fields degrade gracefully
+        Node::Variable(name) => Ok(context.get(name).cloned().unwrap_or(Value::Null)),
+        Node::Field(target, field) => match eval_node(target, context)? {
+            Value::Map(mut fields) => Ok(fields.remove(field).unwrap_or(Value::Null)),
+            _ => Ok(Value::Null),
+        },
+        Node::Not(inner) => Ok(Value::Bool(!eval_node(inner, context)?.truthy())),
+        Node::Binary(BinaryOp::And, left, right) => {
+            Ok(Value::Bool(eval_node(left, context)?.truthy() && eval_node(right, context)?.truthy()))
+        }
+        Node::Binary(BinaryOp::Or, left, right) => {
+            Ok(Value::Bool(eval_node(left, context)?.truthy() || eval_node(right, context)?.truthy()))
+        }
+        Node::Binary(op, left, right) => {
+            let left = eval_node(left, context)?;
+            let right = eval_node(right, context)?;
+            Ok(Value::Bool(compare(*op, &left, &right)))
+        }
+        Node::Call { target, method, args, column } => {
+            let target = eval_node(target, context)?;
+            let args = args.iter()
+                .map(|arg| eval_node(arg, context))
+                .collect::<Result<Vec<_>, _>>()?;
+            call_method(&target, method, &args, *column)
+        }
+    }
+}
+
+fn compare(op: BinaryOp, left: &Value, right: &Value) -> bool {
+    match op {
+        BinaryOp::Eq => left == right,
+        BinaryOp::Ne => left != right,
+        _ => {
+            let ordering = match (left, right) {
+                (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+                (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+                // Comparisons involving null (e.g. an unknown duration estimate) are never true
+                _ => None,
+            };
+            match ordering {
+                Some(ordering) => match op {
+                    BinaryOp::Lt => ordering.is_lt(),
+                    BinaryOp::Le => ordering.is_le(),
+                    BinaryOp::Gt => ordering.is_gt(),
+                    BinaryOp::Ge => ordering.is_ge(),
+                    _ => false,
+                },
+                None => false,
+            }
+        }
+    }
+}
+
+fn call_method(target: &Value, method: &str, args: &[Value], column: usize) -> Result<Value, ExprError> {
+    let type_error = || ExprError {
+        message: format!("method '{}' is not supported on {}", method, target.type_name()),
+        column,
+    };
+
+    match (method, target) {
+        ("len", Value::Str(s)) => Ok(Value::Number(s.chars().count() as f64)),
+        ("len", Value::List(items)) => Ok(Value::Number(items.len() as f64)),
+        ("len", Value::Null) => Ok(Value::Number(0.0)),
+        ("contains", Value::List(items)) => Ok(Value::Bool(items.contains(&args[0]))),
+        ("contains", Value::Str(s)) => match &args[0] {
+            Value::Str(needle) => Ok(Value::Bool(s.contains(needle.as_str()))),
+            _ => Ok(Value::Bool(false)),
+        },
+        ("starts_with", Value::Str(s)) => match &args[0] {
+            Value::Str(prefix) => Ok(Value::Bool(s.starts_with(prefix.as_str()))),
+            _ => Ok(Value::Bool(false)),
+        },
+        ("ends_with", Value::Str(s)) => match &args[0] {
+            Value::Str(suffix) => Ok(Value::Bool(s.ends_with(suffix.as_str()))),
+            _ => Ok(Value::Bool(false)),
+        },
+        ("matches", Value::Str(s)) => match &args[0] {
+            Value::Str(pattern) => {
+                let glob = Glob::new(pattern).map_err(|e| ExprError {
+                    message: format!("invalid glob '{}': {}", pattern, e),
+                    column,
+                })?;
+                Ok(Value::Bool(glob.compile_matcher().is_match(s)))
+            }
+            _ => Ok(Value::Bool(false)),
+        },
+        // Methods on missing values are simply false, e.g. `cwd.starts_with(...)` without a cwd
+        (_, Value::Null) => Ok(Value::Bool(false)),
+        _ => Err(type_error()),
+    }
+}
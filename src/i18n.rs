@@ -0,0 +1,407 @@
+// Localization of user-facing text
+// Setup prompts, terminal notices and the generated config header go through `t`/`tf` so they can be
+// shown in the user's language. Log messages stay in English so bug reports remain readable.
+//
+// The locale comes from `locale` in config.toml, falling back to LC_ALL/LC_MESSAGES/LANG (or the
+// Windows user locale). Missing translations fall back to English.
+//
+// Adding a locale: add a `Locale` variant, map its language code in `Locale::from_code`, and write a
+// translation function like `de` below. Untranslated messages can simply return None.
+
+use std::fmt::Display;
+use std::sync::RwLock;
+
+/// Languages with a message catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    De,
+    Es,
+}
+
+impl Locale {
+    /// Parses a language tag such as `de`, `de_DE.UTF-8` or `es-MX`
+    pub fn from_code(code: &str) -> Option<Self> {
+        let language = code.split(['_', '-', '.', '@']).next()?.to_ascii_lowercase();
+        match language.as_str() {
+            "en" => Some(Locale::En),
+            "de" => Some(Locale::De),
+            "es" => Some(Locale::Es),
+            _ => None,
+        }
+    }
+}
+
+static LOCALE: RwLock<Option<Locale>> = RwLock::new(None);
+
+/// Selects the locale, preferring the configured one over the environment
+pub fn init(configured: Option<&str>) {
+    let locale = configured.and_then(Locale::from_code).unwrap_or_else(detect_locale);
+    *LOCALE.write().unwrap() = Some(locale);
+}
+
+/// The active locale, detected from the environment if `init` hasn't run yet
+pub fn current() -> Locale {
+    if let Some(locale) = *LOCALE.read().unwrap() {
+        return locale;
+    }
+    detect_locale()
+}
+
+fn detect_locale() -> Locale {
+    for var in ["LC_ALL", "LC_MESSAGES", "LANG"] {
+        if let Ok(value) = std::env::var(var) {
+            if !value.is_empty() {
+                return Locale::from_code(&value).unwrap_or(Locale::En);
+            }
+        }
+    }
+    system_locale().and_then(|code| Locale::from_code(&code)).unwrap_or(Locale::En)
+}
+
+#[cfg(windows)]
+fn system_locale() -> Option<String> {
+    use windows::Win32::Globalization::GetUserDefaultLocaleName;
+
+    let mut buffer = [0u16; 85]; // LOCALE_NAME_MAX_LENGTH
+    let len = unsafe { GetUserDefaultLocaleName(&mut buffer) };
+    if len <= 1 {
+        return None;
+    }
+    Some(String::from_utf16_lossy(&buffer[..len as usize - 1]))
+}
+
+#[cfg(not(windows))]
+fn system_locale() -> Option<String> {
+    None
+}
+
+/// User-facing messages. `{}` placeholders are filled in order by `tf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // Shell setup and sandbox messages are only shown on Linux/macOS, buttons on Windows
+pub enum Msg {
+    LogLocation,
+    ConfigHeader,
+    SetupWelcome,
+    SetupIntro,
+    SetupManualLine,
+    SetupBashImportant,
+    SetupBashPreexec,
+    SetupSee,
+    SetupPrompt,
+    /// Letters accepted as "yes" at the setup prompt, in addition to `y`
+    SetupYesLetters,
+    SetupRestartShell,
+    SetupCancelled,
+    SetupLater,
+    SetupSuppressHint,
+    SetupRunning,
+    SetupNohupTip,
+    SetupIncomplete,
+    InstallingPreexec,
+    InstallSuccess,
+    InstallCreated,
+    InstallUpdated,
+    SandboxFlatpak,
+    SandboxFlatpakGrant,
+    SandboxSnap,
+    SandboxSnapConfined,
+    SandboxContainer,
+    SandboxContainerShared,
+    SandboxTcpSuggest,
+    NotifyOpenTerminal,
+    NotifySnooze,
+    NotifyDismiss,
+    BudgetTitle,
+    BudgetMessage,
+    DistractionOverTitle,
+    DistractionOverMessage,
+    MissingOverlayTitle,
+    MissingOverlayMessage,
+    MissingActionTitle,
+    MissingActionMessage,
+    AchievementTitle,
+    AchievementSatThrough,
+    AchievementSatThroughInfo,
+    AchievementOnARoll,
+    AchievementOnARollInfo,
+    AchievementUnskippable,
+    AchievementUnskippableInfo,
+    AchievementConnoisseur,
+    AchievementConnoisseurInfo,
+    AchievementSpeedrunner,
+    AchievementSpeedrunnerInfo,
+    /// Spoken by `speak` actions; `{command}`, `{duration}` and `{outcome}` are filled in
+    SpeakDefault,
+    /// Spoken by `speak` actions when the exit code is unknown
+    SpeakDefaultNoOutcome,
+    SpeakSucceeded,
+    SpeakFailed,
+    SpeakNoOutcome,
+    SpeakSecond,
+    SpeakSeconds,
+    SpeakMinute,
+    SpeakMinutes,
+    SpeakHour,
+    SpeakHours,
+}
+
+/// Translates a message into the active locale
+pub fn t(msg: Msg) -> &'static str {
+    let translated = match current() {
+        Locale::En => None,
+        Locale::De => de(msg),
+        Locale::Es => es(msg),
+    };
+    translated.unwrap_or_else(|| en(msg))
+}
+
+/// Translates a message and fills its `{}` placeholders in order
+pub fn tf(msg: Msg, args: &[&dyn Display]) -> String {
+    let mut parts = t(msg).split("{}");
+    let mut text = parts.next().unwrap_or_default().to_string();
+    for (index, part) in parts.enumerate() {
+        if let Some(arg) = args.get(index) {
+            text.push_str(&arg.to_string());
+        }
+        text.push_str(part);
+    }
+    text
+}
+
+fn en(msg: Msg) -> &'static str {
+    match msg {
+        Msg::LogLocation => "VibeRot logs will be written to: {}",
+        Msg::ConfigHeader => "# VibeRot Configuration File
+#
+# This file defines rules for intercepting and handling commands.
+# Each rule consists of command pattern(s) and action(s) to execute.
+#
+# Rules support the following:
+# - Optional name, passed to actions as VIBEROT_RULE_NAME
+# - Single command or array of commands
+# - Single action or array of actions
+",
+        Msg::SetupWelcome => "Welcome to VibeRot!",
+        Msg::SetupIntro => "To enable VibeRot to react to shell commands, please do the following.",
+        Msg::SetupManualLine => "Or manually add the following line to your shell configuration:",
+        Msg::SetupBashImportant => "IMPORTANT: For bash users:",
+        Msg::SetupBashPreexec => "  You need to install preexec and precmd functions for bash.",
+        Msg::SetupSee => "  See: {}",
+        Msg::SetupPrompt => "Would you like VibeRot to set things up automatically for you? [y/N]: ",
+        Msg::SetupYesLetters => "y",
+        Msg::SetupRestartShell => "Restart your shell or run the following to apply the changes:",
+        Msg::SetupCancelled => "Cancelled.",
+        Msg::SetupLater => "The service will continue running. You can set up shell integration later.",
+        Msg::SetupSuppressHint => "Note: To suppress this prompt in the future without setting up integration, you can create an empty file at:",
+        Msg::SetupRunning => "You can now let VibeRot run in the background and it will launch brainrot when a configured command is executed.",
+        Msg::SetupNohupTip => "Tip: use nohup to keep it running after you close the terminal.",
+        Msg::SetupIncomplete => "\n⚠️  Shell integration setup was not completed.
+The VibeRot service will continue running, but shell command monitoring will not work
+until you manually set up the integration as described above.\n",
+        Msg::InstallingPreexec => "Installing bash-preexec for bash...",
+        Msg::InstallSuccess => "\n✅ Shell integration installed successfully!",
+        Msg::InstallCreated => "  Created: {}",
+        Msg::InstallUpdated => "  Updated: {}",
+        Msg::SandboxFlatpak => "This terminal appears to be running inside a Flatpak sandbox.",
+        Msg::SandboxFlatpakGrant => "Shells in the sandbox may not be able to see the default socket. Either grant access to it:",
+        Msg::SandboxSnap => "This terminal appears to be running inside the '{}' snap.",
+        Msg::SandboxSnapConfined => "Strictly confined snaps can't reach sockets outside their own runtime directory.
+Point the shell probe at a socket inside the snap's user data instead:",
+        Msg::SandboxContainer => "This terminal appears to be running inside a {} container.",
+        Msg::SandboxContainerShared => "Shells on the host (or in other containers) need a socket path that is shared with this container,
+for example one inside your home directory:",
+        Msg::SandboxTcpSuggest => "Or enable the localhost TCP fallback, which sandboxed shells use automatically:",
+        Msg::NotifyOpenTerminal => "Open terminal",
+        Msg::NotifySnooze => "Snooze rule 1h",
+        Msg::NotifyDismiss => "Dismiss",
+        Msg::BudgetTitle => "Daily budget used up",
+        Msg::BudgetMessage => "That's {} of brainrot for today. Go touch grass.",
+        Msg::DistractionOverTitle => "Your command is done",
+        Msg::DistractionOverMessage => "{} finished. Back to work!",
+        Msg::MissingOverlayTitle => "The overlay isn't installed",
+        Msg::MissingOverlayMessage => "Run `viberot-service setup-actions` to get it.",
+        Msg::MissingActionTitle => "An action's program is missing",
+        Msg::MissingActionMessage => "{} doesn't exist, so its rules can't start it. Check the path in the config.",
+        Msg::AchievementTitle => "Achievement unlocked: {}",
+        Msg::AchievementSatThrough => "Sat through it",
+        Msg::AchievementSatThroughInfo => "Let an overlay run until its command finished",
+        Msg::AchievementOnARoll => "On a roll",
+        Msg::AchievementOnARollInfo => "Sat through 5 overlays in a row",
+        Msg::AchievementUnskippable => "Unskippable",
+        Msg::AchievementUnskippableInfo => "Sat through 25 overlays in a row",
+        Msg::AchievementConnoisseur => "Brainrot connoisseur",
+        Msg::AchievementConnoisseurInfo => "Sat through 100 overlays",
+        Msg::AchievementSpeedrunner => "Speedrunner",
+        Msg::AchievementSpeedrunnerInfo => "Dismissed 10 overlays within 10 seconds",
+        Msg::SpeakDefault => "{command} finished after {duration}, {outcome}",
+        Msg::SpeakDefaultNoOutcome => "{command} finished after {duration}",
+        Msg::SpeakSucceeded => "it worked",
+        Msg::SpeakFailed => "it failed",
+        Msg::SpeakNoOutcome => "it ended",
+        Msg::SpeakSecond => "{} second",
+        Msg::SpeakSeconds => "{} seconds",
+        Msg::SpeakMinute => "{} minute",
+        Msg::SpeakMinutes => "{} minutes",
+        Msg::SpeakHour => "{} hour",
+        Msg::SpeakHours => "{} hours",
+    }
+}
+
+fn de(msg: Msg) -> Option<&'static str> {
+    Some(match msg {
+        Msg::LogLocation => "VibeRot schreibt Logs nach: {}",
+        Msg::ConfigHeader => "# VibeRot-Konfigurationsdatei
+#
+# Diese Datei legt Regeln fest, die Befehle abfangen und darauf reagieren.
+# Jede Regel besteht aus Befehlsmuster(n) und auszuführenden Aktion(en).
+#
+# Regeln unterstützen:
+# - Optionalen Namen, der an Aktionen als VIBEROT_RULE_NAME übergeben wird
+# - Einen einzelnen Befehl oder eine Liste von Befehlen
+# - Eine einzelne Aktion oder eine Liste von Aktionen
+",
+        Msg::SetupWelcome => "Willkommen bei VibeRot!",
+        Msg::SetupIntro => "Damit VibeRot auf Shell-Befehle reagieren kann, führe bitte Folgendes aus.",
+        Msg::SetupManualLine => "Oder füge diese Zeile manuell zu deiner Shell-Konfiguration hinzu:",
+        Msg::SetupBashImportant => "WICHTIG für bash-Nutzer:",
+        Msg::SetupBashPreexec => "  Für bash müssen die Funktionen preexec und precmd installiert sein.",
+        Msg::SetupSee => "  Siehe: {}",
+        Msg::SetupPrompt => "Soll VibeRot alles automatisch für dich einrichten? [j/N]: ",
+        Msg::SetupYesLetters => "j",
+        Msg::SetupRestartShell => "Starte deine Shell neu oder führe Folgendes aus, um die Änderungen zu übernehmen:",
+        Msg::SetupCancelled => "Abgebrochen.",
+        Msg::SetupLater => "Der Dienst läuft weiter. Du kannst die Shell-Integration später einrichten.",
+        Msg::SetupSuppressHint => "Hinweis: Um diese Abfrage künftig ohne Einrichtung zu unterdrücken, lege eine leere Datei an unter:",
+        Msg::SetupRunning => "VibeRot kann jetzt im Hintergrund laufen und startet Brainrot, sobald ein konfigurierter Befehl ausgeführt wird.",
+        Msg::SetupNohupTip => "Tipp: Mit nohup läuft es weiter, nachdem du das Terminal geschlossen hast.",
+        Msg::SetupIncomplete => "\n⚠️  Die Einrichtung der Shell-Integration wurde nicht abgeschlossen.
+Der VibeRot-Dienst läuft weiter, aber Shell-Befehle werden erst erkannt,
+wenn du die Integration wie oben beschrieben manuell einrichtest.\n",
+        Msg::InstallingPreexec => "Installiere bash-preexec für bash...",
+        Msg::InstallSuccess => "\n✅ Shell-Integration erfolgreich installiert!",
+        Msg::InstallCreated => "  Erstellt: {}",
+        Msg::InstallUpdated => "  Aktualisiert: {}",
+        Msg::SandboxFlatpak => "Dieses Terminal scheint in einer Flatpak-Sandbox zu laufen.",
+        Msg::SandboxFlatpakGrant => "Shells in der Sandbox sehen den Standard-Socket eventuell nicht. Gib entweder Zugriff darauf:",
+        Msg::SandboxSnap => "Dieses Terminal scheint im Snap '{}' zu laufen.",
+        Msg::SandboxSnapConfined => "Streng isolierte Snaps erreichen keine Sockets außerhalb ihres eigenen Laufzeitverzeichnisses.
+Richte die Shell-Probe stattdessen auf einen Socket in den Benutzerdaten des Snaps:",
+        Msg::SandboxContainer => "Dieses Terminal scheint in einem {}-Container zu laufen.",
+        Msg::SandboxContainerShared => "Shells auf dem Host (oder in anderen Containern) brauchen einen Socket-Pfad, den sie mit diesem Container teilen,
+zum Beispiel einen in deinem Home-Verzeichnis:",
+        Msg::SandboxTcpSuggest => "Oder aktiviere den lokalen TCP-Fallback, den Shells in Sandboxes automatisch nutzen:",
+        Msg::NotifyOpenTerminal => "Terminal öffnen",
+        Msg::NotifySnooze => "Regel 1 Std. pausieren",
+        Msg::NotifyDismiss => "Schließen",
+        Msg::BudgetTitle => "Tagesbudget aufgebraucht",
+        Msg::BudgetMessage => "Das waren {} Brainrot für heute. Geh mal raus.",
+        Msg::DistractionOverTitle => "Dein Befehl ist fertig",
+        Msg::DistractionOverMessage => "{} ist durchgelaufen. Zurück an die Arbeit!",
+        Msg::MissingOverlayTitle => "Das Overlay ist nicht installiert",
+        Msg::MissingOverlayMessage => "`viberot-service setup-actions` holt es.",
+        Msg::MissingActionTitle => "Das Programm einer Aktion fehlt",
+        Msg::MissingActionMessage => "{} gibt es nicht, die Regeln damit können es nicht starten. Prüf den Pfad in der Konfiguration.",
+        Msg::AchievementTitle => "Erfolg freigeschaltet: {}",
+        Msg::AchievementSatThrough => "Durchgehalten",
+        Msg::AchievementSatThroughInfo => "Ein Overlay bis zum Ende des Befehls laufen lassen",
+        Msg::AchievementOnARoll => "Im Flow",
+        Msg::AchievementOnARollInfo => "5 Overlays in Folge durchgehalten",
+        Msg::AchievementUnskippable => "Nicht überspringbar",
+        Msg::AchievementUnskippableInfo => "25 Overlays in Folge durchgehalten",
+        Msg::AchievementConnoisseur => "Brainrot-Kenner",
+        Msg::AchievementConnoisseurInfo => "100 Overlays durchgehalten",
+        Msg::AchievementSpeedrunner => "Speedrunner",
+        Msg::AchievementSpeedrunnerInfo => "10 Overlays innerhalb von 10 Sekunden geschlossen",
+        Msg::SpeakDefault => "{command} ist nach {duration} fertig, {outcome}",
+        Msg::SpeakDefaultNoOutcome => "{command} ist nach {duration} fertig",
+        Msg::SpeakSucceeded => "es hat geklappt",
+        Msg::SpeakFailed => "es ist fehlgeschlagen",
+        Msg::SpeakNoOutcome => "es ist beendet",
+        Msg::SpeakSecond => "{} Sekunde",
+        Msg::SpeakSeconds => "{} Sekunden",
+        Msg::SpeakMinute => "{} Minute",
+        Msg::SpeakMinutes => "{} Minuten",
+        Msg::SpeakHour => "{} Stunde",
+        Msg::SpeakHours => "{} Stunden",
+    })
+}
+
+fn es(msg: Msg) -> Option<&'static str> {
+    Some(match msg {
+        Msg::LogLocation => "VibeRot escribirá los registros en: {}",
+        Msg::ConfigHeader => "# Archivo de configuración de VibeRot
+#
+# Este archivo define reglas para interceptar y manejar comandos.
+# Cada regla consiste en patrón(es) de comando y acción(es) a ejecutar.
+#
+# Las reglas admiten:
+# - Un nombre opcional, pasado a las acciones como VIBEROT_RULE_NAME
+# - Un solo comando o una lista de comandos
+# - Una sola acción o una lista de acciones
+",
+        Msg::SetupWelcome => "¡Bienvenido a VibeRot!",
+        Msg::SetupIntro => "Para que VibeRot reaccione a los comandos de la shell, haz lo siguiente.",
+        Msg::SetupManualLine => "O añade manualmente esta línea a la configuración de tu shell:",
+        Msg::SetupBashImportant => "IMPORTANTE para usuarios de bash:",
+        Msg::SetupBashPreexec => "  Necesitas instalar las funciones preexec y precmd para bash.",
+        Msg::SetupSee => "  Consulta: {}",
+        Msg::SetupPrompt => "¿Quieres que VibeRot lo configure todo automáticamente? [s/N]: ",
+        Msg::SetupYesLetters => "s",
+        Msg::SetupRestartShell => "Reinicia tu shell o ejecuta lo siguiente para aplicar los cambios:",
+        Msg::SetupCancelled => "Cancelado.",
+        Msg::SetupLater => "El servicio seguirá ejecutándose. Puedes configurar la integración con la shell más tarde.",
+        Msg::SetupSuppressHint => "Nota: para no volver a ver esta pregunta sin configurar la integración, crea un archivo vacío en:",
+        Msg::SetupRunning => "Ya puedes dejar VibeRot en segundo plano: lanzará brainrot cuando se ejecute un comando configurado.",
+        Msg::SetupNohupTip => "Consejo: usa nohup para que siga ejecutándose al cerrar la terminal.",
+        Msg::SetupIncomplete => "\n⚠️  La configuración de la integración con la shell no se completó.
+El servicio VibeRot seguirá ejecutándose, pero no detectará comandos de la shell
+hasta que configures la integración manualmente como se describe arriba.\n",
+        Msg::InstallingPreexec => "Instalando bash-preexec para bash...",
+        Msg::InstallSuccess => "\n✅ ¡Integración con la shell instalada correctamente!",
+        Msg::InstallCreated => "  Creado: {}",
+        Msg::InstallUpdated => "  Actualizado: {}",
+        Msg::SandboxFlatpak => "Parece que esta terminal se ejecuta dentro de un sandbox de Flatpak.",
+        Msg::SandboxFlatpakGrant => "Es posible que las shells del sandbox no vean el socket predeterminado. Concede acceso a él:",
+        Msg::SandboxSnap => "Parece que esta terminal se ejecuta dentro del snap '{}'.",
+        Msg::SandboxSnapConfined => "Los snaps con confinamiento estricto no pueden acceder a sockets fuera de su directorio de ejecución.
+Apunta la sonda de shell a un socket dentro de los datos de usuario del snap:",
+        Msg::SandboxContainer => "Parece que esta terminal se ejecuta dentro de un contenedor {}.",
+        Msg::SandboxContainerShared => "Las shells del host (o de otros contenedores) necesitan una ruta de socket compartida con este contenedor,
+por ejemplo una dentro de tu directorio personal:",
+        Msg::SandboxTcpSuggest => "O activa la alternativa TCP local, que las shells en sandbox usan automáticamente:",
+        Msg::NotifyOpenTerminal => "Abrir terminal",
+        Msg::NotifySnooze => "Pausar regla 1 h",
+        Msg::NotifyDismiss => "Descartar",
+        Msg::BudgetTitle => "Presupuesto diario agotado",
+        Msg::BudgetMessage => "Ya van {} de brainrot hoy. Sal a tocar pasto.",
+        Msg::DistractionOverTitle => "Tu comando terminó",
+        Msg::DistractionOverMessage => "{} ha terminado. ¡A trabajar!",
+        Msg::MissingOverlayTitle => "El overlay no está instalado",
+        Msg::MissingOverlayMessage => "Ejecuta `viberot-service setup-actions` para obtenerlo.",
+        Msg::MissingActionTitle => "Falta el programa de una acción",
+        Msg::MissingActionMessage => "{} no existe, así que sus reglas no pueden iniciarlo. Revisa la ruta en la configuración.",
+        Msg::AchievementTitle => "Logro desbloqueado: {}",
+        Msg::AchievementSatThrough => "Aguantaste",
+        Msg::AchievementSatThroughInfo => "Dejaste un overlay hasta que terminó su comando",
+        Msg::AchievementOnARoll => "En racha",
+        Msg::AchievementOnARollInfo => "Aguantaste 5 overlays seguidos",
+        Msg::AchievementUnskippable => "Imposible de saltar",
+        Msg::AchievementUnskippableInfo => "Aguantaste 25 overlays seguidos",
+        Msg::AchievementConnoisseur => "Conocedor del brainrot",
+        Msg::AchievementConnoisseurInfo => "Aguantaste 100 overlays",
+        Msg::AchievementSpeedrunner => "Speedrunner",
+        Msg::AchievementSpeedrunnerInfo => "Cerraste 10 overlays en menos de 10 segundos",
+        Msg::SpeakDefault => "{command} terminó después de {duration}, {outcome}",
+        Msg::SpeakDefaultNoOutcome => "{command} terminó después de {duration}",
+        Msg::SpeakSucceeded => "funcionó",
+        Msg::SpeakFailed => "falló",
+        Msg::SpeakNoOutcome => "terminó",
+        Msg::SpeakSecond => "{} segundo",
+        Msg::SpeakSeconds => "{} segundos",
+        Msg::SpeakMinute => "{} minuto",
+        Msg::SpeakMinutes => "{} minutos",
+        Msg::SpeakHour => "{} hora",
+        Msg::SpeakHours => "{} horas",
+    })
+}
@@ -0,0 +1,226 @@
+// Input activity
+// While actions run, samples how long ago the keyboard or mouse was last used, every few seconds;
+// never which keys or where the pointer went. When the command ends, the share of recent samples
+// with input tells whether the user is deep in the distraction (scrolling, clicking through it)
+// or has drifted off, so the end can be made hard to miss (a banner and a sound) or left quiet.
+// Actions the user dismissed don't count, however busy the keyboard stays afterwards.
+// Backends: GetLastInputInfo (Windows), HIDIdleTime from ioreg (macOS), and xprintidle (X11) or
+// GNOME's Mutter IdleMonitor over gdbus (Linux).
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::config::EndOfDistractionConfig;
+
+/// How often input activity is sampled while actions run
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// Fewest samples that can tell the user is deep in the distraction
+const MIN_SAMPLES: usize = 3;
+const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+const DEFAULT_ACTIVE_PERCENT: u32 = 60;
+
+/// Platform access to how long the user has been idle
+pub trait IdleBackend: Send + Sync {
+    /// Time since the last keyboard or mouse input, or None if it can't be determined
+    fn idle_time(&self) -> Option<Duration>;
+}
+
+/// Returns the idle time backend for the current platform
+pub fn create_backend() -> Box<dyn IdleBackend> {
+    #[cfg(target_os = "linux")]
+    {
+        Box::new(LinuxIdle)
+    }
+
+    #[cfg(windows)]
+    {
+        Box::new(WindowsIdle)
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        Box::new(MacIdle)
+    }
+
+    #[cfg(not(any(target_os = "linux", windows, target_os = "macos")))]
+    {
+        Box::new(UnsupportedIdle)
+    }
+}
+
+#[derive(Default)]
+struct ActivityState {
+    /// Commands with running actions, with when they started and their command line
+    watched: HashMap<u32, (Instant, String)>,
+    /// Whether there was input before each sample, oldest first
+    samples: VecDeque<(Instant, bool)>,
+    sampling: bool,
+}
+
+/// Tracks input activity while actions run
+pub struct InputActivity {
+    backend: Arc<dyn IdleBackend>,
+    window: Duration,
+    active_percent: u32,
+    sound: bool,
+    state: Arc<Mutex<ActivityState>>,
+}
+
+impl InputActivity {
+    pub fn new(backend: Box<dyn IdleBackend>, config: &EndOfDistractionConfig) -> Self {
+        Self {
+            backend: Arc::from(backend),
+            window: config.window.map_or(DEFAULT_WINDOW, |window| window.0),
+            active_percent: config.active_percent.unwrap_or(DEFAULT_ACTIVE_PERCENT),
+            sound: config.sound.unwrap_or(true),
+            state: Arc::new(Mutex::new(ActivityState::default())),
+        }
+    }
+
+    /// Whether the loud ending plays a sound
+    pub fn sound(&self) -> bool {
+        self.sound
+    }
+
+    /// Samples input activity while the command's actions run
+    pub fn watch(&self, pid: u32, command: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.watched.entry(pid).or_insert_with(|| (Instant::now(), command.to_string()));
+        if !state.sampling {
+            state.sampling = true;
+            self.spawn_sampler();
+        }
+    }
+
+    /// Stops watching a command whose actions the user dismissed
+    pub fn forget(&self, pid: u32) {
+        if self.state.lock().unwrap().watched.remove(&pid).is_some() {
+            debug!("Actions of PID {} were dismissed, ending them quietly", pid);
+        }
+    }
+
+    /// Stops watching a command; its command line if the user was deep in the distraction until
+    /// the end
+    pub fn finish(&self, pid: u32) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let (started, command) = state.watched.remove(&pid)?;
+        let since = started.max(Instant::now().checked_sub(self.window).unwrap_or(started));
+        let recent: Vec<bool> = state.samples.iter().filter(|(at, _)| *at >= since).map(|&(_, active)| active).collect();
+        if recent.len() < MIN_SAMPLES {
+            debug!("Too few input samples for PID {} to tell how distracted the user is", pid);
+            return None;
+        }
+        let active_percent = recent.iter().filter(|&&active| active).count() * 100 / recent.len();
+        debug!("Keyboard or mouse used in {}% of the samples while PID {} ran", active_percent, pid);
+        (active_percent >= self.active_percent as usize).then_some(command)
+    }
+
+    /// Samples until no command is watched
+    fn spawn_sampler(&self) {
+        let backend = Arc::clone(&self.backend);
+        let state = Arc::clone(&self.state);
+        let window = self.window;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if state.lock().unwrap().watched.is_empty() {
+                    break;
+                }
+                let backend = Arc::clone(&backend);
+                let idle = tokio::task::spawn_blocking(move || backend.idle_time()).await.ok().flatten();
+                let Some(idle) = idle else {
+                    debug!("Can't tell how long the user has been idle");
+                    continue;
+                };
+                let now = Instant::now();
+                let mut state = state.lock().unwrap();
+                state.samples.push_back((now, idle < SAMPLE_INTERVAL));
+                while state.samples.front().is_some_and(|(at, _)| now.duration_since(*at) > window) {
+                    state.samples.pop_front();
+                }
+            }
+            let mut state = state.lock().unwrap();
+            state.sampling = false;
+            state.samples.clear();
+        });
+    }
+}
+
+/// Linux: xprintidle on X11, or GNOME's idle monitor on Wayland
+#[cfg(target_os = "linux")]
+struct LinuxIdle;
+
+#[cfg(target_os = "linux")]
+impl IdleBackend for LinuxIdle {
+    fn idle_time(&self) -> Option<Duration> {
+        let output = |program: &str, args: &[&str]| {
+            std::process::Command::new(program).args(args).output().ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        };
+        if let Some(millis) = output("xprintidle", &[]).and_then(|out| out.trim().parse::<u64>().ok()) {
+            return Some(Duration::from_millis(millis));
+        }
+        // Prints `(uint64 1234,)`
+        let mutter = output("gdbus", &[
+            "call", "--session", "--dest", "org.gnome.Mutter.IdleMonitor",
+            "--object-path", "/org/gnome/Mutter/IdleMonitor/Core",
+            "--method", "org.gnome.Mutter.IdleMonitor.GetIdletime",
+        ])?;
+        let millis = mutter.trim().trim_start_matches("(uint64 ").trim_end_matches(",)").parse::<u64>().ok()?;
+        Some(Duration::from_millis(millis))
+    }
+}
+
+/// Windows: the tick count of the last input in this session
+#[cfg(windows)]
+struct WindowsIdle;
+
+#[cfg(windows)]
+impl IdleBackend for WindowsIdle {
+    fn idle_time(&self) -> Option<Duration> {
+        use windows::Win32::System::SystemInformation::GetTickCount;
+        use windows::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+
+        let mut info = LASTINPUTINFO {
+            cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+            dwTime: 0,
+        };
+        // SAFETY: `info` is a LASTINPUTINFO with its size set
+        if !unsafe { GetLastInputInfo(&mut info) }.as_bool() {
+            return None;
+        }
+        // Both wrap around after 49 days
+        let now = unsafe { GetTickCount() };
+        Some(Duration::from_millis(now.wrapping_sub(info.dwTime) as u64))
+    }
+}
+
+/// macOS: HIDIdleTime of the HID system, in nanoseconds
+#[cfg(target_os = "macos")]
+struct MacIdle;
+
+#[cfg(target_os = "macos")]
+impl IdleBackend for MacIdle {
+    fn idle_time(&self) -> Option<Duration> {
+        let output = std::process::Command::new("ioreg").args(["-c", "IOHIDSystem", "-d", "4"]).output().ok()?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        let line = text.lines().find(|line| line.contains("\"HIDIdleTime\""))?;
+        let nanos = line.rsplit('=').next()?.trim().parse::<u64>().ok()?;
+        Some(Duration::from_nanos(nanos))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows, target_os = "macos")))]
+struct UnsupportedIdle;
+
+#[cfg(not(any(target_os = "linux", windows, target_os = "macos")))]
+impl IdleBackend for UnsupportedIdle {
+    fn idle_time(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,85 @@
+// Action latency metrics
+// Measures how long it takes from a matched command to its action showing up. Actions report that
+// moment by printing `{"event": "visible", "id": "<event id>"}` on stdout; the id can be left out
+// (null) by actions started for a single command. Actions that never report aren't measured.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Number of recent latencies kept for the summary
+const MAX_SAMPLES: usize = 100;
+/// Triggers that never became visible are forgotten after this long
+const PENDING_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Latency percentiles over recent triggers
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub median: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+}
+
+pub struct LatencyTracker {
+    budget: Option<Duration>,
+    /// Events waiting for an action to become visible, by event ID
+    pending: Mutex<HashMap<String, Instant>>,
+    samples: Mutex<VecDeque<Duration>>,
+}
+
+impl LatencyTracker {
+    pub fn new(budget: Option<Duration>) -> Self {
+        Self {
+            budget,
+            pending: Mutex::new(HashMap::new()),
+            samples: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Starts the clock for an event whose actions are being started
+    pub fn triggered(&self, event_id: &str) {
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, started| now.duration_since(*started) < PENDING_TIMEOUT);
+        pending.entry(event_id.to_string()).or_insert(now);
+    }
+
+    /// Records that an action for the event became visible; only the first report counts
+    pub fn visible(&self, event_id: &str) {
+        let Some(started) = self.pending.lock().unwrap().remove(event_id) else {
+            return;
+        };
+        let latency = started.elapsed();
+
+        match self.budget {
+            Some(budget) if latency > budget => warn!(
+                "Action for event {} took {} ms to show up, over the {} ms budget; consider `prewarm = true`",
+                event_id, latency.as_millis(), budget.as_millis()
+            ),
+            _ => info!("Action for event {} visible after {} ms", event_id, latency.as_millis()),
+        }
+
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(latency);
+    }
+
+    pub fn summary(&self) -> Option<LatencySummary> {
+        let mut samples: Vec<Duration> = self.samples.lock().unwrap().iter().copied().collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort();
+        let percentile = |p: usize| samples[(samples.len() - 1) * p / 100];
+        Some(LatencySummary {
+            count: samples.len(),
+            median: percentile(50),
+            p95: percentile(95),
+            max: samples[samples.len() - 1],
+        })
+    }
+}
@@ -0,0 +1,429 @@
+// Config linting
+// `viberot-service config validate` checks the config like the service does when loading it, then
+// looks for mistakes that are valid TOML but probably not what was meant, each with a suggested
+// fix:
+// - Command globs without a `*` at the ends, which only match exact command lines
+// - Rules whose globs overlap, so both start actions, or that a rule before them already covers
+//   with the same actions
+// - Actions whose program doesn't exist
+// - `when` expressions reading variables that don't exist (usually typos) or that the probe in use
+//   never sets, and action arguments using unknown `VIBEROT_*` variables
+// Lints are guesses, so they never stop the service from loading a config.
+// The other `config` subcommands, moving the config between machines, are in config_sync.rs.
+
+use clap::Subcommand;
+use globset::{GlobSet, GlobSetBuilder};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::action_env::{EnvVar, DEFAULT_PREFIX};
+use crate::action_orchestrator::ActionOrchestrator;
+use crate::config::{Action, Config, Rule, RuleMode};
+use crate::config_sync;
+use crate::enrichment;
+use crate::expr::Expr;
+use crate::output;
+use crate::platform;
+use crate::preflight;
+use crate::rule_engine::{command_glob, RuleEngine};
+use crate::tts;
+use crate::workspace;
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Check the config for errors and likely mistakes
+    Validate {
+        /// Print the findings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write the config, named actions included, to a bundle for another machine; passwords and
+    /// request headers are left out
+    Export {
+        /// The bundle to write, e.g. viberot.tar
+        #[arg(long)]
+        bundle: PathBuf,
+    },
+    /// Put the config of a bundle in place, keeping this machine's passwords and request headers
+    Import {
+        bundle: PathBuf,
+    },
+    /// Sync the config between machines through a git repository
+    Sync {
+        #[command(subcommand)]
+        command: config_sync::SyncCommand,
+    },
+}
+
+/// A likely mistake in the config
+#[derive(Debug, Serialize)]
+pub struct Lint {
+    /// Rule `name`, or `rule #N` for unnamed rules
+    pub rule: String,
+    pub message: String,
+    /// What to change
+    pub fix: String,
+}
+
+/// What `config validate` reports; invalid configs are errors instead
+#[derive(Serialize)]
+struct Validation {
+    valid: bool,
+    warnings: Vec<Lint>,
+}
+
+pub fn run(command: ConfigCommand, config_path: &Path, sync_dir: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let json = match command {
+        ConfigCommand::Validate { json } => json,
+        ConfigCommand::Export { bundle } => return config_sync::export(config_path, &bundle),
+        ConfigCommand::Import { bundle } => return config_sync::import(config_path, &bundle),
+        ConfigCommand::Sync { command } => return config_sync::run(command, config_path, sync_dir),
+    };
+    if !config_path.exists() {
+        return Err(format!("No config at {:?}; the service writes the default one when it starts", config_path).into());
+    }
+    // Errors the service would refuse the config for
+    let config = Config::load(config_path)?;
+    let validation = Validation { valid: true, warnings: lint(&config) };
+    output::print("config validate", json, &validation, |validation| {
+        let mut text = String::new();
+        for lint in &validation.warnings {
+            text.push_str(&format!("warning: {}: {}\n", lint.rule, lint.message));
+            text.push_str(&format!("  fix: {}\n", lint.fix));
+        }
+        match validation.warnings.len() {
+            0 => text.push_str(&format!("{:?} is valid\n", config_path)),
+            count => text.push_str(&format!("{:?} is valid, with {} warning(s)\n", config_path, count)),
+        }
+        text
+    })?;
+    Ok(())
+}
+
+/// Likely mistakes in a valid config
+pub fn lint(config: &Config) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let names: Vec<String> = config.rules.iter().enumerate().map(|(i, rule)| rule.display_name(i)).collect();
+    for (index, rule) in config.rules.iter().enumerate() {
+        let mut add = |message: String, fix: String| lints.push(Lint { rule: names[index].clone(), message, fix });
+        for pattern in rule.command_globs() {
+            if let Some((message, fix)) = check_anchors(pattern) {
+                add(message, fix);
+            }
+        }
+        for (message, fix) in check_variables(config, rule) {
+            add(message, fix);
+        }
+        if cfg!(windows) && rule.stalled {
+            add(
+                "`stalled = true` only matches commands run through `viberot-service wrap --detect-stall`, which needs Linux or macOS".to_string(),
+                "remove `stalled = true` to match the command when it starts".to_string(),
+            );
+        }
+        if cfg!(windows) && rule.mode == RuleMode::Session {
+            add(
+                "`mode = \"session\"` only matches rebuilds of commands run through `viberot-service wrap --cycles`, which needs Linux or macOS".to_string(),
+                "remove `mode = \"session\"` to match the command when it starts".to_string(),
+            );
+        }
+        let actions: Vec<&Action> = rule.action.as_vec().into_iter().chain(rule.escalate.iter().flat_map(|e| e.action.as_vec())).collect();
+        for action in &actions {
+            for (message, fix) in check_action(config, &action.with_params(&rule.with)) {
+                add(message, fix);
+            }
+        }
+        for name in rule.with.keys() {
+            let placeholder = format!("{{{}}}", name);
+            if !actions.iter().any(|action| param_strings(action).iter().any(|text| text.contains(&placeholder))) {
+                add(
+                    format!("`with` sets `{}`, but none of the rule's actions has a `{}` placeholder", name, placeholder),
+                    format!("use `{}` in an action's arguments, or remove it from `with`", placeholder),
+                );
+            }
+        }
+    }
+    lints.extend(check_overlaps(config, &names));
+    lints
+}
+
+/// Globs that only match whole command lines, or ones ETW never reports
+fn check_anchors(pattern: &str) -> Option<(String, String)> {
+    let starts = pattern.starts_with('*');
+    let ends = pattern.ends_with('*');
+    if !pattern.contains(['*', '?']) {
+        return Some((
+            format!("`{}` has no `*`, so it only matches that exact command line, not `{} --release`", pattern, pattern),
+            format!("use `*{}*`", pattern),
+        ));
+    }
+    if !ends {
+        return Some((
+            format!("`{}` only matches commands ending in `{}`, so any further argument stops it matching", pattern,
+                    pattern.trim_start_matches('*')),
+            format!("end it with `*`: `{}*`", pattern),
+        ));
+    }
+    // ETW reports `"C:\...\cargo.exe" build`, never the command as typed
+    if cfg!(windows) && !starts {
+        return Some((
+            format!("`{}` has to match from the start, but ETW reports command lines with the program's full path", pattern),
+            format!("start it with `*`: `*{}`", pattern),
+        ));
+    }
+    None
+}
+
+/// Variables in `when` that don't exist or are never set, and unknown variables in actions
+fn check_variables(config: &Config, rule: &Rule) -> Vec<(String, String)> {
+    let mut found = Vec::new();
+    let known: Vec<&str> = RuleEngine::VARIABLES.iter().chain(enrichment::TAGS).chain(platform::PROBE_TAGS).copied().collect();
+    let when = rule.when.as_deref().and_then(|when| Expr::parse(when).ok());
+    let used = when.as_ref().map(Expr::variables).unwrap_or_default();
+    for name in &used {
+        if !known.contains(name) {
+            let fix = match closest(name, &known) {
+                Some(suggestion) => format!("did you mean `{}`?", suggestion),
+                None => format!("use one of {}", known.join(", ")),
+            };
+            found.push((format!("`when` reads `{}`, which no probe or enricher sets, so it's always null", name), fix));
+        }
+    }
+
+    // ETW doesn't report working directories unless asked to
+    if cfg!(windows) && !config.etw.working_directory && !config.atuin.probe {
+        let mut needs_cwd: Vec<&str> = used.iter().copied()
+            .filter(|name| *name == "cwd" || enrichment::CWD_TAGS.contains(name))
+            .collect();
+        for (condition, set) in [("repo", rule.repo.is_some()), ("branch", rule.branch.is_some()), ("dirty", rule.dirty.is_some())] {
+            if set && !needs_cwd.contains(&condition) {
+                needs_cwd.push(condition);
+            }
+        }
+        if !needs_cwd.is_empty() {
+            found.push((
+                format!("uses {}, which need the working directory ETW doesn't report by default", needs_cwd.join(", ")),
+                "set `working_directory = true` under `[etw]`".to_string(),
+            ));
+        }
+    }
+
+    if cfg!(windows) && config.etw.parent_apps.is_empty() && used.contains(&"parent_app") {
+        found.push((
+            "`when` reads `parent_app`, which is only set for the apps listed in `parent_apps`".to_string(),
+            "list the apps under `[etw]`, e.g. `parent_apps = [\"code.exe\"]`".to_string(),
+        ));
+    }
+
+    let prefix = format!("{}_", config.action_env_prefix.as_deref().unwrap_or(DEFAULT_PREFIX));
+    let mut env_names: Vec<&str> = EnvVar::ALL.iter().map(|var| var.suffix()).collect();
+    // Only expanded in action paths
+    env_names.push("ACTIONS");
+    let strings = rule.action.as_vec().into_iter()
+        .chain(rule.escalate.iter().flat_map(|e| e.action.as_vec()))
+        .flat_map(action_strings);
+    let mut reported = Vec::new();
+    for text in strings {
+        for name in env_references(text, &prefix) {
+            if env_names.contains(&name) || reported.contains(&name) {
+                continue;
+            }
+            reported.push(name);
+            let fix = match closest(name, &env_names) {
+                Some(suggestion) => format!("did you mean `{}{}`?", prefix, suggestion),
+                None => "run `viberot-service env-schema` for the variables actions get".to_string(),
+            };
+            found.push((format!("an action uses `{}{}`, which isn't passed to actions", prefix, name), fix));
+        }
+    }
+    found
+}
+
+/// Strings of an action that may reference environment variables
+fn action_strings(action: &Action) -> Vec<&str> {
+    match action {
+        Action::Executable { path, args, .. } => std::iter::once(path.as_str())
+            .chain(args.iter().flatten().map(String::as_str))
+            .collect(),
+        Action::Attach { path, args, .. } => path.iter().map(String::as_str)
+            .chain(args.iter().flatten().map(String::as_str))
+            .collect(),
+        Action::Notify { title, message, .. } => title.iter().chain(message).map(String::as_str).collect(),
+        Action::Throttle { processes, .. } => processes.iter().map(String::as_str).collect(),
+        Action::Lua { .. } | Action::Speak { .. } | Action::Bell { .. } | Action::Desktop { .. } => Vec::new(),
+    }
+}
+
+/// Strings of an action that `with` values are filled into
+fn param_strings(action: &Action) -> Vec<&str> {
+    match action {
+        Action::Attach { socket, .. } => socket.iter().map(String::as_str).chain(action_strings(action)).collect(),
+        Action::Speak { text_template, voice } => text_template.iter().chain(voice).map(String::as_str).collect(),
+        Action::Bell { text_template, .. } => text_template.iter().map(String::as_str).collect(),
+        _ => action_strings(action),
+    }
+}
+
+/// Names after the prefix of `$PREFIX_NAME` and `${PREFIX_NAME}` references
+fn env_references<'a>(text: &'a str, prefix: &str) -> Vec<&'a str> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        rest = &rest[start + 1..];
+        let braced = rest.strip_prefix('{');
+        let candidate = braced.unwrap_or(rest);
+        let Some(after_prefix) = candidate.strip_prefix(prefix) else {
+            continue;
+        };
+        let end = after_prefix.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after_prefix.len());
+        if end > 0 {
+            names.push(&after_prefix[..end]);
+        }
+    }
+    names
+}
+
+/// Programs that don't exist, and action types that do nothing
+fn check_action(config: &Config, action: &Action) -> Vec<(String, String)> {
+    let path = match action {
+        Action::Executable { path, .. } => path,
+        Action::Attach { path: Some(path), .. } => path,
+        Action::Lua { .. } => {
+            return vec![(
+                "`lua` actions aren't implemented yet and do nothing".to_string(),
+                "use an `exec` action, or a rule `script` to decide on the actions".to_string(),
+            )];
+        }
+        Action::Speak { .. } if program_path(config, tts::PROGRAM).is_none() => {
+            return vec![(
+                format!("`speak` actions need `{}`, which isn't on the PATH", tts::PROGRAM),
+                "install speech-dispatcher".to_string(),
+            )];
+        }
+        Action::Desktop { .. } => match workspace::program() {
+            Some(program) if program_path(config, program).is_none() => {
+                return vec![(
+                    format!("`desktop` actions need `{}` here, which isn't on the PATH", program),
+                    format!("install {}", program),
+                )];
+            }
+            _ => return Vec::new(),
+        },
+        Action::Attach { path: None, .. } | Action::Notify { .. } | Action::Speak { .. } | Action::Bell { .. } | Action::Throttle { .. } => return Vec::new(),
+    };
+    let resolved = match program_path(config, path) {
+        Some(resolved) if resolved.is_file() => {
+            return match preflight::check(path, &resolved) {
+                Ok(_) => Vec::new(),
+                Err(e) => vec![(format!("`{}` can't be started: {}", path, e), "make it runnable, or run it through its interpreter".to_string())],
+            };
+        }
+        Some(resolved) => resolved,
+        None => {
+            return vec![(
+                format!("the program `{}` isn't on the PATH", path),
+                "install it, or give its full path".to_string(),
+            )];
+        }
+    };
+    let fix = if path.contains("overlay/target/") {
+        "run `viberot-service setup-actions` to build or download the overlay".to_string()
+    } else if path.contains("${") && resolved.to_string_lossy().contains("${") {
+        "set the environment variable it uses, or write the path out".to_string()
+    } else {
+        "check the path; relative ones are resolved against the VibeRot home directory".to_string()
+    };
+    vec![(format!("`{}` doesn't exist (resolved to {:?})", path, resolved), fix)]
+}
+
+/// Where the service would look for an action's program: on the PATH for bare names, `None` if
+/// it isn't there
+fn program_path(config: &Config, path: &str) -> Option<PathBuf> {
+    let orchestrator = ActionOrchestrator::with_config(config.clone());
+    let resolved = orchestrator.resolve_action_path(path).unwrap_or_else(|_| PathBuf::from(path));
+    if resolved.components().count() > 1 || resolved.is_absolute() {
+        return Some(resolved);
+    }
+    preflight::find_on_path(&resolved)
+}
+
+/// Pairs of rules matching the same commands: redundant if the earlier one already does the same,
+/// overlapping otherwise
+fn check_overlaps(config: &Config, names: &[String]) -> Vec<Lint> {
+    let rules: Vec<Option<(GlobSet, Vec<&String>, String)>> = config.rules.iter().map(|rule| {
+        let patterns = Some(rule.command_globs()).filter(|patterns| !patterns.is_empty())?;
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            builder.add(command_glob(pattern, rule.case_insensitive).ok()?);
+        }
+        // Rules with different conditions on top of their globs don't overlap
+        let conditions = serde_json::to_string(&(&rule.when, &rule.exe, &rule.args_contains, &rule.repo, &rule.branch, &rule.dirty, &rule.task, &rule.stalled, &rule.mode, &rule.script)).ok()?;
+        Some((builder.build().ok()?, patterns, conditions))
+    }).collect();
+
+    let mut lints = Vec::new();
+    for (later, later_rule) in config.rules.iter().enumerate() {
+        let Some((ref later_globs, ref later_patterns, ref later_conditions)) = rules[later] else {
+            continue;
+        };
+        for (earlier, earlier_rule) in config.rules.iter().enumerate().take(later) {
+            let Some((ref earlier_globs, ref earlier_patterns, ref earlier_conditions)) = rules[earlier] else {
+                continue;
+            };
+            if earlier_conditions != later_conditions {
+                continue;
+            }
+            let covered: Vec<&String> = later_patterns.iter().copied().filter(|pattern| covers(earlier_globs, pattern)).collect();
+            let covering: Vec<&String> = earlier_patterns.iter().copied().filter(|pattern| covers(later_globs, pattern)).collect();
+            let same_actions = serde_json::to_string(&(&earlier_rule.action, &earlier_rule.escalate)).ok()
+                == serde_json::to_string(&(&later_rule.action, &later_rule.escalate)).ok();
+            let (message, fix) = if covered.len() == later_patterns.len() && same_actions {
+                (
+                    format!("{} comes first, matches every command this rule does and starts the same actions, \
+                             so this rule never adds anything", names[earlier]),
+                    "remove it, or change its action".to_string(),
+                )
+            } else if let Some(pattern) = covered.first().or(covering.first()) {
+                (
+                    format!("commands matching `{}` match both this rule and {}, so both start their actions", pattern, names[earlier]),
+                    "narrow one of the globs or add a `when` if only one should fire, or list both actions in one rule".to_string(),
+                )
+            } else {
+                continue;
+            };
+            lints.push(Lint { rule: names[later].clone(), message, fix });
+        }
+    }
+    lints
+}
+
+/// Whether a glob set likely matches every command a pattern does: it matches the pattern with
+/// its wildcards taken out. Patterns with classes or alternatives are skipped
+fn covers(globs: &GlobSet, pattern: &str) -> bool {
+    if pattern.contains(['[', '{']) {
+        return false;
+    }
+    globs.is_match(pattern.replace('*', "").replace('?', "x"))
+}
+
+/// The candidate a likely typo was meant to be, within a couple of edits
+fn closest<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates.iter()
+        .map(|candidate| (edit_distance(&name.to_lowercase(), &candidate.to_lowercase()), *candidate))
+        .filter(|(distance, candidate)| *distance <= 2.min(candidate.len() / 2))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -1,28 +1,340 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+// Builds without a probe for the target platform never produce events, leaving their plumbing unused
+#![cfg_attr(
+    not(any(
+        all(windows, feature = "etw"),
+        all(any(target_os = "linux", target_os = "macos"), feature = "shell"),
+    )),
+    allow(dead_code)
+)]
 
+mod action_env;
+mod action_protocol;
+mod action_state;
+mod agent_hooks;
+mod attach;
+mod audit;
+mod broker;
+mod budget;
+mod capture;
+mod command_line;
 mod config;
+mod config_diff;
+mod config_sync;
 mod config_watcher;
+mod cpu;
+mod dedup;
+#[cfg(all(target_os = "linux", feature = "dbus"))]
+mod dbus;
+mod desktop_integration;
+mod dnd;
+mod enrichment;
+mod event_sink;
+mod expr;
+mod i18n;
+mod input_activity;
+mod latency;
+mod lint;
+mod matcher;
+mod metrics;
+mod missing_actions;
+mod mqtt;
+mod mutes;
+mod notify;
+mod output;
+mod pipe_source;
+mod preflight;
+#[cfg(unix)]
+mod prompt_marks;
+#[cfg(unix)]
+mod rebuild_cycles;
+mod recent;
+mod rule_editor;
 mod rule_engine;
+mod rule_log;
+mod runtime_paths;
+mod sandbox;
+mod schedule;
+mod scripting;
+mod sdk;
+mod setup_actions;
+mod shell_rc;
+mod signals;
+mod stats;
+mod storage;
+mod streaks;
+mod suggest;
+mod terminal_bell;
+mod terminal_progress;
+mod throttle;
+mod tts;
+mod uninstall;
+mod usage;
+mod waiting_room;
+mod watch;
+mod websocket;
+mod workspace;
+#[cfg(unix)]
+mod wrap;
 mod action_orchestrator;
 mod platform;
 
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use futures_util::StreamExt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::{broadcast, RwLock};
-use tokio::signal;
-use tracing::{info, error};
+use tracing::{debug, info, error, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use config::Config;
+use config_diff::ConfigDiff;
 use config_watcher::ConfigWatcher;
 use rule_engine::RuleEngine;
-use action_orchestrator::ActionOrchestrator;
-use platform::{PlatformProbe, PlatformProbeTrait, ProcessLifecycleEvent};
+use action_orchestrator::{ActionOrchestrator, OrchestratorEvent};
+use action_state::ActionStateDirs;
+use audit::AuditLog;
+use capture::CaptureGuard;
+use dedup::Deduplicator;
+use desktop_integration::DesktopProgress;
+use dnd::DndSync;
+use enrichment::EnrichmentPipeline;
+use event_sink::EventSinks;
+use input_activity::InputActivity;
+use metrics::EventMetrics;
+use missing_actions::MissingActions;
+use mutes::MuteStore;
+use notify::{NotificationCommand, Notifier};
+use recent::{Decision, RecentEvents};
+use signals::ServiceSignal;
+use stats::StatsStore;
+use storage::Storage;
+use streaks::StreakTracker;
+use terminal_progress::TerminalProgress;
+use throttle::Throttler;
+use usage::UsageStats;
+use waiting_room::WaitingRoom;
+use platform::{PlatformCapability, PlatformProbeTrait, ProbeAvailability, ProbeSource, ProcessLifecycleEvent};
+
+#[derive(Parser)]
+#[command(name = "viberot-service", version, about = "Autoplays brainrot while you're waiting for commands to complete")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+    /// Write what the probes receive to a capture file, for `replay`
+    #[arg(long, global = true, value_name = "FILE")]
+    record: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Describe the environment variables passed to actions
+    EnvSchema {
+        /// Print the schema as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the action protocol, or generate helper libraries for writing actions
+    Sdk {
+        #[command(subcommand)]
+        command: sdk::SdkCommand,
+    },
+    /// List every process probe and whether it can run on this machine
+    Probes {
+        /// Print the list as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Run the probe for every user of this machine, publishing events to their services
+    Broker {
+        /// Socket (Unix) or named pipe (Windows) to publish on
+        #[arg(long)]
+        endpoint: Option<String>,
+    },
+    /// Print commands as they run with the rules they match, or why none do, without starting actions
+    Watch {
+        /// Show why every rule didn't match, not only those whose command globs did
+        #[arg(long)]
+        all: bool,
+        /// Only show commands matching this glob, e.g. "*cargo*"
+        #[arg(long)]
+        filter: Option<String>,
+    },
+    /// Run a capture made with `--record` through the probes, printing the events they make as JSON lines
+    Replay {
+        /// Capture file
+        capture: PathBuf,
+    },
+    /// Show the last commands the service saw and what it decided for each
+    Recent {
+        /// How many commands to show
+        #[arg(long, short = 'n', default_value_t = 20)]
+        limit: usize,
+        /// Only show commands matching this glob, e.g. "*cargo*"
+        #[arg(long)]
+        filter: Option<String>,
+        /// Show why every rule didn't match, not only those whose command globs did
+        #[arg(long)]
+        all: bool,
+        /// Print the events as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show how the rules were evaluated for a recent command and which actions were chosen
+    Explain {
+        /// Event ID, as `recent` shows it and actions get it in VIBEROT_EVENT_ID; its start is enough
+        event_id: String,
+        /// Print the trace as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show how long the running service takes to process commands
+    Metrics {
+        /// Print the metrics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the usage stats counted, and what would be sent if submitting them is on
+    Usage {
+        /// Print the counts as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check the config, or move it between machines
+    Config {
+        #[command(subcommand)]
+        command: lint::ConfigCommand,
+    },
+    /// Add, change or remove rules in the config
+    Rules {
+        #[command(subcommand)]
+        command: rule_editor::RulesCommand,
+    },
+    /// Propose rules for slow commands that are run often
+    Suggest {
+        /// Also go through bash, zsh and fish histories, or atuin's database
+        #[arg(long)]
+        from_history: bool,
+        /// Shortest typical duration worth a rule, e.g. "30s" or "2m"
+        #[arg(long, default_value = "30s", value_parser = config::parse_duration)]
+        min_duration: Duration,
+        /// Fewest runs worth a rule
+        #[arg(long, default_value_t = 3)]
+        min_runs: usize,
+        /// Most suggestions to show
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        /// Print the suggestions as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Stop a rule from matching for a while, e.g. `mute-rule cargo-build --for 2h`
+    MuteRule {
+        /// Rule `name`, or `rule #N` for unnamed rules
+        name: String,
+        /// How long, e.g. "30m", "2h" or "1h30m"
+        #[arg(long = "for", value_parser = config::parse_duration)]
+        duration: Duration,
+        /// Print the mutes now in place as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Stop every rule from matching for a while, e.g. `snooze-all --for 30m`
+    SnoozeAll {
+        /// How long, e.g. "30m", "2h" or "1h30m"
+        #[arg(long = "for", value_parser = config::parse_duration)]
+        duration: Duration,
+        /// Print the mutes now in place as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Lift the mute of a rule, or every mute and snooze without a name
+    Unmute {
+        name: Option<String>,
+        /// Print the mutes now in place as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the rules, which of them are muted, what the last config reload changed and the streaks
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Run a command on a pseudo-terminal and report it to the service, e.g. from scripts
+    #[cfg(unix)]
+    Wrap {
+        /// Report the command as stalled while it prints nothing, for rules with `stalled = true`
+        #[arg(long)]
+        detect_stall: bool,
+        /// How long the command may print nothing before it counts as stalled
+        #[arg(long, default_value = "30s", value_parser = config::parse_duration)]
+        stall_after: Duration,
+        /// Report each rebuild of a watch-mode command like `cargo watch`, for rules with
+        /// `mode = "session"`
+        #[arg(long)]
+        cycles: bool,
+        /// Output line text starting a rebuild, for watchers VibeRot doesn't know; implies `--cycles`
+        #[arg(long, value_name = "TEXT")]
+        cycle_start: Vec<String>,
+        /// Output line text ending a rebuild; implies `--cycles`
+        #[arg(long, value_name = "TEXT")]
+        cycle_end: Vec<String>,
+        /// The command and its arguments, after `--`
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Run a shell on a pseudo-terminal and report the commands between its OSC 133 prompt marks,
+    /// for terminals whose shell integration sets them up, without the shell hooks
+    #[cfg(unix)]
+    Pty {
+        /// The shell and its arguments, after `--`; `$SHELL` without one
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Print the audit log of executed programs, oldest first
+    AuditExport {
+        /// Only entries at or after this Unix timestamp
+        #[arg(long)]
+        since: Option<u64>,
+        /// Print CSV instead of JSON lines
+        #[arg(long)]
+        csv: bool,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Get the overlay for this platform, building or downloading it, and add rules starting it
+    SetupActions {
+        /// Build the overlay from its sources instead of downloading it
+        #[arg(long, conflicts_with = "download")]
+        build: bool,
+        /// Download the prebuilt overlay even when its sources are at hand
+        #[arg(long)]
+        download: bool,
+        /// Get the overlay again even if it's there already
+        #[arg(long)]
+        force: bool,
+        /// Don't add rules
+        #[arg(long)]
+        no_rules: bool,
+    },
+    /// Remove the shell hooks, sockets, state and registered services; the inverse of setup
+    Uninstall {
+        /// Keep ~/.viberot/config.toml
+        #[arg(long)]
+        keep_config: bool,
+        /// Don't ask before removing
+        #[arg(long)]
+        yes: bool,
+    },
+}
 
 fn init_logging() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Get log directory
-    let log_dir = get_log_dir()?;
+    let log_dir = runtime_paths::log_dir()?;
     
     // Create a file appender that rotates daily
     let file_appender = tracing_appender::rolling::daily(&log_dir, "viberot-service.log");
@@ -49,114 +361,553 @@ fn init_logging() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .init();
         
     // Log the log file location
-    eprintln!("VibeRot logs will be written to: {}", log_dir.join("viberot-service.log").display());
+    eprintln!("{}", i18n::tf(i18n::Msg::LogLocation, &[&log_dir.join("viberot-service.log").display()]));
     
     Ok(())
 }
 
-fn get_log_dir() -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
-    let home_dir = dirs::home_dir()
-        .ok_or("Could not find home directory")?;
-    
-    let mut path = home_dir;
-    path.push(".viberot");
-    path.push("logs");
-    
-    if let Err(e) = std::fs::create_dir_all(&path) {
-        return Err(format!("Failed to create log directory at {:?}: {}", path, e).into());
-    }
-    
-    Ok(path)
-}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cli = Cli::parse();
+    if let Some(path) = &cli.record {
+        platform::recording::start(path)?;
+    }
+    match cli.command {
+        Some(CliCommand::Broker { endpoint }) => {
+            init_logging()?;
+            let config = Config::load(runtime_paths::config_file()?)?;
+            return broker::run_broker(config, endpoint).await;
+        }
+        Some(CliCommand::Rules { command: rule_editor::RulesCommand::Test(args) }) => {
+            return rule_editor::test(args, &runtime_paths::config_file()?, MuteStore::load(runtime_paths::mutes_file()?)).await;
+        }
+        Some(CliCommand::Replay { capture }) => {
+            init_logging()?;
+            let (sender, mut receiver) = broadcast::channel::<ProcessLifecycleEvent>(1024);
+            let print = async {
+                while let Ok(event) = receiver.recv().await {
+                    for event in event.into_events() {
+                        println!("{}", serde_json::to_string(&event)?);
+                    }
+                }
+                Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+            };
+            let config_path = runtime_paths::config_file()?;
+            let config = if config_path.exists() { Config::load(&config_path)? } else { Config::default() };
+            let (replayed, printed) = tokio::join!(platform::recording::replay(&capture, &config, sender), print);
+            replayed?;
+            return printed;
+        }
+        Some(CliCommand::Watch { all, filter }) => {
+            init_logging()?;
+            let stats = StatsStore::load(open_storage(&Config::load(runtime_paths::config_file()?)?)?);
+            let mutes = MuteStore::load(runtime_paths::mutes_file()?);
+            return watch::run(&runtime_paths::config_file()?, stats, mutes, watch::WatchOptions { all, filter }).await;
+        }
+        Some(CliCommand::SetupActions { build, download, force, no_rules }) => {
+            let source = match (build, download) {
+                (true, _) => setup_actions::Source::Build,
+                (_, true) => setup_actions::Source::Download,
+                _ => setup_actions::Source::Auto,
+            };
+            let options = setup_actions::SetupOptions { source, force, no_rules };
+            return setup_actions::run(&runtime_paths::config_file()?, options).await;
+        }
+        Some(command) => return run_cli_command(command),
+        None => {}
+    }
+
     // Initialize logging
     init_logging()?;
 
     info!("Starting VibeRot Core Service");
 
     // Load configuration
-    let config_path = get_config_path()?;
-    let config = Arc::new(RwLock::new(Config::load(&config_path)?));
+    let config_path = runtime_paths::config_file()?;
+    let config = Config::load(&config_path)?;
+    i18n::init(config.locale.as_deref());
+    // What the other machines pushed; the config watcher picks it up
+    if config.sync.auto {
+        config_sync::auto_pull(config_path.clone(), runtime_paths::sync_dir()?);
+    }
+    let config = Arc::new(RwLock::new(config));
+
+    // Buttons on notifications report back here
+    let (notifier, mut notification_rx) = Notifier::new();
+    let notifier = Arc::new(notifier);
+
+    // Actions whose program isn't there, announced now and on every reload that finds new ones
+    let missing_actions = Arc::new(MissingActions::new(Arc::clone(&notifier)));
+    missing_actions.check(&*config.read().await);
 
     // Set up configuration file watching for hot-reload
     let (_config_watcher, mut config_change_rx) = ConfigWatcher::new(&config_path)?;
     
     // Spawn task to handle config changes
     let config_for_watcher = Arc::clone(&config);
+    let config_path_for_watcher = config_path.clone();
+    let missing_actions_for_watcher = Arc::clone(&missing_actions);
     tokio::spawn(async move {
         while let Some(new_config) = config_change_rx.recv().await {
             info!("Configuration changed, updating...");
-            {
-                let mut config_guard = config_for_watcher.write().await;
-                *config_guard = new_config;
-            }
-            info!("Configuration reloaded successfully - rules will be updated for new processes");
+            apply_reload(&config_for_watcher, new_config, &config_path_for_watcher, &missing_actions_for_watcher).await;
         }
     });
 
     info!("Configuration loaded with hot-reload enabled");
 
+    // Load command duration history and persist it, with the processing metrics, periodically
+    let storage = open_storage(&*config.read().await)?;
+    let stats = Arc::new(StatsStore::load(Arc::clone(&storage)));
+    let streaks = Arc::new(StreakTracker::load(Arc::clone(&storage)));
+    let metrics = {
+        let config_guard = config.read().await;
+        Arc::new(EventMetrics::new(runtime_paths::metrics_file()?, config_guard.match_budget()))
+    };
+    let stats_for_saver = Arc::clone(&stats);
+    let streaks_for_saver = Arc::clone(&streaks);
+    let metrics_for_saver = Arc::clone(&metrics);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = stats_for_saver.save() {
+                error!("Failed to save command stats: {}", e);
+            }
+            if let Err(e) = streaks_for_saver.save() {
+                error!("Failed to save streaks: {}", e);
+            }
+            if let Err(e) = metrics_for_saver.save() {
+                error!("Failed to save metrics: {}", e);
+            }
+        }
+    });
+
+    // Usage stats, counted and written only while `[usage_stats] enabled` is on, and sent once a
+    // day when `submit` is on too
+    let usage = Arc::new(UsageStats::load(runtime_paths::usage_file()?));
+    let usage_for_saver = Arc::clone(&usage);
+    let config_for_usage = Arc::clone(&config);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        let mut ticks: u64 = 0;
+        loop {
+            interval.tick().await;
+            let usage_config = config_for_usage.read().await.usage_stats.clone();
+            if !usage_config.enabled {
+                continue;
+            }
+            if let Err(e) = usage_for_saver.save() {
+                error!("Failed to save usage stats: {}", e);
+            }
+            // Checked hourly, so an unreachable endpoint isn't retried every minute
+            if ticks.is_multiple_of(60) {
+                match usage_for_saver.submit_if_due(&usage_config).await {
+                    Ok(true) => info!("Sent the usage stats"),
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to send the usage stats, trying again in an hour: {}", e),
+                }
+            }
+            ticks += 1;
+        }
+    });
+
+    // The last commands and what was decided for them, for `viberot-service recent`; written
+    // shortly after they change so a command that just ran can be looked up
+    let recent = {
+        let config_guard = config.read().await;
+        Arc::new(RecentEvents::new(Arc::clone(&storage), config_guard.recent_events.unwrap_or(recent::DEFAULT_CAPACITY)))
+    };
+    let recent_for_saver = Arc::clone(&recent);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(2));
+        loop {
+            interval.tick().await;
+            if let Err(e) = recent_for_saver.save() {
+                error!("Failed to save recent events: {}", e);
+            }
+        }
+    });
+
+    // Durations atuin recorded, for commands the stats store doesn't know yet
+    #[cfg(feature = "atuin")]
+    let atuin_durations = {
+        let config_guard = config.read().await;
+        match config_guard.atuin.db_path() {
+            Some(path) if !config_guard.atuin.skip_durations && path.exists() => {
+                info!("Using command durations from atuin's history at {:?}", path);
+                Some(platform::atuin::AtuinDurations::start(path))
+            }
+            _ => None,
+        }
+    };
+
     // Create lifecycle event channel (process start/stop events)
     let (lifecycle_tx, mut lifecycle_rx) = broadcast::channel(1024);
 
-    // Create rule engine
-    let rule_engine = RuleEngine::new();
+    // Create rule engine, leaving out rules muted from the CLI or notifications
+    let rule_engine = Arc::new(RuleEngine::new(MuteStore::load(runtime_paths::mutes_file()?)));
+    if !rule_engine.mutes().current().is_empty() {
+        info!("Some rules are muted, see `viberot-service status`");
+    }
+
+    // State directories of actions, with the ones unused for too long removed once a day
+    let state_dirs = {
+        let config_guard = config.read().await;
+        let retention = config_guard.action_state_retention().unwrap_or(action_state::DEFAULT_RETENTION);
+        Arc::new(ActionStateDirs::new(runtime_paths::action_state_dir()?, retention))
+    };
+    let state_dirs_for_pruning = Arc::clone(&state_dirs);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(24 * 60 * 60));
+        loop {
+            interval.tick().await;
+            match state_dirs_for_pruning.prune() {
+                Ok(0) => {}
+                Ok(removed) => info!("Removed {} unused action state directories", removed),
+                Err(e) => error!("Failed to remove unused action state directories: {}", e),
+            }
+        }
+    });
+
+    // The overlay's default page
+    let waiting_room = {
+        let config_guard = config.read().await;
+        match config_guard.waiting_room.enabled {
+            Some(false) => None,
+            _ => WaitingRoom::start(&config_guard.waiting_room, runtime_paths::memes_dir()?).await,
+        }
+    };
 
     // Create action orchestrator with config
-    let action_orchestrator = {
+    let action_orchestrator = Arc::new({
         let config_guard = config.read().await;
-        ActionOrchestrator::with_config(config_guard.clone())
+        let mut orchestrator = ActionOrchestrator::with_config(config_guard.clone())
+            .with_notifier(Arc::clone(&notifier))
+            .with_state_dirs(state_dirs)
+            .with_throttler(Arc::new(Throttler::load(runtime_paths::suspended_file()?)));
+        if config_guard.audit.disabled {
+            info!("Audit log is disabled");
+        } else {
+            let audit_dir = runtime_paths::audit_dir()?;
+            info!("Recording executed programs in the audit log at {:?}", audit_dir);
+            orchestrator = orchestrator.with_audit_log(Arc::new(AuditLog::new(audit_dir, &config_guard.audit)));
+        }
+        if config_guard.capture_safe.enabled {
+            capture::log_setup(&config_guard.capture_safe);
+            orchestrator = orchestrator.with_capture_guard(CaptureGuard::new(config_guard.capture_safe.clone()));
+        }
+        if config_guard.end_of_distraction.enabled {
+            info!("Sampling input activity while actions run, to announce the end of commands the user is busy with");
+            let backend = input_activity::create_backend();
+            orchestrator = orchestrator.with_input_activity(Arc::new(InputActivity::new(backend, &config_guard.end_of_distraction)));
+        }
+        if let Some(room) = &waiting_room {
+            orchestrator = orchestrator.with_waiting_room(Arc::clone(room));
+        }
+        orchestrator
+    });
+    action_orchestrator.prewarm_actions().await;
+
+    // Follow what the orchestrator does, keeping actions that failed to start and what came of the
+    // ones that did for `recent`, and counting overlays sat through or dismissed for the streaks
+    let orchestrator_events = action_orchestrator.subscribe();
+    let recent_for_actions = Arc::clone(&recent);
+    let streaks_for_actions = Arc::clone(&streaks);
+    let notifier_for_actions = Arc::clone(&notifier);
+    tokio::spawn(async move {
+        let mut orchestrator_events = std::pin::pin!(orchestrator_events);
+        while let Some(event) = orchestrator_events.next().await {
+            debug!("Orchestrator: {}", event);
+            let unlocked = match event {
+                OrchestratorEvent::ActionFailed { pid, rule, action, error } => {
+                    recent_for_actions.action_failed(pid, rule, action, error);
+                    continue;
+                }
+                OrchestratorEvent::ActionResult { pid, action, result } => {
+                    recent_for_actions.action_result(pid, action, result);
+                    continue;
+                }
+                OrchestratorEvent::ActionVisible { pid, action } => {
+                    streaks_for_actions.visible(pid, action);
+                    continue;
+                }
+                OrchestratorEvent::ActionDismissed { pid, action } => streaks_for_actions.dismissed(pid, action),
+                OrchestratorEvent::ActionEnded { pid, action } => streaks_for_actions.ended(pid, action),
+                _ => continue,
+            };
+            for achievement in unlocked {
+                let title = i18n::tf(i18n::Msg::AchievementTitle, &[&achievement.name()]);
+                if let Err(e) = notifier_for_actions.announce(&title, achievement.description()) {
+                    debug!("Failed to announce achievement '{}': {}", achievement.id, e);
+                }
+            }
+        }
+    });
+
+    // Pause, resume and manual triggers for desktop widgets, which also get the matches as signals
+    #[cfg(all(target_os = "linux", feature = "dbus"))]
+    let _dbus = match dbus::start(Arc::clone(&action_orchestrator), Arc::clone(&rule_engine), Arc::clone(&config)).await {
+        Ok(connection) => Some(connection),
+        Err(e) => {
+            tracing::warn!("Not serving {} on D-Bus: {}", dbus::BUS_NAME, e);
+            None
+        }
     };
 
-    // Start platform-specific probe
-    let probe = PlatformProbe::new(lifecycle_tx);
-    if let Err(e) = probe.start().await {
-        error!("Failed to start platform probe: {}", e);
-        return Err(e);
+    let (desktop_progress, terminal_progress, dnd) = {
+        let config_guard = config.read().await;
+        (
+            DesktopProgress::new(config_guard.desktop_integration.clone()),
+            TerminalProgress::new(config_guard.desktop_integration.terminal_progress),
+            DndSync::new(dnd::create_backend(), &config_guard.desktop_integration),
+        )
+    };
+
+    // Merges reports of the same command from overlapping probes
+    let dedup = Deduplicator::new();
+    let noisy_rules = rule_log::NoisyRules::default();
+
+    // Tags events with repository, language and command category for the rules
+    let enrichment = {
+        let config_guard = config.read().await;
+        Arc::new(EnrichmentPipeline::new(&config_guard.enrichment))
+    };
+
+    // Republishes matched commands to external systems
+    let sinks = {
+        let config_guard = config.read().await;
+        EventSinks::new(&config_guard.sinks)
+    };
+    if !sinks.is_empty() {
+        info!("Publishing matched commands to {} event sink(s)", config.read().await.sinks.len());
     }
 
+    // Commands other devices report over MQTT, next to the local probe
+    let mqtt_source = match config.read().await.mqtt_source {
+        Some(ref source) => Some(mqtt::MqttSource::start(source, lifecycle_tx.clone())?),
+        None => None,
+    };
+    // Commands editors and agent plugins report over a WebSocket
+    let websocket_source = match config.read().await.websocket_source {
+        Some(ref source) => Some(websocket::WebSocketSource::start(source, lifecycle_tx.clone()).await?),
+        None => None,
+    };
+    // Commands other programs report over a named pipe or Unix socket
+    let pipe_source = match config.read().await.pipe_source {
+        Some(ref source) => Some(pipe_source::PipeSource::start(source, lifecycle_tx.clone())?),
+        None => None,
+    };
+    // Runs of coding agents their hooks report
+    let agent_hooks = match config.read().await.agent_hooks {
+        Some(ref hooks) => Some(agent_hooks::AgentHooks::start(hooks, lifecycle_tx.clone())?),
+        None => None,
+    };
+
+    // Start platform-specific probe
+    let (probe, capability) = {
+        let config_guard = config.read().await;
+        if config_guard.broker.connect {
+            let client = broker::BrokerClient::new(lifecycle_tx.clone(), &config_guard);
+            let capability = client.get_capability();
+            (Box::new(client) as Box<dyn PlatformProbeTrait>, capability)
+        } else {
+            platform::detect_best_probe(lifecycle_tx.clone(), &config_guard)
+        }
+    };
+    info!("Using platform probe with {:?} capability", capability);
+    let probe = match platform::start_probe(probe, lifecycle_tx, &*config.read().await).await {
+        Ok(probe) => probe,
+        Err(e) => {
+            error!("Failed to start platform probe: {}", e);
+            return Err(e);
+        }
+    };
+    // A probe it fell back to may do less
+    let capability = probe.get_capability();
+
     info!("Platform probe started successfully");
+    let probe_data = probe.provided_data();
+    rule_engine.set_probe_data(probe_data, &*config.read().await).await;
+    let active_probe = platform::ActiveProbe { capability: capability.clone(), data: probe_data };
+    if let Err(e) = active_probe.save(&runtime_paths::probe_file()?) {
+        warn!("Failed to save the probe for `rules list`: {}", e);
+    }
+
+    let shutdown_requested = signals::shutdown_requested();
+    tokio::pin!(shutdown_requested);
+    // SIGHUP and SIGUSR1 on Unix
+    let mut service_signals = signals::listen();
 
     // Main event loop - process lifecycle events
     loop {
         tokio::select! {
-            // Handle shutdown signal (Ctrl+C)
-            _ = signal::ctrl_c() => {
-                info!("Received shutdown signal, cleaning up...");
+            // Handle shutdown requests (Ctrl+C, and console close, logoff and shutdown on Windows)
+            reason = &mut shutdown_requested => {
+                info!("Received shutdown signal ({}), cleaning up...", reason);
                 break;
             }
-            // Handle process lifecycle events
-            event_result = lifecycle_rx.recv() => {
-                match event_result {
-                    Ok(ProcessLifecycleEvent::Started(event)) => {
-                        // debug!("Process started: {} (PID: {})", event.command, event.pid);
-                        
-                        // Match against rules
-                        let config_guard = config.read().await;
-                        let actions = rule_engine.match_command(&event.command, &config_guard).await;
-                        if !actions.is_empty() {
-                            info!("Rule matched, starting {} action(s): {:?}", actions.len(), actions);
-                            
-                            // Start all matching actions
-                            if let Err(e) = action_orchestrator.start_actions(actions, &event).await {
-                                error!("Failed to start actions: {}", e);
-                            }
+            Some(service_signal) = service_signals.recv() => match service_signal {
+                ServiceSignal::Reload => match Config::load(&config_path) {
+                    Ok(new_config) => {
+                        info!("Reloading configuration on SIGHUP");
+                        apply_reload(&config, new_config, &config_path, &missing_actions).await;
+                    }
+                    Err(e) => error!("Failed to reload configuration on SIGHUP, keeping the current one: {}", e),
+                },
+                ServiceSignal::DumpState => {
+                    let state = state_text(&*config.read().await, &capability, &stats, &action_orchestrator, &rule_engine, &metrics).await;
+                    for line in state.lines() {
+                        info!("State: {}", line);
+                    }
+                }
+            },
+            // Handle notification buttons
+            Some(command) = notification_rx.recv() => {
+                info!("Notification button: {:?}", command);
+                match command {
+                    NotificationCommand::OpenTerminal { working_directory } => {
+                        notify::open_terminal(working_directory.as_deref());
+                    }
+                    NotificationCommand::Snooze { rule_name } => {
+                        if let Err(e) = rule_engine.mutes().mute_rule(&rule_name, notify::SNOOZE_DURATION) {
+                            error!("Failed to snooze rule '{}': {}", rule_name, e);
                         }
                     }
-                    Ok(ProcessLifecycleEvent::Ended { pid }) => {
-                        // debug!("Process ended: PID {}", pid);
-
-                        // Notify action orchestrator that the process ended
+                    NotificationCommand::Dismiss { pid } => {
                         if let Err(e) = action_orchestrator.finish_action(pid).await {
-                            error!("Failed to finish action for PID {}: {}", pid, e);
+                            error!("Failed to dismiss actions for PID {}: {}", pid, e);
                         }
                     }
+                }
+            }
+            // Handle process lifecycle events
+            event_result = lifecycle_rx.recv() => {
+                let lifecycle_event = match event_result {
+                    Ok(lifecycle_event) => lifecycle_event,
+                    // Falling behind loses those events, not the service
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Fell behind on process events, missed {}", missed);
+                        continue;
+                    }
                     Err(e) => {
                         error!("Lifecycle event channel error: {}", e);
                         break;
                     }
+                };
+                // A batch is handled whole before the next event
+                for lifecycle_event in dedup.filter(lifecycle_event) {
+                    match lifecycle_event {
+                        ProcessLifecycleEvent::Started(event) => {
+                            // debug!("Process started: {} (PID: {})", event.command, event.pid);
+
+                            // A stall isn't a run of the command
+                            if !event.is_stall() {
+                                let running_for = if event.is_replayed() { event.age() } else { Duration::ZERO };
+                                stats.command_started(event.pid, &event.command, running_for);
+                            }
+                            let mut event = enrichment.enrich(event).await;
+                            event.expected_duration = stats.expected_duration(&event.command);
+                            #[cfg(feature = "atuin")]
+                            if event.expected_duration.is_none() {
+                                event.expected_duration = atuin_durations.as_ref().and_then(|d| d.expected_duration(&event.command));
+                            }
+                        
+                            // Match against rules
+                            let config_guard = config.read().await;
+                            let matching_started = Instant::now();
+                            let (actions, verdicts) = rule_engine.decide(&event, &config_guard).await;
+                            metrics.matched(&event.command, matching_started.elapsed());
+                            if config_guard.usage_stats.enabled && !event.is_stall() {
+                                usage.command_seen(&event);
+                            }
+                            if !actions.is_empty() {
+                                sinks.matched(&event, &actions);
+                            }
+                            let matched_rules: Vec<(&str, config::RuleLog)> = verdicts.iter()
+                                .filter(|verdict| verdict.outcome.is_ok())
+                                .filter_map(|verdict| Some((verdict.rule_name.as_str(), config_guard.rules.get(verdict.index)?.log.unwrap_or_default())))
+                                .collect();
+                            rule_log::command_matched(event.pid, matched_rules.iter().map(|(_, log)| *log));
+                            for (rule, _) in matched_rules.iter().filter(|(_, log)| *log != config::RuleLog::Off) {
+                                noisy_rules.matched(rule, config_guard.noisy_rule_threshold());
+                            }
+                            // Local events only: other machines' clocks needn't agree with ours
+                            let stale_age = config_guard.max_event_age()
+                                .filter(|_| event.provenance.hostname == platform::Provenance::local().hostname)
+                                .and_then(|max_age| Some(event.age()).filter(|age| *age > max_age));
+                            let trace = config_guard.explain;
+                            if actions.is_empty() {
+                                recent.started(&event, Decision::NoMatch, &verdicts, trace.then_some(actions.as_slice()));
+                            } else if let Some(age) = stale_age {
+                                warn!("PID {} was reported {}s after it started, not starting {} action(s) for a command that may have ended",
+                                      event.pid, age.as_secs(), actions.len());
+                                recent.started(&event, Decision::Stale { actions: actions.len(), age_secs: age.as_secs() }, &verdicts,
+                                               trace.then_some(actions.as_slice()));
+                            } else if dnd.should_suppress(&config_guard.desktop_integration) {
+                                rule_log::info!(event.pid, "Do-not-disturb is on, suppressing {} action(s) for PID {}", actions.len(), event.pid);
+                                recent.started(&event, Decision::Suppressed { actions: actions.len() }, &verdicts, trace.then_some(actions.as_slice()));
+                            } else {
+                                let actions = missing_actions.apply(actions, &config_guard);
+                                let actions = budget::apply(actions, &config_guard, &stats);
+                                stats.actions_started(event.pid, budget::counted_rules(&actions));
+                                rule_engine.actions_started(&actions);
+                                if config_guard.usage_stats.enabled {
+                                    usage.actions_started(&actions);
+                                }
+                                recent.started(&event, Decision::Started { actions: actions.len() }, &verdicts, trace.then_some(actions.as_slice()));
+                                rule_log::info!(event.pid, "Rule matched, starting {} action(s): {:?}", actions.len(), actions);
+                            
+                                // Start all matching actions, the waiting room knowing the command by the time they open it
+                                if let Some(room) = &waiting_room {
+                                    room.start_command(&event);
+                                }
+                                // Starting actions can take a while; reloads needn't wait for it
+                                let desktop_integration = config_guard.desktop_integration.clone();
+                                drop(config_guard);
+                                if let Err(e) = action_orchestrator.start_actions(actions, &event).await {
+                                    error!("Failed to start actions: {}", e);
+                                }
+                                if let Some(received_at) = event.received_at {
+                                    metrics.processed(received_at.elapsed());
+                                }
+                                desktop_progress.start(&event);
+                                terminal_progress.start(&event);
+                                dnd.hold(event.pid, &desktop_integration);
+                            }
+                        }
+                        ProcessLifecycleEvent::Ended { pid, exit_code } => {
+                            // debug!("Process ended: PID {}", pid);
+                            stats.command_ended(pid);
+                            recent.ended(pid);
+                            sinks.ended(pid, exit_code);
+                            desktop_progress.finish(pid);
+                            terminal_progress.finish(pid);
+                            if let Some(room) = &waiting_room {
+                                room.finish_command(pid, exit_code);
+                            }
+                            dnd.release(pid);
+
+                            // Notify action orchestrator that the process ended
+                            if let Err(e) = action_orchestrator.command_ended(pid, exit_code).await {
+                                error!("Failed to finish action for PID {}: {}", pid, e);
+                            }
+                            rule_log::command_ended(pid);
+                        }
+                        ProcessLifecycleEvent::PidUpgraded { pid, real_pid } => {
+                            info!("PID {} is OS process {}, tracking it until that exits", pid, real_pid);
+                        }
+                        ProcessLifecycleEvent::Progress { pid, percent, message } => {
+                            if let Some(percent) = percent {
+                                desktop_progress.report(pid, percent);
+                                terminal_progress.report(pid, percent);
+                            }
+                            action_orchestrator.command_progress(pid, percent, message.as_deref()).await;
+                        }
+                        // Flattened by the deduplicator
+                        ProcessLifecycleEvent::Batch(_) => {}
+                    }
                 }
             }
         }
@@ -164,6 +915,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     // Cleanup
     info!("Shutting down gracefully...");
+    desktop_progress.clear_all().await;
+    terminal_progress.clear_all();
+    dnd.release_all().await;
     if let Err(e) = action_orchestrator.shutdown().await {
         error!("Error shutting down action orchestrator: {}", e);
     }
@@ -171,23 +925,269 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     if let Err(e) = probe.stop().await {
         error!("Error stopping probe: {}", e);
     }
+    if let Some(source) = mqtt_source {
+        source.stop();
+    }
+    if let Some(source) = websocket_source {
+        source.stop();
+    }
+    if let Some(source) = pipe_source {
+        source.stop();
+    }
+    if let Some(hooks) = agent_hooks {
+        hooks.stop();
+    }
+
+    if let Err(e) = stats.save() {
+        error!("Error saving command stats: {}", e);
+    }
+    if let Err(e) = streaks.save() {
+        error!("Error saving streaks: {}", e);
+    }
+    if let Err(e) = metrics.save() {
+        error!("Error saving metrics: {}", e);
+    }
+    if let Err(e) = recent.save() {
+        error!("Error saving recent events: {}", e);
+    }
+    if config.read().await.usage_stats.enabled {
+        if let Err(e) = usage.save() {
+            error!("Error saving usage stats: {}", e);
+        }
+    }
 
     info!("Shutdown complete");
     Ok(())
 }
 
-fn get_config_path() -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
-    let home_dir = dirs::home_dir()
-        .ok_or("Could not find home directory - this usually means the HOME environment variable is not set")?;
+/// Swaps in a reloaded config, logging what changed and recording it for `viberot-service status`.
+/// Rules are recompiled as they change when the next command is matched; running actions stay.
+/// With `[sync] auto`, the changed config is pushed to the other machines. Programs of its actions
+/// that aren't there are announced.
+async fn apply_reload(config: &RwLock<Config>, new_config: Config, config_path: &Path, missing_actions: &MissingActions) {
+    let diff = ConfigDiff::between(&*config.read().await, &new_config);
+    // Editors often write a file more than once per save
+    if diff.is_empty() {
+        debug!("Configuration file changed, but its content didn't");
+        return;
+    }
+    let sync = new_config.sync.auto;
+    missing_actions.check(&new_config);
+    *config.write().await = new_config;
+    info!("Configuration reloaded, {}", diff.summary());
+    if sync {
+        match runtime_paths::sync_dir() {
+            Ok(sync_dir) => config_sync::auto_push(config_path.to_path_buf(), sync_dir),
+            Err(e) => error!("Failed to sync the config: {}", e),
+        }
+    }
+    let saved = runtime_paths::reload_file().and_then(|path| config_diff::save(&path, &diff));
+    if let Err(e) = saved {
+        error!("Failed to record the config reload: {}", e);
+    }
+}
+
+/// What the service is doing, for the state dump on SIGUSR1
+async fn state_text(
+    config: &Config,
+    capability: &PlatformCapability,
+    stats: &StatsStore,
+    action_orchestrator: &ActionOrchestrator,
+    rule_engine: &RuleEngine,
+    metrics: &EventMetrics,
+) -> String {
+    let mut text = format!("{} rule(s), probe with {:?} capability\n", config.rules.len(), capability);
+    let running = stats.running();
+    text.push_str(&format!("{} command(s) running\n", running.len()));
+    for (pid, command, elapsed) in running {
+        text.push_str(&format!("  PID {}: {} for {}\n", pid, command, mutes::format_duration(elapsed.as_secs())));
+    }
+    text.push_str(&action_orchestrator.state_text().await);
+    text.push_str(&mutes::to_text(&rule_engine.mutes().current()));
+    text.push_str(&metrics::to_text(&metrics.snapshot()));
+    text
+}
+
+/// What `status` reports
+#[derive(serde::Serialize)]
+struct Status {
+    /// `name`, or `rule #N` for unnamed rules
+    rules: Vec<String>,
+    mutes: mutes::MuteData,
+    last_reload: Option<config_diff::Reload>,
+    streaks: Option<streaks::StreakData>,
+}
 
-    let mut path = home_dir;
-    path.push(".viberot");
+/// What `mute-rule`, `snooze-all` and `unmute` report
+#[derive(serde::Serialize)]
+struct MuteChange {
+    /// False when there was nothing to unmute
+    changed: bool,
+    /// The mutes now in place
+    mutes: mutes::MuteData,
+}
 
-    if let Err(e) = std::fs::create_dir_all(&path) {
-        return Err(format!("Failed to create config directory at {:?}: {}", path, e).into());
+/// Runs a one-shot subcommand instead of the service
+fn run_cli_command(command: CliCommand) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match command {
+        CliCommand::EnvSchema { json } => {
+            let schema = action_env::schema(configured_env_prefix()?.as_deref());
+            output::print("env-schema", json, &schema, action_env::schema_text)?;
+        }
+        CliCommand::Probes { json } => {
+            // The atuin probe depends on the config, but don't create one
+            let config_path = runtime_paths::config_file()?;
+            let config = if config_path.exists() { Config::load(&config_path)? } else { Config::default() };
+            let probes: Vec<_> = ProbeSource::ALL.iter().map(|&source| platform::probe_info(source, &config)).collect();
+            output::print("probes", json, &probes, |probes| {
+                let mut text = String::new();
+                for probe in probes {
+                    let status = match &probe.availability {
+                        ProbeAvailability::Available => "available".to_string(),
+                        ProbeAvailability::Unavailable(reason) => format!("unavailable: {}", reason),
+                    };
+                    text.push_str(&format!("{:?} ({:?}): {}\n", probe.source, probe.capability, probe.description));
+                    text.push_str(&format!("  {}\n", status));
+                }
+                text
+            })?;
+        }
+        CliCommand::Recent { limit, filter, all, json } => {
+            let config = Config::load(runtime_paths::config_file()?)?;
+            let events = recent::select(recent::load(&*open_storage(&config)?)?, filter.as_deref(), limit)?;
+            output::print("recent", json, &events, |events| recent::to_text(events, Some(&config), all))?;
+        }
+        CliCommand::Explain { event_id, json } => {
+            let config = Config::load(runtime_paths::config_file()?)?;
+            let events = recent::find(recent::load(&*open_storage(&config)?)?, &event_id)?;
+            output::print("explain", json, &events, |events| recent::explain_text(events))?;
+        }
+        CliCommand::Metrics { json } => {
+            let snapshot = metrics::load(&runtime_paths::metrics_file()?)?;
+            output::print("metrics", json, &snapshot, metrics::to_text)?;
+        }
+        CliCommand::Usage { json } => {
+            let config = Config::load(runtime_paths::config_file()?)?;
+            // null in JSON until the service has written counts
+            let usage = usage::load(&runtime_paths::usage_file()?)?;
+            output::print("usage", json, &usage, |usage| match usage {
+                Some(file) => usage::to_text(file, &config.usage_stats),
+                None if config.usage_stats.enabled => "Nothing counted yet; the counts are written once a minute while the service runs\n".to_string(),
+                None => "Usage stats are off; turn them on with `[usage_stats] enabled = true`. Only counts of commands per probe, rule matches and actions per type are kept, on this machine unless `submit` is on too\n".to_string(),
+            })?;
+        }
+        CliCommand::Sdk { command } => sdk::run(command, configured_env_prefix()?.as_deref())?,
+        CliCommand::Config { command } => lint::run(command, &runtime_paths::config_file()?, &runtime_paths::sync_dir()?)?,
+        CliCommand::Rules { command } => rule_editor::run(command, &runtime_paths::config_file()?, &runtime_paths::probe_file()?)?,
+        CliCommand::Suggest { from_history, min_duration, min_runs, limit, json } => {
+            let config = Config::load(runtime_paths::config_file()?)?;
+            let stats = StatsStore::load(open_storage(&config)?);
+            let mut report = suggest::suggest(&config, &stats, from_history, min_duration, min_runs)?;
+            report.suggestions.truncate(limit);
+            output::print("suggest", json, &report, suggest::to_text)?;
+        }
+        CliCommand::MuteRule { name, duration, json } => {
+            let config = Config::load(runtime_paths::config_file()?)?;
+            let names: Vec<String> = config.rules.iter().enumerate().map(|(i, rule)| rule.display_name(i)).collect();
+            if !names.contains(&name) {
+                return Err(format!("No rule named '{}'; the rules are: {}", name, names.join(", ")).into());
+            }
+            let store = MuteStore::load(runtime_paths::mutes_file()?);
+            store.mute_rule(&name, duration)?;
+            let change = MuteChange { changed: true, mutes: store.current() };
+            output::print("mute-rule", json, &change, |_| format!("Muted '{}'\n", name))?;
+        }
+        CliCommand::SnoozeAll { duration, json } => {
+            let store = MuteStore::load(runtime_paths::mutes_file()?);
+            store.snooze_all(duration)?;
+            let change = MuteChange { changed: true, mutes: store.current() };
+            output::print("snooze-all", json, &change, |_| "Snoozed all rules\n".to_string())?;
+        }
+        CliCommand::Unmute { name, json } => {
+            let store = MuteStore::load(runtime_paths::mutes_file()?);
+            let removed = store.unmute(name.as_deref())?;
+            let change = MuteChange { changed: removed, mutes: store.current() };
+            output::print("unmute", json, &change, |_| match (name, removed) {
+                (Some(name), true) => format!("Unmuted '{}'\n", name),
+                (Some(name), false) => format!("'{}' wasn't muted\n", name),
+                (None, true) => "Unmuted all rules\n".to_string(),
+                (None, false) => "No rules were muted\n".to_string(),
+            })?;
+        }
+        CliCommand::Status { json } => {
+            let config = Config::load(runtime_paths::config_file()?)?;
+            let status = Status {
+                rules: config.rules.iter().enumerate().map(|(i, rule)| rule.display_name(i)).collect(),
+                mutes: MuteStore::load(runtime_paths::mutes_file()?).current(),
+                last_reload: config_diff::load(&runtime_paths::reload_file()?),
+                streaks: streaks::load(&*open_storage(&config)?),
+            };
+            output::print("status", json, &status, |status| {
+                let mut text = format!("{} rule(s) configured\n", status.rules.len());
+                text.push_str(&mutes::to_text(&status.mutes));
+                if let Some(reload) = &status.last_reload {
+                    text.push_str(&config_diff::to_text(reload));
+                }
+                if let Some(streaks) = &status.streaks {
+                    text.push_str(&streaks::to_text(streaks));
+                }
+                text
+            })?;
+        }
+        CliCommand::AuditExport { since, csv, output } => {
+            let entries = audit::read_entries(&runtime_paths::audit_dir()?, since)?;
+            let content = if csv {
+                audit::to_csv(&entries)
+            } else {
+                let mut lines = String::new();
+                for entry in &entries {
+                    lines.push_str(&serde_json::to_string(entry)?);
+                    lines.push('\n');
+                }
+                lines
+            };
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, content)
+                        .map_err(|e| format!("Failed to write audit export to {:?}: {}", path, e))?;
+                    eprintln!("Exported {} audit entries to {:?}", entries.len(), path);
+                }
+                None => print!("{}", content),
+            }
+        }
+        #[cfg(unix)]
+        CliCommand::Wrap { detect_stall, stall_after, cycles, cycle_start, cycle_end, command } => {
+            let options = wrap::WrapOptions {
+                stall_after: detect_stall.then_some(stall_after),
+                cycles: cycles || !cycle_start.is_empty() || !cycle_end.is_empty(),
+                cycle_starts: cycle_start,
+                cycle_ends: cycle_end,
+            };
+            std::process::exit(wrap::run(command, options)?);
+        }
+        #[cfg(unix)]
+        CliCommand::Pty { command } => std::process::exit(prompt_marks::run(command)?),
+        CliCommand::Uninstall { keep_config, yes } => uninstall::run(keep_config, yes)?,
+        CliCommand::Broker { .. } => unreachable!("the broker is a service, started in main"),
+        CliCommand::SetupActions { .. } => unreachable!("downloading is async, started in main"),
+        CliCommand::Watch { .. } => unreachable!("watching runs a probe, started in main"),
+        CliCommand::Replay { .. } => unreachable!("replaying runs the probes' async code, started in main"),
     }
-    
-    path.push("config.toml");
-    info!("Using config file: {:?}", path);
-    Ok(path)
-}
\ No newline at end of file
+    Ok(())
+}
+
+/// `action_env_prefix` of an existing config, without creating one
+fn configured_env_prefix() -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let config_path = runtime_paths::config_file()?;
+    if !config_path.exists() {
+        return Ok(None);
+    }
+    Ok(Config::load(&config_path)?.action_env_prefix)
+}
+
+
+/// The `[storage]` backend stats, streaks and recent events are kept in, in the data directory
+fn open_storage(config: &Config) -> Result<Arc<dyn Storage>, Box<dyn std::error::Error + Send + Sync>> {
+    let capacity = config.recent_events.unwrap_or(recent::DEFAULT_CAPACITY);
+    storage::open(&config.storage, &runtime_paths::data_dir()?, capacity)
+}
@@ -0,0 +1,108 @@
+// Command matchers
+// How a rule's `command` patterns are matched against events, chosen per rule with `matcher`:
+// "glob" (the default, compiled into the rule engine's shared glob set), "regex", "expr"
+// (expressions like `when`) or "custom:<name>" for matchers registered with the rule engine, such
+// as a classifier guessing whether a command will take long. A matcher compiles a rule once, when
+// the config is loaded or changes; the compiled form is then asked about every event, and the
+// engine makes the decision from it together with the rule's other conditions.
+
+use regex::RegexSetBuilder;
+use std::sync::Arc;
+
+use crate::command_line::normalize_unicode;
+use crate::config::Rule;
+use crate::expr::Expr;
+use crate::platform::ProcessEvent;
+use crate::rule_engine::RuleEngine;
+
+/// Prefix of the matchers registered with the rule engine
+pub const CUSTOM_PREFIX: &str = "custom:";
+
+/// Turns a rule into something that tells whether events match it
+pub trait Matcher: Send + Sync {
+    /// Compiles the rule, usually its `command` patterns; the error says what's wrong with them
+    fn compile(&self, rule: &Rule) -> Result<Box<dyn CompiledMatcher>, String>;
+}
+
+/// A rule compiled by a `Matcher`
+pub trait CompiledMatcher: Send + Sync {
+    fn matches(&self, event: &ProcessEvent) -> bool;
+}
+
+/// Matchers that come with VibeRot, by name; "glob" isn't one, the rule engine matches globs itself
+pub fn builtin() -> Vec<(String, Arc<dyn Matcher>)> {
+    vec![
+        ("regex".to_string(), Arc::new(RegexMatcher)),
+        ("expr".to_string(), Arc::new(ExprMatcher)),
+    ]
+}
+
+/// Checks a rule's `matcher` and, for the built-in ones, its patterns; custom matchers are only
+/// known once registered, so their rules are checked when compiled
+pub fn validate(rule: &Rule) -> Result<(), String> {
+    let Some(name) = rule.matcher.as_deref().filter(|name| *name != "glob") else {
+        return Ok(());
+    };
+    if rule.command.is_none() {
+        return Err(format!("`matcher = \"{}\"` needs `command` patterns to match", name));
+    }
+    if let Some(custom) = name.strip_prefix(CUSTOM_PREFIX) {
+        if custom.is_empty() {
+            return Err("`matcher = \"custom:\"` needs the name of the matcher after `custom:`".to_string());
+        }
+        return Ok(());
+    }
+    match builtin().into_iter().find(|(builtin, _)| builtin == name) {
+        Some((_, matcher)) => matcher.compile(rule).map(|_| ()),
+        None => Err(format!("unknown `matcher` '{}', expected \"glob\", \"regex\", \"expr\" or \"custom:<name>\"", name)),
+    }
+}
+
+/// `command` patterns are regular expressions, found anywhere in the command line unless
+/// anchored with `^` and `$`
+struct RegexMatcher;
+
+struct CompiledRegex(regex::RegexSet);
+
+impl Matcher for RegexMatcher {
+    fn compile(&self, rule: &Rule) -> Result<Box<dyn CompiledMatcher>, String> {
+        let patterns: Vec<String> = rule.command.iter()
+            .flat_map(|commands| commands.as_vec())
+            .map(|pattern| normalize_unicode(pattern))
+            .collect();
+        let set = RegexSetBuilder::new(&patterns)
+            .case_insensitive(rule.case_insensitive)
+            .build()
+            .map_err(|e| format!("invalid regex in `command`: {}", e))?;
+        Ok(Box::new(CompiledRegex(set)))
+    }
+}
+
+impl CompiledMatcher for CompiledRegex {
+    fn matches(&self, event: &ProcessEvent) -> bool {
+        self.0.is_match(&event.command)
+    }
+}
+
+/// `command` patterns are expressions over the same variables as `when`; any of them being true
+/// is a match
+struct ExprMatcher;
+
+struct CompiledExprs(Vec<Expr>);
+
+impl Matcher for ExprMatcher {
+    fn compile(&self, rule: &Rule) -> Result<Box<dyn CompiledMatcher>, String> {
+        let exprs = rule.command.iter()
+            .flat_map(|commands| commands.as_vec())
+            .map(|pattern| Expr::parse(pattern).map_err(|e| format!("invalid expression in `command` {}: {}", e, pattern)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Box::new(CompiledExprs(exprs)))
+    }
+}
+
+impl CompiledMatcher for CompiledExprs {
+    fn matches(&self, event: &ProcessEvent) -> bool {
+        let context = RuleEngine::build_context(event);
+        self.0.iter().any(|expr| expr.eval(&context).unwrap_or(false))
+    }
+}
@@ -0,0 +1,150 @@
+// Event processing metrics
+// Times the service's own work for every command: matching it against the rules, and the whole way
+// from receiving the event to having its actions spawned (enrichment, matching, capture checks and
+// spawning). Rolling p50/p99 are written to ~/.viberot/metrics.json, shown by `viberot-service
+// metrics`. Matching slower than `match_budget` is logged, as it points at a pathological rule
+// set (e.g. a slow script or a huge glob list).
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::audit;
+
+/// Number of recent samples the percentiles are taken over
+const WINDOW: usize = 1000;
+/// Matching budget when `match_budget` isn't set
+pub const DEFAULT_MATCH_BUDGET: Duration = Duration::from_millis(50);
+
+/// Percentiles of one measurement, in milliseconds
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Percentiles {
+    pub count: usize,
+    pub p50_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// What `metrics.json` holds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    /// Unix timestamp the snapshot was taken at
+    pub written_at: u64,
+    pub match_budget_ms: u64,
+    /// Matches slower than the budget since the service started
+    pub over_budget: u64,
+    /// Rule matching, for every command
+    pub matching: Option<Percentiles>,
+    /// From receiving an event to its actions being spawned, for commands that matched
+    pub processing: Option<Percentiles>,
+}
+
+/// Fixed-size window of the most recent samples
+#[derive(Default)]
+struct RollingWindow {
+    samples: VecDeque<Duration>,
+}
+
+impl RollingWindow {
+    fn push(&mut self, sample: Duration) {
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    fn percentiles(&self) -> Option<Percentiles> {
+        let mut samples: Vec<Duration> = self.samples.iter().copied().collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let percentile = |p: usize| ms(samples[(samples.len() - 1) * p / 100]);
+        Some(Percentiles {
+            count: samples.len(),
+            p50_ms: percentile(50),
+            p99_ms: percentile(99),
+            max_ms: ms(samples[samples.len() - 1]),
+        })
+    }
+}
+
+pub struct EventMetrics {
+    path: PathBuf,
+    match_budget: Duration,
+    matching: Mutex<RollingWindow>,
+    processing: Mutex<RollingWindow>,
+    over_budget: Mutex<u64>,
+}
+
+impl EventMetrics {
+    pub fn new(path: impl AsRef<Path>, match_budget: Option<Duration>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            match_budget: match_budget.unwrap_or(DEFAULT_MATCH_BUDGET),
+            matching: Mutex::new(RollingWindow::default()),
+            processing: Mutex::new(RollingWindow::default()),
+            over_budget: Mutex::new(0),
+        }
+    }
+
+    /// Records how long matching a command against the rules took
+    pub fn matched(&self, command: &str, elapsed: Duration) {
+        if elapsed > self.match_budget {
+            *self.over_budget.lock().unwrap() += 1;
+            warn!("Matching '{}' against the rules took {} ms, over the {} ms budget; check for slow scripts or `when` expressions",
+                  command, elapsed.as_millis(), self.match_budget.as_millis());
+        }
+        self.matching.lock().unwrap().push(elapsed);
+    }
+
+    /// Records how long it took from receiving an event to spawning its actions
+    pub fn processed(&self, elapsed: Duration) {
+        self.processing.lock().unwrap().push(elapsed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            written_at: audit::unix_now(),
+            match_budget_ms: self.match_budget.as_millis() as u64,
+            over_budget: *self.over_budget.lock().unwrap(),
+            matching: self.matching.lock().unwrap().percentiles(),
+            processing: self.processing.lock().unwrap().percentiles(),
+        }
+    }
+
+    /// Writes the current snapshot for `viberot-service metrics`
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let content = serde_json::to_string_pretty(&self.snapshot())?;
+        let temp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&temp_path, content)?;
+        std::fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Reads the snapshot the running service last wrote
+pub fn load(path: &Path) -> Result<MetricsSnapshot, Box<dyn std::error::Error + Send + Sync>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("No metrics at {:?} ({}); they're written once a minute while the service runs", path, e))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Human-readable form of a snapshot
+pub fn to_text(snapshot: &MetricsSnapshot) -> String {
+    let line = |name: &str, percentiles: Option<Percentiles>| match percentiles {
+        Some(p) => format!("{}: p50 {:.1} ms, p99 {:.1} ms, max {:.1} ms over the last {} command(s)\n",
+                           name, p.p50_ms, p.p99_ms, p.max_ms, p.count),
+        None => format!("{}: no commands yet\n", name),
+    };
+    let age = audit::unix_now().saturating_sub(snapshot.written_at);
+    let mut text = format!("As of {}s ago\n", age);
+    text.push_str(&line("Rule matching", snapshot.matching));
+    text.push_str(&line("Event to action spawn", snapshot.processing));
+    text.push_str(&format!("Matches over the {} ms budget: {}\n", snapshot.match_budget_ms, snapshot.over_budget));
+    text
+}
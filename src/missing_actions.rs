@@ -0,0 +1,131 @@
+// Missing action programs
+// An action whose program isn't there, typically the overlay before `viberot-service
+// setup-actions` got it, would fail to spawn on every command its rule matches. Instead, the
+// programs of all actions are looked for when the config is loaded or reloaded, and each one
+// missing is logged and announced once with a desktop notification saying how to get it. When a
+// rule matches, its actions whose program is still missing are left out, or swapped for a
+// notification about the command with `missing_action = "notify"`. They're looked for again each
+// time, so a program installed in the meantime starts as usual.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info, warn};
+
+use crate::action_orchestrator::ActionOrchestrator;
+use crate::config::{self, Action, Config, MissingAction};
+use crate::i18n::{t, tf, Msg};
+use crate::notify::Notifier;
+use crate::preflight;
+use crate::rule_engine::MatchedAction;
+
+pub struct MissingActions {
+    notifier: Arc<Notifier>,
+    /// Configured paths of programs that weren't there, with where they were looked for; None for
+    /// names looked up on the PATH
+    missing: Mutex<HashMap<String, Option<PathBuf>>>,
+}
+
+impl MissingActions {
+    pub fn new(notifier: Arc<Notifier>) -> Self {
+        Self { notifier, missing: Mutex::new(HashMap::new()) }
+    }
+
+    /// Looks for the programs of the config's actions, announcing the ones newly missing
+    pub fn check(&self, config: &Config) {
+        let orchestrator = ActionOrchestrator::with_config(config.clone());
+        let mut missing = HashMap::new();
+        for rule in &config.rules {
+            let actions = rule.action.as_vec().into_iter().chain(rule.escalate.iter().flat_map(|e| e.action.as_vec()));
+            for action in actions {
+                let action = action.with_params(&rule.with);
+                let Some(path) = program(&action) else {
+                    continue;
+                };
+                if missing.contains_key(path) {
+                    continue;
+                }
+                let location = orchestrator.resolve_action_path(path).ok()
+                    .filter(|resolved| resolved.components().count() > 1 || resolved.is_absolute());
+                if !exists(path, location.as_deref()) {
+                    missing.insert(path.to_string(), location);
+                }
+            }
+        }
+
+        let mut known = self.missing.lock().unwrap();
+        for (path, location) in &missing {
+            if known.contains_key(path) {
+                continue;
+            }
+            let looked_for = location.as_ref().map_or("on the PATH".to_string(), |location| format!("at {}", location.display()));
+            let (title, message) = if is_overlay(path) {
+                (t(Msg::MissingOverlayTitle).to_string(), t(Msg::MissingOverlayMessage).to_string())
+            } else {
+                (t(Msg::MissingActionTitle).to_string(), tf(Msg::MissingActionMessage, &[&path]))
+            };
+            warn!("Action program `{}` isn't there (looked for it {}), its rules won't start it: {}", path, looked_for, message);
+            if let Err(e) = self.notifier.announce(&title, &message) {
+                debug!("Failed to announce the missing action program `{}`: {}", path, e);
+            }
+        }
+        *known = missing;
+    }
+
+    /// Leaves out actions whose program is still missing, or swaps them for a notification
+    pub fn apply(&self, actions: Vec<MatchedAction>, config: &Config) -> Vec<MatchedAction> {
+        let mut missing = self.missing.lock().unwrap();
+        if missing.is_empty() {
+            return actions;
+        }
+        let mut kept = Vec::with_capacity(actions.len());
+        let mut notified: Vec<String> = Vec::new();
+        for matched in actions {
+            let action = matched.action.with_params(&matched.with);
+            let Some((path, location)) = program(&action).and_then(|path| missing.get_key_value(path)) else {
+                kept.push(matched);
+                continue;
+            };
+            if exists(path, location.as_deref()) {
+                info!("Action program `{}` is there now, starting it again", path);
+                let path = path.clone();
+                missing.remove(&path);
+                kept.push(matched);
+                continue;
+            }
+            match config.missing_action {
+                // One notification for the rule, whichever of its actions are missing
+                Some(MissingAction::Notify) if !notified.contains(&matched.rule_name) => {
+                    debug!("Notifying instead of starting `{}` for rule '{}', it isn't there", path, matched.rule_name);
+                    notified.push(matched.rule_name.clone());
+                    kept.push(MatchedAction {
+                        action: Action::Notify { title: None, message: None, buttons: config::default_notify_buttons() },
+                        with: BTreeMap::new(),
+                        ..matched
+                    });
+                }
+                _ => debug!("Not starting `{}` for rule '{}', it isn't there", path, matched.rule_name),
+            }
+        }
+        kept
+    }
+}
+
+/// The configured program an action runs, if it runs one
+fn program(action: &Action) -> Option<&str> {
+    match action {
+        Action::Executable { path, .. } | Action::Attach { path: Some(path), .. } => Some(path),
+        _ => None,
+    }
+}
+
+fn exists(path: &str, location: Option<&Path>) -> bool {
+    match location {
+        Some(location) => location.is_file(),
+        None => preflight::find_on_path(Path::new(path)).is_some(),
+    }
+}
+
+fn is_overlay(path: &str) -> bool {
+    Path::new(path).file_stem().is_some_and(|stem| stem == "viberot-overlay")
+}
@@ -0,0 +1,300 @@
+// MQTT integration
+// Connections to MQTT brokers for the `mqtt` event sink and for `[mqtt_source]`, which feeds
+// commands reported by other devices (e.g. a 3D printer or a CI runner) into the rules like local
+// ones. rumqttc reconnects on its own as long as its event loop is polled.
+// Source messages are JSON, either our own:
+//   {"type": "started", "id": "job-42", "command": "make firmware", "host": "printer"}
+//   {"type": "ended", "id": "job-42"}
+// or what another VibeRot's MQTT sink publishes, minus commands it got over MQTT itself or that
+// ran on this machine, so sinks and sources can't feed each other in a loop. IDs are scoped to
+// the topic they arrive on and mapped to synthetic PIDs.
+
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS, Transport};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::config::{MqttConnection, MqttSourceConfig};
+use crate::platform::{ProbeSource, ProcessEvent, ProcessLifecycleEvent, Provenance};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Wait between reconnection attempts
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Requests queued for the event loop
+const REQUEST_CAPACITY: usize = 64;
+
+/// Synthetic PIDs of remote commands, well clear of the shell probe's
+static REMOTE_PID_COUNTER: AtomicU32 = AtomicU32::new(3_000_000_000);
+
+/// Checks settings that TOML alone can't
+pub fn validate(connection: &MqttConnection, topic: &str) -> Result<(), String> {
+    let (_, _, tls) = parse_url(&connection.url)?;
+    if topic.is_empty() {
+        return Err("`topic` can't be empty".to_string());
+    }
+    if connection.client_cert.is_some() != connection.client_key.is_some() {
+        return Err("`client_cert` and `client_key` go together".to_string());
+    }
+    if !tls && (connection.ca_file.is_some() || connection.client_cert.is_some()) {
+        return Err("certificates need an mqtts:// `url`".to_string());
+    }
+    Ok(())
+}
+
+/// Host, port and whether to use TLS
+fn parse_url(url: &str) -> Result<(String, u16, bool), String> {
+    let (rest, tls) = if let Some(rest) = url.strip_prefix("mqtts://").or_else(|| url.strip_prefix("ssl://")) {
+        (rest, true)
+    } else if let Some(rest) = url.strip_prefix("mqtt://").or_else(|| url.strip_prefix("tcp://")) {
+        (rest, false)
+    } else {
+        return Err(format!("`url` must start with mqtt:// or mqtts://, got '{}'", url));
+    };
+    let rest = rest.trim_end_matches('/');
+    let default_port = if tls { 8883 } else { 1883 };
+
+    // `[::1]:1883` style IPv6 addresses
+    let (host, port) = match rest.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']').ok_or_else(|| format!("unclosed '[' in '{}'", url))?;
+            (host, after.strip_prefix(':'))
+        }
+        None => match rest.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (rest, None),
+        },
+    };
+    if host.is_empty() {
+        return Err(format!("no host in '{}'", url));
+    }
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| format!("invalid port in '{}'", url))?,
+        None => default_port,
+    };
+    Ok((host.to_string(), port, tls))
+}
+
+fn options(connection: &MqttConnection, role: &str) -> Result<MqttOptions, Box<dyn std::error::Error + Send + Sync>> {
+    let (host, port, tls) = parse_url(&connection.url)?;
+    // An explicit client ID keeps the session (subscriptions, queued messages) across reconnects
+    let client_id = connection.client_id.clone().unwrap_or_else(|| {
+        format!("viberot-{}-{}-{}", Provenance::local().hostname, role, std::process::id())
+    });
+    let mut options = MqttOptions::new(client_id, host, port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_clean_session(connection.client_id.is_none());
+    if let Some(ref username) = connection.username {
+        options.set_credentials(username, connection.password.as_deref().unwrap_or_default());
+    }
+    if tls {
+        let client_auth = match (&connection.client_cert, &connection.client_key) {
+            (Some(cert), Some(key)) => Some((read_pem(cert)?, read_pem(key)?)),
+            _ => None,
+        };
+        let transport = match (&connection.ca_file, client_auth) {
+            (Some(ca), client_auth) => Transport::tls(read_pem(ca)?, client_auth, None),
+            (None, None) => Transport::tls_with_default_config(),
+            (None, Some(_)) => return Err("client certificates need a `ca_file`".into()),
+        };
+        options.set_transport(transport);
+    }
+    Ok(options)
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e).into())
+}
+
+/// Connects to a broker, polling the connection in the background; `on_event` sees every packet
+fn connect<F>(
+    connection: &MqttConnection,
+    role: &str,
+    on_event: F,
+) -> Result<(AsyncClient, JoinHandle<()>), Box<dyn std::error::Error + Send + Sync>>
+where
+    F: FnMut(&AsyncClient, Event) + Send + 'static,
+{
+    let (client, event_loop) = AsyncClient::new(options(connection, role)?, REQUEST_CAPACITY);
+    let task = tokio::spawn(poll(client.clone(), event_loop, connection.url.clone(), on_event));
+    Ok((client, task))
+}
+
+async fn poll<F>(client: AsyncClient, mut event_loop: EventLoop, url: String, mut on_event: F)
+where
+    F: FnMut(&AsyncClient, Event),
+{
+    let mut connected = None;
+    loop {
+        match event_loop.poll().await {
+            Ok(event) => {
+                if let Event::Incoming(Packet::ConnAck(_)) = event {
+                    info!("Connected to the MQTT broker at {}", url);
+                    connected = Some(true);
+                }
+                on_event(&client, event);
+            }
+            Err(e) => {
+                // Only log the start of an outage, not every retry
+                if connected != Some(false) {
+                    warn!("MQTT connection to {} failed, retrying every {}s: {}", url, RECONNECT_DELAY.as_secs(), e);
+                }
+                connected = Some(false);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Publishes sink messages to a topic
+pub struct MqttPublisher {
+    client: AsyncClient,
+    task: JoinHandle<()>,
+}
+
+impl MqttPublisher {
+    pub fn connect(connection: &MqttConnection) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let (client, task) = connect(connection, "sink", |_, _| {})?;
+        Ok(Self { client, task })
+    }
+
+    /// Queues a message; it's sent once the broker is reachable
+    pub async fn publish(&self, topic: &str, retain: bool, payload: Vec<u8>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.client.publish(topic, QoS::AtLeastOnce, retain, payload).await?;
+        Ok(())
+    }
+}
+
+impl Drop for MqttPublisher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A message from another device
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RemoteMessage {
+    Started {
+        #[serde(default)]
+        id: Option<String>,
+        #[serde(default)]
+        command: Option<String>,
+        #[serde(default)]
+        working_directory: Option<String>,
+        #[serde(default)]
+        host: Option<String>,
+        /// Set by another VibeRot's sink
+        #[serde(default)]
+        event: Option<Box<RemoteEvent>>,
+    },
+    Ended {
+        #[serde(default, alias = "event_id")]
+        id: Option<String>,
+        #[serde(default)]
+        exit_code: Option<i32>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct RemoteEvent {
+    event_id: String,
+    command: String,
+    #[serde(default)]
+    working_directory: Option<String>,
+    #[serde(default)]
+    provenance: Option<Provenance>,
+    #[serde(default)]
+    probe_source: Option<ProbeSource>,
+}
+
+/// Feeds commands reported over MQTT into the lifecycle channel, next to the local probe
+pub struct MqttSource {
+    task: JoinHandle<()>,
+}
+
+impl MqttSource {
+    pub fn start(
+        config: &MqttSourceConfig,
+        lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let topic = config.topic.clone();
+        // Remote commands still running, by topic and ID
+        let mut running: HashMap<(String, String), u32> = HashMap::new();
+        let (_, task) = connect(&config.connection, "source", move |client, event| match event {
+            Event::Incoming(Packet::ConnAck(ack)) if !ack.session_present => {
+                if let Err(e) = client.try_subscribe(topic.clone(), QoS::AtLeastOnce) {
+                    warn!("Failed to subscribe to MQTT topic {}: {}", topic, e);
+                }
+            }
+            Event::Incoming(Packet::Publish(publish)) => {
+                match serde_json::from_slice::<RemoteMessage>(&publish.payload) {
+                    Ok(message) => handle_message(message, &publish.topic, &mut running, &lifecycle_sender),
+                    Err(e) => debug!("Ignoring unreadable MQTT message on {}: {}", publish.topic, e),
+                }
+            }
+            _ => {}
+        })?;
+        info!("Receiving remote commands from MQTT topic {} at {}", config.topic, config.connection.url);
+        Ok(Self { task })
+    }
+
+    pub fn stop(&self) {
+        self.task.abort();
+    }
+}
+
+fn handle_message(
+    message: RemoteMessage,
+    topic: &str,
+    running: &mut HashMap<(String, String), u32>,
+    lifecycle_sender: &broadcast::Sender<ProcessLifecycleEvent>,
+) {
+    match message {
+        RemoteMessage::Started { id, command, working_directory, host, event } => {
+            let (id, command, working_directory, provenance) = match event {
+                // Relaying remote commands again, or our own, could go round in circles
+                Some(event) if event.probe_source == Some(ProbeSource::Mqtt)
+                    || event.provenance.as_ref().is_some_and(|p| p.hostname == Provenance::local().hostname) => {
+                    debug!("Ignoring relayed command on {}: {}", topic, event.command);
+                    return;
+                }
+                Some(event) => (Some(event.event_id), event.command, event.working_directory, event.provenance),
+                None => match command {
+                    Some(command) => (id, command, working_directory, None),
+                    None => {
+                        debug!("Ignoring MQTT start without a command on {}", topic);
+                        return;
+                    }
+                },
+            };
+
+            let pid = REMOTE_PID_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let mut event = ProcessEvent::new(pid, command, ProbeSource::Mqtt);
+            event.working_directory = working_directory;
+            event.provenance = provenance.unwrap_or_else(|| Provenance {
+                probe_version: String::new(),
+                hostname: host.unwrap_or_default(),
+                session_id: None,
+                uid: None,
+            });
+            // Without an ID nothing can end the command; actions run until the service stops
+            if let Some(id) = id {
+                if let Some(previous) = running.insert((topic.to_string(), id), pid) {
+                    let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid: previous, exit_code: None });
+                }
+            }
+            debug!("Remote command started on {} with synthetic PID {}: {}", topic, pid, event.command);
+            let _ = lifecycle_sender.send(ProcessLifecycleEvent::Started(event));
+        }
+        RemoteMessage::Ended { id: Some(id), exit_code } => {
+            if let Some(pid) = running.remove(&(topic.to_string(), id)) {
+                let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid, exit_code });
+            }
+        }
+        RemoteMessage::Ended { id: None, .. } => debug!("Ignoring MQTT end without an ID on {}", topic),
+    }
+}
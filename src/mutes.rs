@@ -0,0 +1,172 @@
+// Rule mutes
+// Temporarily silences rules without editing the config: `mute-rule <name> --for 2h` mutes one
+// rule, `snooze-all --for 30m` all of them, and the snooze button on notifications mutes its rule.
+// Mutes are kept as expiry timestamps in ~/.viberot/mutes.json, so they survive restarts. The CLI
+// writes the file directly; the service re-reads it whenever it changed on disk.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, warn};
+
+use crate::audit;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MuteData {
+    /// Unix timestamp all rules are snoozed until
+    #[serde(default)]
+    pub all_until: Option<u64>,
+    /// Unix timestamp each muted rule matches again at, by rule name
+    #[serde(default)]
+    pub rules: BTreeMap<String, u64>,
+}
+
+impl MuteData {
+    /// Drops mutes that have run out
+    fn prune(&mut self, now: u64) {
+        self.all_until = self.all_until.filter(|&until| until > now);
+        self.rules.retain(|_, until| *until > now);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.all_until.is_none() && self.rules.is_empty()
+    }
+
+    /// Whether a rule is muted, assuming expired mutes were pruned
+    pub fn is_muted(&self, rule_name: &str) -> bool {
+        self.all_until.is_some() || self.rules.contains_key(rule_name)
+    }
+}
+
+pub struct MuteStore {
+    path: PathBuf,
+    data: Mutex<MuteData>,
+    /// Modification time of the file when it was last read or written
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl MuteStore {
+    /// Loads the store from disk, starting empty if the file is missing or unreadable
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let store = Self {
+            path: path.as_ref().to_path_buf(),
+            data: Mutex::new(MuteData::default()),
+            modified: Mutex::new(None),
+        };
+        store.refresh();
+        store
+    }
+
+    /// Re-reads the file if something else (e.g. the CLI) changed it
+    fn refresh(&self) {
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let mut last_modified = self.modified.lock().unwrap();
+        if modified == *last_modified {
+            return;
+        }
+        *last_modified = modified;
+
+        let data = match std::fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable mutes file {:?}: {}", self.path, e);
+                MuteData::default()
+            }),
+            Err(_) => MuteData::default(),
+        };
+        debug!("Loaded {} muted rule(s) from {:?}", data.rules.len(), self.path);
+        *self.data.lock().unwrap() = data;
+    }
+
+    /// Mutes that haven't run out yet
+    pub fn current(&self) -> MuteData {
+        self.refresh();
+        let mut data = self.data.lock().unwrap().clone();
+        data.prune(audit::unix_now());
+        data
+    }
+
+    /// Stops a rule from matching for a while
+    pub fn mute_rule(&self, rule_name: &str, duration: Duration) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        info!("Muting rule '{}' for {}", rule_name, format_duration(duration.as_secs()));
+        let until = audit::unix_now() + duration.as_secs();
+        self.update(|data| {
+            data.rules.insert(rule_name.to_string(), until);
+        })
+    }
+
+    /// Stops every rule from matching for a while
+    pub fn snooze_all(&self, duration: Duration) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        info!("Snoozing all rules for {}", format_duration(duration.as_secs()));
+        let until = audit::unix_now() + duration.as_secs();
+        self.update(|data| data.all_until = Some(until))
+    }
+
+    /// Lifts the mute of one rule, or every mute; returns whether anything was muted
+    pub fn unmute(&self, rule_name: Option<&str>) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut removed = false;
+        self.update(|data| match rule_name {
+            Some(name) => removed = data.rules.remove(name).is_some(),
+            None => {
+                removed = !data.is_empty();
+                *data = MuteData::default();
+            }
+        })?;
+        Ok(removed)
+    }
+
+    /// Lifts a snooze of all rules, leaving the mutes of single rules; returns whether all rules
+    /// were snoozed
+    #[cfg_attr(not(all(target_os = "linux", feature = "dbus")), allow(dead_code))] // Only D-Bus resumes
+    pub fn resume(&self) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut resumed = false;
+        self.update(|data| resumed = data.all_until.take().is_some())?;
+        Ok(resumed)
+    }
+
+    fn update<F: FnOnce(&mut MuteData)>(&self, change: F) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.refresh();
+        let mut data = self.data.lock().unwrap();
+        change(&mut data);
+        data.prune(audit::unix_now());
+
+        let content = serde_json::to_string_pretty(&*data)?;
+        let temp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&temp_path, content)?;
+        std::fs::rename(&temp_path, &self.path)?;
+        *self.modified.lock().unwrap() = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        Ok(())
+    }
+}
+
+/// Human-readable form of mutes for `viberot-service status`
+pub fn to_text(data: &MuteData) -> String {
+    let now = audit::unix_now();
+    let mut text = String::new();
+    if let Some(until) = data.all_until {
+        text.push_str(&format!("All rules snoozed for another {}\n", format_duration(until.saturating_sub(now))));
+    }
+    if data.rules.is_empty() {
+        if data.all_until.is_none() {
+            text.push_str("No rules muted\n");
+        }
+    } else {
+        text.push_str("Muted rules:\n");
+        for (name, until) in &data.rules {
+            text.push_str(&format!("  {}: another {}\n", name, format_duration(until.saturating_sub(now))));
+        }
+    }
+    text
+}
+
+/// e.g. "1h30m" or "45s"
+pub fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{}s", seconds),
+        (0, _) => format!("{}m", minutes),
+        (_, 0) => format!("{}h", hours),
+        _ => format!("{}h{}m", hours, minutes),
+    }
+}
@@ -0,0 +1,274 @@
+// Desktop notifications
+// Backs the `notify` action. On Windows it's a toast whose buttons act on the command: clicks are
+// delivered in-process through the toast's Activated event and handed to the event loop as
+// `NotificationCommand`s. Unpackaged apps need their AppUserModelID registered (under
+// HKCU\Software\Classes\AppUserModelId) for toasts to show. Clicks on toasts still in the Action
+// Center after the service restarted do nothing; that would need a COM activator.
+// Linux uses notify-send and macOS osascript, both without buttons.
+
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::config::NotifyButton;
+use crate::platform::ProcessEvent;
+
+/// How long the snooze button stops a rule from matching
+pub const SNOOZE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+#[cfg(windows)]
+const APP_ID: &str = "VibeRot.Service";
+
+/// What a notification button asks the service to do
+#[derive(Debug, Clone)]
+#[cfg_attr(not(windows), allow(dead_code))] // Only toasts have buttons
+pub enum NotificationCommand {
+    OpenTerminal { working_directory: Option<String> },
+    Snooze { rule_name: String },
+    Dismiss { pid: u32 },
+}
+
+pub struct Notifier {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    commands: mpsc::UnboundedSender<NotificationCommand>,
+    /// Toasts shown for running commands, by monitored PID, hidden when the command ends
+    #[cfg(windows)]
+    toasts: std::sync::Mutex<std::collections::HashMap<u32, Vec<windows::UI::Notifications::ToastNotification>>>,
+}
+
+impl Notifier {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<NotificationCommand>) {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let notifier = Self {
+            commands,
+            #[cfg(windows)]
+            toasts: std::sync::Mutex::new(std::collections::HashMap::new()),
+        };
+        (notifier, receiver)
+    }
+
+    /// Shows a notification for a matched command
+    pub fn show(
+        &self,
+        title: &str,
+        message: &str,
+        buttons: &[NotifyButton],
+        event: &ProcessEvent,
+        rule_name: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        #[cfg(windows)]
+        {
+            self.show_toast(title, message, buttons, event, rule_name)?;
+        }
+        #[cfg(not(windows))]
+        {
+            let _ = (buttons, event, rule_name);
+            show_plain(title, message)?;
+        }
+        Ok(())
+    }
+
+    /// Shows a notification that isn't about a command, e.g. an unlocked achievement
+    pub fn announce(&self, title: &str, message: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        #[cfg(windows)]
+        {
+            use windows::core::HSTRING;
+            use windows::Data::Xml::Dom::XmlDocument;
+            use windows::UI::Notifications::{ToastNotification, ToastNotificationManager};
+
+            register_app_id();
+            let xml = format!(
+                r#"<toast><visual><binding template="ToastGeneric"><text>{}</text><text>{}</text></binding></visual></toast>"#,
+                escape_xml(title), escape_xml(message)
+            );
+            let document = XmlDocument::new()?;
+            document.LoadXml(&HSTRING::from(xml))?;
+            let toast = ToastNotification::CreateToastNotification(&document)?;
+            ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(APP_ID))?.Show(&toast)?;
+        }
+        #[cfg(not(windows))]
+        show_plain(title, message)?;
+        Ok(())
+    }
+
+    /// Hides the notifications of a command that ended
+    pub fn hide(&self, pid: u32) {
+        #[cfg(windows)]
+        {
+            use windows::core::HSTRING;
+            use windows::UI::Notifications::ToastNotificationManager;
+
+            let Some(toasts) = self.toasts.lock().unwrap().remove(&pid) else {
+                return;
+            };
+            if let Ok(notifier) = ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(APP_ID)) {
+                for toast in toasts {
+                    let _ = notifier.Hide(&toast);
+                }
+            }
+        }
+        #[cfg(not(windows))]
+        let _ = pid;
+    }
+
+    #[cfg(windows)]
+    fn show_toast(
+        &self,
+        title: &str,
+        message: &str,
+        buttons: &[NotifyButton],
+        event: &ProcessEvent,
+        rule_name: &str,
+    ) -> windows::core::Result<()> {
+        use windows::core::{ComInterface, IInspectable, HSTRING};
+        use windows::Data::Xml::Dom::XmlDocument;
+        use windows::Foundation::TypedEventHandler;
+        use windows::UI::Notifications::{ToastActivatedEventArgs, ToastNotification, ToastNotificationManager};
+        use crate::i18n::{t, Msg};
+
+        register_app_id();
+
+        let mut actions = String::new();
+        for button in buttons {
+            let (label, argument) = match button {
+                NotifyButton::OpenTerminal => (t(Msg::NotifyOpenTerminal), "open_terminal"),
+                NotifyButton::Snooze => (t(Msg::NotifySnooze), "snooze"),
+                NotifyButton::Dismiss => (t(Msg::NotifyDismiss), "dismiss"),
+            };
+            actions.push_str(&format!(r#"<action content="{}" arguments="{}"/>"#, escape_xml(label), argument));
+        }
+        let xml = format!(
+            r#"<toast><visual><binding template="ToastGeneric"><text>{}</text><text>{}</text></binding></visual><actions>{}</actions></toast>"#,
+            escape_xml(title), escape_xml(message), actions
+        );
+        let document = XmlDocument::new()?;
+        document.LoadXml(&HSTRING::from(xml))?;
+        let toast = ToastNotification::CreateToastNotification(&document)?;
+
+        let commands = self.commands.clone();
+        let pid = event.pid;
+        let working_directory = event.working_directory.clone();
+        let rule_name = rule_name.to_string();
+        toast.Activated(&TypedEventHandler::new(move |_, args: &Option<IInspectable>| {
+            let Some(args) = args else {
+                return Ok(());
+            };
+            let command = match args.cast::<ToastActivatedEventArgs>()?.Arguments()?.to_string().as_str() {
+                "open_terminal" => NotificationCommand::OpenTerminal { working_directory: working_directory.clone() },
+                "snooze" => NotificationCommand::Snooze { rule_name: rule_name.clone() },
+                "dismiss" => NotificationCommand::Dismiss { pid },
+                // The toast itself was clicked
+                _ => return Ok(()),
+            };
+            let _ = commands.send(command);
+            Ok(())
+        }))?;
+
+        ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(APP_ID))?.Show(&toast)?;
+        self.toasts.lock().unwrap().entry(pid).or_default().push(toast);
+        Ok(())
+    }
+}
+
+/// Registers the app ID toasts are shown under, once per run
+#[cfg(windows)]
+fn register_app_id() {
+    use windows::core::{w, HSTRING};
+    use windows::Win32::System::Registry::{RegSetKeyValueW, HKEY_CURRENT_USER, REG_SZ};
+
+    static REGISTERED: std::sync::Once = std::sync::Once::new();
+    REGISTERED.call_once(|| {
+        let key = HSTRING::from(format!(r"Software\Classes\AppUserModelId\{}", APP_ID));
+        let name: Vec<u16> = "VibeRot".encode_utf16().chain(std::iter::once(0)).collect();
+        // SAFETY: `name` is a NUL-terminated UTF-16 string of the given byte length
+        let result = unsafe {
+            RegSetKeyValueW(HKEY_CURRENT_USER, &key, w!("DisplayName"), REG_SZ.0,
+                            Some(name.as_ptr() as *const _), (name.len() * 2) as u32)
+        };
+        if let Err(e) = result {
+            warn!("Failed to register the notification app ID, toasts may not show: {}", e);
+        }
+    });
+}
+
+#[cfg(windows)]
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(target_os = "linux")]
+fn show_plain(title: &str, message: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    tokio::process::Command::new("notify-send")
+        .args(["--app-name=VibeRot", title, message])
+        .spawn()
+        .map_err(|e| format!("Failed to run notify-send: {}", e))?;
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn show_plain(title: &str, message: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+    let script = format!("display notification {} with title {}", quote(message), quote(title));
+    tokio::process::Command::new("osascript")
+        .args(["-e", &script])
+        .spawn()
+        .map_err(|e| format!("Failed to run osascript: {}", e))?;
+    Ok(())
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+fn show_plain(_title: &str, _message: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    Err("Notifications aren't supported on this platform".into())
+}
+
+/// Opens a terminal in the given directory, for the "Open terminal" button
+pub fn open_terminal(working_directory: Option<&str>) {
+    let directory = working_directory
+        .map(std::path::PathBuf::from)
+        .filter(|dir| dir.is_dir())
+        .or_else(dirs::home_dir)
+        .unwrap_or_else(|| std::path::PathBuf::from("."));
+    debug!("Opening a terminal in {}", directory.display());
+
+    #[cfg(windows)]
+    let result = tokio::process::Command::new("wt.exe")
+        .arg("-d")
+        .arg(&directory)
+        .spawn()
+        .or_else(|_| tokio::process::Command::new("cmd.exe").arg("/K").current_dir(&directory).spawn());
+    #[cfg(target_os = "macos")]
+    let result = tokio::process::Command::new("open").args(["-a", "Terminal"]).arg(&directory).spawn();
+    #[cfg(not(any(windows, target_os = "macos")))]
+    let result = tokio::process::Command::new("x-terminal-emulator").current_dir(&directory).spawn();
+
+    if let Err(e) = result {
+        warn!("Failed to open a terminal in {}: {}", directory.display(), e);
+    }
+}
+
+/// Plays a short chime, e.g. when a command ends while the user is still busy with its actions
+pub fn play_sound() {
+    #[cfg(windows)]
+    {
+        use windows::Win32::System::Diagnostics::Debug::MessageBeep;
+        use windows::Win32::UI::WindowsAndMessaging::MB_OK;
+        // SAFETY: MessageBeep has no preconditions
+        if let Err(e) = unsafe { MessageBeep(MB_OK) } {
+            warn!("Failed to play a sound: {}", e);
+        }
+    }
+    #[cfg(target_os = "macos")]
+    let result = tokio::process::Command::new("afplay").arg("/System/Library/Sounds/Glass.aiff").spawn().map(drop);
+    #[cfg(target_os = "linux")]
+    let result = tokio::process::Command::new("canberra-gtk-play").args(["-i", "complete"]).spawn().map(drop)
+        .or_else(|_| tokio::process::Command::new("paplay").arg("/usr/share/sounds/freedesktop/stereo/complete.oga").spawn().map(drop));
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    if let Err(e) = result {
+        warn!("Failed to play a sound: {}", e);
+    }
+}
@@ -0,0 +1,30 @@
+// CLI output
+// One-shot commands print text for people, or with `--json` one JSON document for scripts, editors
+// and the settings app:
+//   {"command": "status", "version": 1, "data": {...}}
+// `command` is the subcommand as typed ("rules list"), `data` what it reports, as documented in
+// docs/cli-json.md. `version` is raised when a command's `data` changes in a way that breaks
+// readers, like a field removed, renamed or meaning something else; added fields don't raise it.
+// Failures print nothing on stdout and exit non-zero with the error on stderr, JSON or not.
+
+use serde::Serialize;
+
+/// Version of the JSON documents the CLI prints
+pub const VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Document<'a, T: ?Sized> {
+    command: &'a str,
+    version: u32,
+    data: &'a T,
+}
+
+/// Prints what `command` reports: as a JSON document with `json`, else the text `text` makes of it
+pub fn print<T: Serialize + ?Sized>(command: &str, json: bool, data: &T, text: impl FnOnce(&T) -> String) -> Result<(), serde_json::Error> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&Document { command, version: VERSION, data })?);
+    } else {
+        print!("{}", text(data));
+    }
+    Ok(())
+}
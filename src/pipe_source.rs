@@ -0,0 +1,214 @@
+// Pipe event source
+// `[pipe_source]` takes commands from programs that watch processes themselves, like the .NET probe
+// or editor extensions, over a named pipe on Windows (\\.\pipe\viberot-probe) or a Unix socket
+// elsewhere (viberot-probe.sock in the runtime directory). A producer connects and writes one JSON
+// object per line:
+// - a ProcessEvent for a command that started, bare or as `replay` prints it:
+//   {"pid": 4242, "command": "msbuild App.sln", "timestamp": 1792299810, "probe_source": "WindowsEtw"}
+//   {"started": {"pid": 4242, ...}}
+// - {"ended": {"pid": 4242, "exit_code": 0}} when it ended
+// PIDs of probes with system PIDs (WindowsEtw, WindowsWmi) are kept, so the processes can be found;
+// the rest are mapped to synthetic PIDs, so producers can number their commands as they like.
+// "External" is the probe_source for producers that aren't a probe VibeRot knows. Whatever a
+// producer started and didn't end ends when it disconnects. Only the user running the service can
+// write to the pipe or socket.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::config::PipeSourceConfig;
+use crate::platform::{self, ProcessEvent, ProcessLifecycleEvent, Provenance};
+use crate::runtime_paths;
+
+/// Synthetic PIDs of commands producers reported, between the atuin and MQTT ones
+static PIPE_PID_COUNTER: AtomicU32 = AtomicU32::new(2_500_000_000);
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LifecycleLine {
+    Started(Box<ProcessEvent>),
+    Ended {
+        pid: u32,
+        #[serde(default)]
+        exit_code: Option<i32>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Lifecycle(LifecycleLine),
+    Started(Box<ProcessEvent>),
+}
+
+/// A producer's connection
+pub type Connection = Box<dyn AsyncRead + Unpin + Send>;
+
+/// Listens on the pipe or socket until stopped
+pub struct PipeSource {
+    task: JoinHandle<()>,
+    #[cfg_attr(not(unix), allow(dead_code))] // Named pipes go away with their handles
+    path: String,
+}
+
+impl PipeSource {
+    pub fn start(config: &PipeSourceConfig, lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let path = config.path.clone().unwrap_or_else(runtime_paths::probe_endpoint);
+        let task = listen(&path, move |connection| read_events(connection, lifecycle_sender.clone()))?;
+        info!("Receiving commands from other programs on {}", path);
+        Ok(Self { task, path })
+    }
+
+    pub fn stop(&self) {
+        self.task.abort();
+        #[cfg(unix)]
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Accepts connections on a named pipe (Windows) or Unix socket (elsewhere) that only this user can
+/// write to, handing each to `serve`
+#[cfg(unix)]
+pub fn listen<F, Fut>(path: &str, serve: F) -> Result<JoinHandle<()>, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: Fn(Connection) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::path::PathBuf::from(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path)
+        .map_err(|e| format!("Failed to bind socket {}: {}", path.display(), e))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve(Box::new(stream)));
+                }
+                Err(e) => warn!("Failed to accept a connection on {}: {}", path.display(), e),
+            }
+        }
+    }))
+}
+
+#[cfg(windows)]
+pub fn listen<F, Fut>(path: &str, serve: F) -> Result<JoinHandle<()>, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: Fn(Connection) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    // Producers only write. The default pipe security lets only the service's account write, and
+    // being the first instance keeps other processes from posing as the service.
+    let path = path.to_string();
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .access_outbound(false)
+        .create(&path)
+        .map_err(|e| format!("Failed to create pipe {}: {}", path, e))?;
+
+    Ok(tokio::spawn(async move {
+        loop {
+            if let Err(e) = server.connect().await {
+                warn!("Failed to accept a connection on {}: {}", path, e);
+                continue;
+            }
+            let next = ServerOptions::new().access_outbound(false).create(&path);
+            tokio::spawn(serve(Box::new(server)));
+            server = match next {
+                Ok(next) => next,
+                Err(e) => {
+                    warn!("Stopped taking commands on {}: {}", path, e);
+                    return;
+                }
+            };
+        }
+    }))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn listen<F, Fut>(_path: &str, _serve: F) -> Result<JoinHandle<()>, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: Fn(Connection) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Err("Pipes and sockets aren't supported on this platform".into())
+}
+
+/// Reads one producer's events, ending what it started once it disconnects
+async fn read_events(stream: Connection, lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) {
+    debug!("Pipe source producer connected");
+    let mut lines = BufReader::new(stream).lines();
+    // The producer's PIDs of its running commands, and the PIDs they go by here
+    let mut running: HashMap<u32, u32> = HashMap::new();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) if line.trim().is_empty() => {}
+            Ok(Some(line)) => {
+                if let Err(e) = handle_line(&line, &mut running, &lifecycle_sender) {
+                    warn!("Ignoring a line from a pipe source producer: {}", e);
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                debug!("Pipe source producer failed: {}", e);
+                break;
+            }
+        }
+    }
+
+    for (producer_pid, pid) in running {
+        debug!("Pipe source producer left without ending its PID {}, ending PID {}", producer_pid, pid);
+        let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid, exit_code: None });
+    }
+}
+
+/// Turns a line into a lifecycle event, or says what's wrong with it
+fn handle_line(line: &str, running: &mut HashMap<u32, u32>, lifecycle_sender: &broadcast::Sender<ProcessLifecycleEvent>) -> Result<(), String> {
+    let line = serde_json::from_str::<Line>(line)
+        .map_err(|_| format!("neither a ProcessEvent nor an end: {}", line))?;
+    match line {
+        Line::Started(event) | Line::Lifecycle(LifecycleLine::Started(event)) => {
+            let mut event = *event;
+            let pid = if event.probe_source.pid_type() == "system" {
+                event.real_pid.get_or_insert(event.pid);
+                event.pid
+            } else {
+                PIPE_PID_COUNTER.fetch_add(1, Ordering::Relaxed)
+            };
+            // Starting a PID again ends what it was before
+            if let Some(previous) = running.insert(event.pid, pid) {
+                let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid: previous, exit_code: None });
+            }
+            event.pid = pid;
+            if event.event_id.is_empty() {
+                event.event_id = platform::new_event_id();
+            }
+            if event.provenance.hostname.is_empty() {
+                event.provenance = Provenance::local();
+            }
+            event.received_at = Some(Instant::now());
+            debug!("Pipe source command started as PID {}: {}", pid, event.command);
+            let _ = lifecycle_sender.send(ProcessLifecycleEvent::Started(event));
+        }
+        Line::Lifecycle(LifecycleLine::Ended { pid, exit_code }) => {
+            let pid = running.remove(&pid).ok_or_else(|| format!("nothing started as PID {}", pid))?;
+            let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid, exit_code });
+        }
+    }
+    Ok(())
+}
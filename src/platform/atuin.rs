@@ -0,0 +1,301 @@
+// atuin history source
+// atuin (https://atuin.sh) records every shell command in a SQLite database, with its duration,
+// working directory and session. It's used two ways:
+// - Durations: typical durations of commands the service hasn't timed itself yet, for
+//   `expected_duration`, reloaded every few minutes.
+// - Probe: for terminals where our hooks can't be installed but atuin's are (`[atuin] probe`).
+//   atuin inserts a row with a duration of -1 when a command starts and fills it in when the
+//   command ends, so polling the table gives starts and ends. With atuin's daemon, rows are only
+//   written once commands end, which leaves nothing to poll for; the durations still work.
+// Rows synced from other machines are ignored.
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::platform::{EventSink, PlatformCapability, PlatformProbeTrait, ProbeData, ProbeFuture, ProbeSource, ProcessEvent, ProcessLifecycleEvent, Provenance};
+use crate::stats::StatsStore;
+
+/// How often the probe looks for new rows
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How often durations are reloaded
+const DURATIONS_REFRESH: Duration = Duration::from_secs(10 * 60);
+/// Recent durations kept per command, like the stats store
+const MAX_SAMPLES: usize = 10;
+
+/// Synthetic PIDs of commands seen in atuin's history, clear of the shell probe's and MQTT's
+static ATUIN_PID_COUNTER: AtomicU32 = AtomicU32::new(2_000_000_000);
+
+/// A row of atuin's `history` table
+pub struct HistoryRow {
+    pub id: String,
+    /// Nanoseconds since the Unix epoch
+    pub timestamp: i64,
+    /// Nanoseconds, or -1 while the command runs
+    pub duration: i64,
+    pub command: String,
+    pub cwd: String,
+    pub session: String,
+    /// `<host>:<user>`
+    pub hostname: String,
+}
+
+impl HistoryRow {
+    const COLUMNS: &'static str = "id, timestamp, duration, command, cwd, session, hostname";
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            timestamp: row.get(1)?,
+            duration: row.get(2)?,
+            command: row.get(3)?,
+            cwd: row.get(4)?,
+            session: row.get(5)?,
+            hostname: row.get(6)?,
+        })
+    }
+
+    /// Duration in seconds, once the command ended
+    pub fn duration_secs(&self) -> Option<u64> {
+        u64::try_from(self.duration).ok().map(|nanos| nanos / 1_000_000_000)
+    }
+
+    fn host(&self) -> &str {
+        self.hostname.split_once(':').map_or(self.hostname.as_str(), |(host, _)| host)
+    }
+
+    /// The event for a command that started
+    fn to_event(&self, pid: u32) -> ProcessEvent {
+        let mut event = ProcessEvent::new(pid, self.command.clone(), ProbeSource::Atuin);
+        event.working_directory = Some(self.cwd.clone()).filter(|cwd| !cwd.is_empty());
+        event.shell_session_id = Some(self.session.clone());
+        event.provenance.session_id = Some(self.session.clone());
+        if let Ok(timestamp) = u64::try_from(self.timestamp / 1_000_000_000) {
+            event.timestamp = timestamp;
+        }
+        event
+    }
+}
+
+fn open(path: &Path) -> Result<Connection, Box<dyn std::error::Error + Send + Sync>> {
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+        .map_err(|e| format!("Failed to open atuin's history at {:?}: {}", path, e).into())
+}
+
+/// Every command in the history, oldest first
+pub fn read_history(path: &Path) -> Result<Vec<HistoryRow>, Box<dyn std::error::Error + Send + Sync>> {
+    let connection = open(path)?;
+    let mut statement = connection.prepare(&format!(
+        "SELECT {} FROM history WHERE deleted_at IS NULL ORDER BY timestamp", HistoryRow::COLUMNS
+    ))?;
+    let rows = statement.query_map([], HistoryRow::from_row)?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Typical durations from atuin's history, by the stats store's command key
+pub struct AtuinDurations {
+    medians: RwLock<HashMap<String, u64>>,
+}
+
+impl AtuinDurations {
+    /// Loads durations in the background and keeps them fresh
+    pub fn start(path: PathBuf) -> Arc<Self> {
+        let durations = Arc::new(Self { medians: RwLock::new(HashMap::new()) });
+        let weak = Arc::downgrade(&durations);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(DURATIONS_REFRESH);
+            loop {
+                interval.tick().await;
+                let path = path.clone();
+                let medians = match tokio::task::spawn_blocking(move || load_medians(&path)).await {
+                    Ok(Ok(medians)) => medians,
+                    Ok(Err(e)) => {
+                        warn!("Failed to read durations from atuin: {}", e);
+                        continue;
+                    }
+                    Err(_) => continue,
+                };
+                let Some(durations) = weak.upgrade() else {
+                    break;
+                };
+                debug!("Loaded atuin durations for {} command(s)", medians.len());
+                *durations.medians.write().unwrap() = medians;
+            }
+        });
+        durations
+    }
+
+    /// Typical duration in seconds, if atuin has seen the command
+    pub fn expected_duration(&self, command: &str) -> Option<u64> {
+        self.medians.read().unwrap().get(&StatsStore::command_key(command)).copied()
+    }
+}
+
+fn load_medians(path: &Path) -> Result<HashMap<String, u64>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut samples: HashMap<String, Vec<u64>> = HashMap::new();
+    for row in read_history(path)? {
+        let Some(secs) = row.duration_secs() else {
+            continue;
+        };
+        let durations = samples.entry(StatsStore::command_key(&row.command)).or_default();
+        durations.push(secs);
+        if durations.len() > MAX_SAMPLES {
+            durations.remove(0);
+        }
+    }
+    Ok(samples.into_iter()
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, mut durations)| {
+            durations.sort_unstable();
+            let median = durations[durations.len() / 2];
+            (key, median)
+        })
+        .collect())
+}
+
+/// Reports commands as atuin records them
+pub struct AtuinProbe {
+    path: PathBuf,
+    lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
+    stopped: Arc<AtomicBool>,
+    thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+}
+
+impl AtuinProbe {
+    pub fn new(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>, config: &Config) -> Self {
+        Self {
+            path: config.atuin.db_path().unwrap_or_default(),
+            lifecycle_sender,
+            stopped: Arc::new(AtomicBool::new(false)),
+            thread: Mutex::new(None),
+        }
+    }
+}
+
+impl PlatformProbeTrait for AtuinProbe {
+    fn start(&self) -> ProbeFuture<'_> {
+        Box::pin(async move {
+            let connection = open(&self.path)?;
+            // Only commands from now on, not the whole history
+            let newest: i64 = connection.query_row("SELECT COALESCE(MAX(timestamp), 0) FROM history", [], |row| row.get(0))?;
+            info!("Watching atuin's history at {:?} for commands", self.path);
+
+            let poller = Poller {
+                connection,
+                newest,
+                running: HashMap::new(),
+                hostname: Provenance::local().hostname,
+                lifecycle_sender: self.lifecycle_sender.clone(),
+            };
+            let stopped = Arc::clone(&self.stopped);
+            let thread = std::thread::Builder::new()
+                .name("atuin-probe".to_string())
+                .spawn(move || poller.run(&stopped))?;
+            *self.thread.lock().unwrap() = Some(thread);
+            Ok(())
+        })
+    }
+
+    fn stop(&self) -> ProbeFuture<'_> {
+        Box::pin(async move {
+            self.stopped.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.lock().unwrap().take() {
+                let _ = thread.join();
+            }
+            Ok(())
+        })
+    }
+
+    fn get_capability(&self) -> PlatformCapability {
+        PlatformCapability::ShellOnly
+    }
+
+    fn provided_data(&self) -> ProbeData {
+        // atuin records where each command ran
+        ProbeData { working_directory: true, stalls: false, cycles: false, parent_app: false }
+    }
+}
+
+struct Poller {
+    connection: Connection,
+    /// Timestamp of the newest row seen
+    newest: i64,
+    /// Running commands by row ID, with their synthetic PIDs
+    running: HashMap<String, u32>,
+    hostname: String,
+    lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
+}
+
+impl Poller {
+    fn run(mut self, stopped: &AtomicBool) {
+        let mut failing = false;
+        while !stopped.load(Ordering::Relaxed) {
+            match self.poll() {
+                Ok(()) => failing = false,
+                // e.g. the database is locked for a moment; only log the first failure in a row
+                Err(e) if !failing => {
+                    warn!("Failed to read atuin's history: {}", e);
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn poll(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut statement = self.connection.prepare_cached(&format!(
+            "SELECT {} FROM history WHERE timestamp > ?1 AND deleted_at IS NULL ORDER BY timestamp", HistoryRow::COLUMNS
+        ))?;
+        let rows = statement.query_map([self.newest], HistoryRow::from_row)?.collect::<Result<Vec<_>, _>>()?;
+        drop(statement);
+        let mut events = Vec::new();
+        for row in rows {
+            self.newest = self.newest.max(row.timestamp);
+            // Commands that already ended were missed (or come from atuin's daemon), and synced
+            // ones ran elsewhere
+            if row.duration >= 0 || row.host() != self.hostname {
+                continue;
+            }
+            let pid = ATUIN_PID_COUNTER.fetch_add(1, Ordering::Relaxed);
+            debug!("atuin reported a command with synthetic PID {}: {}", pid, row.command);
+            self.running.insert(row.id.clone(), pid);
+            events.push(ProcessLifecycleEvent::Started(row.to_event(pid)));
+        }
+
+        // The starts found are sent even if looking for ends fails
+        let ended = self.ended();
+        for (id, exit_code) in ended.as_deref().unwrap_or_default() {
+            if let Some(pid) = self.running.remove(id) {
+                events.push(ProcessLifecycleEvent::Ended { pid, exit_code: *exit_code });
+            }
+        }
+        let _ = self.lifecycle_sender.send_batch(events);
+        ended?;
+        Ok(())
+    }
+
+    /// Running commands whose rows got a duration or were deleted, with their exit codes
+    fn ended(&self) -> Result<Vec<(String, Option<i32>)>, rusqlite::Error> {
+        let mut ended = Vec::new();
+        for id in self.running.keys() {
+            let row: Option<(i64, i64)> = self.connection
+                .query_row("SELECT duration, exit FROM history WHERE id = ?1 AND deleted_at IS NULL", [id],
+                           |row| Ok((row.get(0)?, row.get(1)?)))
+                .optional()?;
+            // Deleted rows end too, so nothing waits on them forever
+            match row {
+                Some((duration, exit)) if duration >= 0 => ended.push((id.clone(), i32::try_from(exit).ok())),
+                Some(_) => {}
+                None => ended.push((id.clone(), None)),
+            }
+        }
+        Ok(ended)
+    }
+}
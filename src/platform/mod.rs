@@ -1,49 +1,211 @@
 // Platform-specific probes for process monitoring
-// Each platform uses native, high-performance APIs as specified in the design
+// Each platform uses native, high-performance APIs as specified in the design.
+// Probes are used through the object-safe `PlatformProbeTrait`, and every probe can be described
+// and created on every platform: probes that can't run here (wrong OS, or left out of the build
+// via the `etw`/`wmi`/`shell`/`atuin` cargo features) report why at runtime instead of failing to
+// compile.
 
-#[cfg(windows)]
+#[cfg(all(windows, feature = "etw"))]
 pub mod windows_etw;
 
-#[cfg(windows)]
-pub use windows_etw::WindowsEtwProbe as PlatformProbe;
+// Polling WMI, for when ETW needs administrator rights the service doesn't have
+#[cfg(all(windows, feature = "wmi"))]
+pub mod windows_wmi;
 
 // POSIX shell probe
-#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[cfg(all(any(target_os = "linux", target_os = "macos"), feature = "shell"))]
 pub mod posix_shell;
 
-#[cfg(any(target_os = "linux", target_os = "macos"))]
-pub use posix_shell::PosixShellProbe as PlatformProbe;
+#[cfg(all(any(target_os = "linux", target_os = "macos"), feature = "shell"))]
+pub mod sandbox;
 
-// Stub implementation for other platforms
-#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+// atuin history probe, and durations from atuin
+#[cfg(feature = "atuin")]
+pub mod atuin;
+
+// Stand-in for probes that can't run here
 pub mod stub;
 
-#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
-pub use stub::StubProbe as PlatformProbe;
+// Capturing what probes receive, and replaying it
+pub mod recording;
 
 use serde::{Deserialize, Serialize};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::HashMap;
+use crate::config::Config;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// Tags probes add: `image`, the executable's file name, and `parent_app`, the closest of
+/// `[etw] parent_apps` the process was started from (ETW), and `stalled`, `cycle` and `replayed`
+/// (shell, see `STALLED_TAG`, `CYCLE_TAG` and `REPLAYED_TAG`)
+pub const PROBE_TAGS: &[&str] = &["image", "parent_app", STALLED_TAG, CYCLE_TAG, REPLAYED_TAG];
+
+/// Tag of the events `viberot-service wrap --detect-stall` causes while a command prints nothing;
+/// they start when it stalls and end when it prints again or ends, and only match `stalled` rules
+pub const STALLED_TAG: &str = "stalled";
+
+/// Tag of the events `viberot-service wrap --cycles` causes for each rebuild of a watch-mode
+/// command, numbered from 1; they only match `mode = "session"` rules
+pub const CYCLE_TAG: &str = "cycle";
+
+/// Tag of the commands a shell had running when the service started, which its hooks reported
+/// again; their timestamp is still when they started
+pub const REPLAYED_TAG: &str = "replayed";
 
 /// Identifies which probe detected the process event
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ProbeSource {
+    /// Kernel process events on Windows (real PIDs)
     WindowsEtw,
+    /// Processes found by polling WMI on Windows (real PIDs), without administrator rights
+    WindowsWmi,
+    /// bash/zsh hooks on Linux and macOS (synthetic PIDs)
+    #[serde(alias = "LinuxShell")]
     PosixShell,
-    MacOsDtrace,
-    // Future: LinuxEbpf, etc.
+    /// OSC 133 prompt marks seen by `viberot-service pty` on Linux and macOS (synthetic PIDs)
+    Osc133,
+    /// Commands other devices reported over MQTT (synthetic PIDs); runs next to a local probe
+    Mqtt,
+    /// Commands recorded in atuin's shell history (synthetic PIDs)
+    Atuin,
+    /// Rules triggered by hand over D-Bus (synthetic PIDs)
+    Manual,
+    /// Commands editors and agent plugins reported over `[websocket_source]` (synthetic PIDs)
+    WebSocket,
+    /// Commands other programs reported over `[pipe_source]` without naming a probe VibeRot
+    /// knows (synthetic PIDs)
+    External,
+    /// Runs of coding agents like Gemini CLI and Codex CLI, reported by their hooks over
+    /// `[agent_hooks]` (synthetic PIDs)
+    Agent,
+    /// Composer and agent runs of the Cursor editor, reported by its hooks over `[agent_hooks]`
+    /// (synthetic PIDs)
+    #[serde(alias = "cursor")]
+    Cursor,
+    // Future: LinuxEbpf, MacOsDtrace, etc.
+}
+
+impl ProbeSource {
+    /// Every local probe, in order of preference
+    pub const ALL: &'static [ProbeSource] = &[ProbeSource::WindowsEtw, ProbeSource::WindowsWmi, ProbeSource::PosixShell, ProbeSource::Osc133, ProbeSource::Atuin];
+
+    /// Whether PIDs from this probe are real OS PIDs or synthetic ones
+    pub fn pid_type(&self) -> &'static str {
+        match self {
+            ProbeSource::WindowsEtw | ProbeSource::WindowsWmi => "system",
+            ProbeSource::PosixShell | ProbeSource::Osc133 | ProbeSource::Mqtt | ProbeSource::Atuin | ProbeSource::Manual | ProbeSource::WebSocket
+                | ProbeSource::External | ProbeSource::Agent | ProbeSource::Cursor => "synthetic",
+        }
+    }
+}
+
+/// Structured metadata about where an event was observed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Version of the VibeRot service that produced the event
+    pub probe_version: String,
+    /// Hostname of the machine the command ran on
+    pub hostname: String,
+    /// OS login session the command belongs to (Windows session ID, `$XDG_SESSION_ID` elsewhere)
+    pub session_id: Option<String>,
+    /// Unix user that ran the command, when known
+    #[serde(default)]
+    pub uid: Option<u32>,
+}
+
+impl Provenance {
+    /// Provenance for events observed on this machine by this service
+    pub fn local() -> Self {
+        static HOSTNAME: OnceLock<String> = OnceLock::new();
+        let hostname = HOSTNAME.get_or_init(local_hostname).clone();
+
+        Self {
+            probe_version: env!("CARGO_PKG_VERSION").to_string(),
+            hostname,
+            session_id: std::env::var("XDG_SESSION_ID").ok(),
+            uid: local_uid(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn local_uid() -> Option<u32> {
+    // SAFETY: getuid has no preconditions
+    Some(unsafe { libc::getuid() })
+}
+
+#[cfg(not(unix))]
+fn local_uid() -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn local_hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return String::new();
+    }
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn local_hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// A unique ID for a start event
+pub fn new_event_id() -> String {
+    static EVENT_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{:x}-{:x}", now.as_nanos(), EVENT_COUNTER.fetch_add(1, Ordering::Relaxed))
 }
 
 /// Represents a process creation event
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessEvent {
+    /// Unique ID of this start event
+    #[serde(default)]
+    pub event_id: String,
     pub pid: u32,
+    /// Command line, valid UTF-8 in Unicode NFC form
     pub command: String,
+    /// Command line exactly as the probe reported it, when `command` differs from it: bytes that
+    /// aren't valid UTF-8, or text normalization changed. `OsStr` encoded bytes (WTF-8 on Windows)
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "base64_bytes")]
+    pub raw_command: Option<Vec<u8>>,
+    /// When the process started, in seconds since the Unix epoch: as the probe saw it when it
+    /// knows, or when the event was created
     pub timestamp: u64,
     pub working_directory: Option<String>,
     pub environment: Option<HashMap<String, String>>,
     pub shell_session_id: Option<String>, // Keep for context, but PID is primary identifier
     pub probe_source: ProbeSource,
+    /// Probe version, host and session the event came from
+    #[serde(default)]
+    pub provenance: Provenance,
+    /// Typical duration of this command in seconds, from the historical stats store
+    #[serde(default)]
+    pub expected_duration: Option<u64>,
+    /// OS PID of the command when known: `pid` itself for system PIDs, or the process behind a
+    /// synthetic PID when the shell hooks reported it
+    #[serde(default)]
+    pub real_pid: Option<u32>,
+    /// Terminal device the command runs on, e.g. `/dev/pts/3`, when the shell hooks reported it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tty: Option<String>,
+    /// Added by the enrichment pipeline, e.g. `repo`, `branch`, `category`, and by the probes
+    /// (`PROBE_TAGS`)
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// When this service received the event, for the processing latency metrics
+    #[serde(skip)]
+    pub received_at: Option<Instant>,
 }
 
 impl ProcessEvent {
@@ -54,16 +216,40 @@ impl ProcessEvent {
             .as_secs();
 
         Self {
+            event_id: new_event_id(),
             pid,
-            command,
+            command: crate::command_line::normalize_unicode(&command),
+            raw_command: None,
             timestamp,
             working_directory: None,
             environment: None,
             shell_session_id: None,
             probe_source,
+            provenance: Provenance::local(),
+            expected_duration: None,
+            real_pid: (probe_source.pid_type() == "system").then_some(pid),
+            tty: None,
+            tags: BTreeMap::new(),
+            received_at: Some(Instant::now()),
         }
     }
 
+    /// Event for a command line reported as bytes, which may not be valid UTF-8; they are kept in
+    /// `raw_command` if `command` can't represent them exactly
+    pub fn from_raw(pid: u32, raw_command: Vec<u8>, probe_source: ProbeSource) -> Self {
+        let mut event = Self::new(pid, String::from_utf8_lossy(&raw_command).into_owned(), probe_source);
+        if event.command.as_bytes() != raw_command {
+            event.raw_command = Some(raw_command);
+        }
+        event
+    }
+
+    /// How long ago the process started, as far as the probe knows
+    pub fn age(&self) -> Duration {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        Duration::from_secs(now.saturating_sub(self.timestamp))
+    }
+
     pub fn with_working_directory(mut self, wd: String) -> Self {
         self.working_directory = Some(wd);
         self
@@ -74,60 +260,398 @@ impl ProcessEvent {
         self
     }
 
+    #[allow(dead_code)]
+    pub fn with_session_id(mut self, session_id: String) -> Self {
+        self.provenance.session_id = Some(session_id);
+        self
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))] // Only the POSIX shell probe sets these
+    pub fn with_real_pid(mut self, real_pid: u32) -> Self {
+        self.real_pid = Some(real_pid);
+        self
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
     pub fn with_shell_session_id(mut self, session_id: String) -> Self {
         self.shell_session_id = Some(session_id);
         self
     }
+
+    /// Whether this is the `stalled` event of a command rather than its start
+    pub fn is_stall(&self) -> bool {
+        self.tags.contains_key(STALLED_TAG)
+    }
+
+    /// Whether this is a rebuild cycle of a watch-mode command rather than its start
+    pub fn is_cycle(&self) -> bool {
+        self.tags.contains_key(CYCLE_TAG)
+    }
+
+    /// Whether a shell reported this command again after the service started, rather than as it
+    /// started
+    pub fn is_replayed(&self) -> bool {
+        self.tags.contains_key(REPLAYED_TAG)
+    }
 }
 
+/// Serializes bytes as base64, like the shell hooks send commands
+mod base64_bytes {
+    use base64::{engine::general_purpose, Engine as _};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_str(&general_purpose::STANDARD.encode(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|encoded| general_purpose::STANDARD.decode(encoded).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// Future returned by probe methods; boxed so probes can be used as `dyn PlatformProbeTrait`
+pub type ProbeFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>>;
+
 /// Trait that all platform probes must implement
-pub trait PlatformProbeTrait {
+pub trait PlatformProbeTrait: Send + Sync {
     /// Start the probe and begin monitoring process events
-    async fn start(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    fn start(&self) -> ProbeFuture<'_>;
     
     /// Stop the probe
-    async fn stop(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    fn stop(&self) -> ProbeFuture<'_>;
 
     /// Get the capability level of this probe
     fn get_capability(&self) -> PlatformCapability;
+
+    /// Event data the probe reports besides the command line
+    fn provided_data(&self) -> ProbeData;
 }
 
-/// Auto-detect and choose the best available probe method
-pub fn detect_best_probe(
-    lifecycle_sender: tokio::sync::broadcast::Sender<ProcessLifecycleEvent>
-) -> (PlatformProbe, PlatformCapability) {
-    #[cfg(windows)]
+/// Whether a probe can run on this machine
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum ProbeAvailability {
+    Available,
+    Unavailable(String),
+}
+
+/// What a probe does and whether it can run here, e.g. for listing probes
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeInfo {
+    pub source: ProbeSource,
+    pub description: &'static str,
+    pub capability: PlatformCapability,
+    pub availability: ProbeAvailability,
+}
+
+/// Describes a probe and checks whether it can run on this machine
+pub fn probe_info(source: ProbeSource, config: &Config) -> ProbeInfo {
+    match source {
+        ProbeSource::WindowsEtw => ProbeInfo {
+            source,
+            description: "Kernel process events via Event Tracing for Windows",
+            capability: PlatformCapability::SystemWide,
+            availability: etw_availability(),
+        },
+        ProbeSource::WindowsWmi => ProbeInfo {
+            source,
+            description: "Processes found by polling WMI every second, when ETW lacks administrator rights; misses processes shorter than that",
+            capability: PlatformCapability::Polling,
+            availability: wmi_availability(),
+        },
+        ProbeSource::PosixShell => ProbeInfo {
+            source,
+            description: "Commands reported by bash/zsh hooks",
+            capability: PlatformCapability::ShellOnly,
+            availability: shell_availability(),
+        },
+        ProbeSource::Osc133 => ProbeInfo {
+            source,
+            description: "Commands between the OSC 133 prompt marks of shells run in `viberot-service pty`",
+            capability: PlatformCapability::ShellOnly,
+            // It reports to the shell hook socket
+            availability: shell_availability(),
+        },
+        ProbeSource::Mqtt => ProbeInfo {
+            source,
+            description: "Commands reported by other devices over MQTT (`[mqtt_source]`)",
+            // Like shell hooks, it only sees the commands someone reports
+            capability: PlatformCapability::ShellOnly,
+            availability: ProbeAvailability::Available,
+        },
+        ProbeSource::Atuin => ProbeInfo {
+            source,
+            description: "Commands recorded in atuin's shell history (`[atuin] probe = true`)",
+            capability: PlatformCapability::ShellOnly,
+            availability: atuin_availability(config),
+        },
+        ProbeSource::Manual => ProbeInfo {
+            source,
+            description: "Rules triggered by hand through `TriggerRule` on D-Bus",
+            capability: PlatformCapability::ShellOnly,
+            availability: ProbeAvailability::Available,
+        },
+        ProbeSource::WebSocket => ProbeInfo {
+            source,
+            description: "Commands editors and agent plugins report over a WebSocket (`[websocket_source]`)",
+            capability: PlatformCapability::ShellOnly,
+            availability: ProbeAvailability::Available,
+        },
+        ProbeSource::External => ProbeInfo {
+            source,
+            description: "Commands other programs report over a named pipe or Unix socket (`[pipe_source]`)",
+            capability: PlatformCapability::ShellOnly,
+            availability: ProbeAvailability::Available,
+        },
+        ProbeSource::Agent => ProbeInfo {
+            source,
+            description: "Runs of Gemini CLI and Codex CLI, reported by their hooks (`[agent_hooks]`)",
+            capability: PlatformCapability::ShellOnly,
+            availability: ProbeAvailability::Available,
+        },
+        ProbeSource::Cursor => ProbeInfo {
+            source,
+            description: "Agent runs in the Cursor editor, reported by its hooks (`[agent_hooks]`)",
+            capability: PlatformCapability::ShellOnly,
+            availability: ProbeAvailability::Available,
+        },
+    }
+}
+
+fn etw_availability() -> ProbeAvailability {
+    #[cfg(all(windows, feature = "etw"))]
+    {
+        // SAFETY: IsUserAnAdmin has no preconditions
+        if unsafe { windows::Win32::UI::Shell::IsUserAnAdmin() }.as_bool() {
+            ProbeAvailability::Available
+        } else {
+            ProbeAvailability::Unavailable("needs the service to run as administrator".to_string())
+        }
+    }
+    #[cfg(all(windows, not(feature = "etw")))]
     {
-        let probe = PlatformProbe::new(lifecycle_sender);
-        (probe, PlatformCapability::SystemWide)
+        ProbeAvailability::Unavailable("built without the `etw` feature".to_string())
     }
-    
-    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[cfg(not(windows))]
     {
-        let probe = PlatformProbe::new(lifecycle_sender);
-        (probe, PlatformCapability::ShellOnly)
+        ProbeAvailability::Unavailable("only available on Windows".to_string())
     }
-    
-    #[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+}
+
+fn wmi_availability() -> ProbeAvailability {
+    #[cfg(all(windows, feature = "wmi"))]
+    {
+        ProbeAvailability::Available
+    }
+    #[cfg(all(windows, not(feature = "wmi")))]
+    {
+        ProbeAvailability::Unavailable("built without the `wmi` feature".to_string())
+    }
+    #[cfg(not(windows))]
+    {
+        ProbeAvailability::Unavailable("only available on Windows".to_string())
+    }
+}
+
+fn atuin_availability(config: &Config) -> ProbeAvailability {
+    #[cfg(feature = "atuin")]
+    {
+        match config.atuin.db_path() {
+            Some(path) if path.exists() => ProbeAvailability::Available,
+            Some(path) => ProbeAvailability::Unavailable(format!("no atuin history at {}", path.display())),
+            None => ProbeAvailability::Unavailable("couldn't find the home directory".to_string()),
+        }
+    }
+    #[cfg(not(feature = "atuin"))]
+    {
+        let _ = config;
+        ProbeAvailability::Unavailable("built without the `atuin` feature".to_string())
+    }
+}
+
+fn shell_availability() -> ProbeAvailability {
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), feature = "shell"))]
+    {
+        ProbeAvailability::Available
+    }
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), not(feature = "shell")))]
+    {
+        ProbeAvailability::Unavailable("built without the `shell` feature".to_string())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
     {
-        let probe = PlatformProbe::new(lifecycle_sender);
-        (probe, PlatformCapability::Polling) // Stub implementation
+        ProbeAvailability::Unavailable("only available on Linux and macOS".to_string())
     }
 }
 
+/// Creates a probe, or explains why it can't run here
+pub fn create_probe(
+    source: ProbeSource,
+    lifecycle_sender: tokio::sync::broadcast::Sender<ProcessLifecycleEvent>,
+    config: &Config,
+) -> Result<Box<dyn PlatformProbeTrait>, String> {
+    if let ProbeAvailability::Unavailable(reason) = probe_info(source, config).availability {
+        return Err(format!("{:?} probe is unavailable: {}", source, reason));
+    }
+    // Unused when no probe is compiled in
+    let _ = (&lifecycle_sender, config);
+    match source {
+        #[cfg(all(windows, feature = "etw"))]
+        ProbeSource::WindowsEtw => Ok(Box::new(windows_etw::WindowsEtwProbe::new(lifecycle_sender, config))),
+        #[cfg(all(windows, feature = "wmi"))]
+        ProbeSource::WindowsWmi => Ok(Box::new(windows_wmi::WindowsWmiProbe::new(lifecycle_sender, config))),
+        #[cfg(all(any(target_os = "linux", target_os = "macos"), feature = "shell"))]
+        // One socket listener takes both the hooks' messages and `viberot-service pty`'s
+        ProbeSource::PosixShell | ProbeSource::Osc133 => Ok(Box::new(posix_shell::PosixShellProbe::new(lifecycle_sender, config))),
+        #[cfg(feature = "atuin")]
+        ProbeSource::Atuin => Ok(Box::new(atuin::AtuinProbe::new(lifecycle_sender, config))),
+        #[allow(unreachable_patterns)] // Only reached for probes left out of this build
+        _ => Err(format!("{:?} probe is not part of this build", source)),
+    }
+}
+
+/// Auto-detect and choose the best available probe method
+pub fn detect_best_probe(
+    lifecycle_sender: tokio::sync::broadcast::Sender<ProcessLifecycleEvent>,
+    config: &Config,
+) -> (Box<dyn PlatformProbeTrait>, PlatformCapability) {
+    let mut reasons = Vec::new();
+    // atuin's history is only a fallback unless asked for
+    let preferred = config.atuin.probe.then_some(ProbeSource::Atuin);
+    for source in preferred.into_iter().chain(ProbeSource::ALL.iter().copied().filter(|&s| Some(s) != preferred)) {
+        match create_probe(source, lifecycle_sender.clone(), config) {
+            Ok(probe) => {
+                let capability = probe.get_capability();
+                return (probe, capability);
+            }
+            Err(reason) => reasons.push(reason),
+        }
+    }
+    (Box::new(stub::UnsupportedProbe::new(reasons)), PlatformCapability::Polling)
+}
+
+/// Starts the probe `detect_best_probe` chose. ETW turning out to need administrator rights only
+/// once its session starts falls back to polling WMI.
+pub async fn start_probe(
+    probe: Box<dyn PlatformProbeTrait>,
+    lifecycle_sender: tokio::sync::broadcast::Sender<ProcessLifecycleEvent>,
+    config: &Config,
+) -> Result<Box<dyn PlatformProbeTrait>, Box<dyn std::error::Error + Send + Sync>> {
+    let error = match probe.start().await {
+        Ok(()) => return Ok(probe),
+        Err(e) => e,
+    };
+    // Unused without both Windows probes
+    let _ = (&lifecycle_sender, config);
+    #[cfg(all(windows, feature = "etw", feature = "wmi"))]
+    if error.downcast_ref::<windows_etw::AccessDenied>().is_some() {
+        tracing::warn!("{}; polling WMI for processes instead", error);
+        let fallback = create_probe(ProbeSource::WindowsWmi, lifecycle_sender, config)?;
+        fallback.start().await?;
+        return Ok(fallback);
+    }
+    Err(error)
+}
+
 /// Platform capability levels
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum PlatformCapability {
     SystemWide,    // eBPF, ETW, DTrace - monitors all processes
     ShellOnly,     // bash/zsh hooks - only monitors shell commands
-    Polling,       // fallback approach - periodic polling (future)
+    Polling,       // fallback approach - periodic polling, e.g. WMI
+}
+
+/// Event data a probe reports besides the command line, so rules depending on data it lacks can
+/// be flagged instead of silently never matching
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProbeData {
+    /// Working directories: `cwd`, and the repository, language and task command tags
+    pub working_directory: bool,
+    /// `stalled` events, from `viberot-service wrap --detect-stall`
+    pub stalls: bool,
+    /// Rebuild cycles, from `viberot-service wrap --cycles`
+    pub cycles: bool,
+    /// The app a process was started from (`parent_app`)
+    pub parent_app: bool,
+}
+
+impl ProbeData {
+    pub const ALL: ProbeData = ProbeData { working_directory: true, stalls: true, cycles: true, parent_app: true };
+}
+
+/// The probe of the running service, saved for CLI commands like `rules list`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveProbe {
+    pub capability: PlatformCapability,
+    pub data: ProbeData,
+}
+
+impl ActiveProbe {
+    pub fn save(&self, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The probe the service last ran with, if it ever ran
+    pub fn load(path: &std::path::Path) -> Option<Self> {
+        serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+    }
 }
 
 /// Extended process event that includes lifecycle information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)] // Events are infrequent, boxing isn't worth it
 pub enum ProcessLifecycleEvent {
     /// Process started
     Started(ProcessEvent),
-    /// Process ended
-    Ended { pid: u32 },
+    /// Process ended, with its exit code if the probe knows it
+    Ended { pid: u32, exit_code: Option<i32> },
+    /// The lifecycle `pid` (synthetic) turned out to be the OS process `real_pid`, e.g. a shell
+    /// command sent to the background. The lifecycle keeps its `pid`; its end is now detected from
+    /// `real_pid` exiting.
+    #[allow(dead_code)] // Only the shell probe reports upgrades
+    PidUpgraded { pid: u32, real_pid: u32 },
+    /// How far along the command `pid` is, as its source reports it, e.g. an editor's task;
+    /// `percent` from 0 to 100
+    Progress { pid: u32, percent: Option<u8>, message: Option<String> },
+    /// Events a source read in one go, e.g. one poll of a history database, sent with
+    /// `EventSink::send_batch`. They're handled in order with no other events in between, and
+    /// keep the times the source gave them.
+    #[allow(dead_code)] // Only the atuin and WMI probes send batches
+    Batch(Vec<ProcessLifecycleEvent>),
+}
+
+impl ProcessLifecycleEvent {
+    /// The events of a batch, nested ones included, or the event itself
+    pub fn into_events(self) -> Vec<ProcessLifecycleEvent> {
+        match self {
+            Self::Batch(events) => events.into_iter().flat_map(Self::into_events).collect(),
+            event => vec![event],
+        }
+    }
+}
+
+/// Where probes and sources send the commands they see
+#[allow(dead_code)] // Only the atuin and WMI probes send batches
+pub trait EventSink {
+    /// Sends the events as one batch, or as itself if there's only one; fails if nothing is
+    /// listening
+    fn send_batch(&self, events: Vec<ProcessLifecycleEvent>) -> Result<(), tokio::sync::broadcast::error::SendError<()>>;
+}
+
+impl EventSink for tokio::sync::broadcast::Sender<ProcessLifecycleEvent> {
+    fn send_batch(&self, mut events: Vec<ProcessLifecycleEvent>) -> Result<(), tokio::sync::broadcast::error::SendError<()>> {
+        let event = match events.len() {
+            0 => return Ok(()),
+            1 => events.remove(0),
+            _ => ProcessLifecycleEvent::Batch(events),
+        };
+        self.send(event).map(drop).map_err(|_| tokio::sync::broadcast::error::SendError(()))
+    }
 }
\ No newline at end of file
@@ -1,31 +1,80 @@
 use std::collections::HashMap;
-use std::env;
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU32, Ordering};
-use tokio::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::net::{TcpListener, UnixListener};
 use tokio::sync::{broadcast, Mutex};
-use tokio::io::{AsyncBufReadExt, BufReader as AsyncBufReader};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader as AsyncBufReader};
 use tracing::{info, warn, error, debug};
 use serde::{Deserialize, Serialize};
 use base64::{Engine as _, engine::general_purpose};
 
-use crate::platform::{PlatformProbeTrait, ProcessLifecycleEvent, ProcessEvent, ProbeSource, PlatformCapability};
+use crate::command_line::CommandLine;
+use crate::config::Config;
+use crate::i18n::{t, tf, Msg};
+use crate::platform::recording::{self, Record};
+use crate::platform::sandbox;
+use crate::platform::{PlatformProbeTrait, ProbeFuture, ProcessLifecycleEvent, ProcessEvent, ProbeSource, PlatformCapability, ProbeData, CYCLE_TAG, REPLAYED_TAG, STALLED_TAG};
+use crate::runtime_paths;
+use crate::shell_rc;
 
 /// Atomic counter for generating synthetic PIDs starting from 1,000,000
 /// to avoid collision with real system PIDs
 static SYNTHETIC_PID_COUNTER: AtomicU32 = AtomicU32::new(1_000_000);
 
+/// Commands shells had running when the service started that were picked up from their replay,
+/// and ends of commands started before it that weren't
+static PICKED_UP: AtomicU64 = AtomicU64::new(0);
+static MISSED_ENDS: AtomicU64 = AtomicU64::new(0);
+
+/// The shell hooks `setup` installs
+const SHELL_INTEGRATION_SCRIPT: &str = include_str!("../../scripts/shell_integration.sh");
+
+/// How often the sweeper checks whether registered shells are still alive
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
 /// POSIX shell-based process probe
 /// Uses shell hooks to monitor command execution in bash/zsh
 pub struct PosixShellProbe {
     lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
-    socket_path: PathBuf,
+    /// Sockets the probe listens on, e.g. one per container or sandboxed dev environment
+    socket_paths: Vec<PathBuf>,
+    /// Localhost TCP fallback for sandboxed shells that can't reach the sockets
+    tcp_port: Option<u16>,
+    /// Serving every user (broker): sockets anyone can connect to, no hook setup prompt
+    shared: bool,
     listener: Arc<Mutex<Option<UnixListener>>>,
-    /// Track active shell sessions mapping to their synthetic PIDs
-    active_sessions: Arc<Mutex<HashMap<String, u32>>>,
+    /// Track active shell sessions mapping to their in-flight commands
+    active_sessions: Arc<Mutex<HashMap<String, ShellSession>>>,
+}
+
+/// An in-flight command reported by a shell session
+#[derive(Debug, Clone, Copy)]
+struct ShellSession {
+    synthetic_pid: u32,
+    /// Real PID of the shell that sent the command, if the hooks reported it
+    shell_pid: Option<u32>,
+    /// Real PID of the command itself, when run through `viberot_track` or sent to the background
+    command_pid: Option<u32>,
+    /// Synthetic PID of the `stalled` event while `viberot-service wrap` says the command is stuck
+    stall_pid: Option<u32>,
+    /// Synthetic PID of the rebuild `viberot-service wrap --cycles` says the command is in
+    cycle_pid: Option<u32>,
+    /// Rebuilds started so far
+    cycles: u32,
+    /// Whether the shell shares our PID namespace, so the sweeper can check its PIDs
+    local_pids: bool,
+}
+
+impl ShellSession {
+    /// Synthetic PIDs of the lifecycles it has open, the `stalled` and rebuild ones included
+    fn pids(&self) -> impl Iterator<Item = u32> {
+        std::iter::once(self.synthetic_pid).chain(self.stall_pid).chain(self.cycle_pid)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,21 +87,59 @@ struct ShellMessage {
     pub working_directory: Option<String>,
     pub working_directory_b64: Option<String>,
     pub environment: Option<HashMap<String, String>>,
+    /// Real PID of the shell process, used to detect terminals closed mid-command
+    #[serde(default)]
+    pub shell_pid: Option<u32>,
+    /// Real PID of the command (`viberot_track` on start, `$!` when detached)
+    #[serde(default)]
+    pub pid: Option<u32>,
+    /// Terminal device the shell runs on, for progress sequences
+    #[serde(default)]
+    pub tty: Option<String>,
+    /// When the command started, in seconds since the Unix epoch (`$EPOCHSECONDS`), if the shell
+    /// knows
+    #[serde(default)]
+    pub started_at: Option<u64>,
+    /// Sent by `viberot-service pty` from the shell's OSC 133 marks rather than by the hooks
+    #[serde(default)]
+    pub prompt_marks: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[allow(clippy::enum_variant_names)] // Names are part of the hook protocol
 enum ShellEventType {
     CommandStart,
     CommandEnd,
+    /// The command went to the background; it ends when its real PID exits
+    CommandDetached,
+    /// `viberot-service wrap --detect-stall`: the command stopped printing anything
+    CommandStalled,
+    /// `viberot-service wrap --detect-stall`: a stalled command printed again
+    CommandResumed,
+    /// `viberot-service wrap --cycles`: a watch-mode command started a rebuild
+    CommandCycleStarted,
+    /// `viberot-service wrap --cycles`: the rebuild finished, with its exit code if the watcher
+    /// told
+    CommandCycleEnded,
+    /// The start of a command the shell has running, sent again once the hooks see the service
+    /// restarted (`.shell-instance` changed) so its end isn't one of an unknown session; with the
+    /// exit code if it ended before they could
+    CommandInFlight,
 }
 
 impl PosixShellProbe {
-    pub fn new(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) -> Self {
-        let socket_path = Self::get_socket_path();
+    pub fn new(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>, config: &Config) -> Self {
+        let socket_paths = if config.shell.socket_paths.is_empty() {
+            vec![Self::get_default_socket_path()]
+        } else {
+            config.shell.socket_paths.iter().map(PathBuf::from).collect()
+        };
         
         Self {
             lifecycle_sender,
-            socket_path,
+            socket_paths,
+            tcp_port: config.shell.tcp_port,
+            shared: config.shell.shared,
             listener: Arc::new(Mutex::new(None)),
             active_sessions: Arc::new(Mutex::new(HashMap::new())),
         }
@@ -93,27 +180,14 @@ impl PosixShellProbe {
         messages
     }
 
-    fn get_socket_path() -> PathBuf {
-        if let Ok(runtime_dir) = env::var("XDG_RUNTIME_DIR") {
-            PathBuf::from(runtime_dir).join("viberot-shell.sock")
-        } else {
-            dirs::runtime_dir()
-                .unwrap_or_else(|| PathBuf::from("/tmp"))
-                .join("viberot-shell.sock")
-        }
+    fn get_default_socket_path() -> PathBuf {
+        runtime_paths::shell_socket()
     }
 
     fn is_shell_integration_configured() -> bool {
-        // Just check if .viberot/shell_integration file exists in home directory
+        // Just check if .viberot/shell_integration file exists in the data directory
         // and assume integration is set up if it does
-        if let Some(home_dir) = dirs::home_dir() {
-            let integration_file = home_dir.join(".viberot/shell_integration.sh");
-            if integration_file.exists() {
-                return true;
-            }
-        }
-
-        false
+        runtime_paths::shell_integration_file().is_ok_and(|integration_file| integration_file.exists())
     }
 
     async fn setup_shell_hooks(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -124,49 +198,53 @@ impl PosixShellProbe {
         }
 
         println!();
-        println!("Welcome to VibeRot!");
+        println!("{}", t(Msg::SetupWelcome));
         println!("=================================");
         println!();
-        println!("To enable VibeRot to react to shell commands, please do the following.");
+        println!("{}", t(Msg::SetupIntro));
         println!();
-        println!("  cp scripts/shell_integration.sh $HOME/.viberot/shell_integration.sh");
+        let integration_file = runtime_paths::shell_integration_file()?;
+        println!("  cp scripts/shell_integration.sh {}", integration_file.display());
         println!();
-        println!("  echo '. \"$HOME/.viberot/shell_integration.sh\"' >> ~/.bashrc # for bash");
-        println!("  echo '. \"$HOME/.viberot/shell_integration.sh\"' >> ~/.zshrc  # for zsh");
+        println!("  echo '. \"{}\"' >> ~/.bashrc # for bash", integration_file.display());
+        println!("  echo '. \"{}\"' >> ~/.zshrc  # for zsh", integration_file.display());
         println!();
-        println!("Or manually add the following line to your shell configuration:");
-        println!("  . \"$HOME/.viberot/shell_integration.sh\"");
+        println!("{}", t(Msg::SetupManualLine));
+        println!("  . \"{}\"", integration_file.display());
         println!();
-        println!("IMPORTANT: For bash users:");
-        println!("  You need to install preexec and precmd functions for bash.");
-        println!("  See: https://github.com/rcaloras/bash-preexec");
+        println!("{}", t(Msg::SetupBashImportant));
+        println!("{}", t(Msg::SetupBashPreexec));
+        println!("{}", tf(Msg::SetupSee, &[&"https://github.com/rcaloras/bash-preexec"]));
         println!();
         println!("  curl https://raw.githubusercontent.com/rcaloras/bash-preexec/master/bash-preexec.sh -o ~/.bash-preexec.sh");
         println!("  source ~/.bash-preexec.sh");
         println!();
         
-        print!("Would you like VibeRot to set things up automatically for you? [y/N]: ");
+        print!("{}", t(Msg::SetupPrompt));
         std::io::stdout().flush()?;
         
         let mut input = String::new();
         std::io::stdin().read_line(&mut input)?;
         
-        if input.trim().to_lowercase().starts_with('y') {
+        let answer = input.trim().to_lowercase();
+        let accepted = answer.starts_with('y')
+            || t(Msg::SetupYesLetters).chars().any(|letter| answer.starts_with(letter));
+        if accepted {
             self.setup_automatic_integration().await?;
-            println!("Restart your shell or run the following to apply the changes:");
+            println!("{}", t(Msg::SetupRestartShell));
             println!("  . ~/.bashrc # for bash");
             println!("  . ~/.zshrc  # for zsh");
             println!();
         } else {
-            println!("Cancelled.");
-            println!("The service will continue running. You can set up shell integration later.");
-            println!("Note: To suppress this prompt in the future without setting up integration, you can create an empty file at:");
-            println!("  $HOME/.viberot/shell_integration.sh");
+            println!("{}", t(Msg::SetupCancelled));
+            println!("{}", t(Msg::SetupLater));
+            println!("{}", t(Msg::SetupSuppressHint));
+            println!("  {}", integration_file.display());
         }
         println!("=================================");
         println!();
-        println!("You can now let VibeRot run in the background and it will launch brainrot when a configured command is executed.");
-        println!("Tip: use nohup to keep it running after you close the terminal.");
+        println!("{}", t(Msg::SetupRunning));
+        println!("{}", t(Msg::SetupNohupTip));
         println!("  nohup target/release/viberot &");
 
         Ok(())
@@ -174,17 +252,17 @@ impl PosixShellProbe {
 
     async fn setup_automatic_integration(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let home_dir = dirs::home_dir().ok_or("Could not find home directory")?;
-        let integration_file = home_dir.join(".viberot").join("shell_integration.sh");
-        fs::create_dir_all(home_dir.join(".viberot"))?;
-        let bash_config_file = home_dir.join(format!(".bashrc"));
-        let zsh_config_file = home_dir.join(format!(".zshrc"));
+        let integration_file = runtime_paths::shell_integration_file()?;
+        fs::create_dir_all(runtime_paths::data_dir()?)?;
+        let bash_config_file = home_dir.join(".bashrc");
+        let zsh_config_file = home_dir.join(".zshrc");
 
         // Install preexec for bash
         let mut performed_bash_preexec_install = false;
         if bash_config_file.exists() {
             // Borrow some technical debt and just use system command to fetch the file
             if !home_dir.join(".bash-preexec.sh").exists() {
-                println!("Installing bash-preexec for bash...");
+                println!("{}", t(Msg::InstallingPreexec));
                 let status = std::process::Command::new("sh")
                     .arg("-c")
                     .arg("curl https://raw.githubusercontent.com/rcaloras/bash-preexec/master/bash-preexec.sh -o ~/.bash-preexec.sh")
@@ -193,7 +271,7 @@ impl PosixShellProbe {
                     warn!("Failed to download bash-preexec.sh, please install it manually with the instructions above.");
                 } else {
                     performed_bash_preexec_install = true;
-                    let source_line = "\n# Load bash-preexec for VibeRot\nif [ -f \"$HOME/.bash-preexec.sh\" ]; then\n  source \"$HOME/.bash-preexec.sh\"\nfi\n";
+                    let source_line = shell_rc::block("bash-preexec", "if [ -f \"$HOME/.bash-preexec.sh\" ]; then\n  source \"$HOME/.bash-preexec.sh\"\nfi\n");
                     fs::OpenOptions::new()
                         .create(false)
                         .append(true)
@@ -205,17 +283,11 @@ impl PosixShellProbe {
             }
         }
         
-        // Get the absolute path to the viberot.sh script
-        let current_dir = env::current_dir()?;
-        let script_path = current_dir.join("scripts").join("viberot.sh");
-
-        if !script_path.exists() {
-            return Err(format!("Could not find script at expected path: {}", script_path.display()).into());
-        }
-        fs::write(&integration_file, fs::read_to_string(&script_path)?)?;
+        // Built in, so setup doesn't need a checkout to copy it from
+        fs::write(&integration_file, SHELL_INTEGRATION_SCRIPT)?;
         
         // Add line to source the integration file in shell config
-        let source_line = format!("\n# VibeRot shell integration\n. \"{}\"\n", integration_file.display());
+        let source_line = shell_rc::block("shell integration", &format!(". \"{}\"\n", integration_file.display()));
         
         if bash_config_file.exists() {
             fs::OpenOptions::new()
@@ -232,53 +304,74 @@ impl PosixShellProbe {
                 .write_all(source_line.as_bytes())?;
         }
         
-        println!("\n✅ Shell integration installed successfully!");
-        println!("  Created: {}", integration_file.display());
+        println!("{}", t(Msg::InstallSuccess));
+        println!("{}", tf(Msg::InstallCreated, &[&integration_file.display()]));
         if performed_bash_preexec_install {
-            println!("  Created: $HOME/.bash-preexec.sh");
+            println!("{}", tf(Msg::InstallCreated, &[&"$HOME/.bash-preexec.sh"]));
         }
         if bash_config_file.exists() {
-            println!("  Updated: {}", bash_config_file.display());
+            println!("{}", tf(Msg::InstallUpdated, &[&bash_config_file.display()]));
         }
         if zsh_config_file.exists() {
-            println!("  Updated: {}", zsh_config_file.display());
+            println!("{}", tf(Msg::InstallUpdated, &[&zsh_config_file.display()]));
         }
         println!();
         
         Ok(())
     }
 
-    async fn start_socket_server(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // Remove existing socket file if it exists
-        if self.socket_path.exists() {
-            fs::remove_file(&self.socket_path)?;
+    async fn start_socket_servers(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for socket_path in &self.socket_paths {
+            self.start_socket_server(socket_path).await?;
+        }
+        if let Some(port) = self.tcp_port {
+            self.start_tcp_server(port).await?;
         }
 
-        let listener = UnixListener::bind(&self.socket_path)?;
-        info!("Shell probe listening on: {}", self.socket_path.display());
+        // We don't need to store the listeners since we can't really stop individual listeners cleanly
+        {
+            let mut listener_guard = self.listener.lock().await;
+            *listener_guard = None;
+        }
+
+        Ok(())
+    }
+
+    async fn start_socket_server(&self, socket_path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Replace a socket left by an earlier run, but nothing else a configured path may point at
+        match fs::symlink_metadata(socket_path) {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(socket_path)?,
+            Ok(_) => return Err(format!("Not replacing {} with the shell socket: it isn't a socket", socket_path.display()).into()),
+            Err(_) => {}
+        }
+        if let Some(parent) = socket_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let listener = UnixListener::bind(socket_path)
+            .map_err(|e| format!("Failed to bind shell socket {}: {}", socket_path.display(), e))?;
+        if self.shared {
+            // Commands are attributed to whoever connects, see `handle_connection`
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(socket_path, fs::Permissions::from_mode(0o666))?;
+        }
+        info!("Shell probe listening on: {}", socket_path.display());
 
         // Accept connections in a loop
         let lifecycle_sender = self.lifecycle_sender.clone();
         let active_sessions = Arc::clone(&self.active_sessions);
-        let listener_handle = Arc::new(Mutex::new(listener));
-        
-        // Store the listener for cleanup
-        {
-            let mut listener_guard = self.listener.lock().await;
-            *listener_guard = None; // We don't need to store it since we can't really stop individual listeners cleanly
-        }
 
-        let listener_for_task = Arc::clone(&listener_handle);
         tokio::spawn(async move {
             loop {
-                let listener = listener_for_task.lock().await;
                 match listener.accept().await {
                     Ok((stream, _)) => {
-                        drop(listener); // Release the lock
                         let sender = lifecycle_sender.clone();
                         let sessions = Arc::clone(&active_sessions);
+                        let peer = stream.peer_cred().ok();
+                        let peer_uid = peer.map(|cred| cred.uid());
+                        let local_pids = shares_pid_namespace(peer.and_then(|cred| cred.pid()));
                         tokio::spawn(async move {
-                            if let Err(e) = Self::handle_connection(stream, sender, sessions).await {
+                            if let Err(e) = Self::handle_connection(stream, peer_uid, local_pids, sender, sessions).await {
                                 debug!("Connection handling error: {}", e);
                             }
                         });
@@ -294,13 +387,51 @@ impl PosixShellProbe {
         Ok(())
     }
 
-    async fn handle_connection(
-        stream: UnixStream,
+    /// Localhost-only TCP listener used by shells inside sandboxes
+    async fn start_tcp_server(&self, port: u16) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await
+            .map_err(|e| format!("Failed to bind shell TCP fallback on 127.0.0.1:{}: {}", port, e))?;
+        info!("Shell probe listening on: 127.0.0.1:{}", port);
+
+        let lifecycle_sender = self.lifecycle_sender.clone();
+        let active_sessions = Arc::clone(&self.active_sessions);
+
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let sender = lifecycle_sender.clone();
+                        let sessions = Arc::clone(&active_sessions);
+                        tokio::spawn(async move {
+                            // Any local user can connect over TCP, from any PID namespace
+                            if let Err(e) = Self::handle_connection(stream, None, false, sender, sessions).await {
+                                debug!("Connection handling error: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        error!("Failed to accept TCP connection: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Reads hook messages from one shell; `peer_uid` is the user on the other end, if known, and
+    /// `local_pids` whether the PIDs it reports are ours to check
+    async fn handle_connection<S: AsyncRead + Unpin>(
+        stream: S,
+        peer_uid: Option<u32>,
+        local_pids: bool,
         lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
-        active_sessions: Arc<Mutex<HashMap<String, u32>>>,
+        active_sessions: Arc<Mutex<HashMap<String, ShellSession>>>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let mut reader = AsyncBufReader::new(stream);
         let mut buffer = String::new();
+        let recording = recording::recorder().map(|recorder| (recorder, recorder.connection()));
 
         loop {
             let mut line = String::new();
@@ -309,140 +440,378 @@ impl PosixShellProbe {
             if bytes_read == 0 {
                 break; // EOF
             }
-            
-            buffer.push_str(&line);
-            
-            // Try to parse complete JSON messages from buffer
-            let messages = Self::extract_json_messages(&mut buffer);
-            
-            for json_str in messages {
-                match serde_json::from_str::<ShellMessage>(&json_str) {
-                    Ok(msg) => {
-                        match msg.event_type {
-                            ShellEventType::CommandStart => {
-                                // Decode command from base64 or use plain text
-                                let command = if let Some(cmd_b64) = msg.command_b64 {
-                                    match general_purpose::STANDARD.decode(&cmd_b64) {
-                                        Ok(decoded_bytes) => String::from_utf8_lossy(&decoded_bytes).to_string(),
-                                        Err(_) => msg.command.unwrap_or_else(|| "<decode error>".to_string()),
-                                    }
-                                } else {
-                                    msg.command.unwrap_or_else(|| "<unknown command>".to_string())
+            if let Some((recorder, connection)) = recording {
+                recorder.record(Record::Shell { connection, peer_uid, line: line.clone() });
+            }
+
+            Self::handle_line(&mut buffer, &line, peer_uid, local_pids, &lifecycle_sender, &active_sessions).await;
+        }
+
+        Ok(())
+    }
+
+    /// Handles a line a shell sent on a connection, whose unfinished messages are kept in `buffer`
+    async fn handle_line(
+        buffer: &mut String,
+        line: &str,
+        peer_uid: Option<u32>,
+        local_pids: bool,
+        lifecycle_sender: &broadcast::Sender<ProcessLifecycleEvent>,
+        active_sessions: &Mutex<HashMap<String, ShellSession>>,
+    ) {
+        buffer.push_str(line);
+        
+        // Try to parse complete JSON messages from buffer
+        let messages = Self::extract_json_messages(buffer);
+        
+        for json_str in messages {
+            match serde_json::from_str::<ShellMessage>(&json_str) {
+                Ok(msg) => {
+                    // Session IDs come from the hooks, so keep users from touching each other's
+                    let session_key = match peer_uid {
+                        Some(uid) => format!("{}:{}", uid, msg.session_id),
+                        None => msg.session_id.clone(),
+                    };
+                    match msg.event_type {
+                        ShellEventType::CommandStart => {
+                            // `viberot-service wrap` reports the command it runs itself
+                            if Self::is_wrapper(&msg) {
+                                debug!("Leaving a command to the wrapper it runs in, session {}", msg.session_id);
+                                continue;
+                            }
+
+                            // Generate synthetic PID for this command
+                            let synthetic_pid = Self::generate_synthetic_pid();
+                            let event = Self::command_event(&msg, synthetic_pid, peer_uid);
+
+                            // Store the session-to-PID mapping for later matching
+                            {
+                                let mut sessions = active_sessions.lock().await;
+                                let session = ShellSession {
+                                    synthetic_pid,
+                                    shell_pid: msg.shell_pid,
+                                    command_pid: msg.pid,
+                                    stall_pid: None,
+                                    cycle_pid: None,
+                                    cycles: 0,
+                                    local_pids,
                                 };
-                            
-                                // Generate synthetic PID for this command
-                                let synthetic_pid = Self::generate_synthetic_pid();
-                            
-                                let mut event = ProcessEvent::new(synthetic_pid, command, ProbeSource::PosixShell)
-                                    .with_shell_session_id(msg.session_id.clone());
-
-                                // Decode working directory from base64 or use plain text
-                                if let Some(wd_b64) = msg.working_directory_b64 {
-                                    match general_purpose::STANDARD.decode(&wd_b64) {
-                                        Ok(decoded_bytes) => {
-                                            let wd = String::from_utf8_lossy(&decoded_bytes).to_string();
-                                            event = event.with_working_directory(wd);
-                                        },
-                                        Err(_) => {
-                                            if let Some(wd) = msg.working_directory {
-                                                event = event.with_working_directory(wd);
-                                            }
-                                        }
+                                if let Some(previous) = sessions.insert(session_key, session) {
+                                    // The shell never reported the end of its previous command
+                                    debug!("Session {} started a new command, ending stale synthetic PID {}", msg.session_id, previous.synthetic_pid);
+                                    for pid in previous.pids() {
+                                        let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid, exit_code: None });
                                     }
-                                } else if let Some(wd) = msg.working_directory {
-                                    event = event.with_working_directory(wd);
-                                }
-
-                                if let Some(env) = msg.environment {
-                                    event = event.with_environment(env);
                                 }
+                            }
 
-                                // Store the session-to-PID mapping for later matching
-                                {
-                                    let mut sessions = active_sessions.lock().await;
-                                    sessions.insert(msg.session_id.clone(), synthetic_pid);
+                            debug!("Shell command started with synthetic PID {}: {}", synthetic_pid, event.command);
+                        
+                            let lifecycle_event = ProcessLifecycleEvent::Started(event);
+                            if let Err(e) = lifecycle_sender.send(lifecycle_event) {
+                                debug!("Failed to send start event: {}", e);
+                            }
+                        }
+                        ShellEventType::CommandEnd => {
+                            // Remove from active sessions and send end event with the stored PID
+                            let mut sessions = active_sessions.lock().await;
+                            if let Some(ShellSession { synthetic_pid, stall_pid, cycle_pid, .. }) = sessions.remove(&session_key) {
+                                debug!("Shell command ended with synthetic PID {}", synthetic_pid);
+                                for pid in stall_pid.into_iter().chain(cycle_pid) {
+                                    let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid, exit_code: msg.exit_code });
                                 }
-
-                                debug!("Shell command started with synthetic PID {}: {}", synthetic_pid, event.command);
                             
-                                let lifecycle_event = ProcessLifecycleEvent::Started(event);
+                                let lifecycle_event = ProcessLifecycleEvent::Ended {
+                                    pid: synthetic_pid,
+                                    exit_code: msg.exit_code,
+                                };
                                 if let Err(e) = lifecycle_sender.send(lifecycle_event) {
-                                    debug!("Failed to send start event: {}", e);
+                                    debug!("Failed to send end event: {}", e);
                                 }
+                            } else {
+                                MISSED_ENDS.fetch_add(1, Ordering::Relaxed);
+                                debug!("Received end event for unknown session: {}", msg.session_id);
                             }
-                            ShellEventType::CommandEnd => {
-                                // Remove from active sessions and send end event with the stored PID
-                                let mut sessions = active_sessions.lock().await;
-                                if let Some(synthetic_pid) = sessions.remove(&msg.session_id) {
-                                    debug!("Shell command ended with synthetic PID {}", synthetic_pid);
-                                
-                                    let lifecycle_event = ProcessLifecycleEvent::Ended {
-                                        pid: synthetic_pid,
-                                    };
-                                    if let Err(e) = lifecycle_sender.send(lifecycle_event) {
-                                        debug!("Failed to send end event: {}", e);
-                                    }
-                                } else {
-                                    debug!("Received end event for unknown session: {}", msg.session_id);
-                                }
+                        }
+                        ShellEventType::CommandInFlight => {
+                            let mut sessions = active_sessions.lock().await;
+                            if sessions.contains_key(&session_key) || Self::is_wrapper(&msg) {
+                                continue;
+                            }
+                            // Ended already, its end follows: starting its actions now would only
+                            // flash them
+                            if let Some(exit_code) = msg.exit_code {
+                                info!("Session {} ran a command from before the service started until exit code {}, not counting it",
+                                      msg.session_id, exit_code);
+                                continue;
+                            }
+                            let synthetic_pid = Self::generate_synthetic_pid();
+                            let mut event = Self::command_event(&msg, synthetic_pid, peer_uid);
+                            event.tags.insert(REPLAYED_TAG.to_string(), "true".to_string());
+                            sessions.insert(session_key, ShellSession {
+                                synthetic_pid,
+                                shell_pid: msg.shell_pid,
+                                command_pid: msg.pid,
+                                stall_pid: None,
+                                cycle_pid: None,
+                                cycles: 0,
+                                local_pids,
+                            });
+                            PICKED_UP.fetch_add(1, Ordering::Relaxed);
+                            info!("Session {} has had a command running since before the service started, picked it up as synthetic PID {}: {}",
+                                  msg.session_id, synthetic_pid, event.command);
+
+                            if let Err(e) = lifecycle_sender.send(ProcessLifecycleEvent::Started(event)) {
+                                debug!("Failed to send replayed start event: {}", e);
+                            }
+                        }
+                        ShellEventType::CommandDetached => {
+                            let Some(real_pid) = msg.pid else {
+                                debug!("Ignoring detach without a PID from session {}", msg.session_id);
+                                continue;
+                            };
+                            let mut sessions = active_sessions.lock().await;
+                            let Some(session) = sessions.remove(&session_key) else {
+                                debug!("Received detach event for unknown session: {}", msg.session_id);
+                                continue;
+                            };
+                            // Keep tracking it apart from the shell's next command, by the job's own PID.
+                            // Background jobs may outlive the shell, so only the job's PID is checked.
+                            let job = ShellSession {
+                                synthetic_pid: session.synthetic_pid,
+                                shell_pid: None,
+                                command_pid: Some(real_pid),
+                                stall_pid: session.stall_pid,
+                                cycle_pid: session.cycle_pid,
+                                cycles: session.cycles,
+                                local_pids: session.local_pids,
+                            };
+                            sessions.insert(format!("{}:bg:{}", session_key, real_pid), job);
+                            debug!("Synthetic PID {} went to the background as PID {}", session.synthetic_pid, real_pid);
+
+                            let lifecycle_event = ProcessLifecycleEvent::PidUpgraded {
+                                pid: session.synthetic_pid,
+                                real_pid,
+                            };
+                            if let Err(e) = lifecycle_sender.send(lifecycle_event) {
+                                debug!("Failed to send PID upgrade event: {}", e);
+                            }
+                        }
+                        ShellEventType::CommandStalled => {
+                            let mut sessions = active_sessions.lock().await;
+                            let Some(session) = sessions.get_mut(&session_key) else {
+                                debug!("Received stall event for unknown session: {}", msg.session_id);
+                                continue;
+                            };
+                            if session.stall_pid.is_some() {
+                                continue;
+                            }
+                            // A lifecycle of its own while the command is stuck, for rules with
+                            // `stalled = true`
+                            let stall_pid = Self::generate_synthetic_pid();
+                            let mut event = Self::command_event(&msg, stall_pid, peer_uid);
+                            event.real_pid = session.command_pid;
+                            event.tags.insert(STALLED_TAG.to_string(), "true".to_string());
+                            session.stall_pid = Some(stall_pid);
+                            debug!("Synthetic PID {} stalled, as synthetic PID {}", session.synthetic_pid, stall_pid);
+
+                            if let Err(e) = lifecycle_sender.send(ProcessLifecycleEvent::Started(event)) {
+                                debug!("Failed to send stall event: {}", e);
+                            }
+                        }
+                        ShellEventType::CommandResumed => {
+                            let mut sessions = active_sessions.lock().await;
+                            let Some(stall_pid) = sessions.get_mut(&session_key).and_then(|session| session.stall_pid.take()) else {
+                                debug!("Received resume event for a session that isn't stalled: {}", msg.session_id);
+                                continue;
+                            };
+                            debug!("Stalled synthetic PID {} resumed", stall_pid);
+                            if let Err(e) = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid: stall_pid, exit_code: None }) {
+                                debug!("Failed to send resume event: {}", e);
+                            }
+                        }
+                        ShellEventType::CommandCycleStarted => {
+                            let mut sessions = active_sessions.lock().await;
+                            let Some(session) = sessions.get_mut(&session_key) else {
+                                debug!("Received rebuild event for unknown session: {}", msg.session_id);
+                                continue;
+                            };
+                            // The watcher didn't say the previous rebuild finished
+                            if let Some(pid) = session.cycle_pid.take() {
+                                let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid, exit_code: None });
+                            }
+                            // A lifecycle of its own for each rebuild, for rules with
+                            // `mode = "session"`
+                            let cycle_pid = Self::generate_synthetic_pid();
+                            session.cycles += 1;
+                            let mut event = Self::command_event(&msg, cycle_pid, peer_uid);
+                            event.real_pid = session.command_pid;
+                            event.tags.insert(CYCLE_TAG.to_string(), session.cycles.to_string());
+                            session.cycle_pid = Some(cycle_pid);
+                            debug!("Synthetic PID {} started rebuild {}, as synthetic PID {}", session.synthetic_pid, session.cycles, cycle_pid);
+
+                            if let Err(e) = lifecycle_sender.send(ProcessLifecycleEvent::Started(event)) {
+                                debug!("Failed to send rebuild event: {}", e);
+                            }
+                        }
+                        ShellEventType::CommandCycleEnded => {
+                            let mut sessions = active_sessions.lock().await;
+                            let Some(cycle_pid) = sessions.get_mut(&session_key).and_then(|session| session.cycle_pid.take()) else {
+                                debug!("Received rebuild end for a session that isn't rebuilding: {}", msg.session_id);
+                                continue;
+                            };
+                            debug!("Rebuild with synthetic PID {} finished", cycle_pid);
+                            if let Err(e) = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid: cycle_pid, exit_code: msg.exit_code }) {
+                                debug!("Failed to send rebuild end event: {}", e);
                             }
                         }
                     }
-                    Err(e) => {
-                        debug!("Failed to parse shell message '{}': {}", json_str, e);
-                        // Don't return error - just log and continue processing other messages
-                    }
+                }
+                Err(e) => {
+                    debug!("Failed to parse shell message '{}': {}", json_str, e);
+                    // Don't return error - just log and continue processing other messages
                 }
             }
         }
+    }
 
-        Ok(())
+    /// Event for the command a start, stall or rebuild message describes
+    fn command_event(msg: &ShellMessage, synthetic_pid: u32, peer_uid: Option<u32>) -> ProcessEvent {
+        // Decode command from base64 or use plain text
+        // Commands aren't necessarily valid UTF-8, so keep the bytes
+        let command = match msg.command_b64.as_deref().map(|cmd_b64| general_purpose::STANDARD.decode(cmd_b64)) {
+            Some(Ok(decoded_bytes)) => decoded_bytes,
+            Some(Err(_)) => msg.command.clone().unwrap_or_else(|| "<decode error>".to_string()).into_bytes(),
+            None => msg.command.clone().unwrap_or_else(|| "<unknown command>".to_string()).into_bytes(),
+        };
+
+        let source = if msg.prompt_marks { ProbeSource::Osc133 } else { ProbeSource::PosixShell };
+        let mut event = ProcessEvent::from_raw(synthetic_pid, command, source)
+            .with_shell_session_id(msg.session_id.clone());
+        event.provenance.uid = peer_uid;
+
+        // Decode working directory from base64 or use plain text
+        let working_directory = match msg.working_directory_b64.as_deref().map(|wd_b64| general_purpose::STANDARD.decode(wd_b64)) {
+            Some(Ok(decoded_bytes)) => Some(String::from_utf8_lossy(&decoded_bytes).to_string()),
+            _ => msg.working_directory.clone(),
+        };
+        if let Some(wd) = working_directory {
+            event = event.with_working_directory(wd);
+        }
+
+        if let Some(ref env) = msg.environment {
+            event = event.with_environment(env.clone());
+        }
+
+        if let Some(pid) = msg.pid {
+            event = event.with_real_pid(pid);
+        }
+        event.tty = msg.tty.clone();
+        if let Some(started_at) = msg.started_at {
+            event.timestamp = started_at;
+        }
+        event
+    }
+
+    /// Whether a start message is the shell running `viberot-service wrap ...` or `pty`, which
+    /// report the commands in them themselves
+    fn is_wrapper(msg: &ShellMessage) -> bool {
+        let Some(Ok(command)) = msg.command_b64.as_deref().map(|cmd_b64| general_purpose::STANDARD.decode(cmd_b64)) else {
+            return false;
+        };
+        let command_line = CommandLine::parse(&String::from_utf8_lossy(&command));
+        command_line.exe == "viberot-service" && command_line.args.first().is_some_and(|arg| arg == "wrap" || arg == "pty")
+    }
+
+    /// Returns true if a process with the given PID still exists
+    fn is_process_alive(pid: u32) -> bool {
+        // Signal 0 performs error checking only; EPERM still means the process exists
+        let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+        result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+
+    /// Periodically synthesizes Ended events for sessions whose shell has exited,
+    /// e.g. when a terminal is closed while a command is still running, and for
+    /// background jobs whose process has exited
+    fn start_session_sweeper(&self) {
+        let lifecycle_sender = self.lifecycle_sender.clone();
+        let active_sessions = Arc::clone(&self.active_sessions);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SESSION_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+
+                let mut sessions = active_sessions.lock().await;
+                sessions.retain(|session_id, session| {
+                    // PIDs from another namespace (a container, over TCP) may be a different
+                    // process here; those sessions end when their shell reports it
+                    if !session.local_pids {
+                        return true;
+                    }
+                    // Foreground commands end with their shell's next prompt, so the shell is what
+                    // to watch; background jobs have no shell PID and are watched directly
+                    let Some(watched_pid) = session.shell_pid.or(session.command_pid) else {
+                        // Older hooks don't report the shell PID, nothing to check
+                        return true;
+                    };
+                    if Self::is_process_alive(watched_pid) {
+                        return true;
+                    }
+
+                    info!("Process {} (session {}) exited mid-command, ending synthetic PID {}",
+                          watched_pid, session_id, session.synthetic_pid);
+                    for pid in session.pids() {
+                        let lifecycle_event = ProcessLifecycleEvent::Ended { pid, exit_code: None };
+                        if let Err(e) = lifecycle_sender.send(lifecycle_event) {
+                            debug!("Failed to send end event for dead session: {}", e);
+                        }
+                    }
+                    false
+                });
+            }
+        });
     }
 }
 
-impl PlatformProbeTrait for PosixShellProbe {
-    async fn start(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+impl PosixShellProbe {
+    async fn start_probe(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         info!("Starting POSIX shell probe for process monitoring");
 
-        // Write socket path to ~/.viberot/.socket file for shell integration
-        if let Some(home_dir) = dirs::home_dir() {
-            let viberot_dir = home_dir.join(".viberot");
-            fs::create_dir_all(&viberot_dir)?;
-            let socket_file = viberot_dir.join(".socket");
-            fs::write(&socket_file, self.socket_path.to_string_lossy().as_bytes())?;
-            info!("Wrote socket path to: {}", socket_file.display());
-        } else {
-            warn!("Could not find home directory, shell integration may not work");
-        }
+        // Write socket paths to ~/.viberot/.socket file for shell integration
+        write_socket_discovery_file(&self.socket_paths, self.tcp_port)?;
 
-        // First, set up shell hooks (with user approval)
+        // First, set up shell hooks (with user approval); each user does that for themselves
+        // when the probe serves all of them
         // Continue running the service regardless of setup success/failure
-        if let Err(e) = self.setup_shell_hooks().await {
+        if self.shared {
+            info!("Serving shell hooks of every user");
+        } else if let Err(e) = self.setup_shell_hooks().await {
             warn!("Shell hook setup failed: {}", e);
-            println!("\n⚠️  Shell integration setup was not completed.");
-            println!("The VibeRot service will continue running, but shell command monitoring will not work");
-            println!("until you manually set up the integration as described above.\n");
+            println!("{}", t(Msg::SetupIncomplete));
         }
 
         // Then start the socket server
-        self.start_socket_server().await?;
+        self.start_socket_servers().await?;
+        self.start_session_sweeper();
 
         info!("POSIX shell probe started successfully");
         Ok(())
     }
 
-    async fn stop(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    async fn stop_probe(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         info!("Stopping POSIX shell probe");
 
-        // Remove socket file
-        if self.socket_path.exists() {
-            if let Err(e) = fs::remove_file(&self.socket_path) {
-                warn!("Failed to remove socket file: {}", e);
+        // Remove socket files
+        for socket_path in &self.socket_paths {
+            if fs::symlink_metadata(socket_path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                if let Err(e) = fs::remove_file(socket_path) {
+                    warn!("Failed to remove socket file {}: {}", socket_path.display(), e);
+                }
             }
         }
         // Remove socket path file
-        if let Some(home_dir) = dirs::home_dir() {
-            let socket_file = home_dir.join(".viberot").join(".socket");
+        if let Ok(socket_file) = runtime_paths::socket_discovery_file() {
             if socket_file.exists() {
                 if let Err(e) = fs::remove_file(&socket_file) {
                     warn!("Failed to remove socket path file: {}", e);
@@ -450,17 +819,122 @@ impl PlatformProbeTrait for PosixShellProbe {
             }
         }
 
+        if let Ok(instance_file) = runtime_paths::shell_instance_file() {
+            let _ = fs::remove_file(instance_file);
+        }
+        if let Ok(hints_file) = runtime_paths::sandbox_hints_file() {
+            let _ = fs::remove_file(hints_file);
+        }
+
         // Clear active sessions
         {
             let mut sessions = self.active_sessions.lock().await;
             sessions.clear();
         }
+        let (picked_up, missed_ends) = (PICKED_UP.load(Ordering::Relaxed), MISSED_ENDS.load(Ordering::Relaxed));
+        if picked_up + missed_ends > 0 {
+            info!("Picked up {} command(s) shells had running when the service started; {} end(s) of commands started before it weren't counted",
+                  picked_up, missed_ends);
+        }
 
         info!("POSIX shell probe stopped");
         Ok(())
     }
+}
+
+/// Whether the process on the other end of a socket is in our PID namespace, so the PIDs its
+/// shell reports mean the same processes here
+#[cfg(target_os = "linux")]
+fn shares_pid_namespace(peer_pid: Option<i32>) -> bool {
+    // The kernel reports 0 for peers whose PID we can't see
+    let Some(pid) = peer_pid.filter(|pid| *pid > 0) else {
+        return false;
+    };
+    match (fs::read_link(format!("/proc/{}/ns/pid", pid)), fs::read_link("/proc/self/ns/pid")) {
+        (Ok(theirs), Ok(ours)) => theirs == ours,
+        _ => false,
+    }
+}
+
+/// Whether the process on the other end of a socket is in our PID namespace; only Linux has more
+/// than one
+#[cfg(not(target_os = "linux"))]
+fn shares_pid_namespace(_peer_pid: Option<i32>) -> bool {
+    true
+}
+
+/// Runs captured shell lines through the probe, see `recording::replay`
+pub struct ShellReplay {
+    lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
+    active_sessions: Mutex<HashMap<String, ShellSession>>,
+    /// Unfinished messages of each captured connection
+    buffers: HashMap<u64, String>,
+}
+
+impl ShellReplay {
+    pub fn new(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) -> Self {
+        Self { lifecycle_sender, active_sessions: Mutex::new(HashMap::new()), buffers: HashMap::new() }
+    }
+
+    pub async fn feed(&mut self, connection: u64, peer_uid: Option<u32>, line: &str) {
+        let buffer = self.buffers.entry(connection).or_default();
+        PosixShellProbe::handle_line(buffer, line, peer_uid, false, &self.lifecycle_sender, &self.active_sessions).await;
+    }
+}
+
+impl PlatformProbeTrait for PosixShellProbe {
+    fn start(&self) -> ProbeFuture<'_> {
+        Box::pin(self.start_probe())
+    }
+
+    fn stop(&self) -> ProbeFuture<'_> {
+        Box::pin(self.stop_probe())
+    }
 
     fn get_capability(&self) -> PlatformCapability {
         PlatformCapability::ShellOnly
     }
-}
\ No newline at end of file
+
+    fn provided_data(&self) -> ProbeData {
+        ProbeData { working_directory: true, stalls: true, cycles: true, parent_app: false }
+    }
+}
+
+/// Writes the socket paths to ~/.viberot/.socket (one per line) for shell integration.
+/// The file is replaced atomically so hooks never read a partially written list.
+pub fn write_socket_discovery_file(socket_paths: &[PathBuf], tcp_port: Option<u16>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let socket_file = match runtime_paths::socket_discovery_file() {
+        Ok(socket_file) => socket_file,
+        Err(e) => {
+            warn!("{}, shell integration may not work", e);
+            return Ok(());
+        }
+    };
+    if let Some(viberot_dir) = socket_file.parent() {
+        fs::create_dir_all(viberot_dir)?;
+    }
+    let temp_file = socket_file.with_extension("tmp");
+
+    let mut contents: String = socket_paths.iter()
+        .map(|p| format!("{}\n", p.display()))
+        .collect();
+    if let Some(port) = tcp_port {
+        contents.push_str(&format!("tcp:127.0.0.1:{}\n", port));
+    }
+    fs::write(&temp_file, contents)?;
+    fs::rename(&temp_file, &socket_file)?;
+    info!("Wrote {} socket path(s) to: {}", socket_paths.len(), socket_file.display());
+
+    // New with each start, so the hooks replay the commands they have running
+    let instance_file = runtime_paths::shell_instance_file()?;
+    let started = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    fs::write(&temp_file, format!("{}-{:x}\n", std::process::id(), started.as_nanos()))?;
+    fs::rename(&temp_file, &instance_file)?;
+
+    if let Some(socket_path) = socket_paths.first() {
+        let hints_file = runtime_paths::sandbox_hints_file()?;
+        fs::write(&temp_file, sandbox::instructions(socket_path))?;
+        fs::rename(&temp_file, &hints_file)?;
+    }
+    Ok(())
+}
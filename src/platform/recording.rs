@@ -0,0 +1,163 @@
+// Probe captures, for reproducing probe bugs on machines that don't have them
+// `viberot-service --record <file>` writes what the probes receive, before they make events of it,
+// to a capture: JSON lines, the first a header, then one record per line with the milliseconds since
+// recording started (`t`). Records are:
+// - "shell": a line a shell sent to the shell probe's sockets, as it was read, with the connection
+//   it came on and the user on the other end
+// - "etw_running", "etw_start", "etw_exit": the fields the ETW probe read from a kernel process
+//   event and from the process itself (command line, working directory...), which are gone once the
+//   process has exited. The ETW records themselves need the schemas of the machine that made them,
+//   so ferrisetw's parsing isn't captured.
+// `viberot-service replay <file>` runs a capture through the same probe code, the ETW part on
+// Windows builds with the `etw` feature, and prints the lifecycle events it makes as JSON lines, so a
+// bug report's capture can be replayed and its output compared before and after a fix.
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+use crate::config::Config;
+use crate::platform::ProcessLifecycleEvent;
+
+/// Version of the capture format, raised when records change in ways older replays can't read
+const FORMAT: u32 = 1;
+
+/// One line of a capture
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// Milliseconds since recording started
+    #[serde(default)]
+    pub t: u64,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Record {
+    /// First line of every capture
+    Header { format: u32, version: String, os: String },
+    /// A line from a shell, newline included if it had one
+    Shell { connection: u64, peer_uid: Option<u32>, line: String },
+    /// A process running when the ETW probe started, for `[etw] parent_apps`
+    EtwRunning { pid: u32, parent: u32, image: String },
+    /// A process the ETW probe saw start
+    EtwStart(EtwStart),
+    /// A process the ETW probe saw exit
+    EtwExit { pid: u32, exit_code: Option<u32> },
+}
+
+/// What the ETW probe knows about a new process, read from the event and the process
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EtwStart {
+    pub pid: u32,
+    pub parent: Option<u32>,
+    pub image: Option<String>,
+    /// Event header timestamp, a FILETIME
+    pub timestamp: i64,
+    pub session_id: Option<u32>,
+    /// Command line as the process had it, None if it couldn't be read
+    #[serde(default, with = "super::base64_bytes")]
+    pub command_line: Option<Vec<u8>>,
+    pub working_directory: Option<String>,
+    pub environment: Option<std::collections::HashMap<String, String>>,
+}
+
+/// Writes a capture while the service runs
+pub struct Recorder {
+    file: Mutex<File>,
+    started: Instant,
+    next_connection: AtomicU64,
+}
+
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
+
+/// Starts writing what the probes receive to `path`, replacing what's there
+pub fn start(path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let file = File::create(path).map_err(|e| format!("Failed to create the capture {}: {}", path.display(), e))?;
+    let recorder = Recorder { file: Mutex::new(file), started: Instant::now(), next_connection: AtomicU64::new(1) };
+    recorder.record(Record::Header { format: FORMAT, version: env!("CARGO_PKG_VERSION").to_string(), os: std::env::consts::OS.to_string() });
+    if RECORDER.set(recorder).is_err() {
+        return Err("Already recording".into());
+    }
+    info!("Recording what the probes receive to {}", path.display());
+    Ok(())
+}
+
+/// The recorder, if `--record` was given
+pub fn recorder() -> Option<&'static Recorder> {
+    RECORDER.get()
+}
+
+impl Recorder {
+    pub fn record(&self, record: Record) {
+        let entry = Entry { t: self.started.elapsed().as_millis() as u64, record };
+        let line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                debug!("Failed to serialize a capture record: {}", e);
+                return;
+            }
+        };
+        // A line at a time, so a capture is whole up to a crash
+        if let Err(e) = writeln!(self.file.lock().unwrap(), "{}", line) {
+            debug!("Failed to write to the capture: {}", e);
+        }
+    }
+
+    /// Number for a new shell connection, telling its lines apart from other connections'
+    #[allow(dead_code)] // Only the shell probe has connections
+    pub fn connection(&self) -> u64 {
+        self.next_connection.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Runs a capture through the probes' code, sending the lifecycle events they make; `config` is
+/// what the ETW probe's settings are taken from
+#[allow(unused_variables)] // Builds without the shell or ETW probe replay nothing
+pub async fn replay(path: &Path, config: &Config, sender: broadcast::Sender<ProcessLifecycleEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let file = File::open(path).map_err(|e| format!("Failed to open the capture {}: {}", path.display(), e))?;
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), feature = "shell"))]
+    let mut shell = super::posix_shell::ShellReplay::new(sender.clone());
+    #[cfg(all(windows, feature = "etw"))]
+    let mut etw = super::windows_etw::EtwReplay::new(config);
+
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(&line).map_err(|e| format!("Line {} of the capture isn't a record: {}", number + 1, e))?;
+        match entry.record {
+            Record::Header { format, version, os } => {
+                if format > FORMAT {
+                    return Err(format!("The capture was made by viberot {} in a newer format ({}) than this one reads ({})", version, format, FORMAT).into());
+                }
+                debug!("Replaying a capture made by viberot {} on {}", version, os);
+            }
+            #[cfg(all(any(target_os = "linux", target_os = "macos"), feature = "shell"))]
+            Record::Shell { connection, peer_uid, line } => shell.feed(connection, peer_uid, &line).await,
+            #[cfg(not(all(any(target_os = "linux", target_os = "macos"), feature = "shell")))]
+            Record::Shell { .. } => return Err("Shell captures replay on Linux and macOS builds with the `shell` feature".into()),
+            #[cfg(all(windows, feature = "etw"))]
+            Record::EtwRunning { pid, parent, image } => etw.running(pid, parent, &image),
+            #[cfg(all(windows, feature = "etw"))]
+            Record::EtwStart(start) => etw.start(start, &sender),
+            #[cfg(all(windows, feature = "etw"))]
+            Record::EtwExit { pid, exit_code } => etw.exit(pid, exit_code, &sender),
+            #[cfg(not(all(windows, feature = "etw")))]
+            Record::EtwRunning { .. } | Record::EtwStart(_) | Record::EtwExit { .. } => {
+                return Err("ETW captures replay on Windows builds with the `etw` feature".into());
+            }
+        }
+        // Lets whoever receives the events keep up, rather than lag behind a long capture
+        tokio::task::yield_now().await;
+    }
+    Ok(())
+}
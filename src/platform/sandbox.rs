@@ -0,0 +1,46 @@
+// Setup instructions for shells in sandboxed terminals (Flatpak, Snap, toolbox/distrobox containers)
+// Shells running inside these usually can't see the host's $XDG_RUNTIME_DIR, so the default Unix
+// socket is unreachable. Only the shell can tell it's sandboxed, so the service writes the
+// instructions in its language to a file, one section per sandbox, and the hooks print the one
+// that applies. They fill in `{name}` with the Flatpak app id, snap name or container engine.
+
+use std::path::Path;
+
+use crate::i18n::{t, tf, Msg};
+
+const NAME: &str = "{name}";
+
+/// The instructions for each sandbox, as `[flatpak]`, `[snap]` and `[container]` sections
+pub fn instructions(socket_path: &Path) -> String {
+    let sections = [
+        ("flatpak", vec![
+            t(Msg::SandboxFlatpak).to_string(),
+            t(Msg::SandboxFlatpakGrant).to_string(),
+            format!("  flatpak override --user --filesystem={} {}", socket_path.display(), NAME),
+        ]),
+        ("snap", vec![
+            tf(Msg::SandboxSnap, &[&NAME]),
+            t(Msg::SandboxSnapConfined).to_string(),
+            "  [shell]".to_string(),
+            "  socket_paths = [\"$SNAP_USER_DATA/viberot-shell.sock\"]".to_string(),
+        ]),
+        ("container", vec![
+            tf(Msg::SandboxContainer, &[&NAME]),
+            t(Msg::SandboxContainerShared).to_string(),
+            "  [shell]".to_string(),
+            "  socket_paths = [\"/home/<you>/.viberot/viberot-shell.sock\"]".to_string(),
+        ]),
+    ];
+
+    let mut contents = String::new();
+    for (sandbox, mut lines) in sections {
+        lines.extend([t(Msg::SandboxTcpSuggest).to_string(), "  [shell]".to_string(), "  tcp_port = 47913".to_string()]);
+        contents.push_str(&format!("[{}]\n", sandbox));
+        // Multi-line messages stay in their section
+        for line in lines.iter().flat_map(|line| line.lines()) {
+            contents.push_str(line);
+            contents.push('\n');
+        }
+    }
+    contents
+}
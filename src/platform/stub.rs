@@ -1,49 +1,49 @@
-use tokio::sync::broadcast;
 use tracing::{error, info};
 
-use crate::platform::{PlatformProbeTrait, ProcessLifecycleEvent};
+use crate::platform::{PlatformCapability, PlatformProbeTrait, ProbeData, ProbeFuture};
 
-/// Stub implementation for unsupported platforms
-/// This allows the code to compile and provides clear error messages
-pub struct StubProbe {
-    _lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
+/// Stand-in used when no probe can run on this machine
+/// This allows the service to start and explain what's missing
+pub struct UnsupportedProbe {
+    /// Why each probe can't run
+    reasons: Vec<String>,
 }
 
-impl StubProbe {
-    pub fn new(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) -> Self {
-        Self {
-            _lifecycle_sender: lifecycle_sender,
-        }
+impl UnsupportedProbe {
+    pub fn new(reasons: Vec<String>) -> Self {
+        Self { reasons }
     }
 }
 
-impl PlatformProbeTrait for StubProbe {
-    async fn start(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        error!("Process monitoring is not yet implemented for this platform");
-        error!("VibeRot currently supports:");
-        error!("  - Windows (using ETW - Event Tracing for Windows)");
-        error!("Future platform support is planned for:");
-        error!("  - Linux (using ePBF)");
-        error!("  - macOS (technology to be decided)");
-        error!("  - bash/zsh (using shell hooks)");
-
-        #[cfg(target_os = "linux")]
-        info!("Running on Linux - support coming soon!");
-
-        #[cfg(target_os = "macos")]
-        info!("Running on macOS - support coming soon!");
-
-        info!("Learn more or contribute at: https://github.com/endernoke/viberot");
-        
-        Err("Platform not supported yet. Please use Windows or wait for cross-platform support.".into())
+impl PlatformProbeTrait for UnsupportedProbe {
+    fn start(&self) -> ProbeFuture<'_> {
+        Box::pin(async move {
+            error!("No process probe can run on this machine:");
+            for reason in &self.reasons {
+                error!("  - {}", reason);
+            }
+            error!("VibeRot currently supports:");
+            error!("  - Windows (using ETW - Event Tracing for Windows, `etw` feature)");
+            error!("  - Windows without administrator rights (polling WMI, `wmi` feature)");
+            error!("  - Linux and macOS (using bash/zsh hooks, `shell` feature)");
+            error!("  - Anywhere atuin records shell history (`atuin` feature)");
+            info!("Run `viberot-service probes` to see every probe and its status");
+            info!("Learn more or contribute at: https://github.com/endernoke/viberot");
+
+            Err("No supported process probe available".into())
+        })
     }
 
-    async fn stop(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    fn stop(&self) -> ProbeFuture<'_> {
         // Nothing to stop for stub implementation
-        Ok(())
+        Box::pin(async { Ok(()) })
     }
 
-    fn get_capability(&self) -> crate::platform::PlatformCapability {
-        crate::platform::PlatformCapability::Polling
+    fn get_capability(&self) -> PlatformCapability {
+        PlatformCapability::Polling
     }
-}
\ No newline at end of file
+
+    fn provided_data(&self) -> ProbeData {
+        ProbeData::default()
+    }
+}
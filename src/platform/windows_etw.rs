@@ -7,25 +7,218 @@ use ferrisetw::trace;
 use ferrisetw::trace::UserTrace;
 use tokio::sync::broadcast;
 use tracing::{info, error, debug};
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::os::windows::ffi::OsStringExt;
+use std::sync::{Arc, Mutex, Once};
 use std::thread;
 use std::time::Duration;
-use tasklist::get_proc_params;
+use tokio::sync::oneshot;
+use windows::Wdk::System::Threading::{NtQueryInformationProcess, ProcessBasicInformation};
+use windows::Win32::Foundation::{CloseHandle, BOOL, HANDLE, UNICODE_STRING};
+use windows::Win32::System::Diagnostics::Debug::ReadProcessMemory;
+use windows::Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+use windows::Win32::System::RemoteDesktop::ProcessIdToSessionId;
+use windows::Win32::System::Threading::{OpenProcess, PEB, PROCESS_BASIC_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_VM_READ, RTL_USER_PROCESS_PARAMETERS};
+use windows::Win32::System::Console::{SetConsoleCtrlHandler, CTRL_CLOSE_EVENT, CTRL_LOGOFF_EVENT, CTRL_SHUTDOWN_EVENT};
 
-use crate::platform::{PlatformProbeTrait, ProcessLifecycleEvent, ProcessEvent};
+use crate::config::Config;
+use crate::enrichment::PACKAGE_SCRIPT_ENV_VARS;
+use crate::platform::recording::{self, EtwStart, Record};
+use crate::platform::{PlatformProbeTrait, ProbeFuture, ProcessLifecycleEvent, ProcessEvent};
+
+/// Name of the ETW session, shared across runs so a leftover session can be stopped
+const TRACE_NAME: &str = "VibeRotServiceTrace";
+
+/// How long to wait for the ETW thread to report that the session is up
+const TRACE_START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// ETW refused to start the session because the service doesn't run as administrator
+#[derive(Debug)]
+pub struct AccessDenied(String);
+
+impl std::fmt::Display for AccessDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ETW trace session failed to start, it needs administrator rights: {}", self.0)
+    }
+}
+
+impl std::error::Error for AccessDenied {}
+
+fn is_access_denied(error: &str) -> bool {
+    error.contains("Access is denied") || error.contains("-2147024891")
+}
+
+/// Registers a console handler that stops the ETW session when Windows ends the process before the
+/// graceful shutdown is done, so the kernel session doesn't outlive us. Panics are left alone: a
+/// panicking task doesn't end the service, and the probe's `Drop` covers one that does
+fn install_exit_handler() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        unsafe extern "system" fn console_handler(ctrl_type: u32) -> BOOL {
+            // These go through the graceful shutdown as well, but Windows may end the process
+            // before it's done, so stop the session right away; stopping it again later is harmless.
+            // Ctrl+C leaves it to the graceful shutdown alone
+            if ctrl_type == CTRL_CLOSE_EVENT || ctrl_type == CTRL_LOGOFF_EVENT || ctrl_type == CTRL_SHUTDOWN_EVENT {
+                let _ = trace::stop_trace_by_name(TRACE_NAME);
+            }
+            BOOL(0) // Let the next handler run
+        }
+
+        if let Err(e) = unsafe { SetConsoleCtrlHandler(Some(console_handler), true) } {
+            debug!("Failed to install console control handler: {}", e);
+        }
+    });
+}
+
+/// Start of `RTL_USER_PROCESS_PARAMETERS` up to the current directory, which the windows crate
+/// leaves in its reserved fields
+#[repr(C)]
+struct ProcessParametersHead {
+    maximum_length: u32,
+    length: u32,
+    flags: u32,
+    debug_flags: u32,
+    console_handle: HANDLE,
+    console_flags: u32,
+    standard_input: HANDLE,
+    standard_output: HANDLE,
+    standard_error: HANDLE,
+    current_directory: UNICODE_STRING,
+}
+
+/// Start of `RTL_USER_PROCESS_PARAMETERS` up to the environment block
+#[repr(C)]
+struct ProcessParametersEnvironment {
+    head: ProcessParametersHead,
+    current_directory_handle: HANDLE,
+    dll_path: UNICODE_STRING,
+    image_path_name: UNICODE_STRING,
+    command_line: UNICODE_STRING,
+    environment: *const std::ffi::c_void,
+}
+
+/// Most of an environment block read while looking for package script variables
+const MAX_ENVIRONMENT_SIZE: usize = 64 * 1024;
+/// Environment blocks are read a page at a time, as the end of one may be the end of the mapping
+const PAGE_SIZE: usize = 4096;
+
+/// How deep `ProcessTree::app_above` looks, in case reused PIDs make a cycle
+const MAX_TREE_DEPTH: usize = 64;
+
+/// Image and parent of every running process, for finding the app a process was started from
+/// (`[etw] parent_apps`). A PID reused before its children exited can attribute them to the wrong
+/// parent, which is rare enough for tagging.
+#[derive(Default)]
+struct ProcessTree {
+    processes: HashMap<u32, TreeNode>,
+}
+
+struct TreeNode {
+    parent: u32,
+    /// Lowercase file name of the executable, e.g. "code.exe"
+    image: String,
+}
+
+impl ProcessTree {
+    /// The processes running now, so apps opened before the probe started are known
+    fn snapshot() -> Self {
+        let mut tree = Self::default();
+        let snapshot = match unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) } {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                debug!("Failed to list running processes: {}", e);
+                return tree;
+            }
+        };
+        let mut entry = PROCESSENTRY32W {
+            dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+        let mut more = unsafe { Process32FirstW(snapshot, &mut entry) }.is_ok();
+        while more {
+            let length = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
+            let image = String::from_utf16_lossy(&entry.szExeFile[..length]);
+            if let Some(recorder) = recording::recorder() {
+                recorder.record(Record::EtwRunning { pid: entry.th32ProcessID, parent: entry.th32ParentProcessID, image: image.clone() });
+            }
+            tree.started(entry.th32ProcessID, entry.th32ParentProcessID, &image);
+            more = unsafe { Process32NextW(snapshot, &mut entry) }.is_ok();
+        }
+        let _ = unsafe { CloseHandle(snapshot) };
+        tree
+    }
+
+    fn started(&mut self, pid: u32, parent: u32, image: &str) {
+        self.processes.insert(pid, TreeNode { parent, image: image_file_name(image) });
+    }
+
+    fn exited(&mut self, pid: u32) {
+        self.processes.remove(&pid);
+    }
+
+    /// The closest ancestor of a process that is one of `apps`
+    fn app_above(&self, pid: u32, apps: &[String]) -> Option<&str> {
+        let mut current = self.processes.get(&pid)?.parent;
+        for _ in 0..MAX_TREE_DEPTH {
+            let node = self.processes.get(&current)?;
+            if apps.contains(&node.image) {
+                return Some(&node.image);
+            }
+            if node.parent == current {
+                return None;
+            }
+            current = node.parent;
+        }
+        None
+    }
+}
+
+/// Lowercase file name of an executable path, which ETW gives as an NT path like
+/// `\Device\HarddiskVolume3\Windows\System32\cmd.exe`
+fn image_file_name(image: &str) -> String {
+    image.rsplit(['\\', '/']).next().unwrap_or(image).to_lowercase()
+}
+
+/// Unix time in seconds of an event header timestamp, a FILETIME (100ns intervals since 1601)
+fn unix_time_of(filetime: i64) -> Option<u64> {
+    const FILETIME_UNIX_EPOCH_SECS: i64 = 11_644_473_600;
+    u64::try_from(filetime / 10_000_000 - FILETIME_UNIX_EPOCH_SECS).ok()
+}
+
+/// What the event callback needs to know besides where to send events
+struct CallbackOptions {
+    /// Read the working directory of new processes (`[etw] working_directory`)
+    capture_working_directory: bool,
+    /// Read the package script variables of new processes (`[etw] package_scripts`)
+    capture_package_scripts: bool,
+    /// Lowercase image names of `[etw] parent_apps`
+    parent_apps: Vec<String>,
+    /// Only kept up to date when `parent_apps` is set
+    tree: Mutex<ProcessTree>,
+}
 
 /// Windows ETW-based process probe
 /// Uses Event Tracing for Windows to monitor process creation and termination events
 pub struct WindowsEtwProbe {
     lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
     trace_handle: Arc<Mutex<Option<UserTrace>>>,
+    /// Read the working directory of new processes (`[etw] working_directory`)
+    capture_working_directory: bool,
+    /// Read the package script variables of new processes (`[etw] package_scripts`)
+    capture_package_scripts: bool,
+    /// Apps whose descendants get a `parent_app` tag (`[etw] parent_apps`)
+    parent_apps: Vec<String>,
 }
 
 impl WindowsEtwProbe {
-    pub fn new(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) -> Self {
+    pub fn new(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>, config: &Config) -> Self {
         Self {
             lifecycle_sender,
             trace_handle: Arc::new(Mutex::new(None)),
+            capture_working_directory: config.etw.working_directory,
+            capture_package_scripts: config.etw.package_scripts,
+            parent_apps: config.etw.parent_apps.iter().map(|app| image_file_name(app)).collect(),
         }
     }
 
@@ -34,11 +227,12 @@ impl WindowsEtwProbe {
         record: &EventRecord,
         schema_locator: &SchemaLocator,
         sender: broadcast::Sender<ProcessLifecycleEvent>,
+        options: &CallbackOptions,
     ) {
         let event_id = record.event_id();
         match event_id {
-            1 => Self::handle_process_start(record, schema_locator, &sender),
-            2 => Self::handle_process_exit(record, schema_locator, &sender),
+            1 => Self::handle_process_start(record, schema_locator, &sender, options),
+            2 => Self::handle_process_exit(record, schema_locator, &sender, options),
             _ => { /* Ignore other events */ },
         }
     }
@@ -47,6 +241,7 @@ impl WindowsEtwProbe {
         record: &EventRecord,
         schema_locator: &SchemaLocator,
         sender: &broadcast::Sender<ProcessLifecycleEvent>,
+        options: &CallbackOptions,
     ) {
         match schema_locator.event_schema(record) {
             Ok(schema) => {
@@ -57,13 +252,35 @@ impl WindowsEtwProbe {
                 match pid_result {
                     Ok(pid) => {
                         debug!("Successfully parsed process start: PID={}", pid);
-                        // Attempt to get command line via tasklist as fallback
-                        let command_line = get_proc_params(pid).unwrap_or_else(|e| {
-                            debug!("Failed to get command line via tasklist for PID {}: {:?}", pid, e);
-                            String::from("<unknown>")
-                        }).replace('\0', ""); // Remove null chars
-                        let process_event = ProcessEvent::new(pid, command_line, crate::platform::ProbeSource::WindowsEtw);
-                        let lifecycle_event = ProcessLifecycleEvent::Started(process_event);
+                        let command_line = Self::command_line_of(pid).map_err(|e| {
+                            debug!("Failed to read the command line of PID {}: {}", pid, e);
+                        }).ok();
+                        let mut start = EtwStart {
+                            pid,
+                            parent: parser.try_parse::<u32>("ParentProcessID").ok(),
+                            image: parser.try_parse::<String>("ImageName").ok(),
+                            timestamp: record.raw_timestamp(),
+                            session_id: Self::session_id_of(pid),
+                            command_line,
+                            ..Default::default()
+                        };
+                        if options.capture_working_directory {
+                            match Self::working_directory_of(pid) {
+                                Ok(cwd) => start.working_directory = Some(cwd),
+                                Err(e) => debug!("Failed to read the working directory of PID {}: {}", pid, e),
+                            }
+                        }
+                        if options.capture_package_scripts {
+                            match Self::package_script_variables_of(pid) {
+                                Ok(variables) if !variables.is_empty() => start.environment = Some(variables),
+                                Ok(_) => {}
+                                Err(e) => debug!("Failed to read the environment of PID {}: {}", pid, e),
+                            }
+                        }
+                        if let Some(recorder) = recording::recorder() {
+                            recorder.record(Record::EtwStart(start.clone()));
+                        }
+                        let lifecycle_event = ProcessLifecycleEvent::Started(Self::start_event(start, options));
                         
                         if let Err(e) = sender.send(lifecycle_event) {
                             error!("Failed to send process start event: {}", e);
@@ -80,10 +297,154 @@ impl WindowsEtwProbe {
         }
     }
 
+    /// Event for a new process, from what was read about it
+    fn start_event(start: EtwStart, options: &CallbackOptions) -> ProcessEvent {
+        let pid = start.pid;
+        let command_line = start.command_line.unwrap_or_else(|| b"<unknown>".to_vec());
+        let mut process_event = ProcessEvent::from_raw(pid, command_line, crate::platform::ProbeSource::WindowsEtw);
+        // Events can sit in ETW's buffers for a while; the header says when it happened
+        if let Some(timestamp) = unix_time_of(start.timestamp) {
+            process_event.timestamp = timestamp;
+        }
+        if let Some(session_id) = start.session_id {
+            process_event = process_event.with_session_id(session_id.to_string());
+        }
+        if let Some(cwd) = start.working_directory {
+            process_event = process_event.with_working_directory(cwd);
+        }
+        if let Some(variables) = start.environment {
+            process_event = process_event.with_environment(variables);
+        }
+        // Wrappers can mangle the command line, but not the image or the app above
+        if let Some(image) = start.image {
+            process_event.tags.insert("image".to_string(), image_file_name(&image));
+            if !options.parent_apps.is_empty() {
+                let mut tree = options.tree.lock().unwrap();
+                tree.started(pid, start.parent.unwrap_or_default(), &image);
+                if let Some(app) = tree.app_above(pid, &options.parent_apps) {
+                    process_event.tags.insert("parent_app".to_string(), app.to_string());
+                }
+            }
+        }
+        process_event
+    }
+
+    /// Terminal Services session the process runs in
+    fn session_id_of(pid: u32) -> Option<u32> {
+        let mut session_id = 0u32;
+        unsafe { ProcessIdToSessionId(pid, &mut session_id) }.ok()?;
+        Some(session_id)
+    }
+
+    /// Command line from the process parameters in the process's PEB, as `OsStr` encoded bytes
+    /// (WTF-8) so UTF-16 that isn't valid Unicode survives; fails like `working_directory_of`
+    fn command_line_of(pid: u32) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+        Self::with_process(pid, |process| unsafe {
+            let parameters: RTL_USER_PROCESS_PARAMETERS = Self::read_parameters(process)?;
+            let command_line = Self::read_unicode_string(process, &parameters.CommandLine)?;
+            Ok(OsString::from_wide(&command_line).into_encoded_bytes())
+        })
+    }
+
+    /// Current directory from the process parameters in the process's PEB; fails for protected
+    /// processes and ones that exited already
+    fn working_directory_of(pid: u32) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        Self::with_process(pid, |process| unsafe {
+            let parameters: ProcessParametersHead = Self::read_parameters(process)?;
+            let path = String::from_utf16(&Self::read_unicode_string(process, &parameters.current_directory)?)?;
+            // Stored as `C:\dir\`; only drive roots keep their trailing separator
+            Ok(match path.strip_suffix('\\') {
+                Some(trimmed) if !trimmed.ends_with(':') => trimmed.to_string(),
+                _ => path,
+            })
+        })
+    }
+
+    /// The variables package managers set for scripts (`enrichment::PACKAGE_SCRIPT_ENV_VARS`), from
+    /// the environment block in the process's PEB; fails like `working_directory_of`
+    fn package_script_variables_of(pid: u32) -> Result<HashMap<String, String>, Box<dyn std::error::Error + Send + Sync>> {
+        Self::with_process(pid, |process| unsafe {
+            let parameters: ProcessParametersEnvironment = Self::read_parameters(process)?;
+            let block = Self::read_environment_block(process, parameters.environment as usize);
+            Ok(block.split(|&c| c == 0)
+                .take_while(|entry| !entry.is_empty())
+                .filter_map(|entry| {
+                    let entry = String::from_utf16(entry).ok()?;
+                    let (name, value) = entry.split_once('=')?;
+                    PACKAGE_SCRIPT_ENV_VARS.contains(&name).then(|| (name.to_string(), value.to_string()))
+                })
+                .collect())
+        })
+    }
+
+    /// The environment block at `address`, up to its terminating empty entry or as much of it as
+    /// could be read
+    unsafe fn read_environment_block(process: HANDLE, address: usize) -> Vec<u16> {
+        let mut block: Vec<u16> = Vec::new();
+        let mut next = address;
+        while block.len() * 2 < MAX_ENVIRONMENT_SIZE && !block.windows(2).any(|pair| pair == [0, 0]) {
+            let mut chunk = vec![0u16; (PAGE_SIZE - next % PAGE_SIZE) / 2];
+            if ReadProcessMemory(process, next as *const _, chunk.as_mut_ptr().cast(), chunk.len() * 2, None).is_err() {
+                break;
+            }
+            block.extend_from_slice(&chunk);
+            next += chunk.len() * 2;
+        }
+        block
+    }
+
+    /// Opens the process for reading its memory while `read` runs
+    fn with_process<T>(
+        pid: u32,
+        read: impl FnOnce(HANDLE) -> Result<T, Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+        let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_VM_READ, false, pid) }?;
+        let result = read(process);
+        unsafe {
+            let _ = CloseHandle(process);
+        }
+        result
+    }
+
+    /// Reads a `T` from the process's memory
+    unsafe fn read<T>(process: HANDLE, address: *const std::ffi::c_void) -> windows::core::Result<T> {
+        let mut value = std::mem::MaybeUninit::<T>::uninit();
+        ReadProcessMemory(process, address, value.as_mut_ptr().cast(), std::mem::size_of::<T>(), None)?;
+        Ok(value.assume_init())
+    }
+
+    /// The process parameters the process's PEB points to, or the start of them
+    unsafe fn read_parameters<T>(process: HANDLE) -> windows::core::Result<T> {
+        let mut info = std::mem::zeroed::<PROCESS_BASIC_INFORMATION>();
+        NtQueryInformationProcess(
+            process,
+            ProcessBasicInformation,
+            std::ptr::addr_of_mut!(info).cast(),
+            std::mem::size_of::<PROCESS_BASIC_INFORMATION>() as u32,
+            std::ptr::null_mut(),
+        ).ok()?;
+        let peb: PEB = Self::read(process, info.PebBaseAddress.cast())?;
+        Self::read(process, peb.ProcessParameters.cast())
+    }
+
+    /// Contents of a string in the process's memory, without a terminating null
+    unsafe fn read_unicode_string(process: HANDLE, string: &UNICODE_STRING) -> windows::core::Result<Vec<u16>> {
+        let mut buffer = vec![0u16; usize::from(string.Length) / 2];
+        ReadProcessMemory(
+            process,
+            string.Buffer.0.cast(),
+            buffer.as_mut_ptr().cast(),
+            usize::from(string.Length),
+            None,
+        )?;
+        Ok(buffer)
+    }
+
     fn handle_process_exit(
         record: &EventRecord,
         schema_locator: &SchemaLocator,
         sender: &broadcast::Sender<ProcessLifecycleEvent>,
+        options: &CallbackOptions,
     ) {
         match schema_locator.event_schema(record) {
             Ok(schema) => {
@@ -96,9 +457,12 @@ impl WindowsEtwProbe {
                 
                 match pid_result {
                     Ok(pid) => {
-                        let _exit_code = exit_code_result.ok();
+                        let exit_code = exit_code_result.ok();
+                        if let Some(recorder) = recording::recorder() {
+                            recorder.record(Record::EtwExit { pid, exit_code });
+                        }
                         // debug!("Successfully parsed process exit: PID={}, ExitCode={:?}", pid, exit_code);
-                        let lifecycle_event = ProcessLifecycleEvent::Ended { pid };
+                        let lifecycle_event = Self::exit_event(pid, exit_code, options);
                         
                         if let Err(e) = sender.send(lifecycle_event) {
                             debug!("Failed to send process exit event: {}", e);
@@ -114,20 +478,70 @@ impl WindowsEtwProbe {
             }
         }
     }
+
+    /// Lifecycle event for a process exiting
+    fn exit_event(pid: u32, exit_code: Option<u32>, options: &CallbackOptions) -> ProcessLifecycleEvent {
+        if !options.parent_apps.is_empty() {
+            options.tree.lock().unwrap().exited(pid);
+        }
+        // NTSTATUS codes like 0xC0000005 come out negative, as they do in shells
+        ProcessLifecycleEvent::Ended { pid, exit_code: exit_code.map(|code| code as i32) }
+    }
 }
 
-impl PlatformProbeTrait for WindowsEtwProbe {
-    async fn start(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// Runs captured ETW records through the probe, see `recording::replay`; the apps above processes
+/// are those of this machine's `[etw] parent_apps`
+pub struct EtwReplay {
+    options: CallbackOptions,
+}
+
+impl EtwReplay {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            options: CallbackOptions {
+                capture_working_directory: true,
+                capture_package_scripts: true,
+                parent_apps: config.etw.parent_apps.iter().map(|app| image_file_name(app)).collect(),
+                tree: Mutex::new(ProcessTree::default()),
+            },
+        }
+    }
+
+    pub fn running(&mut self, pid: u32, parent: u32, image: &str) {
+        self.options.tree.lock().unwrap().started(pid, parent, image);
+    }
+
+    pub fn start(&mut self, start: EtwStart, sender: &broadcast::Sender<ProcessLifecycleEvent>) {
+        let _ = sender.send(ProcessLifecycleEvent::Started(WindowsEtwProbe::start_event(start, &self.options)));
+    }
+
+    pub fn exit(&mut self, pid: u32, exit_code: Option<u32>, sender: &broadcast::Sender<ProcessLifecycleEvent>) {
+        let _ = sender.send(WindowsEtwProbe::exit_event(pid, exit_code, &self.options));
+    }
+}
+
+impl WindowsEtwProbe {
+    async fn start_probe(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         info!("Starting Windows ETW probe for process monitoring");
 
         let sender = self.lifecycle_sender.clone();
+        let options = CallbackOptions {
+            capture_working_directory: self.capture_working_directory,
+            capture_package_scripts: self.capture_package_scripts,
+            parent_apps: self.parent_apps.clone(),
+            tree: Mutex::new(if self.parent_apps.is_empty() { ProcessTree::default() } else { ProcessTree::snapshot() }),
+        };
+        if !self.parent_apps.is_empty() {
+            info!("Tagging processes started from {}", self.parent_apps.join(", "));
+        }
         let trace_handle = Arc::clone(&self.trace_handle);
+        let (ready_tx, ready_rx) = oneshot::channel::<Result<(), String>>();
 
         // Spawn the ETW trace in a separate thread since it's blocking
         let _join_handle = thread::spawn(move || {
             // Create callback closure that captures the sender
             let callback = move |record: &EventRecord, schema_locator: &SchemaLocator| {
-                Self::process_event_callback(record, schema_locator, sender.clone());
+                Self::process_event_callback(record, schema_locator, sender.clone(), &options);
             };
             let filter = EventFilter::ByEventIds(vec![1, 2]);
 
@@ -140,11 +554,11 @@ impl PlatformProbeTrait for WindowsEtwProbe {
             
             // Stop any existing trace session with the same name to avoid conflicts.
             // This is useful if the application crashed previously and didn't clean up.
-            let _ = trace::stop_trace_by_name("VibeRotServiceTrace");
+            let _ = trace::stop_trace_by_name(TRACE_NAME);
 
             // Start the trace session
             let trace = match UserTrace::new()
-                .named(String::from("VibeRotServiceTrace"))
+                .named(String::from(TRACE_NAME))
                 .enable(process_provider)
                 .start_and_process()
             {
@@ -153,14 +567,9 @@ impl PlatformProbeTrait for WindowsEtwProbe {
                     trace
                 },
                 Err(e) => {
-                    error!("Failed to start ETW trace session: {:?}", e);
-                    
-                    // Check if it's an access denied error
-                    let error_msg = format!("{:?}", e);
-                    if error_msg.contains("Access is denied") || error_msg.contains("-2147024891") {
-                        error!("ETW requires administrator privileges. Please run as administrator for optimal performance.");
-                        error!("Alternatively, consider using the WMI fallback implementation.");
-                    }
+                    // Reported by start_probe, which may fall back to WMI
+                    debug!("Failed to start ETW trace session: {:?}", e);
+                    let _ = ready_tx.send(Err(format!("{:?}", e)));
                     return;
                 }
             };
@@ -170,27 +579,32 @@ impl PlatformProbeTrait for WindowsEtwProbe {
                 let mut handle = trace_handle.lock().unwrap();
                 *handle = Some(trace);
             }
+            let _ = ready_tx.send(Ok(()));
 
             // The trace will run until stopped
             // Note: start_and_process() handles the trace processing in a separate thread
             // so this thread will continue and eventually exit, but the trace will keep running
         });
 
-        // Give the ETW trace a moment to initialize
-        tokio::time::sleep(Duration::from_millis(500)).await;
-        
-        // Check if ETW initialization was successful
-        {
-            let handle = self.trace_handle.lock().unwrap();
-            if handle.is_none() {
-                return Err("ETW trace session failed to start. Run as administrator or use WMI fallback.".into());
+        // Wait for the ETW thread to report whether the session started
+        match tokio::time::timeout(TRACE_START_TIMEOUT, ready_rx).await {
+            Ok(Ok(Ok(()))) => {
+                install_exit_handler();
+                Ok(())
+            }
+            // `platform::start_probe` polls WMI instead
+            Ok(Ok(Err(e))) if is_access_denied(&e) => Err(Box::new(AccessDenied(e))),
+            Ok(Ok(Err(e))) => Err(format!("ETW trace session failed to start: {}", e).into()),
+            Ok(Err(_)) => Err("ETW trace thread exited before the session started".into()),
+            Err(_) => {
+                // The thread may still finish later; make sure a late session doesn't leak
+                let _ = trace::stop_trace_by_name(TRACE_NAME);
+                Err(format!("ETW trace session did not start within {:?}", TRACE_START_TIMEOUT).into())
             }
         }
-        
-        Ok(())
     }
 
-    async fn stop(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    async fn stop_probe(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         info!("Stopping Windows ETW probe");
 
         let mut handle = self.trace_handle.lock().unwrap();
@@ -201,8 +615,41 @@ impl PlatformProbeTrait for WindowsEtwProbe {
 
         Ok(())
     }
+}
+
+impl Drop for WindowsEtwProbe {
+    fn drop(&mut self) {
+        // Covers early returns and errors that skip stop()
+        let handle = match self.trace_handle.lock() {
+            Ok(mut handle) => handle.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        if let Some(trace) = handle {
+            let _ = trace.stop();
+            debug!("ETW trace session stopped on drop");
+        }
+    }
+}
+
+impl PlatformProbeTrait for WindowsEtwProbe {
+    fn start(&self) -> ProbeFuture<'_> {
+        Box::pin(self.start_probe())
+    }
+
+    fn stop(&self) -> ProbeFuture<'_> {
+        Box::pin(self.stop_probe())
+    }
 
     fn get_capability(&self) -> crate::platform::PlatformCapability {
         crate::platform::PlatformCapability::SystemWide
     }
-}
\ No newline at end of file
+
+    fn provided_data(&self) -> crate::platform::ProbeData {
+        crate::platform::ProbeData {
+            working_directory: self.capture_working_directory,
+            stalls: false,
+            cycles: false,
+            parent_app: !self.parent_apps.is_empty(),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+// WMI process probe
+// ETW needs the service to run as administrator. Without that, processes are found by polling
+// WMI's Win32_Process class every second instead: a process that appeared since the last poll
+// started, one that's gone ended. Processes living shorter than a poll are missed, exit codes
+// aren't known, and the command lines of other users' processes can't be read, so their image
+// stands in. Each poll's starts and ends are sent as one batch, timed by the processes' creation
+// dates rather than by when the poll found them.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot};
+use tracing::{debug, info, warn};
+use windows::core::{w, BSTR, PCWSTR};
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CoSetProxyBlanket, CoUninitialize, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED, EOAC_NONE,
+    RPC_C_AUTHN_LEVEL_CALL, RPC_C_IMP_LEVEL_IMPERSONATE,
+};
+use windows::Win32::System::Rpc::{RPC_C_AUTHN_WINNT, RPC_C_AUTHZ_NONE};
+use windows::Win32::System::Variant::{VariantClear, VARIANT, VT_BSTR, VT_I4, VT_UI4};
+use windows::Win32::System::Wmi::{
+    IWbemClassObject, IWbemLocator, IWbemServices, WbemLocator, WBEM_FLAG_FORWARD_ONLY, WBEM_FLAG_RETURN_IMMEDIATELY,
+    WBEM_GENERIC_FLAG_TYPE, WBEM_INFINITE,
+};
+
+use crate::config::Config;
+use crate::platform::{EventSink, PlatformCapability, PlatformProbeTrait, ProbeData, ProbeFuture, ProbeSource, ProcessEvent, ProcessLifecycleEvent};
+
+/// How often the probe looks for new and ended processes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long to wait for the polling thread to connect to WMI
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const QUERY: &str = "SELECT ProcessId, Name, CommandLine, SessionId, CreationDate FROM Win32_Process";
+
+/// Windows process probe polling WMI, for when ETW can't run
+pub struct WindowsWmiProbe {
+    lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
+    stopped: Arc<AtomicBool>,
+    thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+}
+
+impl WindowsWmiProbe {
+    pub fn new(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>, _config: &Config) -> Self {
+        Self {
+            lifecycle_sender,
+            stopped: Arc::new(AtomicBool::new(false)),
+            thread: Mutex::new(None),
+        }
+    }
+}
+
+impl PlatformProbeTrait for WindowsWmiProbe {
+    fn start(&self) -> ProbeFuture<'_> {
+        Box::pin(async move {
+            info!("Starting the WMI probe, polling for processes every {:?}", POLL_INTERVAL);
+            let (ready_tx, ready_rx) = oneshot::channel();
+            let lifecycle_sender = self.lifecycle_sender.clone();
+            let stopped = Arc::clone(&self.stopped);
+            // COM objects stay on the thread that created them
+            let thread = std::thread::Builder::new()
+                .name("wmi-probe".to_string())
+                .spawn(move || run(lifecycle_sender, &stopped, ready_tx))?;
+            *self.thread.lock().unwrap() = Some(thread);
+
+            match tokio::time::timeout(CONNECT_TIMEOUT, ready_rx).await {
+                Ok(Ok(Ok(()))) => Ok(()),
+                Ok(Ok(Err(e))) => Err(format!("Failed to query WMI for processes: {}", e).into()),
+                Ok(Err(_)) => Err("WMI probe thread exited before it connected".into()),
+                Err(_) => {
+                    self.stopped.store(true, Ordering::Relaxed);
+                    Err(format!("WMI didn't answer within {:?}", CONNECT_TIMEOUT).into())
+                }
+            }
+        })
+    }
+
+    fn stop(&self) -> ProbeFuture<'_> {
+        Box::pin(async move {
+            self.stopped.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.lock().unwrap().take() {
+                let _ = thread.join();
+            }
+            Ok(())
+        })
+    }
+
+    fn get_capability(&self) -> PlatformCapability {
+        PlatformCapability::Polling
+    }
+
+    fn provided_data(&self) -> ProbeData {
+        // Win32_Process has neither the working directory nor the parent's image
+        ProbeData::default()
+    }
+}
+
+/// Connects to WMI and polls until stopped, on a thread of its own
+fn run(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>, stopped: &AtomicBool, ready: oneshot::Sender<Result<(), String>>) {
+    // SAFETY: initializes COM for this thread only, uninitialized below once the poller is gone
+    if let Err(e) = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) } {
+        let _ = ready.send(Err(e.to_string()));
+        return;
+    }
+    match Poller::connect(lifecycle_sender) {
+        Ok(poller) => {
+            let _ = ready.send(Ok(()));
+            poller.run(stopped);
+        }
+        Err(e) => {
+            let _ = ready.send(Err(e.to_string()));
+        }
+    }
+    // SAFETY: matches the CoInitializeEx above; the poller's COM objects were dropped with it
+    unsafe { CoUninitialize() };
+}
+
+/// A process as WMI lists it
+struct Process {
+    name: String,
+    command_line: Option<String>,
+    session_id: Option<u32>,
+    /// CIM datetime it was created at, which also tells a reused PID apart
+    creation_date: String,
+}
+
+impl Process {
+    fn to_event(&self, pid: u32) -> ProcessEvent {
+        let command = self.command_line.clone().unwrap_or_else(|| self.name.clone());
+        let mut event = ProcessEvent::new(pid, command, ProbeSource::WindowsWmi);
+        if let Some(timestamp) = unix_time_of(&self.creation_date) {
+            event.timestamp = timestamp;
+        }
+        if let Some(session_id) = self.session_id {
+            event = event.with_session_id(session_id.to_string());
+        }
+        event.tags.insert("image".to_string(), self.name.to_lowercase());
+        event
+    }
+}
+
+struct Poller {
+    services: IWbemServices,
+    /// Processes seen in the last poll, with their creation dates
+    known: HashMap<u32, String>,
+    lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>,
+}
+
+impl Poller {
+    /// Connects to the local `ROOT\CIMV2` namespace and notes the processes already running
+    fn connect(lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) -> windows::core::Result<Self> {
+        // SAFETY: COM is initialized on this thread; the proxy is set up before any call on it
+        let services = unsafe {
+            let locator: IWbemLocator = CoCreateInstance(&WbemLocator, None, CLSCTX_INPROC_SERVER)?;
+            let services = locator.ConnectServer(&BSTR::from("ROOT\\CIMV2"), &BSTR::new(), &BSTR::new(), &BSTR::new(), 0, &BSTR::new(), None)?;
+            CoSetProxyBlanket(&services, RPC_C_AUTHN_WINNT, RPC_C_AUTHZ_NONE, PCWSTR::null(), RPC_C_AUTHN_LEVEL_CALL,
+                              RPC_C_IMP_LEVEL_IMPERSONATE, None, EOAC_NONE)?;
+            services
+        };
+        let mut poller = Self { services, known: HashMap::new(), lifecycle_sender };
+        // Only processes started from now on
+        poller.known = poller.processes()?.into_iter().map(|(pid, process)| (pid, process.creation_date)).collect();
+        info!("Polling WMI for processes, {} running", poller.known.len());
+        Ok(poller)
+    }
+
+    fn run(mut self, stopped: &AtomicBool) {
+        let mut failing = false;
+        while !stopped.load(Ordering::Relaxed) {
+            std::thread::sleep(POLL_INTERVAL);
+            match self.poll() {
+                Ok(()) => failing = false,
+                // Only log the first failure in a row
+                Err(e) if !failing => {
+                    warn!("Failed to query WMI for processes: {}", e);
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    fn poll(&mut self) -> windows::core::Result<()> {
+        let processes = self.processes()?;
+        let mut events = Vec::new();
+        // Ends first, so a reused PID ends before its new process starts
+        self.known.retain(|pid, creation_date| {
+            let running = processes.get(pid).is_some_and(|process| process.creation_date == *creation_date);
+            if !running {
+                events.push(ProcessLifecycleEvent::Ended { pid: *pid, exit_code: None });
+            }
+            running
+        });
+        for (pid, process) in processes {
+            if self.known.contains_key(&pid) {
+                continue;
+            }
+            debug!("WMI found process {}: {}", pid, process.name);
+            events.push(ProcessLifecycleEvent::Started(process.to_event(pid)));
+            self.known.insert(pid, process.creation_date);
+        }
+        let _ = self.lifecycle_sender.send_batch(events);
+        Ok(())
+    }
+
+    /// The processes running now, by PID
+    fn processes(&self) -> windows::core::Result<HashMap<u32, Process>> {
+        let flags = WBEM_GENERIC_FLAG_TYPE(WBEM_FLAG_FORWARD_ONLY.0 | WBEM_FLAG_RETURN_IMMEDIATELY.0);
+        // SAFETY: the enumerator and the objects it returns are used on this thread only
+        let enumerator = unsafe { self.services.ExecQuery(&BSTR::from("WQL"), &BSTR::from(QUERY), flags, None)? };
+        let mut processes = HashMap::new();
+        loop {
+            let mut objects = [None];
+            let mut returned = 0;
+            // SAFETY: `objects` has room for the one object asked for
+            unsafe { enumerator.Next(WBEM_INFINITE, &mut objects, &mut returned) }.ok()?;
+            let Some(object) = objects[0].take().filter(|_| returned == 1) else {
+                break;
+            };
+            // Gone between listing and reading, or the System Idle Process
+            let (Some(pid), Some(creation_date)) = (u32_property(&object, w!("ProcessId")), string_property(&object, w!("CreationDate"))) else {
+                continue;
+            };
+            processes.insert(pid, Process {
+                name: string_property(&object, w!("Name")).unwrap_or_default(),
+                command_line: string_property(&object, w!("CommandLine")),
+                session_id: u32_property(&object, w!("SessionId")),
+                creation_date,
+            });
+        }
+        Ok(processes)
+    }
+}
+
+/// A property's value, which the caller clears
+fn property(object: &IWbemClassObject, name: PCWSTR) -> Option<VARIANT> {
+    let mut value = VARIANT::default();
+    // SAFETY: `value` is a valid VARIANT for Get to fill in
+    unsafe { object.Get(name, 0, &mut value, None, None) }.ok()?;
+    Some(value)
+}
+
+fn string_property(object: &IWbemClassObject, name: PCWSTR) -> Option<String> {
+    let mut value = property(object, name)?;
+    // SAFETY: the union is read as the type `vt` says it holds, then cleared
+    unsafe {
+        let inner = &value.Anonymous.Anonymous;
+        let text = (inner.vt == VT_BSTR).then(|| inner.Anonymous.bstrVal.to_string());
+        let _ = VariantClear(&mut value);
+        text
+    }
+}
+
+fn u32_property(object: &IWbemClassObject, name: PCWSTR) -> Option<u32> {
+    let mut value = property(object, name)?;
+    // SAFETY: the union is read as the type `vt` says it holds, then cleared
+    unsafe {
+        let inner = &value.Anonymous.Anonymous;
+        // WMI hands out uint32 properties as VT_I4
+        let number = match inner.vt {
+            VT_I4 => Some(inner.Anonymous.lVal as u32),
+            VT_UI4 => Some(inner.Anonymous.ulVal),
+            _ => None,
+        };
+        let _ = VariantClear(&mut value);
+        number
+    }
+}
+
+/// Unix time in seconds of a CIM datetime, like "20250314093015.123456+060": local time, then its
+/// offset from UTC in minutes
+fn unix_time_of(datetime: &str) -> Option<u64> {
+    let field = |start: usize, end: usize| datetime.get(start..end)?.parse::<i64>().ok();
+    let (year, month, day) = (field(0, 4)?, field(4, 6)?, field(6, 8)?);
+    let (hour, minute, second) = (field(8, 10)?, field(10, 12)?, field(12, 14)?);
+    let offset = field(21, datetime.len())?;
+    // Days since 1970-01-01 (Howard Hinnant's days_from_civil)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    u64::try_from(days * 86_400 + hour * 3_600 + minute * 60 + second - offset * 60).ok()
+}
@@ -0,0 +1,118 @@
+// Pre-flight checks of action programs
+// Before an action's program is spawned, the file it resolved to is looked at: that it exists, is
+// a file, and can be run (the execute bit on Unix, a runnable extension on Windows). When it
+// can't, the error says why and how the configured path got there, `${VIBEROT_ACTIONS}/...` to
+// the expanded path or a bare name to where it was looked for on the PATH, instead of the OS's
+// "No such file or directory" on its own.
+
+use std::path::{Path, PathBuf};
+
+/// Checks that `resolved`, what the configured `path` resolved to, can be run, and returns the
+/// file that will be: bare names are looked up on the PATH
+pub fn check(path: &str, resolved: &Path) -> Result<PathBuf, String> {
+    let mut chain = vec![format!("`{}`", path)];
+    if resolved.as_os_str() != path {
+        chain.push(resolved.display().to_string());
+    }
+    // A path used as configured needs no explaining
+    let explain = |problem: String, chain: &[String]| match chain.len() {
+        1 => problem,
+        _ => format!("{} ({})", problem, chain.join(" → ")),
+    };
+
+    let program = if is_bare_name(resolved) {
+        match find_on_path(resolved) {
+            Some(found) => {
+                chain.push(format!("found on the PATH as {}", found.display()));
+                found
+            }
+            None => {
+                let searched = std::env::var_os("PATH").map_or(0, |paths| std::env::split_paths(&paths).count());
+                chain.push(format!("searched {} PATH directories", searched));
+                return Err(explain(format!("`{}` isn't on the PATH", resolved.display()), &chain));
+            }
+        }
+    } else {
+        resolved.to_path_buf()
+    };
+
+    let metadata = match std::fs::metadata(&program) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let problem = match unset_variable(&program) {
+                Some(variable) => format!("it doesn't exist, as ${{{}}} isn't set", variable),
+                None => "it doesn't exist".to_string(),
+            };
+            return Err(explain(problem, &chain));
+        }
+        Err(e) => return Err(explain(format!("it can't be read: {}", e), &chain)),
+    };
+    if metadata.is_dir() {
+        return Err(explain("it's a directory, not a program".to_string(), &chain));
+    }
+    if let Some(problem) = not_runnable(&program, &metadata) {
+        return Err(explain(problem, &chain));
+    }
+    Ok(program)
+}
+
+/// A program name without any directory, which the OS looks up on the PATH
+fn is_bare_name(path: &Path) -> bool {
+    !path.is_absolute() && path.components().count() == 1
+}
+
+/// Where a bare program name is found on the PATH
+pub fn find_on_path(name: &Path) -> Option<PathBuf> {
+    let name = name.to_string_lossy();
+    let extensions: Vec<String> = if cfg!(windows) {
+        std::iter::once(String::new()).chain(runnable_extensions()).collect()
+    } else {
+        vec![String::new()]
+    };
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .flat_map(|dir| {
+            let name = &name;
+            extensions.iter().map(move |extension| dir.join(format!("{}{}", name, extension)))
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+/// The first `${VAR}` left in a path, which the expansion leaves alone when the variable isn't set
+fn unset_variable(path: &Path) -> Option<String> {
+    let path = path.to_string_lossy();
+    let start = path.find("${")? + 2;
+    let end = path[start..].find('}')? + start;
+    Some(path[start..end].to_string())
+}
+
+/// Extensions Windows runs, from `PATHEXT`, lowercase with the dot
+fn runnable_extensions() -> Vec<String> {
+    std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string())
+        .split(';')
+        .filter(|extension| !extension.is_empty())
+        .map(|extension| extension.to_lowercase())
+        .collect()
+}
+
+#[cfg(unix)]
+fn not_runnable(_program: &Path, metadata: &std::fs::Metadata) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+    (metadata.permissions().mode() & 0o111 == 0)
+        .then(|| "it isn't executable; `chmod +x` it, or run it through its interpreter".to_string())
+}
+
+#[cfg(windows)]
+fn not_runnable(program: &Path, _metadata: &std::fs::Metadata) -> Option<String> {
+    let extension = program.extension().map(|extension| format!(".{}", extension.to_string_lossy().to_lowercase()));
+    let runnable = runnable_extensions();
+    match extension {
+        Some(extension) if runnable.contains(&extension) => None,
+        _ => Some(format!("Windows only runs {} files directly; run it through its interpreter, e.g. `python`",
+                          runnable.join(", "))),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn not_runnable(_program: &Path, _metadata: &std::fs::Metadata) -> Option<String> {
+    None
+}
@@ -0,0 +1,406 @@
+// Commands from OSC 133 prompt marks (Linux and macOS)
+// Shells set up by WezTerm, kitty, Windows Terminal, iTerm2 or VS Code, and fish 4 on its own,
+// mark their prompts in their output: OSC 133 `A` where the prompt starts, `B` where the command
+// line does, `C` once the command runs and `D;<exit code>` when it's done. `viberot-service pty`
+// runs the shell on a pseudo-terminal, passes everything through untouched, and reports each
+// command between `C` and `D` to the service over the shell hook socket, where it's an `Osc133`
+// event. No rc-file hooks or bash-preexec involved.
+// The command line comes from the mark when the shell puts it there (`C;cmdline_url=` from fish,
+// `C;cmdline=` from kitty, `633;E` from VS Code), otherwise from what the terminal echoed between
+// `B` and `C`.
+// Detection is automatic: a shell without marks is only passed through, and reporting starts with
+// the first mark. With the hooks set up too, the service merges both reports of a command (see
+// `dedup`).
+
+use base64::{engine::general_purpose, Engine as _};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::time::Duration;
+
+use crate::terminal_bell;
+use crate::wrap;
+
+/// How often the terminal's size is passed on to the shell's
+const RESIZE_INTERVAL: Duration = Duration::from_millis(250);
+/// Longest OSC sequence read; longer ones aren't marks and are skipped
+const MAX_SEQUENCE: usize = 64 * 1024;
+/// Most echoed command line kept between `B` and `C`
+const MAX_ECHO: usize = 16 * 1024;
+
+/// What the marks in the shell's output said
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mark {
+    /// A prompt started (`A`)
+    Prompt,
+    /// The command line was entered and runs (`C`), with its text if the shell said or echoed it
+    Executed(Option<String>),
+    /// The command finished (`D`), with its exit code if the shell told
+    Finished(Option<i32>),
+    /// The shell's working directory (OSC 7, or iTerm2's `1337;CurrentDir=`)
+    Directory(String),
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Text,
+    Escape,
+    Osc,
+    /// ESC inside an OSC sequence, ending it if `\` follows
+    OscEscape,
+}
+
+/// Finds marks in output arriving in chunks of any size
+#[derive(Default)]
+pub struct MarkScanner {
+    state: State,
+    /// The OSC sequence being read, without `ESC ]`
+    sequence: Vec<u8>,
+    /// Output since `B`, while the command line is typed
+    echo: Option<Vec<u8>>,
+    /// The command line VS Code's `633;E` sent ahead of `C`
+    command_line: Option<String>,
+}
+
+impl MarkScanner {
+    pub fn scan(&mut self, bytes: &[u8]) -> Vec<Mark> {
+        let mut marks = Vec::new();
+        for &byte in bytes {
+            match self.state {
+                State::Text if byte == 0x1b => self.state = State::Escape,
+                State::Text => self.echo(&[byte]),
+                State::Escape if byte == b']' => {
+                    self.sequence.clear();
+                    self.state = State::Osc;
+                }
+                State::Escape => {
+                    self.echo(&[0x1b, byte]);
+                    self.state = State::Text;
+                }
+                State::Osc | State::OscEscape if byte == 0x07 => marks.extend(self.finish()),
+                State::Osc if byte == 0x1b => self.state = State::OscEscape,
+                State::Osc => {
+                    if self.sequence.len() < MAX_SEQUENCE {
+                        self.sequence.push(byte);
+                    }
+                }
+                // ST ends the sequence, and anything else after ESC aborts it
+                State::OscEscape => marks.extend(self.finish()),
+            }
+        }
+        marks
+    }
+
+    fn echo(&mut self, bytes: &[u8]) {
+        if let Some(echo) = self.echo.as_mut().filter(|echo| echo.len() < MAX_ECHO) {
+            echo.extend_from_slice(bytes);
+        }
+    }
+
+    fn finish(&mut self) -> Option<Mark> {
+        self.state = State::Text;
+        let sequence = String::from_utf8_lossy(&self.sequence).into_owned();
+        let (code, rest) = sequence.split_once(';').unwrap_or((&sequence, ""));
+        match code {
+            // VS Code's own marks are OSC 133's with a few additions
+            "133" | "633" => {
+                let (kind, params) = rest.split_once(';').unwrap_or((rest, ""));
+                match kind {
+                    "A" => {
+                        self.echo = None;
+                        self.command_line = None;
+                        Some(Mark::Prompt)
+                    }
+                    "B" => {
+                        self.echo = Some(Vec::new());
+                        None
+                    }
+                    "C" => {
+                        let echoed = self.echo.take().map(|echo| echoed_line(&String::from_utf8_lossy(&echo)));
+                        let command = marked_command_line(params)
+                            .or_else(|| self.command_line.take())
+                            .or(echoed)
+                            .filter(|command| !command.is_empty());
+                        Some(Mark::Executed(command))
+                    }
+                    "D" => Some(Mark::Finished(params.split(';').next().and_then(|code| code.parse().ok()))),
+                    "E" if code == "633" => {
+                        self.command_line = Some(vscode_unescape(params.split(';').next().unwrap_or_default()));
+                        None
+                    }
+                    _ => None,
+                }
+            }
+            "7" => {
+                let path = rest.strip_prefix("file://")?;
+                // After the host name
+                let path = &path[path.find('/')?..];
+                Some(Mark::Directory(percent_decode(path)))
+            }
+            "1337" => rest.strip_prefix("CurrentDir=").map(|path| Mark::Directory(path.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// The command line a `C` mark carries: `cmdline_url=` percent-encoded (fish), or `cmdline=` as is
+/// up to the end, since it isn't escaped (kitty)
+fn marked_command_line(params: &str) -> Option<String> {
+    if let Some(encoded) = params.split(';').find_map(|param| param.strip_prefix("cmdline_url=")) {
+        return Some(percent_decode(encoded));
+    }
+    let start = if params.starts_with("cmdline=") { 0 } else { params.find(";cmdline=")? + 1 };
+    Some(params[start + "cmdline=".len()..].to_string())
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%').then(|| text.get(i + 1..i + 3)).flatten().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// VS Code's `633;E` escaping: `\\` and `\xAB`
+fn vscode_unescape(text: &str) -> String {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            decoded.push(byte);
+        } else if let Some(tail) = rest.strip_prefix(b"\\") {
+            decoded.push(b'\\');
+            rest = tail;
+        } else if let Some(value) = rest.strip_prefix(b"x").and_then(|tail| std::str::from_utf8(tail.get(..2)?).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            decoded.push(value);
+            rest = &rest[3..];
+        } else {
+            decoded.push(byte);
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The command line as the terminal shows it after what the line editor echoed: typed text,
+/// cursor moves, backspaces and erasures replayed on the line starting at `B`. Continuation lines
+/// keep their prompt, like `> `.
+fn echoed_line(echo: &str) -> String {
+    let mut lines: Vec<Vec<char>> = vec![Vec::new()];
+    let mut cursor: usize = 0;
+    let mut chars = echo.chars().peekable();
+    while let Some(c) = chars.next() {
+        let line = lines.last_mut().unwrap();
+        match c {
+            '\x1b' if chars.peek() == Some(&'[') => {
+                chars.next();
+                let mut params = String::new();
+                let action = loop {
+                    match chars.next() {
+                        Some(c @ '\x40'..='\x7e') => break Some(c),
+                        Some(c) => params.push(c),
+                        None => break None,
+                    }
+                };
+                let count = params.parse::<usize>().unwrap_or(1).max(1);
+                match action {
+                    Some('D') => cursor = cursor.saturating_sub(count),
+                    Some('C') => cursor = (cursor + count).min(MAX_ECHO),
+                    Some('K') if params == "2" => line.clear(),
+                    Some('K') if params.is_empty() || params == "0" => line.truncate(cursor),
+                    Some('P') => {
+                        let end = (cursor + count).min(line.len());
+                        if cursor < end {
+                            line.drain(cursor..end);
+                        }
+                    }
+                    Some('@') if cursor <= line.len() => {
+                        line.splice(cursor..cursor, std::iter::repeat_n(' ', count.min(MAX_ECHO)));
+                    }
+                    _ => {}
+                }
+            }
+            // Other escape sequences only change how the text looks
+            '\x1b' => {
+                chars.next();
+            }
+            '\x08' => cursor = cursor.saturating_sub(1),
+            '\n' => {
+                lines.push(Vec::new());
+                cursor = 0;
+            }
+            c if c.is_control() => {}
+            c => {
+                if cursor >= line.len() {
+                    line.resize(cursor, ' ');
+                    line.push(c);
+                } else {
+                    line[cursor] = c;
+                }
+                cursor += 1;
+            }
+        }
+    }
+    lines.iter()
+        .map(|line| line.iter().collect::<String>().trim_end().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// The service's shell socket, connected when there's something to report and again after it
+/// went away
+struct Reporter {
+    stream: Option<Box<dyn Write + Send>>,
+    shell_pid: u32,
+    tty: Option<String>,
+    environment: HashMap<String, String>,
+}
+
+impl Reporter {
+    fn new(shell_pid: u32) -> Self {
+        Self {
+            stream: None,
+            shell_pid,
+            tty: wrap::terminal_name(),
+            environment: terminal_bell::ENV_VARS.iter()
+                .filter_map(|name| Some((name.to_string(), std::env::var(name).ok()?)))
+                .collect(),
+        }
+    }
+
+    /// Sends a hook message about the shell's command; nothing is printed when the service isn't
+    /// running, as the output is the shell's
+    fn send(&mut self, event_type: &str, command: Option<&str>, working_directory: Option<&str>, exit_code: Option<i32>) {
+        let message = serde_json::json!({
+            "session_id": format!("pty-{}", self.shell_pid),
+            "event_type": event_type,
+            "shell_pid": self.shell_pid,
+            "command_b64": command.map(|command| general_purpose::STANDARD.encode(command)),
+            "working_directory_b64": working_directory.map(|dir| general_purpose::STANDARD.encode(dir)),
+            "exit_code": exit_code,
+            "tty": self.tty,
+            "environment": self.environment,
+            "prompt_marks": true,
+        });
+        // Once more on a fresh connection, in case the service restarted
+        for _ in 0..2 {
+            if self.stream.is_none() {
+                self.stream = wrap::connect_service().ok();
+            }
+            let Some(stream) = self.stream.as_mut() else {
+                return;
+            };
+            if writeln!(stream, "{}", message).and_then(|_| stream.flush()).is_ok() {
+                return;
+            }
+            self.stream = None;
+        }
+    }
+}
+
+/// Where the shell is, when it didn't say with OSC 7
+fn shell_directory(shell_pid: u32) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        Some(std::fs::read_link(format!("/proc/{}/cwd", shell_pid)).ok()?.to_string_lossy().into_owned())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = shell_pid;
+        None
+    }
+}
+
+/// Runs the shell, or `$SHELL` without one, returning its exit code
+pub fn run(command: Vec<String>) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    let command = if command.is_empty() {
+        vec![std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())]
+    } else {
+        command
+    };
+    let (program, args) = command.split_first().ok_or("No shell to run")?;
+    let (controller, terminal) = wrap::open_pty().map_err(|e| format!("Failed to open a pseudo-terminal: {}", e))?;
+    let mut child = wrap::spawn_on(program, args, terminal)?;
+    let raw_mode = wrap::RawMode::enable();
+
+    let mut output = File::from(controller.try_clone()?);
+    wrap::forward_input(File::from(controller.try_clone()?));
+    std::thread::spawn(move || {
+        let mut size = wrap::window_size();
+        loop {
+            std::thread::sleep(RESIZE_INTERVAL);
+            // The shell's terminal follows ours
+            let current = wrap::window_size();
+            if let Some(new_size) = current.filter(|new| size.is_none_or(|old| (old.ws_row, old.ws_col) != (new.ws_row, new.ws_col))) {
+                // SAFETY: TIOCSWINSZ reads a winsize
+                unsafe { libc::ioctl(controller.as_raw_fd(), libc::TIOCSWINSZ as _, &new_size) };
+                size = current;
+            }
+        }
+    });
+
+    let mut reporter = Reporter::new(child.id());
+    let mut scanner = MarkScanner::default();
+    let mut directory = None;
+    let mut running = false;
+    let mut stdout = std::io::stdout();
+    let mut buffer = [0u8; 8192];
+    loop {
+        // Linux reports EIO once the shell and everything it started closed the terminal
+        let read = match output.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        if stdout.write_all(&buffer[..read]).and_then(|_| stdout.flush()).is_err() {
+            break;
+        }
+        for mark in scanner.scan(&buffer[..read]) {
+            match mark {
+                Mark::Directory(path) => directory = Some(path),
+                Mark::Executed(Some(command)) => {
+                    if running {
+                        reporter.send("CommandEnd", None, None, None);
+                    }
+                    let working_directory = directory.clone().or_else(|| shell_directory(child.id()));
+                    reporter.send("CommandStart", Some(&command), working_directory.as_deref(), None);
+                    running = true;
+                }
+                Mark::Executed(None) => {}
+                // Shells that never send `D` end a command with their next prompt
+                Mark::Finished(exit_code) if running => {
+                    reporter.send("CommandEnd", None, None, exit_code);
+                    running = false;
+                }
+                Mark::Prompt if running => {
+                    reporter.send("CommandEnd", None, None, None);
+                    running = false;
+                }
+                Mark::Finished(_) | Mark::Prompt => {}
+            }
+        }
+    }
+
+    let status = child.wait()?;
+    drop(raw_mode);
+    if running {
+        reporter.send("CommandEnd", None, None, None);
+    }
+    Ok(status.code().or_else(|| status.signal().map(|signal| 128 + signal)).unwrap_or(1))
+}
@@ -0,0 +1,272 @@
+// Rebuild cycles of watch-mode commands
+// `cargo watch`, `tsc --watch`, `vite` and the like never end: they rebuild on every save. Rules
+// with `mode = "session"` match each rebuild instead of the watcher's start, and
+// `viberot-service wrap --cycles` tells the service when one starts and ends. Known watchers
+// print lines saying so, looked for in their output (or in that of the command an `npm run dev`
+// script runs); `--cycle-start` and `--cycle-end` name the lines of others. Watchers printing
+// nothing useful, like watchexec, have a cycle while the command they run is: while the wrapped
+// process has child processes.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::enrichment::{unwrap_argv, Enricher, TaskEnricher};
+use crate::platform::{ProbeSource, ProcessEvent};
+
+/// What a line of a watcher's output says
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Marker {
+    /// A rebuild started
+    Start,
+    /// The rebuild finished, with this exit code if the watcher tells; a rebuild that finishes
+    /// without having said it started is a cycle of its own
+    End(Option<i32>),
+}
+
+/// A known watcher: whether a command line runs it, and the lines it prints, checked in order
+struct Watcher {
+    name: &'static str,
+    runs: fn(&str, &[String]) -> bool,
+    markers: &'static [(&'static str, Marker)],
+}
+
+const WATCHERS: &[Watcher] = &[
+    Watcher {
+        name: "cargo-watch",
+        runs: |program, args| program == "cargo-watch" || (program == "cargo" && subcommand(args) == Some("watch")),
+        markers: &[
+            ("[Running '", Marker::Start),
+            ("[Finished running. Exit status: 0]", Marker::End(Some(0))),
+            ("[Finished running. Exit status:", Marker::End(Some(1))),
+        ],
+    },
+    // Prints nothing of its own by default, so its cycles come from the processes it runs
+    Watcher {
+        name: "watchexec",
+        runs: |program, _| program == "watchexec",
+        markers: &[],
+    },
+    Watcher {
+        name: "nodemon",
+        runs: |program, _| program == "nodemon",
+        markers: &[
+            ("[nodemon] starting", Marker::Start),
+            ("[nodemon] restarting", Marker::Start),
+            ("[nodemon] clean exit", Marker::End(Some(0))),
+            ("[nodemon] app crashed", Marker::End(Some(1))),
+        ],
+    },
+    Watcher {
+        name: "tsc",
+        runs: |program, args| program == "tsc" && has_flag(args, &["--watch", "-w"]),
+        markers: &[
+            ("Starting compilation in watch mode", Marker::Start),
+            ("Starting incremental compilation", Marker::Start),
+            ("Found 0 errors", Marker::End(Some(0))),
+            ("Watching for file changes", Marker::End(Some(1))),
+        ],
+    },
+    Watcher {
+        name: "vite",
+        runs: |program, args| program == "vite" && match subcommand(args) {
+            None | Some("dev" | "serve") => true,
+            Some("build") => has_flag(args, &["--watch", "-w"]),
+            _ => false,
+        },
+        markers: &[
+            ("build started", Marker::Start),
+            ("built in", Marker::End(Some(0))),
+            ("error during build", Marker::End(Some(1))),
+            // The dev server updates pages without building anything
+            ("hmr update", Marker::End(Some(0))),
+            ("page reload", Marker::End(Some(0))),
+        ],
+    },
+    Watcher {
+        name: "webpack",
+        runs: |program, args| match program {
+            "webpack-dev-server" => true,
+            "webpack" => subcommand(args) == Some("serve") || has_flag(args, &["--watch", "-w"]),
+            _ => false,
+        },
+        markers: &[
+            ("compiled successfully", Marker::End(Some(0))),
+            // Warnings or errors: which, only the counts after it tell
+            ("compiled with", Marker::End(None)),
+        ],
+    },
+    Watcher {
+        name: "next",
+        runs: |program, args| program == "next" && subcommand(args) == Some("dev"),
+        markers: &[
+            ("Compiling", Marker::Start),
+            ("Compiled", Marker::End(Some(0))),
+            ("Failed to compile", Marker::End(Some(1))),
+        ],
+    },
+    Watcher {
+        name: "gradle",
+        runs: |program, args| matches!(program, "gradle" | "gradlew") && has_flag(args, &["--continuous", "-t"]),
+        markers: &[
+            ("Change detected, executing build", Marker::Start),
+            ("BUILD SUCCESSFUL", Marker::End(Some(0))),
+            ("BUILD FAILED", Marker::End(Some(1))),
+        ],
+    },
+    Watcher {
+        name: "air",
+        runs: |program, _| program == "air",
+        markers: &[
+            ("building...", Marker::Start),
+            ("failed to build", Marker::End(Some(1))),
+            ("running...", Marker::End(Some(0))),
+        ],
+    },
+];
+
+fn subcommand(args: &[String]) -> Option<&str> {
+    args.iter().map(String::as_str).find(|arg| !arg.starts_with('-'))
+}
+
+fn has_flag(args: &[String], flags: &[&str]) -> bool {
+    args.iter().any(|arg| flags.contains(&arg.as_str()))
+}
+
+/// How the cycles of a wrapped command are told apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CycleSource {
+    /// Lines of its output, and what they say
+    Markers(Vec<(String, Marker)>),
+    /// Whether it has child processes
+    Children,
+}
+
+impl CycleSource {
+    /// The source for a command: the given markers, those of the watcher it runs, or its children
+    pub fn for_command(command: &str, working_directory: Option<&Path>, starts: &[String], ends: &[String]) -> CycleSource {
+        if !starts.is_empty() || !ends.is_empty() {
+            let starts = starts.iter().map(|line| (line.clone(), Marker::Start));
+            let ends = ends.iter().map(|line| (line.clone(), Marker::End(None)));
+            return CycleSource::Markers(starts.chain(ends).collect());
+        }
+        match detect(command, working_directory) {
+            Some(watcher) if !watcher.markers.is_empty() => CycleSource::Markers(
+                watcher.markers.iter().map(|(line, marker)| (line.to_string(), *marker)).collect(),
+            ),
+            _ => CycleSource::Children,
+        }
+    }
+}
+
+/// The known watcher a command runs, looking through task runners to the command the task runs
+fn detect(command: &str, working_directory: Option<&Path>) -> Option<&'static Watcher> {
+    let mut event = ProcessEvent::from_raw(0, command.as_bytes().to_vec(), ProbeSource::PosixShell);
+    if let Some(dir) = working_directory {
+        event = event.with_working_directory(dir.to_string_lossy().into_owned());
+    }
+    let mut tags = BTreeMap::new();
+    TaskEnricher.enrich(&event, &mut tags);
+    let watcher = tags.get("task_command").and_then(|task_command| find(task_command)).or_else(|| find(command));
+    if let Some(watcher) = watcher {
+        tracing::debug!("{} runs {}", command, watcher.name);
+    }
+    watcher
+}
+
+fn find(command: &str) -> Option<&'static Watcher> {
+    let argv = unwrap_argv(command);
+    let (program, args) = argv.split_first()?;
+    WATCHERS.iter().find(|watcher| (watcher.runs)(program, args))
+}
+
+/// Longest line kept while waiting for its end; progress bars redraw lines without ending them
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Splits output into lines without escape sequences and finds the markers in them
+pub struct LineScanner {
+    markers: Vec<(String, Marker)>,
+    line: Vec<u8>,
+}
+
+impl LineScanner {
+    pub fn new(markers: Vec<(String, Marker)>) -> Self {
+        Self { markers, line: Vec::new() }
+    }
+
+    /// Markers in the lines this output ends
+    pub fn scan(&mut self, output: &[u8]) -> Vec<Marker> {
+        let mut found = Vec::new();
+        for &byte in output {
+            if byte == b'\n' || byte == b'\r' {
+                if let Some(marker) = self.marker() {
+                    found.push(marker);
+                }
+                self.line.clear();
+            } else if self.line.len() < MAX_LINE_LENGTH {
+                self.line.push(byte);
+            }
+        }
+        found
+    }
+
+    fn marker(&self) -> Option<Marker> {
+        if self.line.is_empty() {
+            return None;
+        }
+        let line = strip_escapes(&String::from_utf8_lossy(&self.line));
+        self.markers.iter().find(|(text, _)| line.contains(text.as_str())).map(|(_, marker)| *marker)
+    }
+}
+
+/// Text without ANSI escape sequences (colors, cursor movement)
+fn strip_escapes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters up to a final byte in @..~
+            Some('[') => for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            },
+            // OSC: up to BEL or ST
+            Some(']') => {
+                let mut previous = None;
+                for c in chars.by_ref() {
+                    if c == '\x07' || (previous == Some('\x1b') && c == '\\') {
+                        break;
+                    }
+                    previous = Some(c);
+                }
+            }
+            _ => {}
+        }
+    }
+    stripped
+}
+
+/// Whether a process has child processes running
+#[cfg(target_os = "linux")]
+pub fn has_children(pid: u32) -> bool {
+    let Ok(tasks) = std::fs::read_dir(format!("/proc/{}/task", pid)) else {
+        return false;
+    };
+    tasks.flatten().any(|task| {
+        std::fs::read_to_string(task.path().join("children")).is_ok_and(|children| !children.trim().is_empty())
+    })
+}
+
+/// Whether a process has child processes running
+#[cfg(not(target_os = "linux"))]
+pub fn has_children(pid: u32) -> bool {
+    std::process::Command::new("pgrep")
+        .args(["-P", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
@@ -0,0 +1,482 @@
+// Recent events
+// The service keeps the last few hundred commands it saw, with what it decided for each: the
+// rules that matched, or why every rule didn't, whether do-not-disturb held the actions back,
+// actions that failed to start, and what came of the ones that did (see `action_protocol`).
+// `viberot-service recent` reads them from the configured `Storage`, which the service appends to
+// shortly after each command, so "why didn't my rule fire?" doesn't need debug logs. The closest
+// command glob of rules that didn't match is worked out when showing them, against the current
+// config. With `explain = true` each command also keeps a full trace, the checks every rule got
+// past and the actions chosen, which `viberot-service explain <event-id>` shows.
+
+use globset::Glob;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::action_protocol::ActionResult;
+use crate::audit;
+use crate::config::{Action, Config, OnlyIf, ThrottleTarget};
+use crate::mutes::format_duration;
+use crate::storage::Storage;
+use crate::platform::ProcessEvent;
+use crate::rule_engine::{MatchedAction, Miss, PartialMatch, RuleVerdict};
+use crate::watch;
+
+/// Commands kept when `recent_events` isn't set
+pub const DEFAULT_CAPACITY: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RecentEvent {
+    Started {
+        /// Unix timestamp
+        at: u64,
+        #[serde(default)]
+        event_id: String,
+        pid: u32,
+        command: String,
+        probe: String,
+        #[serde(default)]
+        working_directory: Option<String>,
+        #[serde(default)]
+        tags: BTreeMap<String, String>,
+        decision: Decision,
+        /// How each rule fared
+        rules: Vec<RuleOutcome>,
+        /// Whether `explain` was on: the rules' passed checks and the chosen actions were recorded
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        traced: bool,
+        /// Actions chosen for the command, after the daily budget, when traced
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        actions: Vec<ChosenAction>,
+    },
+    Ended {
+        at: u64,
+        pid: u32,
+    },
+    /// An action of a matched rule couldn't be started
+    ActionFailed {
+        at: u64,
+        pid: u32,
+        rule: String,
+        action: String,
+        error: String,
+    },
+    /// An action reported what came of it, or exited with a failing status
+    ActionResult {
+        at: u64,
+        pid: u32,
+        action: String,
+        #[serde(flatten)]
+        result: ActionResult,
+    },
+}
+
+/// What the service did about a command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    /// Actions were started
+    Started { actions: usize },
+    /// Rules matched, but do-not-disturb held their actions back
+    Suppressed { actions: usize },
+    /// Rules matched, but the event arrived too late to start their actions
+    Stale { actions: usize, age_secs: u64 },
+    NoMatch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleOutcome {
+    pub rule: String,
+    /// Position of the rule in the config file
+    pub index: usize,
+    pub has_command: bool,
+    pub matched: bool,
+    /// Why it didn't match
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Its command globs didn't match
+    #[serde(default)]
+    pub command_missed: bool,
+    /// Checks it got past, when traced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub passed: Vec<String>,
+}
+
+/// An action chosen for a command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChosenAction {
+    pub rule: String,
+    pub action: String,
+    /// How long the command must run for before it starts
+    #[serde(default)]
+    pub after_secs: u64,
+    #[serde(default)]
+    pub cpu_above: Option<u32>,
+    #[serde(default)]
+    pub only_if: Option<OnlyIf>,
+}
+
+impl From<&MatchedAction> for ChosenAction {
+    fn from(matched: &MatchedAction) -> Self {
+        Self {
+            rule: matched.rule_name.clone(),
+            action: describe_action(&matched.action),
+            after_secs: matched.after.as_secs(),
+            cpu_above: matched.cpu_above,
+            only_if: matched.only_if,
+        }
+    }
+}
+
+impl From<&RuleVerdict> for RuleOutcome {
+    fn from(verdict: &RuleVerdict) -> Self {
+        Self {
+            rule: verdict.rule_name.clone(),
+            index: verdict.index,
+            has_command: verdict.has_command,
+            matched: verdict.outcome.is_ok(),
+            reason: verdict.outcome.as_ref().err().map(Miss::to_string),
+            command_missed: matches!(verdict.outcome, Err(Miss::Command)),
+            passed: Vec::new(),
+        }
+    }
+}
+
+/// The last commands the service saw, saved for `viberot-service recent`
+pub struct RecentEvents {
+    storage: Arc<dyn Storage>,
+    capacity: usize,
+    events: Mutex<VecDeque<RecentEvent>>,
+    /// Events recorded since the last save
+    unsaved: Mutex<Vec<RecentEvent>>,
+}
+
+impl RecentEvents {
+    pub fn new(storage: Arc<dyn Storage>, capacity: usize) -> Self {
+        Self {
+            storage,
+            capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+            unsaved: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a command and what was decided for it, with the full trace if `chosen` has the
+    /// actions chosen for it
+    pub fn started(&self, event: &ProcessEvent, decision: Decision, verdicts: &[RuleVerdict], chosen: Option<&[MatchedAction]>) {
+        let rules = verdicts.iter().map(|verdict| RuleOutcome {
+            passed: match chosen {
+                Some(_) => verdict.passed.iter().map(|check| check.to_string()).collect(),
+                None => Vec::new(),
+            },
+            ..RuleOutcome::from(verdict)
+        }).collect();
+        self.push(RecentEvent::Started {
+            at: event.timestamp,
+            event_id: event.event_id.clone(),
+            pid: event.pid,
+            command: event.command.clone(),
+            probe: format!("{:?}", event.probe_source),
+            working_directory: event.working_directory.clone(),
+            tags: event.tags.clone(),
+            decision,
+            rules,
+            traced: chosen.is_some(),
+            actions: chosen.unwrap_or_default().iter().map(ChosenAction::from).collect(),
+        });
+    }
+
+    /// Records the end of a command, if its start is still kept
+    pub fn ended(&self, pid: u32) {
+        if self.knows(pid) {
+            self.push(RecentEvent::Ended { at: audit::unix_now(), pid });
+        }
+    }
+
+    /// Records an action that failed to start, if the command's start is still kept
+    pub fn action_failed(&self, pid: u32, rule: String, action: String, error: String) {
+        if self.knows(pid) {
+            self.push(RecentEvent::ActionFailed { at: audit::unix_now(), pid, rule, action, error });
+        }
+    }
+
+    /// Records what came of an action, if the command's start is still kept
+    pub fn action_result(&self, pid: u32, action: String, result: ActionResult) {
+        if self.knows(pid) {
+            self.push(RecentEvent::ActionResult { at: audit::unix_now(), pid, action, result });
+        }
+    }
+
+    fn knows(&self, pid: u32) -> bool {
+        self.events.lock().unwrap().iter()
+            .any(|event| matches!(event, RecentEvent::Started { pid: started, .. } if *started == pid))
+    }
+
+    fn push(&self, event: RecentEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        while events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event.clone());
+        self.unsaved.lock().unwrap().push(event);
+    }
+
+    /// Appends the events recorded since the last save
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let unsaved = std::mem::take(&mut *self.unsaved.lock().unwrap());
+        if unsaved.is_empty() {
+            return Ok(());
+        }
+        let records = unsaved.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?;
+        self.storage.append_events(&records)
+    }
+}
+
+/// Reads the events the service saved, oldest first
+pub fn load(storage: &dyn Storage) -> Result<Vec<RecentEvent>, Box<dyn std::error::Error + Send + Sync>> {
+    let records = storage.events(usize::MAX)?;
+    if records.is_empty() {
+        return Err("No recent events yet; they're saved while the service runs, and with `[storage] backend = \"memory\"` never".into());
+    }
+    records.iter().map(|record| Ok(serde_json::from_str(record)?)).collect()
+}
+
+/// The last `limit` commands, with their ends and actions' failures and results, optionally only those matching
+/// a glob
+pub fn select(events: Vec<RecentEvent>, filter: Option<&str>, limit: usize) -> Result<Vec<RecentEvent>, globset::Error> {
+    let filter = filter.map(|pattern| Glob::new(pattern).map(|glob| glob.compile_matcher())).transpose()?;
+    let mut kept_pids = Vec::new();
+    let mut selected: Vec<RecentEvent> = events.into_iter().rev()
+        .filter(|event| match event {
+            RecentEvent::Started { pid, command, .. } => {
+                let keep = kept_pids.len() < limit && filter.as_ref().is_none_or(|filter| filter.is_match(command));
+                if keep {
+                    kept_pids.push(*pid);
+                }
+                keep
+            }
+            // These come after their starts, so they are seen first here
+            RecentEvent::Ended { .. } | RecentEvent::ActionFailed { .. } | RecentEvent::ActionResult { .. } => true,
+        })
+        .collect();
+    selected.reverse();
+    selected.retain(|event| match event {
+        RecentEvent::Ended { pid, .. } | RecentEvent::ActionFailed { pid, .. } | RecentEvent::ActionResult { pid, .. } => {
+            kept_pids.contains(pid)
+        }
+        RecentEvent::Started { .. } => true,
+    });
+    Ok(selected)
+}
+
+/// Human-readable form of the events, with the closest command glob of rules that didn't match
+/// according to `config`
+pub fn to_text(events: &[RecentEvent], config: Option<&Config>, all: bool) -> String {
+    if events.is_empty() {
+        return "No commands seen yet\n".to_string();
+    }
+    let now = audit::unix_now();
+    let ago = |at: u64| format!("{} ago", format_duration(now.saturating_sub(at)));
+    let mut text = String::new();
+    for event in events {
+        match event {
+            RecentEvent::Started { at, event_id, pid, command, probe, working_directory, decision, rules, .. } => {
+                text.push_str(&format!("\n{}  $ {}\n", ago(*at), command));
+                let mut details = vec![format!("PID {}", pid), probe.clone()];
+                details.extend(working_directory.iter().cloned());
+                if !event_id.is_empty() {
+                    details.push(format!("event {}", event_id));
+                }
+                text.push_str(&format!("  {}\n", details.join("  ")));
+                for outcome in rules {
+                    let reason = outcome.reason.as_deref().unwrap_or_default();
+                    // Like `watch`, rules whose globs didn't match would be listed for every command
+                    if outcome.matched {
+                        text.push_str(&format!("  ✓ {}\n", outcome.rule));
+                    } else if all || (outcome.has_command && !outcome.command_missed) {
+                        text.push_str(&format!("  ✗ {}: {}\n", outcome.rule, reason));
+                    }
+                }
+                match decision {
+                    Decision::Started { .. } | Decision::Suppressed { .. } | Decision::Stale { .. } => {
+                        text.push_str(&format!("  {}\n", describe_decision(decision)));
+                    }
+                    // A rule that got past its globs already says why it didn't match
+                    Decision::NoMatch if rules.iter().any(|outcome| outcome.has_command && !outcome.command_missed) => {
+                        text.push_str("  No rule matched\n");
+                    }
+                    Decision::NoMatch => {
+                        let closest = config.and_then(|config| closest(config, command, rules));
+                        match closest {
+                            Some((rule, partial)) => text.push_str(&format!(
+                                "  No rule matched; closest: {}, whose {}\n", rule, watch::describe_partial(&partial))),
+                            None => text.push_str("  No rule matched\n"),
+                        }
+                    }
+                }
+            }
+            RecentEvent::Ended { at, pid } => text.push_str(&format!("{}  PID {} ended\n", ago(*at), pid)),
+            RecentEvent::ActionFailed { at, pid, rule, action, error } => text.push_str(&format!(
+                "{}  PID {}: action '{}' of rule '{}' failed: {}\n", ago(*at), pid, action, rule, error)),
+            RecentEvent::ActionResult { at, pid, action, result } => text.push_str(&format!(
+                "{}  PID {}: action '{}': {}\n", ago(*at), pid, action, result)),
+        }
+    }
+    text
+}
+
+/// The command glob that came closest among rules whose globs didn't match, if the rule is still
+/// where it was in the config
+fn closest(config: &Config, command: &str, rules: &[RuleOutcome]) -> Option<(String, PartialMatch)> {
+    rules.iter()
+        .filter(|outcome| outcome.command_missed)
+        .filter_map(|outcome| {
+            let rule = config.rules.get(outcome.index)?;
+            if rule.display_name(outcome.index) != outcome.rule {
+                return None;
+            }
+            Some((outcome.rule.clone(), PartialMatch::closest(rule, command)?))
+        })
+        .max_by(|(_, a), (_, b)| a.ratio().total_cmp(&b.ratio()))
+}
+
+fn describe_decision(decision: &Decision) -> String {
+    match decision {
+        Decision::Started { actions } => format!("Started {} action(s)", actions),
+        Decision::Suppressed { actions } => format!("Do-not-disturb held back {} action(s)", actions),
+        Decision::Stale { actions, age_secs } => {
+            format!("Reported {} after it started, too late for {} action(s)", format_duration(*age_secs), actions)
+        }
+        Decision::NoMatch => "No rule matched".to_string(),
+    }
+}
+
+/// Short form of an action, e.g. `exec notify-send "Build done"`
+fn describe_action(action: &Action) -> String {
+    match action {
+        Action::Executable { path, args, .. } => {
+            let args = args.iter().flatten().map(|arg| format!(" {}", arg)).collect::<String>();
+            format!("exec {}{}", path, args)
+        }
+        Action::Lua { script, .. } => format!("lua {}", script),
+        Action::Attach { socket, path, .. } => match socket.as_ref().or(path.as_ref()) {
+            Some(target) => format!("attach {}", target),
+            None => "attach".to_string(),
+        },
+        Action::Notify { title, .. } => match title {
+            Some(title) => format!("notify \"{}\"", title),
+            None => "notify".to_string(),
+        },
+        Action::Speak { .. } => "speak".to_string(),
+        Action::Bell { .. } => "bell".to_string(),
+        Action::Throttle { target: ThrottleTarget::List, processes, .. } => format!("throttle {}", processes.join(", ")),
+        Action::Throttle { target: ThrottleTarget::SelfSpawned, .. } => "throttle self-spawned".to_string(),
+        Action::Desktop { desktop, .. } => format!("desktop {}", desktop),
+    }
+}
+
+/// The command with this event ID, or the only one whose ID starts with it, with its ends and
+/// actions' failures and results
+pub fn find(events: Vec<RecentEvent>, id: &str) -> Result<Vec<RecentEvent>, Box<dyn std::error::Error + Send + Sync>> {
+    let started_id = |event: &RecentEvent| match event {
+        RecentEvent::Started { event_id, .. } => Some(event_id.clone()),
+        _ => None,
+    };
+    let ids: Vec<String> = events.iter().filter_map(started_id).filter(|event_id| !event_id.is_empty()).collect();
+    let id = match ids.iter().find(|event_id| *event_id == id) {
+        Some(exact) => exact.clone(),
+        None => {
+            let candidates: Vec<&String> = ids.iter().filter(|event_id| event_id.starts_with(id)).collect();
+            match candidates.as_slice() {
+                [only] => (*only).clone(),
+                [] => return Err(format!("No recent command has event ID '{}'; `viberot-service recent` lists them", id).into()),
+                _ => return Err(format!("'{}' is the start of {} event IDs, give more of it", id, candidates.len()).into()),
+            }
+        }
+    };
+    let (start, pid) = events.iter().enumerate()
+        .find_map(|(index, event)| match event {
+            RecentEvent::Started { event_id, pid, .. } if *event_id == id => Some((index, *pid)),
+            _ => None,
+        })
+        .ok_or("No recent command has that event ID")?;
+    // What followed for its PID, until the PID was reported for another command
+    let mut found = vec![events[start].clone()];
+    for event in &events[start + 1..] {
+        match event {
+            RecentEvent::Started { pid: other, .. } if *other == pid => break,
+            RecentEvent::Ended { pid: other, .. } | RecentEvent::ActionFailed { pid: other, .. } | RecentEvent::ActionResult { pid: other, .. }
+                if *other == pid =>
+            {
+                found.push(event.clone());
+            }
+            _ => {}
+        }
+    }
+    Ok(found)
+}
+
+/// Human-readable decision trace of a command found by `find`
+pub fn explain_text(events: &[RecentEvent]) -> String {
+    let now = audit::unix_now();
+    let ago = |at: u64| format!("{} ago", format_duration(now.saturating_sub(at)));
+    let mut text = String::new();
+    for event in events {
+        match event {
+            RecentEvent::Started { at, event_id, pid, command, probe, working_directory, tags, decision, rules, traced, actions } => {
+                text.push_str(&format!("Event {}, {}\n  $ {}\n", event_id, ago(*at), command));
+                let mut details = vec![format!("PID {}", pid), probe.clone()];
+                details.extend(working_directory.iter().cloned());
+                text.push_str(&format!("  {}\n", details.join("  ")));
+                if !tags.is_empty() {
+                    let tags: Vec<String> = tags.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+                    text.push_str(&format!("  tags: {}\n", tags.join(", ")));
+                }
+
+                text.push_str("Rules, in config order:\n");
+                for outcome in rules {
+                    let passed = match outcome.passed.as_slice() {
+                        [] => String::new(),
+                        passed => format!("passed {}; ", passed.join(", ")),
+                    };
+                    match outcome.reason {
+                        None => text.push_str(&format!("  ✓ {}: {}matched\n", outcome.rule, passed)),
+                        Some(ref reason) => text.push_str(&format!("  ✗ {}: {}{}\n", outcome.rule, passed, reason)),
+                    }
+                }
+                if rules.is_empty() {
+                    text.push_str("  (no rules)\n");
+                }
+
+                text.push_str(&format!("Decision: {}\n", describe_decision(decision)));
+                for action in actions {
+                    let mut when = Vec::new();
+                    if action.after_secs > 0 {
+                        when.push(format!("after {}", format_duration(action.after_secs)));
+                    }
+                    if let Some(cpu) = action.cpu_above {
+                        when.push(format!("once above {}% CPU", cpu));
+                    }
+                    if action.only_if == Some(OnlyIf::Busy) {
+                        when.push("once busy".to_string());
+                    }
+                    let when = if when.is_empty() { String::new() } else { format!(" ({})", when.join(", ")) };
+                    text.push_str(&format!("  {}: {}{}\n", action.rule, action.action, when));
+                }
+                if !traced {
+                    text.push_str("(The checks each rule passed and the chosen actions are only recorded with `explain = true` in the config)\n");
+                }
+            }
+            RecentEvent::Ended { at, .. } => text.push_str(&format!("Ended {}\n", ago(*at))),
+            RecentEvent::ActionFailed { at, rule, action, error, .. } => text.push_str(&format!(
+                "Action '{}' of rule '{}' failed {}: {}\n", action, rule, ago(*at), error)),
+            RecentEvent::ActionResult { at, action, result, .. } => text.push_str(&format!(
+                "Action '{}', {}: {}\n", action, ago(*at), result)),
+        }
+    }
+    text
+}
@@ -0,0 +1,472 @@
+// Rule editing from the CLI
+// `viberot-service rules add/remove/edit/enable/disable/list` change `[[rules]]` in config.toml
+// without hand-editing TOML. The file is edited with toml_edit, so comments and formatting outside
+// the touched rule stay as they are. The result is validated like any config before it's written,
+// and the running service picks it up through the config watcher. `rules test` shows what a
+// command line would match, or whether draft patterns match it, without starting anything.
+// `--json` on `list` and `test` is what the settings app (apps/settings) reads; see output.rs.
+
+use clap::{Args, Subcommand};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, InlineTable, Item, Table};
+
+use crate::config::{Config, Rule};
+use crate::enrichment::EnrichmentPipeline;
+use crate::mutes::MuteStore;
+use crate::output;
+use crate::platform::{ActiveProbe, ProbeSource, ProcessEvent};
+use crate::recent::RuleOutcome;
+use crate::rule_engine::{self, Miss, PartialMatch, RuleEngine};
+use crate::runtime_paths;
+use crate::watch;
+
+/// Overlay action, as in the default config
+const OVERLAY_PATH: &str = "${VIBEROT_ACTIONS}/overlay/target/release/viberot-overlay";
+
+#[derive(Subcommand)]
+pub enum RulesCommand {
+    /// List the configured rules
+    List {
+        /// Print the rules, and the actions they can use, as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Add a rule, e.g. `rules add --name tests --command '*pytest*' --action overlay`
+    Add(RuleArgs),
+    /// Change the given fields of a rule, keeping the rest
+    Edit {
+        /// Rule `name`, or `rule #N` for unnamed rules
+        rule: String,
+        #[command(flatten)]
+        args: RuleArgs,
+    },
+    /// Remove a rule
+    Remove {
+        /// Rule `name`, or `rule #N` for unnamed rules
+        rule: String,
+        /// Print what changed as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Turn a rule back on
+    Enable {
+        /// Rule `name`, or `rule #N` for unnamed rules; the group's name with `--group`
+        rule: String,
+        /// Turn a `[[groups]]` entry on, with all its rules
+        #[arg(long)]
+        group: bool,
+        /// Print what changed as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Turn a rule off, keeping it in the config
+    Disable {
+        /// Rule `name`, or `rule #N` for unnamed rules; the group's name with `--group`
+        rule: String,
+        /// Turn a `[[groups]]` entry off, with all its rules
+        #[arg(long)]
+        group: bool,
+        /// Print what changed as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show which rules a command line would match, e.g. `rules test 'cargo build --release'`
+    Test(TestArgs),
+}
+
+#[derive(Args)]
+pub struct TestArgs {
+    /// The command line, as the shell would run it
+    command: String,
+    /// Check these globs instead of the configured rules, e.g. while writing a rule
+    #[arg(long)]
+    pattern: Vec<String>,
+    /// Match `--pattern` globs ignoring case
+    #[arg(long)]
+    case_insensitive: bool,
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// A rule as `rules list --json` prints it
+#[derive(Serialize)]
+struct ListedRule<'a> {
+    /// `name`, or `rule #N` for unnamed rules
+    name: String,
+    /// Data the rule needs that the probe doesn't report
+    degraded: Vec<String>,
+    rule: &'a Rule,
+}
+
+/// What `rules list` reports
+#[derive(Serialize)]
+struct Listing<'a> {
+    rules: Vec<ListedRule<'a>>,
+    /// The actions named under `[actions]`, which `--action` takes besides "overlay", "notify" and
+    /// programs
+    actions: Vec<String>,
+    /// Where `${VIBEROT_ACTIONS}` points
+    actions_dir: Option<PathBuf>,
+}
+
+/// What the editing subcommands report
+#[derive(Serialize)]
+struct RuleChange {
+    change: Change,
+    /// The rule as it was given, None for `add` without a `--name`
+    rule: Option<String>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Added,
+    Updated,
+    Removed,
+    Enabled,
+    Disabled,
+}
+
+impl Change {
+    /// The subcommand making the change
+    fn command(self) -> &'static str {
+        match self {
+            Change::Added => "rules add",
+            Change::Updated => "rules edit",
+            Change::Removed => "rules remove",
+            Change::Enabled => "rules enable",
+            Change::Disabled => "rules disable",
+        }
+    }
+}
+
+/// How a draft pattern fares against a command
+#[derive(Serialize)]
+struct TestedPattern {
+    pattern: String,
+    matched: bool,
+    /// How far it got, if it didn't match
+    hint: Option<String>,
+}
+
+/// How a configured rule fares against a command
+#[derive(Serialize)]
+struct TestedRule {
+    #[serde(flatten)]
+    outcome: RuleOutcome,
+    /// How far its closest command glob got, if none matched
+    hint: Option<String>,
+}
+
+#[derive(Args)]
+pub struct RuleArgs {
+    /// Name shown in logs and passed to actions
+    #[arg(long)]
+    name: Option<String>,
+    /// Glob matched against the command line; repeat for several
+    #[arg(long)]
+    command: Vec<String>,
+    /// Condition expression, e.g. "category == 'test'"
+    #[arg(long)]
+    when: Option<String>,
+    /// An action defined under `[actions]`, "overlay", "notify", or the path of a program to run
+    #[arg(long)]
+    action: Option<String>,
+    /// Argument for a program action, replacing a named action's; repeat for several
+    #[arg(long = "arg", allow_hyphen_values = true)]
+    args: Vec<String>,
+    /// Print what changed as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+pub fn run(command: RulesCommand, config_path: &Path, probe_path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !config_path.exists() {
+        // Writes the default config to edit
+        Config::load(config_path)?;
+    }
+    let content = std::fs::read_to_string(config_path)?;
+    let mut document: DocumentMut = content.parse()
+        .map_err(|e| format!("Failed to parse {:?}: {}", config_path, e))?;
+    let named_actions: Vec<String> = document.get("actions").and_then(Item::as_table_like)
+        .map(|actions| actions.iter().map(|(name, _)| name.to_string()).collect())
+        .unwrap_or_default();
+
+    let (change, json) = match command {
+        RulesCommand::List { json } => {
+            let config = Config::parse(&content)?;
+            // Rules are only degraded for the probe the service last ran with
+            let probe = ActiveProbe::load(probe_path);
+            let listing = Listing {
+                rules: config.rules.iter().enumerate()
+                    .map(|(index, rule)| ListedRule {
+                        name: rule.display_name(index),
+                        degraded: probe.as_ref().map(|probe| rule_engine::missing_data(rule, &probe.data)).unwrap_or_default(),
+                        rule,
+                    })
+                    .collect(),
+                actions: named_actions,
+                actions_dir: runtime_paths::install_root(Some(&config)).ok().map(|root| root.join("actions")),
+            };
+            output::print("rules list", json, &listing, |listing| {
+                let mut text = String::new();
+                for ListedRule { name, degraded, rule } in &listing.rules {
+                    let group = rule.group.as_ref().map_or(String::new(), |group| format!(" [{}]", group));
+                    text.push_str(&format!("{}{}{}: {}\n", name, group, if rule.enabled { "" } else { " (off)" }, patterns_text(rule)));
+                    if let (Some(probe), false) = (&probe, degraded.is_empty()) {
+                        text.push_str(&format!("  degraded: the {:?} probe doesn't report {}\n", probe.capability, degraded.join(" or ")));
+                    }
+                }
+                text
+            })?;
+            return Ok(());
+        }
+        RulesCommand::Add(args) => {
+            if args.command.is_empty() && args.when.is_none() {
+                return Err("A new rule needs a `--command` or `--when`".into());
+            }
+            if args.action.is_none() {
+                return Err("A new rule needs an `--action`".into());
+            }
+            let mut table = Table::new();
+            args.apply(&mut table, &named_actions)?;
+            rules(&mut document)?.push(table);
+            (RuleChange { change: Change::Added, rule: args.name }, args.json)
+        }
+        RulesCommand::Edit { rule, args } => {
+            let index = find(&document, &rule)?;
+            let table = rules(&mut document)?.get_mut(index).expect("found above");
+            args.apply(table, &named_actions)?;
+            (RuleChange { change: Change::Updated, rule: Some(rule) }, args.json)
+        }
+        RulesCommand::Remove { rule, json } => {
+            let index = find(&document, &rule)?;
+            rules(&mut document)?.remove(index);
+            (RuleChange { change: Change::Removed, rule: Some(rule) }, json)
+        }
+        RulesCommand::Enable { rule, group, json } => {
+            table_of(&mut document, &rule, group)?.remove("enabled");
+            (RuleChange { change: Change::Enabled, rule: Some(rule) }, json)
+        }
+        RulesCommand::Disable { rule, group, json } => {
+            table_of(&mut document, &rule, group)?["enabled"] = value(false);
+            (RuleChange { change: Change::Disabled, rule: Some(rule) }, json)
+        }
+        RulesCommand::Test(_) => unreachable!("testing evaluates rules, started in main"),
+    };
+
+    save(&document, config_path)?;
+    output::print(change.change.command(), json, &change, |change| {
+        let rule = change.rule.as_deref().map_or("the rule".to_string(), |rule| format!("'{}'", rule));
+        let message = match change.change {
+            Change::Added => format!("Added {}", rule),
+            Change::Updated => format!("Updated {}", rule),
+            Change::Removed => format!("Removed {}", rule),
+            Change::Enabled => format!("Turned {} on", rule),
+            Change::Disabled => format!("Turned {} off", rule),
+        };
+        format!("{}; the running service reloads the config on its own\n", message)
+    })?;
+    Ok(())
+}
+
+/// A rule's command globs, or `when` without any, and `exe` conditions, as `rules list` shows them
+fn patterns_text(rule: &Rule) -> String {
+    let mut patterns = match (&rule.command, &rule.when) {
+        (Some(commands), _) => {
+            let patterns = commands.as_vec().iter().map(|c| c.as_str()).collect::<Vec<_>>().join(", ");
+            match rule.matcher.as_deref().filter(|matcher| *matcher != "glob") {
+                Some(matcher) => format!("{} ({})", patterns, matcher),
+                None => patterns,
+            }
+        }
+        (None, Some(when)) => format!("when {}", when),
+        (None, None) => String::new(),
+    };
+    if let Some(exe) = &rule.exe {
+        let mut program = format!("exe {}", exe.as_vec().iter().map(|e| e.as_str()).collect::<Vec<_>>().join(", "));
+        if !rule.args_contains.is_empty() {
+            program.push_str(&format!(" with {}", rule.args_contains.join(" ")));
+        }
+        patterns = match patterns.is_empty() {
+            true => program,
+            false => format!("{}; {}", program, patterns),
+        };
+    }
+    patterns
+}
+
+/// Evaluates the rules, or draft `--pattern`s, against a command line run in the current directory
+pub async fn test(args: TestArgs, config_path: &Path, mutes: MuteStore) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = Config::load(config_path)?;
+    let mut event = ProcessEvent::from_raw(0, args.command.into_bytes(), ProbeSource::PosixShell);
+    if let Ok(dir) = std::env::current_dir() {
+        event = event.with_working_directory(dir.to_string_lossy().into_owned());
+    }
+
+    if !args.pattern.is_empty() {
+        let mut tested = Vec::new();
+        for pattern in args.pattern {
+            let glob = rule_engine::command_glob(&pattern, args.case_insensitive)
+                .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?;
+            let matched = glob.compile_matcher().is_match(&event.command);
+            let hint = (!matched).then(|| watch::describe_partial(&PartialMatch::of(&pattern, &event.command, args.case_insensitive)));
+            tested.push(TestedPattern { pattern, matched, hint });
+        }
+        output::print("rules test", args.json, &tested, |tested| {
+            let mut text = String::new();
+            for pattern in tested {
+                match &pattern.hint {
+                    None => text.push_str(&format!("✓ {}\n", pattern.pattern)),
+                    Some(hint) => text.push_str(&format!("✗ {}\n", hint)),
+                }
+            }
+            text
+        })?;
+        return Ok(());
+    }
+
+    // Tagged like the service would, so `repo`, `task` and `when` conditions are checked too
+    let event = Arc::new(EnrichmentPipeline::new(&config.enrichment)).enrich(event).await;
+    let verdicts = RuleEngine::new(mutes).explain(&event, &config).await;
+    let tested: Vec<TestedRule> = verdicts.iter()
+        .map(|verdict| TestedRule {
+            outcome: RuleOutcome::from(verdict),
+            hint: match verdict.outcome {
+                Err(Miss::Command) => verdict.closest.as_ref().map(watch::describe_partial),
+                _ => None,
+            },
+        })
+        .collect();
+    output::print("rules test", args.json, &tested, |_| watch::to_text(&event, &verdicts))?;
+    Ok(())
+}
+
+/// Writes an edited config, if it's valid
+pub fn save(document: &DocumentMut, config_path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let content = document.to_string();
+    let config = Config::parse(&content).map_err(|e| format!("The edited config is invalid, not saved: {}", e))?;
+    config.validate().map_err(|e| format!("The edited config is invalid, not saved: {}", e))?;
+
+    let temp_path = config_path.with_extension("toml.tmp");
+    std::fs::write(&temp_path, content)?;
+    std::fs::rename(&temp_path, config_path)?;
+    Ok(())
+}
+
+impl RuleArgs {
+    /// Sets the given fields on a rule table; actions defined under `[actions]` are referred to by
+    /// name
+    fn apply(&self, table: &mut Table, named_actions: &[String]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ref name) = self.name {
+            table["name"] = value(name.as_str());
+        }
+        match self.command.as_slice() {
+            [] => {}
+            [command] => table["command"] = value(command.as_str()),
+            commands => table["command"] = value(commands.iter().collect::<Array>()),
+        }
+        if !self.command.is_empty() {
+            table.remove("commands");
+        }
+        if let Some(ref when) = self.when {
+            table["when"] = value(when.as_str());
+        }
+
+        match self.action.as_deref() {
+            Some(action) if named_actions.iter().any(|name| name == action) => {
+                table.remove("actions");
+                if self.args.is_empty() {
+                    table["action"] = value(action);
+                } else {
+                    let mut reference = InlineTable::new();
+                    reference.insert("use", action.into());
+                    reference.insert("args", self.args.iter().collect::<Array>().into());
+                    table["action"] = value(reference);
+                }
+            }
+            Some(action) => {
+                table.remove("actions");
+                let mut action_table = action_table(action, &self.args);
+                // Right under the rule, like the rules in the default config
+                action_table.decor_mut().set_prefix("");
+                table["action"] = Item::Table(action_table);
+            }
+            None if !self.args.is_empty() => return Err("`--arg` goes with `--action`".into()),
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// An action table for `--action`: "overlay", "notify" or a program
+pub fn action_table(action: &str, args: &[String]) -> Table {
+    let mut table = Table::new();
+    match action {
+        "overlay" => {
+            table["type"] = value("exec");
+            table["path"] = value(OVERLAY_PATH);
+            let mut overlay_args: Array = std::iter::once("--exit-on-stdin-close").collect();
+            overlay_args.extend(args.iter());
+            table["args"] = value(overlay_args);
+            table["single_instance"] = value(true);
+        }
+        "notify" => {
+            table["type"] = value("notify");
+        }
+        path => {
+            table["type"] = value("exec");
+            table["path"] = value(path);
+            if !args.is_empty() {
+                table["args"] = value(args.iter().collect::<Array>());
+            }
+        }
+    }
+    table
+}
+
+/// The `[[rules]]` array, created if the config has none yet
+pub fn rules(document: &mut DocumentMut) -> Result<&mut ArrayOfTables, Box<dyn std::error::Error + Send + Sync>> {
+    document.entry("rules")
+        .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| "`rules` isn't written as [[rules]] tables; edit it by hand".into())
+}
+
+/// The table of a rule given by `name` or `rule #N`, or of the `[[groups]]` entry with that name
+fn table_of<'a>(document: &'a mut DocumentMut, name: &str, group: bool) -> Result<&'a mut Table, Box<dyn std::error::Error + Send + Sync>> {
+    if !group {
+        let index = find(document, name)?;
+        return Ok(rules(document)?.get_mut(index).expect("found above"));
+    }
+    let groups = document.get_mut("groups")
+        .and_then(Item::as_array_of_tables_mut)
+        .ok_or("The config has no [[groups]] tables")?;
+    let names: Vec<String> = groups.iter()
+        .filter_map(|table| table.get("name").and_then(Item::as_str).map(str::to_string))
+        .collect();
+    groups.iter_mut()
+        .find(|table| table.get("name").and_then(Item::as_str) == Some(name))
+        .ok_or_else(|| format!("No group named '{}'; the groups are: {}", name, names.join(", ")).into())
+}
+
+/// Position of a rule given by `name` or `rule #N`
+fn find(document: &DocumentMut, rule: &str) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let tables = document.get("rules")
+        .and_then(Item::as_array_of_tables)
+        .ok_or("The config has no [[rules]] tables")?;
+    let mut names = Vec::new();
+    for (index, table) in tables.iter().enumerate() {
+        let name = table.get("name")
+            .and_then(Item::as_str)
+            .map_or_else(|| format!("rule #{}", index + 1), str::to_string);
+        if name == rule {
+            return Ok(index);
+        }
+        names.push(name);
+    }
+    Err(format!("No rule named '{}'; the rules are: {}", rule, names.join(", ")).into())
+}
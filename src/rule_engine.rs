@@ -1,91 +1,548 @@
-use crate::config::{Config, Action};
-use globset::{Glob, GlobSetBuilder, GlobSet};
+use crate::command_line::{normalize_exe, normalize_unicode, CommandLine};
+use crate::config::{Action, Commands, Config, OnlyIf, Rule, RuleMode};
+use crate::expr::{EvalContext, Expr, Value};
+use crate::mutes::MuteStore;
+use crate::enrichment;
+use crate::matcher::{self, CompiledMatcher, Matcher};
+use crate::platform::{ProbeData, ProcessEvent};
+use crate::schedule::{self, Schedule};
+use crate::scripting::{RuleScript, ScriptOutcome};
+use globset::{Glob, GlobBuilder, GlobSetBuilder, GlobSet};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
-use tracing::error;
+use tracing::{debug, error, warn};
+
+/// An action selected by a matching rule
+#[derive(Debug, Clone)]
+pub struct MatchedAction {
+    /// Rule `name`, or `rule #N` for unnamed rules
+    pub rule_name: String,
+    pub action: Action,
+    /// How long the command must still be running before the action starts; zero for right away
+    pub after: Duration,
+    /// CPU usage, in percent of a core, the command must sustain before the action starts
+    pub cpu_above: Option<u32>,
+    /// What else the command must be doing before the action starts
+    pub only_if: Option<OnlyIf>,
+    /// The rule's `with` values, filled into the action when it starts
+    pub with: BTreeMap<String, String>,
+}
+
+/// How a rule fared against an event
+#[derive(Debug, Clone)]
+pub struct RuleVerdict {
+    /// Rule `name`, or `rule #N` for unnamed rules
+    pub rule_name: String,
+    /// Position of the rule in the config file
+    pub index: usize,
+    /// Whether the rule has command globs, or matches on its other conditions alone
+    pub has_command: bool,
+    /// Number of actions it would start, escalations included, or why it didn't match
+    pub outcome: Result<usize, Miss>,
+    /// For rules whose command globs didn't match, the one that came closest
+    pub closest: Option<PartialMatch>,
+    /// Checks the rule got past, in the order they ran: "command", its conditions, "when" and
+    /// "script"
+    pub passed: Vec<&'static str>,
+}
+
+/// Why a rule didn't match
+#[derive(Debug, Clone)]
+pub enum Miss {
+    /// None of its command patterns matched
+    Command,
+    /// A `schedule`, `repo`, `branch`, `dirty` or `task` condition wasn't met
+    Condition(&'static str),
+    /// Its `when` expression was false
+    When,
+    WhenFailed(String),
+    /// Its script vetoed the match
+    Vetoed,
+    ScriptFailed(String),
+    /// It matched, but is muted
+    Muted,
+    /// It matched, but its actions started less than its `cooldown` ago
+    Cooldown,
+    /// It's turned off with `enabled = false`
+    Disabled,
+}
+
+impl std::fmt::Display for Miss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Miss::Command => write!(f, "no command pattern matched"),
+            Miss::Condition(condition) => write!(f, "`{}` condition not met", condition),
+            Miss::When => write!(f, "`when` is false"),
+            Miss::WhenFailed(e) => write!(f, "`when` failed: {}", e),
+            Miss::Vetoed => write!(f, "vetoed by its script"),
+            Miss::ScriptFailed(e) => write!(f, "script failed: {}", e),
+            Miss::Muted => write!(f, "muted"),
+            Miss::Cooldown => write!(f, "cooling down"),
+            Miss::Disabled => write!(f, "turned off"),
+        }
+    }
+}
+
+/// How much of a command glob matches a command: the longest start of the pattern that, followed
+/// by `*`, matches it
+#[derive(Debug, Clone)]
+pub struct PartialMatch {
+    pub pattern: String,
+    /// Length in bytes of the start of `pattern` that matches
+    pub matched: usize,
+}
+
+impl PartialMatch {
+    pub fn of(pattern: &str, command: &str, case_insensitive: bool) -> Self {
+        let pattern = normalize_unicode(pattern);
+        // Starts that cut a `[...]` or `{...}` in half aren't valid globs and are skipped
+        let matched = (1..=pattern.len()).rev()
+            .filter(|&end| pattern.is_char_boundary(end))
+            .filter_map(|end| Some((end, command_glob(&format!("{}*", &pattern[..end]), case_insensitive).ok()?)))
+            .find(|(_, glob)| glob.compile_matcher().is_match(command))
+            .map_or(0, |(end, _)| end);
+        Self { pattern, matched }
+    }
+
+    /// The command glob of a rule that comes closest to matching a command
+    pub fn closest(rule: &Rule, command: &str) -> Option<Self> {
+        rule.command_globs().into_iter()
+            .map(|pattern| Self::of(pattern, command, rule.case_insensitive))
+            .max_by(|a, b| a.ratio().total_cmp(&b.ratio()))
+    }
+
+    /// Share of the pattern that matches
+    pub fn ratio(&self) -> f64 {
+        if self.pattern.is_empty() {
+            return 0.0;
+        }
+        self.matched as f64 / self.pattern.len() as f64
+    }
+}
 
 pub struct RuleEngine {
     cached_glob_data: Arc<RwLock<Option<CachedGlobData>>>,
+    mutes: MuteStore,
+    /// What the probe reports, to warn about rules depending on more
+    probe_data: std::sync::Mutex<ProbeData>,
+    /// Matchers rules can pick with `matcher`, besides globs
+    matchers: std::sync::RwLock<HashMap<String, Arc<dyn Matcher>>>,
+    /// When the actions of each rule last started
+    last_started: std::sync::Mutex<HashMap<String, Instant>>,
 }
 
 struct CachedGlobData {
     config_hash: u64,
     glob_set: GlobSet,
-    rule_actions: Vec<Vec<Action>>,
+    /// Compiled rule for each pattern in the glob set
+    pattern_rules: Vec<usize>,
+    /// Hash of the command globs of the compiled rules, in order; the glob set is kept across
+    /// reloads while it stays the same
+    patterns_hash: u64,
+    /// Compiled rules by the program names of their `exe`, lowercase for rules ignoring case
+    exe_rules: HashMap<String, Vec<usize>>,
+    exe_rules_nocase: HashMap<String, Vec<usize>>,
+    rules: Vec<CompiledRule>,
+}
+
+#[derive(Clone)]
+struct CompiledRule {
+    /// Position of the rule in the config file
+    index: usize,
+    name: String,
+    /// `Rule::content_hash` of the rule it was compiled from, so unchanged rules are reused
+    content_hash: u64,
+    /// Whether the rule has command patterns; rules without them match on their other conditions
+    has_patterns: bool,
+    /// Matches the command patterns when they aren't globs
+    matcher: Option<Arc<dyn CompiledMatcher>>,
+    /// Program names of `exe`, normalized like `CommandLine::exe`
+    exe: Vec<String>,
+    /// Arguments of `args_contains`
+    args_contains: Vec<String>,
+    case_insensitive: bool,
+    /// Patterns for the `remote` or `repo` tag
+    repo: Option<GlobSet>,
+    /// Patterns for the `branch` tag
+    branch: Option<GlobSet>,
+    dirty: Option<bool>,
+    /// Patterns for the `task` tag
+    task: Option<GlobSet>,
+    /// Matches `stalled` events instead of command starts
+    stalled: bool,
+    /// Matches rebuild cycles instead of command starts
+    session: bool,
+    /// Times of the week the rule matches in
+    schedule: Option<Schedule>,
+    enabled: bool,
+    when: Option<Expr>,
+    script: Option<RuleScript>,
+    actions: Vec<Action>,
+    /// Escalation tiers: actions started once the command has run for the delay
+    escalations: Vec<(Duration, Vec<Action>)>,
+    cpu_above: Option<u32>,
+    only_if: Option<OnlyIf>,
+    /// Shortest delay of any of its actions
+    min_duration: Duration,
+    cooldown: Option<Duration>,
+    with: BTreeMap<String, String>,
 }
 
 impl RuleEngine {
-    pub fn new() -> Self {
+    pub fn new(mutes: MuteStore) -> Self {
         Self {
             cached_glob_data: Arc::new(RwLock::new(None)),
+            mutes,
+            probe_data: std::sync::Mutex::new(ProbeData::ALL),
+            matchers: std::sync::RwLock::new(matcher::builtin().into_iter().collect()),
+            last_started: std::sync::Mutex::new(HashMap::new()),
         }
     }
 
-    pub async fn match_command(&self, command: &str, config: &Config) -> Vec<Action> {
-        // Check if we need to rebuild the cache
-        let config_hash = self.calculate_config_hash(config);
-        
-        {
-            let cached_data = self.cached_glob_data.read().await;
-            if let Some(ref data) = *cached_data {
-                if data.config_hash == config_hash {
-                    // Cache hit - use existing glob set
-                    let matches = data.glob_set.matches(command);
-                    let mut all_actions = Vec::new();
-                    for &match_idx in matches.iter() {
-                        all_actions.extend(data.rule_actions[match_idx].iter().cloned());
+    /// Makes a matcher available to rules as `matcher = "custom:<name>"`; rules using it are
+    /// compiled again
+    #[allow(dead_code)] // For matchers built in elsewhere, e.g. a classifier of long commands
+    pub async fn register_matcher(&self, name: &str, matcher: Arc<dyn Matcher>) {
+        self.matchers.write().unwrap().insert(format!("{}{}", matcher::CUSTOM_PREFIX, name), matcher);
+        *self.cached_glob_data.write().await = None;
+    }
+
+    /// Tells the engine what the probe reports and compiles the rules, warning about the ones
+    /// depending on data it lacks
+    pub async fn set_probe_data(&self, data: ProbeData, config: &Config) {
+        *self.probe_data.lock().unwrap() = data;
+        *self.cached_glob_data.write().await = None;
+        self.refresh_cache(config).await;
+    }
+
+    /// Rules silenced for a while, by the CLI or notification buttons
+    pub fn mutes(&self) -> &MuteStore {
+        &self.mutes
+    }
+
+    /// Actions of the rules matching an event, leaving out muted rules, and how every rule fared
+    pub async fn decide(&self, event: &ProcessEvent, config: &Config) -> (Vec<MatchedAction>, Vec<RuleVerdict>) {
+        self.refresh_cache(config).await;
+        let cached_data = self.cached_glob_data.read().await;
+        let Some(ref data) = *cached_data else {
+            return (Vec::new(), Vec::new());
+        };
+        let mutes = self.mutes.current();
+        let matched = Self::glob_matches(data, event);
+        // Only parsed and built if some rule needs them
+        let mut command_line: Option<CommandLine> = None;
+        let exe_matched = Self::exe_matches(data, event, &mut command_line);
+        let mut context: Option<EvalContext> = None;
+
+        let mut all_actions = Vec::new();
+        let mut verdicts = Vec::with_capacity(data.rules.len());
+        for ((rule, matched), exe_matched) in data.rules.iter().zip(matched).zip(exe_matched) {
+            let mut passed = Vec::new();
+            // Scripts and `when` expressions of rules that are off don't run at all
+            let outcome = if !rule.enabled {
+                Err(Miss::Disabled)
+            } else {
+                match rule.evaluate(matched, exe_matched, event, &mut command_line, &mut context, &mut passed).await {
+                    Ok(_) if mutes.is_muted(&rule.name) => {
+                        debug!("Skipping muted rule '{}'", rule.name);
+                        Err(Miss::Muted)
+                    }
+                    Ok(_) if self.cooling_down(rule) => {
+                        debug!("Skipping rule '{}' during its cooldown", rule.name);
+                        Err(Miss::Cooldown)
                     }
-                    return all_actions;
+                    Ok(actions) => {
+                        let before = all_actions.len();
+                        all_actions.extend(rule.matched_actions(actions));
+                        Ok(all_actions.len() - before)
+                    }
+                    Err(miss) => Err(miss),
                 }
+            };
+            verdicts.push(RuleVerdict {
+                rule_name: rule.name.clone(),
+                index: rule.index,
+                has_command: rule.has_patterns || !rule.exe.is_empty(),
+                outcome,
+                closest: None,
+                passed,
+            });
+        }
+        (all_actions, verdicts)
+    }
+
+    /// Starts the cooldown of the rules whose actions are starting
+    pub fn actions_started(&self, actions: &[MatchedAction]) {
+        let now = Instant::now();
+        let mut last_started = self.last_started.lock().unwrap();
+        for action in actions {
+            last_started.insert(action.rule_name.clone(), now);
+        }
+    }
+
+    /// Whether a rule's actions started less than its `cooldown` ago
+    fn cooling_down(&self, rule: &CompiledRule) -> bool {
+        let Some(cooldown) = rule.cooldown else {
+            return false;
+        };
+        self.last_started.lock().unwrap().get(&rule.name)
+            .is_some_and(|started| started.elapsed() < cooldown)
+    }
+
+    /// How every rule fares against an event, muted ones included, without running anything but
+    /// `when` expressions and scripts, with the closest command glob of rules none matched; for
+    /// `viberot-service watch`
+    pub async fn explain(&self, event: &ProcessEvent, config: &Config) -> Vec<RuleVerdict> {
+        let (_, mut verdicts) = self.decide(event, config).await;
+        for verdict in &mut verdicts {
+            if matches!(verdict.outcome, Err(Miss::Command)) {
+                verdict.closest = config.rules.get(verdict.index)
+                    .and_then(|rule| PartialMatch::closest(rule, &event.command));
             }
         }
+        verdicts
+    }
 
-        // Cache miss - rebuild glob set
-        self.rebuild_cache(config, config_hash).await;
-        
-        // Try matching again with the new cache
+    /// Every action of a rule, escalation tiers included, regardless of what it matches; `None`
+    /// if no rule has that name
+    #[cfg_attr(not(all(target_os = "linux", feature = "dbus")), allow(dead_code))] // Only D-Bus triggers rules by hand
+    pub async fn actions_of(&self, rule_name: &str, config: &Config) -> Option<Vec<MatchedAction>> {
+        self.refresh_cache(config).await;
         let cached_data = self.cached_glob_data.read().await;
-        if let Some(ref data) = *cached_data {
-            let matches = data.glob_set.matches(command);
-            let mut all_actions = Vec::new();
-            for &match_idx in matches.iter() {
-                all_actions.extend(data.rule_actions[match_idx].iter().cloned());
+        let rule = cached_data.as_ref()?.rules.iter().find(|rule| rule.name == rule_name)?;
+        Some(rule.matched_actions(rule.actions.clone()).collect())
+    }
+
+    /// Rebuilds the compiled rules if the config changed since they were built
+    async fn refresh_cache(&self, config: &Config) {
+        let config_hash = self.calculate_config_hash(config);
+        {
+            let cached_data = self.cached_glob_data.read().await;
+            if cached_data.as_ref().is_some_and(|data| data.config_hash == config_hash) {
+                return;
             }
-            return all_actions;
         }
+        self.rebuild_cache(config, config_hash).await;
+    }
+
+    /// Whether some command glob of each rule matches
+    fn glob_matches(data: &CachedGlobData, event: &ProcessEvent) -> Vec<bool> {
+        let mut matched = vec![false; data.rules.len()];
+        for match_idx in data.glob_set.matches(&event.command) {
+            matched[data.pattern_rules[match_idx]] = true;
+        }
+        matched
+    }
+
+    /// Whether the program the command runs is one of the `exe` of each rule, looked up by name
+    fn exe_matches(data: &CachedGlobData, event: &ProcessEvent, command_line: &mut Option<CommandLine>) -> Vec<bool> {
+        let mut matched = vec![false; data.rules.len()];
+        if data.exe_rules.is_empty() && data.exe_rules_nocase.is_empty() {
+            return matched;
+        }
+        let exe = &command_line.get_or_insert_with(|| CommandLine::parse(&event.command)).exe;
+        let exact = data.exe_rules.get(exe);
+        let nocase = data.exe_rules_nocase.get(&exe.to_lowercase());
+        for rule in exact.into_iter().chain(nocase).flatten() {
+            matched[*rule] = true;
+        }
+        matched
+    }
+
+    /// Built-in variables of `when` expressions, set by `build_context`; enrichment tags come on top
+    pub const VARIABLES: &'static [&'static str] = &[
+        "cmd", "args", "cwd", "probe", "probe_version", "host", "session", "duration_estimate",
+    ];
+
+    /// Variables exposed to `when` expressions
+    pub fn build_context(event: &ProcessEvent) -> EvalContext {
+        let command_line = CommandLine::parse(&event.command);
+        let args: Vec<Value> = command_line.args.iter().map(|a| Value::from(a.as_str())).collect();
+
+        let mut cmd = BTreeMap::new();
+        cmd.insert("exe".to_string(), Value::from(command_line.exe.as_str()));
+        cmd.insert("line".to_string(), Value::from(event.command.as_str()));
+        cmd.insert("args".to_string(), Value::List(args.clone()));
 
-        Vec::new()
+        let mut context = EvalContext::new();
+        context.insert("cmd".to_string(), Value::Map(cmd));
+        context.insert("args".to_string(), Value::List(args));
+        context.insert("cwd".to_string(), Value::from(event.working_directory.as_deref()));
+        context.insert("probe".to_string(), Value::Str(format!("{:?}", event.probe_source)));
+        context.insert("probe_version".to_string(), Value::from(event.provenance.probe_version.as_str()));
+        context.insert("host".to_string(), Value::from(event.provenance.hostname.as_str()));
+        context.insert("session".to_string(), Value::from(event.provenance.session_id.as_deref()));
+        context.insert("duration_estimate".to_string(),
+                       event.expected_duration.map(|d| Value::Number(d as f64)).unwrap_or(Value::Null));
+        // Enrichment tags (`repo`, `branch`, `category`, ...) as variables, never shadowing built-ins.
+        // Flags like `dirty` become booleans so they can be tested directly.
+        for (name, value) in &event.tags {
+            let value = match value.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                value => Value::from(value),
+            };
+            context.entry(name.clone()).or_insert(value);
+        }
+        context
     }
 
+    /// Compiles the rules of a new config, reusing the ones that didn't change since the last
+    /// build, and the glob set if no command glob did
     async fn rebuild_cache(&self, config: &Config, config_hash: u64) {
-        let mut builder = GlobSetBuilder::new();
-        let mut rule_actions = Vec::new();
-        
-        for rule in config.rules.iter() {
-            let commands = rule.command.as_vec();
+        let previous = self.cached_glob_data.read().await;
+        let reusable: HashMap<u64, &CompiledRule> = previous.iter()
+            .flat_map(|data| &data.rules)
+            .map(|rule| (rule.content_hash, rule))
+            .collect();
+        let mut rules = Vec::new();
+        // Command globs of the compiled rules, with whether they ignore case
+        let mut rule_patterns: Vec<(Vec<&String>, bool)> = Vec::new();
+
+        for (rule_idx, rule) in config.rules.iter().enumerate() {
+            let commands = rule.command_globs();
+            let content_hash = rule.content_hash();
+            if let Some(compiled) = reusable.get(&content_hash) {
+                let mut compiled = (*compiled).clone();
+                compiled.index = rule_idx;
+                compiled.name = rule.display_name(rule_idx);
+                rules.push(compiled);
+                rule_patterns.push((commands, rule.case_insensitive));
+                continue;
+            }
             let actions = rule.action.as_vec().into_iter().cloned().collect::<Vec<_>>();
-            
-            for command in commands {
-                match Glob::new(command) {
-                    Ok(glob) => {
-                        builder.add(glob);
-                        rule_actions.push(actions.clone());
-                    }
-                    Err(e) => {
-                        error!("Invalid glob pattern '{}': {}", command, e);
+            let missing = missing_data(rule, &self.probe_data.lock().unwrap());
+            if !missing.is_empty() {
+                warn!("Rule '{}' is degraded: it depends on {}, which the probe doesn't report, so it may never match",
+                      rule.display_name(rule_idx), missing.join(" and "));
+            }
+
+            // Expressions are validated at config load, so failures here are unexpected
+            let when = match rule.when.as_deref().map(Expr::parse) {
+                Some(Ok(expr)) => Some(expr),
+                Some(Err(e)) => {
+                    error!("Invalid `when` expression in rule #{} {}", rule_idx + 1, e);
+                    continue;
+                }
+                None => None,
+            };
+
+            let escalations: Vec<(Duration, Vec<Action>)> = rule.escalate.iter()
+                .map(|e| (e.after.0, e.action.as_vec().into_iter().cloned().collect()))
+                .collect();
+
+            // Patterns are validated at config load as well
+            let (repo, branch) = match (compile_globs(rule.repo.as_ref()), compile_globs(rule.branch.as_ref())) {
+                (Ok(repo), Ok(branch)) => (repo, branch),
+                (Err(e), _) | (_, Err(e)) => {
+                    error!("Invalid repository pattern in rule #{}: {}", rule_idx + 1, e);
+                    continue;
+                }
+            };
+            let task = match compile_globs(rule.task.as_ref()) {
+                Ok(task) => task,
+                Err(e) => {
+                    error!("Invalid task pattern in rule #{}: {}", rule_idx + 1, e);
+                    continue;
+                }
+            };
+            let schedule = match rule.schedule.as_ref().map(|entries| Schedule::parse(&entries.as_vec())) {
+                Some(Ok(schedule)) => Some(schedule),
+                Some(Err(e)) => {
+                    error!("Invalid schedule in rule #{}: {}", rule_idx + 1, e);
+                    continue;
+                }
+                None => None,
+            };
+            let matcher = match rule.matcher.as_deref().filter(|name| *name != "glob") {
+                Some(name) => {
+                    let Some(found) = self.matchers.read().unwrap().get(name).cloned() else {
+                        error!("Rule #{} uses `matcher = \"{}\"`, which isn't registered; skipping it", rule_idx + 1, name);
+                        continue;
+                    };
+                    match found.compile(rule) {
+                        Ok(compiled) => Some(Arc::from(compiled)),
+                        Err(e) => {
+                            error!("Rule #{} can't be compiled by its matcher: {}", rule_idx + 1, e);
+                            continue;
+                        }
                     }
                 }
+                None => None,
+            };
+
+            rules.push(CompiledRule {
+                index: rule_idx,
+                name: rule.display_name(rule_idx),
+                content_hash,
+                has_patterns: rule.command.as_ref().is_some_and(|c| !c.as_vec().is_empty()),
+                matcher,
+                exe: rule.exe.iter().flat_map(|exe| exe.as_vec())
+                    .map(|exe| normalize_exe(&normalize_unicode(exe)))
+                    .collect(),
+                args_contains: rule.args_contains.iter().map(|arg| normalize_unicode(arg)).collect(),
+                case_insensitive: rule.case_insensitive,
+                repo,
+                branch,
+                dirty: rule.dirty,
+                task,
+                stalled: rule.stalled,
+                session: rule.mode == RuleMode::Session,
+                schedule,
+                enabled: rule.enabled,
+                when,
+                script: rule.script.as_deref().map(RuleScript::new),
+                actions,
+                escalations,
+                cpu_above: rule.only_if_cpu_above,
+                only_if: rule.only_if,
+                min_duration: rule.min_duration.map_or(Duration::ZERO, |duration| duration.0),
+                cooldown: rule.cooldown.map(|cooldown| cooldown.0),
+                with: rule.with.clone(),
+            });
+            rule_patterns.push((commands, rule.case_insensitive));
+        }
+
+        let patterns_hash = Self::hash(&rule_patterns);
+        let reused = rules.iter().filter(|rule| reusable.contains_key(&rule.content_hash)).count();
+        let kept_globs = previous.as_ref()
+            .filter(|data| data.patterns_hash == patterns_hash)
+            .map(|data| (data.glob_set.clone(), data.pattern_rules.clone()));
+        if previous.is_some() {
+            debug!("Recompiled {} of {} rule(s), {} the command globs",
+                   rules.len() - reused, rules.len(), if kept_globs.is_some() { "kept" } else { "rebuilt" });
+        }
+        drop(previous);
+
+        let mut exe_rules: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut exe_rules_nocase: HashMap<String, Vec<usize>> = HashMap::new();
+        for (position, rule) in rules.iter().enumerate() {
+            for exe in &rule.exe {
+                match rule.case_insensitive {
+                    true => exe_rules_nocase.entry(exe.to_lowercase()).or_default().push(position),
+                    false => exe_rules.entry(exe.clone()).or_default().push(position),
+                }
             }
         }
 
-        match builder.build() {
-            Ok(glob_set) => {
+        let globs = match kept_globs {
+            Some(globs) => Ok(globs),
+            None => Self::build_glob_set(&rule_patterns),
+        };
+        match globs {
+            Ok((glob_set, pattern_rules)) => {
                 let new_data = CachedGlobData {
                     config_hash,
                     glob_set,
-                    rule_actions,
+                    pattern_rules,
+                    patterns_hash,
+                    exe_rules,
+                    exe_rules_nocase,
+                    rules,
                 };
-                
+
                 let mut cached_data = self.cached_glob_data.write().await;
                 *cached_data = Some(new_data);
             }
@@ -95,12 +552,36 @@ impl RuleEngine {
         }
     }
 
+    /// One glob set of the command globs of all rules, and the rule each pattern belongs to
+    fn build_glob_set(rule_patterns: &[(Vec<&String>, bool)]) -> Result<(GlobSet, Vec<usize>), globset::Error> {
+        let mut builder = GlobSetBuilder::new();
+        let mut pattern_rules = Vec::new();
+        for (rule, (commands, case_insensitive)) in rule_patterns.iter().enumerate() {
+            for command in commands {
+                match command_glob(command, *case_insensitive) {
+                    Ok(glob) => {
+                        builder.add(glob);
+                        pattern_rules.push(rule);
+                    }
+                    Err(e) => {
+                        error!("Invalid glob pattern '{}': {}", command, e);
+                    }
+                }
+            }
+        }
+        Ok((builder.build()?, pattern_rules))
+    }
+
     fn calculate_config_hash(&self, config: &Config) -> u64 {
+        Self::hash(config)
+    }
+
+    fn hash(value: &impl std::hash::Hash) -> u64 {
         use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-        
+        use std::hash::Hasher;
+
         let mut hasher = DefaultHasher::new();
-        config.hash(&mut hasher);
+        value.hash(&mut hasher);
         hasher.finish()
     }
 
@@ -110,4 +591,212 @@ impl RuleEngine {
         let mut cached_data = self.cached_glob_data.write().await;
         *cached_data = None;
     }
-}
\ No newline at end of file
+}
+
+impl CompiledRule {
+    /// Actions of the rule if it matches, given whether one of its command globs did (for rules
+    /// matching globs) and whether the command runs one of its `exe`, noting the checks it gets
+    /// past in `passed`
+    async fn evaluate(
+        &self,
+        glob_matched: bool,
+        exe_matched: bool,
+        event: &ProcessEvent,
+        command_line: &mut Option<CommandLine>,
+        context: &mut Option<EvalContext>,
+        passed: &mut Vec<&'static str>,
+    ) -> Result<Vec<Action>, Miss> {
+        let command_matched = match self.matcher {
+            Some(ref matcher) => matcher.matches(event),
+            None => glob_matched,
+        };
+        if (self.has_patterns && !command_matched) || (!self.exe.is_empty() && !exe_matched) {
+            return Err(Miss::Command);
+        }
+        if self.has_patterns {
+            passed.push("command");
+        }
+        if !self.exe.is_empty() {
+            passed.push("exe");
+        }
+        if !self.args_contains.is_empty() {
+            let args = &command_line.get_or_insert_with(|| CommandLine::parse(&event.command)).args;
+            let has_arg = |wanted: &String| args.iter().any(|arg| match self.case_insensitive {
+                true => arg.to_lowercase() == wanted.to_lowercase(),
+                false => arg == wanted,
+            });
+            if !self.args_contains.iter().all(has_arg) {
+                return Err(Miss::Condition("args_contains"));
+            }
+            passed.push("args_contains");
+        }
+        let failed = self.failed_condition(event);
+        let conditions = self.conditions();
+        // A command stall or rebuild fails `stalled` or `mode` even for rules not setting them,
+        // before anything else
+        let met = failed.map_or(conditions.len(), |failed| conditions.iter().position(|c| *c == failed).unwrap_or(0));
+        passed.extend(&conditions[..met]);
+        if let Some(condition) = failed {
+            return Err(Miss::Condition(condition));
+        }
+        if let Some(ref when) = self.when {
+            let context = context.get_or_insert_with(|| RuleEngine::build_context(event));
+            match when.eval(context) {
+                Ok(true) => passed.push("when"),
+                Ok(false) => return Err(Miss::When),
+                Err(e) => {
+                    debug!("Rule #{} `when` expression failed {}", self.index + 1, e);
+                    return Err(Miss::WhenFailed(e.to_string()));
+                }
+            }
+        }
+        match self.script {
+            Some(ref script) => match script.run(event, &self.actions).await {
+                Ok(ScriptOutcome::Keep) => {
+                    passed.push("script");
+                    Ok(self.actions.clone())
+                }
+                Ok(ScriptOutcome::Veto) => {
+                    debug!("Rule #{} script vetoed the match", self.index + 1);
+                    Err(Miss::Vetoed)
+                }
+                Ok(ScriptOutcome::Replace(actions)) => {
+                    passed.push("script");
+                    Ok(actions)
+                }
+                Err(e) => {
+                    error!("Rule #{} script failed: {}", self.index + 1, e);
+                    Err(Miss::ScriptFailed(e.to_string()))
+                }
+            },
+            None => Ok(self.actions.clone()),
+        }
+    }
+
+    /// The immediate actions of a match followed by the escalation tiers
+    fn matched_actions(&self, actions: Vec<Action>) -> impl Iterator<Item = MatchedAction> + '_ {
+        let immediate = actions.into_iter().map(|action| MatchedAction {
+            rule_name: self.name.clone(),
+            action,
+            after: self.min_duration,
+            cpu_above: self.cpu_above,
+            only_if: self.only_if,
+            with: self.with.clone(),
+        });
+        let escalations = self.escalations.iter().flat_map(|(after, actions)| {
+            actions.iter().map(|action| MatchedAction {
+                rule_name: self.name.clone(),
+                action: action.clone(),
+                after: (*after).max(self.min_duration),
+                cpu_above: self.cpu_above,
+                only_if: self.only_if,
+                with: self.with.clone(),
+            })
+        });
+        immediate.chain(escalations)
+    }
+
+    /// The `stalled`, `mode`, `schedule`, `repo`, `branch`, `dirty` and `task` conditions the rule
+    /// sets, in the order they're checked
+    fn conditions(&self) -> Vec<&'static str> {
+        [
+            ("stalled", self.stalled),
+            ("mode", self.session),
+            ("schedule", self.schedule.is_some()),
+            ("repo", self.repo.is_some()),
+            ("branch", self.branch.is_some()),
+            ("dirty", self.dirty.is_some()),
+            ("task", self.task.is_some()),
+        ].into_iter().filter(|(_, set)| *set).map(|(name, _)| name).collect()
+    }
+
+    /// The first of the `stalled`, `mode`, `schedule`, `repo`, `branch`, `dirty` and `task`
+    /// conditions the event doesn't meet; commands outside a repository never meet repository
+    /// conditions
+    fn failed_condition(&self, event: &ProcessEvent) -> Option<&'static str> {
+        if self.stalled != event.is_stall() {
+            return Some("stalled");
+        }
+        if self.session != event.is_cycle() {
+            return Some("mode");
+        }
+        if self.schedule.as_ref().is_some_and(|schedule| !schedule.contains(schedule::local_time())) {
+            return Some("schedule");
+        }
+        let tag = |name: &str| event.tags.get(name).map(String::as_str);
+        if let Some(ref repo) = self.repo {
+            let candidates = [tag("remote"), tag("repo")];
+            if !candidates.into_iter().flatten().any(|candidate| repo.is_match(candidate)) {
+                return Some("repo");
+            }
+        }
+        if let Some(ref branch) = self.branch {
+            if !tag("branch").is_some_and(|candidate| branch.is_match(candidate)) {
+                return Some("branch");
+            }
+        }
+        if let Some(dirty) = self.dirty {
+            if tag("dirty") != Some(if dirty { "true" } else { "false" }) {
+                return Some("dirty");
+            }
+        }
+        if let Some(ref task) = self.task {
+            if !tag("task").is_some_and(|candidate| task.is_match(candidate)) {
+                return Some("task");
+            }
+        }
+        None
+    }
+}
+
+/// Compiles a command glob the way rules match commands: in Unicode NFC form like the commands
+/// themselves, and ignoring case if the rule asks to
+pub fn command_glob(pattern: &str, case_insensitive: bool) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(&normalize_unicode(pattern)).case_insensitive(case_insensitive).build()
+}
+
+fn compile_globs(patterns: Option<&Commands>) -> Result<Option<GlobSet>, globset::Error> {
+    let Some(patterns) = patterns else {
+        return Ok(None);
+    };
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns.as_vec() {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build().map(Some)
+}
+
+/// What a rule depends on that a probe doesn't report, e.g. "working directories (for `cwd`)"
+pub fn missing_data(rule: &Rule, data: &ProbeData) -> Vec<String> {
+    // `when`, and the command patterns of rules matching them as expressions
+    let expr_commands = if rule.matcher.as_deref() == Some("expr") { rule.command.as_ref().map(|c| c.as_vec()) } else { None };
+    let exprs: Vec<Expr> = rule.when.iter().chain(expr_commands.unwrap_or_default())
+        .filter_map(|text| Expr::parse(text).ok())
+        .collect();
+    let used: Vec<&str> = exprs.iter().flat_map(Expr::variables).collect();
+    let mut missing = Vec::new();
+    if !data.working_directory {
+        let mut needs: Vec<&str> = used.iter().copied()
+            .filter(|name| *name == "cwd" || enrichment::CWD_TAGS.contains(name))
+            .collect();
+        for (condition, set) in [("repo", rule.repo.is_some()), ("branch", rule.branch.is_some()), ("dirty", rule.dirty.is_some())] {
+            if set && !needs.contains(&condition) {
+                needs.push(condition);
+            }
+        }
+        if !needs.is_empty() {
+            let needs: Vec<String> = needs.iter().map(|name| format!("`{}`", name)).collect();
+            missing.push(format!("working directories (for {})", needs.join(", ")));
+        }
+    }
+    if !data.stalls && rule.stalled {
+        missing.push("stalled events (for `stalled = true`)".to_string());
+    }
+    if !data.cycles && rule.mode == RuleMode::Session {
+        missing.push("rebuild cycles (for `mode = \"session\"`)".to_string());
+    }
+    if !data.parent_app && used.contains(&"parent_app") {
+        missing.push("parent apps (for `parent_app`)".to_string());
+    }
+    missing
+}
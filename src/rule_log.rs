@@ -0,0 +1,100 @@
+// Per-rule logging
+// A rule's `log` says how much is logged about the commands it matches: "info" (default), "debug",
+// or "off" for chatty rules like catch-all experiments, so they don't flood the log file. Logs about
+// a matched command go through `rule_log::info!` and `rule_log::debug!`, which follow the loudest
+// `log` of the rules it matched until it ends. Rules matching more than `noisy_rule_threshold`
+// commands a minute are warned about, at most every 10 minutes, unless their `log` is "off".
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::config::RuleLog;
+
+/// `log` of matched commands whose rules don't all log at the default level, by PID
+static LEVELS: Mutex<BTreeMap<u32, RuleLog>> = Mutex::new(BTreeMap::new());
+
+/// Logs at `info`, or as the `log` of the rules the command with the given PID matched asks
+macro_rules! info {
+    ($pid:expr, $($arg:tt)+) => {
+        match $crate::rule_log::level($pid) {
+            $crate::config::RuleLog::Info => tracing::info!($($arg)+),
+            $crate::config::RuleLog::Debug => tracing::debug!($($arg)+),
+            $crate::config::RuleLog::Off => {}
+        }
+    };
+}
+pub(crate) use info;
+
+/// Logs at `debug`, unless the rules the command with the given PID matched have `log = "off"`
+macro_rules! debug {
+    ($pid:expr, $($arg:tt)+) => {
+        if $crate::rule_log::level($pid) != $crate::config::RuleLog::Off {
+            tracing::debug!($($arg)+);
+        }
+    };
+}
+pub(crate) use debug;
+
+/// Remembers the loudest `log` of the rules a command matched, until `command_ended`
+pub fn command_matched(pid: u32, levels: impl IntoIterator<Item = RuleLog>) {
+    let mut known = LEVELS.lock().unwrap();
+    match levels.into_iter().max().unwrap_or_default() {
+        RuleLog::Info => known.remove(&pid),
+        level => known.insert(pid, level),
+    };
+}
+
+pub fn command_ended(pid: u32) {
+    LEVELS.lock().unwrap().remove(&pid);
+}
+
+/// How a command is logged about; None, e.g. for prewarmed actions, is logged as usual
+pub fn level(pid: impl Into<Option<u32>>) -> RuleLog {
+    pid.into().and_then(|pid| LEVELS.lock().unwrap().get(&pid).copied()).unwrap_or_default()
+}
+
+/// How long matches are counted for `noisy_rule_threshold`
+const NOISE_WINDOW: Duration = Duration::from_secs(60);
+/// How often a noisy rule is warned about at most
+const NOISE_WARNING_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Warns about rules matching more than `noisy_rule_threshold` commands a minute
+#[derive(Default)]
+pub struct NoisyRules {
+    rules: Mutex<HashMap<String, Noise>>,
+}
+
+#[derive(Default)]
+struct Noise {
+    /// Matches in the last minute, at most one more than the threshold
+    matches: VecDeque<Instant>,
+    warned: Option<Instant>,
+}
+
+impl NoisyRules {
+    /// Counts a match of the rule, warning if that makes it noisy; 0 for `threshold` never warns
+    pub fn matched(&self, rule: &str, threshold: u32) {
+        if threshold == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut rules = self.rules.lock().unwrap();
+        let noise = rules.entry(rule.to_string()).or_default();
+        while noise.matches.front().is_some_and(|matched| now.duration_since(*matched) > NOISE_WINDOW) {
+            noise.matches.pop_front();
+        }
+        noise.matches.push_back(now);
+        if noise.matches.len() > threshold as usize + 1 {
+            noise.matches.pop_front();
+        }
+        if noise.matches.len() > threshold as usize
+            && noise.warned.is_none_or(|warned| now.duration_since(warned) >= NOISE_WARNING_INTERVAL)
+        {
+            warn!("Rule '{}' matched more than {} commands in the last minute; narrow its patterns, or set `log = \"off\"` on it to keep them out of the log",
+                  rule, threshold);
+            noise.warned = Some(now);
+        }
+    }
+}
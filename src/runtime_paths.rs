@@ -0,0 +1,305 @@
+// Runtime paths
+// Where VibeRot keeps what it writes and finds what it ships, so it runs the same from a checkout,
+// a package manager's prefix (Homebrew, WinGet) or a folder of its own:
+// - The data directory holds config.toml, the logs, stats, mutes, audit log and the discovery
+//   files clients find the sockets through: ~/.viberot, or `VIBEROT_PREFIX` if set.
+// - The install root holds the bundled actions, `${VIBEROT_HOME}` with `${VIBEROT_ACTIONS}` its
+//   actions/ directory. It's `VIBEROT_HOME` or `viberot_home` if set, `VIBEROT_PREFIX` if that has
+//   actions, the install the executable is part of, the checkout it was built in, or finally the
+//   data directory once `setup-actions` put actions there.
+// The executable finds its install from where it is, so packages work wherever they're put:
+//   <prefix>/bin/viberot-service with <prefix>/share/viberot/actions (Homebrew, Linux packages)
+//   <dir>/viberot-service.exe with <dir>/actions (WinGet, zip archives)
+// `VIBEROT_PREFIX` moves everything into one directory instead: the data, and actions/ beside it,
+// for portable installs or a second service next to the usual one.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::storage;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+const CONFIG_FILE: &str = "config.toml";
+const LOG_DIR: &str = "logs";
+const AUDIT_DIR: &str = "audit";
+const MEMES_DIR: &str = "memes";
+const ACTIONS_DIR: &str = "actions";
+
+/// Where everything VibeRot writes goes: `VIBEROT_PREFIX`, or ~/.viberot
+pub fn data_dir() -> Result<PathBuf, Error> {
+    if let Some(prefix) = env::var_os("VIBEROT_PREFIX").filter(|prefix| !prefix.is_empty()) {
+        return Ok(PathBuf::from(prefix));
+    }
+    let home_dir = dirs::home_dir()
+        .ok_or("Could not find home directory - this usually means the HOME environment variable is not set")?;
+    Ok(home_dir.join(".viberot"))
+}
+
+/// Whether `VIBEROT_PREFIX` points the data directory elsewhere, at a directory that may hold
+/// other things too
+pub fn has_prefix() -> bool {
+    env::var_os("VIBEROT_PREFIX").is_some_and(|prefix| !prefix.is_empty())
+}
+
+/// Everything the functions here hand out in the data directory, and what the storage backends
+/// keep there, for `uninstall`
+pub fn data_entries() -> Result<Vec<PathBuf>, Error> {
+    let config_file = entry(CONFIG_FILE)?;
+    let mut entries = vec![
+        config_backup_file(&config_file),
+        config_file,
+        entry(LOG_DIR)?,
+        entry(AUDIT_DIR)?,
+        entry(MEMES_DIR)?,
+        actions_dir()?,
+        sync_dir()?,
+        action_state_dir()?,
+        metrics_file()?,
+        usage_file()?,
+        reload_file()?,
+        mutes_file()?,
+        suspended_file()?,
+        probe_file()?,
+        socket_discovery_file()?,
+        websocket_discovery_file()?,
+        agent_discovery_file()?,
+    ];
+    #[cfg(unix)]
+    entries.extend([shell_integration_file()?, shell_instance_file()?, sandbox_hints_file()?]);
+    entries.extend(storage::default_files(&data_dir()?));
+    Ok(entries)
+}
+
+fn entry(name: &str) -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join(name))
+}
+
+/// A directory under the data directory, created if it isn't there
+fn created_dir(name: &str, what: &str) -> Result<PathBuf, Error> {
+    let path = entry(name)?;
+    if let Err(e) = std::fs::create_dir_all(&path) {
+        return Err(format!("Failed to create {} directory at {:?}: {}", what, path, e).into());
+    }
+    Ok(path)
+}
+
+/// The config a config import replaced
+pub fn config_backup_file(config_file: &Path) -> PathBuf {
+    config_file.with_extension("toml.bak")
+}
+
+pub fn config_file() -> Result<PathBuf, Error> {
+    let path = created_dir("", "config")?.join(CONFIG_FILE);
+    info!("Using config file: {:?}", path);
+    Ok(path)
+}
+
+pub fn log_dir() -> Result<PathBuf, Error> {
+    created_dir(LOG_DIR, "log")
+}
+
+pub fn audit_dir() -> Result<PathBuf, Error> {
+    created_dir(AUDIT_DIR, "audit")
+}
+
+/// Images and videos the waiting room page shows, unless `[waiting_room] memes_dir` says otherwise
+pub fn memes_dir() -> Result<PathBuf, Error> {
+    created_dir(MEMES_DIR, "memes")
+}
+
+/// Where `setup-actions` puts the actions when VibeRot wasn't installed with them
+pub fn actions_dir() -> Result<PathBuf, Error> {
+    entry(ACTIONS_DIR)
+}
+
+/// The clone of the repository the config is synced through
+pub fn sync_dir() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join("sync"))
+}
+
+/// `VIBEROT_STATE_DIR`s of actions
+pub fn action_state_dir() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join("state"))
+}
+
+pub fn metrics_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join("metrics.json"))
+}
+
+pub fn usage_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join("usage.json"))
+}
+
+pub fn reload_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join("last_reload.json"))
+}
+
+pub fn mutes_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join("mutes.json"))
+}
+
+pub fn suspended_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join("suspended.json"))
+}
+
+pub fn probe_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join("probe.json"))
+}
+
+/// The shell hooks `setup` installs
+#[cfg(unix)]
+pub fn shell_integration_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join("shell_integration.sh"))
+}
+
+/// The shell probe's sockets, one per line, for the shell hooks and `wrap`
+pub fn socket_discovery_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join(".socket"))
+}
+
+/// Which run of the service the shell probe is, so the hooks notice it restarted
+#[cfg(unix)]
+pub fn shell_instance_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join(".shell-instance"))
+}
+
+/// What to change when a sandboxed shell can't reach the service, in the service's language
+#[cfg(unix)]
+pub fn sandbox_hints_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join(".sandbox-hints"))
+}
+
+/// The URL of `[websocket_source]`, token included
+pub fn websocket_discovery_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join(".websocket"))
+}
+
+/// Where `[agent_hooks]` listens, for the hook script
+pub fn agent_discovery_file() -> Result<PathBuf, Error> {
+    Ok(data_dir()?.join(".agents"))
+}
+
+/// The shell probe's socket without `[shell] socket_paths`, in the runtime directory
+pub fn shell_socket() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from)
+        .or_else(dirs::runtime_dir)
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join("viberot-shell.sock")
+}
+
+/// Where `[pipe_source]` listens without `path`: a named pipe on Windows, a socket next to the
+/// shell probe's elsewhere
+pub fn probe_endpoint() -> String {
+    if cfg!(windows) {
+        r"\\.\pipe\viberot-probe".to_string()
+    } else {
+        shell_socket().with_file_name("viberot-probe.sock").to_string_lossy().into_owned()
+    }
+}
+
+/// Where `[agent_hooks]` listens without `path`, like `probe_endpoint`
+pub fn agent_endpoint() -> String {
+    if cfg!(windows) {
+        r"\\.\pipe\viberot-agents".to_string()
+    } else {
+        shell_socket().with_file_name("viberot-agents.sock").to_string_lossy().into_owned()
+    }
+}
+
+/// The install root for a config, see the top of this file
+pub fn install_root(config: Option<&Config>) -> Result<PathBuf, Error> {
+    // 1. Environment variable override (highest priority)
+    if let Ok(path) = env::var("VIBEROT_HOME") {
+        let path_buf = PathBuf::from(path);
+        if path_buf.exists() {
+            debug!("Using VIBEROT_HOME environment variable: {}", path_buf.display());
+            return Ok(path_buf);
+        } else {
+            warn!("VIBEROT_HOME environment variable points to non-existent path: {}", path_buf.display());
+        }
+    }
+
+    // 2. Check config file for installation root
+    if let Some(home_path) = config.and_then(|config| config.viberot_home.as_ref()) {
+        let path_buf = PathBuf::from(home_path);
+        if path_buf.exists() {
+            debug!("Using viberot_home from config: {}", path_buf.display());
+            return Ok(path_buf);
+        } else {
+            warn!("viberot_home in config points to non-existent path: {}", path_buf.display());
+        }
+    }
+
+    // 3. A self-contained install
+    if env::var_os("VIBEROT_PREFIX").is_some_and(|prefix| !prefix.is_empty()) {
+        let prefix = data_dir()?;
+        if prefix.join(ACTIONS_DIR).is_dir() {
+            debug!("Using actions in VIBEROT_PREFIX: {}", prefix.display());
+            return Ok(prefix);
+        }
+    }
+
+    // 4. The package the executable came in, or the checkout it was built in
+    if let Some(root) = packaged_root().or_else(checkout_root) {
+        return Ok(root);
+    }
+
+    // 5. Actions installed by `viberot-service setup-actions`
+    if actions_dir()?.is_dir() {
+        let data_dir = data_dir()?;
+        debug!("Using installed actions in {}", data_dir.display());
+        return Ok(data_dir);
+    }
+    Err("Could not find the VibeRot actions: not next to the executable, in a checkout or in the data directory. \
+         Set VIBEROT_HOME, or run `viberot-service setup-actions`".into())
+}
+
+/// Directories the executable is in: where it was started from, and where links to it lead
+fn executable_dirs() -> Vec<PathBuf> {
+    let Ok(exe_path) = env::current_exe() else {
+        return Vec::new();
+    };
+    // Homebrew links <prefix>/bin/viberot-service to the one in its Cellar
+    let mut dirs: Vec<PathBuf> = [Some(exe_path.clone()), exe_path.canonicalize().ok()].into_iter().flatten()
+        .filter_map(|path| path.parent().map(Path::to_path_buf))
+        .collect();
+    dirs.dedup();
+    dirs
+}
+
+/// The install root of a package: actions beside the executable, or in <prefix>/share/viberot
+fn packaged_root() -> Option<PathBuf> {
+    for dir in executable_dirs() {
+        let candidates = [Some(dir.clone()), dir.parent().map(|prefix| prefix.join("share").join("viberot"))];
+        // A checkout's target/ directory isn't a package, checkout_root finds those
+        for root in candidates.into_iter().flatten() {
+            if root.join("actions").is_dir() && !root.join("Cargo.toml").exists() {
+                debug!("Found packaged viberot root next to the executable: {}", root.display());
+                return Some(root);
+            }
+        }
+    }
+    None
+}
+
+/// Development environment fallback: tries to find project root by walking up directories
+fn checkout_root() -> Option<PathBuf> {
+    let is_checkout = |dir: &Path| dir.join("Cargo.toml").exists() && dir.join("src").exists() && dir.join("actions").exists();
+
+    // Try to find the project root by looking for Cargo.toml starting from current exe
+    for exe_dir in executable_dirs() {
+        if let Some(dir) = exe_dir.ancestors().find(|dir| is_checkout(dir)) {
+            debug!("Found viberot root via exe path: {}", dir.display());
+            return Some(dir.to_path_buf());
+        }
+    }
+
+    // Fallback: try current working directory and walk up
+    let current = env::current_dir().ok()?;
+    let found = current.ancestors().take(5).find(|dir| is_checkout(dir))?; // Limit search depth
+    debug!("Found viberot root via cwd: {}", found.display());
+    Some(found.to_path_buf())
+}
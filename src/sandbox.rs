@@ -0,0 +1,260 @@
+// Sandboxed actions
+// Exec actions with `sandbox = true` run in a restricted context:
+// - Linux: under bubblewrap, with the filesystem mounted read-only, a private /tmp, new namespaces
+//   and no network unless allowed
+// - macOS: under sandbox-exec, with a profile denying file writes and network access
+// - Windows: started suspended, given a restricted token (no privileges, Administrators deny-only)
+//   and put in a job object that can't touch the clipboard, desktops, global atoms or system
+//   settings before it runs. Files and network aren't restricted, which is logged as a warning.
+// If the sandbox can't be set up, the action doesn't run.
+
+use std::path::{Path, PathBuf};
+
+/// What a sandboxed action is still allowed to do
+#[derive(Debug, Clone, Default)]
+pub struct SandboxPolicy {
+    pub allow_network: bool,
+    /// Paths the action may write to, besides its temporary directory
+    pub writable: Vec<PathBuf>,
+}
+
+/// Program and arguments that run `program` inside the sandbox
+#[cfg(target_os = "linux")]
+pub fn wrap(
+    program: &Path,
+    args: &[String],
+    policy: &SandboxPolicy,
+) -> Result<(PathBuf, Vec<String>), Box<dyn std::error::Error + Send + Sync>> {
+    let bwrap = find_in_path("bwrap")
+        .ok_or("Sandboxed actions need bubblewrap (`bwrap`) installed")?;
+
+    let mut wrapped: Vec<String> = [
+        "--ro-bind", "/", "/",
+        "--dev", "/dev",
+        "--proc", "/proc",
+        "--tmpfs", "/tmp",
+        // Keep the X11 sockets the private /tmp would hide
+        "--ro-bind-try", "/tmp/.X11-unix", "/tmp/.X11-unix",
+        "--unshare-all",
+        "--die-with-parent",
+        "--new-session",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    if policy.allow_network {
+        wrapped.push("--share-net".to_string());
+    }
+    for path in &policy.writable {
+        let path = path.to_string_lossy().into_owned();
+        wrapped.extend(["--bind-try".to_string(), path.clone(), path]);
+    }
+    wrapped.push("--".to_string());
+    wrapped.push(program.to_string_lossy().into_owned());
+    wrapped.extend(args.iter().cloned());
+    Ok((bwrap, wrapped))
+}
+
+/// Program and arguments that run `program` inside the sandbox
+#[cfg(target_os = "macos")]
+pub fn wrap(
+    program: &Path,
+    args: &[String],
+    policy: &SandboxPolicy,
+) -> Result<(PathBuf, Vec<String>), Box<dyn std::error::Error + Send + Sync>> {
+    let sandbox_exec = PathBuf::from("/usr/bin/sandbox-exec");
+    if !sandbox_exec.exists() {
+        return Err("Sandboxed actions need /usr/bin/sandbox-exec".into());
+    }
+
+    let mut profile = String::from(
+        "(version 1)\n(allow default)\n(deny file-write*)\n\
+         (allow file-write* (subpath \"/private/tmp\") (subpath \"/private/var/folders\") (literal \"/dev/null\"))\n",
+    );
+    for path in &policy.writable {
+        profile.push_str(&format!("(allow file-write* (subpath {}))\n", quote(&path.to_string_lossy())));
+    }
+    if !policy.allow_network {
+        profile.push_str("(deny network*)\n");
+    }
+
+    let mut wrapped = vec!["-p".to_string(), profile, program.to_string_lossy().into_owned()];
+    wrapped.extend(args.iter().cloned());
+    Ok((sandbox_exec, wrapped))
+}
+
+/// Program and arguments that run `program` inside the sandbox; on Windows the program runs
+/// as-is and is contained before it runs, see `prepare` and `contain`
+#[cfg(windows)]
+pub fn wrap(
+    program: &Path,
+    args: &[String],
+    policy: &SandboxPolicy,
+) -> Result<(PathBuf, Vec<String>), Box<dyn std::error::Error + Send + Sync>> {
+    // Once per program, so it isn't lost among the commands it runs for
+    static WARNED: std::sync::Mutex<Vec<PathBuf>> = std::sync::Mutex::new(Vec::new());
+    let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    if !warned.iter().any(|path| path == program) {
+        let unrestricted = if policy.allow_network { "file writes" } else { "file writes and network access" };
+        tracing::warn!("The sandbox of '{}' can't restrict {} on Windows; it still gets a restricted token and \
+                        can't use the clipboard, desktops or system settings", program.display(), unrestricted);
+        warned.push(program.to_path_buf());
+    }
+    Ok((program.to_path_buf(), args.to_vec()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn wrap(
+    _program: &Path,
+    _args: &[String],
+    _policy: &SandboxPolicy,
+) -> Result<(PathBuf, Vec<String>), Box<dyn std::error::Error + Send + Sync>> {
+    Err("Sandboxed actions aren't supported on this platform".into())
+}
+
+/// Sets up a sandboxed action's command; on Windows it starts suspended, for `contain`
+#[cfg(windows)]
+pub fn prepare(cmd: &mut tokio::process::Command) {
+    cmd.creation_flags(windows::Win32::System::Threading::CREATE_SUSPENDED.0);
+}
+
+/// Sets up a sandboxed action's command; the wrapper does it all on this platform
+#[cfg(not(windows))]
+pub fn prepare(_cmd: &mut tokio::process::Command) {}
+
+#[cfg(windows)]
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtSetInformationProcess(process: windows::Win32::Foundation::HANDLE, class: u32, information: *const std::ffi::c_void, length: u32) -> i32;
+    fn NtResumeProcess(process: windows::Win32::Foundation::HANDLE) -> i32;
+}
+
+/// `PROCESS_ACCESS_TOKEN`: the primary token `NtSetInformationProcess` gives a process that hasn't run yet
+#[cfg(windows)]
+#[repr(C)]
+struct ProcessAccessToken {
+    token: windows::Win32::Foundation::HANDLE,
+    thread: windows::Win32::Foundation::HANDLE,
+}
+
+/// `ProcessAccessToken` of `PROCESSINFOCLASS`
+#[cfg(windows)]
+const PROCESS_ACCESS_TOKEN: u32 = 9;
+
+/// Restricts an action `prepare` started suspended, then lets it run. Nothing it does happens
+/// before, so neither it nor the processes it starts get out.
+#[cfg(windows)]
+pub fn contain(child: &tokio::process::Child) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use windows::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows::Win32::System::JobObjects::{AssignProcessToJobObject, CreateJobObjectW};
+
+    let process = HANDLE(child.raw_handle().ok_or("Action exited before it could be sandboxed")? as isize);
+    // SAFETY: the handle belongs to the child, which outlives these calls
+    unsafe {
+        restrict_token(process)?;
+        let job = CreateJobObjectW(None, None)?;
+        let result = configure_job(job).and_then(|()| AssignProcessToJobObject(job, process));
+        // The job lives on as long as processes are assigned to it
+        let _ = CloseHandle(job);
+        result?;
+        match NtResumeProcess(process) {
+            status if status >= 0 => Ok(()),
+            status => Err(format!("couldn't resume it: NTSTATUS {:#x}", status).into()),
+        }
+    }
+}
+
+/// Replaces the token of a process that hasn't run yet with ours, without privileges and with
+/// Administrators only able to deny access
+#[cfg(windows)]
+unsafe fn restrict_token(process: windows::Win32::Foundation::HANDLE) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use windows::Win32::Foundation::{CloseHandle, HANDLE, PSID};
+    use windows::Win32::Security::{
+        CreateRestrictedToken, CreateWellKnownSid, WinBuiltinAdministratorsSid, DISABLE_MAX_PRIVILEGE,
+        SID_AND_ATTRIBUTES, TOKEN_ASSIGN_PRIMARY, TOKEN_DUPLICATE, TOKEN_QUERY,
+    };
+    use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    let mut own = HANDLE::default();
+    OpenProcessToken(GetCurrentProcess(), TOKEN_DUPLICATE | TOKEN_QUERY | TOKEN_ASSIGN_PRIMARY, &mut own)?;
+    // SECURITY_MAX_SID_SIZE, aligned for a SID
+    let mut administrators = [0u32; 17];
+    let administrators = PSID(administrators.as_mut_ptr().cast());
+    let mut length = 68u32;
+    let restricted = CreateWellKnownSid(WinBuiltinAdministratorsSid, PSID::default(), administrators, &mut length)
+        .and_then(|()| {
+            let disabled = [SID_AND_ATTRIBUTES { Sid: administrators, Attributes: 0 }];
+            let mut restricted = HANDLE::default();
+            CreateRestrictedToken(own, DISABLE_MAX_PRIVILEGE, Some(&disabled), None, None, &mut restricted).map(|()| restricted)
+        });
+    let _ = CloseHandle(own);
+    let restricted = restricted?;
+    let access = ProcessAccessToken { token: restricted, thread: HANDLE::default() };
+    let status = NtSetInformationProcess(
+        process,
+        PROCESS_ACCESS_TOKEN,
+        &access as *const _ as *const std::ffi::c_void,
+        std::mem::size_of::<ProcessAccessToken>() as u32,
+    );
+    let _ = CloseHandle(restricted);
+    if status < 0 {
+        return Err(format!("couldn't give it a restricted token: NTSTATUS {:#x}", status).into());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+unsafe fn configure_job(job: windows::Win32::Foundation::HANDLE) -> windows::core::Result<()> {
+    use windows::Win32::System::JobObjects::{
+        JobObjectBasicUIRestrictions, JobObjectExtendedLimitInformation, SetInformationJobObject,
+        JOBOBJECT_BASIC_UI_RESTRICTIONS, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_DIE_ON_UNHANDLED_EXCEPTION, JOB_OBJECT_UILIMIT_DESKTOP,
+        JOB_OBJECT_UILIMIT_DISPLAYSETTINGS, JOB_OBJECT_UILIMIT_EXITWINDOWS, JOB_OBJECT_UILIMIT_GLOBALATOMS,
+        JOB_OBJECT_UILIMIT_READCLIPBOARD, JOB_OBJECT_UILIMIT_SYSTEMPARAMETERS,
+        JOB_OBJECT_UILIMIT_WRITECLIPBOARD,
+    };
+
+    let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+    limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_DIE_ON_UNHANDLED_EXCEPTION;
+    SetInformationJobObject(
+        job,
+        JobObjectExtendedLimitInformation,
+        &limits as *const _ as *const std::ffi::c_void,
+        std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+    )?;
+
+    let ui = JOBOBJECT_BASIC_UI_RESTRICTIONS {
+        UIRestrictionsClass: JOB_OBJECT_UILIMIT_DESKTOP
+            | JOB_OBJECT_UILIMIT_DISPLAYSETTINGS
+            | JOB_OBJECT_UILIMIT_EXITWINDOWS
+            | JOB_OBJECT_UILIMIT_GLOBALATOMS
+            | JOB_OBJECT_UILIMIT_READCLIPBOARD
+            | JOB_OBJECT_UILIMIT_SYSTEMPARAMETERS
+            | JOB_OBJECT_UILIMIT_WRITECLIPBOARD,
+    };
+    SetInformationJobObject(
+        job,
+        JobObjectBasicUIRestrictions,
+        &ui as *const _ as *const std::ffi::c_void,
+        std::mem::size_of::<JOBOBJECT_BASIC_UI_RESTRICTIONS>() as u32,
+    )
+}
+
+/// Restricts an action once started; the wrapper already did on this platform
+#[cfg(not(windows))]
+pub fn contain(_child: &tokio::process::Child) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Quotes a string for a sandbox profile
+#[cfg(target_os = "macos")]
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
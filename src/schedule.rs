@@ -0,0 +1,139 @@
+// Rule schedules
+// `schedule` limits a rule to times of the week, in local time. Each entry is days, a time range,
+// or both: "mon-fri 09:00-18:00", "sat,sun", "22:00-06:00". A range ending before it starts runs
+// past midnight, into the next day. A rule with several entries matches during any of them.
+
+/// Days of the week as `tm_wday` counts them, from Sunday
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    windows: Vec<Window>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    /// A bit per day, from Sunday
+    days: u8,
+    /// Minutes after midnight; `end` before `start` runs past midnight
+    start: u16,
+    end: u16,
+}
+
+/// A moment of the week in local time
+#[derive(Debug, Clone, Copy)]
+pub struct LocalTime {
+    /// 0 for Sunday
+    pub weekday: u8,
+    /// Minutes after midnight
+    pub minute: u16,
+}
+
+impl Schedule {
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> Result<Self, String> {
+        let windows = entries.iter()
+            .map(|entry| Window::parse(entry.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if windows.is_empty() {
+            return Err("`schedule` needs at least one entry, like \"mon-fri 09:00-18:00\"".into());
+        }
+        Ok(Self { windows })
+    }
+
+    pub fn contains(&self, time: LocalTime) -> bool {
+        self.windows.iter().any(|window| window.contains(time))
+    }
+}
+
+impl Window {
+    fn parse(entry: &str) -> Result<Self, String> {
+        let mut window = Window { days: 0x7f, start: 0, end: MINUTES_PER_DAY };
+        let (mut has_days, mut has_times) = (false, false);
+        for part in entry.split_whitespace() {
+            if part.contains(':') && !has_times {
+                (window.start, window.end) = parse_times(part)
+                    .ok_or_else(|| format!("`schedule` has '{}', which isn't a time range like 09:00-18:00", part))?;
+                has_times = true;
+            } else if !part.contains(':') && !has_days {
+                window.days = parse_days(part)
+                    .ok_or_else(|| format!("`schedule` has '{}', which isn't days like mon-fri or sat,sun", part))?;
+                has_days = true;
+            } else {
+                return Err(format!("`schedule` entry '{}' should be days, a time range, or days then a time range", entry));
+            }
+        }
+        if !has_days && !has_times {
+            return Err("`schedule` has an empty entry".into());
+        }
+        Ok(window)
+    }
+
+    fn contains(&self, time: LocalTime) -> bool {
+        let on = |weekday: u8| self.days & (1 << weekday) != 0;
+        if self.start < self.end {
+            return on(time.weekday) && (self.start..self.end).contains(&time.minute);
+        }
+        // Past midnight: the evening of one of the days, or the morning after it
+        (on(time.weekday) && time.minute >= self.start) || (on((time.weekday + 6) % 7) && time.minute < self.end)
+    }
+}
+
+/// Days like "mon-fri", "sat,sun" or "mon,wed-fri", as a bit per day
+fn parse_days(text: &str) -> Option<u8> {
+    let day = |name: &str| DAYS.iter().position(|day| name.eq_ignore_ascii_case(day));
+    let mut days = 0u8;
+    for item in text.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (day(first)?, day(last)?),
+            None => (day(item)?, day(item)?),
+        };
+        // Ranges may wrap around the week, like fri-mon
+        let mut current = first;
+        loop {
+            days |= 1 << current;
+            if current == last {
+                break;
+            }
+            current = (current + 1) % 7;
+        }
+    }
+    Some(days)
+}
+
+/// A range like "09:00-18:30" in minutes after midnight; "24:00" ends at midnight
+fn parse_times(text: &str) -> Option<(u16, u16)> {
+    let minute = |time: &str| {
+        let (hours, minutes) = time.split_once(':')?;
+        let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+        let minute = hours * 60 + minutes;
+        (minutes < 60 && minute <= MINUTES_PER_DAY).then_some(minute)
+    };
+    let (start, end) = text.split_once('-')?;
+    let (start, end) = (minute(start)?, minute(end)?);
+    (start != end && start < MINUTES_PER_DAY).then_some((start, end))
+}
+
+/// The current moment of the week in local time
+#[cfg(unix)]
+pub fn local_time() -> LocalTime {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as libc::time_t;
+    // SAFETY: localtime_r only writes to the tm we own
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        return LocalTime { weekday: 0, minute: 0 };
+    }
+    LocalTime { weekday: tm.tm_wday as u8, minute: (tm.tm_hour * 60 + tm.tm_min) as u16 }
+}
+
+/// The current moment of the week in local time
+#[cfg(windows)]
+pub fn local_time() -> LocalTime {
+    use windows::Win32::System::SystemInformation::GetLocalTime;
+
+    let time = unsafe { GetLocalTime() };
+    LocalTime { weekday: time.wDayOfWeek as u8, minute: time.wHour * 60 + time.wMinute }
+}
@@ -0,0 +1,220 @@
+// Lua rule hooks
+//
+// A rule's `script` runs after the rule matched and decides what happens next:
+// - return nothing (nil) to run the rule's actions unchanged
+// - return false to veto the match
+// - return a list of actions to replace the rule's actions (e.g. with computed args)
+//
+// Scripts see two globals: `event` (read-only view of the ProcessEvent) and `actions`
+// (the rule's actions as tables, same shape as in config.toml). Only the table, string
+// and math libraries are loaded. Each rule's script keeps its own VM between matches and runs on
+// a blocking thread, stopped after an instruction budget, a time limit or a memory limit.
+//
+// Builds without the `lua` feature reject configs that use scripts.
+
+#[cfg(feature = "lua")]
+use mlua::{HookTriggers, Lua, LuaOptions, LuaSerdeExt, StdLib, Value, VmState};
+#[cfg(feature = "lua")]
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+#[cfg(feature = "lua")]
+use std::sync::{Mutex, TryLockError};
+#[cfg(feature = "lua")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "lua")]
+use crate::command_line::CommandLine;
+use crate::config::Action;
+use crate::platform::ProcessEvent;
+
+/// Instructions are counted in batches of this size
+#[cfg(feature = "lua")]
+const INSTRUCTION_BATCH: u32 = 10_000;
+/// Maximum number of instruction batches a rule script may run
+#[cfg(feature = "lua")]
+const MAX_INSTRUCTION_BATCHES: u32 = 100;
+/// How long a rule script may run for one match
+#[cfg(feature = "lua")]
+const TIME_LIMIT: Duration = Duration::from_millis(500);
+/// Memory the VM of a rule script may use
+#[cfg(feature = "lua")]
+const MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// What a rule script decided for a match
+#[derive(Debug)]
+#[cfg_attr(not(feature = "lua"), allow(dead_code))] // Only scripts veto or replace
+pub enum ScriptOutcome {
+    /// Keep the rule's actions as configured
+    Keep,
+    /// Drop the match entirely
+    Veto,
+    /// Run these actions instead
+    Replace(Vec<Action>),
+}
+
+/// A rule's script, run on a blocking thread in a VM of its own that's kept between matches
+#[derive(Clone)]
+pub struct RuleScript {
+    source: Arc<str>,
+    /// Created on the first match
+    #[cfg(feature = "lua")]
+    vm: Arc<Mutex<Option<ScriptVm>>>,
+}
+
+impl RuleScript {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.into(),
+            #[cfg(feature = "lua")]
+            vm: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+/// Checks a script for syntax errors without running it
+#[cfg(feature = "lua")]
+pub fn validate(source: &str) -> Result<(), String> {
+    let lua = create_sandbox(Arc::new(RunBudget::new())).map_err(|e| e.to_string())?;
+    lua.load(source).set_name("rule script").into_function()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "lua")]
+impl RuleScript {
+    /// Runs the script for a matched event
+    pub async fn run(&self, event: &ProcessEvent, actions: &[Action]) -> Result<ScriptOutcome, mlua::Error> {
+        let (script, event, actions) = (self.clone(), event.clone(), actions.to_vec());
+        let run = tokio::task::spawn_blocking(move || script.run_blocking(&event, &actions));
+        // The hook stops the script in time unless a single library call runs long
+        match tokio::time::timeout(TIME_LIMIT * 2, run).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => Err(mlua::Error::runtime(format!("rule script crashed: {}", e))),
+            Err(_) => Err(mlua::Error::runtime("rule script exceeded its time limit")),
+        }
+    }
+
+    fn run_blocking(&self, event: &ProcessEvent, actions: &[Action]) -> Result<ScriptOutcome, mlua::Error> {
+        let mut vm = match self.vm.try_lock() {
+            Ok(vm) => vm,
+            Err(TryLockError::WouldBlock) => {
+                return Err(mlua::Error::runtime("rule script is still running for an earlier command"));
+            }
+            // A VM that panicked mid-run starts over
+            Err(TryLockError::Poisoned(poisoned)) => {
+                self.vm.clear_poison();
+                let mut vm = poisoned.into_inner();
+                *vm = None;
+                vm
+            }
+        };
+        if vm.is_none() {
+            *vm = Some(ScriptVm::new(&self.source)?);
+        }
+        vm.as_ref().expect("VM was just created").run(event, actions)
+    }
+}
+
+/// Instructions and time left for the current run of a script
+#[cfg(feature = "lua")]
+struct RunBudget {
+    batches: AtomicU32,
+    deadline: Mutex<Instant>,
+}
+
+#[cfg(feature = "lua")]
+impl RunBudget {
+    fn new() -> Self {
+        Self { batches: AtomicU32::new(0), deadline: Mutex::new(Instant::now() + TIME_LIMIT) }
+    }
+
+    fn reset(&self) {
+        self.batches.store(0, Ordering::Relaxed);
+        *self.deadline.lock().unwrap() = Instant::now() + TIME_LIMIT;
+    }
+}
+
+#[cfg(feature = "lua")]
+struct ScriptVm {
+    lua: Lua,
+    chunk: mlua::Function,
+    read_only: mlua::Function,
+    budget: Arc<RunBudget>,
+}
+
+#[cfg(feature = "lua")]
+impl ScriptVm {
+    fn new(source: &str) -> Result<Self, mlua::Error> {
+        let budget = Arc::new(RunBudget::new());
+        let lua = create_sandbox(Arc::clone(&budget))?;
+        let chunk = lua.load(source).set_name("rule script").into_function()?;
+        let read_only = lua.load(
+            "return function(t) return setmetatable({}, { __index = t, __newindex = function() error('event is read-only', 2) end }) end",
+        ).eval()?;
+        Ok(Self { lua, chunk, read_only, budget })
+    }
+
+    fn run(&self, event: &ProcessEvent, actions: &[Action]) -> Result<ScriptOutcome, mlua::Error> {
+        let lua = &self.lua;
+        self.budget.reset();
+
+        let event_table = lua.create_table()?;
+        let command_line = CommandLine::parse(&event.command);
+        event_table.set("pid", event.pid)?;
+        event_table.set("command", event.command.as_str())?;
+        event_table.set("exe", command_line.exe)?;
+        event_table.set("args", command_line.args)?;
+        event_table.set("timestamp", event.timestamp)?;
+        event_table.set("working_directory", event.working_directory.clone())?;
+        event_table.set("shell_session_id", event.shell_session_id.clone())?;
+        event_table.set("probe", format!("{:?}", event.probe_source))?;
+        event_table.set("expected_duration", event.expected_duration)?;
+        event_table.set("provenance", lua.to_value(&event.provenance)?)?;
+        event_table.set("tags", lua.to_value(&event.tags)?)?;
+
+        let globals = lua.globals();
+        globals.set("event", self.read_only.call::<Value>(event_table)?)?;
+        globals.set("actions", lua.to_value(actions)?)?;
+
+        match self.chunk.call::<Value>(())? {
+            Value::Nil => Ok(ScriptOutcome::Keep),
+            Value::Boolean(true) => Ok(ScriptOutcome::Keep),
+            Value::Boolean(false) => Ok(ScriptOutcome::Veto),
+            value @ Value::Table(_) => Ok(ScriptOutcome::Replace(lua.from_value(value)?)),
+            other => Err(mlua::Error::runtime(format!(
+                "rule script must return nil, a boolean or a list of actions, got {}",
+                other.type_name()
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "lua")]
+fn create_sandbox(budget: Arc<RunBudget>) -> Result<Lua, mlua::Error> {
+    let lua = Lua::new_with(StdLib::TABLE | StdLib::STRING | StdLib::MATH, LuaOptions::default())?;
+    lua.set_memory_limit(MEMORY_LIMIT)?;
+
+    lua.set_hook(HookTriggers::new().every_nth_instruction(INSTRUCTION_BATCH), move |_, _| {
+        if budget.batches.fetch_add(1, Ordering::Relaxed) >= MAX_INSTRUCTION_BATCHES {
+            return Err(mlua::Error::runtime("rule script exceeded its instruction budget"));
+        }
+        if Instant::now() >= *budget.deadline.lock().unwrap() {
+            return Err(mlua::Error::runtime("rule script exceeded its time limit"));
+        }
+        Ok(VmState::Continue)
+    })?;
+
+    Ok(lua)
+}
+
+#[cfg(not(feature = "lua"))]
+pub fn validate(_source: &str) -> Result<(), String> {
+    Err("this build of VibeRot has no Lua support (the `lua` feature)".to_string())
+}
+
+#[cfg(not(feature = "lua"))]
+impl RuleScript {
+    pub async fn run(&self, _event: &ProcessEvent, _actions: &[Action]) -> Result<ScriptOutcome, String> {
+        validate(&self.source).map(|()| ScriptOutcome::Keep)
+    }
+}
@@ -0,0 +1,298 @@
+// Action SDKs
+// `viberot-service sdk schema` prints the action protocol (environment, stdin and stdout messages,
+// exit rules; see `action_protocol`) as JSON, and `sdk generate <language>` turns it into a
+// single-file helper library action authors can vendor: typed access to the environment, the
+// stdin commands, the events to report and waiting for the command to end. The files are
+// generated from the same description the service uses, so regenerating them picks up protocol
+// changes; they have no dependencies beyond the language's standard library.
+
+use clap::{Subcommand, ValueEnum};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use crate::action_protocol::{self, FieldSpec, MessageSpec, Protocol};
+
+#[derive(Subcommand)]
+pub enum SdkCommand {
+    /// Print the action protocol as JSON
+    Schema,
+    /// Write a helper library for actions written in a language
+    Generate {
+        language: Language,
+        /// Directory to write the library to
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Language {
+    Python,
+    /// Node.js (CommonJS)
+    Node,
+}
+
+impl Language {
+    fn file_name(self) -> &'static str {
+        match self {
+            Language::Python => "viberot_action.py",
+            Language::Node => "viberot-action.js",
+        }
+    }
+}
+
+/// `prefix` is the configured `action_env_prefix`, baked into the generated variable names
+pub fn run(command: SdkCommand, prefix: Option<&str>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match command {
+        SdkCommand::Schema => println!("{}", action_protocol::protocol_json(prefix)?),
+        SdkCommand::Generate { language, out_dir } => {
+            let path = out_dir.join(language.file_name());
+            write(&path, &generate(language, &action_protocol::protocol(prefix)))?;
+            println!("Wrote {} for action protocol v{}", path.display(), action_protocol::PROTOCOL_VERSION);
+        }
+    }
+    Ok(())
+}
+
+fn write(path: &Path, content: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e).into())
+}
+
+pub fn generate(language: Language, protocol: &Protocol) -> String {
+    match language {
+        Language::Python => python(protocol),
+        Language::Node => node(protocol),
+    }
+}
+
+/// Variable name without the prefix, in lower case, e.g. `pid_type`
+fn short_name(protocol: &Protocol, name: &str) -> String {
+    name.strip_prefix(&protocol.env.prefix)
+        .and_then(|rest| rest.strip_prefix('_'))
+        .unwrap_or(name)
+        .to_lowercase()
+}
+
+fn camel_case(name: &str) -> String {
+    let mut parts = name.split('_');
+    let mut camel = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
+
+fn python(protocol: &Protocol) -> String {
+    let mut out = format!(
+        r#""""VibeRot action helpers, generated by `viberot-service sdk generate python` from action protocol v{version}.
+Vendor this file next to your action, and regenerate it rather than editing it.
+
+    import viberot_action as viberot
+
+    print("Started for", viberot.env.command)
+    viberot.report_visible()
+    viberot.wait_until_done()  # Returns once the command ended
+"""
+
+import json
+import os
+import sys
+
+PROTOCOL_VERSION = {version}
+# {exit}
+EXIT_GRACE_SECONDS = {grace}
+
+
+class Env:
+    """The environment VibeRot passes to actions; variables that aren't set are None"""
+
+    def __init__(self, environ=None):
+        self._environ = os.environ if environ is None else environ
+
+    def _int(self, name):
+        value = self._environ.get(name)
+        return int(value) if value is not None else None
+"#,
+        version = protocol.version,
+        exit = protocol.exit.description,
+        grace = protocol.exit.grace_period_seconds,
+    );
+    for var in &protocol.env.variables {
+        let getter = match var.value_type {
+            "integer" => format!("self._int(\"{}\")", var.name),
+            _ => format!("self._environ.get(\"{}\")", var.name),
+        };
+        let _ = write!(
+            out,
+            "\n    # {}\n    @property\n    def {}(self):\n        return {}\n",
+            var.description, short_name(protocol, &var.name), getter
+        );
+    }
+
+    out.push_str("\n\nenv = Env()\n\n# Commands VibeRot writes to stdin, by `cmd`:\n");
+    for command in &protocol.stdin {
+        let _ = writeln!(out, "#   {}: {}", command.name, command.description);
+    }
+    let names: Vec<String> = protocol.stdin.iter().map(|command| format!("\"{}\"", command.name)).collect();
+    let _ = writeln!(out, "COMMANDS = [{}]", names.join(", "));
+    out.push_str(r#"
+
+def commands(stream=None):
+    """Yields the commands VibeRot writes to stdin as dicts, e.g. {"cmd": "open", "id": "..."},
+    until stdin closes"""
+    for line in sys.stdin if stream is None else stream:
+        line = line.strip()
+        if not line:
+            continue
+        try:
+            command = json.loads(line)
+        except ValueError:
+            continue
+        if isinstance(command, dict) and command.get("cmd") in COMMANDS:
+            yield command
+
+
+def wait_until_done(stream=None):
+    """Blocks until stdin closes, which is when the action should exit"""
+    for _ in commands(stream):
+        pass
+
+
+def report(event, id=None, **fields):
+    """Tells VibeRot about the action on stdout; `id` defaults to the command it was started for"""
+    print(json.dumps({"event": event, "id": id, **fields}), flush=True)
+"#);
+    for event in &protocol.stdout {
+        let (required, optional) = fields(event);
+        let params: Vec<String> = required.iter().map(|field| field.to_string())
+            .chain(optional.iter().map(|field| format!("{}=None", field)))
+            .chain(["id=None".to_string()])
+            .collect();
+        let args: String = required.iter().chain(&optional).map(|field| format!(", {}={}", field, field)).collect();
+        let _ = write!(
+            out,
+            "\n\n# {}\ndef report_{}({}):\n    report(\"{}\", id{})\n",
+            event.description, event.name, params.join(", "), event.name, args
+        );
+    }
+    out
+}
+
+/// Fields of a stdout message besides `id`: those it needs, and those that may be null
+fn fields(message: &MessageSpec) -> (Vec<&'static str>, Vec<&'static str>) {
+    let (required, optional): (Vec<_>, Vec<_>) = message.fields.iter()
+        .filter(|field| field.name != "id")
+        .partition(|field| !field.value_type.ends_with("|null"));
+    let names = |fields: Vec<&FieldSpec>| fields.into_iter().map(|field| field.name).collect();
+    (names(required), names(optional))
+}
+
+fn node(protocol: &Protocol) -> String {
+    let mut out = format!(
+        r#"// VibeRot action helpers, generated by `viberot-service sdk generate node` from action protocol v{version}.
+// Vendor this file next to your action, and regenerate it rather than editing it.
+//
+//     const viberot = require('./viberot-action');
+//
+//     console.log('Started for', viberot.env.command);
+//     viberot.reportVisible();
+//     await viberot.waitUntilDone(); // Resolves once the command ended
+
+'use strict';
+
+const readline = require('readline');
+
+const PROTOCOL_VERSION = {version};
+// {exit}
+const EXIT_GRACE_SECONDS = {grace};
+
+function int(name) {{
+  const value = process.env[name];
+  return value === undefined ? undefined : Number(value);
+}}
+
+/** The environment VibeRot passes to actions; variables that aren't set are undefined */
+const env = {{
+"#,
+        version = protocol.version,
+        exit = protocol.exit.description,
+        grace = protocol.exit.grace_period_seconds,
+    );
+    for var in &protocol.env.variables {
+        let getter = match var.value_type {
+            "integer" => format!("int('{}')", var.name),
+            _ => format!("process.env.{}", var.name),
+        };
+        let _ = write!(
+            out,
+            "  /** {} */\n  get {}() {{ return {}; }},\n",
+            var.description, camel_case(&short_name(protocol, &var.name)), getter
+        );
+    }
+    out.push_str("};\n\n// Commands VibeRot writes to stdin, by `cmd`:\n");
+    for command in &protocol.stdin {
+        let _ = writeln!(out, "//   {}: {}", command.name, command.description);
+    }
+    let names: Vec<String> = protocol.stdin.iter().map(|command| format!("'{}'", command.name)).collect();
+    let _ = writeln!(out, "const COMMANDS = [{}];", names.join(", "));
+    out.push_str(r#"
+/** Yields the commands VibeRot writes to stdin, e.g. {cmd: 'open', id: '...'}, until stdin closes */
+async function* commands(input = process.stdin) {
+  const lines = readline.createInterface({ input, crlfDelay: Infinity });
+  for await (const line of lines) {
+    let command;
+    try {
+      command = JSON.parse(line);
+    } catch {
+      continue;
+    }
+    if (command && COMMANDS.includes(command.cmd)) {
+      yield command;
+    }
+  }
+}
+
+/** Resolves once stdin closes, which is when the action should exit */
+async function waitUntilDone(input) {
+  for await (const _ of commands(input)) {
+    // Only waiting for the end
+  }
+}
+
+/** Tells VibeRot about the action on stdout; `id` defaults to the command it was started for */
+function report(event, id = null, fields = {}) {
+  process.stdout.write(JSON.stringify({ event, id, ...fields }) + '\n');
+}
+"#);
+    let mut exports = vec!["PROTOCOL_VERSION", "EXIT_GRACE_SECONDS", "COMMANDS", "env", "commands", "waitUntilDone", "report"]
+        .into_iter()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    for event in &protocol.stdout {
+        let function = camel_case(&format!("report_{}", event.name));
+        let (required, optional) = fields(event);
+        let params: Vec<String> = required.iter().map(|field| field.to_string())
+            .chain(optional.iter().map(|field| format!("{} = null", field)))
+            .chain(["id = null".to_string()])
+            .collect();
+        let args = match required.iter().chain(&optional).copied().collect::<Vec<_>>().as_slice() {
+            [] => String::new(),
+            fields => format!(", {{ {} }}", fields.join(", ")),
+        };
+        let _ = write!(
+            out,
+            "\n/** {} */\nfunction {}({}) {{\n  report('{}', id{});\n}}\n",
+            event.description, function, params.join(", "), event.name, args
+        );
+        exports.push(function);
+    }
+    let _ = write!(out, "\nmodule.exports = {{\n{}}};\n", exports.iter().map(|name| format!("  {},\n", name)).collect::<String>());
+    out
+}
@@ -0,0 +1,182 @@
+// Setting up the default actions
+// `viberot-service setup-actions` puts the overlay where `${VIBEROT_ACTIONS}` points, so the
+// overlay rules of the default config find it without building a Tauri app by hand. In a checkout
+// with the overlay's sources and cargo at hand it's built from them; otherwise the prebuilt overlay
+// for this platform is downloaded from the release of this version into ~/.viberot/actions, and
+// checked against the release's SHA-256 before it's kept. Configs without a rule starting the
+// overlay get a couple for common slow commands.
+
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml_edit::{value, Array, DocumentMut, Item, Table};
+
+use crate::config::{Action, Config};
+use crate::preflight;
+use crate::rule_editor;
+use crate::runtime_paths;
+
+/// Where releases are downloaded from, followed by `v<version>/<file>`
+const RELEASES_URL: &str = "https://github.com/endernoke/viberot/releases/download";
+/// Longest downloading the overlay may take
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+/// Rules added when none starts the overlay: name, programs and the argument they need
+const DEFAULT_RULES: &[(&str, &[&str], &str)] = &[
+    ("overlay-builds", &["cargo", "docker", "podman", "gradle", "mvn"], "build"),
+    ("overlay-installs", &["npm", "pnpm", "yarn", "pip", "pip3"], "install"),
+];
+
+/// Where the overlay comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// Built if its sources and cargo are there, downloaded otherwise
+    Auto,
+    Build,
+    Download,
+}
+
+pub struct SetupOptions {
+    pub source: Source,
+    /// Get the overlay again even if it's there
+    pub force: bool,
+    /// Leave the rules alone
+    pub no_rules: bool,
+}
+
+pub async fn run(config_path: &Path, options: SetupOptions) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Writes the default config, with its overlay rules, on a fresh install
+    let config = Config::load(config_path)?;
+    let actions_dir = match runtime_paths::install_root(Some(&config)) {
+        Ok(root) => root.join("actions"),
+        Err(_) => runtime_paths::actions_dir()?,
+    };
+    let overlay_dir = actions_dir.join("overlay");
+    let binary = overlay_dir.join("target").join("release")
+        .join(format!("viberot-overlay{}", std::env::consts::EXE_SUFFIX));
+
+    if binary.is_file() && !options.force {
+        println!("The overlay is already at {}; `--force` gets it again", binary.display());
+    } else {
+        let can_build = overlay_dir.join("Cargo.toml").is_file() && preflight::find_on_path(Path::new("cargo")).is_some();
+        match options.source {
+            Source::Build if !can_build => {
+                return Err(format!("Can't build the overlay: it needs its sources in {} and cargo on the PATH; \
+                                    leave out `--build` to download it", overlay_dir.display()).into());
+            }
+            Source::Build | Source::Auto if can_build => build(&overlay_dir)?,
+            _ => download(&binary).await?,
+        }
+        println!("The overlay is at {}", binary.display());
+    }
+
+    if !options.no_rules {
+        add_rules(&config, config_path)?;
+    }
+    Ok(())
+}
+
+/// Builds the overlay from its sources, where `cargo build --release` puts it
+fn build(overlay_dir: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    println!("Building the overlay in {}; this takes a while the first time", overlay_dir.display());
+    let status = std::process::Command::new("cargo")
+        .args(["build", "--release"])
+        .current_dir(overlay_dir)
+        .status()
+        .map_err(|e| format!("Failed to run cargo: {}", e))?;
+    if !status.success() {
+        return Err(format!("Building the overlay failed ({}); leave out `--build` to download it instead", status).into());
+    }
+    Ok(())
+}
+
+/// Downloads the prebuilt overlay of this version and platform to `binary`
+async fn download(binary: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let asset = format!("viberot-overlay-{}-{}{}", std::env::consts::OS, std::env::consts::ARCH, std::env::consts::EXE_SUFFIX);
+    let url = format!("{}/v{}/{}", RELEASES_URL, env!("CARGO_PKG_VERSION"), asset);
+    println!("Downloading {}", url);
+
+    let client = reqwest::Client::builder().timeout(DOWNLOAD_TIMEOUT).build()?;
+    let fetch = |url: String| {
+        let client = client.clone();
+        async move {
+            let response = client.get(&url).send().await
+                .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Err(format!("There's no prebuilt overlay for {} {} in this release ({} not found); \
+                                    build it from a checkout with `setup-actions --build`",
+                                   std::env::consts::OS, std::env::consts::ARCH, url));
+            }
+            let response = response.error_for_status().map_err(|e| format!("Failed to download {}: {}", url, e))?;
+            response.bytes().await.map_err(|e| format!("Failed to download {}: {}", url, e))
+        }
+    };
+    let content = fetch(url.clone()).await?;
+    let checksum = fetch(format!("{}.sha256", url)).await?;
+
+    // `sha256sum` output: the hash, then the file name
+    let expected = String::from_utf8_lossy(&checksum).split_whitespace().next().unwrap_or_default().to_lowercase();
+    let actual: String = Sha256::digest(&content).iter().map(|byte| format!("{:02x}", byte)).collect();
+    if actual != expected {
+        return Err(format!("The downloaded overlay doesn't match its checksum (expected {}, got {}); not keeping it", expected, actual).into());
+    }
+
+    let dir = binary.parent().expect("the binary is in target/release");
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let temp_path = binary.with_extension("download");
+    std::fs::write(&temp_path, &content).map_err(|e| format!("Failed to write {}: {}", temp_path.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&temp_path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    std::fs::rename(&temp_path, binary)?;
+    Ok(())
+}
+
+/// Adds rules starting the overlay for common slow commands, unless some rule starts it already
+fn add_rules(config: &Config, config_path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let overlay_rules: Vec<String> = config.rules.iter().enumerate()
+        .filter(|(_, rule)| rule.action.as_vec().into_iter().any(starts_overlay))
+        .map(|(index, rule)| rule.display_name(index))
+        .collect();
+    if !overlay_rules.is_empty() {
+        println!("Rules already start the overlay ({}); not adding any", overlay_rules.join(", "));
+        return Ok(());
+    }
+
+    let content = std::fs::read_to_string(config_path)?;
+    let mut document: DocumentMut = content.parse()
+        .map_err(|e| format!("Failed to parse {:?}: {}", config_path, e))?;
+    let mut added = Vec::new();
+    for (name, programs, arg) in DEFAULT_RULES {
+        if config.rules.iter().any(|rule| rule.name.as_deref() == Some(*name)) {
+            continue;
+        }
+        let mut table = Table::new();
+        table["name"] = value(*name);
+        table["exe"] = value(programs.iter().copied().collect::<Array>());
+        table["args_contains"] = value(std::iter::once(*arg).collect::<Array>());
+        let mut action = rule_editor::action_table("overlay", &[]);
+        action.decor_mut().set_prefix("");
+        table["action"] = Item::Table(action);
+        rule_editor::rules(&mut document)?.push(table);
+        added.push(*name);
+    }
+    if added.is_empty() {
+        return Ok(());
+    }
+    rule_editor::save(&document, config_path)?;
+    println!("Added the rules {} to {}; the running service reloads the config on its own",
+             added.join(" and "), config_path.display());
+    Ok(())
+}
+
+/// Whether an action runs the overlay
+fn starts_overlay(action: &Action) -> bool {
+    let path = match action {
+        Action::Executable { path, .. } => Some(path),
+        Action::Attach { path, .. } => path.as_ref(),
+        _ => None,
+    };
+    path.is_some_and(|path| PathBuf::from(path).file_stem().is_some_and(|stem| stem == "viberot-overlay"))
+}
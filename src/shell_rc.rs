@@ -0,0 +1,63 @@
+// Shell rc blocks
+// What VibeRot adds to ~/.bashrc and ~/.zshrc goes between `# VibeRot <what> START` and
+// `# VibeRot <what> END` comments, so `viberot-service uninstall` can take it out again without
+// touching anything around it. Blocks written by setups from before the markers are recognized
+// by their exact lines.
+
+/// rc files setup adds blocks to
+pub const RC_FILES: &[&str] = &[".bashrc", ".zshrc"];
+
+/// Header of the sourcing line written before the markers
+const LEGACY_INTEGRATION: &str = "# VibeRot shell integration";
+/// bash-preexec loader written before the markers, header first
+const LEGACY_PREEXEC: &[&str] = &[
+    "# Load bash-preexec for VibeRot",
+    "if [ -f \"$HOME/.bash-preexec.sh\" ]; then",
+    "  source \"$HOME/.bash-preexec.sh\"",
+    "fi",
+];
+
+/// A block to append to an rc file, `body` being whole lines
+#[cfg_attr(not(unix), allow(dead_code))] // Written by the shell hooks' setup, removed everywhere
+pub fn block(what: &str, body: &str) -> String {
+    format!("\n# VibeRot {} START\n{}# VibeRot {} END\n", what, body, what)
+}
+
+/// `content` without VibeRot's blocks and the blank line before each, and how many there were.
+/// A START without its END is left alone rather than taking the rest of the file with it.
+pub fn remove_blocks(content: &str) -> (String, usize) {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let line = |index: usize| lines.get(index).map(|line| line.trim_end());
+    let mut kept: Vec<&str> = Vec::with_capacity(lines.len());
+    let mut removed = 0;
+    let mut index = 0;
+    while index < lines.len() {
+        let current = line(index).unwrap_or_default();
+        let last = if let Some(what) = current.strip_prefix("# VibeRot ").and_then(|rest| rest.strip_suffix(" START")) {
+            let end = format!("# VibeRot {} END", what);
+            lines[index + 1..].iter().position(|line| line.trim_end() == end).map(|offset| index + 1 + offset)
+        } else if current == LEGACY_INTEGRATION
+            && line(index + 1).is_some_and(|next| next.starts_with(". \"") && next.ends_with("shell_integration.sh\""))
+        {
+            Some(index + 1)
+        } else if LEGACY_PREEXEC.iter().enumerate().all(|(offset, expected)| line(index + offset) == Some(*expected)) {
+            Some(index + LEGACY_PREEXEC.len() - 1)
+        } else {
+            None
+        };
+        match last {
+            Some(last) => {
+                if kept.last().is_some_and(|line| line.trim().is_empty()) {
+                    kept.pop();
+                }
+                removed += 1;
+                index = last + 1;
+            }
+            None => {
+                kept.push(lines[index]);
+                index += 1;
+            }
+        }
+    }
+    (kept.concat(), removed)
+}
@@ -0,0 +1,160 @@
+// Signals from the OS
+// Ctrl+C, SIGTERM on Unix (`systemctl stop`), and on Windows closing the console window, logging
+// off and shutting down, all lead to the same graceful shutdown, so the ETW session, shell
+// sockets, the broker pipe and running actions are cleaned up instead of outliving the service.
+// On Unix, SIGHUP reloads the config and SIGUSR1 logs what the service is doing.
+// The Windows service isn't run by the service control manager but from a terminal or Task
+// Scheduler, and release builds have no console at all, so Windows reports the end of a session in
+// two ways:
+// - Console control events, for debug builds and anything else with a console
+// - `WM_ENDSESSION` to top-level windows, which is all GUI programs get; Windows doesn't send the
+//   logoff and shutdown console events to programs using user32, like this one
+// Either way the handler doesn't return until the process exits, as Windows may end the process
+// as soon as it does; it still ends it after the few seconds it allows.
+
+use tokio::signal;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// What a signal other than a shutdown asks the service to do
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(unix), allow(dead_code))] // Only Unix has these signals
+pub enum ServiceSignal {
+    /// SIGHUP: load the config again, without waiting for the file watcher
+    Reload,
+    /// SIGUSR1: write the current state to the log
+    DumpState,
+}
+
+/// Completes when the service should shut down, with what asked for it. Create it once, before
+/// the event loop, so a request arriving between two iterations isn't missed
+pub async fn shutdown_requested() -> &'static str {
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_close, ctrl_logoff, ctrl_shutdown};
+        let mut session_ending = session_end::subscribe();
+        match (ctrl_close(), ctrl_logoff(), ctrl_shutdown()) {
+            (Ok(mut close), Ok(mut logoff), Ok(mut shutdown)) => {
+                return tokio::select! {
+                    _ = signal::ctrl_c() => "Ctrl+C",
+                    _ = session_ending.wait_for(|ending| *ending) => "session ending",
+                    _ = close.recv() => "console window closed",
+                    _ = logoff.recv() => "user logging off",
+                    _ = shutdown.recv() => "system shutting down",
+                };
+            }
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                warn!("Failed to listen for console close, logoff and shutdown, only Ctrl+C shuts down cleanly: {}", e);
+            }
+        }
+    }
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{self, SignalKind};
+        match unix::signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                return tokio::select! {
+                    _ = signal::ctrl_c() => "Ctrl+C",
+                    _ = terminate.recv() => "SIGTERM",
+                };
+            }
+            Err(e) => warn!("Failed to listen for SIGTERM, only Ctrl+C shuts down cleanly: {}", e),
+        }
+    }
+    if let Err(e) = signal::ctrl_c().await {
+        warn!("Failed to listen for Ctrl+C: {}", e);
+        std::future::pending::<()>().await;
+    }
+    "Ctrl+C"
+}
+
+/// Signals asking the running service for a reload or a state dump; never yields anything where
+/// there are no such signals
+pub fn listen() -> mpsc::Receiver<ServiceSignal> {
+    let (tx, rx) = mpsc::channel(4);
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{self, SignalKind};
+        match (unix::signal(SignalKind::hangup()), unix::signal(SignalKind::user_defined1())) {
+            (Ok(mut hangup), Ok(mut user_defined1)) => {
+                tokio::spawn(async move {
+                    loop {
+                        let service_signal = tokio::select! {
+                            Some(()) = hangup.recv() => ServiceSignal::Reload,
+                            Some(()) = user_defined1.recv() => ServiceSignal::DumpState,
+                            else => break,
+                        };
+                        if tx.send(service_signal).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            (Err(e), _) | (_, Err(e)) => warn!("Failed to listen for SIGHUP and SIGUSR1: {}", e),
+        }
+    }
+    #[cfg(not(unix))]
+    drop(tx);
+    rx
+}
+
+/// Hidden window told by Windows when the session ends
+#[cfg(windows)]
+mod session_end {
+    use std::sync::OnceLock;
+    use tokio::sync::watch;
+    use tracing::warn;
+    use windows::core::w;
+    use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM};
+    use windows::Win32::UI::WindowsAndMessaging::{
+        CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, RegisterClassW, HMENU, MSG,
+        WINDOW_EX_STYLE, WINDOW_STYLE, WM_ENDSESSION, WNDCLASSW,
+    };
+
+    static ENDING: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+    /// Turns true once the session ends; the window is created on first use
+    pub fn subscribe() -> watch::Receiver<bool> {
+        ENDING.get_or_init(|| {
+            std::thread::spawn(run_window);
+            watch::channel(false).0
+        }).subscribe()
+    }
+
+    fn run_window() {
+        let class_name = w!("VibeRotSessionEnd");
+        unsafe {
+            let class = WNDCLASSW { lpfnWndProc: Some(window_proc), lpszClassName: class_name, ..Default::default() };
+            if RegisterClassW(&class) == 0 {
+                warn!("Failed to register the session end window class, logoff won't shut down cleanly");
+                return;
+            }
+            // Never shown; message-only windows would be simpler but don't get session messages
+            let window = CreateWindowExW(
+                WINDOW_EX_STYLE(0), class_name, w!("VibeRot"), WINDOW_STYLE(0),
+                0, 0, 0, 0, HWND::default(), HMENU::default(), HINSTANCE::default(), None,
+            );
+            if window == HWND::default() {
+                warn!("Failed to create the session end window, logoff won't shut down cleanly");
+                return;
+            }
+            let mut message = MSG::default();
+            while GetMessageW(&mut message, HWND::default(), 0, 0).as_bool() {
+                DispatchMessageW(&message);
+            }
+        }
+    }
+
+    unsafe extern "system" fn window_proc(window: HWND, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        // Nonzero `wparam` means the session really ends, rather than a logoff being cancelled
+        if message == WM_ENDSESSION && wparam.0 != 0 {
+            if let Some(ending) = ENDING.get() {
+                ending.send_replace(true);
+            }
+            loop {
+                std::thread::park();
+            }
+        }
+        DefWindowProcW(window, message, wparam, lparam)
+    }
+}
@@ -0,0 +1,230 @@
+// Historical command statistics
+// Records how long commands take so actions can be told how long the wait usually is
+// (e.g. the overlay showing "usually takes ~4m"), and how long actions ran today for the daily
+// action budgets. Persisted through the configured `Storage`, as JSON in ~/.viberot/stats.json by
+// default.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+use crate::budget;
+use crate::command_line::CommandLine;
+use crate::storage::Storage;
+
+/// Number of recent durations kept per command
+const MAX_SAMPLES: usize = 10;
+/// Maximum number of distinct commands tracked; the least recently seen are evicted
+const MAX_COMMANDS: usize = 500;
+/// Commands running longer than this are assumed to have ended without us hearing of it, e.g.
+/// through a lost ETW stop event
+const MAX_RUNNING_TIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StatsData {
+    commands: HashMap<String, CommandStats>,
+    #[serde(default)]
+    action_time: ActionTime,
+}
+
+/// How long actions ran on a day, in seconds
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct ActionTime {
+    /// Local date, e.g. "2025-03-14"
+    date: String,
+    /// Counted once per command, however many rules matched it
+    total: u64,
+    rules: HashMap<String, u64>,
+}
+
+impl ActionTime {
+    /// Starts counting afresh on a new day
+    fn roll_over(&mut self, today: &str) {
+        if self.date != today {
+            *self = Self { date: today.to_string(), ..Self::default() };
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct CommandStats {
+    /// Most recent durations in seconds, oldest first
+    durations: Vec<u64>,
+    /// Unix timestamp of the last completed run
+    last_seen: u64,
+}
+
+/// Persistent store of per-command duration history
+pub struct StatsStore {
+    storage: Arc<dyn Storage>,
+    data: Mutex<StatsData>,
+    /// Commands currently running, by PID
+    pending: Mutex<HashMap<u32, (String, Instant)>>,
+    /// Rules whose actions are running, and since when, by PID
+    running_actions: Mutex<HashMap<u32, (Vec<String>, Instant)>>,
+    dirty: Mutex<bool>,
+}
+
+impl StatsStore {
+    /// Loads the store, starting empty if there's nothing saved or it's unreadable
+    pub fn load(storage: Arc<dyn Storage>) -> Self {
+        let data = match storage.load("stats") {
+            Ok(Some(content)) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable stats: {}", e);
+                StatsData::default()
+            }),
+            Ok(None) => StatsData::default(),
+            Err(e) => {
+                warn!("Failed to load stats, starting empty: {}", e);
+                StatsData::default()
+            }
+        };
+        info!("Loaded duration history for {} command(s)", data.commands.len());
+
+        Self {
+            storage,
+            data: Mutex::new(data),
+            pending: Mutex::new(HashMap::new()),
+            running_actions: Mutex::new(HashMap::new()),
+            dirty: Mutex::new(false),
+        }
+    }
+
+    /// Key used to group runs of the same command, e.g. `cargo build` for `cargo build --release`
+    pub fn command_key(command: &str) -> String {
+        let command_line = CommandLine::parse(command);
+        match command_line.args.iter().find(|arg| !arg.starts_with('-')) {
+            Some(subcommand) => format!("{} {}", command_line.exe, subcommand),
+            None => command_line.exe,
+        }
+    }
+
+    /// Expected duration in seconds (median of recent runs), if the command has history
+    pub fn expected_duration(&self, command: &str) -> Option<u64> {
+        let key = Self::command_key(command);
+        let data = self.data.lock().unwrap();
+        let stats = data.commands.get(&key)?;
+        if stats.durations.is_empty() {
+            return None;
+        }
+        let mut sorted = stats.durations.clone();
+        sorted.sort_unstable();
+        Some(sorted[sorted.len() / 2])
+    }
+
+    /// Recorded durations in seconds, by command key
+    pub fn durations(&self) -> Vec<(String, Vec<u64>)> {
+        self.data.lock().unwrap().commands.iter()
+            .map(|(key, stats)| (key.clone(), stats.durations.clone()))
+            .collect()
+    }
+
+    /// Commands currently running, by PID, with how long they have been
+    pub fn running(&self) -> Vec<(u32, String, Duration)> {
+        let mut running: Vec<_> = self.pending.lock().unwrap().iter()
+            .map(|(pid, (key, started))| (*pid, key.clone(), started.elapsed()))
+            .collect();
+        running.sort_by_key(|(_, _, elapsed)| std::cmp::Reverse(*elapsed));
+        running
+    }
+
+    /// Starts timing a command, which has been running for `running_for` if it started before the
+    /// service did
+    pub fn command_started(&self, pid: u32, command: &str, running_for: Duration) {
+        let key = Self::command_key(command);
+        if key.is_empty() {
+            return;
+        }
+        let started = Instant::now().checked_sub(running_for).unwrap_or_else(Instant::now);
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, (_, started)| started.elapsed() < MAX_RUNNING_TIME);
+        pending.insert(pid, (key, started));
+    }
+
+    /// Starts counting action time of a command for the budgets, unless it already is
+    pub fn actions_started(&self, pid: u32, rules: Vec<String>) {
+        if rules.is_empty() {
+            return;
+        }
+        let mut running_actions = self.running_actions.lock().unwrap();
+        running_actions.retain(|_, (_, started)| started.elapsed() < MAX_RUNNING_TIME);
+        running_actions.entry(pid).or_insert_with(|| (rules, Instant::now()));
+    }
+
+    /// How long actions ran today, including those still running, for a rule or all of them
+    pub fn action_time_today(&self, rule: Option<&str>) -> Duration {
+        let mut data = self.data.lock().unwrap();
+        data.action_time.roll_over(&budget::local_date());
+        let recorded = match rule {
+            Some(rule) => data.action_time.rules.get(rule).copied().unwrap_or_default(),
+            None => data.action_time.total,
+        };
+        let running = self.running_actions.lock().unwrap().values()
+            .filter(|(rules, _)| rule.is_none_or(|rule| rules.iter().any(|r| r == rule)))
+            .map(|(_, started)| started.elapsed().as_secs())
+            .sum::<u64>();
+        Duration::from_secs(recorded + running)
+    }
+
+    pub fn command_ended(&self, pid: u32) {
+        if let Some((rules, started)) = self.running_actions.lock().unwrap().remove(&pid) {
+            let seconds = started.elapsed().as_secs();
+            let mut data = self.data.lock().unwrap();
+            data.action_time.roll_over(&budget::local_date());
+            data.action_time.total += seconds;
+            for rule in rules {
+                *data.action_time.rules.entry(rule).or_default() += seconds;
+            }
+            *self.dirty.lock().unwrap() = true;
+        }
+
+        let Some((key, started)) = self.pending.lock().unwrap().remove(&pid) else {
+            return;
+        };
+        let duration = started.elapsed().as_secs();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        let mut data = self.data.lock().unwrap();
+        let stats = data.commands.entry(key.clone()).or_default();
+        stats.durations.push(duration);
+        if stats.durations.len() > MAX_SAMPLES {
+            stats.durations.remove(0);
+        }
+        stats.last_seen = now;
+
+        if data.commands.len() > MAX_COMMANDS {
+            let oldest = data.commands.iter()
+                .min_by_key(|(_, stats)| stats.last_seen)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                data.commands.remove(&oldest);
+            }
+        }
+
+        debug!("Recorded {}s for '{}'", duration, key);
+        *self.dirty.lock().unwrap() = true;
+    }
+
+    /// Saves the store if anything changed since the last save; a failed save is tried again
+    /// next time
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        {
+            let mut dirty = self.dirty.lock().unwrap();
+            if !*dirty {
+                return Ok(());
+            }
+            // Cleared first so changes made while saving aren't lost
+            *dirty = false;
+        }
+
+        let result = serde_json::to_string(&*self.data.lock().unwrap())
+            .map_err(Into::into)
+            .and_then(|content| self.storage.save("stats", &content));
+        if result.is_err() {
+            *self.dirty.lock().unwrap() = true;
+        }
+        result
+    }
+}
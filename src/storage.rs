@@ -0,0 +1,226 @@
+// Persistence backends
+// What the service keeps across restarts goes through a `Storage`: documents the stores keep whole,
+// like the command durations and the budgets' action time (`stats`) and the streak counts
+// (`streaks`), and an append-only log of the recent events (`recent`). `[storage] backend` picks
+// where they go:
+// - "file" (default): JSON files in ~/.viberot, the log keeping the last `recent_events` entries
+// - "sqlite": one database, ~/.viberot/viberot.db, keeping every event for dashboards (`sqlite`
+//   cargo feature)
+// - "memory": nothing is written to disk, for headless and embedded setups; CLI commands reading
+//   the history, like `recent`, find none
+// CLI commands open the backend the config names, so they read what the service wrote.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{StorageBackend, StorageConfig};
+
+pub type StorageError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Documents the stores save, and the event log
+const DOCUMENTS: &[&str] = &["stats", "streaks", RECENT];
+const RECENT: &str = "recent";
+/// The "sqlite" backend's database, unless `[storage] path` says otherwise
+const DATABASE_FILE: &str = "viberot.db";
+
+/// Where the stores keep their data
+pub trait Storage: Send + Sync {
+    /// The document last saved under `name`, None if there's none
+    fn load(&self, name: &str) -> Result<Option<String>, StorageError>;
+
+    /// Replaces the document saved under `name`
+    fn save(&self, name: &str, content: &str) -> Result<(), StorageError>;
+
+    /// Adds records, JSON values, to the end of the event log
+    fn append_events(&self, records: &[String]) -> Result<(), StorageError>;
+
+    /// The last `limit` records of the event log, oldest first
+    fn events(&self, limit: usize) -> Result<Vec<String>, StorageError>;
+}
+
+/// Opens the configured backend; `dir` is ~/.viberot, and `event_capacity` how many events the
+/// file backend keeps
+pub fn open(config: &StorageConfig, dir: &Path, event_capacity: usize) -> Result<Arc<dyn Storage>, StorageError> {
+    match config.backend.unwrap_or_default() {
+        StorageBackend::File => Ok(Arc::new(FileStorage { dir: dir.to_path_buf(), event_capacity })),
+        StorageBackend::Memory => Ok(Arc::new(MemoryStorage::default())),
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => {
+            let path = config.path.as_ref().map_or_else(|| dir.join(DATABASE_FILE), PathBuf::from);
+            Ok(Arc::new(sqlite::SqliteStorage::open(&path)?))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        StorageBackend::Sqlite => Err("`[storage] backend = \"sqlite\"` needs a build with the `sqlite` feature".into()),
+    }
+}
+
+/// What the backends write to `dir` in their default places, the database's journal included
+pub fn default_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = DOCUMENTS.iter().map(|name| FileStorage::path_in(dir, name)).collect();
+    files.extend(["", "-wal", "-shm"].map(|suffix| dir.join(format!("{}{}", DATABASE_FILE, suffix))));
+    files
+}
+
+/// JSON files, one per document, and `recent.json` for the event log
+struct FileStorage {
+    dir: PathBuf,
+    event_capacity: usize,
+}
+
+impl FileStorage {
+    fn path(&self, name: &str) -> PathBuf {
+        Self::path_in(&self.dir, name)
+    }
+
+    fn path_in(dir: &Path, name: &str) -> PathBuf {
+        dir.join(format!("{}.json", name))
+    }
+
+    /// Writes through a temporary file, so readers never see half of it
+    fn write(&self, name: &str, content: &str) -> Result<(), StorageError> {
+        let path = self.path(name);
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, content)?;
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self, name: &str) -> Result<Option<String>, StorageError> {
+        match std::fs::read_to_string(self.path(name)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read {:?}: {}", self.path(name), e).into()),
+        }
+    }
+
+    fn save(&self, name: &str, content: &str) -> Result<(), StorageError> {
+        self.write(name, content)
+    }
+
+    fn append_events(&self, records: &[String]) -> Result<(), StorageError> {
+        let mut events: Vec<serde_json::Value> = match self.load(RECENT)? {
+            // A log that can't be read is started afresh rather than never written again
+            Some(content) => serde_json::from_str(&content).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        for record in records {
+            events.push(serde_json::from_str(record)?);
+        }
+        let excess = events.len().saturating_sub(self.event_capacity);
+        events.drain(..excess);
+        self.write(RECENT, &serde_json::to_string(&events)?)
+    }
+
+    fn events(&self, limit: usize) -> Result<Vec<String>, StorageError> {
+        let Some(content) = self.load(RECENT)? else {
+            return Ok(Vec::new());
+        };
+        let events: Vec<serde_json::Value> = serde_json::from_str(&content)?;
+        let skip = events.len().saturating_sub(limit);
+        Ok(events.iter().skip(skip).map(|event| event.to_string()).collect())
+    }
+}
+
+/// Kept in memory for as long as the service runs
+#[derive(Default)]
+struct MemoryStorage {
+    documents: Mutex<HashMap<String, String>>,
+    events: Mutex<VecDeque<String>>,
+}
+
+/// Events the memory backend keeps, so a service running for months doesn't grow without bound
+const MEMORY_EVENT_CAPACITY: usize = 10_000;
+
+impl Storage for MemoryStorage {
+    fn load(&self, name: &str) -> Result<Option<String>, StorageError> {
+        Ok(self.documents.lock().unwrap().get(name).cloned())
+    }
+
+    fn save(&self, name: &str, content: &str) -> Result<(), StorageError> {
+        self.documents.lock().unwrap().insert(name.to_string(), content.to_string());
+        Ok(())
+    }
+
+    fn append_events(&self, records: &[String]) -> Result<(), StorageError> {
+        let mut events = self.events.lock().unwrap();
+        events.extend(records.iter().cloned());
+        while events.len() > MEMORY_EVENT_CAPACITY {
+            events.pop_front();
+        }
+        Ok(())
+    }
+
+    fn events(&self, limit: usize) -> Result<Vec<String>, StorageError> {
+        let events = self.events.lock().unwrap();
+        Ok(events.iter().skip(events.len().saturating_sub(limit)).cloned().collect())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use rusqlite::{params, Connection, OptionalExtension};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use super::{Storage, StorageError};
+
+    /// Documents and events in one SQLite database; the event log is never trimmed
+    pub struct SqliteStorage {
+        connection: Mutex<Connection>,
+    }
+
+    impl SqliteStorage {
+        pub fn open(path: &Path) -> Result<Self, StorageError> {
+            let connection = Connection::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+            // The service writes while CLI commands read
+            connection.pragma_update(None, "journal_mode", "WAL")?;
+            connection.busy_timeout(std::time::Duration::from_secs(5))?;
+            connection.execute_batch(
+                "CREATE TABLE IF NOT EXISTS documents (name TEXT PRIMARY KEY, content TEXT NOT NULL);
+                 CREATE TABLE IF NOT EXISTS events (id INTEGER PRIMARY KEY AUTOINCREMENT, record TEXT NOT NULL);",
+            )?;
+            Ok(Self { connection: Mutex::new(connection) })
+        }
+    }
+
+    impl Storage for SqliteStorage {
+        fn load(&self, name: &str) -> Result<Option<String>, StorageError> {
+            let connection = self.connection.lock().unwrap();
+            Ok(connection.query_row("SELECT content FROM documents WHERE name = ?1", params![name], |row| row.get(0)).optional()?)
+        }
+
+        fn save(&self, name: &str, content: &str) -> Result<(), StorageError> {
+            let connection = self.connection.lock().unwrap();
+            connection.execute(
+                "INSERT INTO documents (name, content) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET content = excluded.content",
+                params![name, content],
+            )?;
+            Ok(())
+        }
+
+        fn append_events(&self, records: &[String]) -> Result<(), StorageError> {
+            let mut connection = self.connection.lock().unwrap();
+            let transaction = connection.transaction()?;
+            {
+                let mut insert = transaction.prepare_cached("INSERT INTO events (record) VALUES (?1)")?;
+                for record in records {
+                    insert.execute(params![record])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        }
+
+        fn events(&self, limit: usize) -> Result<Vec<String>, StorageError> {
+            let connection = self.connection.lock().unwrap();
+            let mut select = connection.prepare_cached("SELECT record FROM (SELECT id, record FROM events ORDER BY id DESC LIMIT ?1) ORDER BY id")?;
+            // A negative limit is none at all
+            let limit = i64::try_from(limit).unwrap_or(-1);
+            let records = select.query_map(params![limit], |row| row.get(0))?.collect::<Result<Vec<String>, _>>()?;
+            Ok(records)
+        }
+    }
+}
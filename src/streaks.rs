@@ -0,0 +1,197 @@
+// Streaks and achievements
+// Overlays tell the service when they come on screen and when the user closes them
+// (`{"event": "dismissed"}` on stdout). Each overlay that showed up is counted as dismissed,
+// quickly if within 10 seconds, or as sat through when it stayed until its command ended. Sitting
+// through overlays in a row makes a streak, and achievements unlock along the way, announced with
+// a notification. Persisted through the configured `Storage`, as JSON in ~/.viberot/streaks.json
+// by default, which `viberot-service status` shows.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::audit;
+use crate::i18n::{t, Msg};
+use crate::storage::Storage;
+
+/// Dismissals sooner than this after the overlay showed up count as quick
+pub const QUICK_DISMISSAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StreakData {
+    /// Overlays that stayed until their command ended
+    pub completed: u64,
+    pub dismissed: u64,
+    /// Dismissed within `QUICK_DISMISSAL` of showing up
+    pub quick_dismissals: u64,
+    /// Overlays sat through since the last dismissal
+    pub streak: u64,
+    pub best_streak: u64,
+    /// Unlock time (Unix timestamp) by achievement ID
+    #[serde(default)]
+    pub achievements: BTreeMap<String, u64>,
+}
+
+pub struct Achievement {
+    pub id: &'static str,
+    name: Msg,
+    description: Msg,
+    reached: fn(&StreakData) -> bool,
+}
+
+impl Achievement {
+    pub fn name(&self) -> &'static str {
+        t(self.name)
+    }
+
+    pub fn description(&self) -> &'static str {
+        t(self.description)
+    }
+}
+
+pub const ACHIEVEMENTS: &[Achievement] = &[
+    Achievement {
+        id: "sat_through",
+        name: Msg::AchievementSatThrough,
+        description: Msg::AchievementSatThroughInfo,
+        reached: |data| data.completed >= 1,
+    },
+    Achievement {
+        id: "on_a_roll",
+        name: Msg::AchievementOnARoll,
+        description: Msg::AchievementOnARollInfo,
+        reached: |data| data.best_streak >= 5,
+    },
+    Achievement {
+        id: "unskippable",
+        name: Msg::AchievementUnskippable,
+        description: Msg::AchievementUnskippableInfo,
+        reached: |data| data.best_streak >= 25,
+    },
+    Achievement {
+        id: "connoisseur",
+        name: Msg::AchievementConnoisseur,
+        description: Msg::AchievementConnoisseurInfo,
+        reached: |data| data.completed >= 100,
+    },
+    Achievement {
+        id: "speedrunner",
+        name: Msg::AchievementSpeedrunner,
+        description: Msg::AchievementSpeedrunnerInfo,
+        reached: |data| data.quick_dismissals >= 10,
+    },
+];
+
+/// Persistent streak counts, fed with the actions the orchestrator reports
+pub struct StreakTracker {
+    storage: Arc<dyn Storage>,
+    data: Mutex<StreakData>,
+    /// Actions on screen, and since when, by monitored PID and action key
+    showing: Mutex<HashMap<(u32, String), Instant>>,
+    dirty: Mutex<bool>,
+}
+
+impl StreakTracker {
+    /// Loads the counts, starting afresh if there are none saved or they're unreadable
+    pub fn load(storage: Arc<dyn Storage>) -> Self {
+        let data = match storage.load("streaks") {
+            Ok(Some(content)) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable streaks: {}", e);
+                StreakData::default()
+            }),
+            Ok(None) => StreakData::default(),
+            Err(e) => {
+                warn!("Failed to load streaks, starting afresh: {}", e);
+                StreakData::default()
+            }
+        };
+
+        Self {
+            storage,
+            data: Mutex::new(data),
+            showing: Mutex::new(HashMap::new()),
+            dirty: Mutex::new(false),
+        }
+    }
+
+    /// An action came on screen for a command; only the first report counts
+    pub fn visible(&self, pid: u32, action: String) {
+        self.showing.lock().unwrap().entry((pid, action)).or_insert_with(Instant::now);
+    }
+
+    /// The user closed an action that was on screen, returning the achievements this unlocked
+    pub fn dismissed(&self, pid: u32, action: String) -> Vec<&'static Achievement> {
+        let Some(shown) = self.showing.lock().unwrap().remove(&(pid, action)) else {
+            return Vec::new();
+        };
+        let mut data = self.data.lock().unwrap();
+        data.dismissed += 1;
+        if shown.elapsed() < QUICK_DISMISSAL {
+            data.quick_dismissals += 1;
+        }
+        if data.streak > 0 {
+            debug!("Streak of {} ended by a dismissal", data.streak);
+        }
+        data.streak = 0;
+        self.unlock(&mut data)
+    }
+
+    /// The command of an action ended, so an action still on screen was sat through. Returns the
+    /// achievements this unlocked.
+    pub fn ended(&self, pid: u32, action: String) -> Vec<&'static Achievement> {
+        if self.showing.lock().unwrap().remove(&(pid, action)).is_none() {
+            return Vec::new();
+        }
+        let mut data = self.data.lock().unwrap();
+        data.completed += 1;
+        data.streak += 1;
+        data.best_streak = data.best_streak.max(data.streak);
+        self.unlock(&mut data)
+    }
+
+    fn unlock(&self, data: &mut StreakData) -> Vec<&'static Achievement> {
+        *self.dirty.lock().unwrap() = true;
+        let unlocked: Vec<&'static Achievement> = ACHIEVEMENTS.iter()
+            .filter(|achievement| !data.achievements.contains_key(achievement.id) && (achievement.reached)(data))
+            .collect();
+        for achievement in &unlocked {
+            info!("Achievement unlocked: {}", achievement.name());
+            data.achievements.insert(achievement.id.to_string(), audit::unix_now());
+        }
+        unlocked
+    }
+
+    /// Saves the counts if anything changed since the last save
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        {
+            let mut dirty = self.dirty.lock().unwrap();
+            if !*dirty {
+                return Ok(());
+            }
+            *dirty = false;
+        }
+        let content = serde_json::to_string(&*self.data.lock().unwrap())?;
+        self.storage.save("streaks", &content)
+    }
+}
+
+/// The counts the service last wrote, if it ever did
+pub fn load(storage: &dyn Storage) -> Option<StreakData> {
+    serde_json::from_str(&storage.load("streaks").ok()??).ok()
+}
+
+/// Human-readable form of the counts for `viberot-service status`
+pub fn to_text(data: &StreakData) -> String {
+    let mut text = format!(
+        "Overlays sat through: {} (streak {}, best {}); dismissed: {}, {} within {}s\n",
+        data.completed, data.streak, data.best_streak, data.dismissed, data.quick_dismissals, QUICK_DISMISSAL.as_secs()
+    );
+    for achievement in ACHIEVEMENTS {
+        if data.achievements.contains_key(achievement.id) {
+            text.push_str(&format!("  ★ {}: {}\n", achievement.name(), achievement.description()));
+        }
+    }
+    text
+}
@@ -0,0 +1,276 @@
+// Rule suggestions
+// `viberot-service suggest` proposes rules for slow commands that are run often, so nobody has to
+// start from a blank config. Commands are grouped like the duration stats (`cargo build`,
+// `npm install`). Durations come from the service's own stats and, with `--from-history`, from
+// zsh's extended history or atuin's database. bash and fish don't record durations, so their
+// commands are only proposed if their category (build, test, install, deploy) says they're usually
+// slow. Commands an existing rule already matches are left out.
+
+use globset::{GlobSet, GlobSetBuilder};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::enrichment;
+use crate::mutes::format_duration;
+use crate::rule_engine::command_glob;
+use crate::stats::StatsStore;
+
+/// A command from a shell history
+struct HistoryEntry {
+    command: String,
+    /// Seconds, if the history records it
+    duration: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Suggestion {
+    /// Command group, e.g. `cargo build`
+    pub command: String,
+    /// Glob for the rule's `command`
+    pub pattern: String,
+    pub runs: usize,
+    /// Median duration in seconds, if anything recorded one
+    pub typical_secs: Option<u64>,
+    pub category: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SuggestReport {
+    /// What was read, e.g. "1234 commands from /home/me/.zsh_history"
+    pub sources: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// A history file and the commands read from it
+type History = (PathBuf, Vec<HistoryEntry>);
+/// Parses the contents of one kind of history file
+type HistoryReader = fn(&[u8]) -> Vec<HistoryEntry>;
+
+#[derive(Default)]
+struct CommandGroup {
+    runs: usize,
+    durations: Vec<u64>,
+}
+
+pub fn suggest(
+    config: &Config,
+    stats: &StatsStore,
+    from_history: bool,
+    min_duration: Duration,
+    min_runs: usize,
+) -> Result<SuggestReport, Box<dyn std::error::Error + Send + Sync>> {
+    let mut groups: HashMap<String, CommandGroup> = HashMap::new();
+    let mut sources = Vec::new();
+
+    let recorded = stats.durations();
+    if !recorded.is_empty() {
+        sources.push(format!("{} commands timed by the service", recorded.len()));
+    }
+    for (key, durations) in recorded {
+        let group = groups.entry(key).or_default();
+        group.runs += durations.len();
+        group.durations.extend(durations);
+    }
+
+    if from_history {
+        for (path, entries) in read_histories(config)? {
+            sources.push(format!("{} commands from {}", entries.len(), path.display()));
+            for entry in entries {
+                let key = StatsStore::command_key(&entry.command);
+                if key.is_empty() {
+                    continue;
+                }
+                let group = groups.entry(key).or_default();
+                group.runs += 1;
+                group.durations.extend(entry.duration);
+            }
+        }
+    }
+
+    let covered = covered_patterns(config)?;
+    let mut suggestions: Vec<Suggestion> = groups.into_iter()
+        .filter(|(key, group)| group.runs >= min_runs && is_plain(key) && !covered.is_match(key))
+        .filter_map(|(key, mut group)| {
+            group.durations.sort_unstable();
+            let typical_secs = group.durations.get(group.durations.len() / 2).copied();
+            let category = enrichment::classify(&key);
+            let slow = match typical_secs {
+                Some(secs) => secs >= min_duration.as_secs(),
+                None => category.is_some(),
+            };
+            slow.then(|| Suggestion {
+                pattern: format!("*{}*", key),
+                command: key,
+                runs: group.runs,
+                typical_secs,
+                category,
+            })
+        })
+        .collect();
+
+    // Where the most time goes first, then what is run most
+    suggestions.sort_by(|a, b| {
+        let waited = |s: &Suggestion| s.typical_secs.map(|secs| secs * s.runs as u64);
+        waited(b).cmp(&waited(a)).then(b.runs.cmp(&a.runs)).then(a.command.cmp(&b.command))
+    });
+    Ok(SuggestReport { sources, suggestions })
+}
+
+/// Human-readable form of a report, with the command adding each rule
+pub fn to_text(report: &SuggestReport) -> String {
+    let mut text = String::new();
+    if report.sources.is_empty() {
+        text.push_str("Nothing to go on yet; try --from-history, or let the service time some commands\n");
+        return text;
+    }
+    text.push_str(&format!("Read {}\n", report.sources.join(", ")));
+    if report.suggestions.is_empty() {
+        text.push_str("No slow commands without a rule found\n");
+        return text;
+    }
+    for suggestion in &report.suggestions {
+        let why = match (suggestion.typical_secs, suggestion.category) {
+            (Some(secs), _) => format!("usually {}", format_duration(secs)),
+            (None, Some(category)) => format!("{} command", category),
+            (None, None) => String::new(),
+        };
+        text.push_str(&format!("\n{} ({} runs, {})\n", suggestion.command, suggestion.runs, why));
+        text.push_str(&format!("  viberot-service rules add --name {} --command '{}' --action overlay\n",
+                               suggestion.command.replace(' ', "-"), suggestion.pattern));
+    }
+    text
+}
+
+/// Command groups that can go in a glob and a shell command as they are
+fn is_plain(key: &str) -> bool {
+    !key.contains(['*', '?', '[', ']', '{', '}', '\'', '"', '\\'])
+}
+
+/// Command globs of the configured rules
+fn covered_patterns(config: &Config) -> Result<GlobSet, Box<dyn std::error::Error + Send + Sync>> {
+    let mut builder = GlobSetBuilder::new();
+    for rule in &config.rules {
+        for pattern in rule.command_globs() {
+            builder.add(command_glob(pattern, rule.case_insensitive)?);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// Reads every shell history there is; atuin's database replaces the others as it holds the same
+/// commands, with durations
+fn read_histories(config: &Config) -> Result<Vec<History>, Box<dyn std::error::Error + Send + Sync>> {
+    let home = dirs::home_dir().ok_or("Could not find home directory")?;
+    let data_dir = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".local").join("share"));
+
+    if let Some(atuin_db) = config.atuin.db_path().filter(|path| path.exists()) {
+        #[cfg(feature = "atuin")]
+        return Ok(vec![(atuin_db.clone(), read_atuin(&atuin_db)?)]);
+        #[cfg(not(feature = "atuin"))]
+        eprintln!("Found atuin's history at {:?}, but this build lacks the `atuin` feature; reading shell histories instead", atuin_db);
+    }
+
+    let readers: [(PathBuf, HistoryReader); 4] = [
+        (home.join(".bash_history"), read_bash),
+        (home.join(".zsh_history"), read_zsh),
+        (home.join(".histfile"), read_zsh),
+        (data_dir.join("fish").join("fish_history"), read_fish),
+    ];
+    let mut histories = Vec::new();
+    for (path, read) in readers {
+        match std::fs::read(&path) {
+            Ok(content) => histories.push((path, read(&content))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to read {:?}: {}", path, e).into()),
+        }
+    }
+    Ok(histories)
+}
+
+/// One command per line, with `#<timestamp>` lines if HISTTIMEFORMAT is set
+fn read_bash(content: &[u8]) -> Vec<HistoryEntry> {
+    String::from_utf8_lossy(content).lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter(|line| !line.strip_prefix('#').is_some_and(|rest| rest.bytes().all(|b| b.is_ascii_digit())))
+        .map(|line| HistoryEntry { command: line.to_string(), duration: None })
+        .collect()
+}
+
+/// Plain, or `: <start>:<seconds>;<command>` with EXTENDED_HISTORY; lines ending in `\` continue
+fn read_zsh(content: &[u8]) -> Vec<HistoryEntry> {
+    let text = String::from_utf8_lossy(&unmetafy(content)).into_owned();
+    let mut entries = Vec::new();
+    let mut continued: Option<String> = None;
+    for line in text.lines() {
+        let mut line = match continued.take() {
+            Some(previous) => previous + "\n" + line,
+            None => line.to_string(),
+        };
+        if line.ends_with('\\') {
+            line.pop();
+            continued = Some(line);
+            continue;
+        }
+
+        let extended = line.strip_prefix(": ").and_then(|rest| rest.split_once(';'));
+        let entry = match extended {
+            Some((meta, command)) => HistoryEntry {
+                command: command.to_string(),
+                duration: meta.rsplit_once(':').and_then(|(_, seconds)| seconds.parse().ok()),
+            },
+            None => HistoryEntry { command: line, duration: None },
+        };
+        if !entry.command.trim().is_empty() {
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+/// Undoes zsh's escaping of bytes that are special to it (0x83 followed by the byte XOR 32)
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut unmetafied = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == 0x83 {
+            if let Some(&next) = bytes.next() {
+                unmetafied.push(next ^ 32);
+            }
+        } else {
+            unmetafied.push(byte);
+        }
+    }
+    unmetafied
+}
+
+/// `- cmd: <command>` entries, with `\n` and `\\` escaped
+fn read_fish(content: &[u8]) -> Vec<HistoryEntry> {
+    String::from_utf8_lossy(content).lines()
+        .filter_map(|line| line.strip_prefix("- cmd: "))
+        .map(|escaped| {
+            let mut command = String::with_capacity(escaped.len());
+            let mut chars = escaped.chars();
+            while let Some(c) = chars.next() {
+                match (c, chars.clone().next()) {
+                    ('\\', Some('n')) => { command.push('\n'); chars.next(); }
+                    ('\\', Some('\\')) => { command.push('\\'); chars.next(); }
+                    _ => command.push(c),
+                }
+            }
+            HistoryEntry { command, duration: None }
+        })
+        .collect()
+}
+
+#[cfg(feature = "atuin")]
+fn read_atuin(path: &std::path::Path) -> Result<Vec<HistoryEntry>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(crate::platform::atuin::read_history(path)?
+        .into_iter()
+        .map(|row| HistoryEntry { duration: row.duration_secs(), command: row.command })
+        .collect())
+}
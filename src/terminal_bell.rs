@@ -0,0 +1,75 @@
+// Terminal end notifications
+// `bell` actions tell users who live in terminals that a command finished, in the terminal it ran
+// in and without any GUI: a BEL, which terminals turn into a bell, an urgency hint or a tab marker,
+// and the text as an OSC 777 notification, which WezTerm, foot, Ghostty and others show as a
+// desktop notification. In tmux, which keeps escape sequences to itself, the text is shown with
+// `tmux display-message` on the command's pane instead. The terminal is the one the shell hooks
+// report (`tty`, and `TMUX`/`TMUX_PANE` in the environment), for local commands only.
+
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::platform::{ProcessEvent, Provenance};
+use crate::terminal_progress::{is_terminal_device, write_sequence};
+
+/// Environment variables `wrap` reports for finding the command's tmux pane, like the hooks do
+#[cfg(unix)]
+pub const ENV_VARS: &[&str] = &["TMUX", "TMUX_PANE"];
+
+/// Longest `tmux display-message` may take
+const TMUX_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where a command ran, as far as the shell hooks told
+#[derive(Debug, Clone)]
+pub struct Terminal {
+    tty: Option<PathBuf>,
+    /// Socket of the tmux server and the pane, e.g. `%3`
+    tmux: Option<(String, String)>,
+}
+
+impl Terminal {
+    /// The terminal of a local command, if its shell reported one
+    pub fn of(event: &ProcessEvent) -> Option<Terminal> {
+        // Events from other machines name their terminals
+        if event.provenance.hostname != Provenance::local().hostname {
+            return None;
+        }
+        let tty = event.tty.as_deref().map(PathBuf::from).filter(|tty| is_terminal_device(tty));
+        let tmux = event.environment.as_ref().and_then(|env| {
+            // `$TMUX` is "<socket>,<server PID>,<session>"
+            let socket = env.get("TMUX")?.split(',').next()?.to_string();
+            let pane = env.get("TMUX_PANE")?.clone();
+            (!socket.is_empty() && pane.starts_with('%')).then_some((socket, pane))
+        });
+        (tty.is_some() || tmux.is_some()).then_some(Terminal { tty, tmux })
+    }
+
+    /// Rings the bell, if asked to, and shows the text
+    pub async fn ring(&self, text: &str, bell: bool) {
+        let mut sequence = String::new();
+        if bell {
+            sequence.push('\x07');
+        }
+        if self.tmux.is_none() {
+            // Separators and control characters would end the sequence early
+            let text: String = text.chars().map(|c| if c == ';' || c.is_control() { ' ' } else { c }).collect();
+            sequence.push_str(&format!("\x1b]777;notify;VibeRot;{}\x07", text));
+        }
+        if let Some(ref tty) = self.tty {
+            write_sequence(tty, &sequence);
+        }
+        if let Some((ref socket, ref pane)) = self.tmux {
+            let display = tokio::process::Command::new("tmux")
+                .args(["-S", socket, "display-message", "-t", pane, text])
+                .kill_on_drop(true)
+                .status();
+            match tokio::time::timeout(TMUX_TIMEOUT, display).await {
+                Ok(Ok(status)) if status.success() => {}
+                Ok(Ok(status)) => debug!("tmux display-message for pane {} failed ({})", pane, status),
+                Ok(Err(e)) => debug!("Failed to run tmux: {}", e),
+                Err(_) => debug!("tmux display-message for pane {} timed out", pane),
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// Terminal progress
+// Shows matched commands as progress in the terminal they run in, with the OSC 9;4 sequence that
+// ConEmu introduced and Windows Terminal, WezTerm, Ghostty and others picked up: a bar in the tab
+// or title bar, or the taskbar button. It needs no overlay, launcher entry or desktop at all.
+// The shell hooks report the terminal device of the shell (`tty`), which the sequences are
+// written to. Progress is determinate when the command has a duration history, indeterminate
+// otherwise, and cleared when it ends.
+// Only terminal devices (/dev/pts/*, /dev/tty*) of local commands are written to, and without
+// waiting: the hooks' `tty` can't make the service write anywhere else, and a terminal paused
+// with Ctrl+S doesn't hold it up.
+
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::platform::{ProcessEvent, Provenance};
+
+/// How often determinate progress is refreshed
+const PROGRESS_UPDATE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Terminal and refresh task per monitored PID
+type ProgressUpdaters = HashMap<u32, (PathBuf, JoinHandle<()>)>;
+
+/// Tracks progress sequences for running matched commands
+pub struct TerminalProgress {
+    enabled: bool,
+    updaters: Arc<Mutex<ProgressUpdaters>>,
+}
+
+impl TerminalProgress {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            updaters: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Starts showing progress in the terminal of a matched command
+    pub fn start(&self, event: &ProcessEvent) {
+        if !self.enabled {
+            return;
+        }
+        // Events from other machines name their terminals
+        let local = event.provenance.hostname == Provenance::local().hostname;
+        let Some(tty) = event.tty.as_deref().filter(|_| local).map(PathBuf::from).filter(|tty| is_terminal_device(tty)) else {
+            debug!("No terminal known for PID {}, not showing progress in it", event.pid);
+            return;
+        };
+
+        let expected = event.expected_duration.filter(|d| *d > 0).map(Duration::from_secs);
+        let started = Instant::now();
+        let update_tty = tty.clone();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PROGRESS_UPDATE_INTERVAL);
+            loop {
+                interval.tick().await;
+                // Never report completion before the command actually ends
+                let progress = expected.map(|expected| {
+                    (started.elapsed().as_secs_f64() / expected.as_secs_f64()).min(0.99)
+                });
+                let sequence = match progress {
+                    Some(progress) => format!("\x1b]9;4;1;{}\x07", (progress * 100.0) as u32),
+                    None => "\x1b]9;4;3;0\x07".to_string(),
+                };
+                write_sequence(&update_tty, &sequence);
+                if progress.is_none() {
+                    // Indeterminate state doesn't need refreshing
+                    break;
+                }
+            }
+        });
+
+        if let Some((old_tty, old_handle)) = self.updaters.lock().unwrap().insert(event.pid, (tty, handle)) {
+            old_handle.abort();
+            clear_progress(&old_tty);
+        }
+    }
+
+    /// Shows the progress the command's source reported instead of the estimate
+    pub fn report(&self, pid: u32, percent: u8) {
+        if let Some((tty, handle)) = self.updaters.lock().unwrap().get(&pid) {
+            handle.abort();
+            write_sequence(tty, &format!("\x1b]9;4;1;{}\x07", percent.min(100)));
+        }
+    }
+
+    /// Clears the progress when the command ends
+    pub fn finish(&self, pid: u32) {
+        if let Some((tty, handle)) = self.updaters.lock().unwrap().remove(&pid) {
+            handle.abort();
+            clear_progress(&tty);
+        }
+    }
+
+    /// Clears all progress, e.g. on shutdown
+    pub fn clear_all(&self) {
+        for (_, (tty, handle)) in self.updaters.lock().unwrap().drain() {
+            handle.abort();
+            clear_progress(&tty);
+        }
+    }
+}
+
+/// Pseudo-terminals and consoles, like `/dev/pts/3` or `/dev/ttys003`
+pub fn is_terminal_device(tty: &Path) -> bool {
+    if !tty.components().all(|component| matches!(component, Component::RootDir | Component::Normal(_))) {
+        return false;
+    }
+    tty.starts_with("/dev/pts/")
+        || (tty.parent() == Some(Path::new("/dev")) && tty.file_name().is_some_and(|name| name.to_string_lossy().starts_with("tty")))
+}
+
+fn clear_progress(tty: &Path) {
+    write_sequence(tty, "\x1b]9;4;0;0\x07");
+}
+
+/// Writes an escape sequence to a terminal device, if it is one
+pub fn write_sequence(tty: &Path, sequence: &str) {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        // The service mustn't become the terminal's controlling process or block on it
+        options.custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK);
+    }
+    let result = options.open(tty).and_then(|mut terminal| {
+        if !terminal.is_terminal() {
+            return Err(std::io::Error::other("not a terminal"));
+        }
+        terminal.write_all(sequence.as_bytes())
+    });
+    if let Err(e) = result {
+        debug!("Failed to write to terminal {}: {}", tty.display(), e);
+    }
+}
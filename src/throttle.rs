@@ -0,0 +1,301 @@
+// Suspending background processes
+// `throttle` actions pause processes for as long as a heavy command runs, e.g. a game or OBS's
+// replay buffer: SIGSTOP and SIGCONT on Unix, NtSuspendProcess and NtResumeProcess on Windows. A
+// process stays suspended while any running command holds it, and is resumed with the last one.
+// Nothing may stay suspended by accident, so every process is written to ~/.viberot/suspended.json
+// before it's suspended: shutdown resumes everything, and a service that crashed resumes what the
+// file lists when it starts again.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::capture::{normalize_process_name, process_matches};
+
+/// A process suspended by a `throttle` action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Suspended {
+    name: String,
+    /// Monitored PIDs of the commands holding it suspended
+    #[serde(default)]
+    holders: BTreeSet<u32>,
+    /// Suspended by an action with `resume_on_end = false`, so it's held until resumed on purpose
+    #[serde(default)]
+    kept: bool,
+}
+
+pub struct Throttler {
+    path: PathBuf,
+    /// Suspended processes, by PID
+    suspended: Mutex<BTreeMap<u32, Suspended>>,
+}
+
+impl Throttler {
+    /// Resumes the processes a previous run left suspended, then starts with none
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let left: BTreeMap<u32, Suspended> = std::fs::read_to_string(&path).ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        for (pid, process) in &left {
+            // The PID may belong to another process by now
+            if process_name(*pid).is_some_and(|name| name == process.name) {
+                warn!("Resuming {} (PID {}), left suspended when the service last stopped", process.name, pid);
+                if let Err(e) = resume(*pid) {
+                    warn!("Failed to resume {} (PID {}): {}", process.name, pid, e);
+                }
+            }
+        }
+        let throttler = Self { path, suspended: Mutex::new(BTreeMap::new()) };
+        if !left.is_empty() {
+            throttler.save();
+        }
+        throttler
+    }
+
+    /// Suspends processes for a command, until it ends or, with `keep`, until they're resumed on
+    /// purpose. Returns the names of the processes that couldn't be suspended, with why
+    pub fn suspend(&self, command_pid: u32, targets: &[(u32, String)], keep: bool) -> Vec<String> {
+        let mut suspended = self.suspended.lock().unwrap();
+        let mut failed = Vec::new();
+        for (pid, name) in targets {
+            if let Some(process) = suspended.get_mut(pid) {
+                process.holders.insert(command_pid);
+                process.kept |= keep;
+                continue;
+            }
+            suspended.insert(*pid, Suspended { name: name.clone(), holders: BTreeSet::from([command_pid]), kept: keep });
+            // Written down before it's suspended, so a crash right after still resumes it
+            self.write(&suspended);
+            match suspend(*pid) {
+                Ok(()) => info!("Suspended {} (PID {}) while PID {} runs", name, pid, command_pid),
+                Err(e) => {
+                    suspended.remove(pid);
+                    failed.push(format!("{} (PID {}): {}", name, pid, e));
+                }
+            }
+        }
+        if !failed.is_empty() {
+            self.write(&suspended);
+        }
+        failed
+    }
+
+    /// Lets go of a command's holds, resuming the processes no other command holds
+    pub fn release(&self, command_pid: u32) {
+        let mut suspended = self.suspended.lock().unwrap();
+        let resumable: Vec<u32> = suspended.iter_mut()
+            .filter_map(|(pid, process)| {
+                let held = process.holders.remove(&command_pid);
+                (held && process.holders.is_empty() && !process.kept).then_some(*pid)
+            })
+            .collect();
+        if resumable.is_empty() {
+            return;
+        }
+        for pid in resumable {
+            if let Some(process) = suspended.remove(&pid) {
+                Self::resume_logged(pid, &process.name);
+            }
+        }
+        self.write(&suspended);
+    }
+
+    /// Resumes processes whoever holds them, e.g. for `suspend_on_start = false` actions, or a
+    /// shared action that a command needs again. Also resumes processes suspended by something else
+    pub fn resume(&self, targets: &[(u32, String)]) {
+        if targets.is_empty() {
+            return;
+        }
+        let mut suspended = self.suspended.lock().unwrap();
+        for (pid, name) in targets {
+            suspended.remove(pid);
+            Self::resume_logged(*pid, name);
+        }
+        self.write(&suspended);
+    }
+
+    /// Resumes every process suspended, when the service stops
+    pub fn resume_all(&self) {
+        let mut suspended = self.suspended.lock().unwrap();
+        if suspended.is_empty() {
+            return;
+        }
+        for (pid, process) in std::mem::take(&mut *suspended) {
+            Self::resume_logged(pid, &process.name);
+        }
+        self.write(&suspended);
+    }
+
+    pub fn is_suspended(&self, pid: u32) -> bool {
+        self.suspended.lock().unwrap().contains_key(&pid)
+    }
+
+    /// Names and PIDs of the suspended processes, for the state dump
+    pub fn state_text(&self) -> String {
+        let suspended = self.suspended.lock().unwrap();
+        if suspended.is_empty() {
+            return String::new();
+        }
+        let processes: Vec<String> = suspended.iter().map(|(pid, process)| format!("{} (PID {})", process.name, pid)).collect();
+        format!("Suspended: {}\n", processes.join(", "))
+    }
+
+    fn resume_logged(pid: u32, name: &str) {
+        match resume(pid) {
+            Ok(()) => info!("Resumed {} (PID {})", name, pid),
+            // It may have exited, or been killed, while suspended
+            Err(e) => debug!("Failed to resume {} (PID {}): {}", name, pid, e),
+        }
+    }
+
+    fn save(&self) {
+        self.write(&self.suspended.lock().unwrap());
+    }
+
+    fn write(&self, suspended: &BTreeMap<u32, Suspended>) {
+        let result = serde_json::to_string_pretty(suspended).map_err(std::io::Error::other)
+            .and_then(|content| {
+                let temp_path = self.path.with_extension("json.tmp");
+                std::fs::write(&temp_path, content)?;
+                std::fs::rename(&temp_path, &self.path)
+            });
+        if let Err(e) = result {
+            warn!("Failed to write {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// PIDs and names of the running processes with one of these names, leaving out VibeRot itself
+pub fn find_processes(names: &[String]) -> std::io::Result<Vec<(u32, String)>> {
+    let names: Vec<String> = names.iter().map(|name| normalize_process_name(name)).collect();
+    let own = std::process::id();
+    Ok(running_processes()?.into_iter()
+        .filter(|(pid, process)| *pid != own && names.iter().any(|name| process_matches(process, name)))
+        .collect())
+}
+
+/// Normalized name of a running process
+pub fn process_name(pid: u32) -> Option<String> {
+    running_processes().ok()?.into_iter().find(|(other, _)| *other == pid).map(|(_, name)| name)
+}
+
+/// PIDs and normalized names of the running processes
+#[cfg(target_os = "linux")]
+fn running_processes() -> std::io::Result<Vec<(u32, String)>> {
+    let mut processes = Vec::new();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else {
+            continue;
+        };
+        if let Ok(comm) = std::fs::read_to_string(entry.path().join("comm")) {
+            processes.push((pid, normalize_process_name(&comm)));
+        }
+    }
+    Ok(processes)
+}
+
+/// PIDs and normalized names of the running processes
+#[cfg(target_os = "macos")]
+fn running_processes() -> std::io::Result<Vec<(u32, String)>> {
+    let output = std::process::Command::new("ps").args(["-axco", "pid=,comm="]).output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).lines()
+        .filter_map(|line| {
+            let (pid, name) = line.trim_start().split_once(' ')?;
+            Some((pid.parse().ok()?, normalize_process_name(name)))
+        })
+        .collect())
+}
+
+/// PIDs and normalized names of the running processes
+#[cfg(windows)]
+fn running_processes() -> std::io::Result<Vec<(u32, String)>> {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) }.map_err(std::io::Error::other)?;
+    let mut processes = Vec::new();
+    let mut entry = PROCESSENTRY32W {
+        dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+        ..Default::default()
+    };
+    let mut more = unsafe { Process32FirstW(snapshot, &mut entry) }.is_ok();
+    while more {
+        let length = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
+        let name = String::from_utf16_lossy(&entry.szExeFile[..length]);
+        processes.push((entry.th32ProcessID, normalize_process_name(&name)));
+        more = unsafe { Process32NextW(snapshot, &mut entry) }.is_ok();
+    }
+    let _ = unsafe { CloseHandle(snapshot) };
+    Ok(processes)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn running_processes() -> std::io::Result<Vec<(u32, String)>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "listing processes isn't supported on this platform"))
+}
+
+#[cfg(unix)]
+fn suspend(pid: u32) -> std::io::Result<()> {
+    signal(pid, libc::SIGSTOP)
+}
+
+#[cfg(unix)]
+fn resume(pid: u32) -> std::io::Result<()> {
+    signal(pid, libc::SIGCONT)
+}
+
+#[cfg(unix)]
+fn signal(pid: u32, signal: libc::c_int) -> std::io::Result<()> {
+    // SAFETY: kill has no memory safety preconditions
+    match unsafe { libc::kill(pid as libc::pid_t, signal) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(windows)]
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtSuspendProcess(process: windows::Win32::Foundation::HANDLE) -> i32;
+    fn NtResumeProcess(process: windows::Win32::Foundation::HANDLE) -> i32;
+}
+
+#[cfg(windows)]
+fn suspend(pid: u32) -> std::io::Result<()> {
+    // SAFETY: the handle is open for the duration of the call
+    with_process(pid, |process| unsafe { NtSuspendProcess(process) })
+}
+
+#[cfg(windows)]
+fn resume(pid: u32) -> std::io::Result<()> {
+    // SAFETY: the handle is open for the duration of the call
+    with_process(pid, |process| unsafe { NtResumeProcess(process) })
+}
+
+/// Calls an Nt* function on a process opened to suspend and resume it
+#[cfg(windows)]
+fn with_process(pid: u32, call: impl FnOnce(windows::Win32::Foundation::HANDLE) -> i32) -> std::io::Result<()> {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Threading::{OpenProcess, PROCESS_SUSPEND_RESUME};
+
+    let process = unsafe { OpenProcess(PROCESS_SUSPEND_RESUME, false, pid) }.map_err(std::io::Error::other)?;
+    let status = call(process);
+    let _ = unsafe { CloseHandle(process) };
+    match status {
+        status if status >= 0 => Ok(()),
+        status => Err(std::io::Error::other(format!("NTSTATUS {:#x}", status))),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn suspend(_pid: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "suspending processes isn't supported on this platform"))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn resume(_pid: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "resuming processes isn't supported on this platform"))
+}
@@ -0,0 +1,102 @@
+// Text-to-speech
+// Speaks through what the platform ships: SAPI (System.Speech via PowerShell) on Windows, `say` on
+// macOS and speech-dispatcher's `spd-say` elsewhere. Each announcement runs as a child process
+// that lasts as long as the speech, so announcements take turns instead of talking over each
+// other, and stopping one just kills its process.
+
+use std::sync::{Arc, Mutex};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// The program speech goes through, for checking that it's installed
+#[cfg(windows)]
+pub const PROGRAM: &str = "powershell";
+#[cfg(target_os = "macos")]
+pub const PROGRAM: &str = "say";
+#[cfg(not(any(windows, target_os = "macos")))]
+pub const PROGRAM: &str = "spd-say";
+
+/// Speaks announcements one after the other in the background
+pub struct Speaker {
+    /// Held while speaking
+    turn: Arc<tokio::sync::Mutex<()>>,
+    /// Announcements speaking or waiting for their turn
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Speaker {
+    pub fn new() -> Self {
+        Self {
+            turn: Arc::new(tokio::sync::Mutex::new(())),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Speaks `text` once the announcements before it are done, in `voice` if given (a voice
+    /// name the platform knows, e.g. "Samantha" on macOS)
+    pub fn speak(&self, text: String, voice: Option<String>) {
+        let turn = Arc::clone(&self.turn);
+        let task = tokio::spawn(async move {
+            let _turn = turn.lock().await;
+            debug!("Speaking: {}", text);
+            // Aborting the task drops the child, which kills it and cuts the speech short
+            let result = match command(&text, voice.as_deref()).kill_on_drop(true).spawn() {
+                Ok(mut child) => child.wait().await.map_err(|e| e.to_string()).and_then(|status| {
+                    status.success().then_some(()).ok_or_else(|| format!("exited with {}", status))
+                }),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = result {
+                warn!("Failed to speak with {}: {}", PROGRAM, e);
+            }
+        });
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    /// Stops the announcement being spoken and drops the ones waiting
+    pub fn stop(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(windows)]
+fn command(text: &str, voice: Option<&str>) -> Command {
+    // Passed through the environment so nothing in them needs quoting
+    const SCRIPT: &str = "Add-Type -AssemblyName System.Speech; \
+        $synth = New-Object System.Speech.Synthesis.SpeechSynthesizer; \
+        if ($env:VIBEROT_TTS_VOICE) { $synth.SelectVoice($env:VIBEROT_TTS_VOICE) }; \
+        $synth.Speak($env:VIBEROT_TTS_TEXT)";
+    let mut command = Command::new(PROGRAM);
+    command.args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT])
+        .env("VIBEROT_TTS_TEXT", text)
+        .env("VIBEROT_TTS_VOICE", voice.unwrap_or_default())
+        .creation_flags(0x0800_0000); // CREATE_NO_WINDOW
+    command
+}
+
+#[cfg(target_os = "macos")]
+fn command(text: &str, voice: Option<&str>) -> Command {
+    let mut command = Command::new(PROGRAM);
+    if let Some(voice) = voice {
+        command.args(["-v", voice]);
+    }
+    command.arg("--").arg(text);
+    command
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn command(text: &str, voice: Option<&str>) -> Command {
+    let mut command = Command::new(PROGRAM);
+    // Waits until the text was spoken, so the next announcement doesn't cut in
+    command.arg("--wait");
+    if let Some(voice) = voice {
+        command.args(["--synthesis-voice", voice]);
+    }
+    command.arg("--").arg(text);
+    command
+}
@@ -0,0 +1,262 @@
+// Uninstalling
+// `viberot-service uninstall` undoes what setting VibeRot up did: the blocks in the shell rc
+// files, the shell sockets and the file listing them, ~/.viberot with its state, stats, logs and
+// audit log, and services registered to start VibeRot (systemd user units, launch agents and
+// scheduled tasks mentioning it). A `VIBEROT_PREFIX` directory may be shared, e.g. a portable
+// install, so only what VibeRot writes there goes. The config can be kept for a later reinstall. Everything is
+// listed and confirmed before anything goes, and it refuses while the service answers on its
+// sockets, since the service would put them back. ~/.bash-preexec.sh stays: other tools use it too.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::runtime_paths;
+use crate::shell_rc;
+
+/// What uninstalling removes
+struct Plan {
+    /// rc files with VibeRot blocks
+    rc_files: Vec<PathBuf>,
+    sockets: Vec<PathBuf>,
+    services: Vec<Service>,
+    /// ~/.viberot, or what's in it besides the config
+    data: Vec<PathBuf>,
+}
+
+enum Service {
+    SystemdUnit(PathBuf),
+    LaunchAgent(PathBuf),
+    ScheduledTask(String),
+}
+
+impl std::fmt::Display for Service {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Service::SystemdUnit(path) => write!(f, "systemd user unit {}", path.display()),
+            Service::LaunchAgent(path) => write!(f, "launch agent {}", path.display()),
+            Service::ScheduledTask(name) => write!(f, "scheduled task {}", name),
+        }
+    }
+}
+
+pub fn run(keep_config: bool, yes: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let home_dir = dirs::home_dir().ok_or("Could not find home directory")?;
+    let viberot_dir = runtime_paths::data_dir()?;
+    let sockets = sockets(&viberot_dir);
+    if let Some(socket) = sockets.iter().find(|socket| is_listening(socket)) {
+        return Err(format!("The service is still running (it answers on {}); stop it first", socket.display()).into());
+    }
+
+    let plan = Plan {
+        rc_files: shell_rc::RC_FILES.iter().map(|name| home_dir.join(name))
+            .filter(|path| std::fs::read_to_string(path).is_ok_and(|content| shell_rc::remove_blocks(&content).1 > 0))
+            .collect(),
+        sockets: sockets.into_iter().filter(|socket| socket.exists()).collect(),
+        services: services(&home_dir),
+        data: data(&viberot_dir, keep_config, runtime_paths::has_prefix())?,
+    };
+    if plan.rc_files.is_empty() && plan.sockets.is_empty() && plan.services.is_empty() && plan.data.is_empty() {
+        println!("Nothing of VibeRot left to remove");
+        return Ok(());
+    }
+
+    println!("This removes:");
+    for path in &plan.rc_files {
+        println!("  the VibeRot lines in {}", path.display());
+    }
+    for socket in &plan.sockets {
+        println!("  {}", socket.display());
+    }
+    for service in &plan.services {
+        println!("  the {}", service);
+    }
+    for path in &plan.data {
+        println!("  {}", path.display());
+    }
+    if keep_config {
+        println!("and keeps {}", viberot_dir.join("config.toml").display());
+    }
+    if runtime_paths::has_prefix() {
+        println!("Anything else in {} (VIBEROT_PREFIX) stays", viberot_dir.display());
+    }
+    if !yes && !confirm()? {
+        println!("Nothing was removed");
+        return Ok(());
+    }
+
+    let mut failures = Vec::new();
+    for path in &plan.rc_files {
+        let result = std::fs::read_to_string(path)
+            .and_then(|content| std::fs::write(path, shell_rc::remove_blocks(&content).0));
+        if let Err(e) = result {
+            failures.push(format!("{}: {}", path.display(), e));
+        }
+    }
+    for socket in &plan.sockets {
+        if let Err(e) = std::fs::remove_file(socket) {
+            failures.push(format!("{}: {}", socket.display(), e));
+        }
+    }
+    for service in &plan.services {
+        if let Err(e) = unregister(service) {
+            failures.push(format!("the {}: {}", service, e));
+        }
+    }
+    for path in &plan.data {
+        let result = if path.is_dir() { std::fs::remove_dir_all(path) } else { std::fs::remove_file(path) };
+        if let Err(e) = result {
+            failures.push(format!("{}: {}", path.display(), e));
+        }
+    }
+
+    if home_dir.join(".bash-preexec.sh").exists() {
+        println!("~/.bash-preexec.sh stays, as other tools use it too");
+    }
+    if !failures.is_empty() {
+        return Err(format!("VibeRot was only partly removed, these failed:\n  {}", failures.join("\n  ")).into());
+    }
+    println!("VibeRot is uninstalled; open a new shell to drop the hooks from running ones");
+    Ok(())
+}
+
+fn confirm() -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    print!("Continue? [y/N] ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_lowercase().starts_with('y'))
+}
+
+/// Shell sockets: those the service last listed, the configured ones and the default
+fn sockets(viberot_dir: &Path) -> Vec<PathBuf> {
+    let listed = runtime_paths::socket_discovery_file().and_then(|path| Ok(std::fs::read_to_string(path)?));
+    let mut sockets: Vec<PathBuf> = listed
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with("tcp:"))
+        .map(PathBuf::from)
+        .collect();
+    // Loading a config that isn't there would write the default one
+    let config_path = viberot_dir.join("config.toml");
+    if let Some(config) = config_path.exists().then(|| Config::load(&config_path).ok()).flatten() {
+        sockets.extend(config.shell.socket_paths.iter().map(PathBuf::from));
+    }
+    sockets.push(runtime_paths::shell_socket());
+    sockets.sort();
+    sockets.dedup();
+    sockets
+}
+
+#[cfg(unix)]
+fn is_listening(socket: &Path) -> bool {
+    std::os::unix::net::UnixStream::connect(socket).is_ok()
+}
+
+#[cfg(not(unix))]
+fn is_listening(_socket: &Path) -> bool {
+    false
+}
+
+/// What to remove of ~/.viberot: all of it, or only what VibeRot writes to a `VIBEROT_PREFIX`
+/// directory
+fn data(viberot_dir: &Path, keep_config: bool, prefixed: bool) -> std::io::Result<Vec<PathBuf>> {
+    if !viberot_dir.exists() {
+        return Ok(Vec::new());
+    }
+    if !keep_config && !prefixed {
+        return Ok(vec![viberot_dir.to_path_buf()]);
+    }
+    // What VibeRot writes there, and temporary `.tmp` files of it
+    let ours: Vec<String> = runtime_paths::data_entries().map_err(std::io::Error::other)?
+        .iter()
+        .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()))
+        .collect();
+    let is_ours = |name: &str| {
+        let name = name.strip_suffix(".tmp").unwrap_or(name);
+        ours.iter().any(|entry| entry == name)
+    };
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(viberot_dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().is_some_and(|name| {
+            let name = name.to_string_lossy();
+            (!keep_config || name != "config.toml") && (!prefixed || is_ours(&name))
+        }))
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+/// Services whose file name or definition mentions VibeRot
+fn services(home_dir: &Path) -> Vec<Service> {
+    let mentions_viberot = |path: &Path| {
+        path.file_name().is_some_and(|name| name.to_string_lossy().to_lowercase().contains("viberot"))
+            || std::fs::read_to_string(path).is_ok_and(|content| content.contains("viberot-service"))
+    };
+    let files_in = |dir: PathBuf| -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(dir).into_iter().flatten().flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && mentions_viberot(path))
+            .collect();
+        files.sort();
+        files
+    };
+
+    let mut services = Vec::new();
+    if cfg!(target_os = "linux") {
+        let units_dir = dirs::config_dir().unwrap_or_else(|| home_dir.join(".config")).join("systemd").join("user");
+        services.extend(files_in(units_dir).into_iter().map(Service::SystemdUnit));
+    }
+    if cfg!(target_os = "macos") {
+        let agents_dir = home_dir.join("Library").join("LaunchAgents");
+        services.extend(files_in(agents_dir).into_iter().map(Service::LaunchAgent));
+    }
+    if cfg!(windows) {
+        services.extend(scheduled_tasks().into_iter().map(Service::ScheduledTask));
+    }
+    services
+}
+
+/// Names of scheduled tasks with VibeRot in their name
+fn scheduled_tasks() -> Vec<String> {
+    let Ok(output) = std::process::Command::new("schtasks").args(["/Query", "/FO", "CSV", "/NH"]).output() else {
+        return Vec::new();
+    };
+    // `"\VibeRot","Next run time","Status"` per task
+    let mut tasks: Vec<String> = String::from_utf8_lossy(&output.stdout).lines()
+        .filter_map(|line| line.split(',').next())
+        .map(|name| name.trim_matches('"').to_string())
+        .filter(|name| name.to_lowercase().contains("viberot"))
+        .collect();
+    tasks.dedup();
+    tasks
+}
+
+fn unregister(service: &Service) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let run = |program: &str, args: &[&str]| -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let output = std::process::Command::new(program).args(args).output()
+            .map_err(|e| format!("couldn't run {}: {}", program, e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("`{} {}` failed: {}", program, args.join(" "), stderr.trim()).into());
+        }
+        Ok(())
+    };
+    match service {
+        Service::SystemdUnit(path) => {
+            let unit = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            // A unit that isn't enabled or running is fine too
+            let _ = run("systemctl", &["--user", "disable", "--now", &unit]);
+            std::fs::remove_file(path)?;
+            // systemd forgets the unit on its next reload anyway
+            let _ = run("systemctl", &["--user", "daemon-reload"]);
+            Ok(())
+        }
+        Service::LaunchAgent(path) => {
+            let _ = run("launchctl", &["unload", "-w", &path.to_string_lossy()]);
+            Ok(std::fs::remove_file(path)?)
+        }
+        Service::ScheduledTask(name) => run("schtasks", &["/Delete", "/TN", name, "/F"]),
+    }
+}
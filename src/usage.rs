@@ -0,0 +1,230 @@
+// Usage stats
+// Opt-in counts of how VibeRot is used: commands seen per probe, rule matches, and actions started
+// per type. Commands, rule names, paths and hosts are never recorded, only numbers. With
+// `[usage_stats] enabled` they're kept in ~/.viberot/usage.json for `viberot-service usage`, and
+// nothing leaves the machine; only with `submit` as well are the counts since the last report sent
+// to `submit_url` once a day, with the VibeRot version and OS but nothing identifying the machine.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::audit;
+use crate::config::{Action, UsageStatsConfig};
+use crate::platform::ProcessEvent;
+use crate::rule_engine::MatchedAction;
+
+/// Time between two reports
+pub const SUBMIT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Longest sending a report may take
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The numbers that are counted
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Counts {
+    /// Commands seen, by the probe that reported them
+    #[serde(default)]
+    pub commands: BTreeMap<String, u64>,
+    /// Commands at least one rule matched
+    #[serde(default)]
+    pub matched_commands: u64,
+    /// Rules matched, a command matching two rules counting twice
+    #[serde(default)]
+    pub rule_matches: u64,
+    /// Actions started, by type ("exec", "attach", "notify", ...)
+    #[serde(default)]
+    pub actions: BTreeMap<String, u64>,
+}
+
+impl Counts {
+    fn is_empty(&self) -> bool {
+        self.commands.is_empty() && self.matched_commands == 0 && self.actions.is_empty()
+    }
+
+    /// Takes away what `other` counted, for counts that were sent
+    fn subtract(&mut self, other: &Counts) {
+        let subtract_map = |map: &mut BTreeMap<String, u64>, other: &BTreeMap<String, u64>| {
+            for (key, count) in other {
+                if let Some(value) = map.get_mut(key) {
+                    *value = value.saturating_sub(*count);
+                }
+            }
+            map.retain(|_, value| *value > 0);
+        };
+        subtract_map(&mut self.commands, &other.commands);
+        subtract_map(&mut self.actions, &other.actions);
+        self.matched_commands = self.matched_commands.saturating_sub(other.matched_commands);
+        self.rule_matches = self.rule_matches.saturating_sub(other.rule_matches);
+    }
+
+    fn total_commands(&self) -> u64 {
+        self.commands.values().sum()
+    }
+}
+
+/// What `usage.json` holds
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageFile {
+    /// Unix timestamp counting started at
+    pub since: u64,
+    /// Everything counted since then
+    pub total: Counts,
+    /// Counted since the last report was sent
+    pub unsent: Counts,
+    /// Unix timestamp of the last report sent
+    pub last_submitted: Option<u64>,
+}
+
+/// What a report sends: no identifier, so reports can't be tied to each other or to a machine
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    pub version: &'static str,
+    pub os: &'static str,
+    /// Days the counts cover, rounded up
+    pub days: u64,
+    #[serde(flatten)]
+    pub counts: &'a Counts,
+}
+
+pub struct UsageStats {
+    path: PathBuf,
+    file: Mutex<UsageFile>,
+}
+
+impl UsageStats {
+    /// Loads the counts kept so far, starting over when there are none
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let file = std::fs::read_to_string(&path).ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_else(|| UsageFile { since: audit::unix_now(), ..UsageFile::default() });
+        Self { path, file: Mutex::new(file) }
+    }
+
+    /// Counts a command a probe reported
+    pub fn command_seen(&self, event: &ProcessEvent) {
+        let probe = format!("{:?}", event.probe_source);
+        let mut guard = self.file.lock().unwrap();
+        let file = &mut *guard;
+        for counts in [&mut file.total, &mut file.unsent] {
+            *counts.commands.entry(probe.clone()).or_default() += 1;
+        }
+    }
+
+    /// Counts the rules a command matched and the actions that were started for it
+    pub fn actions_started(&self, actions: &[MatchedAction]) {
+        let rules = actions.iter().map(|action| &action.rule_name).collect::<BTreeSet<_>>().len();
+        let mut guard = self.file.lock().unwrap();
+        let file = &mut *guard;
+        for counts in [&mut file.total, &mut file.unsent] {
+            counts.matched_commands += 1;
+            counts.rule_matches += rules as u64;
+            for action in actions {
+                *counts.actions.entry(action_type(&action.action).to_string()).or_default() += 1;
+            }
+        }
+    }
+
+    /// Writes the counts for `viberot-service usage`
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let content = serde_json::to_string_pretty(&*self.file.lock().unwrap())?;
+        let temp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&temp_path, content)?;
+        std::fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Sends the counts since the last report when submitting is on and a day has passed since
+    /// then. Returns whether a report was sent
+    pub async fn submit_if_due(&self, config: &UsageStatsConfig) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(url) = config.submit_url.as_deref().filter(|_| config.enabled && config.submit) else {
+            return Ok(false);
+        };
+        let now = audit::unix_now();
+        let (sent, covered_from) = {
+            let file = self.file.lock().unwrap();
+            let covered_from = file.last_submitted.unwrap_or(file.since);
+            if now.saturating_sub(covered_from) < SUBMIT_INTERVAL.as_secs() || file.unsent.is_empty() {
+                return Ok(false);
+            }
+            (file.unsent.clone(), covered_from)
+        };
+        let body = serde_json::to_vec(&report(&sent, now.saturating_sub(covered_from)))?;
+
+        let client = reqwest::Client::builder().timeout(SUBMIT_TIMEOUT).build()?;
+        client.post(url).header("Content-Type", "application/json").body(body)
+            .send().await?.error_for_status()?;
+
+        // Commands counted while the report was on its way stay for the next one
+        {
+            let mut file = self.file.lock().unwrap();
+            file.unsent.subtract(&sent);
+            file.last_submitted = Some(now);
+        }
+        self.save()?;
+        Ok(true)
+    }
+}
+
+/// The report sent for `counts`, covering `seconds`
+pub fn report(counts: &Counts, seconds: u64) -> Report<'_> {
+    Report {
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        days: seconds.div_ceil(24 * 60 * 60).max(1),
+        counts,
+    }
+}
+
+/// An action's `type` in the config
+fn action_type(action: &Action) -> &'static str {
+    match action {
+        Action::Executable { .. } => "exec",
+        Action::Lua { .. } => "lua",
+        Action::Attach { .. } => "attach",
+        Action::Notify { .. } => "notify",
+        Action::Speak { .. } => "speak",
+        Action::Bell { .. } => "bell",
+        Action::Throttle { .. } => "throttle",
+        Action::Desktop { .. } => "desktop",
+    }
+}
+
+/// Reads the counts the running service last wrote
+pub fn load(path: &Path) -> Result<Option<UsageFile>, Box<dyn std::error::Error + Send + Sync>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Couldn't read {:?}: {}", path, e).into()),
+    }
+}
+
+/// Human-readable summary of the counts and of what submitting would send
+pub fn to_text(file: &UsageFile, config: &UsageStatsConfig) -> String {
+    let mut text = String::new();
+    if !config.enabled {
+        text.push_str("Usage stats are off, so nothing new is counted; turn them on with `[usage_stats] enabled = true`\n");
+    }
+    let days = audit::unix_now().saturating_sub(file.since).div_ceil(24 * 60 * 60).max(1);
+    let total = &file.total;
+    text.push_str(&format!("Over the last {} day(s): {} command(s), {} matched by {} rule match(es)\n",
+                           days, total.total_commands(), total.matched_commands, total.rule_matches));
+    for (probe, count) in &total.commands {
+        text.push_str(&format!("  {} command(s) from the {} probe\n", count, probe));
+    }
+    for (action, count) in &total.actions {
+        text.push_str(&format!("  {} {} action(s) started\n", count, action));
+    }
+
+    match config.submit_url.as_deref().filter(|_| config.enabled && config.submit) {
+        Some(url) => {
+            let covered = audit::unix_now().saturating_sub(file.last_submitted.unwrap_or(file.since));
+            let next = serde_json::to_string(&report(&file.unsent, covered)).unwrap_or_default();
+            text.push_str(&format!("Sent to {} once a day; the next report is:\n  {}\n", url, next));
+        }
+        None => text.push_str("Kept on this machine only; nothing is sent\n"),
+    }
+    text
+}
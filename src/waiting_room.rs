@@ -0,0 +1,317 @@
+// Waiting room page
+// The overlay's default page, served by the service on 127.0.0.1 so it works offline and doesn't
+// depend on a site putting up with a transparent webview: a progress bar for the command the
+// overlay was started for, how long it has run, and images and videos from ~/.viberot/memes taking
+// turns, in dark or light mode. Actions get its URL as `VIBEROT_WAITING_ROOM_URL`, with the
+// command's PID in it.
+// Routes:
+//   GET /                 the page, built into the service
+//   GET /state?pid=N      the command as JSON; without a PID, the latest one
+//   GET /memes            the folder and the file names in it as JSON
+//   GET /memes/<name>     one of the files, with byte ranges for seeking in videos
+// Only requests naming 127.0.0.1 or localhost as their host are answered, so other sites can't
+// read the commands through DNS rebinding.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+use crate::config::WaitingRoomConfig;
+use crate::platform::ProcessEvent;
+
+/// Port the page is served on unless `[waiting_room] port` says otherwise
+pub const DEFAULT_PORT: u16 = 47613;
+
+const PAGE: &str = include_str!("waiting_room.html");
+
+/// How long an ended command is still shown, for overlays closing a little later
+const ENDED_KEPT: Duration = Duration::from_secs(60);
+/// Largest request head read
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+/// Longest a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Files the page can show, by extension, with their content type
+const MEDIA_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("mov", "video/quicktime"),
+];
+
+/// A matched command, as the page shows it
+struct Waiting {
+    command: String,
+    started: Instant,
+    expected: Option<u64>,
+    /// When it ended, and how
+    ended: Option<(Instant, Option<i32>)>,
+}
+
+#[derive(Serialize)]
+struct CommandState<'a> {
+    pid: u32,
+    command: &'a str,
+    /// Seconds since it started, or that it ran for once it ended
+    elapsed: f64,
+    /// Median duration of recent runs in seconds
+    expected: Option<u64>,
+    ended: bool,
+    exit_code: Option<i32>,
+}
+
+pub struct WaitingRoom {
+    port: u16,
+    memes_dir: PathBuf,
+    commands: Mutex<BTreeMap<u32, Waiting>>,
+}
+
+impl WaitingRoom {
+    /// Starts serving the page, or returns None if the port can't be had
+    pub async fn start(config: &WaitingRoomConfig, default_memes_dir: PathBuf) -> Option<Arc<Self>> {
+        let port = config.port.unwrap_or(DEFAULT_PORT);
+        let listener = match TcpListener::bind(("127.0.0.1", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!("Not serving the waiting room page, port {} isn't available: {}", port, e);
+                return None;
+            }
+        };
+        let memes_dir = config.memes_dir.as_deref().map(PathBuf::from).unwrap_or(default_memes_dir);
+        let room = Arc::new(Self { port, memes_dir, commands: Mutex::new(BTreeMap::new()) });
+        info!("Serving the waiting room page at {}", room.url(None));
+
+        let server = Arc::clone(&room);
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let room = Arc::clone(&server);
+                        tokio::spawn(async move {
+                            if let Err(e) = room.serve(stream).await {
+                                debug!("Waiting room request failed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => debug!("Failed to accept a waiting room connection: {}", e),
+                }
+            }
+        });
+        Some(room)
+    }
+
+    /// The page for a command, or for whichever ran last
+    pub fn url(&self, pid: Option<u32>) -> String {
+        match pid {
+            Some(pid) => format!("http://127.0.0.1:{}/?pid={}", self.port, pid),
+            None => format!("http://127.0.0.1:{}/", self.port),
+        }
+    }
+
+    /// Starts showing a matched command
+    pub fn start_command(&self, event: &ProcessEvent) {
+        let mut commands = self.commands.lock().unwrap();
+        commands.retain(|_, waiting| waiting.ended.is_none_or(|(ended, _)| ended.elapsed() < ENDED_KEPT));
+        commands.insert(event.pid, Waiting {
+            command: event.command.clone(),
+            started: Instant::now(),
+            expected: event.expected_duration.filter(|expected| *expected > 0),
+            ended: None,
+        });
+    }
+
+    pub fn finish_command(&self, pid: u32, exit_code: Option<i32>) {
+        if let Some(waiting) = self.commands.lock().unwrap().get_mut(&pid) {
+            waiting.ended.get_or_insert((Instant::now(), exit_code));
+        }
+    }
+
+    /// The command as JSON: the one asked for, else the latest still running, else the latest
+    fn state_json(&self, pid: Option<u32>) -> String {
+        let commands = self.commands.lock().unwrap();
+        let latest = |running: bool| commands.iter()
+            .filter(|(_, waiting)| !running || waiting.ended.is_none())
+            .max_by_key(|(_, waiting)| waiting.started);
+        let found = match pid {
+            Some(pid) => commands.get_key_value(&pid),
+            None => latest(true).or_else(|| latest(false)),
+        };
+        let state = found.map(|(pid, waiting)| CommandState {
+            pid: *pid,
+            command: &waiting.command,
+            elapsed: match waiting.ended {
+                Some((ended, _)) => ended.duration_since(waiting.started),
+                None => waiting.started.elapsed(),
+            }.as_secs_f64(),
+            expected: waiting.expected,
+            ended: waiting.ended.is_some(),
+            exit_code: waiting.ended.and_then(|(_, exit_code)| exit_code),
+        });
+        serde_json::to_string(&state).unwrap_or_else(|_| "null".to_string())
+    }
+
+    /// The folder and the files in it the page can show, sorted by name
+    fn memes_json(&self) -> String {
+        let mut files: Vec<String> = std::fs::read_dir(&self.memes_dir).into_iter().flatten().flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| media_type(name).is_some())
+            .collect();
+        files.sort();
+        serde_json::json!({ "dir": self.memes_dir.display().to_string(), "files": files }).to_string()
+    }
+
+    async fn serve(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let Some(request) = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await.ok().flatten() else {
+            return respond(&mut stream, "400 Bad Request", "text/plain", b"Bad request").await;
+        };
+        let host = request.header("host").unwrap_or_default();
+        let host = host.rsplit_once(':').map_or(host, |(name, _)| name);
+        if !matches!(host, "127.0.0.1" | "localhost" | "[::1]") {
+            return respond(&mut stream, "403 Forbidden", "text/plain", b"Forbidden").await;
+        }
+        if request.method != "GET" {
+            return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"Method not allowed").await;
+        }
+        let (path, query) = request.target.split_once('?').unwrap_or((&request.target, ""));
+        match path {
+            "/" => respond(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE.as_bytes()).await,
+            "/state" => {
+                let pid = query.split('&').find_map(|pair| pair.strip_prefix("pid=")).and_then(|pid| pid.parse().ok());
+                respond(&mut stream, "200 OK", "application/json", self.state_json(pid).as_bytes()).await
+            }
+            "/memes" => respond(&mut stream, "200 OK", "application/json", self.memes_json().as_bytes()).await,
+            _ => match path.strip_prefix("/memes/").and_then(|name| self.meme_path(name)) {
+                Some((file, content_type)) => send_file(&mut stream, &file, content_type, request.header("range")).await,
+                None => respond(&mut stream, "404 Not Found", "text/plain", b"Not found").await,
+            },
+        }
+    }
+
+    /// A file in the memes folder the page can show, never one outside it
+    fn meme_path(&self, name: &str) -> Option<(PathBuf, &'static str)> {
+        let name = percent_decode(name)?;
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return None;
+        }
+        let content_type = media_type(&name)?;
+        let path = self.memes_dir.join(&name);
+        path.is_file().then_some((path, content_type))
+    }
+}
+
+struct Request {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+/// Reads the request line and headers; bodies aren't needed
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await.ok().filter(|read| *read > 0)?;
+        head.extend_from_slice(&buffer[..read]);
+        if head.len() > MAX_REQUEST_SIZE {
+            return None;
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let (method, target) = (request_line.next()?.to_string(), request_line.next()?.to_string());
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Some(Request { method, target, headers })
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len(),
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await
+}
+
+/// Sends a file, or the byte range asked for, as `<video>` needs for seeking
+async fn send_file(stream: &mut TcpStream, path: &Path, content_type: &str, range: Option<&str>) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let (status, start, end) = match range.and_then(|range| parse_range(range, size)) {
+        Some((start, end)) => ("206 Partial Content", start, end),
+        None if range.is_some() && size > 0 => {
+            let head = format!("HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", size);
+            stream.write_all(head.as_bytes()).await?;
+            return stream.shutdown().await;
+        }
+        None => ("200 OK", 0, size.saturating_sub(1)),
+    };
+    let length = if size == 0 { 0 } else { end - start + 1 };
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n",
+        status, content_type, length,
+    );
+    if status.starts_with("206") {
+        head.push_str(&format!("Content-Range: bytes {}-{}/{}\r\n", start, end, size));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    tokio::io::copy(&mut file.take(length), stream).await?;
+    stream.shutdown().await
+}
+
+/// First and last byte of a `bytes=start-end` range, if it fits the file
+fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split(',').next()?.trim().split_once('-')?;
+    let (start, end) = match (start, end) {
+        // The last N bytes
+        ("", suffix) => (size.checked_sub(suffix.parse::<u64>().ok()?.min(size))?, size.checked_sub(1)?),
+        (start, "") => (start.parse().ok()?, size.checked_sub(1)?),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(size.checked_sub(1)?)),
+    };
+    (start <= end && end < size).then_some((start, end))
+}
+
+fn media_type(name: &str) -> Option<&'static str> {
+    let extension = Path::new(name).extension()?.to_str()?.to_lowercase();
+    MEDIA_TYPES.iter().find(|(known, _)| *known == extension).map(|(_, content_type)| *content_type)
+}
+
+/// `%XX` escapes decoded, as the page encodes file names in URLs
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
@@ -0,0 +1,212 @@
+// Watch mode
+// `viberot-service watch` runs the probe like the service does and prints every command it sees
+// with the rules that matched, or why none did, without starting any actions. Rules that weren't
+// ruled out by their command globs say which condition failed; when no glob matched at all, the
+// one that came closest shows how far it got, which is usually where the typo is.
+// It takes the service's place, as both would listen on the same shell sockets or ETW session, so
+// stop the service first. With `[broker] connect = true` it subscribes to the broker instead and
+// can run next to the service. The config is reloaded as it's edited, so rules can be fixed while
+// watching.
+
+use globset::{Glob, GlobMatcher};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast;
+
+use crate::broker;
+use crate::config::Config;
+use crate::config_watcher::ConfigWatcher;
+use crate::dedup::Deduplicator;
+use crate::enrichment::EnrichmentPipeline;
+use crate::mqtt;
+use crate::mutes::{format_duration, MuteStore};
+use crate::platform::{self, PlatformProbeTrait, ProcessEvent, ProcessLifecycleEvent};
+use crate::rule_engine::{Miss, PartialMatch, RuleEngine, RuleVerdict};
+use crate::signals;
+use crate::stats::StatsStore;
+
+/// What to print
+pub struct WatchOptions {
+    /// Every rule's verdict, not just the ones that got past their command globs
+    pub all: bool,
+    /// Only commands matching this glob
+    pub filter: Option<String>,
+}
+
+pub async fn run(
+    config_path: &Path,
+    stats: StatsStore,
+    mutes: MuteStore,
+    options: WatchOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut config = Config::load(config_path)?;
+    let (_config_watcher, mut config_change_rx) = ConfigWatcher::new(config_path)?;
+    let filter: Option<GlobMatcher> = match options.filter {
+        Some(ref pattern) => Some(Glob::new(pattern)?.compile_matcher()),
+        None => None,
+    };
+    let style = Style::detect();
+
+    let (lifecycle_tx, mut lifecycle_rx) = broadcast::channel(1024);
+    let rule_engine = RuleEngine::new(mutes);
+    let dedup = Deduplicator::new();
+    let enrichment = Arc::new(EnrichmentPipeline::new(&config.enrichment));
+    let mqtt_source = match config.mqtt_source {
+        Some(ref source) => Some(mqtt::MqttSource::start(source, lifecycle_tx.clone())?),
+        None => None,
+    };
+    let probe: Box<dyn PlatformProbeTrait> = if config.broker.connect {
+        Box::new(broker::BrokerClient::new(lifecycle_tx.clone(), &config))
+    } else {
+        platform::detect_best_probe(lifecycle_tx.clone(), &config).0
+    };
+    let probe = platform::start_probe(probe, lifecycle_tx, &config).await?;
+    eprintln!("Watching commands against {} rule(s), no actions are started; Ctrl+C to stop", config.rules.len());
+
+    // Start times of the commands shown, to tell when they end
+    let mut shown: HashMap<u32, Instant> = HashMap::new();
+    let shutdown_requested = signals::shutdown_requested();
+    tokio::pin!(shutdown_requested);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_requested => break,
+            Some(new_config) = config_change_rx.recv() => {
+                eprintln!("{}", style.paint(DIM, &format!("Config reloaded, {} rule(s)", new_config.rules.len())));
+                config = new_config;
+            }
+            event_result = lifecycle_rx.recv() => {
+                let lifecycle_event = match event_result {
+                    Ok(lifecycle_event) => lifecycle_event,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        eprintln!("{}", style.paint(YELLOW, &format!("Missed {} event(s)", missed)));
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                for lifecycle_event in dedup.filter(lifecycle_event) {
+                    match lifecycle_event {
+                        ProcessLifecycleEvent::Started(event) => {
+                            if filter.as_ref().is_some_and(|filter| !filter.is_match(&event.command)) {
+                                continue;
+                            }
+                            let mut event = enrichment.enrich(event).await;
+                            event.expected_duration = stats.expected_duration(&event.command);
+                            let verdicts = rule_engine.explain(&event, &config).await;
+                            shown.insert(event.pid, Instant::now());
+                            print!("{}", render(&event, &verdicts, options.all, style));
+                        }
+                        ProcessLifecycleEvent::Ended { pid, .. } => {
+                            if let Some(started) = shown.remove(&pid) {
+                                let ran = format_duration(started.elapsed().as_secs());
+                                println!("{}", style.paint(DIM, &format!("PID {} ended after {}", pid, ran)));
+                            }
+                        }
+                        ProcessLifecycleEvent::PidUpgraded { .. } | ProcessLifecycleEvent::Progress { .. } => {}
+                        // Flattened by the deduplicator
+                        ProcessLifecycleEvent::Batch(_) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    probe.stop().await?;
+    if let Some(source) = mqtt_source {
+        source.stop();
+    }
+    Ok(())
+}
+
+const BOLD: &str = "1";
+const DIM: &str = "2";
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+
+/// ANSI colors, when printing to a terminal and NO_COLOR isn't set
+#[derive(Clone, Copy)]
+struct Style {
+    enabled: bool,
+}
+
+impl Style {
+    fn detect() -> Self {
+        Self { enabled: std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none() }
+    }
+
+    fn paint(self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// A command's verdicts as `watch --all` prints them; for `rules test`
+pub fn to_text(event: &ProcessEvent, verdicts: &[RuleVerdict]) -> String {
+    render(event, verdicts, true, Style::detect())
+}
+
+/// A command with its verdicts, one line each
+fn render(event: &ProcessEvent, verdicts: &[RuleVerdict], all: bool, style: Style) -> String {
+    let mut text = format!("\n{}\n", style.paint(BOLD, &format!("$ {}", event.command)));
+
+    let mut details = vec![format!("PID {}", event.pid), format!("{:?}", event.probe_source)];
+    details.extend(event.working_directory.iter().cloned());
+    details.extend(event.tags.iter()
+        .filter(|(name, _)| name.as_str() != "repo_root")
+        .map(|(name, value)| format!("{}={}", name, value)));
+    if let Some(expected) = event.expected_duration {
+        details.push(format!("usually {}", format_duration(expected)));
+    }
+    text.push_str(&format!("  {}\n", style.paint(DIM, &details.join("  "))));
+
+    for verdict in verdicts {
+        let line = match verdict.outcome {
+            Ok(actions) => style.paint(GREEN, &format!("✓ {}: {} action(s)", verdict.rule_name, actions)),
+            Err(Miss::Muted) => style.paint(YELLOW, &format!("~ {}: matched, but muted", verdict.rule_name)),
+            // Rules that didn't get past their globs, or have none, or are off, would be listed for
+            // every command
+            Err(Miss::Command | Miss::Disabled) if !all => continue,
+            Err(_) if !verdict.has_command && !all => continue,
+            Err(ref miss) => style.paint(RED, &format!("✗ {}: {}", verdict.rule_name, describe(miss, verdict.closest.as_ref()))),
+        };
+        text.push_str(&format!("  {}\n", line));
+    }
+
+    let matched = verdicts.iter().any(|verdict| verdict.outcome.is_ok());
+    if !matched && !all {
+        let closest = verdicts.iter()
+            .filter_map(|verdict| Some((verdict, verdict.closest.as_ref()?)))
+            .max_by(|(_, a), (_, b)| a.ratio().total_cmp(&b.ratio()));
+        let line = match closest {
+            Some((verdict, partial)) => format!("No rule matched; closest: {}, whose {}", verdict.rule_name, describe_partial(partial)),
+            None => "No rule matched".to_string(),
+        };
+        text.push_str(&format!("  {}\n", style.paint(DIM, &line)));
+    }
+    text
+}
+
+fn describe(miss: &Miss, closest: Option<&PartialMatch>) -> String {
+    match (miss, closest) {
+        (Miss::Command, Some(partial)) => format!("{}; {}", miss, describe_partial(partial)),
+        _ => miss.to_string(),
+    }
+}
+
+/// Why a command glob didn't match, judging by how far it got
+pub fn describe_partial(partial: &PartialMatch) -> String {
+    let pattern = &partial.pattern;
+    if partial.matched == pattern.len() {
+        format!("`{}` only matches the start of the command (missing a trailing `*`?)", pattern)
+    } else if partial.matched == 0 {
+        format!("`{}` doesn't match from the start (missing a leading `*`?)", pattern)
+    } else {
+        format!("`{}` stops matching after `{}`", pattern, &pattern[..partial.matched])
+    }
+}
@@ -0,0 +1,242 @@
+// WebSocket event source
+// `[websocket_source]` serves a WebSocket on localhost for editors and agent plugins reporting
+// their own work. A client holds a connection open and sends JSON text messages:
+//   {"type": "started", "id": "task-1", "command": "cargo test", "working_directory": "/src/app"}
+//   {"type": "progress", "id": "task-1", "percent": 40, "message": "Compiling"}
+//   {"type": "ended", "id": "task-1", "exit_code": 0}
+// IDs are scoped to the connection and mapped to synthetic PIDs. Unlike the shell socket's
+// fire-and-forget lines, the connection is the lifetime of what it started: when it closes, drops,
+// or stops answering pings, everything it started and didn't end ends, so a crashed editor can't
+// leave an overlay up. Messages the service can't use are answered with
+// {"type": "error", "message": "..."}; the rest aren't answered.
+// The URL, with a token made up each time the service starts, is written to ~/.viberot/.websocket
+// for clients to read. Connections without the token, or from web pages (which send an Origin
+// header), are refused, so neither other users nor websites can start actions.
+
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+use crate::config::WebSocketSourceConfig;
+use crate::platform::{ProbeSource, ProcessEvent, ProcessLifecycleEvent};
+use crate::runtime_paths;
+
+/// Port served without `port` in the config
+pub const DEFAULT_PORT: u16 = 47914;
+/// How often clients are pinged
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a client may send nothing, not even a pong, before it counts as gone
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Synthetic PIDs of commands reported over WebSocket, between the MQTT and D-Bus ones
+static WEBSOCKET_PID_COUNTER: AtomicU32 = AtomicU32::new(3_500_000_000);
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Started {
+        id: String,
+        command: String,
+        #[serde(default)]
+        working_directory: Option<String>,
+        /// Tags for `when` conditions, e.g. {"agent": "copilot"}
+        #[serde(default)]
+        tags: BTreeMap<String, String>,
+    },
+    Progress {
+        id: String,
+        /// 0 to 100
+        #[serde(default)]
+        percent: Option<f64>,
+        #[serde(default)]
+        message: Option<String>,
+    },
+    Ended {
+        id: String,
+        #[serde(default)]
+        exit_code: Option<i32>,
+    },
+}
+
+/// Serves the WebSocket until stopped
+pub struct WebSocketSource {
+    task: JoinHandle<()>,
+}
+
+impl WebSocketSource {
+    pub async fn start(config: &WebSocketSourceConfig, lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let port = config.port.unwrap_or(DEFAULT_PORT);
+        let listener = TcpListener::bind(("127.0.0.1", port)).await
+            .map_err(|e| format!("Failed to serve the WebSocket on port {}: {}", port, e))?;
+        let token: Arc<str> = new_token()
+            .map_err(|e| format!("Failed to make a token for the WebSocket: {}", e))?
+            .into();
+        write_discovery_file(&format!("ws://127.0.0.1:{}/?token={}", port, token))?;
+
+        let task = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept a WebSocket connection: {}", e);
+                        continue;
+                    }
+                };
+                let token = Arc::clone(&token);
+                let lifecycle_sender = lifecycle_sender.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, &token, lifecycle_sender).await {
+                        debug!("WebSocket connection from {} failed: {}", peer, e);
+                    }
+                });
+            }
+        });
+        info!("Receiving commands over WebSocket on 127.0.0.1:{}", port);
+        Ok(Self { task })
+    }
+
+    pub fn stop(&self) {
+        self.task.abort();
+    }
+}
+
+/// Handles one client, ending what it started once it's gone
+#[allow(clippy::result_large_err)] // The handshake callback's error type is tungstenite's
+async fn serve(stream: TcpStream, token: &str, lifecycle_sender: broadcast::Sender<ProcessLifecycleEvent>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut socket = tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response: Response| check_request(request, response, token)).await?;
+    let mut running: HashMap<String, u32> = HashMap::new();
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    let result = loop {
+        let message = tokio::select! {
+            message = socket.next() => message,
+            _ = ping.tick() => {
+                if last_seen.elapsed() > CLIENT_TIMEOUT {
+                    break Err("stopped answering pings".into());
+                }
+                if let Err(e) = socket.send(Message::Ping(Vec::new())).await {
+                    break Err(e.into());
+                }
+                continue;
+            }
+        };
+        last_seen = Instant::now();
+        let text = match message {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | None => break Ok(()),
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => break Err(e.into()),
+        };
+        let reply = match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(message) => handle_message(message, &mut running, &lifecycle_sender).err(),
+            Err(e) => Some(format!("Not a message VibeRot knows: {}", e)),
+        };
+        if let Some(error) = reply {
+            let error = serde_json::json!({ "type": "error", "message": error }).to_string();
+            if let Err(e) = socket.send(Message::Text(error)).await {
+                break Err(e.into());
+            }
+        }
+    };
+
+    for (id, pid) in running {
+        debug!("WebSocket client left without ending '{}', ending synthetic PID {}", id, pid);
+        let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid, exit_code: None });
+    }
+    result
+}
+
+/// Refuses connections without the token, and from web pages
+#[allow(clippy::result_large_err)] // The handshake callback's error type is tungstenite's
+fn check_request(request: &Request, response: Response, token: &str) -> Result<Response, ErrorResponse> {
+    let refuse = |status: StatusCode, reason: &str| {
+        let mut response = ErrorResponse::new(Some(reason.to_string()));
+        *response.status_mut() = status;
+        Err(response)
+    };
+    if request.headers().contains_key("origin") {
+        return refuse(StatusCode::FORBIDDEN, "Web pages can't connect");
+    }
+    let given = request.uri().query().unwrap_or_default().split('&')
+        .find_map(|pair| pair.strip_prefix("token="));
+    if !given.is_some_and(|given| tokens_match(given, token)) {
+        return refuse(StatusCode::UNAUTHORIZED, "The token in ~/.viberot/.websocket is needed");
+    }
+    Ok(response)
+}
+
+/// Turns a client message into lifecycle events, or says what's wrong with it
+fn handle_message(message: ClientMessage, running: &mut HashMap<String, u32>, lifecycle_sender: &broadcast::Sender<ProcessLifecycleEvent>) -> Result<(), String> {
+    match message {
+        ClientMessage::Started { id, command, working_directory, tags } => {
+            let pid = WEBSOCKET_PID_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let mut event = ProcessEvent::new(pid, command, ProbeSource::WebSocket);
+            if let Some(working_directory) = working_directory {
+                event = event.with_working_directory(working_directory);
+            }
+            event.tags = tags;
+            // Starting an ID again ends what it was before
+            if let Some(previous) = running.insert(id, pid) {
+                let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid: previous, exit_code: None });
+            }
+            debug!("WebSocket command started with synthetic PID {}: {}", pid, event.command);
+            let _ = lifecycle_sender.send(ProcessLifecycleEvent::Started(event));
+        }
+        ClientMessage::Progress { id, percent, message } => {
+            let pid = *running.get(&id).ok_or_else(|| format!("Nothing started as '{}'", id))?;
+            let percent = match percent {
+                Some(percent) if !(0.0..=100.0).contains(&percent) => return Err(format!("`percent` goes from 0 to 100, got {}", percent)),
+                percent => percent.map(|percent| percent as u8),
+            };
+            let _ = lifecycle_sender.send(ProcessLifecycleEvent::Progress { pid, percent, message });
+        }
+        ClientMessage::Ended { id, exit_code } => {
+            let pid = running.remove(&id).ok_or_else(|| format!("Nothing started as '{}'", id))?;
+            let _ = lifecycle_sender.send(ProcessLifecycleEvent::Ended { pid, exit_code });
+        }
+    }
+    Ok(())
+}
+
+/// 128 bits from the OS's random source
+fn new_token() -> Result<String, getrandom::Error> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Compares tokens in time independent of where they differ
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given.bytes().zip(token.bytes()).fold(0u8, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+/// Writes the URL clients connect to, token included, where only this user can read it
+fn write_discovery_file(url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let path = runtime_paths::websocket_discovery_file()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let temp_path = path.with_extension("tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    std::io::Write::write_all(&mut options.open(&temp_path)?, format!("{}\n", url).as_bytes())?;
+    std::fs::rename(&temp_path, &path)?;
+    Ok(())
+}
@@ -0,0 +1,346 @@
+// Virtual desktop switching
+// `desktop` actions move the user to another virtual desktop (workspace) while a matched command
+// runs, so the overlay opens there instead of covering the editor, and back to the one they came
+// from when the last such command ends, unless they went somewhere else in the meantime.
+// Backends: sway (`swaymsg`) and Hyprland (`hyprctl`) on Wayland, EWMH window managers through
+// `wmctrl` on X11, and Windows, where IVirtualDesktopManager can't switch desktops: the current one
+// is read from Explorer's registry keys and Ctrl+Win+Left/Right pressed to get to the other. On
+// macOS, Spaces has no API; the "Switch to Desktop N" shortcuts (Ctrl+N, off by default in the
+// keyboard settings) are pressed, and the desktop to go back to has to be configured.
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
+
+/// Platform access to virtual desktops, numbered from 1
+pub trait WorkspaceBackend: Send + Sync {
+    /// The desktop the user is on, or None if it can't be determined
+    fn current(&self) -> Option<u32>;
+
+    fn switch_to(&self, desktop: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Returns the virtual desktop backend for the current session
+pub fn create_backend() -> Box<dyn WorkspaceBackend> {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        if std::env::var_os("SWAYSOCK").is_some() {
+            return Box::new(Sway);
+        }
+        if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+            return Box::new(Hyprland);
+        }
+        Box::new(Wmctrl)
+    }
+
+    #[cfg(windows)]
+    {
+        Box::new(WindowsDesktops)
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        Box::new(MacSpaces)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        Box::new(UnsupportedWorkspaces)
+    }
+}
+
+/// The program the backend for the current session runs, for lint to check it's installed
+pub fn program() -> Option<&'static str> {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        if std::env::var_os("SWAYSOCK").is_some() {
+            Some("swaymsg")
+        } else if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+            Some("hyprctl")
+        } else {
+            Some("wmctrl")
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        Some("osascript")
+    }
+
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+#[derive(Debug, Default)]
+struct SwitchState {
+    /// PIDs of matched commands whose `desktop` action switched, or kept, the user there
+    holders: HashSet<u32>,
+    /// The desktop switched to
+    target: u32,
+    /// The desktop to go back to
+    previous: Option<u32>,
+}
+
+/// Switches desktops for matched commands and back once they end
+pub struct WorkspaceSwitcher {
+    backend: Box<dyn WorkspaceBackend>,
+    state: Mutex<SwitchState>,
+}
+
+impl WorkspaceSwitcher {
+    pub fn new(backend: Box<dyn WorkspaceBackend>) -> Self {
+        Self {
+            backend,
+            state: Mutex::new(SwitchState::default()),
+        }
+    }
+
+    /// Switches to `desktop` while the command runs; `back_to` is where to go afterwards when the
+    /// current desktop can't be determined
+    pub fn hold(&self, pid: u32, desktop: u32, back_to: Option<u32>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.holders.is_empty() {
+            // Another command took the user away already; where they came from stays the way back
+            if state.target != desktop {
+                self.backend.switch_to(desktop)?;
+                state.target = desktop;
+            }
+            state.holders.insert(pid);
+            return Ok(());
+        }
+        let current = self.backend.current();
+        if current == Some(desktop) {
+            debug!("Already on desktop {}, not switching", desktop);
+            return Ok(());
+        }
+        let previous = current.or(back_to);
+        if previous.is_none() {
+            warn!("Can't tell which desktop is current, so desktop {} stays when PID {} ends; set `back_to` to return", desktop, pid);
+        }
+        self.backend.switch_to(desktop)?;
+        info!("Switched to desktop {} while PID {} runs", desktop, pid);
+        *state = SwitchState { holders: HashSet::from([pid]), target: desktop, previous };
+        Ok(())
+    }
+
+    /// Goes back once the last holding command ends. Returns the desktop the command switched to,
+    /// if it held one
+    pub fn release(&self, pid: u32) -> Option<u32> {
+        let mut state = self.state.lock().unwrap();
+        if !state.holders.remove(&pid) {
+            return None;
+        }
+        if state.holders.is_empty() {
+            self.restore(&mut state);
+        }
+        Some(state.target)
+    }
+
+    /// Goes back regardless of running commands, e.g. on shutdown
+    pub fn release_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.holders.is_empty() {
+            return;
+        }
+        state.holders.clear();
+        self.restore(&mut state);
+    }
+
+    fn restore(&self, state: &mut SwitchState) {
+        let Some(previous) = state.previous.take() else {
+            return;
+        };
+        // The user moved on by themselves; taking them back would be in the way
+        if self.backend.current().is_some_and(|current| current != state.target) {
+            debug!("No longer on desktop {}, not switching back", state.target);
+            return;
+        }
+        match self.backend.switch_to(previous) {
+            Ok(()) => info!("Switched back to desktop {}", previous),
+            Err(e) => warn!("Failed to switch back to desktop {}: {}", previous, e),
+        }
+    }
+}
+
+/// Runs a command, failing with its stderr if it doesn't succeed
+#[cfg(unix)]
+fn run(program: &str, args: &[&str]) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let output = std::process::Command::new(program).args(args).output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// sway: numbered workspaces
+#[cfg(all(unix, not(target_os = "macos")))]
+struct Sway;
+
+#[cfg(all(unix, not(target_os = "macos")))]
+impl WorkspaceBackend for Sway {
+    fn current(&self) -> Option<u32> {
+        let workspaces: serde_json::Value = serde_json::from_str(&run("swaymsg", &["-t", "get_workspaces"]).ok()?).ok()?;
+        let focused = workspaces.as_array()?.iter().find(|workspace| workspace["focused"] == true)?;
+        focused["num"].as_u64().and_then(|num| u32::try_from(num).ok())
+    }
+
+    fn switch_to(&self, desktop: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        run("swaymsg", &["workspace", "number", &desktop.to_string()]).map(|_| ())
+    }
+}
+
+/// Hyprland: workspace IDs
+#[cfg(all(unix, not(target_os = "macos")))]
+struct Hyprland;
+
+#[cfg(all(unix, not(target_os = "macos")))]
+impl WorkspaceBackend for Hyprland {
+    fn current(&self) -> Option<u32> {
+        let workspace: serde_json::Value = serde_json::from_str(&run("hyprctl", &["activeworkspace", "-j"]).ok()?).ok()?;
+        workspace["id"].as_u64().and_then(|id| u32::try_from(id).ok())
+    }
+
+    fn switch_to(&self, desktop: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        run("hyprctl", &["dispatch", "workspace", &desktop.to_string()]).map(|_| ())
+    }
+}
+
+/// X11 window managers following EWMH (GNOME, KDE, Xfce, i3, ...), through `wmctrl`, which
+/// numbers desktops from 0
+#[cfg(all(unix, not(target_os = "macos")))]
+struct Wmctrl;
+
+#[cfg(all(unix, not(target_os = "macos")))]
+impl WorkspaceBackend for Wmctrl {
+    fn current(&self) -> Option<u32> {
+        // "0  * DG: ...": the current desktop has a `*`
+        let desktops = run("wmctrl", &["-d"]).ok()?;
+        let current = desktops.lines().find(|line| line.split_whitespace().nth(1) == Some("*"))?;
+        current.split_whitespace().next()?.parse::<u32>().ok().map(|index| index + 1)
+    }
+
+    fn switch_to(&self, desktop: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if std::env::var_os("DISPLAY").is_none() {
+            return Err("switching desktops on Wayland needs sway or Hyprland".into());
+        }
+        run("wmctrl", &["-s", &(desktop - 1).to_string()]).map(|_| ())
+    }
+}
+
+/// Windows: virtual desktops in the order Explorer keeps them in the registry
+#[cfg(windows)]
+struct WindowsDesktops;
+
+#[cfg(windows)]
+impl WindowsDesktops {
+    const KEY: &'static str = r"Software\Microsoft\Windows\CurrentVersion\Explorer";
+
+    /// A binary value under Explorer's key
+    fn binary_value(subkey: &str, name: &str) -> Option<Vec<u8>> {
+        use windows::core::HSTRING;
+        use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_BINARY};
+
+        let subkey = HSTRING::from(format!(r"{}\{}", Self::KEY, subkey));
+        let name = HSTRING::from(name);
+        let mut size = 0u32;
+        unsafe { RegGetValueW(HKEY_CURRENT_USER, &subkey, &name, RRF_RT_REG_BINARY, None, None, Some(&mut size)) }.ok()?;
+        let mut data = vec![0u8; size as usize];
+        unsafe { RegGetValueW(HKEY_CURRENT_USER, &subkey, &name, RRF_RT_REG_BINARY, None, Some(data.as_mut_ptr().cast()), Some(&mut size)) }.ok()?;
+        data.truncate(size as usize);
+        Some(data)
+    }
+
+    /// Presses Ctrl+Win+Left or Right `times` times
+    fn press(right: bool, times: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        use windows::Win32::UI::Input::KeyboardAndMouse::{
+            SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYBD_EVENT_FLAGS, KEYEVENTF_EXTENDEDKEY,
+            KEYEVENTF_KEYUP, VIRTUAL_KEY, VK_LCONTROL, VK_LEFT, VK_LWIN, VK_RIGHT,
+        };
+
+        let key = |vk: VIRTUAL_KEY, flags: KEYBD_EVENT_FLAGS| INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 { ki: KEYBDINPUT { wVk: vk, dwFlags: flags, ..Default::default() } },
+        };
+        let arrow = if right { VK_RIGHT } else { VK_LEFT };
+        for _ in 0..times {
+            let inputs = [
+                key(VK_LCONTROL, KEYBD_EVENT_FLAGS(0)),
+                key(VK_LWIN, KEYEVENTF_EXTENDEDKEY),
+                key(arrow, KEYEVENTF_EXTENDEDKEY),
+                key(arrow, KEYEVENTF_EXTENDEDKEY | KEYEVENTF_KEYUP),
+                key(VK_LWIN, KEYEVENTF_EXTENDEDKEY | KEYEVENTF_KEYUP),
+                key(VK_LCONTROL, KEYEVENTF_KEYUP),
+            ];
+            let sent = unsafe { SendInput(&inputs, std::mem::size_of::<INPUT>() as i32) };
+            if sent as usize != inputs.len() {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            // Explorer animates every switch and drops presses that come too fast
+            std::thread::sleep(std::time::Duration::from_millis(150));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+impl WorkspaceBackend for WindowsDesktops {
+    fn current(&self) -> Option<u32> {
+        use windows::Win32::System::RemoteDesktop::ProcessIdToSessionId;
+
+        // The GUIDs of the desktops, 16 bytes each, in order
+        let ids = Self::binary_value("VirtualDesktops", "VirtualDesktopIDs")?;
+        // Windows 11 keeps the current one next to them, Windows 10 per logon session
+        let current = Self::binary_value("VirtualDesktops", "CurrentVirtualDesktop").or_else(|| {
+            let mut session = 0u32;
+            unsafe { ProcessIdToSessionId(std::process::id(), &mut session) }.ok()?;
+            Self::binary_value(&format!(r"SessionInfo\{}\VirtualDesktops", session), "CurrentVirtualDesktop")
+        })?;
+        let index = ids.chunks_exact(16).position(|id| id == current.as_slice())?;
+        Some(index as u32 + 1)
+    }
+
+    fn switch_to(&self, desktop: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let current = self.current().ok_or("can't tell which virtual desktop is current")?;
+        let count = Self::binary_value("VirtualDesktops", "VirtualDesktopIDs").map_or(0, |ids| ids.len() / 16) as u32;
+        if desktop > count {
+            return Err(format!("there are only {} virtual desktops", count).into());
+        }
+        Self::press(desktop > current, desktop.abs_diff(current))
+    }
+}
+
+/// macOS: the "Switch to Desktop N" keyboard shortcuts, Ctrl+1 to Ctrl+9
+#[cfg(target_os = "macos")]
+struct MacSpaces;
+
+#[cfg(target_os = "macos")]
+impl WorkspaceBackend for MacSpaces {
+    fn current(&self) -> Option<u32> {
+        None
+    }
+
+    fn switch_to(&self, desktop: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Key codes of the number keys 1 to 9
+        const KEY_CODES: [u32; 9] = [18, 19, 20, 21, 23, 22, 26, 28, 25];
+        let key_code = KEY_CODES.get(desktop as usize - 1).ok_or("only desktops 1 to 9 have shortcuts")?;
+        let script = format!("tell application \"System Events\" to key code {} using control down", key_code);
+        run("osascript", &["-e", &script]).map(|_| ())
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+struct UnsupportedWorkspaces;
+
+#[cfg(not(any(unix, windows)))]
+impl WorkspaceBackend for UnsupportedWorkspaces {
+    fn current(&self) -> Option<u32> {
+        None
+    }
+
+    fn switch_to(&self, _desktop: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Err("Switching virtual desktops is not supported on this platform".into())
+    }
+}
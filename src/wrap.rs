@@ -0,0 +1,350 @@
+// Command wrapper (Linux and macOS)
+// `viberot-service wrap -- docker pull ubuntu` runs a command on a pseudo-terminal, so it still
+// sees a terminal (colors, progress bars, prompts), and reports it to the service over the shell
+// hook socket like the hooks do, for commands started where no hooks are: scripts, editors, CI.
+// The shell hooks leave `viberot-service wrap ...` to it, so wrapped commands aren't reported
+// twice.
+// With `--detect-stall`, a command that prints nothing for `--stall-after` (30s by default) is
+// reported as stalled: the service starts a `stalled` event for it, which rules with
+// `stalled = true` match, and ends it once the command prints again or ends. So a `docker pull`
+// stuck on the network can get other actions than one that's making progress.
+// With `--cycles`, each rebuild of a watch-mode command like `cargo watch` is reported as a cycle
+// of its own, for rules with `mode = "session"` (see `rebuild_cycles`).
+// The wrapper never gets in the way of the command: without a running service it only runs it,
+// and it exits with the command's exit code.
+
+use base64::{engine::general_purpose, Engine as _};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{IsTerminal, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::enrichment;
+use crate::rebuild_cycles::{self, CycleSource, LineScanner, Marker};
+use crate::runtime_paths;
+use crate::terminal_bell;
+
+/// How often the wrapper checks for a stall, child processes and terminal resizes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct WrapOptions {
+    /// How long the command may print nothing before it counts as stalled; None turns detection off
+    pub stall_after: Option<Duration>,
+    /// Report rebuild cycles
+    pub cycles: bool,
+    /// Lines starting and ending a cycle, instead of those of the watcher the command runs
+    pub cycle_starts: Vec<String>,
+    pub cycle_ends: Vec<String>,
+}
+
+/// The service's shell socket, or nothing if it isn't running
+struct Reporter {
+    stream: Option<Mutex<Box<dyn Write + Send>>>,
+    session_id: String,
+    command_b64: String,
+    working_directory_b64: Option<String>,
+    /// The wrapper's terminal, where the service can show progress
+    tty: Option<String>,
+    /// The wrapper's tmux pane and the package script it runs in, if any
+    environment: HashMap<String, String>,
+}
+
+impl Reporter {
+    fn connect(command: &str) -> Self {
+        let stream = match connect_service() {
+            Ok(stream) => Some(Mutex::new(stream)),
+            Err(e) => {
+                eprintln!("viberot: not reporting the command, the service isn't reachable: {}", e);
+                None
+            }
+        };
+        Self {
+            stream,
+            session_id: format!("wrap-{}", std::process::id()),
+            command_b64: general_purpose::STANDARD.encode(command),
+            working_directory_b64: std::env::current_dir().ok()
+                .map(|dir| general_purpose::STANDARD.encode(dir.as_os_str().as_encoded_bytes())),
+            tty: terminal_name(),
+            environment: terminal_bell::ENV_VARS.iter().chain(enrichment::PACKAGE_SCRIPT_ENV_VARS)
+                .filter_map(|name| Some((name.to_string(), std::env::var(name).ok()?)))
+                .collect(),
+        }
+    }
+
+    /// Sends a hook message about the command; the wrapper's PID stands in for the shell's, so the
+    /// service ends the command if the wrapper dies
+    fn send(&self, event_type: &str, child_pid: u32, exit_code: Option<i32>) {
+        let Some(ref stream) = self.stream else {
+            return;
+        };
+        let message = serde_json::json!({
+            "session_id": self.session_id,
+            "event_type": event_type,
+            "shell_pid": std::process::id(),
+            "pid": child_pid,
+            "command_b64": self.command_b64,
+            "working_directory_b64": self.working_directory_b64,
+            "exit_code": exit_code,
+            "tty": self.tty,
+            "environment": self.environment,
+        });
+        let mut stream = stream.lock().unwrap();
+        // A service that went away doesn't concern the command
+        let _ = writeln!(stream, "{}", message).and_then(|_| stream.flush());
+    }
+}
+
+/// Device of the terminal the wrapper's output goes to, like `tty` prints it
+pub fn terminal_name() -> Option<String> {
+    [libc::STDOUT_FILENO, libc::STDERR_FILENO].into_iter().find_map(|fd| {
+        // SAFETY: ttyname returns null or a string that stays valid until the next call, copied
+        // right away
+        let name = unsafe { libc::ttyname(fd) };
+        (!name.is_null()).then(|| unsafe { std::ffi::CStr::from_ptr(name) }.to_string_lossy().into_owned())
+    })
+}
+
+/// Connects to the first socket the service listed in ~/.viberot/.socket
+pub fn connect_service() -> Result<Box<dyn Write + Send>, Box<dyn std::error::Error + Send + Sync>> {
+    let path = runtime_paths::socket_discovery_file()?;
+    let sockets = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut last_error: Box<dyn std::error::Error + Send + Sync> = "no sockets listed".into();
+    for socket in sockets.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let connected: std::io::Result<Box<dyn Write + Send>> = match socket.strip_prefix("tcp:") {
+            Some(address) => std::net::TcpStream::connect(address).map(|stream| Box::new(stream) as _),
+            None => UnixStream::connect(socket).map(|stream| Box::new(stream) as _),
+        };
+        match connected {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = format!("{}: {}", socket, e).into(),
+        }
+    }
+    Err(last_error)
+}
+
+/// Puts the terminal back the way it was when dropped
+pub struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Passes keys straight to the command, which has a terminal of its own; None when stdin
+    /// isn't a terminal
+    pub fn enable() -> Option<Self> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: `original` is a valid termios to fill in
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return None;
+        }
+        let mut raw = original;
+        // SAFETY: `raw` is a valid termios
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+        }
+        Some(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in `enable`
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Size of the wrapper's terminal, if it has one
+pub fn window_size() -> Option<libc::winsize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ fills in a winsize
+    (unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ as _, &mut size) } == 0).then_some(size)
+}
+
+/// Opens a pseudo-terminal like the wrapper's own: (controller, the command's end)
+pub fn open_pty() -> std::io::Result<(OwnedFd, OwnedFd)> {
+    let (mut controller, mut terminal) = (-1, -1);
+    let mut settings: libc::termios = unsafe { std::mem::zeroed() };
+    // SAFETY: `settings` is a valid termios to fill in
+    let has_settings = std::io::stdin().is_terminal() && unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut settings) } == 0;
+    let mut size = window_size();
+    // SAFETY: the out pointers are valid, and the settings and size are either valid or null
+    let result = unsafe {
+        libc::openpty(
+            &mut controller,
+            &mut terminal,
+            std::ptr::null_mut(),
+            if has_settings { &mut settings } else { std::ptr::null_mut() },
+            size.as_mut().map_or(std::ptr::null_mut(), |size| size as *mut _),
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: openpty returned two open descriptors we now own
+    let (controller, terminal) = unsafe { (OwnedFd::from_raw_fd(controller), OwnedFd::from_raw_fd(terminal)) };
+    // Piped input was never typed, so it's not echoed either
+    if !has_settings {
+        // SAFETY: `settings` is a valid termios to fill in and write back
+        unsafe {
+            if libc::tcgetattr(terminal.as_raw_fd(), &mut settings) == 0 {
+                settings.c_lflag &= !libc::ECHO;
+                libc::tcsetattr(terminal.as_raw_fd(), libc::TCSANOW, &settings);
+            }
+        }
+    }
+    // The command mustn't inherit the controller, or its terminal never closes, and gets its end
+    // as stdio only
+    for fd in [&controller, &terminal] {
+        // SAFETY: sets a flag on a descriptor we own
+        unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    Ok((controller, terminal))
+}
+
+/// Starts a program with the command's end of a pseudo-terminal as its controlling terminal
+pub fn spawn_on(program: &str, args: &[String], terminal: OwnedFd) -> Result<Child, Box<dyn std::error::Error + Send + Sync>> {
+    // The builder holds the wrapper's copies of the command's end, dropped once it's spawned so
+    // reading hits the end when the command exits
+    let mut builder = Command::new(program);
+    builder.args(args)
+        .stdin(Stdio::from(terminal.try_clone()?))
+        .stdout(Stdio::from(terminal.try_clone()?))
+        .stderr(Stdio::from(terminal));
+    // SAFETY: only async-signal-safe calls between fork and exec
+    unsafe {
+        builder.pre_exec(|| {
+            // A session of its own, with the pseudo-terminal as its controlling terminal, so
+            // Ctrl+C and job control reach it
+            if libc::setsid() < 0 || libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(builder.spawn().map_err(|e| format!("Failed to run {}: {}", program, e))?)
+}
+
+/// Copies the wrapper's input to the pseudo-terminal's controller in the background
+pub fn forward_input(mut input: File) {
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut std::io::stdin(), &mut input);
+        // Piped input ran out: Ctrl+D tells the command
+        if !std::io::stdin().is_terminal() {
+            let _ = input.write_all(&[4]);
+        }
+    });
+}
+
+/// Runs the command, returning its exit code
+pub fn run(command: Vec<String>, options: WrapOptions) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    let (program, args) = command.split_first().ok_or("No command to run")?;
+    let (controller, terminal) = open_pty().map_err(|e| format!("Failed to open a pseudo-terminal: {}", e))?;
+
+    let mut child = spawn_on(program, args, terminal)?;
+
+    let command_text = shell_words(&command);
+    let reporter = Arc::new(Reporter::connect(&command_text));
+    reporter.send("CommandStart", child.id(), None);
+    let cycle_source = options.cycles.then(|| {
+        let working_directory = std::env::current_dir().ok();
+        CycleSource::for_command(&command_text, working_directory.as_deref(), &options.cycle_starts, &options.cycle_ends)
+    });
+    let raw_mode = RawMode::enable();
+
+    let mut output = File::from(controller.try_clone()?);
+    forward_input(File::from(controller.try_clone()?));
+
+    let last_output = Arc::new(Mutex::new(Instant::now()));
+    let stalled = Arc::new(AtomicBool::new(false));
+    {
+        let (last_output, stalled, reporter) = (Arc::clone(&last_output), Arc::clone(&stalled), Arc::clone(&reporter));
+        let child_pid = child.id();
+        let watch_children = cycle_source == Some(CycleSource::Children);
+        std::thread::spawn(move || {
+            let mut size = window_size();
+            let mut cycle_open = false;
+            loop {
+                std::thread::sleep(POLL_INTERVAL);
+                // The command's terminal follows the wrapper's size
+                let current = window_size();
+                if let Some(new_size) = current.filter(|new| size.is_none_or(|old| (old.ws_row, old.ws_col) != (new.ws_row, new.ws_col))) {
+                    // SAFETY: TIOCSWINSZ reads a winsize
+                    unsafe { libc::ioctl(controller.as_raw_fd(), libc::TIOCSWINSZ as _, &new_size) };
+                    size = current;
+                }
+                if let Some(stall_after) = options.stall_after {
+                    if last_output.lock().unwrap().elapsed() >= stall_after && !stalled.swap(true, Ordering::SeqCst) {
+                        reporter.send("CommandStalled", child_pid, None);
+                    }
+                }
+                // A cycle lasts as long as the command the watcher runs
+                if watch_children && rebuild_cycles::has_children(child_pid) != cycle_open {
+                    cycle_open = !cycle_open;
+                    reporter.send(if cycle_open { "CommandCycleStarted" } else { "CommandCycleEnded" }, child_pid, None);
+                }
+            }
+        });
+    }
+
+    let mut scanner = match cycle_source {
+        Some(CycleSource::Markers(markers)) => Some(LineScanner::new(markers)),
+        _ => None,
+    };
+    let mut cycle_open = false;
+    let mut stdout = std::io::stdout();
+    let mut buffer = [0u8; 8192];
+    loop {
+        // Linux reports EIO once the command and everything it started closed the terminal
+        let read = match output.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        if stdout.write_all(&buffer[..read]).and_then(|_| stdout.flush()).is_err() {
+            break;
+        }
+        *last_output.lock().unwrap() = Instant::now();
+        if stalled.swap(false, Ordering::SeqCst) {
+            reporter.send("CommandResumed", child.id(), None);
+        }
+        for marker in scanner.as_mut().map(|scanner| scanner.scan(&buffer[..read])).unwrap_or_default() {
+            match marker {
+                // The service ends the previous cycle if the watcher never said it finished
+                Marker::Start => reporter.send("CommandCycleStarted", child.id(), None),
+                Marker::End(exit_code) => {
+                    if !cycle_open {
+                        reporter.send("CommandCycleStarted", child.id(), None);
+                    }
+                    reporter.send("CommandCycleEnded", child.id(), exit_code);
+                }
+            }
+            cycle_open = marker == Marker::Start;
+        }
+    }
+
+    let status = child.wait()?;
+    drop(raw_mode);
+    // Killed by a signal exits like a shell would report it
+    let code = status.code().or_else(|| status.signal().map(|signal| 128 + signal)).unwrap_or(1);
+    reporter.send("CommandEnd", child.id(), Some(code));
+    Ok(code)
+}
+
+/// The command as it would be typed, for matching rules against
+fn shell_words(command: &[String]) -> String {
+    command.iter()
+        .map(|word| {
+            if !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c)) {
+                word.clone()
+            } else {
+                format!("'{}'", word.replace('\'', r"'\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}